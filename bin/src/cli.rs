@@ -2,7 +2,9 @@ use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
 use tracing_otel_extra::Logger;
 
-use crate::commands::{api, node, scheduler, streams};
+use crate::commands::{
+    analyze, api, backfill, backfill_candlesticks, db, doctor, export, node, scheduler, streams,
+};
 
 #[derive(Parser)]
 #[clap(version, about, propagate_version = true)]
@@ -23,8 +25,23 @@ impl Cli {
 /// See `sola --help` for more information.
 #[derive(Debug, Subcommand)]
 pub enum Commands {
+    #[command(name = "analyze-tx", about = "Decode the swaps of a transaction")]
+    AnalyzeTx(analyze::Command),
     #[command(name = "api", about = "Start the API server")]
     Api(api::Command),
+    #[command(name = "backfill-rollups", about = "Roll up the swap events of past days per token")]
+    BackfillRollups(backfill::Command),
+    #[command(
+        name = "backfill-candlesticks",
+        about = "Roll the 5m, 15m, 30m and 4h candlesticks of past days up"
+    )]
+    BackfillCandlesticks(backfill_candlesticks::Command),
+    #[command(name = "db", about = "Maintain the database")]
+    Db(db::Command),
+    #[command(name = "doctor", about = "Check the env, the connectivity and the schema")]
+    Doctor(doctor::Command),
+    #[command(name = "export", about = "Export data to files")]
+    Export(export::Command),
     #[command(name = "node", about = "Start the Node server")]
     Node(node::Command),
     #[command(name = "scheduler", about = "Start the Scheduler server")]
@@ -39,7 +56,13 @@ pub async fn run() -> anyhow::Result<()> {
     let guard = Logger::from_env(None)?.init().expect("Failed to initialize logging");
 
     match opt.command {
+        Commands::AnalyzeTx(command) => command.execute().await?,
         Commands::Api(command) => command.execute().await?,
+        Commands::BackfillRollups(command) => command.execute().await?,
+        Commands::BackfillCandlesticks(command) => command.execute().await?,
+        Commands::Db(command) => command.execute().await?,
+        Commands::Doctor(command) => command.execute().await?,
+        Commands::Export(command) => command.execute().await?,
         Commands::Node(command) => command.execute().await?,
         Commands::Scheduler(command) => command.execute().await?,
         Commands::Streams(command) => command.execute().await?,
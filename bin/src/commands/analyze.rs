@@ -0,0 +1,63 @@
+use anyhow::Context;
+use clap::Parser;
+use dotenvy::dotenv;
+use serde_json::json;
+use sonar_db::{make_kv_store_from_env, EnvReader};
+use sonar_ingestor::{analyze_transaction, fetch_transaction_update, SwapFilterConfig};
+use sonar_sol_price::restore_sol_price_snapshot;
+use tracing::warn;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = "Decode the swaps of a transaction without storing them")]
+pub struct Command {
+    /// signature of the transaction
+    signature: String,
+    /// usd price of SOL, defaults to the persisted snapshot of the SOL price
+    #[arg(long)]
+    sol_price: Option<f64>,
+}
+
+impl Command {
+    /// Execute `analyze-tx` command
+    pub async fn execute(self) -> anyhow::Result<()> {
+        dotenv().ok();
+        let mut env = EnvReader::from_env();
+        let config = SwapFilterConfig::read_env(&mut env);
+        let config = env.finish(config)?;
+        let sol_price = match self.sol_price {
+            Some(sol_price) => sol_price,
+            None => snapshot_sol_price().await,
+        };
+        let update = fetch_transaction_update(&self.signature).await?;
+        let swaps = analyze_transaction(&update, sol_price, &config).await?;
+        if swaps.is_empty() {
+            println!("No swap instruction of the decoded dexes in {}", self.signature);
+        }
+        for swap in swaps {
+            let path = swap.path.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(".");
+            let mut analyzed = json!({ "instruction": path, "dex": swap.dex, "pair": swap.pair });
+            match swap.result {
+                Ok(swap_event) => analyzed["swap_event"] = json!(swap_event),
+                Err(e) => analyzed["skipped"] = json!(e.reason()),
+            }
+            println!("{}", serde_json::to_string_pretty(&analyzed)?);
+        }
+        Ok(())
+    }
+}
+
+/// snapshot_sol_price returns the SOL price persisted by the ingestor, 0 if there is none,
+/// leaving the WSOL quoted swaps unpriced
+async fn snapshot_sol_price() -> f64 {
+    let kv_store = match make_kv_store_from_env().await.context("Failed to make kv store") {
+        Ok(kv_store) => kv_store,
+        Err(e) => {
+            warn!("{e:?}, pass --sol-price to price the WSOL quoted swaps");
+            return 0.0;
+        }
+    };
+    restore_sol_price_snapshot(&kv_store).await.unwrap_or_else(|| {
+        warn!("No SOL price snapshot, pass --sol-price to price the WSOL quoted swaps");
+        0.0
+    })
+}
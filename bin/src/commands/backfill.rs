@@ -0,0 +1,44 @@
+use anyhow::bail;
+use chrono::NaiveDate;
+use clap::Parser;
+use dotenvy::dotenv;
+use sonar_db::make_db_from_env;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = "Sonar daily token rollups backfill")]
+#[command(propagate_version = true)]
+pub struct Command {
+    /// first day to roll up, `YYYY-MM-DD`
+    #[arg(long)]
+    from: NaiveDate,
+    /// last day to roll up, inclusive, `YYYY-MM-DD`
+    #[arg(long)]
+    to: NaiveDate,
+}
+
+impl Command {
+    /// Execute `backfill-rollups` command
+    pub async fn execute(self) -> anyhow::Result<()> {
+        dotenv().ok();
+        if self.from > self.to {
+            bail!("--from must not be after --to");
+        }
+        backfill_rollups(self.from, self.to).await
+    }
+}
+
+/// Roll up the swap events of each day of the range, the days whose swap events were
+/// already dropped keep their stored rollups
+async fn backfill_rollups(from: NaiveDate, to: NaiveDate) -> anyhow::Result<()> {
+    let db = Arc::new(make_db_from_env().await?);
+    // a day whose written rows are unknown leaves the total unknown
+    let mut total = Some(0);
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let rows = sonar_scheduler::job::aggregate_daily_token_rollups(db.clone(), date).await?;
+        total = total.zip(rows).map(|(total, rows)| total + rows);
+    }
+    info!(rows = ?total, %from, %to, "Backfilled daily token rollups");
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use anyhow::bail;
+use chrono::{NaiveDate, NaiveTime, TimeDelta};
+use clap::Parser;
+use dotenvy::dotenv;
+use sonar_db::make_db_from_env;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = "Sonar derived candlesticks backfill")]
+#[command(propagate_version = true)]
+pub struct Command {
+    /// first day to roll up, `YYYY-MM-DD`
+    #[arg(long)]
+    from: NaiveDate,
+    /// last day to roll up, inclusive, `YYYY-MM-DD`
+    #[arg(long)]
+    to: NaiveDate,
+}
+
+impl Command {
+    /// Execute `backfill-candlesticks` command
+    pub async fn execute(self) -> anyhow::Result<()> {
+        dotenv().ok();
+        if self.from > self.to {
+            bail!("--from must not be after --to");
+        }
+        backfill_candlesticks(self.from, self.to).await
+    }
+}
+
+/// Roll the 5m, 15m, 30m and 4h candlesticks of each day of the range up from the stored
+/// 1m and 1h ones, e.g. for the days aggregated before they were stored
+async fn backfill_candlesticks(from: NaiveDate, to: NaiveDate) -> anyhow::Result<()> {
+    let db = Arc::new(make_db_from_env().await?);
+    // a day whose written rows are unknown leaves the total unknown
+    let mut total = Some(0);
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let start_ts = date.and_time(NaiveTime::MIN).and_utc().timestamp();
+        let end_ts = start_ts + TimeDelta::days(1).num_seconds();
+        let rows = sonar_scheduler::job::derive_candlesticks(db.clone(), start_ts, end_ts).await?;
+        total = total.zip(rows).map(|(total, rows)| total + rows);
+    }
+    info!(rows = ?total, %from, %to, "Backfilled derived candlesticks");
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use clap::{Parser, Subcommand};
+use dotenvy::dotenv;
+use sonar_db::ck::ClickhouseConfig;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = "Sonar database maintenance")]
+#[command(propagate_version = true)]
+pub struct Command {
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand, Debug)]
+/// `sonar db` subcommands
+pub enum Subcommands {
+    /// collapse the duplicate token rows, keeping the latest row of each token,
+    /// run it with the ingestion stopped as the rows written meanwhile are lost
+    DedupeTokens {
+        /// only count the duplicate rows
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// rekey the candlesticks created without the interval in their sorting key,
+    /// run it with the ingestion stopped as the candlesticks written meanwhile are lost
+    RekeyCandlesticks,
+}
+
+impl Command {
+    /// Execute `db` command
+    pub async fn execute(self) -> anyhow::Result<()> {
+        dotenv().ok();
+        match self.command {
+            Subcommands::DedupeTokens { dry_run } => dedupe_tokens(dry_run).await,
+            Subcommands::RekeyCandlesticks => rekey_candlesticks().await,
+        }
+    }
+}
+
+/// Collapse the duplicate rows of the ClickHouse tokens table
+async fn dedupe_tokens(dry_run: bool) -> anyhow::Result<()> {
+    let db = ClickhouseConfig::from_env()?.connect();
+    let dedupe = db.dedupe_tokens(dry_run).await?;
+    let duplicates = dedupe.rows - dedupe.unique_rows;
+    if dry_run {
+        info!(
+            rows = dedupe.rows,
+            unique_rows = dedupe.unique_rows,
+            "Found {duplicates} duplicate token rows"
+        );
+    } else {
+        info!(
+            rows = dedupe.rows,
+            unique_rows = dedupe.unique_rows,
+            "Removed {duplicates} duplicate token rows"
+        );
+    }
+    Ok(())
+}
+
+/// Rekey the ClickHouse candlesticks table by interval
+async fn rekey_candlesticks() -> anyhow::Result<()> {
+    let db = ClickhouseConfig::from_env()?.connect();
+    db.rekey_candlesticks().await?;
+    info!("The candlesticks are keyed by interval");
+    Ok(())
+}
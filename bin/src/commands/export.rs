@@ -0,0 +1,125 @@
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use dotenvy::dotenv;
+use futures::StreamExt;
+use sonar_db::{make_db_from_env, TradeFilter, TRADE_CSV_HEADER};
+use std::path::PathBuf;
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+};
+use tracing::{info, warn};
+
+/// Print progress every `PROGRESS_ROWS` rows
+const PROGRESS_ROWS: u64 = 100_000;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = "Sonar data export")]
+#[command(propagate_version = true)]
+pub struct Command {
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand, Debug)]
+/// `sonar export` subcommands
+pub enum Subcommands {
+    /// export swap events of a token or a pair
+    Trades(TradesArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TradesArgs {
+    /// token mint to export
+    #[arg(long, required_unless_present = "pair")]
+    token: Option<String>,
+    /// pair address to export
+    #[arg(long)]
+    pair: Option<String>,
+    /// start unix timestamp, inclusive
+    #[arg(long)]
+    from: Option<u64>,
+    /// end unix timestamp, exclusive
+    #[arg(long)]
+    to: Option<u64>,
+    /// maximum number of rows to export
+    #[arg(long)]
+    limit: Option<usize>,
+    /// output format
+    #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+    format: ExportFormat,
+    /// output file
+    #[arg(long)]
+    out: PathBuf,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl Command {
+    /// Execute `export` command
+    pub async fn execute(self) -> anyhow::Result<()> {
+        dotenv().ok();
+        match self.command {
+            Subcommands::Trades(args) => export_trades(args).await,
+        }
+    }
+}
+
+/// Stream trades into the output file, flushing it on completion or Ctrl+C
+async fn export_trades(args: TradesArgs) -> anyhow::Result<()> {
+    if args.token.is_none() && args.pair.is_none() {
+        bail!("Either --token or --pair must be set");
+    }
+    let db = make_db_from_env().await?;
+    let filter = TradeFilter {
+        token: args.token,
+        pair: args.pair,
+        time_from: args.from,
+        time_to: args.to,
+        limit: args.limit,
+        ..Default::default()
+    };
+
+    let file = File::create(&args.out)
+        .await
+        .with_context(|| format!("Failed to create {}", args.out.display()))?;
+    let mut writer = BufWriter::new(file);
+    if args.format == ExportFormat::Csv {
+        writer.write_all(format!("{TRADE_CSV_HEADER}\n").as_bytes()).await?;
+    }
+
+    let mut rows: u64 = 0;
+    let mut trades = db.stream_trades(filter);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        tokio::select! {
+            trade = trades.next() => {
+                let Some(trade) = trade else { break };
+                let trade = trade?;
+                let line = match args.format {
+                    ExportFormat::Csv => trade.to_csv(),
+                    ExportFormat::Jsonl => serde_json::to_string(&trade)?,
+                };
+                writer.write_all(format!("{line}\n").as_bytes()).await?;
+                rows += 1;
+                if rows % PROGRESS_ROWS == 0 {
+                    info!(rows, "Exported trades");
+                }
+            }
+            _ = &mut ctrl_c => {
+                warn!(rows, "Interrupted, closing the export file");
+                break;
+            }
+        }
+    }
+
+    writer.flush().await?;
+    writer.into_inner().sync_all().await?;
+    info!(rows, out = %args.out.display(), "Export completed");
+    Ok(())
+}
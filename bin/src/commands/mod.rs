@@ -1,4 +1,10 @@
+pub mod analyze;
 pub mod api;
+pub mod backfill;
+pub mod backfill_candlesticks;
+pub mod db;
+pub mod doctor;
+pub mod export;
 pub mod node;
 pub mod scheduler;
 pub mod streams;
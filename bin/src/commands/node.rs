@@ -1,9 +1,9 @@
 use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use sonar_db::{make_db_from_env, make_kv_store_from_env, make_message_queue_from_env};
-use sonar_ingestor::prelude::*;
+use sonar_ingestor::{drain_and_close, prelude::*, shutdown_signal, Config, Storages};
 use sonar_sol_price::SolPriceCache;
 use std::sync::Arc;
+use tokio_util::task::TaskTracker;
 use tracing::{error, info};
 
 #[derive(Parser, Debug)]
@@ -12,6 +12,9 @@ use tracing::{error, info};
 pub struct Command {
     #[clap(subcommand)]
     command: Subcommands,
+    /// Process the swaps but log them instead of writing them to ClickHouse and Redis
+    #[clap(long, global = true)]
+    dry_run: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -24,7 +27,7 @@ pub enum Subcommands {
     /// websocket
     Ws,
     /// rpc transaction crawler
-    Transaction,
+    Transaction(TransactionCrawlerArgs),
     /// rpc block crawler
     #[cfg(feature = "block")]
     Block,
@@ -34,50 +37,91 @@ impl Command {
     /// Execute `node` command
     pub async fn execute(self) -> anyhow::Result<()> {
         dotenv().ok();
-        let db = make_db_from_env().await?;
-        info!("db connected");
-        let kv_store = make_kv_store_from_env().await?;
-        info!("kv connected");
-        let message_queue = make_message_queue_from_env().await?;
-        info!("message queue connected");
+        let config = Config::from_env(self.dry_run)?;
+        let Storages { db, kv_store, message_queue } =
+            Storages::connect(config.storages.as_ref()).await?;
 
-        let kv_store = Arc::new(kv_store);
-        let message_queue = Arc::new(message_queue);
-        let db = Arc::new(db);
-
-        let price_cache = SolPriceCache::new(Some(kv_store.clone()), Some(message_queue.clone()));
+        let price_cache =
+            SolPriceCache::new_with_snapshot(Some(kv_store.clone()), Some(message_queue.clone()))
+                .await;
         let price_cache = Arc::new(price_cache);
         let sol_price = price_cache.get_price().await;
         info!("Solana price {}", sol_price);
         assert!(sol_price > 0.0, "Solana price should initialize");
 
+        // the crawls of the transaction command end, the other datasources run until shutdown
+        let crawl_progress = Arc::new(CrawlProgress::default());
+        let is_crawl = matches!(self.command, Subcommands::Transaction(_));
+        // the swap tasks are drained on shutdown, before the db is closed
+        let tasks = TaskTracker::new();
+
         let mut pipeline = match self.command {
             Subcommands::HeliusWs => {
                 info!("Starting helius atlas pipeline...");
-                let datasource = make_helius_ws_datasource();
-                build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+                let datasource = make_helius_ws_datasource()?;
+                build_pipeline(
+                    vec![datasource],
+                    db.clone(),
+                    kv_store.clone(),
+                    message_queue.clone(),
+                    tasks.clone(),
+                    &config,
+                )?
             }
             Subcommands::Geyser => {
                 info!("Starting geyser pipeline...");
-                let datasource = make_geyser_datasource();
-                build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+                let datasource = make_geyser_datasource()?;
+                build_pipeline(
+                    vec![datasource],
+                    db.clone(),
+                    kv_store.clone(),
+                    message_queue.clone(),
+                    tasks.clone(),
+                    &config,
+                )?
             }
             #[cfg(feature = "ws")]
             Subcommands::Ws => {
                 info!("Starting ws pipeline...");
-                let datasource = make_ws_datasource();
-                build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+                let datasource = make_ws_datasource()?;
+                build_pipeline(
+                    vec![datasource],
+                    db.clone(),
+                    kv_store.clone(),
+                    message_queue.clone(),
+                    tasks.clone(),
+                    &config,
+                )?
             }
-            Subcommands::Transaction => {
-                info!("Starting rpc transaction crawler pipeline...");
-                let datasource = make_transaction_crawler_datasource();
-                build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+            Subcommands::Transaction(args) => {
+                let crawler_config = TransactionCrawlerConfig::from_args(&args)?;
+                info!(
+                    addresses = crawler_config.addresses.len(),
+                    "Starting rpc transaction crawler pipeline..."
+                );
+                let datasources =
+                    make_transaction_crawler_datasources(&crawler_config, crawl_progress.clone())?;
+                build_pipeline(
+                    datasources,
+                    db.clone(),
+                    kv_store.clone(),
+                    message_queue.clone(),
+                    tasks.clone(),
+                    &config,
+                )?
             }
             #[cfg(feature = "block")]
             Subcommands::Block => {
                 info!("Starting rpc block crawler pipeline...");
-                let datasource = make_block_crawler_datasource();
-                build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+                let datasource = make_block_crawler_datasource()?;
+                build_pipeline(
+                    vec![datasource],
+                    db.clone(),
+                    kv_store.clone(),
+                    message_queue.clone(),
+                    tasks.clone(),
+                    &config,
+                )?
             }
         };
         tokio::spawn(async move {
@@ -86,7 +130,18 @@ impl Command {
             }
         });
 
-        pipeline.run().await?;
-        Ok(())
+        let result: anyhow::Result<()> = tokio::select! {
+            result = pipeline.run() => result.map_err(Into::into),
+            _ = crawl_progress.finished(), if is_crawl => {
+                info!("Completed every transaction crawl");
+                Ok(())
+            }
+            _ = shutdown_signal() => {
+                info!("Received shutdown signal at {:?}", chrono::Utc::now());
+                Ok(())
+            }
+        };
+        drain_and_close(&tasks, &db, config.db_close_timeout).await;
+        result
     }
 }
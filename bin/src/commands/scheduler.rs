@@ -1,12 +1,15 @@
 use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use sonar_db::make_db_from_env;
+use sonar_db::{make_db_from_env, make_db_with_config};
 use sonar_scheduler::{
-    job::{run_jobs, stop_jobs},
-    shutdown_signal_with_handler, JobScheduler,
+    job::{
+        make_job_stores, prune_inactive_token_events, run_jobs, stop_jobs,
+        DEFAULT_PRUNE_INACTIVE_DAYS, DEFAULT_PRUNE_OLDER_THAN_DAYS,
+    },
+    serve_status, shutdown_signal_with_handler, Config, JobScheduler, JobStatusRegistry,
 };
 use std::sync::Arc;
-use tracing::info;
+use tracing::{error, info};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = "Sonar scheduler")]
@@ -21,28 +24,72 @@ pub struct Command {
 pub enum Subcommands {
     /// candlestick aggregator
     Candlestick,
+    /// prune swap events of inactive tokens
+    Prune {
+        /// tokens without swaps in the last `inactive_days` are considered inactive
+        #[arg(long, default_value_t = DEFAULT_PRUNE_INACTIVE_DAYS)]
+        inactive_days: u32,
+        /// only swap events older than `older_than_days` are deleted
+        #[arg(long, default_value_t = DEFAULT_PRUNE_OLDER_THAN_DAYS)]
+        older_than_days: u32,
+        /// only print the number of rows that would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 impl Command {
     /// Execute `node` command
     pub async fn execute(self) -> anyhow::Result<()> {
         dotenv().ok();
-        let graceful_shutdown_timeout = tokio::time::Duration::from_secs(5);
 
-        let db = make_db_from_env().await.expect("Failed to make db");
-        let db = Arc::new(db);
+        if let Subcommands::Prune { inactive_days, older_than_days, dry_run } = self.command {
+            // the prune only needs the database, not the redis of the jobs
+            let db = Arc::new(make_db_from_env().await?);
+            if dry_run {
+                let rows = db.estimate_prunable_rows(inactive_days, older_than_days).await?;
+                info!(rows, inactive_days, older_than_days, "Estimated prunable rows");
+                return Ok(());
+            }
+            return prune_inactive_token_events(db, inactive_days, older_than_days).await;
+        }
+
+        let config = Config::from_env()?;
+        let db = Arc::new(make_db_with_config(&config.database).await?);
+        let (kv_store, message_queue) = make_job_stores(config.redis.as_ref()).await?;
+        let status = JobStatusRegistry::default();
+        if let Some(port) = config.status_port {
+            let status = status.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_status(port, status).await {
+                    error!(error = ?e, "Job status server stopped");
+                }
+            });
+        }
 
         let mut scheduler = JobScheduler::new().await.expect("Could not create scheduler");
         info!("Starting jobs");
-        let jobs = run_jobs(&mut scheduler, db).await.expect("Could not run jobs");
+        let jobs = run_jobs(
+            &mut scheduler,
+            db.clone(),
+            Arc::new(kv_store),
+            Arc::new(message_queue),
+            &config,
+            &status,
+        )
+        .await
+        .expect("Could not run jobs");
 
         // Wait for shutdown signal
         shutdown_signal_with_handler(|| async {
             let stop_time = tokio::time::Instant::now();
-            stop_jobs(&mut scheduler, jobs, graceful_shutdown_timeout)
+            stop_jobs(&mut scheduler, jobs, config.shutdown_timeout)
                 .await
                 .expect("Could not stop jobs");
             info!("Jobs stopped in {:?}ms", stop_time.elapsed().as_millis());
+            if let Err(e) = db.close().await {
+                error!(error = ?e, "Failed to close db");
+            }
         })
         .await;
 
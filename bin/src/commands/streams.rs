@@ -29,17 +29,17 @@ impl Command {
     pub async fn execute(self) -> anyhow::Result<()> {
         dotenv().ok();
 
-        let app = App::new();
+        let app = App::from_env()?;
 
         match self.command {
             Subcommands::Geyser => {
                 info!("Starting geyser pipeline...");
-                let datasources = vec![make_geyser_datasource()];
+                let datasources = vec![make_geyser_datasource()?];
                 app.run(datasources).await.context("Failed to run app")?;
             }
             Subcommands::Ws => {
                 info!("Starting ws datasource...");
-                let datasources = vec![make_ws_datasource()];
+                let datasources = vec![make_ws_datasource()?];
                 app.run(datasources).await.context("Failed to run app")?;
             }
         };
@@ -0,0 +1,142 @@
+use crate::errors::ApiError;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{env::var, sync::Arc};
+
+/// The header carrying the admin or the export api key
+pub const ADMIN_API_KEY_HEADER: &str = "x-api-key";
+
+/// The api key guarding the admin routes, read from `ADMIN_API_KEY`.
+/// The admin routes reject every request when no key is configured.
+#[derive(Clone, Default)]
+pub struct AdminAuth {
+    api_key: Option<Arc<str>>,
+}
+
+impl AdminAuth {
+    pub fn new(api_key: Option<String>) -> Self {
+        Self { api_key: api_key.filter(|key| !key.is_empty()).map(Arc::from) }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(var("ADMIN_API_KEY").ok())
+    }
+
+    /// is_authorized returns true if `api_key` matches the configured key
+    pub fn is_authorized(&self, api_key: Option<&str>) -> bool {
+        match (&self.api_key, api_key) {
+            (Some(expected), Some(given)) => {
+                constant_time_eq(expected.as_bytes(), given.as_bytes())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The api key guarding the trade exports, read from `EXPORT_API_KEY`. A scope of its own,
+/// the consumers of the exports don't hold the admin key.
+/// The exports reject every request when no key is configured.
+#[derive(Clone, Default)]
+pub struct ExportAuth(AdminAuth);
+
+impl ExportAuth {
+    pub fn new(api_key: Option<String>) -> Self {
+        Self(AdminAuth::new(api_key))
+    }
+
+    /// is_authorized returns true if `api_key` matches the configured export key
+    pub fn is_authorized(&self, api_key: Option<&str>) -> bool {
+        self.0.is_authorized(api_key)
+    }
+}
+
+/// Compares in time independent of where the inputs differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Middleware rejecting requests without the admin api key
+pub async fn require_admin_key(
+    State(auth): State<AdminAuth>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !auth.is_authorized(request_api_key(&request)) {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Middleware rejecting requests without the export api key
+pub async fn require_export_key(
+    State(auth): State<ExportAuth>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !auth.is_authorized(request_api_key(&request)) {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(next.run(request).await)
+}
+
+/// request_api_key returns the api key header of the request
+fn request_api_key(request: &Request) -> Option<&str> {
+    request.headers().get(ADMIN_API_KEY_HEADER).and_then(|v| v.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app(auth: AdminAuth) -> Router {
+        Router::new()
+            .route("/admin", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(auth, require_admin_key))
+    }
+
+    async fn call(auth: AdminAuth, api_key: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().uri("/admin");
+        if let Some(api_key) = api_key {
+            request = request.header(ADMIN_API_KEY_HEADER, api_key);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        app(auth).oneshot(request).await.expect("Failed to call endpoint").status()
+    }
+
+    #[tokio::test]
+    async fn test_require_admin_key() {
+        let auth = AdminAuth::new(Some("secret".to_string()));
+        assert_eq!(call(auth.clone(), Some("secret")).await, StatusCode::OK);
+        assert_eq!(call(auth.clone(), Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(auth, None).await, StatusCode::UNAUTHORIZED);
+
+        // admin routes are disabled without a configured key
+        assert_eq!(call(AdminAuth::new(None), Some("")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            call(AdminAuth::new(Some(String::new())), Some("")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_export_key() {
+        let app = Router::new().route("/export", get(|| async { "ok" })).route_layer(
+            middleware::from_fn_with_state(
+                ExportAuth::new(Some("export".to_string())),
+                require_export_key,
+            ),
+        );
+        let call = |api_key: &str| {
+            let request = Request::builder().uri("/export").header(ADMIN_API_KEY_HEADER, api_key);
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        assert_eq!(call("export").await.unwrap().status(), StatusCode::OK);
+        // the admin key doesn't open the exports
+        assert_eq!(call("admin").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,207 @@
+//! The settings of the api, read and validated once at startup
+use crate::{
+    handlers::{
+        candlesticks::{DEFAULT_COMPACT_DIGITS, DEFAULT_MAX_CANDLESTICK_BUCKETS},
+        swap::DEFAULT_EXPORT_MAX_ROWS,
+    },
+    timeout::RequestTimeouts,
+    ws::WsAdapter,
+};
+use sonar_auth::{ApiKeyAuthorizer, RoomPolicy, WsAuthConfig};
+use sonar_db::{ConfigError, DatabaseConfig, EnvReader, OutlierPolicy, RedisConfig};
+use sonar_token_metadata::DEFAULT_BACKGROUND_RESOLVE_CONCURRENCY;
+
+/// How old a stored price may be before it is refreshed when unset
+pub const DEFAULT_PRICE_MAX_STALENESS_SECS: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The port the server listens on
+    pub port: u16,
+    /// The redis of the kv store and the subscriber, optional with the local adapter
+    pub redis: Option<RedisConfig>,
+    /// The socket.io adapter fanning the emits out
+    pub ws_adapter: WsAdapter,
+    /// The redis of the socket.io adapter, set whenever `ws_adapter` is redis
+    pub redis_adapter_url: Option<String>,
+    /// The key of the admin routes, the admin routes reject every request when unset
+    pub admin_api_key: Option<String>,
+    /// The key of the trade exports, the exports reject every request when unset
+    pub export_api_key: Option<String>,
+    pub price_max_staleness_secs: u64,
+    pub max_candlestick_buckets: usize,
+    /// The significant digits of the compact candlesticks, between 1 and 17
+    pub compact_digits: u32,
+    pub export_max_rows: usize,
+    /// How many unknown tokens are resolved in the background at a time
+    pub token_resolve_concurrency: usize,
+    pub request_timeouts: RequestTimeouts,
+    /// The clamping of the candlesticks built from swap events
+    pub outlier_policy: OutlierPolicy,
+    /// The auth of the socket.io clients and the rooms they may join
+    pub ws_auth: WsAuthConfig,
+    /// The database the handlers read
+    pub database: DatabaseConfig,
+}
+
+/// read_ws_auth reads `WS_AUTH_SECRET`, `WS_API_KEYS` and `WS_ROOM_TIERS`
+fn read_ws_auth(env: &mut EnvReader) -> WsAuthConfig {
+    let api_keys = env.var("WS_API_KEYS").and_then(|keys| {
+        env.merge(ApiKeyAuthorizer::parse_keys(&keys).map_err(|e| format!("WS_API_KEYS: {e}")))
+    });
+    let room_policy = env.var("WS_ROOM_TIERS").and_then(|rules| {
+        env.merge(RoomPolicy::parse(&rules).map_err(|e| format!("WS_ROOM_TIERS: {e}")))
+    });
+    WsAuthConfig {
+        secret: env.var("WS_AUTH_SECRET"),
+        api_keys: api_keys.unwrap_or_default(),
+        room_policy: room_policy.unwrap_or_default(),
+    }
+}
+
+impl Config {
+    /// from_env reads `PORT`, the redis settings, `WS_ADAPTER`, `REDIS_ADAPTER_URL`,
+    /// `REDIS_URL` being required by the redis adapter only,
+    /// `ADMIN_API_KEY`, `EXPORT_API_KEY`, the limits of the handlers, the request timeouts,
+    /// the candlestick clamping, the websocket auth and the database settings, listing every
+    /// invalid one
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_reader(EnvReader::from_env())
+    }
+
+    pub fn from_reader(mut env: EnvReader) -> Result<Self, ConfigError> {
+        let port = env.parse_required::<u16>("PORT");
+        let ws_adapter = WsAdapter::read_env(&mut env);
+        let redis_adapter_url = env.var("REDIS_ADAPTER_URL");
+        env.check(
+            ws_adapter != WsAdapter::Redis || redis_adapter_url.is_some(),
+            "REDIS_ADAPTER_URL must be set with the redis WS_ADAPTER",
+        );
+        let compact_digits = env.parse_or("CANDLESTICK_COMPACT_DIGITS", DEFAULT_COMPACT_DIGITS);
+        env.check(
+            (1..=17).contains(&compact_digits),
+            format!("CANDLESTICK_COMPACT_DIGITS must be between 1 and 17, got {compact_digits}"),
+        );
+        let token_resolve_concurrency =
+            env.parse_or("TOKEN_RESOLVE_CONCURRENCY", DEFAULT_BACKGROUND_RESOLVE_CONCURRENCY);
+        env.check(token_resolve_concurrency > 0, "TOKEN_RESOLVE_CONCURRENCY must not be 0");
+
+        let config = Self {
+            port: port.unwrap_or_default(),
+            redis: match ws_adapter {
+                WsAdapter::Redis => Some(RedisConfig::read_env(&mut env)),
+                WsAdapter::Local => RedisConfig::read_env_optional(&mut env),
+            },
+            ws_adapter,
+            redis_adapter_url,
+            admin_api_key: env.var("ADMIN_API_KEY"),
+            export_api_key: env.var("EXPORT_API_KEY"),
+            price_max_staleness_secs: env
+                .parse_or("PRICE_MAX_STALENESS_SECS", DEFAULT_PRICE_MAX_STALENESS_SECS),
+            max_candlestick_buckets: env
+                .parse_or("MAX_CANDLESTICK_BUCKETS", DEFAULT_MAX_CANDLESTICK_BUCKETS),
+            compact_digits,
+            export_max_rows: env.parse_or("TRADES_EXPORT_MAX_ROWS", DEFAULT_EXPORT_MAX_ROWS),
+            token_resolve_concurrency,
+            request_timeouts: RequestTimeouts::read_env(&mut env),
+            outlier_policy: env.outlier_policy(),
+            ws_auth: read_ws_auth(&mut env),
+            database: DatabaseConfig::read_env(&mut env),
+        };
+        env.finish(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn read(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        Config::from_reader(EnvReader::from_vars(vars))
+    }
+
+    const CLICKHOUSE_URL: (&str, &str) = ("CLICKHOUSE_URL", "http://localhost:8123");
+
+    #[test]
+    fn test_config_defaults() {
+        let config =
+            read(&[("PORT", "8080"), ("REDIS_URL", "redis://localhost:6379"), CLICKHOUSE_URL])
+                .unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.redis.unwrap().url, "redis://localhost:6379");
+        assert_eq!(config.ws_adapter, WsAdapter::Local);
+        assert_eq!(config.admin_api_key, None);
+        assert_eq!(config.export_api_key, None);
+        assert_eq!(config.price_max_staleness_secs, DEFAULT_PRICE_MAX_STALENESS_SECS);
+        assert_eq!(config.max_candlestick_buckets, DEFAULT_MAX_CANDLESTICK_BUCKETS);
+        assert_eq!(config.compact_digits, DEFAULT_COMPACT_DIGITS);
+        assert_eq!(config.export_max_rows, DEFAULT_EXPORT_MAX_ROWS);
+        assert_eq!(config.token_resolve_concurrency, DEFAULT_BACKGROUND_RESOLVE_CONCURRENCY);
+        assert_eq!(config.request_timeouts, RequestTimeouts::default());
+        assert_eq!(config.outlier_policy, OutlierPolicy::default());
+        assert_eq!(config.ws_auth, WsAuthConfig::default());
+
+        let config = read(&[
+            ("PORT", "3000"),
+            ("REDIS_URL", "redis://localhost:6379"),
+            ("REDIS_ADAPTER_URL", "redis://adapter:6379"),
+            ("API_REQUEST_TIMEOUT_MS", "2000"),
+            CLICKHOUSE_URL,
+        ])
+        .unwrap();
+        assert_eq!(config.ws_adapter, WsAdapter::Redis);
+        assert_eq!(config.request_timeouts.heavy, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_every_problem_is_listed() {
+        let error = read(&[
+            ("PORT", "http"),
+            ("WS_ADAPTER", "redis"),
+            ("CANDLESTICK_COMPACT_DIGITS", "20"),
+            ("TOKEN_RESOLVE_CONCURRENCY", "0"),
+            ("MAX_CANDLESTICK_BUCKETS", "-1"),
+            ("API_HEAVY_REQUEST_TIMEOUT_MS", "soon"),
+            ("CANDLESTICK_CLAMP_QUANTILE", "0.2"),
+            ("WS_API_KEYS", "k1"),
+            ("WS_ROOM_TIERS", "pair:=gold"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![
+                "PORT must be a valid u16, got \"http\"",
+                "REDIS_ADAPTER_URL must be set with the redis WS_ADAPTER",
+                "CANDLESTICK_COMPACT_DIGITS must be between 1 and 17, got 20",
+                "TOKEN_RESOLVE_CONCURRENCY must not be 0",
+                "REDIS_URL must be set",
+                "MAX_CANDLESTICK_BUCKETS must be a valid usize, got \"-1\"",
+                "API_HEAVY_REQUEST_TIMEOUT_MS must be a positive number of milliseconds, got \"soon\"",
+                "CANDLESTICK_CLAMP_QUANTILE must be between 0.5 and 1, got 0.2",
+                "WS_API_KEYS: Invalid API key entry",
+                "WS_ROOM_TIERS: Invalid tier in room rule: pair:=gold",
+                "CLICKHOUSE_URL must be set",
+            ]
+        );
+
+        let error = read(&[]).unwrap_err();
+        assert_eq!(error.problems, vec!["PORT must be set", "CLICKHOUSE_URL must be set"]);
+    }
+
+    #[test]
+    fn test_local_adapter_without_redis() {
+        let config = read(&[("PORT", "8080"), CLICKHOUSE_URL]).unwrap();
+        assert_eq!(config.ws_adapter, WsAdapter::Local);
+        assert_eq!(config.redis, None);
+
+        // the redis adapter shares the published messages, it needs the redis of the subscriber
+        let error = read(&[
+            ("PORT", "8080"),
+            ("REDIS_ADAPTER_URL", "redis://adapter:6379"),
+            CLICKHOUSE_URL,
+        ])
+        .unwrap_err();
+        assert_eq!(error.problems, vec!["REDIS_URL must be set"]);
+    }
+}
@@ -1,95 +1,296 @@
-// https://github.com/LemmyNet/lemmy/blob/main/crates/utils/src/error.rs#L73
-use axum::{extract::rejection::JsonRejection, http::StatusCode};
-use serde_json::json;
-use std::fmt::{Debug, Display};
-use tracing_error::SpanTrace;
+use axum::{
+    body::Body,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Request,
+    },
+    http::{header::CONTENT_LENGTH, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use tower_http::request_id::RequestId;
+use tracing::error;
+use utoipa::ToSchema;
 
 #[allow(dead_code)]
-pub type SrvResult<T> = Result<T, SonarError>;
+pub type SrvResult<T> = Result<T, ApiError>;
 
-// https://docs.rs/tracing-error/latest/tracing_error/
-#[allow(dead_code)]
+/// The error returned by every API handler, rendered as an [`ApiErrorBody`]
 #[derive(Debug, thiserror::Error)]
-pub enum SonarErrorKind {
-    // The request body contained invalid JSON
-    #[error("{0}")]
-    JsonRejection(#[from] JsonRejection),
+pub enum ApiError {
+    #[error("{0} not found")]
+    NotFound(String),
 
-    #[error("{0}")]
-    ValidationError(#[from] validator::ValidationErrors),
+    #[error("invalid parameter `{field}`: {reason}")]
+    InvalidParameter { field: String, reason: String },
 
-    #[error("the data for key {0} is not found")]
-    NotFound(String),
+    // the address isn't echoed, it may be anything the client sent
+    #[error("invalid address `{field}`: expected a base58 public key")]
+    InvalidAddress { field: String },
+
+    // the source is logged rather than returned to the client
+    #[error("upstream service unavailable")]
+    Upstream(#[source] anyhow::Error),
+
+    #[error("too many requests")]
+    RateLimited,
+
+    #[error("missing or invalid api key")]
+    Unauthorized,
+
+    #[error("request timed out after {}ms", .0.as_millis())]
+    Timeout(std::time::Duration),
+
+    #[error("{0} is not supported by the storage backend")]
+    NotImplemented(String),
+
+    #[error("internal server error")]
+    Internal(#[source] anyhow::Error),
+}
+
+/// The machine-readable error code of an [`ApiErrorBody`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiErrorCode {
+    NotFound,
+    InvalidParameter,
+    InvalidAddress,
+    Upstream,
+    RateLimited,
+    Unauthorized,
+    Timeout,
+    NotImplemented,
+    Internal,
+}
+
+/// The JSON body of an error response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ApiErrorBody {
+    pub code: ApiErrorCode,
+    pub message: String,
+    /// The `x-request-id` of the request, to be quoted when reporting the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
 
-    #[error("{1}")]
-    Custom(StatusCode, String),
+impl ApiError {
+    pub fn invalid_parameter(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        ApiError::InvalidParameter { field: field.into(), reason: reason.into() }
+    }
+
+    pub fn code(&self) -> ApiErrorCode {
+        match self {
+            ApiError::NotFound(_) => ApiErrorCode::NotFound,
+            ApiError::InvalidParameter { .. } => ApiErrorCode::InvalidParameter,
+            ApiError::InvalidAddress { .. } => ApiErrorCode::InvalidAddress,
+            ApiError::Upstream(_) => ApiErrorCode::Upstream,
+            ApiError::RateLimited => ApiErrorCode::RateLimited,
+            ApiError::Unauthorized => ApiErrorCode::Unauthorized,
+            ApiError::Timeout(_) => ApiErrorCode::Timeout,
+            ApiError::NotImplemented(_) => ApiErrorCode::NotImplemented,
+            ApiError::Internal(_) => ApiErrorCode::Internal,
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidParameter { .. } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidAddress { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Upstream(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
-    #[error("{0}")]
-    Any(#[from] anyhow::Error),
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        if let Some(sonar_db::StorageError::NotSupported(operation)) = sonar_db::storage_error(&e) {
+            ApiError::NotImplemented(operation)
+        } else if sonar_db::is_unavailable(&e) {
+            ApiError::Upstream(e)
+        } else {
+            ApiError::Internal(e)
+        }
+    }
+}
+
+impl From<validator::ValidationErrors> for ApiError {
+    fn from(e: validator::ValidationErrors) -> Self {
+        // report the first invalid field, the errors are keyed by field name
+        let first = e.field_errors().into_iter().min_by(|(a, _), (b, _)| a.cmp(b));
+        match first {
+            Some((field, errors)) => {
+                let reason = errors
+                    .iter()
+                    .map(|e| e.message.as_ref().map_or(e.code.to_string(), |m| m.to_string()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                ApiError::invalid_parameter(field.to_string(), reason)
+            }
+            None => ApiError::invalid_parameter("request", e.to_string()),
+        }
+    }
+}
 
-    #[error("storage error: `{0}`")]
-    StorageError(#[from] sonar_db::StorageError),
+impl From<sonar_db::StorageError> for ApiError {
+    fn from(e: sonar_db::StorageError) -> Self {
+        anyhow::Error::new(e).into()
+    }
+}
 
-    #[error("invalid query: `{0}`")]
-    InvalidQuery(String),
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        ApiError::invalid_parameter("body", e.body_text())
+    }
+}
 
-    #[error("invalid json: `{0}`")]
-    InvalidJson(#[from] serde_json::Error),
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        ApiError::invalid_parameter("query", e.body_text())
+    }
 }
 
-#[derive(Debug)]
-pub struct SonarError {
-    pub error_kind: SonarErrorKind,
-    pub inner: anyhow::Error,
-    pub context: SpanTrace,
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> Self {
+        ApiError::invalid_parameter("path", e.body_text())
+    }
 }
 
-impl Display for SonarError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}: ", &self.error_kind)?;
-        // print anyhow including trace
-        // https://docs.rs/anyhow/latest/anyhow/struct.Error.html#display-representations
-        // this will print the anyhow trace (only if it exists)
-        // and if RUST_BACKTRACE=1, also a full backtrace
-        writeln!(f, "{:?}", self.inner)?;
-        // writeln!(f, "source {:?}", self.inner.backtrace())?;
-        // print the tracing span trace
-        std::fmt::Display::fmt(&self.context, f)
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status_code = self.status_code();
+        match &self {
+            ApiError::Upstream(e) | ApiError::Internal(e) => error!(error = ?e, "{}", self),
+            _ => {}
+        }
+        let body = ApiErrorBody { code: self.code(), message: self.to_string(), request_id: None };
+        let mut response = (status_code, Json(body.clone())).into_response();
+        // picked up by `attach_request_id` once the request id is known
+        response.extensions_mut().insert(body);
+        response
     }
 }
 
-impl<T> From<T> for SonarError
-where
-    T: Into<SonarErrorKind>,
-{
-    fn from(t: T) -> Self {
-        let into = t.into();
-        SonarError {
-            inner: anyhow::anyhow!("{:?}", &into),
-            error_kind: into,
-            context: SpanTrace::capture(),
+/// Middleware adding the request id set by `SetRequestIdLayer` to error bodies
+pub async fn attach_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_string);
+    let response = next.run(request).await;
+
+    let (Some(request_id), Some(body)) =
+        (request_id, response.extensions().get::<ApiErrorBody>().cloned())
+    else {
+        return response;
+    };
+    let body = ApiErrorBody { request_id: Some(request_id), ..body };
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    match serde_json::to_vec(&body) {
+        Ok(bytes) => Response::from_parts(parts, Body::from(bytes)),
+        Err(e) => {
+            error!("Failed to serialize error body: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
 
-impl axum::response::IntoResponse for SonarError {
-    fn into_response(self) -> axum::response::Response {
-        let status_code = match self.error_kind {
-            SonarErrorKind::JsonRejection(_) => StatusCode::BAD_REQUEST,
-            SonarErrorKind::InvalidQuery(_) => StatusCode::BAD_REQUEST,
-            SonarErrorKind::Custom(code, _) => code,
-            SonarErrorKind::ValidationError(_) => StatusCode::BAD_REQUEST,
-            SonarErrorKind::NotFound(_) => StatusCode::NOT_FOUND,
-            SonarErrorKind::Any(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            SonarErrorKind::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        let body = axum::Json(json!({
-            "success": false,
-            "code": status_code.as_u16(),
-            "error": status_code.canonical_reason().unwrap_or("Unknown").to_string(),
-            "message": self.error_kind.to_string(),
-        }));
-        (status_code, body).into_response()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, http::HeaderValue, middleware, routing::get, Router};
+    use serde::Deserialize;
+    use tower::{ServiceBuilder, ServiceExt};
+    use tower_http::request_id::{MakeRequestUuid, SetRequestIdLayer};
+    use validator::Validate;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct TokenQuery {
+        #[validate(length(min = 10))]
+        token: String,
+    }
+
+    async fn get_token(query: Query<TokenQuery>) -> Result<Json<()>, ApiError> {
+        query.validate()?;
+        Err(ApiError::NotFound(format!("token {}", query.token)))
+    }
+
+    async fn get_internal() -> Result<Json<()>, ApiError> {
+        Err(anyhow::anyhow!("Failed to decode row").into())
+    }
+
+    async fn get_not_supported() -> Result<Json<()>, ApiError> {
+        let e = anyhow::Error::new(sonar_db::StorageError::NotSupported("get_pair_detail".into()));
+        Err(e.context("Failed to get the pair").into())
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/token", get(get_token))
+            .route("/internal", get(get_internal))
+            .route("/not-supported", get(get_not_supported))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                    .layer(middleware::from_fn(attach_request_id)),
+            )
+    }
+
+    async fn call(uri: &str) -> (StatusCode, HeaderValue, ApiErrorBody) {
+        let request = Request::builder()
+            .uri(uri)
+            .header("x-request-id", "test-request-id")
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.expect("Failed to call endpoint");
+        let status = response.status();
+        let content_type = response.headers()["content-type"].clone();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice::<ApiErrorBody>(&bytes).expect("Expected error body");
+        (status, content_type, body)
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let (status, content_type, body) = call("/token?token=2Y6GkQJR93PNL1iYwGcjggoaB").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body.code, ApiErrorCode::NotFound);
+        assert_eq!(body.message, "token 2Y6GkQJR93PNL1iYwGcjggoaB not found");
+        assert_eq!(body.request_id.as_deref(), Some("test-request-id"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_parameter() {
+        let (status, _, body) = call("/token?token=short").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.code, ApiErrorCode::InvalidParameter);
+        assert_eq!(body.message, "invalid parameter `token`: length");
+        assert_eq!(body.request_id.as_deref(), Some("test-request-id"));
+    }
+
+    #[tokio::test]
+    async fn test_internal() {
+        let (status, _, body) = call("/internal").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body.code, ApiErrorCode::Internal);
+        // the cause is logged, not returned
+        assert_eq!(body.message, "internal server error");
+    }
+
+    #[tokio::test]
+    async fn test_not_supported() {
+        let (status, _, body) = call("/not-supported").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(body.code, ApiErrorCode::NotImplemented);
+        assert_eq!(body.message, "get_pair_detail is not supported by the storage backend");
     }
 }
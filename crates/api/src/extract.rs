@@ -0,0 +1,125 @@
+//! The extractors of the handlers, their rejections are rendered as an [`ApiErrorBody`]
+//!
+//! [`ApiErrorBody`]: crate::errors::ApiErrorBody
+use crate::errors::ApiError;
+use axum::{
+    extract::{FromRequest, FromRequestParts},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+
+/// Query deserializes the query string, an invalid one is an `invalid_parameter` error
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+/// Path deserializes the path parameters, an invalid one is an `invalid_parameter` error
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+/// Json deserializes the JSON body, an invalid one is an `invalid_parameter` error, and
+/// serializes the responses
+#[derive(Debug, Clone, Copy, Default, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+macro_rules! impl_deref {
+    ($($extractor:ident),*) => {
+        $(
+            impl<T> Deref for $extractor<T> {
+                type Target = T;
+
+                fn deref(&self) -> &T {
+                    &self.0
+                }
+            }
+
+            impl<T> DerefMut for $extractor<T> {
+                fn deref_mut(&mut self) -> &mut T {
+                    &mut self.0
+                }
+            }
+        )*
+    };
+}
+
+impl_deref!(Query, Path, Json);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ApiErrorBody, ApiErrorCode};
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Debug, Deserialize)]
+    struct LimitQuery {
+        limit: usize,
+    }
+
+    async fn get_limit(query: Query<LimitQuery>) -> Json<usize> {
+        Json(query.limit)
+    }
+
+    async fn get_mint(Path(mint): Path<u64>) -> Json<u64> {
+        Json(mint)
+    }
+
+    async fn post_limit(Json(body): Json<LimitQuery>) -> Json<usize> {
+        Json(body.limit)
+    }
+
+    async fn call(request: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let app = Router::new()
+            .route("/limit", get(get_limit))
+            .route("/mint/{mint}", get(get_mint))
+            .route("/post", post(post_limit));
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, bytes.to_vec())
+    }
+
+    fn get_request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    fn post_request(body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/post")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_enveloped() {
+        for request in
+            [get_request("/limit?limit=many"), get_request("/mint/mint"), post_request("{")]
+        {
+            let (status, bytes) = call(request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let body = serde_json::from_slice::<ApiErrorBody>(&bytes).expect("Expected error body");
+            assert_eq!(body.code, ApiErrorCode::InvalidParameter);
+        }
+
+        assert_eq!(call(get_request("/limit?limit=5")).await, (StatusCode::OK, b"5".to_vec()));
+        assert_eq!(call(get_request("/mint/7")).await, (StatusCode::OK, b"7".to_vec()));
+        assert_eq!(call(post_request(r#"{"limit":3}"#)).await, (StatusCode::OK, b"3".to_vec()));
+    }
+}
@@ -0,0 +1,507 @@
+use crate::{
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Path},
+    state::AppState,
+    validation::is_valid_base58_pubkey,
+};
+use axum::extract::State;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sonar_db::{CandlestickInterval, PrimaryPair, WalletCategory, WalletLabel};
+use sonar_token_metadata::{
+    refresh_tokens_with_missing_metadata, RpcTokenResolver, DEFAULT_REFRESH_MISSING_CONCURRENCY,
+    DEFAULT_REFRESH_MISSING_LIMIT,
+};
+use std::collections::HashSet;
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+
+/// The number of candlestick buckets aggregated per query, one hour of swap events for 1m
+const BUCKETS_PER_CHUNK: i64 = 60;
+/// Backfills spanning more than this are rejected unless forced
+const MAX_BACKFILL_SPAN_SECONDS: i64 = 7 * 86400;
+/// The most tokens refreshed per request
+const MAX_REFRESH_TOKENS_LIMIT: usize = 5000;
+/// The most tokens resolved at a time, to stay under the RPC rate limit
+const MAX_REFRESH_TOKENS_CONCURRENCY: usize = 32;
+/// The most wallet labels imported per request
+const MAX_WALLET_LABELS: usize = 1000;
+/// The longest wallet label, in characters
+const MAX_WALLET_LABEL_LENGTH: usize = 64;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AdminAggregateCandlesticksBody {
+    pub interval: CandlestickInterval,
+    pub start_time: i64,
+    pub end_time: i64,
+    /// Allow spans longer than seven days
+    #[serde(default)]
+    pub force: bool,
+    /// Roll up the stored candlesticks of this finer interval instead of the swap events,
+    /// for backfilling after the swap events were removed
+    pub source_interval: Option<CandlestickInterval>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AggregateCandlesticksSummary {
+    pub interval: CandlestickInterval,
+    /// The start of the aggregated range, aligned down to the interval
+    pub start_time: i64,
+    /// The end of the aggregated range, aligned up to the interval
+    pub end_time: i64,
+    pub chunks: usize,
+    /// The candlesticks written, null when the database doesn't report them
+    pub rows_written: Option<u64>,
+}
+
+/// Checks the requested range, returns it aligned to whole candlesticks
+fn validate_range(body: &AdminAggregateCandlesticksBody) -> Result<(i64, i64), ApiError> {
+    if body.start_time < 0 || body.start_time >= body.end_time {
+        return Err(ApiError::invalid_parameter(
+            "start_time",
+            "start_time must be non-negative and before end_time",
+        ));
+    }
+    if !body.force && body.end_time - body.start_time > MAX_BACKFILL_SPAN_SECONDS {
+        return Err(ApiError::invalid_parameter(
+            "end_time",
+            format!("span exceeds {MAX_BACKFILL_SPAN_SECONDS} seconds, set force to override"),
+        ));
+    }
+    if let Some(source_interval) = &body.source_interval {
+        if !body.interval.can_derive_from(source_interval) {
+            return Err(ApiError::invalid_parameter(
+                "source_interval",
+                format!("{} candlesticks can't be derived from {source_interval}", body.interval),
+            ));
+        }
+    }
+    let interval_seconds = body.interval.get_seconds();
+    let start_time = body.start_time - body.start_time % interval_seconds;
+    let end_time = body.end_time.div_ceil(interval_seconds) * interval_seconds;
+    Ok((start_time, end_time))
+}
+
+/// Splits an aligned range into consecutive chunks of `BUCKETS_PER_CHUNK` candlesticks
+fn chunk_ranges(start_time: i64, end_time: i64, interval_seconds: i64) -> Vec<(i64, i64)> {
+    let chunk_seconds = interval_seconds * BUCKETS_PER_CHUNK;
+    (start_time..end_time)
+        .step_by(chunk_seconds as usize)
+        .map(|chunk_start| (chunk_start, (chunk_start + chunk_seconds).min(end_time)))
+        .collect()
+}
+
+/// aggregate_candlesticks re-aggregates swap events, or finer candlesticks, into candlesticks
+/// chunk by chunk, for backfilling the gaps left by scheduler downtime
+#[utoipa::path(
+    post,
+    path = "/admin/aggregate-candlesticks",
+    request_body = AdminAggregateCandlesticksBody,
+    params(("x-api-key" = String, Header, description = "The admin api key")),
+    responses(
+        (status = 200, description = "Candlesticks aggregated successfully", body = AggregateCandlesticksSummary),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 401, description = "Missing or invalid api key", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn aggregate_candlesticks(
+    State(state): State<AppState>,
+    Json(body): Json<AdminAggregateCandlesticksBody>,
+) -> Result<Json<AggregateCandlesticksSummary>, ApiError> {
+    let (start_time, end_time) = validate_range(&body)?;
+    let chunks = chunk_ranges(start_time, end_time, body.interval.get_seconds());
+
+    let mut rows_written = Some(0);
+    for &(chunk_start, chunk_end) in &chunks {
+        let rows = match &body.source_interval {
+            Some(source_interval) => {
+                state
+                    .db
+                    .aggregate_candlesticks_from_candlesticks(
+                        source_interval.clone(),
+                        body.interval.clone(),
+                        chunk_start,
+                        chunk_end,
+                    )
+                    .await?
+            }
+            None => {
+                state
+                    .db
+                    .aggregate_into_candlesticks(chunk_start, chunk_end, body.interval.clone())
+                    .await?
+            }
+        };
+        rows_written = rows_written.zip(rows).map(|(total, rows)| total + rows);
+    }
+    info!(
+        interval = ?body.interval,
+        start_time,
+        end_time,
+        chunks = chunks.len(),
+        ?rows_written,
+        "Backfilled candlesticks"
+    );
+
+    Ok(Json(AggregateCandlesticksSummary {
+        interval: body.interval,
+        start_time,
+        end_time,
+        chunks: chunks.len(),
+        rows_written,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AdminSetTokenVerifiedBody {
+    pub verified: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TokenVerifiedSummary {
+    pub mint: String,
+    pub verified: bool,
+}
+
+/// set_token_verified sets the verified flag of a token, verified tokens rank higher in search
+#[utoipa::path(
+    put,
+    path = "/admin/tokens/{mint}/verified",
+    request_body = AdminSetTokenVerifiedBody,
+    params(
+        ("mint" = String, Path, description = "The token mint"),
+        ("x-api-key" = String, Header, description = "The admin api key")
+    ),
+    responses(
+        (status = 200, description = "Verified flag updated successfully", body = TokenVerifiedSummary),
+        (status = 401, description = "Missing or invalid api key", body = ApiErrorBody),
+        (status = 404, description = "Token not found", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn set_token_verified(
+    State(state): State<AppState>,
+    Path(mint): Path<String>,
+    Json(body): Json<AdminSetTokenVerifiedBody>,
+) -> Result<Json<TokenVerifiedSummary>, ApiError> {
+    if state.db.get_token(&mint).await?.is_none() {
+        return Err(ApiError::NotFound(format!("token {mint}")));
+    }
+    state.db.set_token_verified(&mint, body.verified).await?;
+    info!(mint, verified = body.verified, "Updated token verification");
+    Ok(Json(TokenVerifiedSummary { mint, verified: body.verified }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AdminSetPrimaryPairBody {
+    pub pair: String,
+    /// Keep the pair whatever the trades, false releases a previous pin
+    #[serde(default = "default_pinned")]
+    pub pinned: bool,
+}
+
+fn default_pinned() -> bool {
+    true
+}
+
+/// set_primary_pair pins the primary pair of a token, the pair charted by default
+#[utoipa::path(
+    put,
+    path = "/admin/tokens/{mint}/primary-pair",
+    request_body = AdminSetPrimaryPairBody,
+    params(
+        ("mint" = String, Path, description = "The token mint"),
+        ("x-api-key" = String, Header, description = "The admin api key")
+    ),
+    responses(
+        (status = 200, description = "Primary pair updated successfully", body = PrimaryPair),
+        (status = 400, description = "The pair doesn't trade the token", body = ApiErrorBody),
+        (status = 401, description = "Missing or invalid api key", body = ApiErrorBody),
+        (status = 404, description = "Token not found", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn set_primary_pair(
+    State(state): State<AppState>,
+    Path(mint): Path<String>,
+    Json(body): Json<AdminSetPrimaryPairBody>,
+) -> Result<Json<PrimaryPair>, ApiError> {
+    if state.db.get_token(&mint).await?.is_none() {
+        return Err(ApiError::NotFound(format!("token {mint}")));
+    }
+    let recorded = state.db.get_pair_detail(&body.pair).await?.is_some_and(|detail| {
+        detail.base_mint == mint || detail.quote_mint.as_deref() == Some(mint.as_str())
+    });
+    // a pair whose swap events were pruned is still in the candlesticks of the token
+    let trades_token = recorded
+        || state.db.get_pairs_for_token(&mint, None).await?.iter().any(|p| p.pair == body.pair);
+    if !trades_token {
+        return Err(ApiError::invalid_parameter(
+            "pair",
+            format!("pair {} doesn't trade {mint}", body.pair),
+        ));
+    }
+    let now = Utc::now().timestamp().max(0) as u64;
+    let primary = state.kv_store.set_primary_pair(&mint, &body.pair, body.pinned, now).await?;
+    info!(mint, pair = body.pair, pinned = body.pinned, "Updated token primary pair");
+    Ok(Json(primary))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AdminRefreshTokensBody {
+    /// How many tokens with missing metadata are refreshed
+    #[serde(default = "default_refresh_limit")]
+    pub limit: usize,
+    /// How many tokens are resolved at a time
+    #[serde(default = "default_refresh_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_refresh_limit() -> usize {
+    DEFAULT_REFRESH_MISSING_LIMIT
+}
+
+fn default_refresh_concurrency() -> usize {
+    DEFAULT_REFRESH_MISSING_CONCURRENCY
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RefreshTokensSummary {
+    /// How many tokens with missing metadata were found
+    pub requested: usize,
+    /// How many tokens now have a name, a symbol and a supply
+    pub fixed: usize,
+    /// How many tokens still miss metadata
+    pub failed: usize,
+    /// How many tokens were skipped, their metadata failed to resolve recently
+    pub skipped: usize,
+}
+
+/// refresh_tokens re-resolves the tokens stored with an empty name, symbol or supply,
+/// dropping their cached entries first
+#[utoipa::path(
+    post,
+    path = "/admin/refresh-tokens",
+    request_body = AdminRefreshTokensBody,
+    params(("x-api-key" = String, Header, description = "The admin api key")),
+    responses(
+        (status = 200, description = "Tokens refreshed successfully", body = RefreshTokensSummary),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 401, description = "Missing or invalid api key", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn refresh_tokens(
+    State(state): State<AppState>,
+    Json(body): Json<AdminRefreshTokensBody>,
+) -> Result<Json<RefreshTokensSummary>, ApiError> {
+    if body.limit == 0 || body.limit > MAX_REFRESH_TOKENS_LIMIT {
+        return Err(ApiError::invalid_parameter(
+            "limit",
+            format!("limit must be between 1 and {MAX_REFRESH_TOKENS_LIMIT}"),
+        ));
+    }
+    if body.concurrency == 0 || body.concurrency > MAX_REFRESH_TOKENS_CONCURRENCY {
+        return Err(ApiError::invalid_parameter(
+            "concurrency",
+            format!("concurrency must be between 1 and {MAX_REFRESH_TOKENS_CONCURRENCY}"),
+        ));
+    }
+    let summary = refresh_tokens_with_missing_metadata(
+        &state.db,
+        &state.kv_store,
+        &RpcTokenResolver,
+        body.limit,
+        body.concurrency,
+    )
+    .await?;
+    Ok(Json(RefreshTokensSummary {
+        requested: summary.requested,
+        fixed: summary.fixed,
+        failed: summary.failed,
+        skipped: summary.skipped,
+    }))
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct AdminWalletLabelBody {
+    pub address: String,
+    /// What the wallet is, e.g. `Binance 1`
+    pub label: String,
+    pub category: WalletCategory,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WalletLabelsSummary {
+    /// How many labels were recorded
+    pub imported: usize,
+}
+
+/// validate_wallet_labels checks every label of a bulk import, reporting the first invalid one
+fn validate_wallet_labels(labels: &[AdminWalletLabelBody]) -> Result<(), ApiError> {
+    if labels.is_empty() || labels.len() > MAX_WALLET_LABELS {
+        return Err(ApiError::invalid_parameter(
+            "body",
+            format!("between 1 and {MAX_WALLET_LABELS} labels must be given"),
+        ));
+    }
+    let mut addresses = HashSet::new();
+    for (index, label) in labels.iter().enumerate() {
+        if !is_valid_base58_pubkey(&label.address) {
+            return Err(ApiError::invalid_parameter(
+                format!("[{index}].address"),
+                "invalid wallet address",
+            ));
+        }
+        if !addresses.insert(label.address.as_str()) {
+            return Err(ApiError::invalid_parameter(
+                format!("[{index}].address"),
+                format!("{} is labeled more than once", label.address),
+            ));
+        }
+        let length = label.label.trim().chars().count();
+        if length == 0 || length > MAX_WALLET_LABEL_LENGTH {
+            return Err(ApiError::invalid_parameter(
+                format!("[{index}].label"),
+                format!("the label must be between 1 and {MAX_WALLET_LABEL_LENGTH} characters"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// import_wallet_labels records the labels of known wallets, e.g. the CEX hot wallets, the MEV
+/// bots or the token deployers, replacing their previous labels, the trades of the labeled
+/// owners are returned with their `owner_label`
+#[utoipa::path(
+    post,
+    path = "/admin/wallet-labels",
+    request_body = Vec<AdminWalletLabelBody>,
+    params(("x-api-key" = String, Header, description = "The admin api key")),
+    responses(
+        (status = 200, description = "Wallet labels imported successfully", body = WalletLabelsSummary),
+        (status = 400, description = "Invalid wallet labels", body = ApiErrorBody),
+        (status = 401, description = "Missing or invalid api key", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state, labels))]
+pub async fn import_wallet_labels(
+    State(state): State<AppState>,
+    Json(labels): Json<Vec<AdminWalletLabelBody>>,
+) -> Result<Json<WalletLabelsSummary>, ApiError> {
+    validate_wallet_labels(&labels)?;
+    let added_at = Utc::now().timestamp().max(0) as u64;
+    for label in &labels {
+        let label = WalletLabel {
+            address: label.address.clone(),
+            label: label.label.trim().to_string(),
+            category: label.category.to_string(),
+            added_at,
+        };
+        state.db.upsert_wallet_label(&label).await?;
+        // the cached lookups would hide the new label for an hour
+        state.kv_store.set_wallet_label(&label.address, Some(&label)).await?;
+    }
+    info!(labels = labels.len(), "Imported wallet labels");
+    Ok(Json(WalletLabelsSummary { imported: labels.len() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(start_time: i64, end_time: i64, force: bool) -> AdminAggregateCandlesticksBody {
+        AdminAggregateCandlesticksBody {
+            interval: CandlestickInterval::OneMinute,
+            start_time,
+            end_time,
+            force,
+            source_interval: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_range() {
+        assert!(validate_range(&body(100, 100, false)).is_err());
+        assert!(validate_range(&body(200, 100, false)).is_err());
+        assert!(validate_range(&body(-60, 100, false)).is_err());
+
+        let span = MAX_BACKFILL_SPAN_SECONDS + 60;
+        assert!(matches!(
+            validate_range(&body(0, span, false)),
+            Err(ApiError::InvalidParameter { field, .. }) if field == "end_time"
+        ));
+        assert_eq!(validate_range(&body(0, span, true)).unwrap(), (0, span));
+
+        // the range is widened to whole minutes
+        assert_eq!(validate_range(&body(90, 150, false)).unwrap(), (60, 180));
+
+        let derived = |interval, source_interval| AdminAggregateCandlesticksBody {
+            interval,
+            source_interval: Some(source_interval),
+            ..body(0, 3600, false)
+        };
+        assert!(validate_range(&derived(
+            CandlestickInterval::FiveMinutes,
+            CandlestickInterval::OneMinute
+        ))
+        .is_ok());
+        assert!(matches!(
+            validate_range(&derived(CandlestickInterval::OneMinute, CandlestickInterval::OneHour)),
+            Err(ApiError::InvalidParameter { field, .. }) if field == "source_interval"
+        ));
+    }
+
+    #[test]
+    fn test_chunk_ranges() {
+        // a day of 1m candlesticks is aggregated one hour at a time
+        let chunks = chunk_ranges(0, 86400, 60);
+        assert_eq!(chunks.len(), 24);
+        assert_eq!(chunks[0], (0, 3600));
+        assert_eq!(chunks[23], (82800, 86400));
+
+        // the last chunk is cut at the end of the range
+        let chunks = chunk_ranges(3600, 3600 * 2 + 120, 60);
+        assert_eq!(chunks, vec![(3600, 7200), (7200, 7320)]);
+
+        // a range shorter than a chunk is a single chunk
+        assert_eq!(chunk_ranges(0, 3600, 3600), vec![(0, 3600)]);
+    }
+
+    #[test]
+    fn test_validate_wallet_labels() {
+        let label = |address: &str, label: &str| AdminWalletLabelBody {
+            address: address.to_string(),
+            label: label.to_string(),
+            category: WalletCategory::Cex,
+        };
+        let cex = "5tzFkiKscXHK5ZXCGbXZxdw7gTjjD1mBwuoFbhUvuAi9";
+        let bot = "AC5RDfQFmDS1deWZos921JfqscXdByf8BKHs5ACWjtW2";
+        let invalid_field = |labels: &[AdminWalletLabelBody]| match validate_wallet_labels(labels) {
+            Err(ApiError::InvalidParameter { field, .. }) => Some(field),
+            _ => None,
+        };
+
+        assert!(validate_wallet_labels(&[label(cex, "Binance 1"), label(bot, "Bot")]).is_ok());
+        assert_eq!(invalid_field(&[]).as_deref(), Some("body"));
+        let too_many = vec![label(cex, "Binance 1"); MAX_WALLET_LABELS + 1];
+        assert_eq!(invalid_field(&too_many).as_deref(), Some("body"));
+        assert_eq!(
+            invalid_field(&[label(cex, "Binance 1"), label("not-an-address", "Bot")]).as_deref(),
+            Some("[1].address")
+        );
+        assert_eq!(
+            invalid_field(&[label(cex, "Binance 1"), label(cex, "Binance 2")]).as_deref(),
+            Some("[1].address")
+        );
+        assert_eq!(invalid_field(&[label(cex, "  ")]).as_deref(), Some("[0].label"));
+        let long = "x".repeat(MAX_WALLET_LABEL_LENGTH + 1);
+        assert_eq!(invalid_field(&[label(cex, &long)]).as_deref(), Some("[0].label"));
+    }
+}
@@ -1,58 +1,316 @@
-use crate::{errors::SonarError, state::AppState};
+use crate::{
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Query},
+    state::AppState,
+};
 use anyhow::Result;
 use axum::{
-    extract::{Query, State},
-    response::Json,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
 };
-use serde::Deserialize;
+use chrono::Utc;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 use serde_json::{json, Value};
 use serde_with::skip_serializing_none;
-use sonar_db::{Candlestick, CandlestickInterval};
+use sonar_db::{
+    Candlestick, CandlestickInterval, Denomination, LatestCandlestick, PairInfo, QuotePrices,
+    WSOL_MINT,
+};
 use tracing::instrument;
 use utoipa::{IntoParams, ToSchema};
 
+/// The maximum number of pairs auto-discovered when no pair is given
+const MAX_DISCOVERED_PAIRS: usize = 10;
+/// The latest candlestick changes with every trade, the clients poll it every second or so
+const LATEST_CANDLESTICK_CACHE_CONTROL: &str = "public, max-age=1";
+/// The SOL buckets loaded before the first candlestick, to carry a SOL price into it
+const SOL_LOOKBACK_BUCKETS: u64 = 60;
+/// The most candlesticks a request may span or return, unless set by `MAX_CANDLESTICK_BUCKETS`
+pub const DEFAULT_MAX_CANDLESTICK_BUCKETS: usize = 5000;
+/// The significant digits of the compact prices, unless set by `CANDLESTICK_COMPACT_DIGITS`
+pub const DEFAULT_COMPACT_DIGITS: u32 = 8;
+/// The header counting the buckets skipped or clamped by `invert=true` for a zero price
+pub const INVERT_ADJUSTED_HEADER: &str = "x-invert-adjusted";
+
+/// round_significant rounds a value to `digits` significant digits
+fn round_significant(value: f64, digits: u32) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let exponent = digits as i32 - 1 - value.abs().log10().floor() as i32;
+    // divide by the exact power of ten, its inverse isn't exact
+    match exponent >= 0 {
+        true => (value * 10f64.powi(exponent)).round() / 10f64.powi(exponent),
+        false => (value / 10f64.powi(-exponent)).round() * 10f64.powi(-exponent),
+    }
+}
+
+/// invert_candlesticks prices the candlesticks in the other side of the pair, the open and the
+/// close are inverted and the high and the low swap, the volumes and the turnovers are kept.
+/// A bucket opening or closing at a zero price is skipped, a zero low clamps the inverted high
+/// to the inverted open or close, returns the number of skipped or clamped buckets
+fn invert_candlesticks(candlesticks: Vec<Candlestick>) -> (Vec<Candlestick>, usize) {
+    let mut adjusted = 0;
+    let inverted = candlesticks
+        .into_iter()
+        .filter_map(|c| {
+            if c.open <= 0.0 || c.close <= 0.0 {
+                adjusted += 1;
+                return None;
+            }
+            let (open, close) = (1.0 / c.open, 1.0 / c.close);
+            let high = match c.low > 0.0 {
+                true => 1.0 / c.low,
+                false => {
+                    adjusted += 1;
+                    open.max(close)
+                }
+            };
+            // the high is at least the open and the close, both positive here
+            let low = 1.0 / c.high.max(c.open).max(c.close);
+            Some(Candlestick { open, high, low, close, ..c })
+        })
+        .collect();
+    (inverted, adjusted)
+}
+
+/// CompactCandlesticks serializes candlesticks as columns, `{t:[],o:[],h:[],l:[],c:[],v:[]}`,
+/// with the floats rounded to `digits` significant digits
+pub struct CompactCandlesticks<'a> {
+    pub candlesticks: &'a [Candlestick],
+    pub digits: u32,
+}
+
+impl Serialize for CompactCandlesticks<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let column = |value: fn(&Candlestick) -> f64| -> Vec<f64> {
+            self.candlesticks.iter().map(|c| round_significant(value(c), self.digits)).collect()
+        };
+        let mut map = serializer.serialize_map(Some(6))?;
+        map.serialize_entry(
+            "t",
+            &self.candlesticks.iter().map(|c| c.timestamp).collect::<Vec<_>>(),
+        )?;
+        map.serialize_entry("o", &column(|c| c.open))?;
+        map.serialize_entry("h", &column(|c| c.high))?;
+        map.serialize_entry("l", &column(|c| c.low))?;
+        map.serialize_entry("c", &column(|c| c.close))?;
+        map.serialize_entry("v", &column(|c| c.volume))?;
+        map.end()
+    }
+}
+
+/// The columns of the compact candlesticks as `CompactCandlesticks` serializes them
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompactCandlestickColumns {
+    pub t: Vec<u64>,
+    pub o: Vec<f64>,
+    pub h: Vec<f64>,
+    pub l: Vec<f64>,
+    pub c: Vec<f64>,
+    pub v: Vec<f64>,
+}
+
+/// The body of the candlesticks responses, the rows, or the columns with `compact=true`
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum CandlesticksBody {
+    Rows(Vec<Candlestick>),
+    Compact(CompactCandlestickColumns),
+}
+
+/// candlesticks_response returns the candlesticks as rows, or as columns when `compact`
+fn candlesticks_response(
+    state: &AppState,
+    candlesticks: Vec<Candlestick>,
+    compact: Option<bool>,
+) -> Response {
+    match compact.unwrap_or_default() {
+        true => {
+            let digits = state.compact_digits;
+            Json(CompactCandlesticks { candlesticks: &candlesticks, digits }).into_response()
+        }
+        false => Json(candlesticks).into_response(),
+    }
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Deserialize, IntoParams, ToSchema)]
 pub struct TokenOhlcvQuery {
     pub token: String,
+    /// Comma separated pairs, defaults to the primary pair of the token
     pub pair: Option<String>,
     pub interval: CandlestickInterval,
     pub limit: Option<usize>,
     pub time_from: Option<i32>,
     pub time_to: Option<i32>,
+    /// Coarsen the interval when the range spans too many candlesticks, instead of failing
+    pub auto_interval: Option<bool>,
+    /// Clamp fat-finger highs and lows, defaults to true
+    pub clamp: Option<bool>,
+    /// The currency of the prices, defaults to usd
+    pub denomination: Option<Denomination>,
+    /// Return the columns `{t,o,h,l,c,v}` with rounded prices instead of the candlesticks
+    pub compact: Option<bool>,
+}
+
+/// check_candlestick_range checks the limit and the number of candlesticks the range spans
+/// against `max_buckets`, returns the interval to query, the finest coarser one fitting the
+/// range with `auto_interval`
+fn check_candlestick_range(
+    interval: &CandlestickInterval,
+    limit: Option<usize>,
+    time_from: Option<i32>,
+    time_to: Option<i32>,
+    auto_interval: bool,
+    max_buckets: usize,
+    now: i64,
+) -> Result<CandlestickInterval, ApiError> {
+    if limit.is_some_and(|limit| limit == 0 || limit > max_buckets) {
+        return Err(ApiError::invalid_parameter(
+            "limit",
+            format!("limit must be between 1 and {max_buckets}"),
+        ));
+    }
+    let Some(time_from) = time_from.map(i64::from) else {
+        return Ok(interval.clone());
+    };
+    let time_to = time_to.map_or(now, i64::from);
+    let buckets = interval.bucket_count(time_from, time_to);
+    if buckets <= max_buckets as u64 {
+        return Ok(interval.clone());
+    }
+    if auto_interval {
+        return interval.fit(time_from, time_to, max_buckets as u64).ok_or_else(|| {
+            ApiError::invalid_parameter(
+                "time_from",
+                format!("the range spans more than {max_buckets} 1d candlesticks"),
+            )
+        });
+    }
+    let max_seconds = max_buckets as i64 * interval.get_seconds();
+    Err(ApiError::invalid_parameter(
+        "time_from",
+        format!(
+            "the range spans {buckets} {interval} candlesticks, at most {max_buckets} are \
+            allowed: narrow it to {max_seconds} seconds, use a coarser interval or set \
+            auto_interval=true"
+        ),
+    ))
 }
 
 #[utoipa::path(
     get,
     path = "/token-ohlcv",
+    description = "The high and low of candlesticks built from swap events are clamped to the \
+        0.995 and 0.005 quantile prices of their bucket when they are more than 20 times away from \
+        them, in buckets of at least 10 trades. The defaults are set by the CANDLESTICK_CLAMP_* \
+        environment variables, `clamp=false` returns the raw high and low. With \
+        `denomination=sol` the prices are divided by the SOL price of their bucket, the turnover \
+        stays in USD. A range spanning more than MAX_CANDLESTICK_BUCKETS candlesticks is \
+        rejected, or queried at the finest coarser interval fitting it with `auto_interval=true`. \
+        With `compact=true` the response is the columns `{t:[],o:[],h:[],l:[],c:[],v:[]}`, with \
+        the floats rounded to CANDLESTICK_COMPACT_DIGITS significant digits.",
     params(TokenOhlcvQuery),
     responses(
-        (status = 200, description = "Candlesticks retrieved successfully", body = Vec<Candlestick>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Candlesticks retrieved successfully", body = CandlesticksBody),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_candlesticks_by_token(
     State(state): State<AppState>,
     query: Query<TokenOhlcvQuery>,
-) -> Result<Json<Vec<Candlestick>>, SonarError> {
+) -> Result<Response, ApiError> {
+    let interval = check_candlestick_range(
+        &query.interval,
+        query.limit,
+        query.time_from,
+        query.time_to,
+        query.auto_interval.unwrap_or_default(),
+        state.max_candlestick_buckets,
+        Utc::now().timestamp(),
+    )?;
     let pairs = match query.pair.as_deref() {
         Some(pair) => pair.split(',').map(|p| p.trim().to_string()).collect(),
-        None => vec![],
+        None => match state.kv_store.get_primary_pair(&query.token).await? {
+            Some(primary) => vec![primary.pair],
+            // Discover the pools the token trades in over the requested window
+            None => {
+                let since = query.time_from.map(|time_from| time_from.max(0) as u64);
+                let pairs = state.db.get_pairs_for_token(&query.token, since).await?;
+                top_pairs(pairs, MAX_DISCOVERED_PAIRS)
+            }
+        },
     };
     let candlesticks = state
         .db
         .get_candlesticks_by_token(
             &query.token,
             &pairs,
-            query.interval.clone(),
+            interval.clone(),
+            &state.outlier_policy.with_clamp(query.clamp),
             query.limit,
             query.time_from,
             query.time_to,
         )
         .await?;
-    Ok(Json(candlesticks))
+    let candlesticks =
+        denominate(&state, candlesticks, &interval, query.denomination.unwrap_or_default()).await?;
+    Ok(candlesticks_response(&state, candlesticks, query.compact))
+}
+
+/// denominate converts USD candlesticks into `denomination`
+async fn denominate(
+    state: &AppState,
+    candlesticks: Vec<Candlestick>,
+    interval: &CandlestickInterval,
+    denomination: Denomination,
+) -> Result<Vec<Candlestick>, ApiError> {
+    let (Some(from), Some(to)) =
+        (candlesticks.first().map(|c| c.timestamp), candlesticks.last().map(|c| c.timestamp))
+    else {
+        return Ok(candlesticks);
+    };
+    if denomination == Denomination::Usd {
+        return Ok(candlesticks);
+    }
+    let sol_prices = load_sol_prices(state, interval, from, to).await?;
+    sol_prices.denominate(candlesticks).ok_or_else(|| ApiError::NotFound("SOL price".to_string()))
+}
+
+/// load_sol_prices loads the SOL candlesticks of `interval` from a few buckets before `from`
+/// until the bucket of `to`
+pub(crate) async fn load_sol_prices(
+    state: &AppState,
+    interval: &CandlestickInterval,
+    from: u64,
+    to: u64,
+) -> Result<QuotePrices, ApiError> {
+    let seconds = interval.get_seconds() as u64;
+    let from = interval.bucket_start(from).saturating_sub(SOL_LOOKBACK_BUCKETS * seconds);
+    let to = interval.bucket_start(to) + seconds;
+    let buckets = ((to - from) / seconds) as usize;
+    let sol_candlesticks = state
+        .db
+        .get_candlesticks_by_token(
+            WSOL_MINT,
+            &[],
+            interval.clone(),
+            &state.outlier_policy,
+            Some(buckets),
+            Some(from as i32),
+            Some(to as i32),
+        )
+        .await?;
+    Ok(QuotePrices::new(&sol_candlesticks, interval))
+}
+
+/// Returns the first `limit` pairs, which are ordered by turnover descending
+fn top_pairs(pairs: Vec<PairInfo>, limit: usize) -> Vec<String> {
+    pairs.into_iter().take(limit).map(|p| p.pair).collect()
 }
 
 #[skip_serializing_none]
@@ -64,35 +322,127 @@ pub struct CandlestickPairQuery {
     pub limit: Option<usize>,
     pub time_from: Option<i32>,
     pub time_to: Option<i32>,
+    /// Coarsen the interval when the range spans too many candlesticks, instead of failing
+    pub auto_interval: Option<bool>,
+    /// Clamp fat-finger highs and lows, defaults to true
+    pub clamp: Option<bool>,
+    /// The currency of the prices, defaults to usd
+    pub denomination: Option<Denomination>,
+    /// Return the columns `{t,o,h,l,c,v}` with rounded prices instead of the candlesticks
+    pub compact: Option<bool>,
+    /// Price the other side of the pair, e.g. the tokens one SOL buys with `denomination=sol`,
+    /// the volumes stay in the base token and the turnovers in USD
+    pub invert: Option<bool>,
 }
 
 #[utoipa::path(
     get,
     path = "/pair-ohlcv",
+    description = "The high and low of candlesticks built from swap events are clamped to the \
+        0.995 and 0.005 quantile prices of their bucket when they are more than 20 times away from \
+        them, in buckets of at least 10 trades. The defaults are set by the CANDLESTICK_CLAMP_* \
+        environment variables, `clamp=false` returns the raw high and low. With \
+        `denomination=sol` the prices are divided by the SOL price of their bucket, the turnover \
+        stays in USD. A range spanning more than MAX_CANDLESTICK_BUCKETS candlesticks is \
+        rejected, or queried at the finest coarser interval fitting it with `auto_interval=true`. \
+        With `compact=true` the response is the columns `{t:[],o:[],h:[],l:[],c:[],v:[]}`, with \
+        the floats rounded to CANDLESTICK_COMPACT_DIGITS significant digits. With `invert=true` \
+        the prices, once denominated, are inverted: the open and the close are 1/open and \
+        1/close, the high is 1/low and the low is 1/high, while the volumes stay denominated in \
+        the base token and the turnovers in USD. A bucket opening or closing at a zero price is \
+        left out and a zero low caps the inverted high at the inverted open or close, the \
+        `x-invert-adjusted` header counts these buckets. The `SOLUSD` candlesticks are built from \
+        the Binance SOL/USDT trades the ingestor persists with PERSIST_SOL_PRICE_TRADES, sampled \
+        once per second, and have no volume.",
     params(CandlestickPairQuery),
     responses(
-        (status = 200, description = "Candlesticks retrieved successfully", body = Vec<Candlestick>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Candlesticks retrieved successfully", body = CandlesticksBody),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_candlesticks_by_pair(
     State(state): State<AppState>,
     query: Query<CandlestickPairQuery>,
-) -> Result<Json<Vec<Candlestick>>, SonarError> {
+) -> Result<Response, ApiError> {
+    // an empty pair would read the token-level candlesticks
+    if query.pair.split(',').any(str::is_empty) {
+        return Err(ApiError::invalid_parameter("pair", "the pairs can't be empty"));
+    }
+    let interval = check_candlestick_range(
+        &query.interval,
+        query.limit,
+        query.time_from,
+        query.time_to,
+        query.auto_interval.unwrap_or_default(),
+        state.max_candlestick_buckets,
+        Utc::now().timestamp(),
+    )?;
     let candlesticks = state
         .db
         .get_candlesticks_by_pair(
             query.pair.as_str(),
             query.token.as_deref(),
-            &query.interval,
+            &interval,
+            &state.outlier_policy.with_clamp(query.clamp),
             query.limit,
             query.time_from,
             query.time_to,
         )
         .await?;
-    Ok(Json(candlesticks))
+    let candlesticks =
+        denominate(&state, candlesticks, &interval, query.denomination.unwrap_or_default()).await?;
+    if !query.invert.unwrap_or_default() {
+        return Ok(candlesticks_response(&state, candlesticks, query.compact));
+    }
+    let (candlesticks, adjusted) = invert_candlesticks(candlesticks);
+    let mut response = candlesticks_response(&state, candlesticks, query.compact);
+    if adjusted > 0 {
+        response.headers_mut().insert(INVERT_ADJUSTED_HEADER, adjusted.into());
+    }
+    Ok(response)
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, IntoParams, ToSchema)]
+pub struct LatestCandlestickQuery {
+    pub pair: String,
+    pub token: Option<String>,
+    pub interval: CandlestickInterval,
+    /// Clamp fat-finger highs and lows, defaults to true
+    pub clamp: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/candlestick-latest",
+    description = "Returns the candlestick of the current bucket and of the previous one, \
+        aggregated from the swap events of both buckets only. When nothing traded in the current \
+        bucket yet, it is flat at the previous close and flagged `synthetic`.",
+    params(LatestCandlestickQuery),
+    responses(
+        (status = 200, description = "Latest candlestick retrieved successfully", body = LatestCandlestick),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn get_latest_candlestick(
+    State(state): State<AppState>,
+    query: Query<LatestCandlestickQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let latest = state
+        .db
+        .get_latest_candlestick(
+            query.pair.as_str(),
+            query.token.as_deref(),
+            &query.interval,
+            &state.outlier_policy.with_clamp(query.clamp),
+            Utc::now().timestamp() as u64,
+        )
+        .await?;
+    Ok(([(header::CACHE_CONTROL, LATEST_CANDLESTICK_CACHE_CONTROL)], Json(latest)))
 }
 
 #[skip_serializing_none]
@@ -110,15 +460,15 @@ pub struct AggregateCandlesticksBody {
     request_body = AggregateCandlesticksBody,
     responses(
         (status = 200, description = "Candlesticks aggregated successfully", body = Value),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn aggregate_candlesticks(
     State(state): State<AppState>,
     body: Json<AggregateCandlesticksBody>,
-) -> Result<Json<Value>, SonarError> {
+) -> Result<Json<Value>, ApiError> {
     state
         .db
         .aggregate_into_candlesticks(body.start_time, body.end_time, body.interval.clone())
@@ -127,3 +477,116 @@ pub async fn aggregate_candlesticks(
         "success": true,
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_pairs() {
+        let pairs: Vec<PairInfo> = (0..12)
+            .map(|i| PairInfo {
+                pair: format!("pair-{i}"),
+                dex: None,
+                turnover: (100 - i) as f64,
+                last_trade_ts: 0,
+            })
+            .collect();
+        let top = top_pairs(pairs, MAX_DISCOVERED_PAIRS);
+        assert_eq!(top.len(), MAX_DISCOVERED_PAIRS);
+        assert_eq!(top[0], "pair-0");
+        assert_eq!(top[9], "pair-9");
+
+        // no discovered pairs falls back to querying all pairs of the token
+        assert!(top_pairs(vec![], MAX_DISCOVERED_PAIRS).is_empty());
+    }
+
+    #[test]
+    fn test_round_significant() {
+        assert_eq!(round_significant(123.456789, 4), 123.5);
+        assert_eq!(round_significant(0.000012345678, 3), 0.0000123);
+        assert_eq!(round_significant(-9.87654321, 2), -9.9);
+        assert_eq!(round_significant(98_765_432.0, 3), 98_800_000.0);
+        assert_eq!(round_significant(0.0, 3), 0.0);
+
+        let candlestick = Candlestick {
+            timestamp: 60,
+            open: 0.123456789,
+            high: 0.2,
+            low: 0.1,
+            close: 0.15,
+            volume: 1234.5678,
+            turnover: 0.0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            buy_turnover: 0.0,
+            sell_turnover: 0.0,
+        };
+        let compact = CompactCandlesticks { candlesticks: &[candlestick], digits: 4 };
+        assert_eq!(
+            serde_json::to_value(&compact).unwrap(),
+            json!({"t": [60], "o": [0.1235], "h": [0.2], "l": [0.1], "c": [0.15], "v": [1235.0]})
+        );
+        // the documented columns are the serialized ones
+        let columns: CompactCandlestickColumns =
+            serde_json::from_value(serde_json::to_value(&compact).unwrap()).unwrap();
+        assert_eq!((columns.t, columns.o), (vec![60], vec![0.1235]));
+    }
+
+    #[test]
+    fn test_invert_candlesticks() {
+        let candlestick = |timestamp, open, high, low, close| Candlestick {
+            timestamp,
+            open,
+            high,
+            low,
+            close,
+            volume: 10.0,
+            turnover: 20.0,
+            buy_volume: 6.0,
+            sell_volume: 4.0,
+            buy_turnover: 12.0,
+            sell_turnover: 8.0,
+        };
+        let (inverted, adjusted) = invert_candlesticks(vec![
+            candlestick(60, 2.0, 4.0, 0.5, 1.0),
+            // a zero low caps the inverted high at the inverted open or close
+            candlestick(120, 2.0, 4.0, 0.0, 0.25),
+            // no inverse of a zero open
+            candlestick(180, 0.0, 4.0, 0.0, 2.0),
+        ]);
+        assert_eq!(adjusted, 2);
+        let prices: Vec<_> =
+            inverted.iter().map(|c| (c.timestamp, c.open, c.high, c.low, c.close)).collect();
+        assert_eq!(prices, vec![(60, 0.5, 2.0, 0.25, 1.0), (120, 0.5, 4.0, 0.25, 4.0)]);
+        // the volumes and the turnovers stay in the base token
+        assert!(inverted.iter().all(|c| c.volume == 10.0 && c.turnover == 20.0));
+        assert!(inverted.iter().all(|c| c.buy_volume == 6.0 && c.sell_turnover == 8.0));
+    }
+
+    #[test]
+    fn test_check_candlestick_range() {
+        let minute = CandlestickInterval::OneMinute;
+        let now = 365 * 86400;
+        let check = |limit, time_from, auto_interval| {
+            check_candlestick_range(&minute, limit, time_from, None, auto_interval, 5000, now)
+        };
+        assert_eq!(check(None, None, false).unwrap(), minute);
+        assert_eq!(check(Some(5000), Some(now as i32 - 3600), false).unwrap(), minute);
+        assert!(matches!(
+            check(Some(5001), None, false),
+            Err(ApiError::InvalidParameter { field, .. }) if field == "limit"
+        ));
+        assert!(check(Some(0), None, false).is_err());
+
+        // a year of 1m candlesticks
+        assert!(matches!(
+            check(None, Some(0), false),
+            Err(ApiError::InvalidParameter { field, .. }) if field == "time_from"
+        ));
+        assert_eq!(check(None, Some(0), true).unwrap(), CandlestickInterval::FourHours);
+        // the range ends at time_to when given
+        let hour = check_candlestick_range(&minute, None, Some(0), Some(3600), false, 5000, now);
+        assert_eq!(hour.unwrap(), minute);
+    }
+}
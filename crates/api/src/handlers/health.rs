@@ -1,4 +1,4 @@
-use axum::response::Json;
+use crate::extract::Json;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -1,45 +1,108 @@
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+pub mod admin;
 pub mod candlesticks;
 pub mod health;
+pub mod pairs;
 pub mod price;
 pub mod swap;
 pub mod tokens;
+pub mod wallet;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         health::get_health,
         price::get_price,
+				admin::aggregate_candlesticks,
+				admin::set_token_verified,
+				admin::set_primary_pair,
+				admin::refresh_tokens,
+				admin::import_wallet_labels,
 				price::get_prices,
 				candlesticks::aggregate_candlesticks,
 				candlesticks::get_candlesticks_by_token,
 				candlesticks::get_candlesticks_by_pair,
+				candlesticks::get_latest_candlestick,
+				pairs::get_pairs,
+				pairs::get_pair,
+				pairs::get_fee_stats,
 				swap::get_trades,
+				swap::export_trades,
 				tokens::create_token,
 				tokens::get_token,
 				tokens::get_tokens,
+				tokens::resolve_tokens,
 				tokens::get_tokens_daily_stats,
+				tokens::get_token_history,
 				tokens::get_tokens_stats,
 				tokens::search,
 				tokens::get_top_tokens,
+				wallet::get_wallet_activity,
     ),
     components(
         schemas(
             health::HealthResponse,
+            crate::errors::ApiErrorBody,
+            crate::errors::ApiErrorCode,
             sonar_db::models::tokens::TokenPrice,
             price::PriceQuery,
             price::PricesQuery,
+            price::PriceError,
+            price::PriceResult,
+            price::PricesResponse,
 						candlesticks::AggregateCandlesticksBody,
+						admin::AdminAggregateCandlesticksBody,
+						admin::AggregateCandlesticksSummary,
+						admin::AdminSetTokenVerifiedBody,
+						admin::TokenVerifiedSummary,
+						admin::AdminSetPrimaryPairBody,
+						admin::AdminRefreshTokensBody,
+						admin::RefreshTokensSummary,
+						admin::AdminWalletLabelBody,
+						admin::WalletLabelsSummary,
+            sonar_db::WalletCategory,
+            sonar_db::PrimaryPair,
             candlesticks::TokenOhlcvQuery,
+            candlesticks::CandlesticksBody,
+            candlesticks::CompactCandlestickColumns,
+            sonar_db::Denomination,
+            swap::DenominatedTrade,
+            swap::TradeExportQuery,
+            swap::ExportFormat,
             candlesticks::CandlestickPairQuery,
+            candlesticks::LatestCandlestickQuery,
+            sonar_db::LatestCandlestick,
+            sonar_db::CurrentCandlestick,
+            sonar_db::Dexes,
+            sonar_db::PairInfo,
+            pairs::PairsQuery,
+            sonar_db::PairDetail,
+            pairs::PairQuery,
+            pairs::FeeStatsQuery,
+            sonar_db::FeeStat,
             tokens::TopTokensQuery,
+            sonar_db::TopToken,
+            sonar_db::TopTokensPage,
+            sonar_db::TopTokensSort,
+            sonar_db::SortOrder,
             tokens::TokenStatsQuery,
+            tokens::TokenHistoryQuery,
+            sonar_db::TokenDailyRollup,
             tokens::TokenMetadataQuery,
+            tokens::TokenWithRisk,
+            sonar_db::TokenRiskFlags,
             tokens::TokensQuery,
+            tokens::ResolveTokensBody,
+            tokens::ResolveStatus,
+            tokens::ResolvedTokenEntry,
             tokens::CreateTokenBody,
             tokens::SearchQuery,
+            sonar_db::TokenSearchResult,
+            sonar_db::MatchReason,
+            wallet::WalletActivityQuery,
+            sonar_db::WalletActivity,
         )
     ),
     tags(
@@ -52,3 +115,30 @@ pub struct ApiDoc;
 pub fn api_doc() -> SwaggerUi {
     SwaggerUi::new("/api-docs").url("/api-docs/openapi.json", ApiDoc::openapi())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dexes_schema_is_registered() {
+        let schemas = ApiDoc::openapi().components.unwrap().schemas;
+        assert!(schemas.contains_key("Dexes"));
+        assert!(schemas.contains_key("PairInfo"));
+    }
+
+    #[test]
+    fn test_candlesticks_document_the_compact_columns() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for path in ["/token-ohlcv", "/pair-ohlcv"] {
+            let schema = &doc["paths"][path]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"];
+            assert_eq!(schema["$ref"], "#/components/schemas/CandlesticksBody", "{path}");
+        }
+        let body = &doc["components"]["schemas"]["CandlesticksBody"];
+        let variants = body["oneOf"].as_array().expect("Expected the rows or the columns");
+        assert!(variants
+            .iter()
+            .any(|v| v["$ref"] == "#/components/schemas/CompactCandlestickColumns"));
+    }
+}
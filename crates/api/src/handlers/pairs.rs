@@ -0,0 +1,114 @@
+use crate::{
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Query},
+    state::AppState,
+};
+use anyhow::Result;
+use axum::extract::State;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_with::skip_serializing_none;
+use sonar_db::{FeeStat, PairDetail, PairInfo};
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+/// The window of the fee stats when no `since` is given
+const DEFAULT_FEE_STATS_WINDOW_SECS: u64 = 86400;
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Validate, IntoParams, ToSchema)]
+pub struct PairsQuery {
+    #[validate(length(min = 10))]
+    pub token: String,
+    pub since: Option<u64>,
+}
+
+/// Get the pairs a token trades in, ordered by turnover
+#[utoipa::path(
+    get,
+    path = "/pairs",
+    params(PairsQuery),
+    responses(
+        (status = 200, description = "Pairs retrieved successfully", body = Vec<PairInfo>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn get_pairs(
+    State(state): State<AppState>,
+    query: Query<PairsQuery>,
+) -> Result<Json<Vec<PairInfo>>, ApiError> {
+    query.validate()?;
+    let pairs = state.db.get_pairs_for_token(&query.token, query.since).await?;
+    Ok(Json(pairs))
+}
+
+#[derive(Debug, Deserialize, Validate, IntoParams, ToSchema)]
+pub struct PairQuery {
+    #[validate(length(min = 10))]
+    pub address: String,
+}
+
+/// Get the mints, the latest price and the 24h stats of a pair
+#[utoipa::path(
+    get,
+    path = "/pair",
+    params(PairQuery),
+    responses(
+        (status = 200, description = "Pair retrieved successfully", body = PairDetail),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 404, description = "Pair never traded", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn get_pair(
+    State(state): State<AppState>,
+    query: Query<PairQuery>,
+) -> Result<Json<PairDetail>, ApiError> {
+    query.validate()?;
+    let detail = state
+        .db
+        .get_pair_detail(&query.address)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("pair {}", query.address)))?;
+    Ok(Json(detail))
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Validate, IntoParams, ToSchema)]
+pub struct FeeStatsQuery {
+    /// Only the fees of this pair, all pairs by default
+    pub pair: Option<String>,
+    /// The start of the window, the last 24 hours by default
+    pub since: Option<u64>,
+    #[validate(range(min = 1, max = 1000))]
+    pub limit: Option<usize>,
+}
+
+/// Get the fees collected per pair and fee mint, ordered by fees
+#[utoipa::path(
+    get,
+    path = "/fee-stats",
+    params(FeeStatsQuery),
+    responses(
+        (status = 200, description = "Fee stats retrieved successfully", body = Vec<FeeStat>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn get_fee_stats(
+    State(state): State<AppState>,
+    query: Query<FeeStatsQuery>,
+) -> Result<Json<Vec<FeeStat>>, ApiError> {
+    query.validate()?;
+    let since = query.since.unwrap_or_else(|| {
+        (Utc::now().timestamp() as u64).saturating_sub(DEFAULT_FEE_STATS_WINDOW_SECS)
+    });
+    let stats =
+        state.db.get_fee_stats(query.pair.as_deref(), since, query.limit.unwrap_or(100)).await?;
+    Ok(Json(stats))
+}
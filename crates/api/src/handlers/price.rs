@@ -1,14 +1,16 @@
-use crate::{errors::SonarError, state::AppState};
-use anyhow::Result;
-use axum::{
-    extract::{Query, State},
-    response::Json,
+use crate::{
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Query},
+    state::AppState,
+    validation::is_valid_base58_pubkey,
 };
+use anyhow::Result;
+use axum::extract::State;
 use chrono::Utc;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
-use sonar_db::models::tokens::TokenPrice;
-use tracing::instrument;
+use sonar_db::models::tokens::{PriceSource, TokenPrice};
+use tracing::{instrument, warn};
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
@@ -28,38 +30,57 @@ pub struct PriceQuery {
     params(PriceQuery),
     responses(
         (status = 200, description = "Token price retrieved successfully", body = TokenPrice),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 404, description = "Token price not found"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 404, description = "Token price not found", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_price(
     State(state): State<AppState>,
     query: Query<PriceQuery>,
-) -> Result<Json<TokenPrice>, SonarError> {
+) -> Result<Json<TokenPrice>, ApiError> {
     query.validate()?;
     let now = Utc::now().timestamp() as i32;
+    let timestamp = query.timestamp.unwrap_or(now);
 
-    // If no timestamp specified, try to get latests price
-    if query.timestamp.is_none() {
-        if let Some(price) = state.kv_store.get_price(&query.token).await? {
-            return Ok(Json(TokenPrice {
-                token: query.token.clone(),
-                timestamp: now,
-                price: Some(price.price),
-                neatest_timestamp: Some(price.timestamp as i32),
-            }));
-        }
+    // The kv store holds the latest trade, which is fresher than the batched db inserts
+    let latest = state.kv_store.get_latest_price(&query.token).await?;
+    if let Some(price) =
+        price_from_kv(&query.token, timestamp, latest, state.price_max_staleness_secs)
+    {
+        return Ok(Json(price));
     }
 
-    // Get price for specific timestamp or fallback to latest
-    let timestamp = query.timestamp.unwrap_or(now);
     let price = state.db.get_price(&query.token, timestamp).await?;
-
     Ok(Json(price))
 }
 
+/// Build a price from the latest kv price, if it is not after `timestamp`
+/// and not older than `max_staleness_secs` relative to it
+fn price_from_kv(
+    token: &str,
+    timestamp: i32,
+    latest: Option<(f64, u64)>,
+    max_staleness_secs: u64,
+) -> Option<TokenPrice> {
+    let (price, latest_timestamp) = latest?;
+    let age = timestamp as i64 - latest_timestamp as i64;
+    if age < 0 || age as u64 > max_staleness_secs {
+        return None;
+    }
+    Some(TokenPrice {
+        token: token.to_string(),
+        timestamp,
+        price: Some(price),
+        neatest_timestamp: Some(latest_timestamp as i32),
+        source: Some(PriceSource::Kv),
+    })
+}
+
+/// How far past the server clock a requested timestamp may be, for the clock skew of the clients
+const MAX_PRICE_TIMESTAMP_SKEW_SECS: i64 = 300;
+
 #[derive(Debug, Deserialize, Validate, IntoParams, ToSchema)]
 pub struct PricesQuery {
     #[validate(length(min = 10))]
@@ -68,26 +89,142 @@ pub struct PricesQuery {
     pub timestamp: i32,
 }
 
-/// Get prices for multiple tokens at specific timestamps
+/// The error of one item of a batch price request
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PriceError {
+    pub token: String,
+    pub error: String,
+}
+
+/// The price of one item of a batch price request, or why it has none
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum PriceResult {
+    Price(TokenPrice),
+    Error(PriceError),
+}
+
+impl PriceResult {
+    fn error(token: &str, error: impl Into<String>) -> Self {
+        PriceResult::Error(PriceError { token: token.to_string(), error: error.into() })
+    }
+}
+
+/// The prices in the order of the request, with the count of the failed items
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PricesResponse {
+    pub prices: Vec<PriceResult>,
+    pub errors_count: usize,
+}
+
+/// check_prices_query returns why a price can't be requested for the item, if it can't
+fn check_prices_query(query: &PricesQuery, now: i64) -> Result<(), &'static str> {
+    if !is_valid_base58_pubkey(&query.token) {
+        return Err("invalid token address");
+    }
+    if query.timestamp < 0 || query.timestamp as i64 > now + MAX_PRICE_TIMESTAMP_SKEW_SECS {
+        return Err("invalid timestamp");
+    }
+    Ok(())
+}
+
+/// Get prices for multiple tokens at specific timestamps, an invalid or failed item
+/// is returned as an error in its place rather than failing the request
 #[utoipa::path(
     post,
     path = "/prices",
     request_body = Vec<PricesQuery>,
     responses(
-        (status = 200, description = "Token prices retrieved successfully", body = Vec<TokenPrice>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Token prices and per-item errors", body = PricesResponse),
+        (status = 400, description = "Malformed request body", body = ApiErrorBody)
     ),
 )]
 #[instrument(skip(state))]
 pub async fn get_prices(
     State(state): State<AppState>,
     query: Json<Vec<PricesQuery>>,
-) -> Result<Json<Vec<TokenPrice>>, SonarError> {
-    query.validate()?;
+) -> Json<PricesResponse> {
+    let now = Utc::now().timestamp();
+    let mut results = query
+        .iter()
+        .map(|q| check_prices_query(q, now).err().map(|error| PriceResult::error(&q.token, error)))
+        .collect::<Vec<_>>();
+    let valid = (0..query.len()).filter(|&i| results[i].is_none()).collect::<Vec<_>>();
 
-    let queries = query.iter().map(|q| (q.token.as_str(), q.timestamp)).collect();
-    let prices = state.db.get_prices(queries).await?;
+    let mints = valid.iter().map(|&i| query[i].token.as_str()).collect::<Vec<_>>();
+    let latest_prices = match state.kv_store.get_latest_prices(&mints).await {
+        Ok(latest_prices) => latest_prices,
+        Err(e) => {
+            warn!(error = ?e, "Failed to get the latest prices, falling back to the db");
+            vec![None; mints.len()]
+        }
+    };
+    let mut misses = vec![];
+    for (&i, latest) in valid.iter().zip(latest_prices) {
+        let q = &query[i];
+        match price_from_kv(&q.token, q.timestamp, latest, state.price_max_staleness_secs) {
+            Some(price) => results[i] = Some(PriceResult::Price(price)),
+            None => misses.push(i),
+        }
+    }
 
-    Ok(Json(prices))
+    // Only hit the db for the kv misses
+    if !misses.is_empty() {
+        let queries = misses.iter().map(|&i| (query[i].token.as_str(), query[i].timestamp));
+        let db_prices = state.db.get_prices(queries.collect()).await;
+        for (&i, price) in misses.iter().zip(db_prices) {
+            results[i] = Some(match price {
+                Ok(price) => PriceResult::Price(price),
+                Err(e) => {
+                    warn!(error = ?e, token = %query[i].token, "Failed to get price");
+                    PriceResult::error(&query[i].token, "failed to get price")
+                }
+            });
+        }
+    }
+
+    let prices = results.into_iter().flatten().collect::<Vec<_>>();
+    let errors_count = prices.iter().filter(|p| matches!(p, PriceResult::Error(_))).count();
+    Json(PricesResponse { prices, errors_count })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "2Y6GkQJR93PNL1iYwGcjggoaBRaeTM1p9pC7oCzTpump";
+
+    #[test]
+    fn test_price_from_fresh_kv() {
+        let price = price_from_kv(TOKEN, 1_000, Some((1.5, 990)), 60).expect("Expected kv price");
+        assert_eq!(price.price, Some(1.5));
+        assert_eq!(price.timestamp, 1_000);
+        assert_eq!(price.neatest_timestamp, Some(990));
+        assert_eq!(price.source, Some(PriceSource::Kv));
+    }
+
+    #[test]
+    fn test_price_from_stale_kv() {
+        assert!(price_from_kv(TOKEN, 1_000, Some((1.5, 900)), 60).is_none());
+        // the latest trade is after the requested timestamp, the db has the historical price
+        assert!(price_from_kv(TOKEN, 1_000, Some((1.5, 1_001)), 60).is_none());
+    }
+
+    #[test]
+    fn test_price_from_kv_miss() {
+        assert!(price_from_kv(TOKEN, 1_000, None, 60).is_none());
+    }
+
+    #[test]
+    fn test_check_prices_query() {
+        let query = |token: &str, timestamp| PricesQuery { token: token.to_string(), timestamp };
+        assert_eq!(check_prices_query(&query(TOKEN, 1_000), 1_000), Ok(()));
+        assert_eq!(check_prices_query(&query(TOKEN, 1_300), 1_000), Ok(()));
+        assert_eq!(
+            check_prices_query(&query("0OIl-not-base58-address", 1_000), 1_000),
+            Err("invalid token address")
+        );
+        assert_eq!(check_prices_query(&query(TOKEN, -1), 1_000), Err("invalid timestamp"));
+        assert_eq!(check_prices_query(&query(TOKEN, 1_301), 1_000), Err("invalid timestamp"));
+    }
 }
@@ -1,15 +1,42 @@
-use crate::{errors::SonarError, state::AppState};
+use crate::{
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Query},
+    handlers::candlesticks::load_sol_prices,
+    state::AppState,
+    validation::check_address,
+};
 use anyhow::Result;
 use axum::{
-    extract::{Query, State},
-    response::Json,
+    body::Body,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sonar_db::{
+    CandlestickInterval, Database, Denomination, QuotePrices, Trade, TradeFilter, WalletCategory,
+    WalletLabel, TRADE_CSV_HEADER,
 };
-use serde::Deserialize;
-use sonar_db::Trade;
-use tracing::instrument;
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::Infallible,
+    sync::Arc,
+};
+use tracing::{error, instrument};
+
+/// The most SOL buckets loaded to price a page of trades, coarser buckets are used beyond
+const MAX_SOL_BUCKETS: u64 = 1440;
+
+/// The trades serialized into each chunk of an export body
+pub const EXPORT_CHUNK_ROWS: usize = 1000;
+
+/// The most trades of an export unless `TRADES_EXPORT_MAX_ROWS` is set
+pub const DEFAULT_EXPORT_MAX_ROWS: usize = 1_000_000;
 
 #[derive(Deserialize, Debug, utoipa::IntoParams, utoipa::ToSchema)]
 pub struct TradeQuery {
+    /// The trades owned or signed by this wallet, e.g. the relayer paying the fees of a bot
     #[serde(skip_serializing_if = "Option::is_none")]
     pub address: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -18,37 +45,347 @@ pub struct TradeQuery {
     pub pair: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub signature: Option<String>,
+    /// Only the trades of this slot, narrows a signature lookup to the days around it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// Only the trades at or after this unix timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_from: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub offset: Option<usize>,
+    /// `sol` adds the price of the trades in SOL, defaults to usd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denomination: Option<Denomination>,
+    /// Only the trades of the owners labeled with this category, e.g. `cex`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<WalletCategory>,
+    /// Only the trades moving at least this share of the token supply, e.g. `0.005` for 0.5%
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_pct_supply: Option<f64>,
 }
 
+/// A trade with its price in SOL when requested
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct DenominatedTrade {
+    #[serde(flatten)]
+    pub trade: Trade,
+    /// The USD price divided by the SOL price nearest to the trade
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_sol: Option<f64>,
+    /// The label of the owner when it is a known wallet, e.g. a CEX hot wallet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_label: Option<String>,
+}
+
+/// The latest trades matching the filters.
+///
+/// A `signature` without `time_from` is looked up in the last hour first, then in the days
+/// around `slot` when given, then in all the stored trades, so an older trade is found too,
+/// only slower. The signatures of the swap events already dropped by the daily aggregation
+/// return an empty array.
 #[utoipa::path(
     get,
     path = "/trades",
     params(TradeQuery),
     responses(
-        (status = 200, description = "Trades retrieved successfully", body = Vec<Trade>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Trades retrieved successfully", body = Vec<DenominatedTrade>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_trades(
     State(state): State<AppState>,
     query: Query<TradeQuery>,
-) -> Result<Json<Vec<Trade>>, SonarError> {
-    let swaps = state
+) -> Result<Json<Vec<DenominatedTrade>>, ApiError> {
+    if query.min_pct_supply.is_some_and(|min| !(0.0..=1.0).contains(&min)) {
+        return Err(ApiError::invalid_parameter("min_pct_supply", "must be between 0 and 1"));
+    }
+    let filter = TradeFilter {
+        address: query.address.clone(),
+        token: query.token.clone(),
+        pair: query.pair.clone(),
+        signature: query.signature.clone(),
+        slot: query.slot,
+        time_from: query.time_from,
+        time_to: None,
+        category: query.category,
+        min_pct_supply: query.min_pct_supply,
+        limit: query.limit,
+        offset: query.offset,
+    };
+    let swaps = state.db.get_trades(&filter).await?;
+    let sol_prices = match query.denomination.unwrap_or_default() {
+        Denomination::Usd => None,
+        Denomination::Sol => trade_sol_prices(&state, &swaps).await?,
+    };
+    let owner_labels = load_owner_labels(&state, &swaps).await?;
+    let trades = swaps
+        .into_iter()
+        .map(|trade| {
+            let price_sol = sol_prices
+                .as_ref()
+                .and_then(|prices| prices.nearest(trade.timestamp))
+                .map(|sol_price| trade.price / sol_price);
+            let owner_label = owner_labels.get(&trade.owner).map(|label| label.label.clone());
+            DenominatedTrade { trade, price_sol, owner_label }
+        })
+        .collect();
+    Ok(Json(trades))
+}
+
+/// The format of an export body
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A JSON trade per line
+    #[default]
+    Ndjson,
+    /// A csv record per trade after a header line
+    Csv,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+
+    /// line serializes a trade into a line of the export
+    fn line(self, trade: &Trade) -> String {
+        match self {
+            ExportFormat::Ndjson => serde_json::to_string(trade).unwrap_or_default() + "\n",
+            ExportFormat::Csv => trade.to_csv() + "\n",
+        }
+    }
+
+    /// trailer returns the last line of an export ended before its last trade,
+    /// `reason` is `truncated` or `error`
+    fn trailer(self, reason: &str, rows: usize) -> String {
+        match self {
+            ExportFormat::Ndjson => format!("{{\"{reason}\":true,\"rows\":{rows}}}\n"),
+            ExportFormat::Csv => format!("# {reason} after {rows} rows\n"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, utoipa::IntoParams, utoipa::ToSchema)]
+pub struct TradeExportQuery {
+    /// The mint of the exported trades
+    pub token: String,
+    /// Start unix timestamp, inclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<u64>,
+    /// End unix timestamp, exclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<u64>,
+    /// `csv` or `ndjson`, defaults to ndjson
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<ExportFormat>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/trades/export",
+    params(TradeExportQuery),
+    responses(
+        (status = 200, description = "Trades streamed in ascending time order, a `truncated` or `error` trailer line ends an incomplete export", body = String),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 401, description = "Missing or invalid export api key", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn export_trades(
+    State(state): State<AppState>,
+    Query(query): Query<TradeExportQuery>,
+) -> Result<Response, ApiError> {
+    check_address("token", &query.token)?;
+    if query.from.zip(query.to).is_some_and(|(from, to)| from >= to) {
+        return Err(ApiError::invalid_parameter("to", "must be after from"));
+    }
+    let format = query.format.unwrap_or_default();
+    let max_rows = state.export_max_rows;
+    let filter = TradeFilter {
+        token: Some(query.token),
+        time_from: query.from,
+        time_to: query.to,
+        // one more trade than exported tells the truncated exports apart
+        limit: Some(max_rows.saturating_add(1)),
+        ..Default::default()
+    };
+    let body = Body::from_stream(export_chunks(state.db.clone(), filter, format, max_rows));
+    Ok(([(header::CONTENT_TYPE, format.content_type())], body).into_response())
+}
+
+/// export_chunks serializes the trades of the filter by chunks of `EXPORT_CHUNK_ROWS`, so that
+/// a chunk at most is held in memory whatever the size of the export. The export ends with a
+/// trailer line past `max_rows` trades or when the database fails
+fn export_chunks(
+    db: Arc<Database>,
+    filter: TradeFilter,
+    format: ExportFormat,
+    max_rows: usize,
+) -> impl Stream<Item = Result<String, Infallible>> + Send + 'static {
+    async_stream::stream! {
+        let mut trades = db.stream_trades(filter);
+        let mut chunk = match format {
+            ExportFormat::Csv => format!("{TRADE_CSV_HEADER}\n"),
+            ExportFormat::Ndjson => String::new(),
+        };
+        let mut rows = 0;
+        while let Some(trade) = trades.next().await {
+            let trade = match trade {
+                Ok(trade) => trade,
+                Err(e) => {
+                    error!(error = ?e, rows, "Failed to export trades");
+                    chunk.push_str(&format.trailer("error", rows));
+                    break;
+                }
+            };
+            if rows == max_rows {
+                chunk.push_str(&format.trailer("truncated", rows));
+                break;
+            }
+            chunk.push_str(&format.line(&trade));
+            rows += 1;
+            if rows % EXPORT_CHUNK_ROWS == 0 {
+                yield Ok(std::mem::take(&mut chunk));
+            }
+        }
+        if !chunk.is_empty() {
+            yield Ok(chunk);
+        }
+    }
+}
+
+/// load_owner_labels returns the labels of the labeled owners of the trades by address, the
+/// lookups missing from the kv store are read in a single query and cached for an hour,
+/// unlabeled owners included
+async fn load_owner_labels(
+    state: &AppState,
+    trades: &[Trade],
+) -> Result<HashMap<String, WalletLabel>, ApiError> {
+    let owners: Vec<&str> = trades
+        .iter()
+        .map(|trade| trade.owner.as_str())
+        .filter(|owner| !owner.is_empty())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let cached = state.kv_store.get_wallet_labels(&owners).await?;
+    let mut labels = HashMap::new();
+    let mut missing = vec![];
+    for (owner, cached) in owners.into_iter().zip(cached) {
+        match cached {
+            Some(Some(label)) => {
+                labels.insert(owner.to_string(), label);
+            }
+            Some(None) => {}
+            None => missing.push(owner),
+        }
+    }
+    if missing.is_empty() {
+        return Ok(labels);
+    }
+    let found: HashMap<String, WalletLabel> = state
         .db
-        .get_trades(
-            query.address.as_deref(),
-            query.token.as_deref(),
-            query.pair.as_deref(),
-            query.signature.as_deref(),
-            query.limit,
-            query.offset,
-        )
-        .await?;
-    Ok(Json(swaps))
+        .get_wallet_labels(&missing)
+        .await?
+        .into_iter()
+        .map(|label| (label.address.clone(), label))
+        .collect();
+    for owner in missing {
+        let label = found.get(owner);
+        state.kv_store.set_wallet_label(owner, label).await?;
+        if let Some(label) = label {
+            labels.insert(owner.to_string(), label.clone());
+        }
+    }
+    Ok(labels)
+}
+
+/// trade_sol_prices loads the SOL prices over the time span of trades
+async fn trade_sol_prices(
+    state: &AppState,
+    trades: &[Trade],
+) -> Result<Option<QuotePrices>, ApiError> {
+    let timestamps = trades.iter().map(|trade| trade.timestamp);
+    let (Some(from), Some(to)) = (timestamps.clone().min(), timestamps.max()) else {
+        return Ok(None);
+    };
+    let interval = sol_interval(to - from);
+    Ok(Some(load_sol_prices(state, &interval, from, to).await?))
+}
+
+/// sol_interval returns the finest interval covering `span` seconds within `MAX_SOL_BUCKETS`
+fn sol_interval(span: u64) -> CandlestickInterval {
+    [CandlestickInterval::OneMinute, CandlestickInterval::OneHour]
+        .into_iter()
+        .find(|interval| span / interval.get_seconds() as u64 <= MAX_SOL_BUCKETS)
+        .unwrap_or(CandlestickInterval::OneDay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::{test_utils::make_swap_event, Database, DatabaseTrait, KvStore, MemoryDb};
+    use std::sync::Arc;
+
+    fn trade(owner: &str) -> Trade {
+        let event = make_swap_event("token", "pair", owner, 0, 1.0);
+        Trade { owner: owner.to_string(), ..event.into() }
+    }
+
+    fn cex_label(address: &str) -> WalletLabel {
+        WalletLabel {
+            address: address.to_string(),
+            label: "Binance 1".to_string(),
+            category: WalletCategory::Cex.to_string(),
+            added_at: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_owner_labels_cache() {
+        let db = MemoryDb::default();
+        db.upsert_wallet_label(&cex_label("cex")).await.unwrap();
+        let boxed: Database = Box::new(db.clone());
+        let state = AppState::new(Arc::new(boxed), Arc::new(KvStore::in_memory()));
+
+        // a single lookup for the page, whatever the number of trades of an owner
+        let trades = [trade("cex"), trade("cex"), trade("wallet")];
+        let labels = load_owner_labels(&state, &trades).await.unwrap();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels["cex"].label, "Binance 1");
+        assert_eq!(db.wallet_label_lookups(), 1);
+
+        // the labeled and the unlabeled owners are both cached
+        let labels = load_owner_labels(&state, &trades).await.unwrap();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(db.wallet_label_lookups(), 1);
+
+        // only the owners missing from the cache are looked up
+        db.upsert_wallet_label(&cex_label("new-cex")).await.unwrap();
+        let trades = [trade("cex"), trade("new-cex")];
+        let labels = load_owner_labels(&state, &trades).await.unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(db.wallet_label_lookups(), 2);
+
+        // no owner, no lookup
+        assert!(load_owner_labels(&state, &[]).await.unwrap().is_empty());
+        assert_eq!(db.wallet_label_lookups(), 2);
+    }
+
+    #[test]
+    fn test_sol_interval() {
+        assert_eq!(sol_interval(0), CandlestickInterval::OneMinute);
+        assert_eq!(sol_interval(86_400), CandlestickInterval::OneMinute);
+        assert_eq!(sol_interval(86_400 * 7), CandlestickInterval::OneHour);
+        assert_eq!(sol_interval(86_400 * 365), CandlestickInterval::OneDay);
+    }
 }
@@ -1,31 +1,42 @@
 use crate::{
-    errors::{SonarError, SonarErrorKind},
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Query},
     state::AppState,
+    validation::{check_address, check_addresses},
 };
 use anyhow::Result;
-use axum::{
-    extract::{Query, State},
-    response::Json,
-};
+use axum::extract::State;
+use chrono::{NaiveDate, TimeDelta, Utc};
 use futures::future;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::{formats::CommaSeparator, serde_as, skip_serializing_none, StringWithSeparator};
 use sonar_db::{
-    models::tokens::{Token, TokenDailyStat, TokenSearch, TokenStat},
-    TopToken,
+    models::tokens::{
+        Token, TokenDailyRollup, TokenDailyStat, TokenRiskFlags, TokenSearchResult, TokenStat,
+    },
+    SortOrder, TopTokensPage, TopTokensSort,
 };
 use sonar_token_metadata::get_token_metadata_with_data;
+use std::collections::HashMap;
 use tracing::{instrument, warn};
 use validator::Validate;
 
 #[skip_serializing_none]
 #[derive(Debug, Deserialize, Validate, utoipa::IntoParams, utoipa::ToSchema)]
 pub struct TopTokensQuery {
+    #[validate(range(min = 1, max = 500))]
     pub limit: Option<usize>,
+    pub offset: Option<usize>,
     pub min_volume: Option<f64>,
     pub min_market_cap: Option<f64>,
     pub timeframe: Option<u64>,
     pub pumpfun: Option<bool>,
+    /// exclude swaps tagged as wash trades
+    pub exclude_wash: Option<bool>,
+    /// the column the tokens are ranked by, volume by default
+    pub sort_by: Option<TopTokensSort>,
+    /// the order of the ranking, descending by default
+    pub order: Option<SortOrder>,
 }
 
 #[utoipa::path(
@@ -33,27 +44,40 @@ pub struct TopTokensQuery {
     path = "/top-tokens",
     params(TopTokensQuery),
     responses(
-        (status = 200, description = "Top tokens retrieved successfully", body = Vec<TopToken>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Top tokens retrieved successfully", body = TopTokensPage),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_top_tokens(
     State(state): State<AppState>,
     query: Query<TopTokensQuery>,
-) -> Result<Json<Vec<TopToken>>, SonarError> {
+) -> Result<Json<TopTokensPage>, ApiError> {
+    query.validate()?;
     let time_range = query.timeframe.unwrap_or(86400); // 24h in seconds
     let current_time = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|_| SonarErrorKind::InvalidQuery("Failed to get current time".to_string()))?;
+        .map_err(|e| ApiError::Internal(e.into()))?;
     let current_time = current_time.as_secs();
-    let start_time = current_time - time_range;
+    let start_time = current_time
+        .checked_sub(time_range)
+        .ok_or_else(|| ApiError::invalid_parameter("timeframe", "exceeds the current time"))?;
     let limit = query.limit.unwrap_or(10);
 
     let tokens = state
         .db
-        .get_top_tokens(limit, start_time, query.min_volume, query.min_market_cap, query.pumpfun)
+        .get_top_tokens(
+            limit,
+            query.offset.unwrap_or(0),
+            start_time,
+            query.min_volume,
+            query.min_market_cap,
+            query.pumpfun,
+            query.exclude_wash.unwrap_or(false),
+            query.sort_by.unwrap_or_default(),
+            query.order.unwrap_or_default(),
+        )
         .await?;
     Ok(Json(tokens))
 }
@@ -64,6 +88,8 @@ pub struct TokenStatsQuery {
     #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
     #[validate(length(min = 1))]
     pub tokens: Vec<String>,
+    /// exclude swaps tagged as wash trades, only applies to `/token-stats`
+    pub exclude_wash: Option<bool>,
 }
 
 #[utoipa::path(
@@ -72,16 +98,20 @@ pub struct TokenStatsQuery {
     params(TokenStatsQuery),
     responses(
         (status = 200, description = "Token stats retrieved successfully", body = Vec<TokenStat>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_tokens_stats(
     State(state): State<AppState>,
     query: Query<TokenStatsQuery>,
-) -> Result<Json<Vec<TokenStat>>, SonarError> {
-    let tokens = state.db.get_token_stats(query.tokens.clone()).await?;
+) -> Result<Json<Vec<TokenStat>>, ApiError> {
+    query.validate()?;
+    check_addresses("tokens", &query.tokens)?;
+    let tokens =
+        state.db.get_token_stats(query.tokens.clone(), query.exclude_wash.unwrap_or(false)).await?;
     Ok(Json(tokens))
 }
 
@@ -91,19 +121,72 @@ pub async fn get_tokens_stats(
     params(TokenStatsQuery),
     responses(
         (status = 200, description = "Token daily stats retrieved successfully", body = Vec<TokenDailyStat>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_tokens_daily_stats(
     State(state): State<AppState>,
     query: Query<TokenStatsQuery>,
-) -> Result<Json<Vec<TokenDailyStat>>, SonarError> {
+) -> Result<Json<Vec<TokenDailyStat>>, ApiError> {
+    query.validate()?;
+    check_addresses("tokens", &query.tokens)?;
     let tokens = state.db.get_token_daily_stats(query.tokens.clone()).await?;
     Ok(Json(tokens))
 }
 
+/// The number of days `/token-history` returns when `from` is unset
+pub const DEFAULT_TOKEN_HISTORY_DAYS: i64 = 365;
+/// The widest range of days `/token-history` serves
+pub const MAX_TOKEN_HISTORY_DAYS: i64 = 3660;
+
+#[derive(Debug, Deserialize, utoipa::IntoParams, utoipa::ToSchema)]
+pub struct TokenHistoryQuery {
+    pub token: String,
+    /// the first day, `YYYY-MM-DD`, a year before `to` by default
+    #[param(value_type = Option<String>, format = Date)]
+    #[schema(value_type = Option<String>, format = Date)]
+    pub from: Option<NaiveDate>,
+    /// the last day included, `YYYY-MM-DD`, today by default
+    #[param(value_type = Option<String>, format = Date)]
+    #[schema(value_type = Option<String>, format = Date)]
+    pub to: Option<NaiveDate>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/token-history",
+    params(TokenHistoryQuery),
+    responses(
+        (status = 200, description = "Token daily rollups retrieved successfully", body = Vec<TokenDailyRollup>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn get_token_history(
+    State(state): State<AppState>,
+    query: Query<TokenHistoryQuery>,
+) -> Result<Json<Vec<TokenDailyRollup>>, ApiError> {
+    check_address("token", &query.token)?;
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - TimeDelta::days(DEFAULT_TOKEN_HISTORY_DAYS));
+    if from > to {
+        return Err(ApiError::invalid_parameter("from", "must not be after to"));
+    }
+    if (to - from).num_days() > MAX_TOKEN_HISTORY_DAYS {
+        return Err(ApiError::invalid_parameter(
+            "from",
+            format!("the range exceeds {MAX_TOKEN_HISTORY_DAYS} days"),
+        ));
+    }
+    let history = state.db.get_token_history(&query.token, from, to).await?;
+    Ok(Json(history))
+}
+
 #[derive(Debug, Deserialize, Validate, utoipa::IntoParams, utoipa::ToSchema)]
 pub struct TokenMetadataQuery {
     #[validate(length(min = 10))]
@@ -123,24 +206,51 @@ pub(crate) async fn get_token_from_state(state: &AppState, mint: &str) -> Option
     Some(token)
 }
 
+/// A token with the risk flags of its Token-2022 extensions
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct TokenWithRisk {
+    #[serde(flatten)]
+    pub token: Token,
+    /// Only set for Token-2022 mints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_flags: Option<TokenRiskFlags>,
+    /// The canonical pool of the token, charted by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_pair: Option<String>,
+}
+
 #[utoipa::path(
     get,
     path = "/token",
     params(TokenMetadataQuery),
     responses(
-        (status = 200, description = "Token retrieved successfully", body = Option<Token>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Token retrieved successfully", body = TokenWithRisk),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody),
+        (status = 404, description = "Token not found", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_token(
     State(state): State<AppState>,
     query: Query<TokenMetadataQuery>,
-) -> Result<Json<Option<Token>>, SonarError> {
+) -> Result<Json<TokenWithRisk>, ApiError> {
     query.validate()?;
-    let token = get_token_from_state(&state, &query.token).await;
-    Ok(Json(token))
+    check_address("token", &query.token)?;
+    let token = get_token_from_state(&state, &query.token)
+        .await
+        .ok_or_else(|| ApiError::NotFound(format!("token {}", query.token)))?;
+    // the flags are best effort, a kv failure shouldn't hide the token
+    let risk_flags = state.kv_store.get_token_risk_flags(&query.token).await.unwrap_or_else(|e| {
+        warn!("Failed to get token risk flags: {}", e);
+        None
+    });
+    let primary_pair = state.kv_store.get_primary_pair(&query.token).await.unwrap_or_else(|e| {
+        warn!("Failed to get token primary pair: {}", e);
+        None
+    });
+    Ok(Json(TokenWithRisk { token, risk_flags, primary_pair: primary_pair.map(|p| p.pair) }))
 }
 
 #[serde_as]
@@ -157,16 +267,18 @@ pub struct TokensQuery {
     params(TokensQuery),
     responses(
         (status = 200, description = "Tokens retrieved successfully", body = Vec<Token>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn get_tokens(
     State(state): State<AppState>,
     query: Query<TokensQuery>,
-) -> Result<Json<Vec<Token>>, SonarError> {
+) -> Result<Json<Vec<Token>>, ApiError> {
     query.validate()?;
+    check_addresses("tokens", &query.tokens)?;
     let mints = query.tokens.clone();
     let tasks = mints.iter().map(|mint| get_token_from_state(&state, mint));
     let tokens = future::join_all(tasks).await;
@@ -174,6 +286,115 @@ pub async fn get_tokens(
     Ok(Json(tokens))
 }
 
+/// The most mints of a `/tokens/resolve` request
+pub const MAX_RESOLVE_TOKENS: u64 = 100;
+
+#[derive(Debug, Deserialize, Validate, utoipa::ToSchema)]
+pub struct ResolveTokensBody {
+    #[validate(length(min = 1, max = MAX_RESOLVE_TOKENS))]
+    pub tokens: Vec<String>,
+    /// start resolving the unknown mints in the background, true by default
+    pub resolve: Option<bool>,
+}
+
+/// Where the metadata of a mint stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolveStatus {
+    /// the metadata was cached or stored
+    Resolved,
+    /// the metadata is being resolved, a later request reads it from the cache
+    Resolving,
+    /// the metadata is unknown and no resolution was started
+    Unknown,
+}
+
+/// The metadata of a requested mint, with its status
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ResolvedTokenEntry {
+    pub mint: String,
+    pub status: ResolveStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/tokens/resolve",
+    request_body = ResolveTokensBody,
+    responses(
+        (status = 200, description = "Tokens resolved in the order of the request", body = Vec<ResolvedTokenEntry>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 422, description = "Invalid token address", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state, body))]
+pub async fn resolve_tokens(
+    State(state): State<AppState>,
+    body: Json<ResolveTokensBody>,
+) -> Result<Json<Vec<ResolvedTokenEntry>>, ApiError> {
+    body.validate()?;
+    check_addresses("tokens", &body.tokens)?;
+    let mints: Vec<&str> = body.tokens.iter().map(String::as_str).collect();
+
+    // a kv failure falls back to the db
+    let mut tokens = state.kv_store.get_tokens(&mints).await.unwrap_or_else(|e| {
+        warn!("Failed to get tokens from kv store: {}", e);
+        vec![None; mints.len()]
+    });
+
+    let misses: Vec<&str> =
+        mints.iter().zip(&tokens).filter(|(_, t)| t.is_none()).map(|(m, _)| *m).collect();
+    if !misses.is_empty() {
+        let stored: HashMap<String, Token> = state
+            .db
+            .get_tokens(&misses)
+            .await?
+            .into_iter()
+            .map(|token| (token.token.clone(), token))
+            .collect();
+        // the stored tokens are cached for the next lookups
+        let kv_store = &state.kv_store;
+        future::join_all(stored.iter().map(|(mint, token)| async move {
+            if let Err(e) = kv_store.set_token(mint, token).await {
+                warn!("Failed to set token in kv store: {}", e);
+            }
+        }))
+        .await;
+        for (mint, token) in mints.iter().zip(tokens.iter_mut()) {
+            if token.is_none() {
+                *token = stored.get(*mint).cloned();
+            }
+        }
+    }
+
+    let resolve = body.resolve.unwrap_or(true);
+    let mut started: HashMap<&str, bool> = HashMap::new();
+    let entries = mints
+        .iter()
+        .zip(tokens)
+        .map(|(mint, token)| {
+            let status = match &token {
+                Some(_) => ResolveStatus::Resolved,
+                None if !resolve => ResolveStatus::Unknown,
+                None => {
+                    let started = *started.entry(*mint).or_insert_with(|| {
+                        state.token_resolver.spawn(mint, state.kv_store.clone(), state.db.clone())
+                    });
+                    if started {
+                        ResolveStatus::Resolving
+                    } else {
+                        ResolveStatus::Unknown
+                    }
+                }
+            };
+            ResolvedTokenEntry { mint: mint.to_string(), status, token }
+        })
+        .collect();
+    Ok(Json(entries))
+}
+
 #[derive(Debug, Deserialize, Validate, utoipa::IntoParams, utoipa::ToSchema)]
 pub struct CreateTokenBody {
     #[serde(flatten)]
@@ -186,20 +407,25 @@ pub struct CreateTokenBody {
     path = "/token",
     request_body = CreateTokenBody,
     responses(
-        (status = 200, description = "Token created successfully", body = Option<Token>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Token created successfully", body = Token),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 404, description = "Token not found after insert", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn create_token(
     State(state): State<AppState>,
     body: Json<CreateTokenBody>,
-) -> Result<Json<Option<Token>>, SonarError> {
+) -> Result<Json<Token>, ApiError> {
     body.validate()?;
     let token = body.token.clone();
     state.db.insert_token(&token).await?;
-    let token = state.db.get_token(&body.token.token).await?;
+    let token = state
+        .db
+        .get_token(&body.token.token)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("token {}", body.token.token)))?;
     Ok(Json(token))
 }
 
@@ -208,6 +434,9 @@ pub struct SearchQuery {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[schema(rename = "s")]
     pub s: String,
+    /// Return the ranking score and match reason of every result
+    #[serde(default)]
+    pub debug: bool,
 }
 
 #[utoipa::path(
@@ -215,17 +444,23 @@ pub struct SearchQuery {
     path = "/search",
     params(SearchQuery),
     responses(
-        (status = 200, description = "Search results retrieved successfully", body = Vec<TokenSearch>),
-        (status = 400, description = "Invalid request parameters"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Search results retrieved successfully", body = Vec<TokenSearchResult>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
     )
 )]
 #[instrument(skip(state))]
 pub async fn search(
     State(state): State<AppState>,
     query: Query<SearchQuery>,
-) -> Result<Json<Vec<TokenSearch>>, SonarError> {
+) -> Result<Json<Vec<TokenSearchResult>>, ApiError> {
     query.validate()?;
-    let tokens = state.db.search_tokens(&query.s).await?;
+    let mut tokens = state.db.search_tokens(&query.s).await?;
+    if !query.debug {
+        for token in &mut tokens {
+            token.score = None;
+            token.match_reason = None;
+        }
+    }
     Ok(Json(tokens))
 }
@@ -0,0 +1,65 @@
+use crate::{
+    errors::{ApiError, ApiErrorBody},
+    extract::{Json, Query},
+    state::AppState,
+};
+use anyhow::Result;
+use axum::extract::State;
+use serde::Deserialize;
+use serde_with::skip_serializing_none;
+use sonar_db::WalletActivity;
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+/// The most tokens a wallet activity response holds
+const MAX_WALLET_ACTIVITY_TOKENS: usize = 500;
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Validate, IntoParams, ToSchema)]
+pub struct WalletActivityQuery {
+    #[validate(length(min = 10))]
+    pub address: String,
+    /// The start of the window, inclusive
+    pub from: Option<u64>,
+    /// The end of the window, inclusive
+    pub to: Option<u64>,
+    /// Only the trades owned by the wallet, not the ones it co-signed
+    #[serde(default)]
+    pub strict_owner: bool,
+}
+
+/// Get the trades of a wallet aggregated per token, ordered by turnover
+#[utoipa::path(
+    get,
+    path = "/wallet-activity",
+    params(WalletActivityQuery),
+    responses(
+        (status = 200, description = "Wallet activity retrieved successfully", body = Vec<WalletActivity>),
+        (status = 400, description = "Invalid request parameters", body = ApiErrorBody),
+        (status = 500, description = "Internal server error", body = ApiErrorBody)
+    )
+)]
+#[instrument(skip(state))]
+pub async fn get_wallet_activity(
+    State(state): State<AppState>,
+    query: Query<WalletActivityQuery>,
+) -> Result<Json<Vec<WalletActivity>>, ApiError> {
+    query.validate()?;
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(ApiError::invalid_parameter("from", "must not be after to"));
+        }
+    }
+    let activity = state
+        .db
+        .get_wallet_activity(
+            &query.address,
+            query.from,
+            query.to,
+            query.strict_owner,
+            MAX_WALLET_ACTIVITY_TOKENS,
+        )
+        .await?;
+    Ok(Json(activity))
+}
@@ -1,89 +1,201 @@
 use crate::{
-    shutdown::shutdown_signal_with_handler,
-    state::AppState,
-    ws::{init_adapter, on_connect, IoProxy},
+    auth::{require_admin_key, require_export_key},
+    timeout::enforce_timeout,
+    ws::{on_connect, IoProxy},
 };
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, put},
     Router,
 };
 use axum_otel::{AxumOtelSpanCreator, Level};
-use socketioxide::SocketIo;
+use socketioxide::{adapter::Adapter, handler::ConnectHandler, SocketIo};
 use socketioxide_redis::RedisAdapter;
-use sonar_db::{make_db_from_env, make_kv_store_from_env, make_redis_subscriber_from_env};
-use std::{env::var, sync::Arc};
+use sonar_auth::{authenticate, WsAuth};
+use sonar_db::{
+    make_db_with_config, make_kv_store_with_config, make_redis_subscriber,
+    shutdown::shutdown_signal_with_handler, KvStore, RedisSubscriber,
+};
+use sonar_token_metadata::{BackgroundResolver, RpcTokenResolver};
+use std::sync::Arc;
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
 use tower_http::{
+    compression::CompressionLayer,
     request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     trace::TraceLayer,
 };
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
+mod auth;
+mod config;
 mod errors;
+mod extract;
 mod handlers;
-mod shutdown;
 mod state;
+mod timeout;
+mod validation;
 mod ws;
 
-/// Initialize the API server
-pub async fn init_api() -> std::io::Result<()> {
-    let port: u16 = var("PORT")
-        .expect("Expected PORT to be set")
-        .parse()
-        .expect("Expected PORT to be a number");
-    let addr = format!("0.0.0.0:{}", port);
-
-    debug!("Initializing database...");
-    let mut db = make_db_from_env().await.expect("Failed to create database");
-    db.initialize().await.expect("Failed to initialize database");
-    debug!("Initializing kv");
-    let kv_store = make_kv_store_from_env().await.expect("Failed to create KvStore client");
-    debug!("Initializing redis subscriber");
-    let redis_subscriber =
-        make_redis_subscriber_from_env().await.expect("Failed to create RedisSubscriber");
-
-    let state: AppState = AppState { db: Arc::new(db), kv_store: Arc::new(kv_store) };
+pub use crate::{
+    auth::{AdminAuth, ExportAuth},
+    config::Config,
+    state::AppState,
+    timeout::RequestTimeouts,
+    ws::{init_adapter, make_redis_adapter, WsAdapter},
+};
 
-    let adapter = init_adapter().await.expect("Failed to create RedisAdapter");
-    let (socket_layer, io) = SocketIo::builder()
-        .with_state(state.clone())
-        .with_adapter::<RedisAdapter<_>>(adapter)
-        .build_layer();
+/// build_router returns the REST routes of the API, the socket.io layer and the `/health`
+/// route outside of it are added by `init_api`
+pub fn build_router(state: AppState, admin_auth: AdminAuth) -> Router {
+    let admin = Router::new()
+        .route("/aggregate-candlesticks", post(handlers::admin::aggregate_candlesticks))
+        .route("/tokens/{mint}/verified", put(handlers::admin::set_token_verified))
+        .route("/tokens/{mint}/primary-pair", put(handlers::admin::set_primary_pair))
+        .route("/refresh-tokens", post(handlers::admin::refresh_tokens))
+        .route("/wallet-labels", post(handlers::admin::import_wallet_labels))
+        .route_layer(middleware::from_fn_with_state(admin_auth, require_admin_key));
 
-    io.ns("/", on_connect).await.expect("Failed to create socket io");
+    // the exports may scan a day of trades, they need the export api key
+    let export = Router::new()
+        .route("/trades/export", get(handlers::swap::export_trades))
+        .route_layer(middleware::from_fn_with_state(state.export_auth.clone(), require_export_key));
 
-    let app = Router::new()
+    // the routes scanning the swap events or the candlesticks get a stricter deadline
+    let timeouts = state.request_timeouts;
+    let heavy = Router::new()
         .route("/top-tokens", get(handlers::tokens::get_top_tokens))
         .route("/candlesticks", get(handlers::candlesticks::get_candlesticks_by_token))
         .route("/token-ohlcv", get(handlers::candlesticks::get_candlesticks_by_token))
         .route("/pair-ohlcv", get(handlers::candlesticks::get_candlesticks_by_pair))
         .route("/ohlcv", post(handlers::candlesticks::aggregate_candlesticks))
+        .route("/search", get(handlers::tokens::search))
+        .route_layer(middleware::from_fn_with_state(timeouts.heavy, enforce_timeout));
+
+    Router::new()
+        .route("/candlestick-latest", get(handlers::candlesticks::get_latest_candlestick))
+        .route("/pairs", get(handlers::pairs::get_pairs))
+        .route("/pair", get(handlers::pairs::get_pair))
+        .route("/fee-stats", get(handlers::pairs::get_fee_stats))
         .route("/price", get(handlers::price::get_price))
         .route("/prices", post(handlers::price::get_prices))
         .route("/token-stats", get(handlers::tokens::get_tokens_stats))
         .route("/token-daily-stats", get(handlers::tokens::get_tokens_daily_stats))
+        .route("/token-history", get(handlers::tokens::get_token_history))
         .route("/token", get(handlers::tokens::get_token))
         .route("/tokens", get(handlers::tokens::get_tokens))
+        .route("/tokens/resolve", post(handlers::tokens::resolve_tokens))
         .route("/token", post(handlers::tokens::create_token))
         .route("/trades", get(handlers::swap::get_trades))
-        .route("/search", get(handlers::tokens::search))
+        .route("/wallet-activity", get(handlers::wallet::get_wallet_activity))
+        .merge(heavy)
+        .merge(export)
+        .nest("/admin", admin)
         .layer(
             ServiceBuilder::new()
                 .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(middleware::from_fn(errors::attach_request_id))
                 .layer(
                     TraceLayer::new_for_http()
                         .make_span_with(AxumOtelSpanCreator::new().level(Level::INFO)),
                 )
-                .layer(PropagateRequestIdLayer::x_request_id()),
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(middleware::from_fn_with_state(timeouts.default, enforce_timeout)),
         )
-        .layer(socket_layer)
-        .route("/health", get(handlers::health::get_health))
         .merge(handlers::api_doc())
-        .with_state(state);
+        // inside the socket.io layer of `init_api`, the socket.io requests never reach it
+        .layer(CompressionLayer::new().no_deflate().no_zstd())
+        .with_state(state)
+}
 
-    let io_proxy = IoProxy::new(Arc::new(redis_subscriber), Arc::new(io), None);
+/// spawn_io_proxy spawns the handlers forwarding the published messages to the socket.io rooms,
+/// nothing is published without a redis subscriber
+async fn spawn_io_proxy<A: Adapter>(
+    io: SocketIo<A>,
+    redis_subscriber: Option<Arc<RedisSubscriber>>,
+    kv_store: Arc<KvStore>,
+) {
+    let Some(redis_subscriber) = redis_subscriber else {
+        return;
+    };
+    let io_proxy = IoProxy::new(redis_subscriber, Arc::new(io), None).with_kv_store(kv_store);
     io_proxy.spawn_handlers().await.expect("Failed to spawn handlers");
+}
+
+/// Initialize the API server with the config of the env, an invalid config is returned
+/// as an `InvalidInput` error listing its problems
+pub async fn init_api() -> std::io::Result<()> {
+    let config =
+        Config::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    init_api_with_config(config).await
+}
+
+/// Initialize the API server with `config`
+pub async fn init_api_with_config(config: Config) -> std::io::Result<()> {
+    let addr = format!("0.0.0.0:{}", config.port);
+
+    debug!("Initializing database...");
+    // make_db_with_config initializes the database
+    let db = make_db_with_config(&config.database).await.map_err(std::io::Error::other)?;
+    let (kv_store, redis_subscriber) = match &config.redis {
+        Some(redis) => {
+            debug!("Initializing kv");
+            let kv_store =
+                make_kv_store_with_config(redis).await.expect("Failed to create KvStore client");
+            debug!("Initializing redis subscriber");
+            let redis_subscriber =
+                make_redis_subscriber(&redis.url).await.expect("Failed to create RedisSubscriber");
+            (kv_store, Some(Arc::new(redis_subscriber)))
+        }
+        None => {
+            warn!("REDIS_URL is not set, the kv store is kept in memory and nothing is published to the sockets");
+            (KvStore::in_memory(), None)
+        }
+    };
+
+    let db = Arc::new(db);
+    let state = AppState::new(db.clone(), Arc::new(kv_store))
+        .with_price_max_staleness_secs(config.price_max_staleness_secs)
+        .with_outlier_policy(config.outlier_policy.clone())
+        .with_max_candlestick_buckets(config.max_candlestick_buckets)
+        .with_compact_digits(config.compact_digits)
+        .with_export_max_rows(config.export_max_rows)
+        .with_export_auth(ExportAuth::new(config.export_api_key.clone()))
+        .with_token_resolver(BackgroundResolver::new(
+            Arc::new(RpcTokenResolver),
+            config.token_resolve_concurrency,
+        ))
+        .with_request_timeouts(config.request_timeouts);
+
+    let kv_store = state.kv_store.clone();
+    let builder = SocketIo::builder()
+        .with_state(state.clone())
+        .with_state(WsAuth::from_config(&config.ws_auth));
+    let app = build_router(state, AdminAuth::new(config.admin_api_key.clone()));
+
+    // a single instance doesn't need the rooms shared through redis
+    let app = match config.ws_adapter {
+        WsAdapter::Redis => {
+            let redis_url = config.redis_adapter_url.as_deref().unwrap_or_default();
+            let adapter =
+                make_redis_adapter(redis_url).await.expect("Failed to create RedisAdapter");
+            let (socket_layer, io) = builder.with_adapter::<RedisAdapter<_>>(adapter).build_layer();
+            io.ns("/", on_connect.with(authenticate)).await.expect("Failed to create socket io");
+            spawn_io_proxy(io, redis_subscriber, kv_store).await;
+            app.layer(socket_layer)
+        }
+        WsAdapter::Local => {
+            warn!(
+                "Using the local socket.io adapter, the rooms are not shared across the instances"
+            );
+            let (socket_layer, io) = builder.build_layer();
+            io.ns("/", on_connect.with(authenticate));
+            spawn_io_proxy(io, redis_subscriber, kv_store).await;
+            app.layer(socket_layer)
+        }
+    };
+    // the liveness probes bypass the socket.io layer
+    let app = app.route("/health", get(handlers::health::get_health));
 
     // Create a `TcpListener` using tokio.
     let listener = TcpListener::bind(addr).await.expect("Failed to bind to address");
@@ -94,5 +206,8 @@ pub async fn init_api() -> std::io::Result<()> {
         }))
         .await?;
     info!("Server shutdown at {:?}", chrono::Utc::now());
+    if let Err(e) = db.close().await {
+        error!(error = ?e, "Failed to close db");
+    }
     Ok(())
 }
@@ -1,8 +1,99 @@
-use sonar_db::{Database, KvStore};
+use crate::{
+    auth::ExportAuth,
+    handlers::{
+        candlesticks::{DEFAULT_COMPACT_DIGITS, DEFAULT_MAX_CANDLESTICK_BUCKETS},
+        swap::DEFAULT_EXPORT_MAX_ROWS,
+    },
+    timeout::RequestTimeouts,
+};
+use sonar_db::{Database, KvStore, OutlierPolicy};
+use sonar_token_metadata::BackgroundResolver;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub kv_store: Arc<KvStore>,
     pub db: Arc<Database>,
+    /// KV prices older than this are ignored in favour of the database
+    pub price_max_staleness_secs: u64,
+    /// The default clamping of candlestick outliers, overridden by the `clamp` query parameter
+    pub outlier_policy: OutlierPolicy,
+    /// The most candlesticks a request may span or return
+    pub max_candlestick_buckets: usize,
+    /// The significant digits of the prices of the compact candlesticks
+    pub compact_digits: u32,
+    /// The server side deadlines of the requests
+    pub request_timeouts: RequestTimeouts,
+    /// The most trades of an export, a trailer line marks the truncated exports
+    pub export_max_rows: usize,
+    /// The api key of `/trades/export`, the exports are rejected without one
+    pub export_auth: ExportAuth,
+    /// Resolves the unknown tokens of `/tokens/resolve` off the request path
+    pub token_resolver: BackgroundResolver,
+}
+
+impl AppState {
+    /// new returns the state with a 60 seconds price staleness and the default outlier policy
+    pub fn new(db: Arc<Database>, kv_store: Arc<KvStore>) -> Self {
+        Self {
+            kv_store,
+            db,
+            price_max_staleness_secs: 60,
+            outlier_policy: OutlierPolicy::default(),
+            max_candlestick_buckets: DEFAULT_MAX_CANDLESTICK_BUCKETS,
+            compact_digits: DEFAULT_COMPACT_DIGITS,
+            request_timeouts: RequestTimeouts::default(),
+            export_max_rows: DEFAULT_EXPORT_MAX_ROWS,
+            export_auth: ExportAuth::default(),
+            token_resolver: BackgroundResolver::default(),
+        }
+    }
+
+    /// Set the age after which KV prices are ignored.
+    pub fn with_price_max_staleness_secs(mut self, price_max_staleness_secs: u64) -> Self {
+        self.price_max_staleness_secs = price_max_staleness_secs;
+        self
+    }
+
+    /// Set the default clamping of candlestick outliers.
+    pub fn with_outlier_policy(mut self, outlier_policy: OutlierPolicy) -> Self {
+        self.outlier_policy = outlier_policy;
+        self
+    }
+
+    /// Set the most candlesticks a request may span or return.
+    pub fn with_max_candlestick_buckets(mut self, max_candlestick_buckets: usize) -> Self {
+        self.max_candlestick_buckets = max_candlestick_buckets;
+        self
+    }
+
+    /// Set the significant digits of the prices of the compact candlesticks.
+    pub fn with_compact_digits(mut self, compact_digits: u32) -> Self {
+        self.compact_digits = compact_digits;
+        self
+    }
+
+    /// Set the server side deadlines of the requests.
+    pub fn with_request_timeouts(mut self, request_timeouts: RequestTimeouts) -> Self {
+        self.request_timeouts = request_timeouts;
+        self
+    }
+
+    /// Set the most trades of an export.
+    pub fn with_export_max_rows(mut self, export_max_rows: usize) -> Self {
+        self.export_max_rows = export_max_rows;
+        self
+    }
+
+    /// Set the api key of the exports.
+    pub fn with_export_auth(mut self, export_auth: ExportAuth) -> Self {
+        self.export_auth = export_auth;
+        self
+    }
+
+    /// Set the resolver of the unknown tokens of `/tokens/resolve`.
+    pub fn with_token_resolver(mut self, token_resolver: BackgroundResolver) -> Self {
+        self.token_resolver = token_resolver;
+        self
+    }
 }
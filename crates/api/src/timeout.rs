@@ -0,0 +1,85 @@
+use crate::errors::ApiError;
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use sonar_db::EnvReader;
+use std::time::Duration;
+
+/// The deadline of the requests when `API_REQUEST_TIMEOUT_MS` is unset
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// The deadline of the heavy requests when `API_HEAVY_REQUEST_TIMEOUT_MS` is unset
+pub const DEFAULT_HEAVY_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server side deadlines of the requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    /// The deadline of every request
+    pub default: Duration,
+    /// The stricter deadline of the ohlcv, top tokens and search requests
+    pub heavy: Duration,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self { default: DEFAULT_REQUEST_TIMEOUT, heavy: DEFAULT_HEAVY_REQUEST_TIMEOUT }
+    }
+}
+
+impl RequestTimeouts {
+    /// from_env reads `API_REQUEST_TIMEOUT_MS` and `API_HEAVY_REQUEST_TIMEOUT_MS`, falling back
+    /// to the defaults, the heavy deadline never exceeds the default one
+    pub fn from_env() -> Result<Self> {
+        let mut env = EnvReader::from_env();
+        let timeouts = Self::read_env(&mut env);
+        Ok(env.finish(timeouts)?)
+    }
+
+    /// read_env reads the variables of `from_env`, recording the invalid ones
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let default = env.millis("API_REQUEST_TIMEOUT_MS").unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+        let heavy =
+            env.millis("API_HEAVY_REQUEST_TIMEOUT_MS").unwrap_or(DEFAULT_HEAVY_REQUEST_TIMEOUT);
+        Self { default, heavy: heavy.min(default) }
+    }
+}
+
+/// Middleware answering a 504 once the request outlives the timeout, the handler future is
+/// dropped with the database queries it awaits
+pub async fn enforce_timeout(
+    State(timeout): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    tokio::time::timeout(timeout, next.run(request)).await.map_err(|_| ApiError::Timeout(timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_timeouts_from_env() {
+        let read = |vars: &[(&str, &str)]| {
+            let mut env = EnvReader::from_vars(vars);
+            let timeouts = RequestTimeouts::read_env(&mut env);
+            env.finish(timeouts)
+        };
+        assert_eq!(read(&[]).unwrap(), RequestTimeouts::default());
+
+        let timeouts = read(&[("API_REQUEST_TIMEOUT_MS", "5000")]).unwrap();
+        // the heavy deadline is capped by the default one
+        assert_eq!(timeouts.default, Duration::from_secs(5));
+        assert_eq!(timeouts.heavy, Duration::from_secs(5));
+
+        let timeouts =
+            read(&[("API_REQUEST_TIMEOUT_MS", "5000"), ("API_HEAVY_REQUEST_TIMEOUT_MS", "1500")])
+                .unwrap();
+        assert_eq!(timeouts.heavy, Duration::from_millis(1500));
+
+        assert!(read(&[("API_HEAVY_REQUEST_TIMEOUT_MS", "0")]).is_err());
+        assert!(read(&[("API_HEAVY_REQUEST_TIMEOUT_MS", "soon")]).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Validation of the addresses given to the handlers, before they reach the storages
+use crate::errors::ApiError;
+use solana_pubkey::Pubkey;
+use std::str::FromStr;
+
+/// is_valid_base58_pubkey returns true if the address is a base58 encoded public key,
+/// e.g. a mint, a pair or a wallet
+pub fn is_valid_base58_pubkey(address: &str) -> bool {
+    Pubkey::from_str(address).is_ok()
+}
+
+/// check_address returns an invalid address error for the field unless the address is a
+/// base58 encoded public key
+pub fn check_address(field: impl Into<String>, address: &str) -> Result<(), ApiError> {
+    if is_valid_base58_pubkey(address) {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidAddress { field: field.into() })
+    }
+}
+
+/// check_addresses returns an invalid address error for the first invalid address of the
+/// list, named `field[index]`
+pub fn check_addresses(field: &str, addresses: &[String]) -> Result<(), ApiError> {
+    addresses
+        .iter()
+        .enumerate()
+        .try_for_each(|(index, address)| check_address(format!("{field}[{index}]"), address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_base58_pubkey() {
+        assert!(is_valid_base58_pubkey("DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263"));
+        assert!(is_valid_base58_pubkey("So11111111111111111111111111111111111111112"));
+        assert!(!is_valid_base58_pubkey(""));
+        assert!(!is_valid_base58_pubkey("DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB26'"));
+        // 0, O, I and l are not base58
+        assert!(!is_valid_base58_pubkey("0ezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263"));
+        // base58 but not 32 bytes
+        assert!(!is_valid_base58_pubkey("2Y6GkQJR93PNL1iYwGcjggoaB"));
+    }
+
+    #[test]
+    fn test_check_addresses() {
+        let addresses = vec![
+            "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263".to_string(),
+            "bonk' OR '1'='1".to_string(),
+        ];
+        assert!(check_addresses("tokens", &addresses[..1]).is_ok());
+        let e = check_addresses("tokens", &addresses).unwrap_err();
+        assert_eq!(e.to_string(), "invalid address `tokens[1]`: expected a base58 public key");
+    }
+}
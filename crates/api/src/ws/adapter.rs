@@ -1,13 +1,125 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use socketioxide_redis::{
     drivers::redis::{redis_client::Client, RedisDriver},
     RedisAdapterCtr,
 };
+use sonar_db::EnvReader;
 use std::env;
 
-pub async fn init_adapter() -> Result<RedisAdapterCtr<RedisDriver>> {
-    let redis_url = env::var("REDIS_ADAPTER_URL").expect("Expected REDIS_ADAPTER_URL to be set");
+/// The socket.io adapter fanning the emits out, `Redis` shares the rooms across the
+/// instances of the api while `Local` keeps them in memory for a single instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsAdapter {
+    Local,
+    Redis,
+}
+
+impl std::str::FromStr for WsAdapter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "redis" => Ok(Self::Redis),
+            _ => bail!("WS_ADAPTER must be local or redis, got {s:?}"),
+        }
+    }
+}
+
+impl WsAdapter {
+    /// from_env reads `WS_ADAPTER`, the adapter is `redis` when unset and
+    /// `REDIS_ADAPTER_URL` is set, `local` otherwise
+    pub fn from_env() -> Result<Self> {
+        let mut env = EnvReader::from_env();
+        let adapter = Self::read_env(&mut env);
+        Ok(env.finish(adapter)?)
+    }
+
+    /// read_env reads the variables of `from_env`, recording an invalid `WS_ADAPTER`
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        match env.var("WS_ADAPTER") {
+            Some(v) => env.merge(v.parse()).unwrap_or(Self::Local),
+            None if env.var("REDIS_ADAPTER_URL").is_some() => Self::Redis,
+            None => Self::Local,
+        }
+    }
+}
+
+/// make_redis_adapter connects the socket.io adapter to `redis_url`
+pub async fn make_redis_adapter(redis_url: &str) -> Result<RedisAdapterCtr<RedisDriver>> {
     let client = Client::open(redis_url)?;
     let adapter = RedisAdapterCtr::new_with_redis(&client).await?;
     Ok(adapter)
 }
+
+pub async fn init_adapter() -> Result<RedisAdapterCtr<RedisDriver>> {
+    let redis_url =
+        env::var("REDIS_ADAPTER_URL").context("Expected REDIS_ADAPTER_URL to be set")?;
+    make_redis_adapter(&redis_url).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::on_connect;
+    use engineioxide::Packet;
+    use socketioxide::{handler::ConnectHandler, SocketIo};
+    use sonar_auth::{authenticate, WsAuth};
+    use std::time::Duration;
+
+    fn with_env(vars: &[(&str, &str)], f: impl FnOnce()) {
+        for name in ["WS_ADAPTER", "REDIS_ADAPTER_URL"] {
+            env::remove_var(name);
+        }
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        f();
+        for name in ["WS_ADAPTER", "REDIS_ADAPTER_URL"] {
+            env::remove_var(name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adapter_from_env() {
+        with_env(&[], || assert_eq!(WsAdapter::from_env().unwrap(), WsAdapter::Local));
+        with_env(&[("REDIS_ADAPTER_URL", "redis://localhost:6379")], || {
+            assert_eq!(WsAdapter::from_env().unwrap(), WsAdapter::Redis)
+        });
+        with_env(
+            &[("WS_ADAPTER", "local"), ("REDIS_ADAPTER_URL", "redis://localhost:6379")],
+            || assert_eq!(WsAdapter::from_env().unwrap(), WsAdapter::Local),
+        );
+        with_env(&[("WS_ADAPTER", "Redis")], || {
+            assert_eq!(WsAdapter::from_env().unwrap(), WsAdapter::Redis)
+        });
+        with_env(&[("WS_ADAPTER", "nats")], || assert!(WsAdapter::from_env().is_err()));
+
+        // the redis adapter can't be built without its url
+        assert!(init_adapter().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_adapter_round_trip() {
+        // the namespace of the api, without a redis connection
+        let (_svc, io) = SocketIo::builder().with_state(WsAuth::default()).build_svc();
+        io.ns("/", on_connect.with(authenticate));
+        let (_tx, mut rx) = io.new_dummy_sock("/", ()).await;
+
+        io.emit("candles_closed", &serde_json::json!({ "interval": "1m" })).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(1), async {
+            while let Some(packet) = rx.recv().await {
+                // the emits are socket.io EVENT packets, their type 2 followed by the json array
+                if let Packet::Message(message) = packet {
+                    if let Some(event) = message.strip_prefix('2') {
+                        return serde_json::from_str::<serde_json::Value>(event).ok();
+                    }
+                }
+            }
+            None
+        })
+        .await
+        .unwrap();
+        assert_eq!(received, Some(serde_json::json!(["candles_closed", { "interval": "1m" }])));
+    }
+}
@@ -0,0 +1,19 @@
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Extension, SocketRef, State},
+};
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+
+/// The room receiving the candlestick buckets closed by the scheduler
+pub const CANDLES_CLOSED_ROOM: &str = "candles_closed";
+
+pub async fn on_subscribe_candles_closed<A: Adapter>(
+    socket: SocketRef<A>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    let rooms =
+        authorize_rooms(&socket, &claims, &auth, vec![CANDLES_CLOSED_ROOM.to_string()], ack);
+    socket.join(rooms);
+}
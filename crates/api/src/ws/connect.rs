@@ -1,15 +1,22 @@
-pub use crate::ws::{event::RequestEvent, token::on_token_trade};
+pub use crate::ws::{
+    candles::on_subscribe_candles_closed, event::RequestEvent,
+    graduation::on_subscribe_graduations, large_trade::on_subscribe_large_trades,
+    new_pool::on_subscribe_new_pools, pair::on_subscribe_pair_price, resume::on_resume,
+    token::on_token_trade,
+};
 use socketioxide::{adapter::Adapter, extract::SocketRef};
 use tracing::{info, warn};
 
 /// Called when a client connects to the server
-pub async fn on_connect<A: Adapter>(
-    socket: SocketRef<A>,
-    // Data(data): Data<Value>, // auth data
-    // State(state): State<AppState>,
-) {
+pub async fn on_connect<A: Adapter>(socket: SocketRef<A>) {
     info!(ns = socket.ns(), ?socket.id, "Websocket connected");
     socket.on(RequestEvent::TokenTrade.to_string(), on_token_trade);
+    socket.on(RequestEvent::SubscribeNewPools.to_string(), on_subscribe_new_pools);
+    socket.on(RequestEvent::SubscribePairPrice.to_string(), on_subscribe_pair_price);
+    socket.on(RequestEvent::SubscribeGraduations.to_string(), on_subscribe_graduations);
+    socket.on(RequestEvent::SubscribeLargeTrades.to_string(), on_subscribe_large_trades);
+    socket.on(RequestEvent::SubscribeCandlesClosed.to_string(), on_subscribe_candles_closed);
+    socket.on(RequestEvent::Resume.to_string(), on_resume);
     socket.on_disconnect(on_disconnect);
 }
 
@@ -2,10 +2,38 @@
 pub enum RequestEvent {
     #[strum(to_string = "tokenTrade")]
     TokenTrade,
+    #[strum(to_string = "subscribe_new_pools")]
+    SubscribeNewPools,
+    #[strum(to_string = "subscribe_pair_price")]
+    SubscribePairPrice,
+    #[strum(to_string = "subscribe_graduations")]
+    SubscribeGraduations,
+    #[strum(to_string = "subscribe_large_trades")]
+    SubscribeLargeTrades,
+    #[strum(to_string = "subscribe_candles_closed")]
+    SubscribeCandlesClosed,
+    #[strum(to_string = "resume")]
+    Resume,
 }
 
 #[derive(Debug, Eq, PartialEq, strum_macros::Display)]
 pub enum ResponseEvent {
     #[strum(to_string = "tradeCreated")]
     TradeCreated,
+    #[strum(to_string = "new_pool")]
+    NewPool,
+    #[strum(to_string = "trade_snapshot")]
+    TradeSnapshot,
+    #[strum(to_string = "price")]
+    Price,
+    #[strum(to_string = "token_graduated")]
+    TokenGraduated,
+    #[strum(to_string = "trade_replay")]
+    TradeReplay,
+    #[strum(to_string = "large_trade")]
+    LargeTrade,
+    #[strum(to_string = "candles_closed")]
+    CandlesClosed,
+    #[strum(to_string = "token_stats_lite")]
+    TokenStatsLite,
 }
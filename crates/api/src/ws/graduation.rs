@@ -0,0 +1,18 @@
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Extension, SocketRef, State},
+};
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+
+/// The room receiving the tokens migrating from their bonding curve to an AMM pool
+pub const GRADUATIONS_ROOM: &str = "graduations";
+
+pub async fn on_subscribe_graduations<A: Adapter>(
+    socket: SocketRef<A>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    let rooms = authorize_rooms(&socket, &claims, &auth, vec![GRADUATIONS_ROOM.to_string()], ack);
+    socket.join(rooms);
+}
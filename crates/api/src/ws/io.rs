@@ -1,19 +1,68 @@
-use crate::ws::event::ResponseEvent;
+use crate::ws::{
+    candles::CANDLES_CLOSED_ROOM,
+    event::ResponseEvent,
+    graduation::GRADUATIONS_ROOM,
+    large_trade::LARGE_TRADES_ROOM,
+    new_pool::{new_pools_dex_room, NEW_POOLS_ROOM},
+    pair::{pair_room, PriceConflator, PAIR_PRICE_INTERVAL},
+    resume::SequencedTrade,
+    token_stats::{TokenStatsConflator, TOKEN_STATS_INTERVAL},
+};
 use anyhow::Result;
 use futures::StreamExt;
+use serde::Serialize;
 use socketioxide::{adapter::Adapter, SocketIo};
-use sonar_db::{RedisSubscriber, Trade};
-use std::sync::Arc;
+use sonar_db::{
+    decode_message, decode_sequenced_message,
+    models::{CandlesClosedEvent, NewPoolEvent, Token, TokenGraduatedEvent},
+    KvStore, RedisSubscriber, TradeV2, CANDLES_CLOSED_CHANNEL, GRADUATIONS_CHANNEL,
+    LARGE_TRADES_CHANNEL, NEW_POOLS_CHANNEL,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tracing::warn;
 
 pub const CHANNEL_BUFFER_SIZE: usize = 4 * 1000; // 4k
+/// How long a new pool waits for the metadata of its tokens before it is emitted without it
+const METADATA_LOOKUP_TIMEOUT: Duration = Duration::from_millis(200);
+
 pub struct IoProxy<A: Adapter> {
     io: Arc<SocketIo<A>>,
     redis_subscriber: Arc<RedisSubscriber>,
+    kv_store: Option<Arc<KvStore>>,
     pub channel_buffer_size: usize,
 }
 
+/// The metadata of a pool token, as cached by the ingestor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolTokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub uri: String,
+}
+
+impl From<Token> for PoolTokenMetadata {
+    fn from(token: Token) -> Self {
+        Self { name: token.name, symbol: token.symbol, decimals: token.decimals, uri: token.uri }
+    }
+}
+
+/// A new pool with the metadata of its tokens, omitted when not cached
+#[derive(Debug, Clone, Serialize)]
+pub struct NewPoolListing {
+    #[serde(flatten)]
+    pub pool: NewPoolEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_a: Option<PoolTokenMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_b: Option<PoolTokenMetadata>,
+}
+
 impl<A: Adapter> IoProxy<A> {
     pub fn new(
         redis_subscriber: Arc<RedisSubscriber>,
@@ -23,10 +72,17 @@ impl<A: Adapter> IoProxy<A> {
         Self {
             redis_subscriber,
             io,
+            kv_store: None,
             channel_buffer_size: channel_buffer_size.unwrap_or(CHANNEL_BUFFER_SIZE),
         }
     }
 
+    /// Set the kv store the new pools are enriched from.
+    pub fn with_kv_store(mut self, kv_store: Arc<KvStore>) -> Self {
+        self.kv_store = Some(kv_store);
+        self
+    }
+
     /// Set the channel buffer size for the trade receiver.
     #[allow(dead_code)]
     pub fn with_channel_buffer_size(mut self, channel_buffer_size: usize) -> Self {
@@ -46,7 +102,30 @@ impl<A: Adapter> IoProxy<A> {
         let trade_sender_clone = trade_sender.clone();
 
         let trade_fetcher = trade_fetcher(redis_subscriber_clone, trade_sender_clone);
-        let trade_processor = trade_processor(trade_receiver, io);
+        let conflator = Arc::new(Mutex::new(PriceConflator::default()));
+        let stats_conflator = Arc::new(Mutex::new(TokenStatsConflator::default()));
+        let trade_processor =
+            trade_processor(trade_receiver, io.clone(), conflator.clone(), stats_conflator.clone());
+        let pair_price_emitter = pair_price_emitter(conflator, self.kv_store.clone(), io.clone());
+        let token_stats_emitter = token_stats_emitter(stats_conflator, io.clone());
+
+        let (new_pool_sender, new_pool_receiver) = mpsc::channel(channel_buffer_size);
+        let new_pool_fetcher = new_pool_fetcher(redis_subscriber.clone(), new_pool_sender);
+        let new_pool_processor =
+            new_pool_processor(new_pool_receiver, self.kv_store.clone(), io.clone());
+
+        let (graduation_sender, graduation_receiver) = mpsc::channel(channel_buffer_size);
+        let graduation_fetcher = graduation_fetcher(redis_subscriber.clone(), graduation_sender);
+        let graduation_processor = graduation_processor(graduation_receiver, io.clone());
+
+        let (large_trade_sender, large_trade_receiver) = mpsc::channel(channel_buffer_size);
+        let large_trade_fetcher = large_trade_fetcher(redis_subscriber.clone(), large_trade_sender);
+        let large_trade_processor = large_trade_processor(large_trade_receiver, io.clone());
+
+        let (candles_closed_sender, candles_closed_receiver) = mpsc::channel(channel_buffer_size);
+        let candles_closed_fetcher =
+            candles_closed_fetcher(redis_subscriber.clone(), candles_closed_sender);
+        let candles_closed_processor = candles_closed_processor(candles_closed_receiver, io);
 
         tokio::spawn(async move {
             tokio::select! {
@@ -56,6 +135,36 @@ impl<A: Adapter> IoProxy<A> {
                 _ = trade_processor => {
                     warn!("Trade processor task completed");
                 }
+                _ = pair_price_emitter => {
+                    warn!("Pair price emitter task completed");
+                }
+                _ = token_stats_emitter => {
+                    warn!("Token stats emitter task completed");
+                }
+                _ = new_pool_fetcher => {
+                    warn!("New pool fetcher task completed");
+                }
+                _ = new_pool_processor => {
+                    warn!("New pool processor task completed");
+                }
+                _ = graduation_fetcher => {
+                    warn!("Graduation fetcher task completed");
+                }
+                _ = graduation_processor => {
+                    warn!("Graduation processor task completed");
+                }
+                _ = large_trade_fetcher => {
+                    warn!("Large trade fetcher task completed");
+                }
+                _ = large_trade_processor => {
+                    warn!("Large trade processor task completed");
+                }
+                _ = candles_closed_fetcher => {
+                    warn!("Candles closed fetcher task completed");
+                }
+                _ = candles_closed_processor => {
+                    warn!("Candles closed processor task completed");
+                }
             }
         });
 
@@ -63,19 +172,71 @@ impl<A: Adapter> IoProxy<A> {
     }
 }
 
-/// Spawns a task to fetch trades from Redis and send them to the trade sender.
-pub async fn trade_fetcher(redis_subscriber: Arc<RedisSubscriber>, trade_sender: Sender<Trade>) {
+/// Spawns a task to fetch trades from Redis, with the sequence number they were published
+/// with, and send them to the trade sender.
+pub async fn trade_fetcher(
+    redis_subscriber: Arc<RedisSubscriber>,
+    trade_sender: Sender<SequencedTrade>,
+) {
+    let decode = |payload: &str| {
+        decode_sequenced_message(payload).map(|(trade, seq)| SequencedTrade { trade, seq })
+    };
+    channel_fetcher(redis_subscriber, "trade", trade_sender, decode).await
+}
+
+/// Spawns a task to fetch new pools from Redis and send them to the new pool sender.
+pub async fn new_pool_fetcher(
+    redis_subscriber: Arc<RedisSubscriber>,
+    new_pool_sender: Sender<NewPoolEvent>,
+) {
+    channel_fetcher(redis_subscriber, NEW_POOLS_CHANNEL, new_pool_sender, decode_message).await
+}
+
+/// Spawns a task to fetch token graduations from Redis and send them to the graduation sender.
+pub async fn graduation_fetcher(
+    redis_subscriber: Arc<RedisSubscriber>,
+    graduation_sender: Sender<TokenGraduatedEvent>,
+) {
+    channel_fetcher(redis_subscriber, GRADUATIONS_CHANNEL, graduation_sender, decode_message).await
+}
+
+/// Spawns a task to fetch large trades from Redis and send them to the large trade sender.
+pub async fn large_trade_fetcher(
+    redis_subscriber: Arc<RedisSubscriber>,
+    large_trade_sender: Sender<TradeV2>,
+) {
+    channel_fetcher(redis_subscriber, LARGE_TRADES_CHANNEL, large_trade_sender, decode_message)
+        .await
+}
+
+/// Spawns a task to fetch the closed candlestick buckets from Redis and send them to the
+/// candles closed sender.
+pub async fn candles_closed_fetcher(
+    redis_subscriber: Arc<RedisSubscriber>,
+    candles_closed_sender: Sender<CandlesClosedEvent>,
+) {
+    channel_fetcher(redis_subscriber, CANDLES_CLOSED_CHANNEL, candles_closed_sender, decode_message)
+        .await
+}
+
+/// Subscribes to a Redis channel and sends the messages decoded by `decode`, raw or
+/// enveloped, to the sender, resubscribing when the subscription fails.
+async fn channel_fetcher<T>(
+    redis_subscriber: Arc<RedisSubscriber>,
+    channel_name: &str,
+    sender: Sender<T>,
+    decode: fn(&str) -> Result<T>,
+) {
     let mut retry_count = 0;
-    let channel_name = "trade";
     loop {
         match redis_subscriber.subscriber(channel_name).await {
             Ok(mut msg_stream) => {
                 retry_count = 0; // Reset retry count on successful connection
                 while let Some(msg) = msg_stream.next().await {
                     if let Ok(payload) = msg.get_payload::<String>() {
-                        if let Ok(trade) = serde_json::from_str::<Trade>(&payload) {
-                            if trade_sender.send(trade).await.is_err() {
-                                warn!("Failed to send trade, retrying...");
+                        if let Ok(message) = decode(&payload) {
+                            if sender.send(message).await.is_err() {
+                                warn!("Failed to send {} message, retrying...", channel_name);
                                 tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
                             }
                         }
@@ -84,24 +245,306 @@ pub async fn trade_fetcher(redis_subscriber: Arc<RedisSubscriber>, trade_sender:
             }
             Err(e) => {
                 retry_count += 1;
-                warn!("Failed to subscribe to trade channel (attempt {}): {}", retry_count, e);
+                warn!(
+                    "Failed to subscribe to {} channel (attempt {}): {}",
+                    channel_name, retry_count, e
+                );
                 tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
             }
         }
     }
 }
 
-/// Process the task and send the trade to the sender
-pub async fn trade_processor<A: Adapter>(trade_receiver: Receiver<Trade>, io: Arc<SocketIo<A>>) {
+/// Process the task and send the trade with its sequence number to the room of its token,
+/// queueing its pair price and the stats of its token
+pub async fn trade_processor<A: Adapter>(
+    trade_receiver: Receiver<SequencedTrade>,
+    io: Arc<SocketIo<A>>,
+    conflator: Arc<Mutex<PriceConflator>>,
+    stats_conflator: Arc<Mutex<TokenStatsConflator>>,
+) {
     let mut trade_receiver = trade_receiver;
     while let Some(trade) = trade_receiver.recv().await {
-        if let Err(e) = io
-            .to(trade.pubkey.to_string())
-            .emit(ResponseEvent::TradeCreated.to_string(), &trade.clone())
-            .await
-        {
+        conflator.lock().unwrap().push(&trade.trade);
+        stats_conflator.lock().unwrap().push(&trade.trade);
+        let room = trade.trade.pubkey.to_string();
+        if let Err(e) = io.to(room).emit(ResponseEvent::TradeCreated.to_string(), &trade).await {
             warn!("Failed to emit trade to websocket: {}", e);
         }
     }
     warn!("Trade receiver channel closed");
 }
+
+/// Emits the latest price of the traded pairs to their rooms every `PAIR_PRICE_INTERVAL`,
+/// read from the per-pair price of the kv store, falling back to the price of the trade
+pub async fn pair_price_emitter<A: Adapter>(
+    conflator: Arc<Mutex<PriceConflator>>,
+    kv_store: Option<Arc<KvStore>>,
+    io: Arc<SocketIo<A>>,
+) {
+    let mut ticker = tokio::time::interval(PAIR_PRICE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let updates = conflator.lock().unwrap().drain();
+        for mut update in updates {
+            if let Some(kv_store) = &kv_store {
+                match kv_store.get_latest_pair_price(&update.pair).await {
+                    Ok(Some(price)) => update.price = price,
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to get the price of pair {}: {}", update.pair, e),
+                }
+            }
+            if let Err(e) =
+                io.to(pair_room(&update.pair)).emit(ResponseEvent::Price.to_string(), &update).await
+            {
+                warn!("Failed to emit pair price to websocket: {}", e);
+            }
+        }
+    }
+}
+
+/// Emits the latest price, market cap and rolling volume of the traded tokens to their rooms
+/// every `TOKEN_STATS_INTERVAL`, the tokens whose room has no members are forgotten
+pub async fn token_stats_emitter<A: Adapter>(
+    stats_conflator: Arc<Mutex<TokenStatsConflator>>,
+    io: Arc<SocketIo<A>>,
+) {
+    let mut ticker = tokio::time::interval(TOKEN_STATS_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let rooms: HashSet<String> = match io.rooms().await {
+            Ok(rooms) => rooms.into_iter().map(|room| room.to_string()).collect(),
+            Err(e) => {
+                warn!("Failed to get the websocket rooms: {}", e);
+                continue;
+            }
+        };
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        let updates = stats_conflator.lock().unwrap().drain(now, |token| rooms.contains(token));
+        for update in updates {
+            let event = ResponseEvent::TokenStatsLite.to_string();
+            if let Err(e) = io.to(update.token.clone()).emit(event, &update).await {
+                warn!("Failed to emit token stats to websocket: {}", e);
+            }
+        }
+    }
+}
+
+/// Looks up the cached metadata of a token, None if it is absent, the lookup fails or
+/// takes longer than the timeout
+async fn lookup_token_metadata(kv_store: &KvStore, mint: &str) -> Option<PoolTokenMetadata> {
+    match tokio::time::timeout(METADATA_LOOKUP_TIMEOUT, kv_store.get_token(mint)).await {
+        Ok(Ok(token)) => token.map(PoolTokenMetadata::from),
+        Ok(Err(e)) => {
+            warn!("Failed to get metadata of {}: {}", mint, e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Enriches a new pool with the metadata of both its tokens
+pub async fn enrich_new_pool(kv_store: Option<&KvStore>, pool: NewPoolEvent) -> NewPoolListing {
+    let (token_a, token_b) = match kv_store {
+        Some(kv_store) => {
+            tokio::join!(
+                lookup_token_metadata(kv_store, &pool.token_a_mint),
+                lookup_token_metadata(kv_store, &pool.token_b_mint)
+            )
+        }
+        None => (None, None),
+    };
+    NewPoolListing { pool, token_a, token_b }
+}
+
+/// Enrich the new pools and emit them to the global and the dex rooms
+pub async fn new_pool_processor<A: Adapter>(
+    new_pool_receiver: Receiver<NewPoolEvent>,
+    kv_store: Option<Arc<KvStore>>,
+    io: Arc<SocketIo<A>>,
+) {
+    let mut new_pool_receiver = new_pool_receiver;
+    while let Some(pool) = new_pool_receiver.recv().await {
+        let listing = enrich_new_pool(kv_store.as_deref(), pool).await;
+        let rooms = vec![NEW_POOLS_ROOM.to_string(), new_pools_dex_room(listing.pool.dex)];
+        if let Err(e) = io.to(rooms).emit(ResponseEvent::NewPool.to_string(), &listing).await {
+            warn!("Failed to emit new pool to websocket: {}", e);
+        }
+    }
+    warn!("New pool receiver channel closed");
+}
+
+/// Emit the token graduations to the graduations room
+pub async fn graduation_processor<A: Adapter>(
+    graduation_receiver: Receiver<TokenGraduatedEvent>,
+    io: Arc<SocketIo<A>>,
+) {
+    let mut graduation_receiver = graduation_receiver;
+    while let Some(graduation) = graduation_receiver.recv().await {
+        let event = ResponseEvent::TokenGraduated.to_string();
+        if let Err(e) = io.to(GRADUATIONS_ROOM).emit(event, &graduation).await {
+            warn!("Failed to emit token graduation to websocket: {}", e);
+        }
+    }
+    warn!("Graduation receiver channel closed");
+}
+
+/// Emit the large trades to the large trades room
+pub async fn large_trade_processor<A: Adapter>(
+    large_trade_receiver: Receiver<TradeV2>,
+    io: Arc<SocketIo<A>>,
+) {
+    let mut large_trade_receiver = large_trade_receiver;
+    while let Some(trade) = large_trade_receiver.recv().await {
+        let event = ResponseEvent::LargeTrade.to_string();
+        if let Err(e) = io.to(LARGE_TRADES_ROOM).emit(event, &trade).await {
+            warn!("Failed to emit large trade to websocket: {}", e);
+        }
+    }
+    warn!("Large trade receiver channel closed");
+}
+
+/// Emit the closed candlestick buckets to the candles closed room
+pub async fn candles_closed_processor<A: Adapter>(
+    candles_closed_receiver: Receiver<CandlesClosedEvent>,
+    io: Arc<SocketIo<A>>,
+) {
+    let mut candles_closed_receiver = candles_closed_receiver;
+    while let Some(candles_closed) = candles_closed_receiver.recv().await {
+        let event = ResponseEvent::CandlesClosed.to_string();
+        if let Err(e) = io.to(CANDLES_CLOSED_ROOM).emit(event, &candles_closed).await {
+            warn!("Failed to emit candles closed to websocket: {}", e);
+        }
+    }
+    warn!("Candles closed receiver channel closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::on_connect;
+    use engineioxide::Packet;
+    use socketioxide::handler::ConnectHandler;
+    use sonar_auth::{authenticate, WsAuth};
+    use sonar_db::{make_redis_subscriber, Dexes};
+
+    fn make_new_pool_event(pool: &str) -> NewPoolEvent {
+        NewPoolEvent {
+            dex: Dexes::RaydiumAmmV4,
+            token_a_mint: format!("{pool}-mint-a"),
+            token_b_mint: "So11111111111111111111111111111111111111112".to_string(),
+            pool: pool.to_string(),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    fn make_token(mint: &str, symbol: &str, decimals: u8) -> Token {
+        Token { decimals, supply: 0.0, ..sonar_db::test_utils::make_token(mint, symbol, symbol) }
+    }
+
+    /// Publishes the new pool through Redis and returns it as received by the fetcher
+    async fn publish_and_receive(pool: &NewPoolEvent) -> NewPoolEvent {
+        let redis_subscriber =
+            Arc::new(make_redis_subscriber("redis://localhost:6379").await.unwrap());
+        let (sender, mut receiver) = mpsc::channel(16);
+        let fetcher = tokio::spawn(new_pool_fetcher(redis_subscriber.clone(), sender));
+
+        let payload = serde_json::to_string(pool).unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                // the fetcher may not be subscribed yet, publish until it receives the pool
+                redis_subscriber.publish(NEW_POOLS_CHANNEL, &payload).await.unwrap();
+                if let Ok(Some(received)) =
+                    tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await
+                {
+                    if received.pool == pool.pool {
+                        return received;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("New pool not delivered");
+        fetcher.abort();
+        received
+    }
+
+    #[tokio::test]
+    async fn test_new_pool_delivered_with_metadata() {
+        let pool = make_new_pool_event("test-new-pool-enriched");
+        let received = publish_and_receive(&pool).await;
+
+        let kv_store = KvStore::in_memory();
+        let token_a = make_token(&pool.token_a_mint, "NEW", 6);
+        let token_b = make_token(&pool.token_b_mint, "SOL", 9);
+        kv_store.set_token(&token_a.token, &token_a).await.unwrap();
+        kv_store.set_token(&token_b.token, &token_b).await.unwrap();
+
+        let listing = enrich_new_pool(Some(&kv_store), received).await;
+        assert_eq!(listing.pool.pool, pool.pool);
+        assert_eq!(listing.token_a.unwrap().symbol, "NEW");
+        assert_eq!(listing.token_b.unwrap().decimals, 9);
+    }
+
+    #[tokio::test]
+    async fn test_new_pool_delivered_without_metadata() {
+        let pool = make_new_pool_event("test-new-pool-bare");
+        let received = publish_and_receive(&pool).await;
+
+        let listing = enrich_new_pool(Some(&KvStore::in_memory()), received).await;
+        assert!(listing.token_a.is_none());
+        assert!(listing.token_b.is_none());
+
+        let value = serde_json::to_value(&listing).unwrap();
+        assert_eq!(value["pool"], pool.pool);
+        assert_eq!(value["dex"], "raydium_amm_v4");
+        assert!(value.get("token_a").is_none());
+        assert!(value.get("token_b").is_none());
+    }
+
+    /// received_pools returns the pools of the new_pool events a client received, until it
+    /// receives nothing for a while
+    async fn received_pools(rx: &mut mpsc::Receiver<Packet>) -> Vec<String> {
+        let mut pools = vec![];
+        while let Ok(Some(packet)) =
+            tokio::time::timeout(Duration::from_millis(200), rx.recv()).await
+        {
+            // the emits are socket.io EVENT packets, their type 2 followed by the json array
+            let Packet::Message(message) = packet else { continue };
+            let Some(event) = message.strip_prefix('2') else { continue };
+            let event: serde_json::Value = serde_json::from_str(event).unwrap();
+            if event[0] == ResponseEvent::NewPool.to_string() {
+                pools.push(event[1]["pool"].as_str().unwrap().to_string());
+            }
+        }
+        pools
+    }
+
+    #[tokio::test]
+    async fn test_new_pool_rooms() {
+        let (_svc, io) = SocketIo::builder().with_state(WsAuth::default()).build_svc();
+        io.ns("/", on_connect.with(authenticate));
+        let (every_tx, mut every_rx) = io.new_dummy_sock("/", ()).await;
+        let (raydium_tx, mut raydium_rx) = io.new_dummy_sock("/", ()).await;
+        let (_idle_tx, mut idle_rx) = io.new_dummy_sock("/", ()).await;
+        let subscribe =
+            |data: &str| Packet::Message(format!(r#"2["subscribe_new_pools",{data}]"#).into());
+        every_tx.send(subscribe("{}")).await.unwrap();
+        raydium_tx.send(subscribe(r#"{"dexes":["raydium_amm_v4"]}"#)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let (sender, receiver) = mpsc::channel(16);
+        let processor = tokio::spawn(new_pool_processor(receiver, None, Arc::new(io)));
+        let raydium = make_new_pool_event("test-new-pool-raydium");
+        let pump =
+            NewPoolEvent { dex: Dexes::PumpAmm, ..make_new_pool_event("test-new-pool-pump") };
+        sender.send(raydium.clone()).await.unwrap();
+        sender.send(pump.clone()).await.unwrap();
+
+        // the dex rooms only receive the pools of their dex
+        assert_eq!(received_pools(&mut every_rx).await, vec![raydium.pool.clone(), pump.pool]);
+        assert_eq!(received_pools(&mut raydium_rx).await, vec![raydium.pool]);
+        assert!(received_pools(&mut idle_rx).await.is_empty());
+        processor.abort();
+    }
+}
@@ -0,0 +1,18 @@
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Extension, SocketRef, State},
+};
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+
+/// The room receiving the trades moving a large share of the token supply
+pub const LARGE_TRADES_ROOM: &str = "large_trades";
+
+pub async fn on_subscribe_large_trades<A: Adapter>(
+    socket: SocketRef<A>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    let rooms = authorize_rooms(&socket, &claims, &auth, vec![LARGE_TRADES_ROOM.to_string()], ack);
+    socket.join(rooms);
+}
@@ -1,9 +1,16 @@
 pub mod adapter;
+pub mod candles;
 pub mod connect;
 pub mod event;
+pub mod graduation;
 pub mod io;
+pub mod large_trade;
+pub mod new_pool;
+pub mod pair;
+pub mod resume;
 pub mod token;
+pub mod token_stats;
 
-pub use adapter::init_adapter;
+pub use adapter::{init_adapter, make_redis_adapter, WsAdapter};
 pub use connect::on_connect;
 pub use io::IoProxy;
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Data, Extension, SocketRef, State},
+};
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+use sonar_db::Dexes;
+
+/// The room receiving every new pool
+pub const NEW_POOLS_ROOM: &str = "new_pools";
+
+/// new_pools_dex_room returns the room receiving the new pools of a dex
+pub fn new_pools_dex_room(dex: Dexes) -> String {
+    format!("{NEW_POOLS_ROOM}:{dex}")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SubscribeNewPools {
+    /// Only receive the pools of these dexes, every dex if empty
+    #[serde(default)]
+    dexes: Vec<Dexes>,
+}
+
+impl SubscribeNewPools {
+    /// rooms returns the rooms to join for the dex filter
+    pub fn rooms(&self) -> Vec<String> {
+        if self.dexes.is_empty() {
+            vec![NEW_POOLS_ROOM.to_string()]
+        } else {
+            self.dexes.iter().map(|dex| new_pools_dex_room(*dex)).collect()
+        }
+    }
+}
+
+pub async fn on_subscribe_new_pools<A: Adapter>(
+    socket: SocketRef<A>,
+    Data(req): Data<Option<SubscribeNewPools>>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    let rooms = req.unwrap_or_default().rooms();
+    let rooms = authorize_rooms(&socket, &claims, &auth, rooms, ack);
+    socket.join(rooms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe_new_pools_rooms() {
+        assert_eq!(SubscribeNewPools::default().rooms(), vec!["new_pools"]);
+
+        let req: SubscribeNewPools =
+            serde_json::from_str(r#"{"dexes": ["raydium_amm_v4", "pump_amm"]}"#).unwrap();
+        assert_eq!(req.rooms(), vec!["new_pools:raydium_amm_v4", "new_pools:pump_amm"]);
+        // the unknown dexes are rejected instead of joining a room no pool is emitted to
+        assert!(
+            serde_json::from_str::<SubscribeNewPools>(r#"{"dexes": ["RaydiumAmmV4"]}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Data, Extension, SocketRef, State},
+};
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+use sonar_db::{PairPrice, Trade};
+use std::{collections::HashMap, time::Duration};
+
+/// How often the latest price of a pair is emitted at most
+pub const PAIR_PRICE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// pair_room returns the room receiving the prices of a pair
+pub fn pair_room(pair: &str) -> String {
+    format!("pair:{pair}")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SubscribePairPrice {
+    pairs: Vec<String>,
+}
+
+impl SubscribePairPrice {
+    /// rooms returns the rooms to join for the pairs
+    pub fn rooms(&self) -> Vec<String> {
+        self.pairs.iter().map(|pair| pair_room(pair)).collect()
+    }
+}
+
+pub async fn on_subscribe_pair_price<A: Adapter>(
+    socket: SocketRef<A>,
+    Data(req): Data<SubscribePairPrice>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    let rooms = authorize_rooms(&socket, &claims, &auth, req.rooms(), ack);
+    socket.join(rooms);
+}
+
+/// The latest price of a pair, as emitted to its room
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PairPriceUpdate {
+    pub pair: String,
+    #[serde(flatten)]
+    pub price: PairPrice,
+}
+
+/// Keeps the latest price of each pair traded since the last drain, so that a pair
+/// trading many times in an interval only emits its latest price
+#[derive(Debug, Default)]
+pub struct PriceConflator {
+    pending: HashMap<String, PairPrice>,
+}
+
+impl PriceConflator {
+    /// push replaces the pending price of the pair of the trade, unless it is older
+    pub fn push(&mut self, trade: &Trade) {
+        let price = PairPrice {
+            price: trade.price,
+            timestamp: trade.timestamp,
+            signature: trade.signature.clone(),
+            turnover: 0.0,
+        };
+        match self.pending.get(&trade.pair) {
+            Some(pending) if pending.timestamp > price.timestamp => {}
+            _ => {
+                self.pending.insert(trade.pair.clone(), price);
+            }
+        }
+    }
+
+    /// drain returns the pending prices, at most one per pair
+    pub fn drain(&mut self) -> Vec<PairPriceUpdate> {
+        self.pending.drain().map(|(pair, price)| PairPriceUpdate { pair, price }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::test_utils::make_swap_event;
+
+    fn make_trade(pair: &str, price: f64, timestamp: u64) -> Trade {
+        make_swap_event("token", pair, &format!("{pair}-{timestamp}"), timestamp, price).into()
+    }
+
+    #[test]
+    fn test_subscribe_pair_price_rooms() {
+        let req: SubscribePairPrice = serde_json::from_str(r#"{"pairs": ["a", "b"]}"#).unwrap();
+        assert_eq!(req.rooms(), vec!["pair:a", "pair:b"]);
+    }
+
+    #[test]
+    fn test_price_conflation() {
+        let mut conflator = PriceConflator::default();
+        conflator.push(&make_trade("a", 1.0, 10));
+        conflator.push(&make_trade("a", 1.2, 12));
+        // a late trade doesn't override a newer price
+        conflator.push(&make_trade("a", 0.9, 11));
+        conflator.push(&make_trade("b", 2.0, 10));
+
+        let mut updates = conflator.drain();
+        updates.sort_by(|a, b| a.pair.cmp(&b.pair));
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].pair, "a");
+        assert_eq!(updates[0].price.price, 1.2);
+        assert_eq!(updates[0].price.signature, "a-12");
+        assert_eq!(updates[1].pair, "b");
+        assert_eq!(updates[1].price.price, 2.0);
+
+        // nothing is emitted until the pairs trade again
+        assert!(conflator.drain().is_empty());
+        conflator.push(&make_trade("b", 2.1, 13));
+        assert_eq!(conflator.drain().len(), 1);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{state::AppState, ws::event::ResponseEvent};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Data, Extension, SocketRef, State},
+};
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+use sonar_db::{Database, KvStore, Trade, TradeFilter, RECENT_TRADES_MAX, RECENT_TRADES_TTL_SECS};
+use tracing::warn;
+
+/// The most trades replayed to a resuming client, as many as the queue keeps
+pub const REPLAY_MAX_TRADES: usize = RECENT_TRADES_MAX;
+/// How far back the trades replayed to a resuming client go
+pub const REPLAY_WINDOW_SECS: u64 = RECENT_TRADES_TTL_SECS;
+
+/// A trade as emitted to its room, with the sequence number the message queue published it
+/// with in the trades of its token, None when it was published without one or read from the db
+#[derive(Debug, Clone, Serialize)]
+pub struct SequencedTrade {
+    #[serde(flatten)]
+    pub trade: Trade,
+    pub seq: Option<u64>,
+}
+
+/// The trades of a room a resuming client missed, oldest first
+#[derive(Debug, Clone, Serialize)]
+pub struct TradeReplay {
+    pub room: String,
+    /// The latest sequence number published to the room
+    pub seq: u64,
+    pub trades: Vec<SequencedTrade>,
+    /// true when the queue no longer kept every missed trade, they were older than the replay
+    /// window, more than `REPLAY_MAX_TRADES` or the sequence started again. The trades before
+    /// the kept ones are then read from the db without a sequence number, the client tells
+    /// the ones it already received by their signature
+    pub gap: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResumeRequest {
+    pub room: String,
+    /// The sequence number of the last trade the client received
+    pub last_seq: u64,
+}
+
+/// replay selects the trades of the room a client missed since `last_seq` out of the recent
+/// trades the message queue kept, `seq` being the latest sequence number it published
+pub fn replay(
+    room: &str,
+    last_seq: u64,
+    seq: u64,
+    recent: Vec<(u64, Trade)>,
+    now: u64,
+) -> TradeReplay {
+    // an expired sequence starts again above the numbers it took, it only falls below
+    // `last_seq` when Redis lost it, the client can't tell which of the new trades it received
+    let restarted = seq < last_seq;
+    let after = if restarted { 0 } else { last_seq };
+    let from = now.saturating_sub(REPLAY_WINDOW_SECS);
+    let mut trades: Vec<SequencedTrade> = recent
+        .into_iter()
+        .filter(|(trade_seq, trade)| *trade_seq > after && trade.timestamp >= from)
+        .map(|(trade_seq, trade)| SequencedTrade { trade, seq: Some(trade_seq) })
+        .collect();
+    trades.sort_by_key(|sequenced| sequenced.seq);
+    trades.drain(..trades.len().saturating_sub(REPLAY_MAX_TRADES));
+
+    let missed = seq.saturating_sub(after) as usize;
+    let gap = restarted || missed > trades.len();
+    TradeReplay { room: room.to_string(), seq, trades, gap }
+}
+
+/// fill_gap adds the stored trades older than the replayed ones in front of them, keeping the
+/// newest `REPLAY_MAX_TRADES`
+fn fill_gap(replay: &mut TradeReplay, stored: Vec<Trade>) {
+    let oldest = replay.trades.first().map(|sequenced| sequenced.trade.cursor());
+    let mut trades: Vec<SequencedTrade> = stored
+        .into_iter()
+        .filter(|trade| oldest.as_ref().is_none_or(|oldest| &trade.cursor() < oldest))
+        .map(|trade| SequencedTrade { trade, seq: None })
+        .collect();
+    trades.sort_by_key(|sequenced| sequenced.trade.cursor());
+    trades.append(&mut replay.trades);
+    trades.drain(..trades.len().saturating_sub(REPLAY_MAX_TRADES));
+    replay.trades = trades;
+}
+
+/// resume_trades reads the recent trades of the room the message queue kept and replays
+/// the ones the client missed since `last_seq`, completed with the trades of the db when
+/// the queue no longer kept them all
+pub async fn resume_trades(
+    kv_store: &KvStore,
+    db: &Database,
+    room: &str,
+    last_seq: u64,
+    now: u64,
+) -> Result<TradeReplay> {
+    let (seq, recent) = kv_store.get_recent_trades(room).await?;
+    let mut replay = replay(room, last_seq, seq, recent, now);
+    if replay.gap {
+        let stored = db
+            .get_trades(&TradeFilter {
+                token: Some(room.to_string()),
+                time_from: Some(now.saturating_sub(REPLAY_WINDOW_SECS)),
+                limit: Some(REPLAY_MAX_TRADES),
+                ..Default::default()
+            })
+            .await?;
+        fill_gap(&mut replay, stored);
+    }
+    Ok(replay)
+}
+
+/// Joins the room again and replays the trades the client missed while disconnected,
+/// the live trades emitted meanwhile are told apart by their sequence number
+pub async fn on_resume<A: Adapter>(
+    socket: SocketRef<A>,
+    Data(req): Data<ResumeRequest>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    for room in authorize_rooms(&socket, &claims, &auth, vec![req.room], ack) {
+        socket.join(room.clone());
+        match resume_trades(&state.kv_store, &state.db, &room, req.last_seq, now).await {
+            Ok(replay) => {
+                if let Err(e) = socket.emit(ResponseEvent::TradeReplay.to_string(), &replay) {
+                    warn!("Failed to emit trade replay to websocket: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to replay the trades of {}: {}", room, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::{test_utils::make_swap_event, DatabaseTrait, MemoryDb};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn make_trade(signature: &str, timestamp: u64) -> Trade {
+        make_swap_event("token", "pair", signature, timestamp, 1.0).into()
+    }
+
+    fn empty_db() -> Database {
+        Box::new(MemoryDb::default())
+    }
+
+    #[tokio::test]
+    async fn test_sequence_is_shared() {
+        let (kv_store, db) = (KvStore::in_memory(), empty_db());
+        let first = kv_store.push_recent_trade(&make_trade("1", NOW)).unwrap();
+        assert_eq!(kv_store.push_recent_trade(&make_trade("2", NOW)).unwrap(), first + 1);
+        let other = Trade { pubkey: "other".to_string(), ..make_trade("3", NOW) };
+        assert!(kv_store.push_recent_trade(&other).unwrap() > 0);
+
+        // every instance reads the numbers the queue published the trades with
+        let replay = resume_trades(&kv_store, &db, "token", first - 1, NOW).await.unwrap();
+        let seqs: Vec<Option<u64>> = replay.trades.iter().map(|t| t.seq).collect();
+        assert_eq!(seqs, vec![Some(first), Some(first + 1)]);
+        assert_eq!((replay.seq, replay.gap), (first + 1, false));
+        let replay = resume_trades(&kv_store, &db, "token", first, NOW).await.unwrap();
+        assert_eq!(replay.trades[0].trade.signature, "2");
+    }
+
+    #[tokio::test]
+    async fn test_gap_is_filled_from_the_db() {
+        let memory_db = MemoryDb::default();
+        for (signature, timestamp) in [("a", NOW - 10), ("b", NOW - 5), ("old", NOW - 1000)] {
+            let event = make_swap_event("token", "pair", signature, timestamp, 1.0);
+            memory_db.insert_swap_event(&event).await.unwrap();
+        }
+        let db: Database = Box::new(memory_db);
+        let kv_store = KvStore::in_memory();
+        let first = kv_store.push_recent_trade(&make_trade("c", NOW - 1)).unwrap();
+
+        // the client saw trades of an older sequence, the queue only kept "c"
+        let replay = resume_trades(&kv_store, &db, "token", 0, NOW).await.unwrap();
+        let trades: Vec<(&str, Option<u64>)> =
+            replay.trades.iter().map(|t| (t.trade.signature.as_str(), t.seq)).collect();
+        assert_eq!(trades, vec![("a", None), ("b", None), ("c", Some(first))]);
+        assert!(replay.gap);
+
+        // no gap, the db is not read
+        let replay = resume_trades(&kv_store, &db, "token", first - 1, NOW).await.unwrap();
+        assert_eq!(replay.trades.len(), 1);
+        assert!(!replay.gap);
+    }
+
+    #[test]
+    fn test_replay() {
+        let recent: Vec<(u64, Trade)> =
+            (0..5).map(|i| (i + 1, make_trade(&i.to_string(), NOW + i))).collect();
+
+        let missed = replay("token", 3, 5, recent.clone(), NOW + 5);
+        let seqs: Vec<Option<u64>> = missed.trades.iter().map(|t| t.seq).collect();
+        assert_eq!(seqs, vec![Some(4), Some(5)]);
+        assert_eq!(missed.trades[0].trade.signature, "3");
+        assert_eq!((missed.seq, missed.gap), (5, false));
+
+        // up to date
+        let up_to_date = replay("token", 5, 5, recent.clone(), NOW + 5);
+        assert!(up_to_date.trades.is_empty() && !up_to_date.gap);
+
+        // a trade no longer kept is a gap
+        let trimmed = replay("token", 0, 5, recent[2..].to_vec(), NOW + 5);
+        assert_eq!(trimmed.trades.len(), 3);
+        assert!(trimmed.gap);
+
+        // the sequence restarted below the last one received
+        let restarted = replay("token", 40, 5, recent, NOW + 5);
+        assert_eq!(restarted.trades.len(), 5);
+        assert!(restarted.gap);
+    }
+
+    #[tokio::test]
+    async fn test_replay_bounds() {
+        let (kv_store, db) = (KvStore::in_memory(), empty_db());
+        let mut base = 0;
+        for i in 0..REPLAY_MAX_TRADES as u64 + 100 {
+            let seq =
+                kv_store.push_recent_trade(&make_trade(&format!("{i:04}"), NOW + i / 10)).unwrap();
+            base = seq - i - 1;
+        }
+        let now = NOW + (REPLAY_MAX_TRADES as u64 + 99) / 10;
+
+        // the backlog exceeds the bound, the newest trades are replayed
+        let replay = resume_trades(&kv_store, &db, "token", base, now).await.unwrap();
+        assert_eq!(replay.trades.len(), REPLAY_MAX_TRADES);
+        assert_eq!(replay.trades.last().unwrap().trade.signature, "0599");
+        assert_eq!(replay.trades.last().unwrap().seq, Some(base + 600));
+        assert!(replay.gap);
+
+        // within the bound
+        let replay = resume_trades(&kv_store, &db, "token", base + 590, now).await.unwrap();
+        assert_eq!(replay.trades.len(), 10);
+        assert_eq!(replay.trades[0].seq, Some(base + 591));
+        assert!(!replay.gap);
+
+        // the trades older than the window are not replayed
+        let later = now + REPLAY_WINDOW_SECS + 1;
+        let replay = resume_trades(&kv_store, &db, "token", base + 590, later).await.unwrap();
+        assert!(replay.trades.is_empty());
+        assert!(replay.gap);
+    }
+}
@@ -1,15 +1,247 @@
+use crate::{state::AppState, ws::event::ResponseEvent};
 use serde::{Deserialize, Serialize};
 use socketioxide::{
     adapter::Adapter,
-    extract::{Data, SocketRef},
+    extract::{AckSender, Data, Extension, SocketRef, State},
+    socket::Socket,
 };
+use sonar_auth::{authorize_rooms, ClientClaims, WsAuth};
+use sonar_db::{Database, KvStore, Trade, TradeCursor, TradeFilter};
+use std::collections::HashSet;
+use tracing::warn;
+
+/// The most trades a snapshot holds
+pub const MAX_SNAPSHOT_TRADES: usize = 100;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenTrade {
     tokens: Vec<String>,
+    /// The number of recent trades of each token to receive before the live trades
+    #[serde(default)]
+    snapshot: Option<usize>,
+}
+
+/// The recent trades of a token, oldest first, emitted once its room is joined, so the live
+/// trades received around it may also be in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeSnapshot {
+    pub token: String,
+    pub trades: Vec<Trade>,
+    /// The `(timestamp, signature, pair)` of the newest trade, None when the token has no trades
+    pub cursor: Option<TradeCursor>,
+}
+
+impl TradeSnapshot {
+    /// new keeps the newest `limit` of the trades, each signature once, ordered oldest first
+    pub fn new(token: String, mut trades: Vec<Trade>, limit: usize) -> Self {
+        trades.sort_by_key(Trade::cursor);
+        let mut signatures = HashSet::new();
+        trades.retain(|trade| signatures.insert(trade.signature.clone()));
+        trades.drain(..trades.len().saturating_sub(limit));
+        let cursor = trades.last().map(Trade::cursor);
+        Self { token, trades, cursor }
+    }
+
+    /// contains returns true if the snapshot holds a trade of the same signature
+    pub fn contains(&self, trade: &Trade) -> bool {
+        self.trades.iter().any(|snapshot_trade| snapshot_trade.signature == trade.signature)
+    }
+}
+
+/// The socket side of a trade subscription
+pub trait TradeSubscriber {
+    fn emit_snapshot(&self, snapshot: &TradeSnapshot);
+    fn join(&self, rooms: Vec<String>);
 }
 
-pub async fn on_token_trade<A: Adapter>(socket: SocketRef<A>, Data(req): Data<TokenTrade>) {
-    let rooms: Vec<String> = req.tokens.clone();
-    socket.join(rooms);
+impl<A: Adapter> TradeSubscriber for SocketRef<A> {
+    fn emit_snapshot(&self, snapshot: &TradeSnapshot) {
+        if let Err(e) = self.emit(ResponseEvent::TradeSnapshot.to_string(), snapshot) {
+            warn!("Failed to emit trade snapshot to websocket: {}", e);
+        }
+    }
+
+    fn join(&self, rooms: Vec<String>) {
+        Socket::join(self, rooms);
+    }
+}
+
+/// subscribe_token_trades joins the room of each token, then emits its requested snapshot,
+/// so that no trade falls between the two. The snapshot adds the trades recently published
+/// to the room to the ones of the db, the buffered inserts may not have reached it yet
+pub async fn subscribe_token_trades(
+    subscriber: &impl TradeSubscriber,
+    db: &Database,
+    kv_store: &KvStore,
+    req: TokenTrade,
+) {
+    subscriber.join(req.tokens.clone());
+    let Some(limit) = req.snapshot.map(|n| n.min(MAX_SNAPSHOT_TRADES)).filter(|n| *n > 0) else {
+        return;
+    };
+    for token in req.tokens {
+        match db
+            .get_trades(&TradeFilter {
+                token: Some(token.to_string()),
+                limit: Some(limit),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(mut trades) => {
+                match kv_store.get_recent_trades(&token).await {
+                    Ok((_, recent)) => trades.extend(recent.into_iter().map(|(_, trade)| trade)),
+                    Err(e) => warn!("Failed to get the recent trades of {}: {}", token, e),
+                }
+                subscriber.emit_snapshot(&TradeSnapshot::new(token, trades, limit));
+            }
+            Err(e) => warn!("Failed to get the trade snapshot of {}: {}", token, e),
+        }
+    }
+}
+
+pub async fn on_token_trade<A: Adapter>(
+    socket: SocketRef<A>,
+    Data(mut req): Data<TokenTrade>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<ClientClaims>,
+    State(auth): State<WsAuth>,
+    ack: AckSender<A>,
+) {
+    req.tokens = authorize_rooms(&socket, &claims, &auth, req.tokens, ack);
+    subscribe_token_trades(&socket, &state.db, &state.kv_store, req).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::{test_utils::make_swap_event, DatabaseTrait, MemoryDb, SwapEvent};
+    use std::sync::Mutex;
+
+    #[derive(Debug, PartialEq)]
+    enum Received {
+        Joined(Vec<String>),
+        Snapshot(Vec<String>),
+        Live(String),
+    }
+
+    /// Records what a socket receives, live trades only reach the joined rooms
+    #[derive(Default)]
+    struct Recorder {
+        rooms: Mutex<Vec<String>>,
+        received: Mutex<Vec<Received>>,
+        cursors: Mutex<Vec<Option<TradeCursor>>>,
+    }
+
+    impl Recorder {
+        fn publish(&self, trade: &Trade) {
+            if self.rooms.lock().unwrap().contains(&trade.pubkey) {
+                self.received.lock().unwrap().push(Received::Live(trade.signature.clone()));
+            }
+        }
+    }
+
+    impl TradeSubscriber for Recorder {
+        fn emit_snapshot(&self, snapshot: &TradeSnapshot) {
+            let signatures = snapshot.trades.iter().map(|t| t.signature.clone()).collect();
+            self.received.lock().unwrap().push(Received::Snapshot(signatures));
+            self.cursors.lock().unwrap().push(snapshot.cursor.clone());
+        }
+
+        fn join(&self, rooms: Vec<String>) {
+            self.received.lock().unwrap().push(Received::Joined(rooms.clone()));
+            self.rooms.lock().unwrap().extend(rooms);
+        }
+    }
+
+    fn swap_event(signature: &str, timestamp: u64) -> SwapEvent {
+        make_swap_event("token", "pair", signature, timestamp, 1.0)
+    }
+
+    #[tokio::test]
+    async fn test_room_is_joined_before_the_snapshot() {
+        let memory_db = MemoryDb::default();
+        for (signature, timestamp) in [("a", 10), ("c", 20), ("b", 20), ("d", 5)] {
+            memory_db.insert_swap_event(&swap_event(signature, timestamp)).await.unwrap();
+        }
+        let db: Database = Box::new(memory_db);
+        // "e" was published to the room but its insert is still buffered
+        let kv_store = KvStore::in_memory();
+        for event in [swap_event("c", 20), swap_event("e", 30)] {
+            kv_store.push_recent_trade(&event.into()).unwrap();
+        }
+        let recorder = Recorder::default();
+
+        // published before the subscription, not received
+        recorder.publish(&swap_event("d", 5).into());
+        let req: TokenTrade =
+            serde_json::from_str(r#"{"tokens": ["token"], "snapshot": 3}"#).unwrap();
+        subscribe_token_trades(&recorder, &db, &kv_store, req).await;
+        recorder.publish(&swap_event("f", 40).into());
+
+        assert_eq!(
+            *recorder.received.lock().unwrap(),
+            vec![
+                Received::Joined(vec!["token".to_string()]),
+                Received::Snapshot(vec!["b".to_string(), "c".to_string(), "e".to_string()]),
+                Received::Live("f".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_dedups_by_signature() {
+        // "c" is both in the db and in the recently emitted trades
+        let trades: Vec<Trade> = [("b", 20), ("a", 10), ("c", 20), ("c", 20)]
+            .map(|(s, t)| swap_event(s, t).into())
+            .into();
+        let snapshot = TradeSnapshot::new("token".to_string(), trades.clone(), 10);
+        let signatures: Vec<&str> = snapshot.trades.iter().map(|t| t.signature.as_str()).collect();
+        assert_eq!(signatures, vec!["a", "b", "c"]);
+        assert_eq!(snapshot.cursor, Some((20, "c".to_string(), "pair".to_string())));
+
+        // a live trade received around the snapshot is told apart by its signature
+        assert!(snapshot.contains(&swap_event("c", 20).into()));
+        assert!(!snapshot.contains(&swap_event("d", 20).into()));
+
+        let snapshot = TradeSnapshot::new("token".to_string(), trades, 2);
+        let signatures: Vec<&str> = snapshot.trades.iter().map(|t| t.signature.as_str()).collect();
+        assert_eq!(signatures, vec!["b", "c"]);
+
+        let empty = TradeSnapshot::new("token".to_string(), vec![], 10);
+        assert_eq!(empty.cursor, None);
+        assert!(!empty.contains(&swap_event("a", 10).into()));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_is_capped_and_optional() {
+        let memory_db = MemoryDb::default();
+        for timestamp in 0..(MAX_SNAPSHOT_TRADES as u64 + 10) {
+            let event = swap_event(&format!("sig-{timestamp:03}"), timestamp);
+            memory_db.insert_swap_event(&event).await.unwrap();
+        }
+        let db: Database = Box::new(memory_db);
+
+        let kv_store = KvStore::in_memory();
+        let recorder = Recorder::default();
+        let req: TokenTrade =
+            serde_json::from_str(r#"{"tokens": ["token"], "snapshot": 1000}"#).unwrap();
+        subscribe_token_trades(&recorder, &db, &kv_store, req).await;
+        match &recorder.received.lock().unwrap()[1] {
+            Received::Snapshot(signatures) => assert_eq!(signatures.len(), MAX_SNAPSHOT_TRADES),
+            received => panic!("unexpected {received:?}"),
+        }
+        let cursor = recorder.cursors.lock().unwrap()[0].clone().unwrap();
+        assert_eq!(cursor.0, MAX_SNAPSHOT_TRADES as u64 + 9);
+
+        // without a snapshot the room is only joined
+        let recorder = Recorder::default();
+        let req: TokenTrade = serde_json::from_str(r#"{"tokens": ["token"]}"#).unwrap();
+        subscribe_token_trades(&recorder, &db, &kv_store, req).await;
+        assert_eq!(
+            *recorder.received.lock().unwrap(),
+            vec![Received::Joined(vec!["token".to_string()])]
+        );
+        assert_eq!(*recorder.rooms.lock().unwrap(), vec!["token".to_string()]);
+    }
 }
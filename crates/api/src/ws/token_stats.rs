@@ -0,0 +1,190 @@
+use serde::Serialize;
+use sonar_db::Trade;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// How often the stats of a token room are emitted at most
+pub const TOKEN_STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// The window of the rolling volume, in seconds
+pub const TOKEN_STATS_WINDOW_SECS: u64 = 5 * 60;
+/// The most volume entries kept per token, the trades of a second share one entry so only
+/// trades arriving out of order can reach it
+pub const MAX_TOKEN_STATS_ENTRIES: usize = TOKEN_STATS_WINDOW_SECS as usize;
+
+/// The latest price and market cap of a token and its volume over the last
+/// `TOKEN_STATS_WINDOW_SECS`, as emitted to its room
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenStatsLite {
+    pub token: String,
+    pub price: f64,
+    pub market_cap: f64,
+    /// The USD volume of the trades of the window
+    pub volume_5m: f64,
+    /// The timestamp of the latest trade
+    pub timestamp: u64,
+}
+
+/// The trades of a token within the window, the `(timestamp, swap_amount)` of each second
+#[derive(Debug, Default)]
+struct TokenWindow {
+    price: f64,
+    market_cap: f64,
+    timestamp: u64,
+    volumes: VecDeque<(u64, f64)>,
+    /// Whether the token traded since the last emission
+    pending: bool,
+}
+
+impl TokenWindow {
+    fn push(&mut self, timestamp: u64, swap_amount: f64) {
+        match self.volumes.back_mut() {
+            Some((last, volume)) if *last == timestamp => *volume += swap_amount,
+            _ => self.volumes.push_back((timestamp, swap_amount)),
+        }
+        if self.volumes.len() > MAX_TOKEN_STATS_ENTRIES {
+            self.volumes.pop_front();
+        }
+    }
+
+    /// prune drops the volumes older than the window ending at `now`
+    fn prune(&mut self, now: u64) {
+        let start = now.saturating_sub(TOKEN_STATS_WINDOW_SECS);
+        self.volumes.retain(|(timestamp, _)| *timestamp > start);
+    }
+
+    fn volume(&self) -> f64 {
+        self.volumes.iter().map(|(_, volume)| volume).sum()
+    }
+}
+
+/// Derives the stats of the token rooms from the live trades, so that a token trading many
+/// times in an interval only emits its latest stats
+#[derive(Debug, Default)]
+pub struct TokenStatsConflator {
+    tokens: HashMap<String, TokenWindow>,
+}
+
+impl TokenStatsConflator {
+    /// push adds the volume of the trade to the window of its token, the price and the
+    /// market cap are replaced unless the trade is older
+    pub fn push(&mut self, trade: &Trade) {
+        let window = self.tokens.entry(trade.pubkey.clone()).or_default();
+        if trade.timestamp >= window.timestamp {
+            window.price = trade.price;
+            window.market_cap = trade.market_cap;
+            window.timestamp = trade.timestamp;
+        }
+        window.push(trade.timestamp, trade.swap_amount);
+        window.prune(window.timestamp);
+        window.pending = true;
+    }
+
+    /// drain returns the stats of the tokens traded since the last drain whose room has
+    /// members, the tokens without members are forgotten
+    pub fn drain(&mut self, now: u64, has_members: impl Fn(&str) -> bool) -> Vec<TokenStatsLite> {
+        self.tokens.retain(|token, _| has_members(token));
+        self.tokens
+            .iter_mut()
+            .filter(|(_, window)| window.pending)
+            .map(|(token, window)| {
+                window.prune(now.max(window.timestamp));
+                window.pending = false;
+                TokenStatsLite {
+                    token: token.clone(),
+                    price: window.price,
+                    market_cap: window.market_cap,
+                    volume_5m: window.volume(),
+                    timestamp: window.timestamp,
+                }
+            })
+            .collect()
+    }
+
+    /// len returns the number of tracked tokens
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::test_utils::make_swap_event;
+
+    fn make_trade(token: &str, price: f64, swap_amount: f64, timestamp: u64) -> Trade {
+        let pair = format!("{token}-pair");
+        let event =
+            make_swap_event(token, &pair, &format!("{token}-{timestamp}"), timestamp, price);
+        Trade { market_cap: price * 1_000.0, swap_amount, ..event.into() }
+    }
+
+    #[test]
+    fn test_rolling_volume_pruning() {
+        let mut window = TokenWindow::default();
+        window.push(100, 1.0);
+        window.push(100, 2.0);
+        window.push(250, 4.0);
+        // the trades of a second share an entry
+        assert_eq!(window.volumes.len(), 2);
+        assert_eq!(window.volume(), 7.0);
+
+        // the window ending at 400 starts after 100
+        window.prune(400);
+        assert_eq!(window.volume(), 4.0);
+        window.prune(550);
+        assert!(window.volumes.is_empty());
+
+        // the entries are capped whatever the order of the trades
+        for i in 0..2 * MAX_TOKEN_STATS_ENTRIES as u64 {
+            window.push(1_000 + (i % 2) * i, 1.0);
+        }
+        assert_eq!(window.volumes.len(), MAX_TOKEN_STATS_ENTRIES);
+    }
+
+    #[test]
+    fn test_token_stats_conflation() {
+        let mut conflator = TokenStatsConflator::default();
+        conflator.push(&make_trade("a", 1.0, 10.0, 1_000));
+        conflator.push(&make_trade("a", 1.2, 20.0, 1_002));
+        // a late trade counts in the volume without overriding the newer price
+        conflator.push(&make_trade("a", 0.9, 5.0, 1_001));
+        conflator.push(&make_trade("b", 2.0, 1.0, 1_000));
+
+        let mut stats = conflator.drain(1_002, |_| true);
+        stats.sort_by(|a, b| a.token.cmp(&b.token));
+        assert_eq!(
+            stats[0],
+            TokenStatsLite {
+                token: "a".to_string(),
+                price: 1.2,
+                market_cap: 1_200.0,
+                volume_5m: 35.0,
+                timestamp: 1_002,
+            }
+        );
+        assert_eq!(stats[1].volume_5m, 1.0);
+
+        // nothing is emitted until the tokens trade again, once per drain whatever the trades
+        assert!(conflator.drain(1_003, |_| true).is_empty());
+        conflator.push(&make_trade("a", 1.3, 1.0, 1_003));
+        conflator.push(&make_trade("a", 1.4, 1.0, 1_003));
+        let stats = conflator.drain(1_004, |_| true);
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].price, stats[0].volume_5m), (1.4, 37.0));
+
+        // the volume leaves the window as time passes
+        conflator.push(&make_trade("a", 1.5, 3.0, 1_350));
+        assert_eq!(conflator.drain(1_350, |_| true)[0].volume_5m, 3.0);
+
+        // the tokens whose room has no members are dropped
+        conflator.push(&make_trade("b", 2.1, 1.0, 1_350));
+        assert!(conflator.drain(1_351, |token| token == "a").is_empty());
+        assert_eq!(conflator.len(), 1);
+    }
+}
@@ -0,0 +1,904 @@
+//! The handlers behind the API router, backed by the in-memory database and kv store
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, Request, StatusCode},
+};
+use futures::StreamExt;
+use serde_json::Value;
+use sonar_api::{build_router, AdminAuth, AppState, ExportAuth, RequestTimeouts};
+use sonar_db::{
+    test_utils::{make_swap_event, make_token, seeded_db, seeded_storages},
+    Database, DatabaseTrait, Dexes, KvStore, MemoryDb, Pair, ReferencePrice, SwapEvent,
+    SOL_USD_PAIR,
+};
+use sonar_token_metadata::{BackgroundResolver, ResolvedToken, TokenResolver};
+use std::{sync::Arc, time::Duration};
+use tokio::io::AsyncWriteExt;
+use tower::ServiceExt;
+
+const TOKEN: &str = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+const OTHER_TOKEN: &str = "EKpQGSJtjMFqKZ9KQanSqYXRcF8fBopzLHYxdM65zcjm";
+const PAIR: &str = "Hs97TCZeuYiJxooo3U73qEHXg3dKpRL4uYKYRryEK9CF";
+const OTHER_PAIR: &str = "2QdhepnKRTLjjSqPL1PtKNwqrUkoLee5Gqs8bvZhRdMv";
+const SOL: &str = "So11111111111111111111111111111111111111112";
+const SOL_PAIR: &str = "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2";
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// swap_events returns two minutes of trades of `TOKEN` and one trade of `OTHER_TOKEN`,
+/// ending a minute before `end`
+fn swap_events(end: u64) -> Vec<SwapEvent> {
+    let start = end / 60 * 60 - 180;
+    vec![
+        make_swap_event(TOKEN, PAIR, "first", start, 1.0),
+        make_swap_event(TOKEN, PAIR, "second", start + 30, 2.0),
+        make_swap_event(TOKEN, PAIR, "third", start + 60, 1.5),
+        make_swap_event(OTHER_TOKEN, OTHER_PAIR, "other", start + 90, 0.1),
+    ]
+}
+
+/// call sends a GET request to a router over the seeded storages, returns the status and body
+async fn call(uri: &str) -> (StatusCode, Value) {
+    call_with(&swap_events(now()), uri).await
+}
+
+/// call_with sends a GET request to a router over storages seeded with `swap_events`
+async fn call_with(swap_events: &[SwapEvent], uri: &str) -> (StatusCode, Value) {
+    let tokens = [make_token(TOKEN, "BONK", "Bonk"), make_token(OTHER_TOKEN, "WIF", "dogwifhat")];
+    let (db, kv_store) = seeded_storages(swap_events, &tokens).await;
+    let router = build_router(AppState::new(db, kv_store), AdminAuth::default());
+    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let response = router.oneshot(request).await.expect("Failed to call endpoint");
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_price() {
+    let (status, body) = call(&format!("/price?token={TOKEN}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["price"], 1.5);
+    assert_eq!(body["source"], "db");
+
+    // a token without trades has no price
+    let (status, body) = call("/price?token=So11111111111111111111111111111111111111112").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("price").is_none_or(Value::is_null));
+
+    let (status, _) = call("/price").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = call("/price?token=short").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_parameter");
+}
+
+/// post_prices posts a batch price request to a router over the database
+async fn post_prices(db: MemoryDb, body: Value) -> (StatusCode, Value) {
+    let db: Database = Box::new(db);
+    let router = build_router(
+        AppState::new(Arc::new(db), Arc::new(KvStore::in_memory())),
+        AdminAuth::default(),
+    );
+    let request = Request::builder()
+        .method("POST")
+        .uri("/prices")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = router.oneshot(request).await.expect("Failed to call endpoint");
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_prices_partial_results() {
+    let now = now();
+    let tokens = [make_token(TOKEN, "BONK", "Bonk"), make_token(OTHER_TOKEN, "WIF", "dogwifhat")];
+    let db = seeded_db(&swap_events(now), &tokens).await;
+    // the query of the other token fails, and so does its retry
+    db.fail_prices(OTHER_TOKEN, 2);
+
+    let body = serde_json::json!([
+        {"token": TOKEN, "timestamp": now},
+        {"token": "not-a-base58-address", "timestamp": now},
+        {"token": OTHER_TOKEN, "timestamp": now},
+        {"token": SOL, "timestamp": now},
+        {"token": TOKEN, "timestamp": now + 86400},
+    ]);
+    let (status, body) = post_prices(db, body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["errors_count"], 3);
+    let prices = body["prices"].as_array().unwrap();
+    assert_eq!(prices.len(), 5);
+    assert_eq!(prices[0]["price"], 1.5);
+    assert_eq!(prices[1]["token"], "not-a-base58-address");
+    assert_eq!(prices[1]["error"], "invalid token address");
+    assert_eq!(prices[2]["token"], OTHER_TOKEN);
+    assert_eq!(prices[2]["error"], "failed to get price");
+    // a token without trades has a price item without a price
+    assert_eq!(prices[3]["token"], SOL);
+    assert!(prices[3].get("error").is_none());
+    assert!(prices[3]["price"].is_null());
+    assert_eq!(prices[4]["error"], "invalid timestamp");
+}
+
+#[tokio::test]
+async fn test_price_sources() {
+    let now = now();
+    let tokens = [make_token(TOKEN, "BONK", "Bonk"), make_token(OTHER_TOKEN, "WIF", "dogwifhat")];
+    let (db, kv_store) = seeded_storages(&swap_events(now), &tokens).await;
+    // a fresh kv price of the token, a stale one of the other token and none of SOL
+    let fresh = make_swap_event(TOKEN, PAIR, "fresh", now - 10, 9.0);
+    let stale = make_swap_event(OTHER_TOKEN, OTHER_PAIR, "stale", now - 600, 7.0);
+    for swap_event in [fresh, stale] {
+        kv_store.insert_price(&swap_event.into()).await.expect("Failed to insert price");
+    }
+    let state = AppState::new(db, kv_store).with_price_max_staleness_secs(60);
+    let router = build_router(state, AdminAuth::default());
+
+    let get_price = |token: &str| {
+        let request = Request::builder().uri(format!("/price?token={token}&timestamp={now}"));
+        send(&router, request.body(Body::empty()).unwrap())
+    };
+    let (status, body) = get_price(TOKEN).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((body["price"].clone(), body["source"].clone()), (9.0.into(), "kv".into()));
+    let (_, body) = get_price(OTHER_TOKEN).await;
+    assert_eq!((body["price"].clone(), body["source"].clone()), (0.1.into(), "db".into()));
+    let (_, body) = get_price(SOL).await;
+    assert!(body.get("price").is_none_or(Value::is_null));
+
+    // the batch reads the kv prices at once and only queries the db for the misses
+    let body = serde_json::json!([
+        {"token": TOKEN, "timestamp": now},
+        {"token": OTHER_TOKEN, "timestamp": now},
+        {"token": SOL, "timestamp": now},
+    ]);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/prices")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let (status, body) = send(&router, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["errors_count"], 0);
+    let sources: Vec<(Value, Value)> = body["prices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|price| (price["price"].clone(), price["source"].clone()))
+        .collect();
+    assert_eq!(sources[0], (9.0.into(), "kv".into()));
+    assert_eq!(sources[1], (0.1.into(), "db".into()));
+    assert!(sources[2].0.is_null());
+}
+
+#[tokio::test]
+async fn test_trades() {
+    let (status, body) = call(&format!("/trades?token={TOKEN}&limit=2")).await;
+    assert_eq!(status, StatusCode::OK);
+    let signatures: Vec<&str> =
+        body.as_array().unwrap().iter().map(|trade| trade["signature"].as_str().unwrap()).collect();
+    assert_eq!(signatures, vec!["third", "second"]);
+
+    // every trade without a filter
+    let (status, body) = call("/trades").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 4);
+
+    let (status, body) = call("/trades?pair=unknown").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Array(vec![]));
+
+    // a signature is looked up as is, time_from narrows it
+    let (status, body) = call("/trades?signature=second").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    let (status, body) = call("/trades?signature=second&time_from=99999999999999").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Array(vec![]));
+
+    let (status, _) = call("/trades?limit=many").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_pair_discovery() {
+    let start = now() / 60 * 60 - 120;
+    // eleven pools, the one with the least turnover trades at an outlying price
+    let swap_events: Vec<SwapEvent> = (0..11u64)
+        .map(|i| {
+            let pair = format!("pool-{i:02}");
+            let price = if i == 10 { 50.0 } else { 1.0 };
+            SwapEvent {
+                swap_amount: 100.0 * (11 - i) as f64,
+                ..make_swap_event(TOKEN, &pair, &pair, start + i, price)
+            }
+        })
+        .collect();
+    let db = seeded_db(&swap_events, &[make_token(TOKEN, "BONK", "Bonk")]).await;
+    let recorded = Pair {
+        pair: "pool-00".to_string(),
+        base_mint: TOKEN.to_string(),
+        quote_mint: SOL.to_string(),
+        dex: Dexes::PumpAmm,
+        first_seen: start,
+    };
+    db.insert_pair(&recorded).await.expect("Failed to insert pair");
+    let db: Database = Box::new(db);
+    let router = build_router(
+        AppState::new(Arc::new(db), Arc::new(KvStore::in_memory())),
+        AdminAuth::default(),
+    );
+    let get = |uri: String| send(&router, Request::builder().uri(uri).body(Body::empty()).unwrap());
+
+    let (status, body) = get(format!("/pairs?token={TOKEN}")).await;
+    assert_eq!(status, StatusCode::OK);
+    let pairs = body.as_array().unwrap();
+    assert_eq!(pairs.len(), 11);
+    assert_eq!(
+        (pairs[0]["pair"].clone(), pairs[0]["turnover"].clone()),
+        ("pool-00".into(), 1100.0.into())
+    );
+    assert_eq!(pairs[0]["dex"], Dexes::PumpAmm.to_string());
+    assert!(pairs[1].get("dex").is_none());
+    assert_eq!(pairs[10]["pair"], "pool-10");
+
+    // without a pair the candlesticks span the top pools only, the outlying pool is left out
+    let (status, body) = get(format!("/token-ohlcv?token={TOKEN}&interval=1m")).await;
+    assert_eq!(status, StatusCode::OK);
+    let candlesticks = body.as_array().unwrap();
+    assert_eq!(candlesticks.len(), 1);
+    assert_eq!(candlesticks[0]["h"], 1.0);
+    let (_, body) = get(format!("/token-ohlcv?token={TOKEN}&pair=pool-10&interval=1m")).await;
+    assert_eq!(body[0]["h"], 50.0);
+}
+
+#[tokio::test]
+async fn test_trades_min_pct_supply() {
+    let mut swap_events = swap_events(now());
+    swap_events[1].pct_of_supply = 0.01;
+    swap_events[2].pct_of_supply = 0.004;
+    let (status, body) =
+        call_with(&swap_events, &format!("/trades?token={TOKEN}&min_pct_supply=0.005")).await;
+    assert_eq!(status, StatusCode::OK);
+    let trades = body.as_array().unwrap();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["signature"], "second");
+    assert_eq!(trades[0]["pct_of_supply"], 0.01);
+
+    // the share of the supply is a fraction
+    for min_pct_supply in ["1.5", "-0.1", "NaN"] {
+        let uri = format!("/trades?token={TOKEN}&min_pct_supply={min_pct_supply}");
+        let (status, body) = call_with(&swap_events, &uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_parameter");
+    }
+}
+
+#[tokio::test]
+async fn test_candlesticks() {
+    let (status, body) = call(&format!("/candlesticks?token={TOKEN}&interval=1m")).await;
+    assert_eq!(status, StatusCode::OK);
+    let candlesticks = body.as_array().unwrap();
+    assert_eq!(candlesticks.len(), 2);
+    let first = &candlesticks[0];
+    assert_eq!((first["o"].as_f64(), first["h"].as_f64()), (Some(1.0), Some(2.0)));
+    assert_eq!(first["c"], 2.0);
+    assert_eq!(first["v"], 2_000.0);
+    assert_eq!(candlesticks[1]["o"], 1.5);
+
+    let (status, body) =
+        call(&format!("/candlesticks?token={TOKEN}&interval=1m&pair={OTHER_PAIR}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Array(vec![]));
+
+    let (status, _) = call(&format!("/candlesticks?token={TOKEN}")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(&format!("/candlesticks?token={TOKEN}&interval=7m")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // a month of minutes is too many candlesticks, unless the interval may be coarsened
+    let month_ago = now() - 30 * 86400;
+    let uri = format!("/candlesticks?token={TOKEN}&interval=1m&time_from={month_ago}");
+    let (status, body) = call(&uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_parameter");
+    let (status, body) = call(&format!("{uri}&auto_interval=true")).await;
+    assert_eq!(status, StatusCode::OK);
+    // queried as 15m candlesticks
+    let candlesticks = body.as_array().unwrap();
+    assert!(!candlesticks.is_empty());
+    assert!(candlesticks.iter().all(|c| c["t"].as_u64().unwrap() % 900 == 0));
+}
+
+/// call_encoded sends a GET request accepting `accept_encoding`, returns the status, the
+/// headers and the raw body
+async fn call_encoded(
+    swap_events: &[SwapEvent],
+    uri: &str,
+    accept_encoding: Option<&str>,
+) -> (StatusCode, HeaderMap, Bytes) {
+    let (db, kv_store) = seeded_storages(swap_events, &[make_token(TOKEN, "BONK", "Bonk")]).await;
+    let router = build_router(AppState::new(db, kv_store), AdminAuth::default());
+    let mut request = Request::builder().uri(uri);
+    if let Some(accept_encoding) = accept_encoding {
+        request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+    }
+    let response = router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+    let (parts, body) = response.into_parts();
+    (parts.status, parts.headers, axum::body::to_bytes(body, usize::MAX).await.unwrap())
+}
+
+#[tokio::test]
+async fn test_candlestick_compression() {
+    // a trade per minute over 1000 minutes, at prices full of float noise
+    let start = now() / 60 * 60 - 1000 * 60;
+    let events: Vec<SwapEvent> = (0..1000)
+        .map(|i| {
+            let price = 1.0 + i as f64 / 7.0;
+            make_swap_event(TOKEN, PAIR, &format!("swap-{i}"), start + i * 60, price)
+        })
+        .collect();
+    let uri = format!("/pair-ohlcv?pair={PAIR}&interval=1m&limit=1000&time_from={start}");
+
+    let (status, headers, rows) = call_encoded(&events, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers.get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(serde_json::from_slice::<Value>(&rows).unwrap().as_array().unwrap().len(), 1000);
+
+    for encoding in ["gzip", "br"] {
+        let (status, headers, body) = call_encoded(&events, &uri, Some(encoding)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_ENCODING], encoding);
+        assert!(body.len() < rows.len() / 4, "{encoding} {} of {}", body.len(), rows.len());
+    }
+    // the encodings the client doesn't accept are not used
+    let (_, headers, _) = call_encoded(&events, &uri, Some("identity")).await;
+    assert!(headers.get(header::CONTENT_ENCODING).is_none());
+
+    let (status, _, columns) = call_encoded(&events, &format!("{uri}&compact=true"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(columns.len() < rows.len() / 2, "compact {} of {}", columns.len(), rows.len());
+    let columns: Value = serde_json::from_slice(&columns).unwrap();
+    for column in ["t", "o", "h", "l", "c", "v"] {
+        assert_eq!(columns[column].as_array().unwrap().len(), 1000);
+    }
+    assert_eq!(columns["t"][1], start + 60);
+    // 1 + 1/7 rounded to 8 significant digits
+    assert_eq!(columns["o"][1], 1.1428571);
+}
+
+#[tokio::test]
+async fn test_sol_denomination() {
+    let mut events = swap_events(now());
+    let start = events[0].timestamp;
+    // SOL traded two minutes before the first candlestick and in the second one only
+    events.push(make_swap_event(SOL, SOL_PAIR, "sol-before", start - 120, 100.0));
+    events.push(make_swap_event(SOL, SOL_PAIR, "sol-second", start + 60, 50.0));
+
+    let uri = format!("/token-ohlcv?token={TOKEN}&pair={PAIR}&interval=1m&denomination=sol");
+    let (status, body) = call_with(&events, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let candlesticks = body.as_array().unwrap();
+    assert_eq!(candlesticks.len(), 2);
+    // the first bucket carries the SOL price from before it
+    assert_eq!(
+        (candlesticks[0]["o"].as_f64(), candlesticks[0]["c"].as_f64()),
+        (Some(0.01), Some(0.02))
+    );
+    assert_eq!(candlesticks[0]["vc"], 3_000.0);
+    assert_eq!(candlesticks[1]["o"], 0.03);
+
+    let (status, body) =
+        call_with(&events, &format!("/trades?token={TOKEN}&denomination=sol")).await;
+    assert_eq!(status, StatusCode::OK);
+    let trades = body.as_array().unwrap();
+    assert_eq!(trades[0]["signature"], "third");
+    assert_eq!(trades[0]["price"], 1.5);
+    assert_eq!(trades[0]["price_sol"], 0.03);
+
+    // usd prices have no SOL price
+    let (_, body) = call_with(&events, &format!("/trades?token={TOKEN}")).await;
+    assert!(body[0].get("price_sol").is_none());
+
+    // without SOL trades there is no SOL price to convert with
+    let (status, _) = call(&uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_sol_usd_candlesticks() {
+    let db = MemoryDb::default();
+    let start = now() / 60 * 60 - 120;
+    for (offset, price) in [(0, 150.0), (10, 152.0), (59, 151.0), (60, 149.0)] {
+        let price =
+            ReferencePrice { pair: SOL_USD_PAIR.to_string(), price, timestamp: start + offset };
+        db.insert_reference_price(&price).await.unwrap();
+    }
+    let db: Database = Box::new(db);
+    let router = build_router(
+        AppState::new(Arc::new(db), Arc::new(KvStore::in_memory())),
+        AdminAuth::default(),
+    );
+    let request = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    // the SOLUSD candlesticks are built from the persisted Binance trades, without volume
+    let uri = format!("/pair-ohlcv?pair={SOL_USD_PAIR}&interval=1m");
+    let (status, body) = send(&router, request(uri)).await;
+    assert_eq!(status, StatusCode::OK);
+    let candlesticks = body.as_array().unwrap();
+    assert_eq!(candlesticks.len(), 2);
+    assert_eq!(candlesticks[0]["t"], start);
+    assert_eq!(
+        (candlesticks[0]["o"].as_f64(), candlesticks[0]["h"].as_f64()),
+        (Some(150.0), Some(152.0))
+    );
+    assert_eq!(
+        (candlesticks[0]["l"].as_f64(), candlesticks[0]["c"].as_f64()),
+        (Some(150.0), Some(151.0))
+    );
+    assert_eq!(candlesticks[0]["v"], 0.0);
+    assert_eq!(candlesticks[1]["o"], 149.0);
+
+    let uri = format!("/pair-ohlcv?pair={SOL_USD_PAIR}&interval=1m&time_from={}", start + 60);
+    let (status, body) = send(&router, request(uri)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_top_tokens() {
+    let (status, body) = call("/top-tokens").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 2);
+    let tokens = body["tokens"].as_array().unwrap();
+    assert_eq!(tokens[0]["pubkey"], TOKEN);
+    assert_eq!(tokens[0]["volume"], 3_000.0);
+    assert_eq!(tokens[0]["unique_traders"], 1);
+
+    let (status, body) = call("/top-tokens?sort_by=volume&order=asc&limit=1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["tokens"][0]["pubkey"], OTHER_TOKEN);
+
+    let (status, body) = call("/top-tokens?min_volume=1000000").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["tokens"], Value::Array(vec![]));
+
+    let (status, body) = call("/top-tokens?limit=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_parameter");
+}
+
+#[tokio::test]
+async fn test_search() {
+    let (status, body) = call("/search?s=bonk").await;
+    assert_eq!(status, StatusCode::OK);
+    let results = body.as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["token"], TOKEN);
+    // the ranking is only returned with debug
+    assert!(results[0].get("match_reason").is_none());
+
+    let (status, body) = call("/search?s=wif&debug=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["token"], OTHER_TOKEN);
+    assert_eq!(body[0]["match_reason"], "symbol_exact");
+
+    let (status, body) = call("/search?s=nothing").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Array(vec![]));
+
+    let (status, _) = call("/search").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call("/search?s=").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// send sends a request to the router, returns the status and body
+async fn send(router: &axum::Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = router.clone().oneshot(request).await.expect("Failed to call endpoint");
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_token_address_validation() {
+    let (status, body) = call(&format!("/token?token={TOKEN}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["symbol"], "BONK");
+
+    // a quote is rejected before the query is built
+    let (status, body) = call(&format!("/token?token={TOKEN}%27%20OR%20%271%27=%271")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "invalid_address");
+    assert_eq!(body["message"], "invalid address `token`: expected a base58 public key");
+    // the length is checked first
+    let (status, _) = call("/token?token=short").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = call(&format!("/tokens?tokens={TOKEN},bonk%27")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["message"], "invalid address `tokens[1]`: expected a base58 public key");
+    for uri in ["/token-stats", "/token-daily-stats"] {
+        let (status, _) = call(&format!("{uri}?tokens={OTHER_TOKEN}%27")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn test_rejections_use_the_error_body() {
+    // a missing or malformed parameter is rejected by the extractor
+    for uri in ["/token", "/pairs?token=short&since=soon", "/candlestick-latest"] {
+        let (status, body) = call(uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(body["code"], "invalid_parameter", "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn test_wallet_labels() {
+    const CEX: &str = "5tzFkiKscXHK5ZXCGbXZxdw7gTjjD1mBwuoFbhUvuAi9";
+    let mut events = swap_events(now());
+    events[1].owner = CEX.to_string();
+    let (db, kv_store) = seeded_storages(&events, &[]).await;
+    let router =
+        build_router(AppState::new(db, kv_store), AdminAuth::new(Some("admin-key".to_string())));
+    let import = |body: Value| {
+        Request::builder()
+            .method("POST")
+            .uri("/admin/wallet-labels")
+            .header("content-type", "application/json")
+            .header("x-api-key", "admin-key")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    // the trades are cached as unlabeled before the import
+    let (status, body) = send(&router, get(&format!("/trades?token={TOKEN}"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.as_array().unwrap().iter().all(|trade| trade.get("owner_label").is_none()));
+
+    // a single invalid label rejects the whole import
+    let body = serde_json::json!([
+        {"address": CEX, "label": "Binance 1", "category": "cex"},
+        {"address": "not-an-address", "label": "Bot", "category": "mev"},
+    ]);
+    let (status, body) = send(&router, import(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_parameter");
+    let body = serde_json::json!([{"address": CEX, "label": "Binance 1", "category": "bank"}]);
+    let (status, _) = send(&router, import(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = serde_json::json!([{"address": CEX, "label": " Binance 1 ", "category": "cex"}]);
+    let (status, body) = send(&router, import(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["imported"], 1);
+
+    // the import refreshes the cached label
+    let (status, body) = send(&router, get(&format!("/trades?token={TOKEN}"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let labels: Vec<Option<&str>> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|trade| trade.get("owner_label").and_then(Value::as_str))
+        .collect();
+    assert_eq!(labels, vec![None, Some("Binance 1"), None]);
+
+    let (status, body) = send(&router, get(&format!("/trades?token={TOKEN}&category=cex"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let trades = body.as_array().unwrap();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(
+        (&trades[0]["signature"], &trades[0]["owner"]),
+        (&Value::from("second"), &Value::from(CEX))
+    );
+    let (status, body) = send(&router, get(&format!("/trades?token={TOKEN}&category=mev"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Array(vec![]));
+    let (status, _) = send(&router, get("/trades?category=bank")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // the admin api key is required
+    let request = Request::builder()
+        .method("POST")
+        .uri("/admin/wallet-labels")
+        .header("content-type", "application/json")
+        .body(Body::from("[]"))
+        .unwrap();
+    let (status, _) = send(&router, request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// slow_router returns a router over a database taking a second to answer the heavy queries
+async fn slow_router(timeouts: RequestTimeouts) -> (axum::Router, MemoryDb) {
+    let tokens = [make_token(TOKEN, "BONK", "Bonk")];
+    let memory_db =
+        seeded_db(&swap_events(now()), &tokens).await.with_query_delay(Duration::from_secs(1));
+    let db: Database = Box::new(memory_db.clone());
+    let state =
+        AppState::new(Arc::new(db), Arc::new(KvStore::in_memory())).with_request_timeouts(timeouts);
+    (build_router(state, AdminAuth::default()), memory_db)
+}
+
+#[tokio::test]
+async fn test_request_timeout() {
+    let timeouts =
+        RequestTimeouts { default: Duration::from_secs(5), heavy: Duration::from_millis(50) };
+    let (router, db) = slow_router(timeouts).await;
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let request = Request::builder()
+        .uri("/top-tokens")
+        .header("x-request-id", "slow-request")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&router, request).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["code"], "timeout");
+    assert_eq!(body["message"], "request timed out after 50ms");
+    assert_eq!(body["request_id"], "slow-request");
+    // the query was dropped with the request
+    assert_eq!(db.cancelled_queries(), 1);
+
+    let (status, body) =
+        send(&router, get(&format!("/token-ohlcv?token={TOKEN}&interval=1m"))).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["code"], "timeout");
+    assert_eq!(db.cancelled_queries(), 2);
+
+    // the light routes only have the default deadline
+    let (status, _) = send(&router, get(&format!("/token?token={TOKEN}"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // as do the heavy ones when it is the stricter one
+    let timeouts =
+        RequestTimeouts { default: Duration::from_millis(50), heavy: Duration::from_secs(5) };
+    let (router, db) = slow_router(timeouts).await;
+    let (status, body) = send(&router, get("/search?s=bonk")).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["message"], "request timed out after 50ms");
+    assert_eq!(db.cancelled_queries(), 1);
+}
+
+#[tokio::test]
+async fn test_client_disconnect_drops_query() {
+    let (router, db) = slow_router(RequestTimeouts::default()).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /top-tokens HTTP/1.1\r\nhost: localhost\r\n\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(db.cancelled_queries(), 0);
+
+    // the server notices the closed connection and drops the handler future with its query,
+    // well before the query would have completed
+    drop(stream);
+    let cancelled = async {
+        while db.cancelled_queries() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(tokio::time::timeout(Duration::from_millis(500), cancelled).await.is_ok());
+    assert_eq!(db.cancelled_queries(), 1);
+}
+
+/// export_router returns a router over the database exporting at most `max_rows` trades
+fn export_router(db: &MemoryDb, max_rows: usize) -> axum::Router {
+    let db: Database = Box::new(db.clone());
+    let state = AppState::new(Arc::new(db), Arc::new(KvStore::in_memory()))
+        .with_export_max_rows(max_rows)
+        .with_export_auth(ExportAuth::new(Some("secret".to_string())));
+    build_router(state, AdminAuth::new(Some("admin-secret".to_string())))
+}
+
+/// export sends an export request with the api key, returns the status and the body chunks
+/// as they are polled
+async fn export(router: axum::Router, uri: &str) -> (StatusCode, Vec<Bytes>) {
+    let request =
+        Request::builder().uri(uri).header("x-api-key", "secret").body(Body::empty()).unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    let status = response.status();
+    let chunks = response.into_body().into_data_stream().map(Result::unwrap).collect().await;
+    (status, chunks)
+}
+
+#[tokio::test]
+async fn test_trades_export() {
+    let db = MemoryDb::default();
+    let start = now() - 100_000;
+    for i in 0..50_000 {
+        let event = make_swap_event(TOKEN, PAIR, &format!("sig{i}"), start + i, 1.0);
+        db.insert_swap_event(&event).await.unwrap();
+    }
+    db.insert_swap_event(&make_swap_event(OTHER_TOKEN, OTHER_PAIR, "other", start, 0.1))
+        .await
+        .unwrap();
+
+    // the trades are streamed chunk by chunk, never buffered as a whole
+    let (status, chunks) =
+        export(export_router(&db, 1_000_000), &format!("/trades/export?token={TOKEN}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(chunks.len(), 50);
+    let lines: Vec<Value> = chunks
+        .iter()
+        .inspect(|chunk| assert_eq!(chunk.iter().filter(|&&b| b == b'\n').count(), 1000))
+        .flat_map(|chunk| chunk.split(|&b| b == b'\n').filter(|line| !line.is_empty()))
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 50_000);
+    assert_eq!(lines[0]["signature"], "sig0");
+    assert_eq!(lines[49_999]["signature"], "sig49999");
+    assert!(lines.iter().all(|line| line["token"] == TOKEN));
+
+    // the exports past the cap end with a truncation line
+    let (status, chunks) =
+        export(export_router(&db, 20_000), &format!("/trades/export?token={TOKEN}")).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = chunks.concat();
+    let lines: Vec<&[u8]> = body.split(|&b| b == b'\n').filter(|line| !line.is_empty()).collect();
+    assert_eq!(lines.len(), 20_001);
+    let trailer: Value = serde_json::from_slice(lines[20_000]).unwrap();
+    assert_eq!(trailer, serde_json::json!({ "truncated": true, "rows": 20_000 }));
+
+    // csv within a time range
+    let uri =
+        format!("/trades/export?token={TOKEN}&format=csv&from={}&to={}", start + 10, start + 20);
+    let (status, chunks) = export(export_router(&db, 5), &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(chunks.concat()).unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert!(lines[0].starts_with("pair,token,price"));
+    assert!(lines[1].contains(",sig10,"));
+    assert_eq!(lines[6], "# truncated after 5 rows");
+
+    // the export needs the api key
+    let request = Request::builder()
+        .uri(format!("/trades/export?token={TOKEN}"))
+        .body(Body::empty())
+        .unwrap();
+    let (status, _) = send(&export_router(&db, 5), request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    // the admin key doesn't open the exports
+    let request = Request::builder()
+        .uri(format!("/trades/export?token={TOKEN}"))
+        .header("x-api-key", "admin-secret")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _) = send(&export_router(&db, 5), request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = export(export_router(&db, 5), "/trades/export?token=short").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) =
+        export(export_router(&db, 5), &format!("/trades/export?token={TOKEN}&format=xml")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_token_history() {
+    let db = MemoryDb::default();
+    // 2025-01-01 and 2025-01-02
+    for (signature, timestamp, price) in
+        [("a", 1_735_689_600, 1.0), ("b", 1_735_689_660, 4.0), ("c", 1_735_776_000, 2.0)]
+    {
+        db.insert_swap_event(&make_swap_event(TOKEN, PAIR, signature, timestamp, price))
+            .await
+            .unwrap();
+    }
+    for day in 1..=2 {
+        let date = chrono::NaiveDate::from_ymd_opt(2025, 1, day).unwrap();
+        assert_eq!(db.aggregate_daily_token_rollups(date).await.unwrap(), Some(1));
+    }
+    let database: Database = Box::new(db.clone());
+    let router = build_router(
+        AppState::new(Arc::new(database), Arc::new(KvStore::in_memory())),
+        AdminAuth::default(),
+    );
+    let get = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let (status, body) =
+        send(&router, get(format!("/token-history?token={TOKEN}&from=2025-01-01&to=2025-01-31")))
+            .await;
+    assert_eq!(status, StatusCode::OK);
+    let days = body.as_array().unwrap();
+    assert_eq!(days.len(), 2);
+    assert_eq!(days[0]["date"], "2025-01-01");
+    assert_eq!((days[0]["open"].clone(), days[0]["high"].clone()), (1.0.into(), 4.0.into()));
+    assert_eq!(days[0]["trade_count"], 2);
+    assert_eq!(days[1]["date"], "2025-01-02");
+
+    // the range is inclusive
+    let (_, body) =
+        send(&router, get(format!("/token-history?token={TOKEN}&from=2025-01-02&to=2025-01-02")))
+            .await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    for uri in [
+        format!("/token-history?token={TOKEN}&from=2025-01-02&to=2025-01-01"),
+        format!("/token-history?token={TOKEN}&from=2000-01-01&to=2025-01-01"),
+        format!("/token-history?token={TOKEN}&from=2025-13-01"),
+    ] {
+        let (status, _) = send(&router, get(uri.clone())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+    }
+    let (status, _) = send(&router, get("/token-history?token=short".to_string())).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+/// Resolves every mint with the SOL metadata, as the RPC would
+struct MockResolver;
+
+#[async_trait::async_trait]
+impl TokenResolver for MockResolver {
+    async fn resolve(&self, mint: &str) -> anyhow::Result<ResolvedToken> {
+        let token = make_token(mint, "SOL", "Wrapped SOL");
+        Ok(ResolvedToken { token, risk_flags: None, complete: true })
+    }
+}
+
+#[tokio::test]
+async fn test_resolve_tokens() {
+    let (db, kv_store) = seeded_storages(&[], &[make_token(OTHER_TOKEN, "WIF", "dogwifhat")]).await;
+    kv_store.set_token(TOKEN, &make_token(TOKEN, "BONK", "Bonk")).await.unwrap();
+    let state = AppState::new(db, kv_store.clone())
+        .with_token_resolver(BackgroundResolver::new(Arc::new(MockResolver), 4));
+    let router = build_router(state, AdminAuth::default());
+    let resolve = |body: Value| {
+        Request::builder()
+            .method("POST")
+            .uri("/tokens/resolve")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    // the cache hit, the db hit and the unknown mint, in the order of the request
+    let body = serde_json::json!({ "tokens": [SOL, TOKEN, OTHER_TOKEN, SOL] });
+    let (status, entries) = send(&router, resolve(body.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let statuses: Vec<&str> =
+        entries.as_array().unwrap().iter().map(|e| e["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, vec!["resolving", "resolved", "resolved", "resolving"]);
+    assert_eq!(entries[0]["mint"], SOL);
+    assert!(entries[0].get("token").is_none());
+    assert_eq!(entries[1]["token"]["symbol"], "BONK");
+    assert_eq!(entries[2]["token"]["symbol"], "WIF");
+    // the db hit is cached for the next lookups
+    assert_eq!(kv_store.get_token(OTHER_TOKEN).await.unwrap().unwrap().symbol, "WIF");
+
+    // the next request reads the resolved mint from the cache
+    for _ in 0..50 {
+        if kv_store.get_token(SOL).await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let (_, entries) = send(&router, resolve(body)).await;
+    assert!(entries.as_array().unwrap().iter().all(|e| e["status"] == "resolved"));
+    assert_eq!(entries[0]["token"]["symbol"], "SOL");
+
+    let (status, entries) =
+        send(&router, resolve(serde_json::json!({ "tokens": [PAIR], "resolve": false }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(entries[0]["status"], "unknown");
+
+    let (status, _) =
+        send(&router, resolve(serde_json::json!({ "tokens": vec![TOKEN; 101] }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&router, resolve(serde_json::json!({ "tokens": ["bonk'"] }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -0,0 +1,450 @@
+//! this crate authorizes the socket.io clients of the api and the streams, from a signed token
+//! or an API key, and gates the rooms they may join by their tier
+mod socket;
+
+pub use socket::{authenticate, authorize_rooms};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, env, str::FromStr, sync::Arc};
+use strum_macros::{Display, EnumString};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The subscription tier of a client, ordered from the least to the most privileged
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    EnumString,
+    Display
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Tier {
+    #[default]
+    Anonymous,
+    Free,
+    Premium,
+}
+
+/// The identity of a socket, attached to its extensions on connect
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientClaims {
+    pub tier: Tier,
+    pub user_id: Option<String>,
+}
+
+/// The socket.io auth payload sent by a client on connect
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthPayload {
+    /// A signed token or an API key, the client is anonymous without it
+    #[serde(default, alias = "api_key", alias = "apiKey")]
+    pub token: Option<String>,
+}
+
+/// The claims of a signed token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub tier: Tier,
+    /// The unix timestamp the token expires at
+    pub exp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenHeader {
+    alg: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Malformed token")]
+    Malformed,
+    #[error("Unsupported token algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("Invalid token signature")]
+    InvalidSignature,
+    #[error("Token expired")]
+    Expired,
+    #[error("Authorizer unavailable: {0}")]
+    Unavailable(String),
+}
+
+/// Validates the credentials of a connecting socket
+#[async_trait]
+pub trait Authorizer: Send + Sync {
+    /// authorize returns the claims of the payload, the anonymous claims without credentials
+    async fn authorize(&self, payload: &AuthPayload) -> Result<ClientClaims, AuthError>;
+}
+
+/// Treats every client as anonymous, used when no secret is configured
+#[derive(Debug, Clone, Default)]
+pub struct AnonymousAuthorizer;
+
+#[async_trait]
+impl Authorizer for AnonymousAuthorizer {
+    async fn authorize(&self, _payload: &AuthPayload) -> Result<ClientClaims, AuthError> {
+        Ok(ClientClaims::default())
+    }
+}
+
+/// Validates HS256 signed tokens with a shared secret
+#[derive(Clone)]
+pub struct HmacAuthorizer {
+    secret: Vec<u8>,
+}
+
+impl HmacAuthorizer {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self { secret: secret.into() }
+    }
+
+    fn mac(&self, header: &str, claims: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(header.as_bytes());
+        mac.update(b".");
+        mac.update(claims.as_bytes());
+        mac
+    }
+
+    /// sign returns the HS256 token of the claims
+    pub fn sign(&self, claims: &TokenClaims) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(claims).expect("Token claims are always serializable"));
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&header, &claims).finalize().into_bytes());
+        format!("{header}.{claims}.{signature}")
+    }
+
+    /// verify returns the claims of a token signed with the secret and not expired at `now`
+    pub fn verify(&self, token: &str, now: u64) -> Result<TokenClaims, AuthError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthError::Malformed);
+        };
+
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| AuthError::Malformed);
+        let token_header: TokenHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|_| AuthError::Malformed)?;
+        if token_header.alg != "HS256" {
+            return Err(AuthError::UnsupportedAlgorithm(token_header.alg));
+        }
+        self.mac(header, claims)
+            .verify_slice(&decode(signature)?)
+            .map_err(|_| AuthError::InvalidSignature)?;
+
+        let claims: TokenClaims =
+            serde_json::from_slice(&decode(claims)?).map_err(|_| AuthError::Malformed)?;
+        if claims.exp <= now {
+            return Err(AuthError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+#[async_trait]
+impl Authorizer for HmacAuthorizer {
+    async fn authorize(&self, payload: &AuthPayload) -> Result<ClientClaims, AuthError> {
+        let Some(token) = payload.token.as_deref() else {
+            return Ok(ClientClaims::default());
+        };
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        let claims = self.verify(token, now)?;
+        Ok(ClientClaims { tier: claims.tier, user_id: Some(claims.sub) })
+    }
+}
+
+/// Accepts the configured API keys, and hands the other credentials to `fallback`
+#[derive(Clone)]
+pub struct ApiKeyAuthorizer {
+    keys: HashMap<String, Tier>,
+    fallback: Arc<dyn Authorizer>,
+}
+
+impl ApiKeyAuthorizer {
+    pub fn new(fallback: Arc<dyn Authorizer>) -> Self {
+        Self { keys: HashMap::new(), fallback }
+    }
+
+    /// with_key grants `tier` to the clients sending `key`
+    pub fn with_key(mut self, key: impl Into<String>, tier: Tier) -> Self {
+        self.keys.insert(key.into(), tier);
+        self
+    }
+
+    /// parse reads comma separated `key=tier` entries, e.g. `k1=premium,k2=free`
+    pub fn parse(keys: &str, fallback: Arc<dyn Authorizer>) -> Result<Self> {
+        Ok(Self { keys: Self::parse_keys(keys)?, fallback })
+    }
+
+    /// parse_keys reads the tier of each key of comma separated `key=tier` entries
+    pub fn parse_keys(keys: &str) -> Result<HashMap<String, Tier>> {
+        keys.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (key, tier) =
+                    entry.rsplit_once('=').ok_or_else(|| anyhow!("Invalid API key entry"))?;
+                let tier =
+                    Tier::from_str(tier.trim()).map_err(|_| anyhow!("Invalid API key tier"))?;
+                Ok((key.trim().to_string(), tier))
+            })
+            .collect()
+    }
+
+    /// key_id identifies the clients of a key without exposing it
+    fn key_id(key: &str) -> String {
+        let digest = Sha256::digest(key.as_bytes());
+        format!("api_key:{}", URL_SAFE_NO_PAD.encode(&digest[..9]))
+    }
+}
+
+#[async_trait]
+impl Authorizer for ApiKeyAuthorizer {
+    async fn authorize(&self, payload: &AuthPayload) -> Result<ClientClaims, AuthError> {
+        match payload.token.as_deref().and_then(|key| Some((key, self.keys.get(key)?))) {
+            Some((key, tier)) => Ok(ClientClaims { tier: *tier, user_id: Some(Self::key_id(key)) }),
+            None => self.fallback.authorize(payload).await,
+        }
+    }
+}
+
+/// The structured ack of a join request denied some of its rooms
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JoinError {
+    pub error: &'static str,
+    pub denied: Vec<DeniedRoom>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeniedRoom {
+    pub room: String,
+    pub required_tier: Tier,
+}
+
+/// The minimum tier joining the rooms of each prefix, rooms matching no prefix are public
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomPolicy {
+    rules: Vec<(String, Tier)>,
+}
+
+impl RoomPolicy {
+    /// with_rule requires `tier` to join the rooms starting with `prefix`
+    pub fn with_rule(mut self, prefix: impl Into<String>, tier: Tier) -> Self {
+        self.rules.push((prefix.into(), tier));
+        self
+    }
+
+    /// parse reads comma separated `prefix=tier` rules, e.g. `pair:=premium,new_pools=free`
+    pub fn parse(rules: &str) -> Result<Self> {
+        rules.split(',').map(str::trim).filter(|rule| !rule.is_empty()).try_fold(
+            Self::default(),
+            |policy, rule| {
+                let (prefix, tier) =
+                    rule.rsplit_once('=').ok_or_else(|| anyhow!("Invalid room rule: {rule}"))?;
+                let tier = Tier::from_str(tier.trim())
+                    .map_err(|_| anyhow!("Invalid tier in room rule: {rule}"))?;
+                Ok(policy.with_rule(prefix.trim(), tier))
+            },
+        )
+    }
+
+    /// Create a room policy from `WS_ROOM_TIERS`, every room is public when unset
+    pub fn from_env() -> Result<Self> {
+        env::var("WS_ROOM_TIERS").map_or_else(|_| Ok(Self::default()), |rules| Self::parse(&rules))
+    }
+
+    /// required_tier returns the tier of the longest prefix matching the room
+    pub fn required_tier(&self, room: &str) -> Tier {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| room.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(Tier::Anonymous, |(_, tier)| *tier)
+    }
+
+    /// authorize splits the rooms into the ones the claims may join and the error of the
+    /// denied ones, if any
+    pub fn authorize(
+        &self,
+        claims: &ClientClaims,
+        rooms: Vec<String>,
+    ) -> (Vec<String>, Option<JoinError>) {
+        let mut allowed = Vec::with_capacity(rooms.len());
+        let mut denied = Vec::new();
+        for room in rooms {
+            let required_tier = self.required_tier(&room);
+            if claims.tier >= required_tier {
+                allowed.push(room);
+            } else {
+                denied.push(DeniedRoom { room, required_tier });
+            }
+        }
+        let error = (!denied.is_empty()).then_some(JoinError { error: "unauthorized", denied });
+        (allowed, error)
+    }
+}
+
+/// The settings of the websocket auth, read and validated with the config of the service
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WsAuthConfig {
+    /// The secret the tokens are signed with, every client is anonymous when unset
+    pub secret: Option<String>,
+    /// The tier each API key grants
+    pub api_keys: HashMap<String, Tier>,
+    pub room_policy: RoomPolicy,
+}
+
+/// The socket.io state authorizing connections and room joins
+#[derive(Clone)]
+pub struct WsAuth {
+    pub authorizer: Arc<dyn Authorizer>,
+    pub policy: Arc<RoomPolicy>,
+}
+
+impl Default for WsAuth {
+    fn default() -> Self {
+        Self::new(Arc::new(AnonymousAuthorizer), RoomPolicy::default())
+    }
+}
+
+impl WsAuth {
+    pub fn new(authorizer: Arc<dyn Authorizer>, policy: RoomPolicy) -> Self {
+        Self { authorizer, policy: Arc::new(policy) }
+    }
+
+    /// from_config creates the websocket auth of validated settings
+    pub fn from_config(config: &WsAuthConfig) -> Self {
+        let mut authorizer: Arc<dyn Authorizer> = match &config.secret {
+            Some(secret) => Arc::new(HmacAuthorizer::new(secret.clone())),
+            None => Arc::new(AnonymousAuthorizer),
+        };
+        if !config.api_keys.is_empty() {
+            authorizer =
+                Arc::new(ApiKeyAuthorizer { keys: config.api_keys.clone(), fallback: authorizer });
+        }
+        Self::new(authorizer, config.room_policy.clone())
+    }
+
+    /// Create the websocket auth from `WS_AUTH_SECRET`, `WS_API_KEYS` and `WS_ROOM_TIERS`
+    pub fn from_env() -> Result<Self> {
+        let secret = env::var("WS_AUTH_SECRET").ok().filter(|secret| !secret.is_empty());
+        let api_keys = match env::var("WS_API_KEYS") {
+            Ok(keys) => ApiKeyAuthorizer::parse_keys(&keys)?,
+            Err(_) => HashMap::new(),
+        };
+        let room_policy = RoomPolicy::from_env()?;
+        Ok(Self::from_config(&WsAuthConfig { secret, api_keys, room_policy }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(tier: Tier, exp: u64) -> TokenClaims {
+        TokenClaims { sub: "user".to_string(), tier, exp }
+    }
+
+    #[test]
+    fn test_hmac_token_validation() {
+        let authorizer = HmacAuthorizer::new("secret");
+        let token = authorizer.sign(&claims(Tier::Premium, 100));
+        assert_eq!(authorizer.verify(&token, 99).unwrap(), claims(Tier::Premium, 100));
+        assert!(matches!(authorizer.verify(&token, 100), Err(AuthError::Expired)));
+
+        let forged = HmacAuthorizer::new("other").sign(&claims(Tier::Premium, 100));
+        assert!(matches!(authorizer.verify(&forged, 0), Err(AuthError::InvalidSignature)));
+
+        // a valid signature doesn't cover tampered claims
+        let (header, rest) = token.split_once('.').unwrap();
+        let signature = rest.split_once('.').unwrap().1;
+        let tampered = URL_SAFE_NO_PAD.encode(r#"{"sub":"user","tier":"premium","exp":999}"#);
+        let tampered = format!("{header}.{tampered}.{signature}");
+        assert!(matches!(authorizer.verify(&tampered, 0), Err(AuthError::InvalidSignature)));
+
+        let none = format!("{}.{}.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#), "e30");
+        assert!(matches!(authorizer.verify(&none, 0), Err(AuthError::UnsupportedAlgorithm(_))));
+        assert!(matches!(authorizer.verify("not-a-token", 0), Err(AuthError::Malformed)));
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_default_tier() {
+        let payload: AuthPayload = serde_json::from_str("{}").unwrap();
+        let claims = HmacAuthorizer::new("secret").authorize(&payload).await.unwrap();
+        assert_eq!(claims, ClientClaims { tier: Tier::Anonymous, user_id: None });
+
+        let payload: AuthPayload = serde_json::from_str(r#"{"api_key": "key"}"#).unwrap();
+        assert!(HmacAuthorizer::new("secret").authorize(&payload).await.is_err());
+        assert_eq!(AnonymousAuthorizer.authorize(&payload).await.unwrap(), ClientClaims::default());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_authorization() {
+        let hmac = HmacAuthorizer::new("secret");
+        let authorizer =
+            ApiKeyAuthorizer::parse(" k1=premium, k2=free", Arc::new(hmac.clone())).unwrap();
+        let payload = |json: &str| serde_json::from_str::<AuthPayload>(json).unwrap();
+
+        let claims = authorizer.authorize(&payload(r#"{"api_key": "k1"}"#)).await.unwrap();
+        assert_eq!(claims.tier, Tier::Premium);
+        let user_id = claims.user_id.unwrap();
+        assert!(user_id.starts_with("api_key:") && !user_id.contains("k1"));
+        let claims = authorizer.authorize(&payload(r#"{"apiKey": "k2"}"#)).await.unwrap();
+        assert_eq!(claims.tier, Tier::Free);
+
+        // the signed tokens and the anonymous clients fall back to the token authorizer
+        let token = hmac.sign(&claims(Tier::Premium, u64::MAX));
+        let json = serde_json::json!({ "token": token }).to_string();
+        assert_eq!(authorizer.authorize(&payload(&json)).await.unwrap().tier, Tier::Premium);
+        assert_eq!(authorizer.authorize(&payload("{}")).await.unwrap(), ClientClaims::default());
+        assert!(authorizer.authorize(&payload(r#"{"api_key": "k3"}"#)).await.is_err());
+
+        assert!(ApiKeyAuthorizer::parse("k1", Arc::new(AnonymousAuthorizer)).is_err());
+        assert!(ApiKeyAuthorizer::parse("k1=gold", Arc::new(AnonymousAuthorizer)).is_err());
+    }
+
+    #[test]
+    fn test_room_policy_gates_prefixes() {
+        let policy =
+            RoomPolicy::parse("pair:=premium, new_pools=free,new_pools:pump=premium").unwrap();
+        assert_eq!(policy.required_tier("pair:abc"), Tier::Premium);
+        assert_eq!(policy.required_tier("new_pools"), Tier::Free);
+        assert_eq!(policy.required_tier("new_pools:pump_amm"), Tier::Premium);
+        assert_eq!(policy.required_tier("mint"), Tier::Anonymous);
+        assert!(RoomPolicy::parse("pair:=gold").is_err());
+        assert_eq!(RoomPolicy::parse("").unwrap(), RoomPolicy::default());
+
+        let rooms = || vec!["mint".to_string(), "new_pools".to_string(), "pair:abc".to_string()];
+        let (allowed, error) = policy.authorize(&ClientClaims::default(), rooms());
+        assert_eq!(allowed, vec!["mint"]);
+        let error = error.unwrap();
+        assert_eq!(error.error, "unauthorized");
+        assert_eq!(
+            error.denied,
+            vec![
+                DeniedRoom { room: "new_pools".to_string(), required_tier: Tier::Free },
+                DeniedRoom { room: "pair:abc".to_string(), required_tier: Tier::Premium },
+            ]
+        );
+
+        let premium = ClientClaims { tier: Tier::Premium, user_id: Some("user".to_string()) };
+        assert_eq!(policy.authorize(&premium, rooms()), (rooms(), None));
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{AuthError, AuthPayload, ClientClaims, WsAuth};
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, SocketRef, State, TryData},
+};
+use tracing::warn;
+
+/// Connect middleware validating the socket.io auth payload and attaching the claims
+/// of the client to the socket extensions, a client without credentials is anonymous
+pub async fn authenticate<A: Adapter>(
+    socket: SocketRef<A>,
+    TryData(payload): TryData<AuthPayload>,
+    State(auth): State<WsAuth>,
+) -> Result<(), AuthError> {
+    let claims = auth.authorizer.authorize(&payload.unwrap_or_default()).await.inspect_err(
+        |e| warn!(ns = socket.ns(), ?socket.id, "Rejected websocket connection: {e}"),
+    )?;
+    socket.extensions.insert(claims);
+    Ok(())
+}
+
+/// authorize_rooms returns the rooms the claims may join, and acks an error listing the
+/// denied ones
+pub fn authorize_rooms<A: Adapter>(
+    socket: &SocketRef<A>,
+    claims: &ClientClaims,
+    auth: &WsAuth,
+    rooms: Vec<String>,
+    ack: AckSender<A>,
+) -> Vec<String> {
+    let (rooms, error) = auth.policy.authorize(claims, rooms);
+    if let Some(error) = error {
+        warn!(?socket.id, ?error, "Denied joining rooms");
+        // the client may not have requested an ack
+        let _ = ack.send(&error);
+    }
+    rooms
+}
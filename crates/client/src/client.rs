@@ -0,0 +1,188 @@
+use crate::{
+    error::ClientError,
+    query::{PairCandlesticksQuery, TopTokensQuery, TradesQuery},
+};
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use sonar_models::{
+    candlesticks::{Candlestick, CandlestickInterval, LatestCandlestick},
+    swap::Trade,
+    tokens::{TokenPrice, TokenSearchResult, TokenStat, TopTokensPage},
+};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// The header carrying the api key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// How the idempotent GET requests are retried
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// The number of attempts of a request, 1 disables the retries
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled on every further retry
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { max_attempts: 3, initial_backoff: Duration::from_millis(200) }
+    }
+}
+
+/// A client of the sonar REST API
+#[derive(Debug, Clone)]
+pub struct SonarClient {
+    http: reqwest::Client,
+    base_url: Url,
+    api_key: Option<String>,
+    retry_config: RetryConfig,
+}
+
+impl SonarClient {
+    /// Create a client of the API served at `base_url`, sending `api_key` with every request
+    pub fn new(base_url: &str, api_key: Option<String>) -> Result<Self, ClientError> {
+        // a trailing slash keeps the path of the base url when joining the routes
+        let base_url = format!("{}/", base_url.trim_end_matches('/'));
+        let base_url = Url::parse(&base_url)
+            .map_err(|e| ClientError::InvalidUrl(format!("{base_url}: {e}")))?;
+        Ok(Self {
+            http: reqwest::Client::new(),
+            base_url,
+            api_key: api_key.filter(|key| !key.is_empty()),
+            retry_config: RetryConfig::default(),
+        })
+    }
+
+    /// set how the GET requests are retried
+    pub fn with_retry_config(mut self, retry_config: RetryConfig) -> Self {
+        self.retry_config = retry_config;
+        self
+    }
+
+    /// set the http client, e.g. to configure timeouts or a proxy
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// top_tokens returns a page of the tokens ranked by the query, volume by default,
+    /// with the number of tokens matching the filters
+    pub async fn top_tokens(&self, query: &TopTokensQuery) -> Result<TopTokensPage, ClientError> {
+        self.get("top-tokens", &query.params()).await
+    }
+
+    /// candlesticks_by_pair returns the candlesticks of a pair
+    pub async fn candlesticks_by_pair(
+        &self,
+        query: &PairCandlesticksQuery,
+    ) -> Result<Vec<Candlestick>, ClientError> {
+        self.get("pair-ohlcv", &query.params()).await
+    }
+
+    /// latest_candlestick returns the candlesticks of the current and previous buckets of a pair
+    pub async fn latest_candlestick(
+        &self,
+        pair: &str,
+        interval: CandlestickInterval,
+    ) -> Result<LatestCandlestick, ClientError> {
+        let params = vec![("pair", pair.to_string()), ("interval", interval.to_string())];
+        self.get("candlestick-latest", &params).await
+    }
+
+    /// trades returns the latest trades matching the query, newest first
+    pub async fn trades(&self, query: &TradesQuery) -> Result<Vec<Trade>, ClientError> {
+        self.get("trades", &query.params()).await
+    }
+
+    /// price returns the price of a token at `timestamp`, now if None
+    pub async fn price(
+        &self,
+        token: &str,
+        timestamp: Option<i32>,
+    ) -> Result<TokenPrice, ClientError> {
+        let mut params = vec![("token", token.to_string())];
+        if let Some(timestamp) = timestamp {
+            params.push(("timestamp", timestamp.to_string()));
+        }
+        self.get("price", &params).await
+    }
+
+    /// token_stats returns the price, volume and turnover windows of the tokens
+    pub async fn token_stats(
+        &self,
+        tokens: &[&str],
+        exclude_wash: bool,
+    ) -> Result<Vec<TokenStat>, ClientError> {
+        let params = vec![("tokens", tokens.join(",")), ("exclude_wash", exclude_wash.to_string())];
+        self.get("token-stats", &params).await
+    }
+
+    /// search returns the tokens matching a mint, symbol or name, best match first
+    pub async fn search(&self, query: &str) -> Result<Vec<TokenSearchResult>, ClientError> {
+        self.get("search", &[("s", query.to_string())]).await
+    }
+
+    /// get sends a GET request, retrying with backoff while the error is retryable
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, ClientError> {
+        let url = self
+            .base_url
+            .join(path)
+            .map_err(|e| ClientError::InvalidUrl(format!("{path}: {e}")))?;
+        let mut backoff = self.retry_config.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.send_get(url.clone(), params).await {
+                Err(e) if e.is_retryable() && attempt < self.retry_config.max_attempts => {
+                    warn!(error = %e, attempt, %url, "Request failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_get<T: DeserializeOwned>(
+        &self,
+        url: Url,
+        params: &[(&str, String)],
+    ) -> Result<T, ClientError> {
+        debug!(%url, ?params, "GET");
+        let mut request = self.http.get(url).query(params);
+        if let Some(api_key) = &self.api_key {
+            request = request.header(API_KEY_HEADER, api_key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(ClientError::from_response(status, &body));
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let client = SonarClient::new("https://api.example.com/sonar", None).unwrap();
+        assert_eq!(client.base_url().join("trades").unwrap().path(), "/sonar/trades");
+        let client = SonarClient::new("https://api.example.com/", Some(String::new())).unwrap();
+        assert_eq!(client.base_url().join("trades").unwrap().path(), "/trades");
+        assert!(client.api_key.is_none());
+        assert!(matches!(SonarClient::new("not a url", None), Err(ClientError::InvalidUrl(_))));
+    }
+}
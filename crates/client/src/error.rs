@@ -0,0 +1,118 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+/// The JSON body of an API error response
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ApiErrorBody {
+    /// The machine-readable error code, e.g. `invalid_parameter`
+    pub code: String,
+    pub message: String,
+    /// The `x-request-id` of the request, to be quoted when reporting the error
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("invalid base url: {0}")]
+    InvalidUrl(String),
+
+    /// The request didn't get a response, e.g. the connection was refused or timed out
+    #[error("request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// A 4xx response, retrying the same request won't help except for a 429
+    #[error("client error {status}: {}", message(.body))]
+    Client { status: StatusCode, body: Option<ApiErrorBody> },
+
+    /// A 5xx response
+    #[error("server error {status}: {}", message(.body))]
+    Server { status: StatusCode, body: Option<ApiErrorBody> },
+
+    /// A response that is neither a success nor an error, e.g. a redirect
+    #[error("unexpected status {0}")]
+    UnexpectedStatus(StatusCode),
+
+    #[error("failed to decode response: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[cfg(feature = "ws")]
+    #[error("socket.io error: {0}")]
+    Socket(#[from] rust_socketio::Error),
+}
+
+fn message(body: &Option<ApiErrorBody>) -> &str {
+    body.as_ref().map_or("no error body", |body| body.message.as_str())
+}
+
+impl ClientError {
+    /// from_response classifies an unsuccessful response by its status class
+    pub fn from_response(status: StatusCode, body: &str) -> Self {
+        let body = serde_json::from_str::<ApiErrorBody>(body).ok();
+        if status.is_client_error() {
+            ClientError::Client { status, body }
+        } else if status.is_server_error() {
+            ClientError::Server { status, body }
+        } else {
+            ClientError::UnexpectedStatus(status)
+        }
+    }
+
+    /// status returns the status of the response, None if there was no response
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ClientError::Client { status, .. } | ClientError::Server { status, .. } => {
+                Some(*status)
+            }
+            ClientError::UnexpectedStatus(status) => Some(*status),
+            ClientError::Transport(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// api_error returns the error body returned by the API
+    pub fn api_error(&self) -> Option<&ApiErrorBody> {
+        match self {
+            ClientError::Client { body, .. } | ClientError::Server { body, .. } => body.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// is_retryable returns true if the request may succeed when retried
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            ClientError::Server { .. } => true,
+            ClientError::Client { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_response() {
+        let body = r#"{"code":"invalid_parameter","message":"invalid parameter `tokens`","request_id":"42"}"#;
+        let error = ClientError::from_response(StatusCode::BAD_REQUEST, body);
+        assert!(matches!(error, ClientError::Client { .. }));
+        assert_eq!(error.api_error().unwrap().code, "invalid_parameter");
+        assert_eq!(error.api_error().unwrap().request_id.as_deref(), Some("42"));
+        assert!(!error.is_retryable());
+
+        let error = ClientError::from_response(StatusCode::TOO_MANY_REQUESTS, "");
+        assert!(error.api_error().is_none());
+        assert!(error.is_retryable());
+
+        let error = ClientError::from_response(StatusCode::SERVICE_UNAVAILABLE, "bad gateway");
+        assert!(matches!(error, ClientError::Server { .. }));
+        assert_eq!(error.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(error.is_retryable());
+
+        let error = ClientError::from_response(StatusCode::MOVED_PERMANENTLY, "");
+        assert!(matches!(error, ClientError::UnexpectedStatus(_)));
+        assert!(!error.is_retryable());
+    }
+}
@@ -0,0 +1,30 @@
+//! A typed client for the sonar REST API and its socket.io rooms
+//!
+//! The responses are the models of `sonar_models`, re-exported in [`models`].
+pub mod client;
+pub mod error;
+pub mod query;
+#[cfg(feature = "ws")]
+pub mod ws;
+
+pub use {
+    client::{RetryConfig, SonarClient, API_KEY_HEADER},
+    error::{ApiErrorBody, ClientError},
+    query::{PairCandlesticksQuery, TopTokensQuery, TradesQuery},
+};
+
+#[cfg(feature = "ws")]
+pub use ws::{NewPool, PoolToken, SocketEvent, SonarSubscriber, TradeSnapshot};
+
+/// The models returned by the API
+pub mod models {
+    pub use sonar_models::{
+        candlesticks::{Candlestick, CandlestickInterval},
+        events::{CandlesClosedEvent, NewPoolEvent},
+        swap::Trade,
+        tokens::{
+            MatchReason, SortOrder, TokenPrice, TokenSearchResult, TokenStat, TopToken,
+            TopTokensPage, TopTokensSort,
+        },
+    };
+}
@@ -0,0 +1,148 @@
+use sonar_models::{
+    candlesticks::CandlestickInterval,
+    tokens::{SortOrder, TopTokensSort},
+};
+
+/// The query string of a request, the unset parameters are left out
+type Params = Vec<(&'static str, String)>;
+
+fn push<T: ToString>(params: &mut Params, name: &'static str, value: Option<T>) {
+    if let Some(value) = value {
+        params.push((name, value.to_string()));
+    }
+}
+
+/// The parameters of `GET /top-tokens`
+#[derive(Debug, Clone, Default)]
+pub struct TopTokensQuery {
+    /// At most 500
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub min_volume: Option<f64>,
+    pub min_market_cap: Option<f64>,
+    /// The window of the ranking in seconds, 24h by default
+    pub timeframe: Option<u64>,
+    pub pumpfun: Option<bool>,
+    pub exclude_wash: Option<bool>,
+    /// The column the tokens are ranked by, volume by default
+    pub sort_by: Option<TopTokensSort>,
+    /// Descending by default
+    pub order: Option<SortOrder>,
+}
+
+impl TopTokensQuery {
+    pub(crate) fn params(&self) -> Params {
+        let mut params = vec![];
+        push(&mut params, "limit", self.limit);
+        push(&mut params, "offset", self.offset);
+        push(&mut params, "min_volume", self.min_volume);
+        push(&mut params, "min_market_cap", self.min_market_cap);
+        push(&mut params, "timeframe", self.timeframe);
+        push(&mut params, "pumpfun", self.pumpfun);
+        push(&mut params, "exclude_wash", self.exclude_wash);
+        push(&mut params, "sort_by", self.sort_by);
+        push(&mut params, "order", self.order);
+        params
+    }
+}
+
+/// The parameters of `GET /pair-ohlcv`
+#[derive(Debug, Clone)]
+pub struct PairCandlesticksQuery {
+    pub pair: String,
+    /// The token the candlesticks are priced in, the base token of the pair by default
+    pub token: Option<String>,
+    pub interval: CandlestickInterval,
+    pub limit: Option<usize>,
+    pub time_from: Option<i32>,
+    pub time_to: Option<i32>,
+}
+
+impl PairCandlesticksQuery {
+    pub fn new(pair: impl Into<String>, interval: CandlestickInterval) -> Self {
+        Self {
+            pair: pair.into(),
+            token: None,
+            interval,
+            limit: None,
+            time_from: None,
+            time_to: None,
+        }
+    }
+
+    pub(crate) fn params(&self) -> Params {
+        let mut params = vec![("pair", self.pair.clone()), ("interval", self.interval.to_string())];
+        push(&mut params, "token", self.token.as_ref());
+        push(&mut params, "limit", self.limit);
+        push(&mut params, "time_from", self.time_from);
+        push(&mut params, "time_to", self.time_to);
+        params
+    }
+}
+
+/// The parameters of `GET /trades`
+#[derive(Debug, Clone, Default)]
+pub struct TradesQuery {
+    /// The wallet of the trader
+    pub address: Option<String>,
+    pub token: Option<String>,
+    pub pair: Option<String>,
+    pub signature: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl TradesQuery {
+    pub(crate) fn params(&self) -> Params {
+        let mut params = vec![];
+        push(&mut params, "address", self.address.as_ref());
+        push(&mut params, "token", self.token.as_ref());
+        push(&mut params, "pair", self.pair.as_ref());
+        push(&mut params, "signature", self.signature.as_ref());
+        push(&mut params, "limit", self.limit);
+        push(&mut params, "offset", self.offset);
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params() {
+        let query = TopTokensQuery { limit: Some(5), pumpfun: Some(true), ..Default::default() };
+        assert_eq!(
+            query.params(),
+            vec![("limit", "5".to_string()), ("pumpfun", "true".to_string())]
+        );
+
+        let query = TopTokensQuery {
+            offset: Some(20),
+            sort_by: Some(TopTokensSort::PriceChange),
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        };
+        assert_eq!(
+            query.params(),
+            vec![
+                ("offset", "20".to_string()),
+                ("sort_by", "price_change".to_string()),
+                ("order", "asc".to_string()),
+            ]
+        );
+
+        let query = PairCandlesticksQuery {
+            limit: Some(100),
+            ..PairCandlesticksQuery::new("pair", CandlestickInterval::OneMinute)
+        };
+        assert_eq!(
+            query.params(),
+            vec![
+                ("pair", "pair".to_string()),
+                ("interval", "1m".to_string()),
+                ("limit", "100".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Subscriptions to the socket.io rooms of the API
+use crate::error::ClientError;
+use futures_util::FutureExt;
+use rust_socketio::{
+    asynchronous::{Client, ClientBuilder},
+    Payload,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use sonar_models::{
+    events::{CandlesClosedEvent, NewPoolEvent},
+    swap::{Trade, TradeCursor},
+};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::warn;
+
+/// The events of the rooms, named as emitted by the API
+const TRADE_CREATED_EVENT: &str = "tradeCreated";
+const NEW_POOL_EVENT: &str = "new_pool";
+const TRADE_SNAPSHOT_EVENT: &str = "trade_snapshot";
+const CANDLES_CLOSED_EVENT: &str = "candles_closed";
+const TOKEN_TRADE_REQUEST: &str = "tokenTrade";
+const SUBSCRIBE_NEW_POOLS_REQUEST: &str = "subscribe_new_pools";
+const SUBSCRIBE_CANDLES_CLOSED_REQUEST: &str = "subscribe_candles_closed";
+/// The number of events buffered before the socket waits for the receiver
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+/// The metadata of a pool token, when the API has it cached
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PoolToken {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub uri: String,
+}
+
+/// A new pool listing
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewPool {
+    #[serde(flatten)]
+    pub pool: NewPoolEvent,
+    #[serde(default)]
+    pub token_a: Option<PoolToken>,
+    #[serde(default)]
+    pub token_b: Option<PoolToken>,
+}
+
+/// The recent trades of a token, oldest first, received once its room is joined, so the
+/// live trades received around it may also be in it
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradeSnapshot {
+    pub token: String,
+    pub trades: Vec<Trade>,
+    /// The newest trade of the snapshot, None when the token has no trades
+    pub cursor: Option<TradeCursor>,
+}
+
+impl TradeSnapshot {
+    /// contains returns true if the snapshot holds a trade of the same signature
+    pub fn contains(&self, trade: &Trade) -> bool {
+        self.trades.iter().any(|snapshot_trade| snapshot_trade.signature == trade.signature)
+    }
+}
+
+/// An event received from a joined room
+#[derive(Debug, Clone)]
+pub enum SocketEvent {
+    Trade(Trade),
+    TradeSnapshot(TradeSnapshot),
+    NewPool(NewPool),
+    CandlesClosed(CandlesClosedEvent),
+}
+
+/// A socket.io connection to the API, the events of the joined rooms are sent to the
+/// receiver returned by [`SonarSubscriber::connect`]
+pub struct SonarSubscriber {
+    client: Client,
+}
+
+impl SonarSubscriber {
+    /// Connect to the socket.io server of the API served at `base_url`
+    pub async fn connect(base_url: &str) -> Result<(Self, Receiver<SocketEvent>), ClientError> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+        let trade_sender = sender.clone();
+        let snapshot_sender = sender.clone();
+        let candles_sender = sender.clone();
+        let client = ClientBuilder::new(base_url.trim_end_matches('/'))
+            .on(TRADE_CREATED_EVENT, move |payload, _| {
+                forward(payload, trade_sender.clone(), SocketEvent::Trade).boxed()
+            })
+            .on(TRADE_SNAPSHOT_EVENT, move |payload, _| {
+                forward(payload, snapshot_sender.clone(), SocketEvent::TradeSnapshot).boxed()
+            })
+            .on(NEW_POOL_EVENT, move |payload, _| {
+                forward(payload, sender.clone(), SocketEvent::NewPool).boxed()
+            })
+            .on(CANDLES_CLOSED_EVENT, move |payload, _| {
+                forward(payload, candles_sender.clone(), SocketEvent::CandlesClosed).boxed()
+            })
+            .connect()
+            .await?;
+        Ok((Self { client }, receiver))
+    }
+
+    /// subscribe_trades joins the trade rooms of the tokens
+    pub async fn subscribe_trades(&self, tokens: &[&str]) -> Result<(), ClientError> {
+        self.client.emit(TOKEN_TRADE_REQUEST, json!({ "tokens": tokens })).await?;
+        Ok(())
+    }
+
+    /// subscribe_trades_with_snapshot joins the trade rooms of the tokens, each followed by
+    /// a snapshot of its last `snapshot` trades, at most 100. The live trades received around
+    /// a snapshot may be in it, see [`TradeSnapshot::contains`]
+    pub async fn subscribe_trades_with_snapshot(
+        &self,
+        tokens: &[&str],
+        snapshot: usize,
+    ) -> Result<(), ClientError> {
+        let request = json!({ "tokens": tokens, "snapshot": snapshot });
+        self.client.emit(TOKEN_TRADE_REQUEST, request).await?;
+        Ok(())
+    }
+
+    /// subscribe_new_pools joins the new pool rooms of the dexes, every dex if empty
+    pub async fn subscribe_new_pools(&self, dexes: &[&str]) -> Result<(), ClientError> {
+        self.client.emit(SUBSCRIBE_NEW_POOLS_REQUEST, json!({ "dexes": dexes })).await?;
+        Ok(())
+    }
+
+    /// subscribe_candles_closed joins the room of the candlestick buckets closed by the
+    /// scheduler, a cue to refetch the candlesticks of the listed pairs
+    pub async fn subscribe_candles_closed(&self) -> Result<(), ClientError> {
+        self.client.emit(SUBSCRIBE_CANDLES_CLOSED_REQUEST, json!({})).await?;
+        Ok(())
+    }
+
+    pub async fn disconnect(self) -> Result<(), ClientError> {
+        self.client.disconnect().await?;
+        Ok(())
+    }
+}
+
+/// Deserializes the arguments of an event and sends them to the receiver
+async fn forward<T: DeserializeOwned>(
+    payload: Payload,
+    sender: Sender<SocketEvent>,
+    event: fn(T) -> SocketEvent,
+) {
+    let Payload::Text(values) = payload else {
+        warn!("Ignoring a non-text socket.io payload");
+        return;
+    };
+    for value in values {
+        match serde_json::from_value::<T>(value) {
+            Ok(value) => {
+                if sender.send(event(value)).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => warn!("Failed to decode a socket.io event: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_forward_candles_closed() {
+        let (sender, mut receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+        let payload = Payload::Text(vec![
+            json!({ "interval": "1m", "bucket_start": 60, "bucket_end": 120, "pairs": ["pair"] }),
+            json!({ "interval": 60 }),
+        ]);
+        forward(payload, sender, SocketEvent::CandlesClosed).await;
+
+        let Some(SocketEvent::CandlesClosed(event)) = receiver.recv().await else {
+            panic!("Expected a candles closed event");
+        };
+        assert_eq!(event.interval, "1m");
+        assert_eq!((event.bucket_start, event.bucket_end), (60, 120));
+        assert_eq!(event.pairs, Some(vec!["pair".to_string()]));
+        // the undecodable event is skipped
+        assert!(receiver.recv().await.is_none());
+    }
+}
@@ -0,0 +1,137 @@
+//! The client against the API router, backed by the in-memory database
+use axum::{http::StatusCode, routing::get, Router};
+use sonar_api::{build_router, AdminAuth, AppState};
+use sonar_client::{
+    models::CandlestickInterval, ClientError, PairCandlesticksQuery, RetryConfig, SonarClient,
+    TopTokensQuery, TradesQuery,
+};
+use sonar_db::{Database, DatabaseTrait, KvStore, MemoryDb, OutlierPolicy, SwapEvent};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::TcpListener;
+
+const TOKEN: &str = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+const PAIR: &str = "Hs97TCZeuYiJxooo3U73qEHXg3dKpRL4uYKYRryEK9CF";
+
+fn make_swap_event(signature: &str, timestamp: u64, price: f64) -> SwapEvent {
+    SwapEvent {
+        market_cap: 0.0,
+        ..sonar_db::test_utils::make_swap_event(TOKEN, PAIR, signature, timestamp, price)
+    }
+}
+
+/// serve binds the router on a free port, returns its base url
+async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{addr}")
+}
+
+/// serve_api serves the API router on a database holding two swaps of `TOKEN`
+async fn serve_api() -> SonarClient {
+    let db = MemoryDb::default();
+    db.insert_swap_event(&make_swap_event("first", 1_700_000_000, 0.00002)).await.unwrap();
+    db.insert_swap_event(&make_swap_event("second", 1_700_000_060, 0.00003)).await.unwrap();
+    let db: Database = Box::new(db);
+    let state = AppState {
+        db: Arc::new(db),
+        kv_store: Arc::new(KvStore::in_memory()),
+        price_max_staleness_secs: 60,
+        outlier_policy: OutlierPolicy::default(),
+    };
+    let base_url = serve(build_router(state, AdminAuth::default())).await;
+    SonarClient::new(&base_url, Some("key".to_string())).unwrap()
+}
+
+#[tokio::test]
+async fn client_trades_returns_newest_first() {
+    let client = serve_api().await;
+    let query = TradesQuery { token: Some(TOKEN.to_string()), ..Default::default() };
+    let trades = client.trades(&query).await.unwrap();
+    let signatures = trades.iter().map(|trade| trade.signature.as_str()).collect::<Vec<_>>();
+    assert_eq!(signatures, vec!["second", "first"]);
+
+    let query = TradesQuery { pair: Some("unknown".to_string()), ..Default::default() };
+    assert!(client.trades(&query).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn client_price_returns_db_price() {
+    let client = serve_api().await;
+    let price = client.price(TOKEN, Some(1_700_000_030)).await.unwrap();
+    assert_eq!(price.price, Some(0.00002));
+    assert_eq!(price.neatest_timestamp, Some(1_700_000_000));
+
+    let price = client.price(PAIR, Some(1_700_000_030)).await.unwrap();
+    assert_eq!(price.price, None);
+}
+
+#[tokio::test]
+async fn client_empty_results_decode() {
+    let client = serve_api().await;
+    let query = TopTokensQuery { limit: Some(5), ..Default::default() };
+    let page = client.top_tokens(&query).await.unwrap();
+    assert!(page.tokens.is_empty());
+    assert_eq!(page.total, 0);
+    let query = PairCandlesticksQuery::new(PAIR, CandlestickInterval::OneMinute);
+    assert!(client.candlesticks_by_pair(&query).await.unwrap().is_empty());
+    // the swaps are long past, nothing traded in the current or previous minute
+    let latest = client.latest_candlestick(PAIR, CandlestickInterval::OneMinute).await.unwrap();
+    assert!(latest.current.is_none() && latest.previous.is_none());
+    assert!(client.token_stats(&[TOKEN], false).await.unwrap().is_empty());
+    assert!(client.search("bonk").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn client_invalid_parameter_is_a_client_error() {
+    let client = serve_api().await;
+    let error = client.search("").await.unwrap_err();
+    assert!(matches!(error, ClientError::Client { status: StatusCode::BAD_REQUEST, .. }));
+    let body = error.api_error().expect("Expected an error body");
+    assert_eq!(body.code, "invalid_parameter");
+    assert!(body.request_id.is_some());
+    assert!(!error.is_retryable());
+
+    let query = TopTokensQuery { limit: Some(501), ..Default::default() };
+    let error = client.top_tokens(&query).await.unwrap_err();
+    assert_eq!(error.api_error().expect("Expected an error body").code, "invalid_parameter");
+}
+
+#[tokio::test]
+async fn client_get_retries_server_errors() {
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counter = attempts.clone();
+    let router = Router::new().route(
+        "/search",
+        get(move || {
+            let counter = counter.clone();
+            async move {
+                // fails twice before answering
+                if counter.fetch_add(1, Ordering::Relaxed) < 2 {
+                    (StatusCode::SERVICE_UNAVAILABLE, "unavailable".to_string())
+                } else {
+                    (StatusCode::OK, "[]".to_string())
+                }
+            }
+        }),
+    );
+    let base_url = serve(router).await;
+    let retry_config = RetryConfig { max_attempts: 3, initial_backoff: Duration::from_millis(1) };
+    let client = SonarClient::new(&base_url, None).unwrap().with_retry_config(retry_config);
+    assert!(client.search("bonk").await.unwrap().is_empty());
+    assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+    // the last error is returned once the attempts are exhausted
+    attempts.store(0, Ordering::Relaxed);
+    let retry_config = RetryConfig { max_attempts: 2, initial_backoff: Duration::from_millis(1) };
+    let client = client.with_retry_config(retry_config);
+    let error = client.search("bonk").await.unwrap_err();
+    assert!(matches!(error, ClientError::Server { status: StatusCode::SERVICE_UNAVAILABLE, .. }));
+    assert_eq!(attempts.load(Ordering::Relaxed), 2);
+}
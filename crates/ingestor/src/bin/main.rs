@@ -1,13 +1,18 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use sonar_db::{make_db_from_env, make_kv_store_from_env, make_message_queue_from_env};
-use sonar_ingestor::prelude::{
-    build_pipeline, make_block_crawler_datasource, make_geyser_datasource,
-    make_helius_ws_datasource, make_transaction_crawler_datasource, make_ws_datasource,
+use sonar_ingestor::{
+    drain_and_close,
+    prelude::{
+        build_pipeline, make_block_crawler_datasource, make_geyser_datasource,
+        make_helius_ws_datasource, make_transaction_crawler_datasources, make_ws_datasource,
+        run_replay, CrawlProgress, ReplayArgs, TransactionCrawlerArgs, TransactionCrawlerConfig,
+    },
+    shutdown_signal, Config, Storages,
 };
 use sonar_sol_price::SolPriceCache;
 use std::sync::Arc;
+use tokio_util::task::TaskTracker;
 use tracing::{error, info};
 use tracing_otel_extra::init_logging;
 
@@ -16,6 +21,9 @@ use tracing_otel_extra::init_logging;
 pub struct Args {
     #[clap(subcommand)]
     command: Commands,
+    /// Process the swaps but log them instead of writing them to ClickHouse and Redis
+    #[clap(long, global = true)]
+    dry_run: bool,
 }
 
 /// Work seamlessly with sonar from the command line.
@@ -23,6 +31,18 @@ pub struct Args {
 /// See `sonar --help` for more information.
 #[derive(Debug, Subcommand)]
 pub enum Commands {
+    #[command(flatten)]
+    Node(NodeCommand),
+    #[command(
+        name = "replay",
+        about = "Replay the transactions of a file and report their outcome"
+    )]
+    Replay(ReplayArgs),
+}
+
+/// The commands running the node, each with its datasource
+#[derive(Debug, Subcommand)]
+pub enum NodeCommand {
     #[command(name = "helius-ws", about = "Start node with helius websocket datasource")]
     HeliusWs,
     #[command(name = "geyser", about = "Start node with geyser datasource")]
@@ -30,7 +50,7 @@ pub enum Commands {
     #[command(name = "block", about = "Start node with block crawler datasource")]
     Block,
     #[command(name = "transaction", about = "Start node with transaction crawler datasource")]
-    Transaction,
+    Transaction(TransactionCrawlerArgs),
     #[command(name = "ws", about = "Start node with ws datasource")]
     Ws,
 }
@@ -49,46 +69,98 @@ async fn main() -> Result<()> {
     let _guard = init_logging(name).expect("Failed to initialize logging");
 
     let opt = Args::from_env_and_args();
-    let db = make_db_from_env().await?;
-    info!("db connected");
-    let kv_store = make_kv_store_from_env().await?;
-    info!("kv connected");
-    let message_queue = make_message_queue_from_env().await?;
-    info!("message queue connected");
-
-    let db = Arc::new(db);
-    let kv_store = Arc::new(kv_store);
-    let message_queue = Arc::new(message_queue);
+    let command = match opt.command {
+        Commands::Node(command) => command,
+        // the replay writes to in-memory storages, like a dry run, and prints a report per
+        // transaction
+        Commands::Replay(args) => {
+            let config = Config::from_env(true)?;
+            let reports = run_replay(&args, &config).await?;
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+            return Ok(());
+        }
+    };
+    let config = Config::from_env(opt.dry_run)?;
+    let Storages { db, kv_store, message_queue } =
+        Storages::connect(config.storages.as_ref()).await?;
+    // the crawls of the transaction command end, the other datasources run until shutdown
+    let crawl_progress = Arc::new(CrawlProgress::default());
+    let is_crawl = matches!(command, NodeCommand::Transaction(_));
+    // the swap tasks are drained on shutdown, before the db is closed
+    let tasks = TaskTracker::new();
 
-    let mut pipeline = match opt.command {
-        Commands::HeliusWs => {
+    let mut pipeline = match command {
+        NodeCommand::HeliusWs => {
             info!("Starting helius websocket pipeline...");
-            let datasource = make_helius_ws_datasource();
-            build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+            let datasource = make_helius_ws_datasource()?;
+            build_pipeline(
+                vec![datasource],
+                db.clone(),
+                kv_store.clone(),
+                message_queue.clone(),
+                tasks.clone(),
+                &config,
+            )?
         }
-        Commands::Geyser => {
+        NodeCommand::Geyser => {
             info!("Starting geyser pipeline...");
-            let datasource = make_geyser_datasource();
-            build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+            let datasource = make_geyser_datasource()?;
+            build_pipeline(
+                vec![datasource],
+                db.clone(),
+                kv_store.clone(),
+                message_queue.clone(),
+                tasks.clone(),
+                &config,
+            )?
         }
-        Commands::Block => {
+        NodeCommand::Block => {
             info!("Starting block pipeline...");
-            let datasource = make_block_crawler_datasource();
-            build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+            let datasource = make_block_crawler_datasource()?;
+            build_pipeline(
+                vec![datasource],
+                db.clone(),
+                kv_store.clone(),
+                message_queue.clone(),
+                tasks.clone(),
+                &config,
+            )?
         }
-        Commands::Transaction => {
-            info!("Starting transaction pipeline...");
-            let datasource = make_transaction_crawler_datasource();
-            build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+        NodeCommand::Transaction(args) => {
+            let crawler_config = TransactionCrawlerConfig::from_args(&args)?;
+            info!(addresses = crawler_config.addresses.len(), "Starting transaction pipeline...");
+            let datasources =
+                make_transaction_crawler_datasources(&crawler_config, crawl_progress.clone())?;
+            build_pipeline(
+                datasources,
+                db.clone(),
+                kv_store.clone(),
+                message_queue.clone(),
+                tasks.clone(),
+                &config,
+            )?
         }
-        Commands::Ws => {
+        NodeCommand::Ws => {
             info!("Starting ws pipeline...");
-            let datasource = make_ws_datasource();
-            build_pipeline(datasource, db, kv_store.clone(), message_queue.clone())?
+            let datasource = make_ws_datasource()?;
+            build_pipeline(
+                vec![datasource],
+                db.clone(),
+                kv_store.clone(),
+                message_queue.clone(),
+                tasks.clone(),
+                &config,
+            )?
         }
     };
 
-    let price_cache = SolPriceCache::new(Some(kv_store.clone()), Some(message_queue.clone()));
+    let mut price_cache =
+        SolPriceCache::new_with_snapshot(Some(kv_store.clone()), Some(message_queue.clone()))
+            .await?;
+    // the SOLUSD candlesticks are built from the persisted trades
+    if config.persist_sol_price_trades {
+        price_cache = price_cache.with_db(db.clone());
+    }
     let price_cache = Arc::new(price_cache);
 
     // Initialize the price cache
@@ -101,6 +173,18 @@ async fn main() -> Result<()> {
         }
     });
 
-    pipeline.run().await?;
-    Ok(())
+    let result: Result<()> = tokio::select! {
+        result = pipeline.run() => result.map_err(Into::into),
+        _ = crawl_progress.finished(), if is_crawl => {
+            info!("Completed every transaction crawl");
+            Ok(())
+        }
+        _ = shutdown_signal() => {
+            info!("Received shutdown signal at {:?}", chrono::Utc::now());
+            Ok(())
+        }
+    };
+
+    drain_and_close(&tasks, &db, config.db_close_timeout).await;
+    result
 }
@@ -0,0 +1,173 @@
+//! The settings of the ingestor, read and validated once at startup
+use crate::{
+    handler::{SwapFilterConfig, DEFAULT_TOKEN_CACHE_CAPACITY, DEFAULT_TOKEN_CACHE_TTL},
+    storages::StoragesConfig,
+};
+use sonar_db::{ConfigError, EnvReader, DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE};
+use std::time::Duration;
+
+/// How long the buffered swap events and tokens may take to be committed on shutdown when unset
+pub const DEFAULT_DB_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_SWAP_PROCESS_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_LARGE_TRADE_PCT: f64 = 0.005;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The storages the swaps are written to, None in a dry run
+    pub storages: Option<StoragesConfig>,
+    /// The capacity of the channel between the datasources and the pipeline
+    pub pipeline_channel_buffer_size: usize,
+    /// The thresholds below which swaps are skipped
+    pub swap_filter: SwapFilterConfig,
+    /// The time budget of processing a swap
+    pub swap_process_timeout: Duration,
+    /// The share of the token supply from which a trade is also published as a large trade
+    pub large_trade_pct: f64,
+    /// Whether the WSOL/stable swaps feed the SOL price, off by default so that the Binance
+    /// price isn't overwritten by a single pool
+    pub onchain_sol_price: bool,
+    /// Whether the Binance SOL price trades are also written to the db, once per second,
+    /// for the SOLUSD candlesticks
+    pub persist_sol_price_trades: bool,
+    /// The address serving the skipped swaps and the counters, no debug server when unset
+    pub debug_http_addr: Option<String>,
+    /// Whether an rpc node is configured to measure the slot lag against
+    pub slot_lag_monitor: bool,
+    /// How long the buffered swap events and tokens may take to be committed on shutdown
+    pub db_close_timeout: Duration,
+    /// How many tokens the process caches in front of the KV store, none when 0
+    pub token_cache_capacity: usize,
+    /// How long a cached token is served before it is looked up again
+    pub token_cache_ttl: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            storages: None,
+            pipeline_channel_buffer_size: DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE,
+            swap_filter: SwapFilterConfig::default(),
+            swap_process_timeout: DEFAULT_SWAP_PROCESS_TIMEOUT,
+            large_trade_pct: DEFAULT_LARGE_TRADE_PCT,
+            onchain_sol_price: false,
+            persist_sol_price_trades: false,
+            debug_http_addr: None,
+            slot_lag_monitor: false,
+            db_close_timeout: DEFAULT_DB_CLOSE_TIMEOUT,
+            token_cache_capacity: DEFAULT_TOKEN_CACHE_CAPACITY,
+            token_cache_ttl: DEFAULT_TOKEN_CACHE_TTL,
+        }
+    }
+}
+
+impl Config {
+    /// from_env reads the database and the redis settings unless in a dry run,
+    /// `PIPELINE_CHANNEL_BUFFER_SIZE`, the swap filter thresholds,
+    /// `SWAP_PROCESS_TIMEOUT_SECS`, `LARGE_TRADE_PCT`, `ONCHAIN_SOL_PRICE`,
+    /// `PERSIST_SOL_PRICE_TRADES`, `DEBUG_HTTP_ADDR`, `RPC_URLS`/`RPC_URL`,
+    /// `DB_CLOSE_TIMEOUT_SECS`, `TOKEN_CACHE_CAPACITY` and `TOKEN_CACHE_TTL_SECS`,
+    /// listing every invalid one
+    pub fn from_env(dry_run: bool) -> Result<Self, ConfigError> {
+        Self::from_reader(EnvReader::from_env(), dry_run)
+    }
+
+    pub fn from_reader(mut env: EnvReader, dry_run: bool) -> Result<Self, ConfigError> {
+        let storages = (!dry_run).then(|| StoragesConfig::read_env(&mut env));
+        let pipeline_channel_buffer_size = env.pipeline_channel_buffer_size();
+        let large_trade_pct = env.parse_or("LARGE_TRADE_PCT", DEFAULT_LARGE_TRADE_PCT);
+        env.check(
+            (0.0..=1.0).contains(&large_trade_pct),
+            format!("LARGE_TRADE_PCT must be between 0 and 1, got {large_trade_pct}"),
+        );
+        let config = Self {
+            storages,
+            pipeline_channel_buffer_size,
+            swap_filter: SwapFilterConfig::read_env(&mut env),
+            swap_process_timeout: env
+                .secs("SWAP_PROCESS_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_SWAP_PROCESS_TIMEOUT),
+            large_trade_pct,
+            onchain_sol_price: env.flag_or("ONCHAIN_SOL_PRICE", false),
+            persist_sol_price_trades: env.flag_or("PERSIST_SOL_PRICE_TRADES", false),
+            debug_http_addr: env.var("DEBUG_HTTP_ADDR"),
+            slot_lag_monitor: env.var("RPC_URLS").is_some() || env.var("RPC_URL").is_some(),
+            db_close_timeout: env.secs("DB_CLOSE_TIMEOUT_SECS").unwrap_or(DEFAULT_DB_CLOSE_TIMEOUT),
+            token_cache_capacity: env
+                .parse_or("TOKEN_CACHE_CAPACITY", DEFAULT_TOKEN_CACHE_CAPACITY),
+            token_cache_ttl: env.secs("TOKEN_CACHE_TTL_SECS").unwrap_or(DEFAULT_TOKEN_CACHE_TTL),
+        };
+        env.finish(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::QuotePairPolicy;
+    use sonar_db::DatabaseConfig;
+
+    #[test]
+    fn test_default_config() {
+        assert_eq!(
+            Config::from_reader(EnvReader::from_vars(&[]), true).unwrap(),
+            Config::default()
+        );
+
+        let vars = [
+            ("PIPELINE_CHANNEL_BUFFER_SIZE", "500"),
+            ("MIN_SWAP_USD", "1"),
+            ("QUOTE_PAIR_SWAPS", "flag"),
+            ("ONCHAIN_SOL_PRICE", "true"),
+            ("PERSIST_SOL_PRICE_TRADES", "true"),
+            ("RPC_URL", "http://localhost:8899"),
+            ("DB_CLOSE_TIMEOUT_SECS", "3"),
+            ("TOKEN_CACHE_CAPACITY", "0"),
+            ("TOKEN_CACHE_TTL_SECS", "60"),
+            ("WASH_TRADE_WINDOW_SECS", "10"),
+        ];
+        let config = Config::from_reader(EnvReader::from_vars(&vars), true).unwrap();
+        assert_eq!(config.storages, None);
+        assert_eq!(config.pipeline_channel_buffer_size, 500);
+        assert_eq!(config.swap_filter.min_swap_usd, 1.0);
+        assert_eq!(config.swap_filter.quote_pair_policy, QuotePairPolicy::Flag);
+        assert!(config.onchain_sol_price);
+        assert!(config.persist_sol_price_trades);
+        assert!(config.slot_lag_monitor);
+        assert_eq!(config.db_close_timeout, Duration::from_secs(3));
+        assert_eq!(config.token_cache_capacity, 0);
+        assert_eq!(config.token_cache_ttl, Duration::from_secs(60));
+        assert_eq!(config.swap_filter.wash_trade_window, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_storages_config() {
+        let vars =
+            [("CLICKHOUSE_URL", "http://localhost:8123"), ("REDIS_URL", "redis://localhost:6379")];
+        let storages =
+            Config::from_reader(EnvReader::from_vars(&vars), false).unwrap().storages.unwrap();
+        assert!(matches!(
+            storages.database,
+            DatabaseConfig::Clickhouse(ref clickhouse) if clickhouse.database_url == vars[0].1
+        ));
+        assert_eq!(storages.redis.url, "redis://localhost:6379");
+
+        let error = Config::from_reader(EnvReader::from_vars(&[]), false).unwrap_err();
+        assert_eq!(error.problems, vec!["CLICKHOUSE_URL must be set", "REDIS_URL must be set"]);
+    }
+
+    #[test]
+    fn test_every_problem_is_listed() {
+        let vars = [
+            ("PIPELINE_CHANNEL_BUFFER_SIZE", "0"),
+            ("LARGE_TRADE_PCT", "5"),
+            ("MIN_SWAP_UI_AMOUNT", "-1"),
+            ("MIN_SWAP_USD", "cheap"),
+            ("QUOTE_PAIR_SWAPS", "keep"),
+            ("SWAP_PROCESS_TIMEOUT_SECS", "0"),
+            ("PAIR_SEEN_TTL_SECS", "0"),
+        ];
+        let error = Config::from_reader(EnvReader::from_vars(&vars), true).unwrap_err();
+        assert_eq!(error.problems.len(), 7, "{error}");
+        assert!(error.to_string().contains("QUOTE_PAIR_SWAPS must be skip or flag"));
+    }
+}
@@ -1,7 +1,8 @@
-use serde::{Deserialize, Serialize};
 use solana_pubkey::{pubkey, Pubkey};
 use std::{collections::HashSet, sync::LazyLock};
-use strum::Display;
+
+/// The dexes are shared with the storage, the API and the streams
+pub use sonar_db::{Dexes, PUMP_LAUNCHPAD};
 
 pub const WSOL_MINT_KEY: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
 pub const USDC_MINT_KEY: Pubkey = pubkey!("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
@@ -17,6 +18,16 @@ pub const RAYDIUM_AMM_V4_PROGRAM_ID: Pubkey =
 
 pub const RAYDIUM_CLMM_PROGRAM_ID: Pubkey = pubkey!("CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK");
 
+pub const RAYDIUM_CPMM_PROGRAM_ID: Pubkey = pubkey!("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C");
+
+pub const RAYDIUM_LAUNCHPAD_PROGRAM_ID: Pubkey =
+    pubkey!("LanMV9sAd7wArD4vJFi2qDdfnVhFxYSUg6eADduJ3uj");
+
+pub const METEORA_POOLS_PROGRAM_ID: Pubkey =
+    pubkey!("Eo7WjKq67rjJQSZxS6z3YkapzY3eMj6Xy8X5EQVn5UaB");
+
+pub const PUMP_AMM_PROGRAM_ID: Pubkey = pubkey!("pAMMBay6oceH9fJKBRHGP5D4bD4sWpmSwMn52FMfXEA");
+
 pub const WHIRLPOOLS_PROGRAM_ID: Pubkey = pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
 pub const WHIRLPOOLS_PROGRAM_ID_STR: &str = "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc";
 
@@ -29,6 +40,21 @@ pub const WSOL_MARKET_ID_STR: &str = "8sLbNZoA1cfnvMJLPfp98ZLAnFSYCFApfJKMbiXNLw
 // hardcoded program ids
 pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
 pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const SYSTEM_PROGRAM_ID: Pubkey = pubkey!("11111111111111111111111111111111");
+pub const SYSTEM_PROGRAM_ID_STR: &str = "11111111111111111111111111111111";
+pub const VOTE_PROGRAM_ID: Pubkey = pubkey!("Vote111111111111111111111111111111111111111");
+
+/// The programs of the swap decoders registered in the pipeline
+pub const DEX_PROGRAM_IDS: [Pubkey; 8] = [
+    RAYDIUM_AMM_V4_PROGRAM_ID,
+    RAYDIUM_CLMM_PROGRAM_ID,
+    RAYDIUM_CPMM_PROGRAM_ID,
+    RAYDIUM_LAUNCHPAD_PROGRAM_ID,
+    METEORA_DLMM_PROGRAM_ID,
+    METEORA_POOLS_PROGRAM_ID,
+    WHIRLPOOLS_PROGRAM_ID,
+    PUMP_AMM_PROGRAM_ID,
+];
 
 /// A set of USD-denominated mints
 pub static USDT_SET: LazyLock<HashSet<String>> = LazyLock::new(|| {
@@ -38,16 +64,20 @@ pub static USDT_SET: LazyLock<HashSet<String>> = LazyLock::new(|| {
     ])
 });
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display)]
-#[serde(rename_all = "snake_case")]
-#[strum(serialize_all = "snake_case")]
-pub enum Dexes {
-    MeteoraDlmm,
-    MeteoraPools,
-    OcraWhirlpool,
-    PumpAmm,
-    RaydiumAmmV4,
-    RaydiumClmm,
-    RaydiumCpmm,
-    RaydiumLaunchpad,
+/// The pump.fun account signing the migrations of the completed bonding curves to an AMM pool
+pub const PUMP_MIGRATION_AUTHORITY: Pubkey =
+    pubkey!("39azUYFWPz3VHgKCf3VChUwbpURdCHRxjWVowf5jUJjg");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dex_program_ids() {
+        for program_id in DEX_PROGRAM_IDS {
+            let dex = Dexes::from_program_id(&program_id).expect("Expected a dex");
+            assert_eq!(dex.program_id(), program_id);
+        }
+        assert_eq!(Dexes::OcraWhirlpool.program_id(), WHIRLPOOLS_PROGRAM_ID);
+    }
 }
@@ -1,3 +1,8 @@
+use super::{
+    filter::{FilteredDatasource, TransactionFilter},
+    rpc::primary_rpc_url,
+};
+use anyhow::{Context, Result};
 use carbon_rpc_block_crawler_datasource::{RpcBlockConfig, RpcBlockCrawler};
 use solana_commitment_config::CommitmentConfig;
 use solana_transaction_status::UiTransactionEncoding;
@@ -7,41 +12,53 @@ use std::{env::var, time::Duration};
 ///
 /// # Arguments
 ///
-/// * `rpc_url` - The URL of the RPC node
+/// * `rpc_url` - The first URL of `RPC_URLS`
 /// * `start_slot` - The start slot of the block crawler
 /// * `end_slot` - The end slot of the block crawler
 /// * `block_interval` - The interval of the block crawler
-/// * `max_concurrent_requests` - The maximum number of concurrent requests of the block crawler
-pub fn make_block_crawler_datasource() -> RpcBlockCrawler {
-    let rpc_url = var("RPC_URL").expect("RPC_URL is not set");
+/// * `max_concurrent_requests` - The maximum number of concurrent block fetches, defaults to 10
+/// * `commitment` - The commitment level of the fetched blocks, defaults to processed
+///
+/// Vote transactions and transactions not touching the swap programs are dropped
+/// before they enter the pipeline, see [`TransactionFilter::from_env`]
+pub fn make_block_crawler_datasource() -> Result<FilteredDatasource<RpcBlockCrawler>> {
+    let rpc_url = primary_rpc_url()?;
     let start_slot = var("RPC_START_SLOT")
-        .expect("RPC_START_SLOT is not set")
+        .context("RPC_START_SLOT is not set")?
         .parse::<u64>()
-        .expect("RPC_START_SLOT is not a valid number");
+        .context("RPC_START_SLOT is not a valid number")?;
     let end_slot = var("RPC_END_SLOT")
         .ok()
-        .map(|s| s.parse::<u64>().expect("RPC_END_SLOT is not a valid number"));
+        .map(|s| s.parse::<u64>().context("RPC_END_SLOT is not a valid number"))
+        .transpose()?;
     let max_concurrent_requests = var("RPC_MAX_CONCURRENT_REQUESTS")
-        .expect("RPC_MAX_CONCURRENT_REQUESTS is not set")
-        .parse::<usize>()
-        .expect("RPC_MAX_CONCURRENT_REQUESTS is not a valid number");
+        .ok()
+        .map(|s| s.parse::<usize>().context("RPC_MAX_CONCURRENT_REQUESTS is not a valid number"))
+        .transpose()?
+        .unwrap_or(10);
+    let commitment = var("RPC_BLOCK_COMMITMENT")
+        .ok()
+        .map(|s| parse_commitment(&s).context("RPC_BLOCK_COMMITMENT is not a valid commitment"))
+        .transpose()?
+        .unwrap_or_else(CommitmentConfig::processed);
     let block_interval = var("RPC_BLOCK_INTERVAL")
-        .expect("RPC_BLOCK_INTERVAL is not set")
+        .context("RPC_BLOCK_INTERVAL is not set")?
         .parse::<u64>()
-        .expect("RPC_BLOCK_INTERVAL is not a valid number");
+        .context("RPC_BLOCK_INTERVAL is not a valid number")?;
     let channel_buffer_size = var("RPC_CHANNEL_BUFFER_SIZE")
         .ok()
-        .map(|s| s.parse::<usize>().expect("RPC_CHANNEL_BUFFER_SIZE is not a valid number"));
+        .map(|s| s.parse::<usize>().context("RPC_CHANNEL_BUFFER_SIZE is not a valid number"))
+        .transpose()?;
 
     let block_config = RpcBlockConfig {
         rewards: Some(false),
         encoding: Some(UiTransactionEncoding::Binary),
         max_supported_transaction_version: Some(0),
-        commitment: Some(CommitmentConfig::processed()),
+        commitment: Some(commitment),
         ..Default::default()
     };
 
-    RpcBlockCrawler::new(
+    let crawler = RpcBlockCrawler::new(
         rpc_url,
         start_slot,
         end_slot,
@@ -49,5 +66,16 @@ pub fn make_block_crawler_datasource() -> RpcBlockCrawler {
         block_config,
         Some(max_concurrent_requests),
         channel_buffer_size,
-    )
+    );
+    Ok(FilteredDatasource::new(crawler, TransactionFilter::from_env()))
+}
+
+/// Parses a commitment level, returns None for unknown levels
+fn parse_commitment(commitment: &str) -> Option<CommitmentConfig> {
+    match commitment.trim().to_lowercase().as_str() {
+        "processed" => Some(CommitmentConfig::processed()),
+        "confirmed" => Some(CommitmentConfig::confirmed()),
+        "finalized" => Some(CommitmentConfig::finalized()),
+        _ => None,
+    }
 }
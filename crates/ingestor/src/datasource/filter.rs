@@ -0,0 +1,224 @@
+use crate::constants::{DEX_PROGRAM_IDS, VOTE_PROGRAM_ID};
+use async_trait::async_trait;
+use carbon_core::{
+    datasource::{Datasource, DatasourceId, TransactionUpdate, Update, UpdateType},
+    error::CarbonResult,
+    metrics::MetricsCollection,
+};
+use solana_pubkey::Pubkey;
+use std::{collections::HashSet, env::var, str::FromStr, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// The carbon counter of the transactions dropped by the filter
+pub const FILTERED_TRANSACTIONS_METRIC: &str = "datasource_filtered_transactions";
+
+const FILTER_CHANNEL_SIZE: usize = 10_000;
+
+/// Decides which transaction updates of a datasource reach the pipeline
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    /// drop vote transactions
+    pub exclude_votes: bool,
+    /// only forward transactions touching one of these programs, forward all if None
+    pub program_allowlist: Option<HashSet<Pubkey>>,
+}
+
+impl TransactionFilter {
+    /// Reads the filter from the environment
+    ///
+    /// * `DATASOURCE_EXCLUDE_VOTES` - drop vote transactions, defaults to true
+    /// * `DATASOURCE_PROGRAM_ALLOWLIST` - comma separated program ids, defaults to the programs
+    ///   of the swap decoders, `*` forwards every transaction
+    pub fn from_env() -> Self {
+        let exclude_votes = var("DATASOURCE_EXCLUDE_VOTES").as_deref() != Ok("false");
+        let program_allowlist = match var("DATASOURCE_PROGRAM_ALLOWLIST") {
+            Ok(allowlist) if allowlist.trim() == "*" => None,
+            Ok(allowlist) => Some(parse_program_allowlist(&allowlist)),
+            Err(_) => Some(HashSet::from(DEX_PROGRAM_IDS)),
+        };
+        Self { exclude_votes, program_allowlist }
+    }
+
+    /// is_allowed returns true if the transaction should be forwarded to the pipeline
+    pub fn is_allowed(&self, transaction: &TransactionUpdate) -> bool {
+        let static_keys = transaction.transaction.message.static_account_keys();
+        if self.exclude_votes && (transaction.is_vote || static_keys.contains(&VOTE_PROGRAM_ID)) {
+            return false;
+        }
+        let Some(allowlist) = &self.program_allowlist else {
+            return true;
+        };
+        // programs of v0 transactions may come from address lookup tables
+        let loaded_addresses = &transaction.meta.loaded_addresses;
+        static_keys
+            .iter()
+            .chain(loaded_addresses.writable.iter())
+            .chain(loaded_addresses.readonly.iter())
+            .any(|key| allowlist.contains(key))
+    }
+}
+
+/// Parses comma separated program ids, skipping invalid entries
+fn parse_program_allowlist(allowlist: &str) -> HashSet<Pubkey> {
+    allowlist
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match Pubkey::from_str(s) {
+            Ok(pubkey) => Some(pubkey),
+            Err(e) => {
+                warn!("Ignoring invalid program id {} in allowlist: {}", s, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// A datasource wrapper that drops the transactions rejected by a [`TransactionFilter`]
+/// before they enter the pipeline
+pub struct FilteredDatasource<DS> {
+    inner: DS,
+    filter: Arc<TransactionFilter>,
+}
+
+impl<DS> FilteredDatasource<DS> {
+    pub fn new(inner: DS, filter: TransactionFilter) -> Self {
+        Self { inner, filter: Arc::new(filter) }
+    }
+}
+
+#[async_trait]
+impl<DS> Datasource for FilteredDatasource<DS>
+where
+    DS: Datasource + Send + Sync,
+{
+    async fn consume(
+        &self,
+        id: DatasourceId,
+        sender: mpsc::Sender<(Update, DatasourceId)>,
+        cancellation_token: CancellationToken,
+        metrics: Arc<MetricsCollection>,
+    ) -> CarbonResult<()> {
+        let (inner_sender, mut receiver) = mpsc::channel(FILTER_CHANNEL_SIZE);
+        let filter = self.filter.clone();
+        let filter_metrics = metrics.clone();
+
+        tokio::spawn(async move {
+            while let Some((update, id)) = receiver.recv().await {
+                if let Update::Transaction(transaction) = &update {
+                    if !filter.is_allowed(transaction) {
+                        let _ =
+                            filter_metrics.increment_counter(FILTERED_TRANSACTIONS_METRIC, 1).await;
+                        continue;
+                    }
+                }
+                if sender.send((update, id)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.inner.consume(id, inner_sender, cancellation_token, metrics).await
+    }
+
+    fn update_types(&self) -> Vec<UpdateType> {
+        self.inner.update_types()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{RAYDIUM_CLMM_PROGRAM_ID, TOKEN_PROGRAM_ID};
+    use solana_message::{
+        v0::{self, LoadedAddresses},
+        VersionedMessage,
+    };
+    use solana_signature::Signature;
+    use solana_transaction::versioned::VersionedTransaction;
+    use solana_transaction_status::TransactionStatusMeta;
+
+    fn make_transaction_update(
+        account_keys: Vec<Pubkey>,
+        loaded_addresses: LoadedAddresses,
+        is_vote: bool,
+    ) -> TransactionUpdate {
+        TransactionUpdate {
+            signature: Signature::default(),
+            transaction: VersionedTransaction {
+                signatures: vec![Signature::default()],
+                message: VersionedMessage::V0(v0::Message { account_keys, ..Default::default() }),
+            },
+            meta: TransactionStatusMeta { loaded_addresses, ..Default::default() },
+            is_vote,
+            slot: 0,
+            block_time: None,
+            block_hash: None,
+        }
+    }
+
+    fn dex_filter() -> TransactionFilter {
+        TransactionFilter {
+            exclude_votes: true,
+            program_allowlist: Some(HashSet::from(DEX_PROGRAM_IDS)),
+        }
+    }
+
+    #[test]
+    fn test_allows_static_program_key() {
+        let tx = make_transaction_update(
+            vec![Pubkey::new_unique(), RAYDIUM_CLMM_PROGRAM_ID, TOKEN_PROGRAM_ID],
+            LoadedAddresses::default(),
+            false,
+        );
+        assert!(dex_filter().is_allowed(&tx));
+    }
+
+    #[test]
+    fn test_allows_loaded_program_key() {
+        let loaded = LoadedAddresses { writable: vec![], readonly: vec![RAYDIUM_CLMM_PROGRAM_ID] };
+        let tx =
+            make_transaction_update(vec![Pubkey::new_unique(), TOKEN_PROGRAM_ID], loaded, false);
+        assert!(dex_filter().is_allowed(&tx));
+    }
+
+    #[test]
+    fn test_rejects_unrelated_programs() {
+        let loaded = LoadedAddresses {
+            writable: vec![Pubkey::new_unique()],
+            readonly: vec![TOKEN_PROGRAM_ID],
+        };
+        let tx = make_transaction_update(vec![Pubkey::new_unique()], loaded, false);
+        assert!(!dex_filter().is_allowed(&tx));
+        assert!(TransactionFilter::default().is_allowed(&tx));
+    }
+
+    #[test]
+    fn test_rejects_votes() {
+        let tx = make_transaction_update(
+            vec![Pubkey::new_unique(), VOTE_PROGRAM_ID],
+            LoadedAddresses::default(),
+            false,
+        );
+        assert!(!dex_filter().is_allowed(&tx));
+        let tx = make_transaction_update(
+            vec![RAYDIUM_CLMM_PROGRAM_ID],
+            LoadedAddresses::default(),
+            true,
+        );
+        assert!(!dex_filter().is_allowed(&tx));
+        let filter = TransactionFilter { exclude_votes: false, program_allowlist: None };
+        assert!(filter.is_allowed(&tx));
+    }
+
+    #[test]
+    fn test_parse_program_allowlist() {
+        let allowlist = parse_program_allowlist(&format!(
+            " {}, not-a-pubkey,,{}",
+            RAYDIUM_CLMM_PROGRAM_ID, TOKEN_PROGRAM_ID
+        ));
+        assert_eq!(allowlist, HashSet::from([RAYDIUM_CLMM_PROGRAM_ID, TOKEN_PROGRAM_ID]));
+    }
+}
@@ -1,8 +1,11 @@
 use crate::constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR};
+use anyhow::{anyhow, bail, Result};
 use carbon_yellowstone_grpc_datasource::{BlockFilters, YellowstoneGrpcGeyserClient};
+use solana_pubkey::Pubkey;
 use std::{
     collections::{HashMap, HashSet},
     env::var,
+    str::FromStr,
     sync::Arc,
 };
 use tokio::sync::RwLock;
@@ -10,39 +13,210 @@ use yellowstone_grpc_proto::geyser::{
     CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions,
 };
 
-pub fn make_geyser_datasource() -> YellowstoneGrpcGeyserClient {
-    let endpoint = var("GEYSER_URL").expect("GEYSER_URL is not set");
-    let x_token = var("GEYSER_X_TOKEN").ok();
-
-    // Set up transaction filters to swap transactions
-    let mut transaction_filters = HashMap::new();
-    transaction_filters.insert(
-        "swap_transaction_filter".to_string(),
-        SubscribeRequestFilterTransactions {
-            vote: Some(false),
-            failed: Some(false),
-            account_include: vec![
+/// The subscription and channel settings of the geyser datasource
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeyserConfig {
+    pub endpoint: String,
+    pub x_token: Option<String>,
+    /// The transactions touching one of these accounts are streamed
+    pub program_ids: Vec<String>,
+    pub include_failed: bool,
+    pub commitment: CommitmentLevel,
+}
+
+/// The filters and commitment the geyser datasource subscribes with
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeyserSubscription {
+    pub commitment: CommitmentLevel,
+    pub account_filters: HashMap<String, SubscribeRequestFilterAccounts>,
+    pub transaction_filters: HashMap<String, SubscribeRequestFilterTransactions>,
+    pub include_failed: bool,
+}
+
+impl GeyserConfig {
+    /// from_env reads the geyser settings of the env, see `from_lookup`
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|name| var(name).ok().filter(|v| !v.trim().is_empty()))
+    }
+
+    /// from_lookup reads the geyser settings, erroring on the offending variable
+    ///
+    /// * `GEYSER_URL` - the gRPC endpoint, required
+    /// * `GEYSER_X_TOKEN` - the token of the endpoint
+    /// * `GEYSER_PROGRAM_IDS` - comma separated accounts, defaults to the USDC, USDT and WSOL mints
+    /// * `GEYSER_INCLUDE_FAILED` - stream the failed transactions, defaults to false
+    /// * `GEYSER_COMMITMENT` - processed, confirmed or finalized, defaults to processed
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let endpoint = lookup("GEYSER_URL").ok_or_else(|| anyhow!("GEYSER_URL is not set"))?;
+        let program_ids = match lookup("GEYSER_PROGRAM_IDS") {
+            Some(program_ids) => parse_program_ids(&program_ids)?,
+            None => vec![
                 USDC_MINT_KEY_STR.to_string(),
                 USDT_MINT_KEY_STR.to_string(),
                 WSOL_MINT_KEY_STR.to_string(),
             ],
-            account_exclude: vec![],
-            account_required: vec![],
-            signature: None,
-        },
-    );
-    // Create empty account filters since we only care about transactions
-    let account_filters: HashMap<String, SubscribeRequestFilterAccounts> = HashMap::new();
-
-    let block_filters = BlockFilters { filters: HashMap::new(), failed_transactions: Some(false) };
+        };
+        let include_failed = match lookup("GEYSER_INCLUDE_FAILED") {
+            Some(v) => v
+                .parse::<bool>()
+                .map_err(|_| anyhow!("GEYSER_INCLUDE_FAILED must be true or false, got {v:?}"))?,
+            None => false,
+        };
+        let commitment = match lookup("GEYSER_COMMITMENT").as_deref() {
+            Some("processed") | None => CommitmentLevel::Processed,
+            Some("confirmed") => CommitmentLevel::Confirmed,
+            Some("finalized") => CommitmentLevel::Finalized,
+            Some(v) => {
+                bail!("GEYSER_COMMITMENT must be processed, confirmed or finalized, got {v:?}")
+            }
+        };
+        Ok(Self {
+            endpoint,
+            x_token: lookup("GEYSER_X_TOKEN"),
+            program_ids,
+            include_failed,
+            commitment,
+        })
+    }
+
+    /// subscription returns the filters of the swap transactions, without the vote transactions
+    pub fn subscription(&self) -> GeyserSubscription {
+        let mut transaction_filters = HashMap::new();
+        transaction_filters.insert(
+            "swap_transaction_filter".to_string(),
+            SubscribeRequestFilterTransactions {
+                vote: Some(false),
+                failed: Some(self.include_failed),
+                account_include: self.program_ids.clone(),
+                account_exclude: vec![],
+                account_required: vec![],
+                signature: None,
+            },
+        );
+        GeyserSubscription {
+            commitment: self.commitment,
+            // no account filters since we only care about transactions
+            account_filters: HashMap::new(),
+            transaction_filters,
+            include_failed: self.include_failed,
+        }
+    }
+}
+
+/// Parses comma separated accounts, erroring on the first invalid one
+fn parse_program_ids(program_ids: &str) -> Result<Vec<String>> {
+    let program_ids = program_ids
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| match Pubkey::from_str(s) {
+            Ok(_) => Ok(s.to_string()),
+            Err(e) => Err(anyhow!("GEYSER_PROGRAM_IDS has an invalid account {s:?}: {e}")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if program_ids.is_empty() {
+        bail!("GEYSER_PROGRAM_IDS must list at least one account");
+    }
+    Ok(program_ids)
+}
+
+/// Make a geyser datasource subscribing as configured by `GeyserConfig::from_env`
+pub fn make_geyser_datasource() -> Result<YellowstoneGrpcGeyserClient> {
+    let config = GeyserConfig::from_env()?;
+    let subscription = config.subscription();
+    let block_filters = BlockFilters {
+        filters: HashMap::new(),
+        failed_transactions: Some(subscription.include_failed),
+    };
     let account_deletions_tracked = Arc::new(RwLock::new(HashSet::new()));
-    YellowstoneGrpcGeyserClient::new(
-        endpoint,
-        x_token,
-        Some(CommitmentLevel::Processed),
-        account_filters,
-        transaction_filters,
+    Ok(YellowstoneGrpcGeyserClient::new(
+        config.endpoint,
+        config.x_token,
+        Some(subscription.commitment),
+        subscription.account_filters,
+        subscription.transaction_filters,
         block_filters,
         account_deletions_tracked,
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup_in(env: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let env: HashMap<String, String> =
+            env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| env.get(name).cloned()
+    }
+
+    #[test]
+    fn test_geyser_config_defaults() {
+        let config = GeyserConfig::from_lookup(lookup_in(&[("GEYSER_URL", "https://geyser")]))
+            .expect("Failed to read the defaults");
+        assert_eq!(config.endpoint, "https://geyser");
+        assert_eq!(config.x_token, None);
+        assert_eq!(
+            config.program_ids,
+            vec![USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR]
+        );
+        assert!(!config.include_failed);
+        assert_eq!(config.commitment, CommitmentLevel::Processed);
+    }
+
+    #[test]
+    fn test_geyser_config_from_env() {
+        let program_id = "pAMMBay6oceH9fJKBRHGP5D4bD4sWpmSwMn52FMfXEA";
+        let config = GeyserConfig::from_lookup(lookup_in(&[
+            ("GEYSER_URL", "https://geyser"),
+            ("GEYSER_X_TOKEN", "token"),
+            ("GEYSER_PROGRAM_IDS", &format!(" {program_id}, ")),
+            ("GEYSER_INCLUDE_FAILED", "true"),
+            ("GEYSER_COMMITMENT", "confirmed"),
+        ]))
+        .expect("Failed to read the config");
+        assert_eq!(config.x_token.as_deref(), Some("token"));
+        assert_eq!(config.program_ids, vec![program_id]);
+        assert!(config.include_failed);
+        assert_eq!(config.commitment, CommitmentLevel::Confirmed);
+    }
+
+    #[test]
+    fn test_geyser_config_errors_name_the_variable() {
+        for (name, value) in [
+            ("GEYSER_PROGRAM_IDS", "not-a-pubkey"),
+            ("GEYSER_PROGRAM_IDS", " , "),
+            ("GEYSER_INCLUDE_FAILED", "yes"),
+            ("GEYSER_COMMITMENT", "rooted"),
+        ] {
+            let e = GeyserConfig::from_lookup(lookup_in(&[
+                ("GEYSER_URL", "https://geyser"),
+                (name, value),
+            ]))
+            .expect_err(&format!("{name}={value} should be rejected"));
+            assert!(e.to_string().contains(name), "{e} doesn't name {name}");
+        }
+        let e = GeyserConfig::from_lookup(lookup_in(&[])).unwrap_err();
+        assert!(e.to_string().contains("GEYSER_URL"));
+    }
+
+    #[test]
+    fn test_geyser_subscription() {
+        let mut config = GeyserConfig::from_lookup(lookup_in(&[("GEYSER_URL", "https://geyser")]))
+            .expect("Failed to read the defaults");
+        let subscription = config.subscription();
+        assert_eq!(subscription.commitment, CommitmentLevel::Processed);
+        assert!(subscription.account_filters.is_empty());
+        let filter = &subscription.transaction_filters["swap_transaction_filter"];
+        assert_eq!(filter.vote, Some(false));
+        assert_eq!(filter.failed, Some(false));
+        assert_eq!(filter.account_include, config.program_ids);
+
+        config.include_failed = true;
+        config.commitment = CommitmentLevel::Finalized;
+        let subscription = config.subscription();
+        assert_eq!(subscription.commitment, CommitmentLevel::Finalized);
+        assert!(subscription.include_failed);
+        assert_eq!(subscription.transaction_filters["swap_transaction_filter"].failed, Some(true));
+    }
 }
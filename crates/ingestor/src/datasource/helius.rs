@@ -1,4 +1,5 @@
 use crate::constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR};
+use anyhow::{anyhow, Context, Result};
 use carbon_helius_atlas_ws_datasource::{Filters, HeliusWebsocket};
 use helius::types::{
     Cluster, RpcTransactionsConfig, TransactionCommitment, TransactionDetails,
@@ -17,7 +18,7 @@ const HELIUS_TRANSACTION_IDLE_TIMEOUT_SECS: u64 = 10; // 10 seconds
 ///
 /// * `helius_atlas_ws_url` - The URL of the Helius Atlas websocket
 /// * `helius_atlas_api_key` - The API key for the Helius Atlas websocket
-pub fn make_helius_ws_datasource() -> HeliusWebsocket {
+pub fn make_helius_ws_datasource() -> Result<HeliusWebsocket> {
     let transaction_filters = Some(RpcTransactionsConfig {
         filter: TransactionSubscribeFilter {
             account_include: Some(vec![
@@ -40,8 +41,8 @@ pub fn make_helius_ws_datasource() -> HeliusWebsocket {
         },
     });
     let filters = Filters::new(vec![], transaction_filters)
-        .expect("Error creating Filters for the Helius WebSocket");
-    let api_key = var("HELIUS_ATLAS_API_KEY").expect("HELIUS_ATLAS_API_KEY is not set");
+        .map_err(|e| anyhow!("Failed to create the Helius WebSocket filters: {e:?}"))?;
+    let api_key = var("HELIUS_ATLAS_API_KEY").context("HELIUS_ATLAS_API_KEY is not set")?;
     let ping_interval_secs = var("HELIUS_PING_INTERVAL_SECS")
         .map(|v| v.parse::<u64>().unwrap_or(HELIUS_PING_INTERVAL_SECS))
         .unwrap_or(HELIUS_PING_INTERVAL_SECS);
@@ -52,7 +53,7 @@ pub fn make_helius_ws_datasource() -> HeliusWebsocket {
         .map(|v| v.parse::<u64>().unwrap_or(HELIUS_TRANSACTION_IDLE_TIMEOUT_SECS))
         .unwrap_or(HELIUS_TRANSACTION_IDLE_TIMEOUT_SECS);
 
-    HeliusWebsocket::new(
+    Ok(HeliusWebsocket::new(
         api_key,
         Some(ping_interval_secs),
         Some(pong_timeout_secs),
@@ -63,5 +64,5 @@ pub fn make_helius_ws_datasource() -> HeliusWebsocket {
     )
     .with_ping_interval_secs(ping_interval_secs)
     .with_pong_timeout_secs(pong_timeout_secs)
-    .with_transaction_idle_timeout_secs(transaction_idle_timeout_secs)
+    .with_transaction_idle_timeout_secs(transaction_idle_timeout_secs))
 }
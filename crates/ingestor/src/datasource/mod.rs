@@ -1,4 +1,6 @@
 use crate::{
+    config::Config,
+    debug_server::spawn_debug_server,
     metrics::NodeMetrics,
     processor::{
         MeteoraDlmmInstructionProcessor, MeteoraPoolsInstructionProcessor,
@@ -6,6 +8,7 @@ use crate::{
         RaydiumAmmV4InstructionProcessor, RaydiumClmmInstructionProcessor,
         RaydiumCpmmInstructionProcessor, RaydiumLaunchpadInstructionProcessor,
     },
+    slot_lag::{spawn_slot_lag_monitor, SlotLagConfig},
     TokenSwapHandler,
 };
 use anyhow::Result;
@@ -23,38 +26,84 @@ use carbon_raydium_clmm_decoder::RaydiumClmmDecoder;
 use carbon_raydium_cpmm_decoder::RaydiumCpmmDecoder;
 use carbon_raydium_launchpad_decoder::RaydiumLaunchpadDecoder;
 use sonar_db::{Database, KvStore, MessageQueue};
+use sonar_pipeline_metrics::{spawn_throughput_monitor, ThroughputConfig};
+use sonar_sol_price::SolPriceCache;
 use std::sync::Arc;
+use tokio_util::task::TaskTracker;
 
 pub mod block;
+pub mod filter;
 pub mod geyser;
 pub mod helius;
 pub mod rpc;
 pub mod tx;
+pub mod vec;
 pub mod ws;
 
+/// build_pipeline feeds the swaps of every datasource to one pipeline configured by `config`,
+/// the swap tasks are spawned on `tasks`
 pub fn build_pipeline<DS>(
-    datasource: DS,
+    datasources: Vec<DS>,
     db: Arc<Database>,
     kv_store: Arc<KvStore>,
     message_queue: Arc<MessageQueue>,
+    tasks: TaskTracker,
+    config: &Config,
 ) -> Result<Pipeline>
 where
     DS: Datasource + Send + Sync + 'static,
 {
-    let channel_buffer_size = std::env::var("PIPELINE_CHANNEL_BUFFER_SIZE")
-        .unwrap_or_else(|_| "10000".to_string())
-        .parse::<usize>()
-        .unwrap_or(10_000);
     let metrics = Arc::new(NodeMetrics::new());
-    let token_swap_handler = Arc::new(TokenSwapHandler::new(
-        kv_store.clone(),
-        message_queue.clone(),
-        db.clone(),
-        metrics,
-    ));
-    let pipeline: Pipeline = Pipeline::builder()
-        .datasource(datasource)
+    spawn_throughput_monitor("ingestor", metrics.pipeline.clone(), ThroughputConfig::from_env());
+    // the lag is measured against the chain slot of the rpc node
+    if config.slot_lag_monitor {
+        spawn_slot_lag_monitor(
+            metrics.clone(),
+            Some(message_queue.clone()),
+            SlotLagConfig::from_env(),
+        );
+    }
+    let mut token_swap_handler =
+        TokenSwapHandler::new(kv_store.clone(), message_queue.clone(), db.clone(), metrics, config)
+            .with_task_tracker(tasks);
+    // feed the SOL price from the WSOL/USDC and WSOL/USDT swaps when enabled
+    if config.onchain_sol_price {
+        let sol_price_cache =
+            SolPriceCache::new(Some(kv_store.clone()), Some(message_queue.clone()))?;
+        token_swap_handler = token_swap_handler.with_sol_price_cache(Arc::new(sol_price_cache));
+    }
+    // the skipped swaps and the counters are only served when an address is configured
+    if let Some(addr) = &config.debug_http_addr {
+        spawn_debug_server(
+            addr.clone(),
+            token_swap_handler.skipped_swaps.clone(),
+            token_swap_handler.metrics.clone(),
+        );
+    }
+    build_pipeline_with_handler(
+        datasources,
+        Arc::new(token_swap_handler),
+        config.pipeline_channel_buffer_size,
+    )
+}
+
+/// build_pipeline_with_handler feeds the swaps of every datasource to `token_swap_handler`,
+/// without the monitors and the debug server of `build_pipeline`
+pub fn build_pipeline_with_handler<DS>(
+    datasources: Vec<DS>,
+    token_swap_handler: Arc<TokenSwapHandler>,
+    channel_buffer_size: usize,
+) -> Result<Pipeline>
+where
+    DS: Datasource + Send + Sync + 'static,
+{
+    let mut builder = Pipeline::builder();
+    for datasource in datasources {
+        builder = builder.datasource(datasource);
+    }
+    let pipeline: Pipeline = builder
         .metrics(Arc::new(LogMetrics::new()))
+        .metrics(token_swap_handler.metrics.pipeline.clone())
         .shutdown_strategy(ShutdownStrategy::Immediate)
         .channel_buffer_size(channel_buffer_size)
         .instruction(
@@ -89,3 +138,52 @@ where
         .build()?;
     Ok(pipeline)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_swaps::MemoryStorages;
+    use async_trait::async_trait;
+    use carbon_core::{
+        datasource::{DatasourceId, Update, UpdateType},
+        error::CarbonResult,
+        metrics::MetricsCollection,
+    };
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    struct EmptyDatasource;
+
+    #[async_trait]
+    impl Datasource for EmptyDatasource {
+        async fn consume(
+            &self,
+            _id: DatasourceId,
+            _sender: mpsc::Sender<(Update, DatasourceId)>,
+            _cancellation_token: CancellationToken,
+            _metrics: Arc<MetricsCollection>,
+        ) -> CarbonResult<()> {
+            Ok(())
+        }
+
+        fn update_types(&self) -> Vec<UpdateType> {
+            vec![UpdateType::Transaction]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_pipeline_with_multiple_datasources() {
+        let (kv_store, message_queue, db) = MemoryStorages::default().storages();
+        let datasources = vec![EmptyDatasource, EmptyDatasource, EmptyDatasource];
+        let pipeline = build_pipeline(
+            datasources,
+            db,
+            kv_store,
+            message_queue,
+            TaskTracker::new(),
+            &Config::default(),
+        )
+        .unwrap();
+        assert_eq!(pipeline.datasources.len(), 3);
+    }
+}
@@ -1,12 +1,18 @@
-use solana_client::nonblocking::rpc_client::RpcClient;
-use std::env::var;
+use anyhow::Result;
+use sonar_token_metadata::{try_rpc_urls_from_env, RotatingRpcClient};
+use std::sync::Arc;
 
-/// Make a RPC client
+/// Returns the RPC client of the process, shared with the token metadata lookups
 ///
 /// # Arguments
 ///
-/// * `rpc_url` - The URL of the RPC node
-pub fn make_rpc_client() -> RpcClient {
-    let rpc_url = var("RPC_URL").expect("RPC_URL is not set");
-    RpcClient::new(rpc_url)
+/// * `rpc_urls` - The URLs of the RPC nodes, `RPC_URLS` or `RPC_URL`, rotated over with
+///   the rate limited ones quarantined, see [`RotatingRpcClient`]
+pub fn make_rpc_client() -> Arc<RotatingRpcClient> {
+    sonar_token_metadata::make_rpc_client()
+}
+
+/// primary_rpc_url returns the first URL of `RPC_URLS`, for the crawlers which own their client
+pub fn primary_rpc_url() -> Result<String> {
+    Ok(try_rpc_urls_from_env()?.swap_remove(0))
 }
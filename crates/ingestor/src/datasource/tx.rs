@@ -1,81 +1,406 @@
-use crate::constants::RAYDIUM_AMM_V4_PROGRAM_ID;
-use carbon_rpc_block_crawler_datasource::{RpcBlockConfig, RpcBlockCrawler};
+use crate::{constants::RAYDIUM_AMM_V4_PROGRAM_ID, datasource::rpc::primary_rpc_url};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use carbon_core::{
+    datasource::{Datasource, DatasourceId, Update, UpdateType},
+    error::CarbonResult,
+    metrics::MetricsCollection,
+};
 use carbon_rpc_transaction_crawler_datasource::{
     ConnectionConfig, Filters, RetryConfig, RpcTransactionCrawler,
 };
 use solana_commitment_config::CommitmentConfig;
-use solana_transaction_status::UiTransactionEncoding;
-use std::{env::var, time::Duration};
+use solana_pubkey::Pubkey;
+use solana_signature::Signature;
+use std::{
+    env::var,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
 
-/// Make a transaction crawler datasource
-///
-/// # Arguments
-///
-/// * `rpc_url` - The URL of the RPC node
-pub fn make_transaction_crawler_datasource() -> RpcTransactionCrawler {
-    let rpc_url = var("RPC_URL").expect("RPC_URL is not set");
-    let connection_config = ConnectionConfig::new(
-        100,                     // Batch limit
-        Duration::from_secs(1),  // Polling interval
-        5,                       // Max Concurrent Requests
-        RetryConfig::no_retry(), // Retry config
-        None,                    // Max Signature Channel Size
-        None,                    // Max Transaction Channel Size
-        false,                   // Blocking send
-    );
-    let filters = Filters::new(None, None, None);
-    RpcTransactionCrawler::new(
-        rpc_url,
-        RAYDIUM_AMM_V4_PROGRAM_ID,
-        connection_config,
-        filters,
-        Some(CommitmentConfig::confirmed()),
-    )
+const CRAWL_CHANNEL_SIZE: usize = 10_000;
+/// How many transactions of an address are crawled between two progress logs
+const CRAWL_PROGRESS_INTERVAL: usize = 1_000;
+
+/// The flags of the transaction crawler, the unset ones fall back to the environment
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct TransactionCrawlerArgs {
+    /// An account whose transactions are crawled, repeatable
+    #[arg(long = "address")]
+    pub addresses: Vec<String>,
+    /// Only crawl the transactions before this signature
+    #[arg(long)]
+    pub before: Option<String>,
+    /// Stop crawling at this signature
+    #[arg(long)]
+    pub until: Option<String>,
+    /// The most transactions crawled per address
+    #[arg(long)]
+    pub limit: Option<usize>,
+}
+
+/// The settings of the transaction crawler, one crawl per address
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionCrawlerConfig {
+    pub addresses: Vec<Pubkey>,
+    pub before: Option<Signature>,
+    pub until: Option<Signature>,
+    pub limit: Option<usize>,
+}
+
+impl TransactionCrawlerConfig {
+    /// Create a transaction crawler config from the flags, falling back to
+    /// `TX_CRAWLER_ADDRESSES` (comma separated, the Raydium AMM v4 program when unset),
+    /// `TX_CRAWLER_BEFORE`, `TX_CRAWLER_UNTIL` and `TX_CRAWLER_LIMIT`
+    pub fn from_args(args: &TransactionCrawlerArgs) -> Result<Self> {
+        Self::from_args_and_env(args, |key| var(key).ok())
+    }
+
+    fn from_args_and_env(
+        args: &TransactionCrawlerArgs,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let addresses = match args.addresses.is_empty() {
+            false => args.addresses.clone(),
+            true => env("TX_CRAWLER_ADDRESSES")
+                .map(|addresses| addresses.split(',').map(|s| s.trim().to_string()).collect())
+                .unwrap_or_default(),
+        };
+        let mut addresses = addresses
+            .iter()
+            .filter(|address| !address.is_empty())
+            .map(|address| {
+                Pubkey::from_str(address).with_context(|| format!("Invalid address {address}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if addresses.is_empty() {
+            addresses.push(RAYDIUM_AMM_V4_PROGRAM_ID);
+        }
+
+        let signature = |flag: &Option<String>, key: &str| -> Result<Option<Signature>> {
+            flag.clone()
+                .or_else(|| env(key))
+                .map(|s| Signature::from_str(&s).with_context(|| format!("Invalid {key} {s}")))
+                .transpose()
+        };
+        let limit = match args.limit {
+            Some(limit) => Some(limit),
+            None => env("TX_CRAWLER_LIMIT")
+                .map(|s| s.parse::<usize>().context("TX_CRAWLER_LIMIT is not a valid number"))
+                .transpose()?,
+        };
+        Ok(Self {
+            addresses,
+            before: signature(&args.before, "TX_CRAWLER_BEFORE")?,
+            until: signature(&args.until, "TX_CRAWLER_UNTIL")?,
+            limit,
+        })
+    }
+}
+
+/// Counts the crawls still running, so that the ingestor exits once they all completed
+#[derive(Debug, Default)]
+pub struct CrawlProgress {
+    pending: AtomicUsize,
+    done: Notify,
+}
+
+impl CrawlProgress {
+    fn register(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn complete(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.done.notify_waiters();
+        }
+    }
+
+    /// pending returns the number of crawls still running
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// finished resolves once every registered crawl completed
+    pub async fn finished(&self) {
+        loop {
+            let done = self.done.notified();
+            if self.pending() == 0 {
+                return;
+            }
+            done.await;
+        }
+    }
+}
+
+/// A datasource wrapper crawling the transactions of one address, which logs its progress
+/// and completes once `limit` transactions were forwarded or the inner crawl ended
+pub struct CrawlDatasource<DS> {
+    inner: DS,
+    address: Pubkey,
+    limit: Option<usize>,
+    progress: Arc<CrawlProgress>,
 }
 
-/// Make a block crawler datasource
+impl<DS> CrawlDatasource<DS> {
+    pub fn new(
+        inner: DS,
+        address: Pubkey,
+        limit: Option<usize>,
+        progress: Arc<CrawlProgress>,
+    ) -> Self {
+        progress.register();
+        Self { inner, address, limit, progress }
+    }
+}
+
+#[async_trait]
+impl<DS> Datasource for CrawlDatasource<DS>
+where
+    DS: Datasource + Send + Sync,
+{
+    async fn consume(
+        &self,
+        id: DatasourceId,
+        sender: mpsc::Sender<(Update, DatasourceId)>,
+        cancellation_token: CancellationToken,
+        metrics: Arc<MetricsCollection>,
+    ) -> CarbonResult<()> {
+        let (inner_sender, mut receiver) = mpsc::channel(CRAWL_CHANNEL_SIZE);
+        // cancels the inner crawl only, the pipeline keeps running the other crawls
+        let crawl_token = cancellation_token.child_token();
+        let (address, limit, limit_token) = (self.address, self.limit, crawl_token.clone());
+
+        let forward = tokio::spawn(async move {
+            let mut crawled = 0;
+            while let Some((update, id)) = receiver.recv().await {
+                let is_transaction = matches!(update, Update::Transaction(_));
+                if sender.send((update, id)).await.is_err() {
+                    break;
+                }
+                if !is_transaction {
+                    continue;
+                }
+                crawled += 1;
+                if crawled % CRAWL_PROGRESS_INTERVAL == 0 {
+                    info!(%address, crawled, "Crawling transactions");
+                }
+                if limit.is_some_and(|limit| crawled >= limit) {
+                    limit_token.cancel();
+                    break;
+                }
+            }
+            crawled
+        });
+
+        info!(%address, ?limit, "Starting transaction crawl");
+        let result = self.inner.consume(id, inner_sender, crawl_token, metrics).await;
+        let crawled = forward.await.unwrap_or_default();
+        info!(%address, crawled, "Completed transaction crawl");
+        self.progress.complete();
+        result
+    }
+
+    fn update_types(&self) -> Vec<UpdateType> {
+        self.inner.update_types()
+    }
+}
+
+/// Make the transaction crawler datasources, one per address of the config
 ///
 /// # Arguments
 ///
-/// * `rpc_url` - The URL of the RPC node
-/// * `start_slot` - The start slot of the block crawler
-/// * `end_slot` - The end slot of the block crawler
-/// * `block_interval` - The interval of the block crawler
-/// * `max_concurrent_requests` - The maximum number of concurrent requests of the block crawler
-pub fn make_block_crawler_datasource() -> RpcBlockCrawler {
-    let rpc_url = var("RPC_URL").expect("RPC_URL is not set");
-    let start_slot = var("RPC_START_SLOT")
-        .expect("RPC_START_SLOT is not set")
-        .parse::<u64>()
-        .expect("RPC_START_SLOT is not a valid number");
-    let end_slot = var("RPC_END_SLOT")
-        .ok()
-        .map(|s| s.parse::<u64>().expect("RPC_END_SLOT is not a valid number"));
-    let max_concurrent_requests = var("RPC_MAX_CONCURRENT_REQUESTS")
-        .expect("RPC_MAX_CONCURRENT_REQUESTS is not set")
-        .parse::<usize>()
-        .expect("RPC_MAX_CONCURRENT_REQUESTS is not a valid number");
-    let block_interval = var("RPC_BLOCK_INTERVAL")
-        .expect("RPC_BLOCK_INTERVAL is not set")
-        .parse::<u64>()
-        .expect("RPC_BLOCK_INTERVAL is not a valid number");
-    let channel_buffer_size = var("RPC_CHANNEL_BUFFER_SIZE")
-        .ok()
-        .map(|s| s.parse::<usize>().expect("RPC_CHANNEL_BUFFER_SIZE is not a valid number"));
-
-    let block_config = RpcBlockConfig {
-        rewards: Some(false),
-        encoding: Some(UiTransactionEncoding::Binary),
-        max_supported_transaction_version: Some(0),
-        ..Default::default()
-    };
-    RpcBlockCrawler::new(
-        rpc_url,
-        start_slot,
-        end_slot,
-        Some(Duration::from_secs(block_interval)),
-        block_config,
-        Some(max_concurrent_requests),
-        channel_buffer_size,
-    )
+/// * `rpc_url` - The first URL of `RPC_URLS`
+/// * `config` - The addresses and signature bounds to crawl
+/// * `progress` - Completed by each crawl
+pub fn make_transaction_crawler_datasources(
+    config: &TransactionCrawlerConfig,
+    progress: Arc<CrawlProgress>,
+) -> Result<Vec<CrawlDatasource<RpcTransactionCrawler>>> {
+    let rpc_url = primary_rpc_url()?;
+    let datasources = config
+        .addresses
+        .iter()
+        .map(|address| {
+            let connection_config = ConnectionConfig::new(
+                100,                     // Batch limit
+                Duration::from_secs(1),  // Polling interval
+                5,                       // Max Concurrent Requests
+                RetryConfig::no_retry(), // Retry config
+                None,                    // Max Signature Channel Size
+                None,                    // Max Transaction Channel Size
+                false,                   // Blocking send
+            );
+            let filters = Filters::new(None, config.before, config.until);
+            let crawler = RpcTransactionCrawler::new(
+                rpc_url.clone(),
+                *address,
+                connection_config,
+                filters,
+                Some(CommitmentConfig::confirmed()),
+            );
+            CrawlDatasource::new(crawler, *address, config.limit, progress.clone())
+        })
+        .collect();
+    Ok(datasources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use carbon_core::datasource::TransactionUpdate;
+    use solana_transaction::versioned::VersionedTransaction;
+    use solana_transaction_status::TransactionStatusMeta;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn test_config_flags_over_env() {
+        let (env_address, flag_address) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (env_signature, flag_signature) = (Signature::new_unique(), Signature::new_unique());
+        let env = env(&[
+            ("TX_CRAWLER_ADDRESSES", &format!("{env_address}, {}", Pubkey::new_unique())),
+            ("TX_CRAWLER_BEFORE", &env_signature.to_string()),
+            ("TX_CRAWLER_UNTIL", &env_signature.to_string()),
+            ("TX_CRAWLER_LIMIT", "10"),
+        ]);
+
+        let from_env =
+            TransactionCrawlerConfig::from_args_and_env(&TransactionCrawlerArgs::default(), &env)
+                .unwrap();
+        assert_eq!(from_env.addresses.len(), 2);
+        assert_eq!(from_env.addresses[0], env_address);
+        assert_eq!(from_env.before, Some(env_signature));
+        assert_eq!(from_env.limit, Some(10));
+
+        let args = TransactionCrawlerArgs {
+            addresses: vec![flag_address.to_string()],
+            before: Some(flag_signature.to_string()),
+            until: None,
+            limit: Some(5),
+        };
+        let config = TransactionCrawlerConfig::from_args_and_env(&args, &env).unwrap();
+        assert_eq!(
+            config,
+            TransactionCrawlerConfig {
+                addresses: vec![flag_address],
+                before: Some(flag_signature),
+                until: Some(env_signature),
+                limit: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_defaults_and_errors() {
+        let config = TransactionCrawlerConfig::from_args_and_env(
+            &TransactionCrawlerArgs::default(),
+            env(&[]),
+        )
+        .unwrap();
+        assert_eq!(config.addresses, vec![RAYDIUM_AMM_V4_PROGRAM_ID]);
+        assert_eq!((config.before, config.until, config.limit), (None, None, None));
+
+        let args = TransactionCrawlerArgs {
+            addresses: vec!["not-a-pubkey".to_string()],
+            ..Default::default()
+        };
+        assert!(TransactionCrawlerConfig::from_args_and_env(&args, env(&[])).is_err());
+        let env = env(&[("TX_CRAWLER_LIMIT", "many")]);
+        assert!(TransactionCrawlerConfig::from_args_and_env(
+            &TransactionCrawlerArgs::default(),
+            env
+        )
+        .is_err());
+    }
+
+    /// Sends `count` transactions, then waits for the cancellation like a live crawler
+    struct MockCrawler {
+        count: usize,
+    }
+
+    #[async_trait]
+    impl Datasource for MockCrawler {
+        async fn consume(
+            &self,
+            id: DatasourceId,
+            sender: mpsc::Sender<(Update, DatasourceId)>,
+            cancellation_token: CancellationToken,
+            _metrics: Arc<MetricsCollection>,
+        ) -> CarbonResult<()> {
+            for _ in 0..self.count {
+                let update = Update::Transaction(Box::new(TransactionUpdate {
+                    signature: Signature::new_unique(),
+                    transaction: VersionedTransaction::default(),
+                    meta: TransactionStatusMeta::default(),
+                    is_vote: false,
+                    slot: 0,
+                    block_time: None,
+                    block_hash: None,
+                }));
+                if sender.send((update, id.clone())).await.is_err() {
+                    return Ok(());
+                }
+            }
+            cancellation_token.cancelled().await;
+            Ok(())
+        }
+
+        fn update_types(&self) -> Vec<UpdateType> {
+            vec![UpdateType::Transaction]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_crawls_complete_at_limit() {
+        let progress = Arc::new(CrawlProgress::default());
+        let crawls = [
+            CrawlDatasource::new(
+                MockCrawler { count: 5 },
+                Pubkey::new_unique(),
+                Some(3),
+                progress.clone(),
+            ),
+            CrawlDatasource::new(
+                MockCrawler { count: 2 },
+                Pubkey::new_unique(),
+                Some(2),
+                progress.clone(),
+            ),
+        ];
+        assert_eq!(progress.pending(), 2);
+
+        let (sender, mut receiver) = mpsc::channel(100);
+        let token = CancellationToken::new();
+        let metrics = Arc::new(MetricsCollection::new(vec![]));
+        for crawl in crawls {
+            let (sender, token, metrics) = (sender.clone(), token.clone(), metrics.clone());
+            tokio::spawn(async move {
+                crawl.consume(DatasourceId::new_unique(), sender, token, metrics).await
+            });
+        }
+        drop(sender);
+
+        tokio::time::timeout(Duration::from_secs(5), progress.finished()).await.unwrap();
+        assert_eq!(progress.pending(), 0);
+        // the pipeline itself isn't cancelled by the crawls
+        assert!(!token.is_cancelled());
+        let mut forwarded = 0;
+        while receiver.recv().await.is_some() {
+            forwarded += 1;
+        }
+        assert_eq!(forwarded, 5);
+    }
 }
@@ -0,0 +1,82 @@
+//! this file feeds a fixed list of transactions to the pipeline, to replay them through the
+//! processors of the live datasources
+use async_trait::async_trait;
+use carbon_core::{
+    datasource::{Datasource, DatasourceId, TransactionUpdate, Update, UpdateType},
+    error::CarbonResult,
+    metrics::MetricsCollection,
+};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// A datasource sending its transactions once, in order, then completing
+pub struct VecDatasource {
+    updates: Mutex<Vec<TransactionUpdate>>,
+}
+
+impl VecDatasource {
+    pub fn new(updates: Vec<TransactionUpdate>) -> Self {
+        Self { updates: Mutex::new(updates) }
+    }
+}
+
+#[async_trait]
+impl Datasource for VecDatasource {
+    async fn consume(
+        &self,
+        id: DatasourceId,
+        sender: mpsc::Sender<(Update, DatasourceId)>,
+        cancellation_token: CancellationToken,
+        _metrics: Arc<MetricsCollection>,
+    ) -> CarbonResult<()> {
+        let updates = std::mem::take(&mut *self.updates.lock().unwrap());
+        for update in updates {
+            if cancellation_token.is_cancelled() {
+                break;
+            }
+            let update = Update::Transaction(Box::new(update));
+            if sender.send((update, id.clone())).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn update_types(&self) -> Vec<UpdateType> {
+        vec![UpdateType::Transaction]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_swaps::get_transaction_data;
+
+    #[tokio::test]
+    async fn test_vec_datasource_sends_its_updates_once() {
+        let signature = "4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7";
+        let (_, update, _) = get_transaction_data(signature).await.unwrap();
+        let datasource = VecDatasource::new(vec![(*update).clone(), *update]);
+
+        let (sender, mut receiver) = mpsc::channel(10);
+        let metrics = Arc::new(MetricsCollection::new(vec![]));
+        let id = DatasourceId::new_unique();
+        let token = CancellationToken::new();
+        datasource
+            .consume(id.clone(), sender.clone(), token.clone(), metrics.clone())
+            .await
+            .unwrap();
+        // a second consume has nothing left to send
+        datasource.consume(id, sender, token, metrics).await.unwrap();
+
+        let mut signatures = vec![];
+        while let Some((update, _)) = receiver.recv().await {
+            let Update::Transaction(transaction) = update else {
+                panic!("Expected a transaction update");
+            };
+            signatures.push(transaction.signature.to_string());
+        }
+        assert_eq!(signatures, vec![signature, signature]);
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::{Context, Result};
 use carbon_rpc_block_subscribe_datasource::{Filters, RpcBlockSubscribe};
 use solana_client::rpc_config::{RpcBlockSubscribeConfig, RpcBlockSubscribeFilter};
 use std::env::var;
@@ -7,7 +8,7 @@ use std::env::var;
 /// # Arguments
 ///
 /// * `rpc_ws_url` - The URL of the RPC websocket
-pub fn make_ws_datasource() -> RpcBlockSubscribe {
+pub fn make_ws_datasource() -> Result<RpcBlockSubscribe> {
     let filters = Filters::new(
         RpcBlockSubscribeFilter::All,
         Some(RpcBlockSubscribeConfig {
@@ -15,6 +16,6 @@ pub fn make_ws_datasource() -> RpcBlockSubscribe {
             ..RpcBlockSubscribeConfig::default()
         }),
     );
-    let rpc_ws_url = var("RPC_WS_URL").expect("RPC_WS_URL is not set");
-    RpcBlockSubscribe::new(rpc_ws_url, filters)
+    let rpc_ws_url = var("RPC_WS_URL").context("RPC_WS_URL is not set")?;
+    Ok(RpcBlockSubscribe::new(rpc_ws_url, filters))
 }
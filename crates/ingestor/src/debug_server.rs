@@ -0,0 +1,118 @@
+//! this file serves the debug endpoints of the ingestor, e.g. the last skipped swaps
+use crate::{
+    handler::{SkippedSwap, SkippedSwapLog, SkippedSwapQuery},
+    metrics::NodeMetrics,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use std::{fmt::Write, sync::Arc};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+/// debug_router routes `/debug/skipped-swaps?reason=&dex=&limit=`,
+/// `/debug/skipped-swaps/{signature}` and `/debug/metrics`
+pub fn debug_router(skipped_swaps: Arc<SkippedSwapLog>, metrics: Arc<NodeMetrics>) -> Router {
+    let metrics = Router::new().route("/debug/metrics", get(get_metrics)).with_state(metrics);
+    Router::new()
+        .route("/debug/skipped-swaps", get(list_skipped_swaps))
+        .route("/debug/skipped-swaps/{signature}", get(find_skipped_swaps))
+        .with_state(skipped_swaps)
+        .merge(metrics)
+}
+
+/// spawn_debug_server serves the debug router on `addr` until the process exits
+pub fn spawn_debug_server(
+    addr: String,
+    skipped_swaps: Arc<SkippedSwapLog>,
+    metrics: Arc<NodeMetrics>,
+) {
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(error = ?e, %addr, "Failed to bind the debug server");
+                return;
+            }
+        };
+        info!(%addr, "Serving the debug endpoints");
+        if let Err(e) = axum::serve(listener, debug_router(skipped_swaps, metrics)).await {
+            error!(error = ?e, "Debug server stopped");
+        }
+    });
+}
+
+/// list_skipped_swaps returns the last skipped swaps, newest first
+async fn list_skipped_swaps(
+    State(skipped_swaps): State<Arc<SkippedSwapLog>>,
+    Query(query): Query<SkippedSwapQuery>,
+) -> Json<Vec<SkippedSwap>> {
+    Json(skipped_swaps.query(&query))
+}
+
+/// find_skipped_swaps returns the skipped swaps of a transaction, 404 when none was skipped
+async fn find_skipped_swaps(
+    State(skipped_swaps): State<Arc<SkippedSwapLog>>,
+    Path(signature): Path<String>,
+) -> Result<Json<Vec<SkippedSwap>>, StatusCode> {
+    let swaps = skipped_swaps.find(&signature);
+    match swaps.is_empty() {
+        true => Err(StatusCode::NOT_FOUND),
+        false => Ok(Json(swaps)),
+    }
+}
+
+/// get_metrics returns the counters of the node in the prometheus text format
+async fn get_metrics(State(metrics): State<Arc<NodeMetrics>>) -> String {
+    let mut body = String::new();
+    for (name, value) in metrics.counters() {
+        let _ = writeln!(body, "# TYPE sonar_ingestor_{name}_total counter");
+        let _ = writeln!(body, "sonar_ingestor_{name}_total {value}");
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constants::Dexes, handler::token_swap_handler::SwapError};
+
+    #[tokio::test]
+    async fn test_skipped_swaps_endpoints() {
+        let log = Arc::new(SkippedSwapLog::default());
+        for (signature, dex, e) in [
+            ("first", Dexes::PumpAmm, SwapError::TinySwap),
+            ("second", Dexes::MeteoraDlmm, SwapError::InvalidPrice),
+        ] {
+            log.record(SkippedSwap::new(signature, "pool", dex, &e, 1_700_000_000));
+        }
+
+        let query = SkippedSwapQuery { dex: Some(Dexes::MeteoraDlmm), ..Default::default() };
+        let Json(swaps) = list_skipped_swaps(State(log.clone()), Query(query)).await;
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0].reason, "invalid_price");
+
+        let Json(swaps) =
+            find_skipped_swaps(State(log.clone()), Path("first".to_string())).await.unwrap();
+        assert_eq!(swaps[0].dex, Dexes::PumpAmm);
+        let missing = find_skipped_swaps(State(log), Path("unknown".to_string())).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let metrics = Arc::new(NodeMetrics::new());
+        // the first disagreement is logged, the next ones of the interval are only counted
+        assert!(metrics.record_direction_disagreement(1_000_000));
+        assert!(!metrics.record_direction_disagreement(1_001_000));
+        assert!(metrics.record_direction_disagreement(1_010_000));
+
+        let body = get_metrics(State(metrics)).await;
+        assert!(body.contains("# TYPE sonar_ingestor_direction_disagreements_total counter\n"));
+        assert!(body.contains("\nsonar_ingestor_direction_disagreements_total 3\n"));
+        assert!(body.contains("\nsonar_ingestor_swaps_processed_total 0\n"));
+    }
+}
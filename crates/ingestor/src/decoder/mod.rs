@@ -1,6 +1,10 @@
 pub mod spl_token_decoder;
+pub mod system_transfer_decoder;
 pub use spl_token_decoder::{
     extra_mint_details_from_tx_metadata, process_token_2022_transfer, process_token_transfer,
-    update_token_accounts_from_meta, update_token_transfer_details, MintDetail, SPLTokenDecoder,
+    token_account_owner, update_token_accounts_from_meta, MintDetail, MintDetails, SPLTokenDecoder,
     TokenTransferDetails, SPL_TOKEN_DECODER,
 };
+pub use system_transfer_decoder::{
+    is_native_transfer, merge_native_transfers, SystemTransferDecoder, SYSTEM_TRANSFER_DECODER,
+};
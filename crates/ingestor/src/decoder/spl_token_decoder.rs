@@ -25,7 +25,7 @@ use carbon_token_program_decoder::{
 use solana_pubkey::Pubkey;
 use solana_signature::Signature;
 use spl_token::amount_to_ui_amount;
-use std::{collections::HashMap, sync::LazyLock};
+use std::{collections::HashMap, str::FromStr, sync::LazyLock};
 use tracing::error;
 
 /// Represents the details of a token transfer instruction
@@ -53,40 +53,85 @@ pub struct TokenTransferDetails {
     pub ui_amount: f64,
 }
 
-/// Implement the From trait for TokenTransferDetails for account types with mint field
-macro_rules! impl_into_token_transfer_details_with_mint {
+/// A decoded transfer whose accounts are not converted to strings yet, so that the mint
+/// details of its token accounts are looked up by `Pubkey`
+struct RawTransfer {
+    program_id: Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+    authority: Pubkey,
+    /// The mint of the checked transfers
+    mint: Option<Pubkey>,
+    amount: u64,
+    /// The decimals of the checked transfers
+    decimals: Option<u8>,
+}
+
+impl RawTransfer {
+    /// into_details builds the transfer details, the mint and decimals are taken from the mint
+    /// detail of the source or destination account when known
+    fn into_details(self, mint_details: Option<&MintDetails>) -> TokenTransferDetails {
+        let mint_detail = mint_details.and_then(|mint_details| {
+            mint_details.get(&self.source).or_else(|| mint_details.get(&self.destination))
+        });
+        let (mint, decimals, ui_amount) = match (mint_detail, self.decimals) {
+            (Some(detail), _) => (
+                detail.mint.clone(),
+                detail.decimals,
+                amount_to_ui_amount(self.amount, detail.decimals),
+            ),
+            (None, Some(decimals)) => (
+                self.mint.map(|mint| mint.to_string()).unwrap_or_default(),
+                decimals,
+                amount_to_ui_amount(self.amount, decimals),
+            ),
+            (None, None) => (String::new(), 0, 0.0),
+        };
+        TokenTransferDetails {
+            program_id: self.program_id.to_string(),
+            source: self.source.to_string(),
+            destination: self.destination.to_string(),
+            mint,
+            authority: self.authority.to_string(),
+            decimals,
+            amount: self.amount,
+            ui_amount,
+        }
+    }
+}
+
+/// Implement the From trait for RawTransfer for account types with mint field
+macro_rules! impl_into_raw_transfer_with_mint {
     ($account_type:ty, $program_id:expr) => {
-        impl From<$account_type> for TokenTransferDetails {
+        impl From<$account_type> for RawTransfer {
             fn from(accounts: $account_type) -> Self {
                 Self {
-                    program_id: $program_id.to_string(),
-                    source: accounts.source.to_string(),
-                    destination: accounts.destination.to_string(),
-                    authority: accounts.authority.to_string(),
-                    mint: accounts.mint.to_string(),
-                    decimals: 0,
+                    program_id: $program_id,
+                    source: accounts.source,
+                    destination: accounts.destination,
+                    authority: accounts.authority,
+                    mint: Some(accounts.mint),
                     amount: 0,
-                    ui_amount: 0.0,
+                    decimals: None,
                 }
             }
         }
     };
 }
 
-/// Implement the From trait for TokenTransferDetails for account types without mint field
-macro_rules! impl_into_token_transfer_details_without_mint {
+/// Implement the From trait for RawTransfer for account types without mint field
+macro_rules! impl_into_raw_transfer_without_mint {
     ($account_type:ty, $program_id:expr) => {
-        impl From<$account_type> for TokenTransferDetails {
+        impl From<$account_type> for RawTransfer {
             fn from(accounts: $account_type) -> Self {
                 Self {
-                    program_id: $program_id.to_string(),
-                    source: accounts.source.to_string(),
-                    destination: accounts.destination.to_string(),
-                    authority: accounts.authority.to_string(),
-                    mint: String::new(),
-                    decimals: 0,
+                    program_id: $program_id,
+                    source: accounts.source,
+                    destination: accounts.destination,
+                    authority: accounts.authority,
+                    mint: None,
                     amount: 0,
-                    ui_amount: 0.0,
+                    decimals: None,
                 }
             }
         }
@@ -94,13 +139,10 @@ macro_rules! impl_into_token_transfer_details_without_mint {
 }
 
 // Implement From trait for different account types
-impl_into_token_transfer_details_without_mint!(TransferAccounts, TOKEN_PROGRAM_ID);
-impl_into_token_transfer_details_with_mint!(TransferCheckedAccounts, TOKEN_PROGRAM_ID);
-impl_into_token_transfer_details_without_mint!(TransferInstructionAccounts, TOKEN_2022_PROGRAM_ID);
-impl_into_token_transfer_details_with_mint!(
-    TransferCheckedInstructionAccounts,
-    TOKEN_2022_PROGRAM_ID
-);
+impl_into_raw_transfer_without_mint!(TransferAccounts, TOKEN_PROGRAM_ID);
+impl_into_raw_transfer_with_mint!(TransferCheckedAccounts, TOKEN_PROGRAM_ID);
+impl_into_raw_transfer_without_mint!(TransferInstructionAccounts, TOKEN_2022_PROGRAM_ID);
+impl_into_raw_transfer_with_mint!(TransferCheckedInstructionAccounts, TOKEN_2022_PROGRAM_ID);
 
 /// A decoder for Solana SPL token transfer instructions
 ///
@@ -114,63 +156,68 @@ pub struct SPLTokenDecoder {
 /// A static instance of SPLTokenDecoder for global access
 pub static SPL_TOKEN_DECODER: LazyLock<SPLTokenDecoder> = LazyLock::new(SPLTokenDecoder::new);
 
-/// Process a standard Token program instruction to extract transfer details
-pub fn process_token_transfer(
+/// Decode a standard Token program transfer, keeping its accounts as keys
+fn raw_token_transfer(
     instruction: DecodedInstruction<TokenProgramInstruction>,
-) -> Option<TokenTransferDetails> {
+) -> Option<RawTransfer> {
     if !instruction.program_id.eq(&TOKEN_PROGRAM_ID) {
         return None;
     }
 
     match &instruction.data {
         TokenProgramInstruction::Transfer(t) => Transfer::arrange_accounts(&instruction.accounts)
-            .map(|accounts| {
-                let mut details = TokenTransferDetails::from(accounts);
-                details.amount = t.amount;
-                details
-            }),
+            .map(|accounts| RawTransfer { amount: t.amount, ..RawTransfer::from(accounts) }),
         TokenProgramInstruction::TransferChecked(t) => {
-            TransferChecked::arrange_accounts(&instruction.accounts).map(|accounts| {
-                let mut details = TokenTransferDetails::from(accounts);
-                details.amount = t.amount;
-                details.decimals = t.decimals;
-                details.ui_amount = amount_to_ui_amount(t.amount, t.decimals);
-                details
+            TransferChecked::arrange_accounts(&instruction.accounts).map(|accounts| RawTransfer {
+                amount: t.amount,
+                decimals: Some(t.decimals),
+                ..RawTransfer::from(accounts)
             })
         }
         _ => None,
     }
 }
 
-/// Process a Token-2022 program instruction to extract transfer details
-pub fn process_token_2022_transfer(
+/// Decode a Token-2022 program transfer, keeping its accounts as keys
+fn raw_token_2022_transfer(
     instruction: DecodedInstruction<Token2022Instruction>,
-) -> Option<TokenTransferDetails> {
+) -> Option<RawTransfer> {
     if !instruction.program_id.eq(&TOKEN_2022_PROGRAM_ID) {
         return None;
     }
 
     match &instruction.data {
         Token2022Instruction::Transfer(t) => {
-            Token2022Transfer::arrange_accounts(&instruction.accounts).map(|accounts| {
-                let mut details = TokenTransferDetails::from(accounts);
-                details.amount = t.amount;
-                details
-            })
+            Token2022Transfer::arrange_accounts(&instruction.accounts)
+                .map(|accounts| RawTransfer { amount: t.amount, ..RawTransfer::from(accounts) })
         }
         Token2022Instruction::TransferChecked(t) => {
             Token2022TransferChecked::arrange_accounts(&instruction.accounts).map(|accounts| {
-                let mut details = TokenTransferDetails::from(accounts);
-                details.amount = t.amount;
-                details.decimals = t.decimals;
-                details.ui_amount = amount_to_ui_amount(t.amount, t.decimals);
-                details
+                RawTransfer {
+                    amount: t.amount,
+                    decimals: Some(t.decimals),
+                    ..RawTransfer::from(accounts)
+                }
             })
         }
         _ => None,
     }
 }
 
+/// Process a standard Token program instruction to extract transfer details
+pub fn process_token_transfer(
+    instruction: DecodedInstruction<TokenProgramInstruction>,
+) -> Option<TokenTransferDetails> {
+    raw_token_transfer(instruction).map(|transfer| transfer.into_details(None))
+}
+
+/// Process a Token-2022 program instruction to extract transfer details
+pub fn process_token_2022_transfer(
+    instruction: DecodedInstruction<Token2022Instruction>,
+) -> Option<TokenTransferDetails> {
+    raw_token_2022_transfer(instruction).map(|transfer| transfer.into_details(None))
+}
+
 impl SPLTokenDecoder {
     /// Create a new SPL token decoder
     pub fn new() -> Self {
@@ -205,18 +252,20 @@ impl SPLTokenDecoder {
     /// Decode a token transfer instruction and enrich it with vault information
     pub fn decode_token_transfer_with_vaults(
         &self,
-        mint_details: &HashMap<String, MintDetail>,
+        mint_details: &MintDetails,
         instruction: &solana_instruction::Instruction,
     ) -> Option<TokenTransferDetails> {
-        let details = match instruction.program_id {
-            TOKEN_PROGRAM_ID => self.try_decode_token_transfer(instruction),
-            TOKEN_2022_PROGRAM_ID => self.try_decode_token_2022_transfer(instruction),
+        let transfer = match instruction.program_id {
+            TOKEN_PROGRAM_ID => {
+                self.token_decoder.decode_instruction(instruction).and_then(raw_token_transfer)
+            }
+            TOKEN_2022_PROGRAM_ID => self
+                .token_2022_decoder
+                .decode_instruction(instruction)
+                .and_then(raw_token_2022_transfer),
             _ => None,
         };
-        details.map(|mut details| {
-            update_token_transfer_details(&mut details, mint_details);
-            details
-        })
+        transfer.map(|transfer| transfer.into_details(Some(mint_details)))
     }
 
     /// Decode token transfers from a list of nested instructions
@@ -226,14 +275,23 @@ impl SPLTokenDecoder {
     pub fn decode_token_transfers_from_instructions(
         &self,
         nested_instructions: &[NestedInstruction],
-        mint_details: &HashMap<String, MintDetail>,
+        mint_details: &MintDetails,
     ) -> Vec<TokenTransferDetails> {
-        nested_instructions
-            .iter()
-            .filter_map(|instruction| {
-                self.decode_token_transfer_with_vaults(mint_details, &instruction.instruction)
-            })
-            .collect()
+        let mut transfers = Vec::new();
+        self.extend_token_transfers(nested_instructions, mint_details, &mut transfers);
+        transfers
+    }
+
+    /// Decode token transfers from a list of nested instructions into `transfers`
+    pub fn extend_token_transfers(
+        &self,
+        nested_instructions: &[NestedInstruction],
+        mint_details: &MintDetails,
+        transfers: &mut Vec<TokenTransferDetails>,
+    ) {
+        transfers.extend(nested_instructions.iter().filter_map(|instruction| {
+            self.decode_token_transfer_with_vaults(mint_details, &instruction.instruction)
+        }));
     }
 }
 
@@ -251,6 +309,9 @@ pub struct MintDetail {
     pub decimals: u8,
 }
 
+/// The mint details of the token accounts of a transaction, by token account
+pub type MintDetails = HashMap<Pubkey, MintDetail>;
+
 impl From<&solana_transaction_status::TransactionTokenBalance> for MintDetail {
     fn from(balance: &solana_transaction_status::TransactionTokenBalance) -> Self {
         Self {
@@ -261,16 +322,28 @@ impl From<&solana_transaction_status::TransactionTokenBalance> for MintDetail {
     }
 }
 
+/// Returns the account at `index` of the accounts split in consecutive segments,
+/// e.g. the static keys followed by the loaded writable and readonly addresses
+fn account_at<'a>(accounts: &[&'a [Pubkey]], mut index: usize) -> Option<&'a Pubkey> {
+    for segment in accounts {
+        if let Some(account) = segment.get(index) {
+            return Some(account);
+        }
+        index -= segment.len();
+    }
+    None
+}
+
 /// Update mint details from transaction token balances
 pub fn update_token_accounts_from_meta<'a>(
     signature: &Signature,
-    accounts: &[Pubkey],
+    accounts: &[&[Pubkey]],
     balances: &[solana_transaction_status::TransactionTokenBalance],
-    mint_details: &'a mut HashMap<String, MintDetail>,
-) -> &'a mut HashMap<String, MintDetail> {
+    mint_details: &'a mut MintDetails,
+) -> &'a mut MintDetails {
     for balance in balances {
-        if let Some(pubkey) = accounts.get(balance.account_index as usize) {
-            mint_details.insert(pubkey.to_string(), MintDetail::from(balance));
+        if let Some(pubkey) = account_at(accounts, balance.account_index as usize) {
+            mint_details.insert(*pubkey, MintDetail::from(balance));
         } else {
             error!("Invalid account_index {} for signature: {}", balance.account_index, signature);
         }
@@ -278,59 +351,55 @@ pub fn update_token_accounts_from_meta<'a>(
     mint_details
 }
 
-/// Update token transfer details with vault and mint information
-pub fn update_token_transfer_details(
-    details: &mut TokenTransferDetails,
-    mint_details: &HashMap<String, MintDetail>,
-) {
-    if let Some(mint_detail) = mint_details.get(&details.source) {
-        update_details_from_mint(details, mint_detail);
-    } else if let Some(mint_detail) = mint_details.get(&details.destination) {
-        update_details_from_mint(details, mint_detail);
-    }
-}
-
-/// Helper function to update token details from mint information
-fn update_details_from_mint(
-    token_transfer_details: &mut TokenTransferDetails,
-    mint_detail: &MintDetail,
-) {
-    token_transfer_details.mint = mint_detail.mint.clone();
-    token_transfer_details.decimals = mint_detail.decimals;
-    token_transfer_details.ui_amount =
-        amount_to_ui_amount(token_transfer_details.amount, mint_detail.decimals);
-}
-
 /// Extract mint details from transaction metadata
 pub fn extra_mint_details_from_tx_metadata(
     transaction_metadata: &TransactionMetadata,
-) -> HashMap<String, MintDetail> {
-    let mut mint_details = HashMap::new();
-    let account_keys = transaction_metadata.message.static_account_keys().to_vec();
-    let loaded_addresses = transaction_metadata.meta.loaded_addresses.clone();
-    let accounts_address =
-        [account_keys, loaded_addresses.writable, loaded_addresses.readonly].concat();
-
+) -> MintDetails {
     let meta = &transaction_metadata.meta;
-    if let Some(pre_balances) = meta.pre_token_balances.as_ref() {
-        update_token_accounts_from_meta(
-            &transaction_metadata.signature,
-            &accounts_address,
-            pre_balances,
-            &mut mint_details,
-        );
-    }
-    if let Some(post_balances) = meta.post_token_balances.as_ref() {
+    let accounts = [
+        transaction_metadata.message.static_account_keys(),
+        meta.loaded_addresses.writable.as_slice(),
+        meta.loaded_addresses.readonly.as_slice(),
+    ];
+    let pre_balances = meta.pre_token_balances.as_deref().unwrap_or_default();
+    let post_balances = meta.post_token_balances.as_deref().unwrap_or_default();
+
+    // the post balances mostly repeat the accounts of the pre balances
+    let mut mint_details = HashMap::with_capacity(pre_balances.len().max(post_balances.len()));
+    for balances in [pre_balances, post_balances] {
         update_token_accounts_from_meta(
             &transaction_metadata.signature,
-            &accounts_address,
-            post_balances,
+            &accounts,
+            balances,
             &mut mint_details,
         );
     }
     mint_details
 }
 
+/// Returns the owner of a token account from the token balances of the transaction, None when
+/// the account holds no token balance, e.g. a native SOL account, or its owner isn't recorded
+pub fn token_account_owner(
+    transaction_metadata: &TransactionMetadata,
+    account: &str,
+) -> Option<String> {
+    let account = Pubkey::from_str(account).ok()?;
+    let meta = &transaction_metadata.meta;
+    let accounts = [
+        transaction_metadata.message.static_account_keys(),
+        meta.loaded_addresses.writable.as_slice(),
+        meta.loaded_addresses.readonly.as_slice(),
+    ];
+    let pre_balances = meta.pre_token_balances.as_deref().unwrap_or_default();
+    let post_balances = meta.post_token_balances.as_deref().unwrap_or_default();
+    pre_balances
+        .iter()
+        .chain(post_balances)
+        .filter(|balance| !balance.owner.is_empty())
+        .find(|balance| account_at(&accounts, balance.account_index as usize) == Some(&account))
+        .map(|balance| balance.owner.clone())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -340,6 +409,21 @@ mod tests {
     use carbon_raydium_amm_v4_decoder::RaydiumAmmV4Decoder;
     use dotenvy::dotenv;
 
+    #[test]
+    fn test_account_at() {
+        let (static_keys, writable, readonly) = (
+            [Pubkey::new_unique(), Pubkey::new_unique()],
+            [Pubkey::new_unique()],
+            [Pubkey::new_unique(), Pubkey::new_unique()],
+        );
+        let accounts = [&static_keys[..], &writable[..], &readonly[..]];
+        assert_eq!(account_at(&accounts, 1), Some(&static_keys[1]));
+        assert_eq!(account_at(&accounts, 2), Some(&writable[0]));
+        assert_eq!(account_at(&accounts, 4), Some(&readonly[1]));
+        assert_eq!(account_at(&accounts, 5), None);
+        assert_eq!(account_at(&[&[], &writable[..]], 0), Some(&writable[0]));
+    }
+
     /// https://solscan.io/tx/3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn
     /// 3.2 - Raydium Liquidity Pool V4: raydium:swap
     #[tokio::test]
@@ -0,0 +1,232 @@
+use crate::{
+    constants::{SYSTEM_PROGRAM_ID, SYSTEM_PROGRAM_ID_STR, WSOL_MINT_KEY_STR},
+    decoder::{MintDetails, TokenTransferDetails},
+};
+use carbon_core::instruction::NestedInstruction;
+use solana_pubkey::Pubkey;
+use spl_token::amount_to_ui_amount;
+use std::{collections::HashSet, sync::LazyLock};
+
+/// The decimals of native SOL, same as WSOL
+const SOL_DECIMALS: u8 = 9;
+/// The discriminator of the System program `Transfer` instruction
+const SYSTEM_TRANSFER_DISCRIMINATOR: u32 = 2;
+
+/// A decoder for native SOL transfers of the System program
+///
+/// Some routes move SOL as lamports (System `Transfer` + `SyncNative`) instead of
+/// SPL WSOL transfers. This decoder synthesizes a WSOL `TokenTransferDetails` for
+/// native transfers into or out of a known WSOL token account or SOL vault, so
+/// that these swaps can be priced like regular SPL swaps.
+#[derive(Default)]
+pub struct SystemTransferDecoder;
+
+/// A static instance of SystemTransferDecoder for global access
+pub static SYSTEM_TRANSFER_DECODER: LazyLock<SystemTransferDecoder> =
+    LazyLock::new(SystemTransferDecoder::new);
+
+impl SystemTransferDecoder {
+    /// Create a new system transfer decoder
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Try to decode a System program transfer instruction into `(source, destination, lamports)`
+    pub fn try_decode_system_transfer(
+        &self,
+        instruction: &solana_instruction::Instruction,
+    ) -> Option<(String, String, u64)> {
+        system_transfer_keys(instruction).map(|(source, destination, lamports)| {
+            (source.to_string(), destination.to_string(), lamports)
+        })
+    }
+
+    /// Decode a native transfer touching a WSOL token account or a known SOL vault
+    pub fn decode_native_transfer(
+        &self,
+        mint_details: &MintDetails,
+        sol_vaults: &HashSet<String>,
+        instruction: &solana_instruction::Instruction,
+    ) -> Option<TokenTransferDetails> {
+        let (source, destination, lamports) = system_transfer_keys(instruction)?;
+        // the vaults are few and only checked for the rare native transfers
+        let is_sol_account = |account: &Pubkey| {
+            mint_details.get(account).is_some_and(|detail| detail.mint == WSOL_MINT_KEY_STR)
+                || (!sol_vaults.is_empty() && sol_vaults.contains(&account.to_string()))
+        };
+        if !is_sol_account(&source) && !is_sol_account(&destination) {
+            return None;
+        }
+        let source = source.to_string();
+        Some(TokenTransferDetails {
+            program_id: SYSTEM_PROGRAM_ID_STR.to_string(),
+            authority: source.clone(),
+            source,
+            destination: destination.to_string(),
+            mint: WSOL_MINT_KEY_STR.to_string(),
+            decimals: SOL_DECIMALS,
+            amount: lamports,
+            ui_amount: amount_to_ui_amount(lamports, SOL_DECIMALS),
+        })
+    }
+
+    /// Decode native transfers from a list of nested instructions
+    pub fn decode_native_transfers_from_instructions(
+        &self,
+        nested_instructions: &[NestedInstruction],
+        mint_details: &MintDetails,
+        sol_vaults: &HashSet<String>,
+    ) -> Vec<TokenTransferDetails> {
+        let mut transfers = Vec::new();
+        self.extend_native_transfers(nested_instructions, mint_details, sol_vaults, &mut transfers);
+        transfers
+    }
+
+    /// Decode native transfers from a list of nested instructions into `transfers`
+    pub fn extend_native_transfers(
+        &self,
+        nested_instructions: &[NestedInstruction],
+        mint_details: &MintDetails,
+        sol_vaults: &HashSet<String>,
+        transfers: &mut Vec<TokenTransferDetails>,
+    ) {
+        transfers.extend(nested_instructions.iter().filter_map(|instruction| {
+            self.decode_native_transfer(mint_details, sol_vaults, &instruction.instruction)
+        }));
+    }
+}
+
+/// Decode a System program transfer instruction into the keys of its accounts and its lamports
+fn system_transfer_keys(
+    instruction: &solana_instruction::Instruction,
+) -> Option<(Pubkey, Pubkey, u64)> {
+    if instruction.program_id != SYSTEM_PROGRAM_ID || instruction.data.len() < 12 {
+        return None;
+    }
+    let discriminator = u32::from_le_bytes(instruction.data[0..4].try_into().ok()?);
+    if discriminator != SYSTEM_TRANSFER_DISCRIMINATOR {
+        return None;
+    }
+    let lamports = u64::from_le_bytes(instruction.data[4..12].try_into().ok()?);
+    let source = instruction.accounts.first()?.pubkey;
+    let destination = instruction.accounts.get(1)?.pubkey;
+    Some((source, destination, lamports))
+}
+
+/// Returns true if the transfer was synthesized from a native SOL transfer
+pub fn is_native_transfer(transfer: &TokenTransferDetails) -> bool {
+    transfer.program_id == SYSTEM_PROGRAM_ID_STR
+}
+
+/// Merge native transfers into SPL transfers
+///
+/// A native transfer followed by `SyncNative` only wraps SOL, the SPL transfer spending
+/// the wrapped amount is the actual swap leg. A native transfer is dropped when an SPL
+/// WSOL transfer with the same amount moves funds through the same account.
+pub fn merge_native_transfers(
+    spl_transfers: Vec<TokenTransferDetails>,
+    native_transfers: Vec<TokenTransferDetails>,
+) -> Vec<TokenTransferDetails> {
+    let mut transfers = spl_transfers;
+    let native_transfers: Vec<_> = native_transfers
+        .into_iter()
+        .filter(|native| {
+            !transfers.iter().any(|spl| {
+                spl.mint == WSOL_MINT_KEY_STR
+                    && spl.amount == native.amount
+                    && (spl.source == native.destination || spl.destination == native.source)
+            })
+        })
+        .collect();
+    transfers.extend(native_transfers);
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::MintDetail;
+    use solana_instruction::{AccountMeta, Instruction};
+    use std::collections::HashMap;
+
+    fn system_transfer(source: Pubkey, destination: Pubkey, lamports: u64) -> Instruction {
+        let mut data = SYSTEM_TRANSFER_DISCRIMINATOR.to_le_bytes().to_vec();
+        data.extend_from_slice(&lamports.to_le_bytes());
+        Instruction {
+            program_id: SYSTEM_PROGRAM_ID,
+            accounts: vec![AccountMeta::new(source, true), AccountMeta::new(destination, false)],
+            data,
+        }
+    }
+
+    fn wsol_transfer(source: &str, destination: &str, amount: u64) -> TokenTransferDetails {
+        TokenTransferDetails {
+            program_id: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+            source: source.to_string(),
+            destination: destination.to_string(),
+            mint: WSOL_MINT_KEY_STR.to_string(),
+            authority: source.to_string(),
+            decimals: SOL_DECIMALS,
+            amount,
+            ui_amount: amount_to_ui_amount(amount, SOL_DECIMALS),
+        }
+    }
+
+    #[test]
+    fn test_decode_native_transfer() {
+        let (user, wsol_ata, other) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mint_details = HashMap::from([(
+            wsol_ata,
+            MintDetail {
+                mint: WSOL_MINT_KEY_STR.to_string(),
+                owner: user.to_string(),
+                decimals: SOL_DECIMALS,
+            },
+        )]);
+        let sol_vaults = HashSet::new();
+
+        let instruction = system_transfer(user, wsol_ata, 1_500_000_000);
+        let details = SYSTEM_TRANSFER_DECODER
+            .decode_native_transfer(&mint_details, &sol_vaults, &instruction)
+            .expect("Failed to decode native transfer");
+        assert_eq!(details.mint, WSOL_MINT_KEY_STR);
+        assert_eq!(details.source, user.to_string());
+        assert_eq!(details.destination, wsol_ata.to_string());
+        assert_eq!(details.amount, 1_500_000_000);
+        assert_eq!(details.ui_amount, 1.5);
+        assert!(is_native_transfer(&details));
+
+        // transfers between unknown accounts are not swap legs
+        let instruction = system_transfer(user, other, 1_500_000_000);
+        assert!(SYSTEM_TRANSFER_DECODER
+            .decode_native_transfer(&mint_details, &sol_vaults, &instruction)
+            .is_none());
+
+        // unless the destination is a known sol vault
+        let sol_vaults = HashSet::from([other.to_string()]);
+        assert!(SYSTEM_TRANSFER_DECODER
+            .decode_native_transfer(&mint_details, &sol_vaults, &instruction)
+            .is_some());
+    }
+
+    #[test]
+    fn test_merge_native_transfers() {
+        // wrap 1 SOL into the user's WSOL account, then spend it into the pool vault
+        let wrap = TokenTransferDetails {
+            program_id: SYSTEM_PROGRAM_ID_STR.to_string(),
+            ..wsol_transfer("user", "user_wsol", 1_000_000_000)
+        };
+        let spend = wsol_transfer("user_wsol", "vault", 1_000_000_000);
+        let merged = merge_native_transfers(vec![spend.clone()], vec![wrap]);
+        assert_eq!(merged, vec![spend.clone()]);
+
+        // native SOL paid straight into the sol vault is kept
+        let native = TokenTransferDetails {
+            program_id: SYSTEM_PROGRAM_ID_STR.to_string(),
+            ..wsol_transfer("user", "sol_vault", 2_000_000_000)
+        };
+        let merged = merge_native_transfers(vec![spend.clone()], vec![native.clone()]);
+        assert_eq!(merged, vec![spend, native]);
+    }
+}
@@ -0,0 +1,384 @@
+//! Decode a transaction into the swaps the ingestor would store, without the storages
+//!
+//! [`analyze_transaction`] runs the swap instructions through the swap event builder of the
+//! processors on dry run storages, for `sonar analyze-tx` debugging why a transaction wasn't
+//! ingested. The token metadata missing from the storages is fetched from the RPC.
+use crate::{
+    constants::Dexes,
+    datasource::rpc::make_rpc_client,
+    handler::{
+        token_cache::TokenCache,
+        token_swap_handler::{
+            get_swap_event_with_token_transfer_details, split_swap_transfers,
+            walk_inner_token_transfers, SwapError,
+        },
+        SwapFilterConfig, TokenSwapAccounts,
+    },
+    metrics::NodeMetrics,
+    processor::{
+        meteora_dlmm_processor, meteora_pools_processor, ocra_whirlpool_processor,
+        pump_amm_processor, raydium_amm_v4_processor, raydium_clmm_processor,
+        raydium_cpmm_processor, raydium_launchpad_processor,
+    },
+    Storages,
+};
+use anyhow::{anyhow, Context, Result};
+use carbon_core::{
+    datasource::TransactionUpdate,
+    instruction::{InstructionDecoder, NestedInstruction, NestedInstructions},
+    transaction::TransactionMetadata,
+    transformers::{extract_instructions_with_metadata, transaction_metadata_from_original_meta},
+};
+use carbon_meteora_dlmm_decoder::MeteoraDlmmDecoder;
+use carbon_meteora_pools_decoder::MeteoraPoolsDecoder;
+use carbon_orca_whirlpool_decoder::OrcaWhirlpoolDecoder;
+use carbon_pump_swap_decoder::PumpSwapDecoder;
+use carbon_raydium_amm_v4_decoder::RaydiumAmmV4Decoder;
+use carbon_raydium_clmm_decoder::RaydiumClmmDecoder;
+use carbon_raydium_cpmm_decoder::RaydiumCpmmDecoder;
+use carbon_raydium_launchpad_decoder::RaydiumLaunchpadDecoder;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_commitment_config::CommitmentConfig;
+use solana_instruction::Instruction;
+use solana_signature::Signature;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use sonar_db::{DryRunLog, SwapEvent};
+use sonar_sol_price::cache::set_sol_price;
+use std::{str::FromStr, sync::Arc};
+
+/// A swap instruction of a transaction and the swap event it produces, or why it was skipped
+#[derive(Debug)]
+pub struct AnalyzedSwap {
+    pub dex: Dexes,
+    /// The index of the outer instruction followed by the indexes of the inner ones
+    pub path: Vec<usize>,
+    pub pair: String,
+    pub result: Result<SwapEvent, SwapError>,
+}
+
+/// A decoded swap instruction, with the instructions nested under it
+struct SwapInstruction<'a> {
+    dex: Dexes,
+    path: Vec<usize>,
+    accounts: TokenSwapAccounts,
+    inner_instructions: &'a [NestedInstruction],
+}
+
+/// analyze_transaction returns the swaps of a transaction filtered with `config`, with SOL
+/// priced at `sol_price`, on dry run storages, see [`analyze_transaction_with`]
+pub async fn analyze_transaction(
+    update: &TransactionUpdate,
+    sol_price: f64,
+    config: &SwapFilterConfig,
+) -> Result<Vec<AnalyzedSwap>> {
+    set_sol_price(sol_price).await;
+    let storages = Storages::dry_run(DryRunLog::default());
+    analyze_transaction_with(update, config, &storages).await
+}
+
+/// analyze_transaction_with returns the swaps of a transaction, in the order of their
+/// instructions, built the way the processors build them from the tokens and the prices of
+/// `storages`, nothing but the token launchpads and the wash trade markers is written to them
+pub async fn analyze_transaction_with(
+    update: &TransactionUpdate,
+    config: &SwapFilterConfig,
+    storages: &Storages,
+) -> Result<Vec<AnalyzedSwap>> {
+    let transaction_metadata: TransactionMetadata = update
+        .clone()
+        .try_into()
+        .map_err(|e| anyhow!("Failed to convert transaction update: {}", e))?;
+    let transaction_metadata = Arc::new(transaction_metadata);
+    let instructions = extract_instructions_with_metadata(&transaction_metadata, update)
+        .map_err(|e| anyhow!("Failed to extract instructions: {}", e))?;
+    let nested_instructions: NestedInstructions = instructions.into();
+
+    let mut swap_instructions = vec![];
+    collect_swap_instructions(&nested_instructions, &mut vec![], &mut swap_instructions);
+
+    let token_cache = TokenCache::default();
+    let metrics = NodeMetrics::new();
+    let mut swaps = Vec::with_capacity(swap_instructions.len());
+    for swap_instruction in swap_instructions {
+        let result = analyze_swap(
+            &swap_instruction,
+            &transaction_metadata,
+            storages,
+            &token_cache,
+            &metrics,
+            config,
+        )
+        .await;
+        swaps.push(AnalyzedSwap {
+            dex: swap_instruction.dex,
+            path: swap_instruction.path,
+            pair: swap_instruction.accounts.pair,
+            result,
+        });
+    }
+    Ok(swaps)
+}
+
+/// collect_swap_instructions appends the swap instructions among the instructions and their
+/// inner instructions, `path` is the position of the parent instruction
+fn collect_swap_instructions<'a>(
+    nested_instructions: &'a [NestedInstruction],
+    path: &mut Vec<usize>,
+    swap_instructions: &mut Vec<SwapInstruction<'a>>,
+) {
+    for (index, nested_instruction) in nested_instructions.iter().enumerate() {
+        path.push(index);
+        if let Some((dex, accounts)) = decode_swap_accounts(&nested_instruction.instruction) {
+            swap_instructions.push(SwapInstruction {
+                dex,
+                path: path.clone(),
+                accounts,
+                inner_instructions: &nested_instruction.inner_instructions,
+            });
+        }
+        collect_swap_instructions(&nested_instruction.inner_instructions, path, swap_instructions);
+        path.pop();
+    }
+}
+
+/// decode_swap_accounts returns the dex and the accounts of a swap instruction of one of the
+/// decoders of the pipeline, None for the other instructions
+pub fn decode_swap_accounts(instruction: &Instruction) -> Option<(Dexes, TokenSwapAccounts)> {
+    let dex = Dexes::from_program_id(&instruction.program_id)?;
+    let accounts = match dex {
+        Dexes::MeteoraDlmm => meteora_dlmm_processor::get_swap_accounts(
+            &MeteoraDlmmDecoder.decode_instruction(instruction)?,
+        ),
+        Dexes::MeteoraPools => meteora_pools_processor::get_swap_accounts(
+            &MeteoraPoolsDecoder.decode_instruction(instruction)?,
+        ),
+        Dexes::OcraWhirlpool => ocra_whirlpool_processor::get_swap_accounts(
+            &OrcaWhirlpoolDecoder.decode_instruction(instruction)?,
+        ),
+        Dexes::PumpAmm => {
+            pump_amm_processor::get_swap_accounts(&PumpSwapDecoder.decode_instruction(instruction)?)
+        }
+        Dexes::RaydiumAmmV4 => raydium_amm_v4_processor::get_swap_accounts(
+            &RaydiumAmmV4Decoder.decode_instruction(instruction)?,
+        ),
+        Dexes::RaydiumClmm => raydium_clmm_processor::get_swap_accounts(
+            &RaydiumClmmDecoder.decode_instruction(instruction)?,
+        ),
+        Dexes::RaydiumCpmm => raydium_cpmm_processor::get_swap_accounts(
+            &RaydiumCpmmDecoder.decode_instruction(instruction)?,
+        ),
+        Dexes::RaydiumLaunchpad => raydium_launchpad_processor::get_swap_accounts(
+            &RaydiumLaunchpadDecoder.decode_instruction(instruction)?,
+        ),
+        // the pipeline has no decoder for these venues
+        Dexes::MeteoraDammV2 | Dexes::PumpFun => None,
+    }?;
+    Some((dex, accounts))
+}
+
+/// analyze_swap returns the swap event of a swap instruction, with the transfers walked and
+/// the swap event built as in `process_token_swap_instruction`
+async fn analyze_swap(
+    swap_instruction: &SwapInstruction<'_>,
+    transaction_metadata: &TransactionMetadata,
+    storages: &Storages,
+    token_cache: &TokenCache,
+    metrics: &NodeMetrics,
+    config: &SwapFilterConfig,
+) -> Result<SwapEvent, SwapError> {
+    let accounts = &swap_instruction.accounts;
+    let transfers = walk_inner_token_transfers(
+        transaction_metadata,
+        swap_instruction.inner_instructions,
+        &accounts.vault_adas,
+        config.max_instruction_depth,
+    )
+    .transfers;
+    let (transfers, fee_transfers) = split_swap_transfers(&transfers, accounts);
+    let (swap_event, _token) = get_swap_event_with_token_transfer_details(
+        swap_instruction.dex,
+        accounts,
+        &transfers,
+        &fee_transfers,
+        transaction_metadata,
+        &storages.kv_store,
+        &storages.db,
+        token_cache,
+        metrics,
+        config,
+        None,
+    )
+    .await?;
+    Ok(swap_event)
+}
+
+/// fetch_transaction_update returns the update of a confirmed transaction fetched from the RPC
+pub async fn fetch_transaction_update(signature: &str) -> Result<TransactionUpdate> {
+    let signature = Signature::from_str(signature).context("Invalid signature")?;
+    let encoded_transaction = make_rpc_client()
+        .get_transaction_with_config(
+            &signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Binary),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await
+        .context("Failed to get transaction")?;
+    transaction_update_from_encoded(signature, encoded_transaction)
+}
+
+/// transaction_update_from_encoded returns the update the datasources would emit for a
+/// transaction fetched from the RPC
+pub fn transaction_update_from_encoded(
+    signature: Signature,
+    encoded_transaction: EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<TransactionUpdate> {
+    let transaction = encoded_transaction.transaction;
+    let meta = transaction
+        .meta
+        .ok_or_else(|| anyhow!("Meta is malformed for transaction: {:?}", signature))?;
+    if meta.status.is_err() {
+        return Err(anyhow!("Transaction failed: {:?}", signature));
+    }
+    let decoded_transaction =
+        transaction.transaction.decode().context("Failed to decode transaction")?;
+    let meta = transaction_metadata_from_original_meta(meta)
+        .map_err(|e| anyhow!("Error getting metadata: {}", e))?;
+    Ok(TransactionUpdate {
+        signature,
+        transaction: decoded_transaction,
+        meta,
+        is_vote: false,
+        slot: encoded_transaction.slot,
+        block_time: encoded_transaction.block_time,
+        block_hash: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_swaps::{get_transaction_data, MemoryStorages, TEST_SOL_PRICE};
+
+    /// The supply the analyzed tokens are seeded with
+    const SUPPLY: f64 = 1_000_000_000.0;
+
+    /// disabled returns the swap filters of the processor tests
+    fn disabled() -> SwapFilterConfig {
+        SwapFilterConfig {
+            min_ui_amount: 0.0,
+            min_ui_amount_overrides: Default::default(),
+            min_swap_usd: 0.0,
+            quote_pair_policy: Default::default(),
+            ..Default::default()
+        }
+    }
+
+    /// analyze returns the swaps of a transaction with `token` seeded, so its metadata isn't
+    /// fetched from the RPC
+    async fn analyze(signature: &str, token: &str, config: &SwapFilterConfig) -> Vec<AnalyzedSwap> {
+        let (_, update, _) =
+            get_transaction_data(signature).await.expect("Failed to get transaction data");
+        set_sol_price(TEST_SOL_PRICE).await;
+        let memory = MemoryStorages::dry_run();
+        memory.seed_token(token, 6, SUPPLY).await;
+        let (kv_store, message_queue, db) = memory.storages();
+        let storages = Storages { db, kv_store, message_queue };
+        analyze_transaction_with(&update, config, &storages)
+            .await
+            .expect("Failed to analyze transaction")
+    }
+
+    /// swap_of returns the swap of `dex` trading `token`
+    fn swap_of<'a>(swaps: &'a [AnalyzedSwap], dex: Dexes, token: &str) -> &'a AnalyzedSwap {
+        swaps
+            .iter()
+            .find(|s| s.dex == dex && s.result.as_ref().is_ok_and(|e| e.pubkey == token))
+            .unwrap_or_else(|| panic!("Expected a {dex} swap of {token} in {swaps:?}"))
+    }
+
+    /// https://solscan.io/tx/3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn
+    #[tokio::test]
+    async fn test_analyze_meteora_dlmm_swap() {
+        let signature = "3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn";
+        let token = "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump";
+        let swaps = analyze(signature, token, &disabled()).await;
+        let swap = swap_of(&swaps, Dexes::MeteoraDlmm, token);
+        let swap_event = swap.result.as_ref().expect("Expected a swap event");
+        assert_eq!(swap_event.signature, signature);
+        assert_eq!(swap_event.pair, swap.pair);
+        assert_eq!(swap_event.base_amount, 24000.0);
+        assert_eq!(swap_event.quote_amount, 65.256388526);
+        assert_eq!(swap_event.swap_amount, 65.256388526 * TEST_SOL_PRICE);
+        assert_eq!(swap_event.market_cap, swap_event.price * SUPPLY);
+        assert_eq!(swap_event.pct_of_supply, 24000.0 / SUPPLY);
+    }
+
+    /// https://solscan.io/tx/5izpNhE1yRru7WuBMV7nWz4DrXvobUWvWuAcTAPgL1tBComtbqQrF6oSbTnhuyv2k4SPX9xNXEpDnunD2eSM1yen
+    #[tokio::test]
+    async fn test_analyze_usdc_quote_swap() {
+        let signature = "5izpNhE1yRru7WuBMV7nWz4DrXvobUWvWuAcTAPgL1tBComtbqQrF6oSbTnhuyv2k4SPX9xNXEpDnunD2eSM1yen";
+        let token = "6p6xgHyF7AeE6TZkSmFsko444wqoP15icUSqi2jfGiPN";
+        let swaps = analyze(signature, token, &disabled()).await;
+        let swap = swap_of(&swaps, Dexes::MeteoraDlmm, token);
+        let swap_event = swap.result.as_ref().expect("Expected a swap event");
+        assert!(swap_event.is_buy);
+        assert_eq!(swap_event.swap_amount, 200.0);
+        assert_eq!(swap_event.price, 200.0 / 18.143267);
+    }
+
+    /// https://solscan.io/tx/4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7
+    #[tokio::test]
+    async fn test_analyze_pump_amm_sell() {
+        let signature = "4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7";
+        let token = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+        let swaps = analyze(signature, token, &disabled()).await;
+        let swap = swap_of(&swaps, Dexes::PumpAmm, token);
+        let swap_event = swap.result.as_ref().expect("Expected a swap event");
+        assert!(!swap_event.is_buy);
+        assert!(swap_event.is_pump);
+        assert_eq!(swap_event.base_amount, 391682.524746);
+        assert_eq!(swap_event.quote_amount, 0.014472232);
+        assert_eq!(swap_event.swap_amount, 0.014472232 * TEST_SOL_PRICE);
+        assert_eq!(swap_event.pct_of_supply, 391682.524746 / SUPPLY);
+    }
+
+    /// the swap is attributed to the owner of the user token accounts, the fee payer is
+    /// recorded apart, a bot relayed swap still needs a recorded fixture to assert they differ
+    #[tokio::test]
+    async fn test_analyze_swap_owner() {
+        let signature = "4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7";
+        let token = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+        let swaps = analyze(signature, token, &disabled()).await;
+        let swap = swap_of(&swaps, Dexes::PumpAmm, token);
+        let swap_event = swap.result.as_ref().expect("Expected a swap event");
+        let (_, update, transaction_metadata) =
+            get_transaction_data(signature).await.expect("Failed to get transaction data");
+        assert_eq!(swap_event.fee_payer, transaction_metadata.fee_payer.to_string());
+        let token_owners: Vec<_> = update
+            .meta
+            .post_token_balances
+            .iter()
+            .flatten()
+            .filter(|balance| balance.mint == token)
+            .map(|balance| balance.owner.as_str())
+            .collect();
+        assert!(token_owners.contains(&swap_event.owner.as_str()), "{token_owners:?}");
+    }
+
+    /// the skipped swaps are returned with their reason
+    #[tokio::test]
+    async fn test_analyze_reports_skip_reasons() {
+        let signature = "4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7";
+        let token = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+        let config = SwapFilterConfig { min_swap_usd: 1_000_000.0, ..disabled() };
+        let swaps = analyze(signature, token, &config).await;
+        let pump_swaps: Vec<_> = swaps.iter().filter(|s| s.dex == Dexes::PumpAmm).collect();
+        assert!(!pump_swaps.is_empty(), "Expected the pump swap in {swaps:?}");
+        for swap in pump_swaps {
+            assert_eq!(swap.result.as_ref().err().map(SwapError::reason), Some("tiny_swap_usd"));
+        }
+    }
+}
@@ -1,6 +1,19 @@
+pub mod analyze;
+pub mod skipped_swaps;
+pub mod swap_filter;
+pub mod token_cache;
 pub mod token_swap_handler;
 
+pub use analyze::{
+    analyze_transaction, analyze_transaction_with, fetch_transaction_update, AnalyzedSwap,
+};
+pub use skipped_swaps::{SkippedSwap, SkippedSwapLog, SkippedSwapQuery};
+pub use swap_filter::{QuotePairPolicy, SwapFilterConfig, DEFAULT_MAX_INSTRUCTION_DEPTH};
+pub use token_cache::{TokenCache, DEFAULT_TOKEN_CACHE_CAPACITY, DEFAULT_TOKEN_CACHE_TTL};
+
 pub use token_swap_handler::{
-    get_inner_token_transfers, get_swap_event_with_token_transfer_details,
-    process_token_swap_instruction, TokenSwapAccounts, TokenSwapHandler,
+    get_inner_token_transfers, get_inner_token_transfers_with_vaults,
+    get_swap_event_with_token_transfer_details, process_token_swap_instruction,
+    walk_inner_token_transfers, InnerTokenTransfers, SolPriceCacheRef, TokenSwapAccounts,
+    TokenSwapHandler,
 };
@@ -0,0 +1,137 @@
+//! this file keeps the last skipped swaps in memory, to debug the trades missing from the feed
+use crate::{constants::Dexes, handler::token_swap_handler::SwapError};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Mutex};
+
+/// How many skipped swaps are kept by default
+pub const DEFAULT_SKIPPED_SWAPS_CAPACITY: usize = 1000;
+/// How many skipped swaps a query returns by default
+pub const DEFAULT_SKIPPED_SWAPS_LIMIT: usize = 100;
+
+/// A swap the handler skipped or failed to store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedSwap {
+    pub signature: String,
+    pub pair: String,
+    pub dex: Dexes,
+    /// The snake case name of the swap error, e.g. `tiny_swap`
+    pub reason: String,
+    /// The error of the storage or the RPC behind the failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// When the swap was skipped, in seconds
+    pub timestamp: i64,
+}
+
+impl SkippedSwap {
+    pub fn new(signature: &str, pair: &str, dex: Dexes, e: &SwapError, timestamp: i64) -> Self {
+        Self {
+            signature: signature.to_string(),
+            pair: pair.to_string(),
+            dex,
+            reason: e.reason().to_string(),
+            detail: e.source_error().map(|e| format!("{e:#}")),
+            timestamp,
+        }
+    }
+}
+
+/// The filters of the skipped swaps
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SkippedSwapQuery {
+    pub reason: Option<String>,
+    pub dex: Option<Dexes>,
+    pub limit: Option<usize>,
+}
+
+/// A ring buffer of the last skipped swaps, the lock is only held to push or copy entries
+#[derive(Debug)]
+pub struct SkippedSwapLog {
+    entries: Mutex<VecDeque<SkippedSwap>>,
+    capacity: usize,
+}
+
+impl Default for SkippedSwapLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_SKIPPED_SWAPS_CAPACITY)
+    }
+}
+
+impl SkippedSwapLog {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { entries: Mutex::new(VecDeque::with_capacity(capacity)), capacity }
+    }
+
+    /// record keeps the skipped swap, dropping the oldest one once full
+    pub fn record(&self, swap: SkippedSwap) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(swap);
+    }
+
+    /// query returns the matching skipped swaps, newest first
+    pub fn query(&self, query: &SkippedSwapQuery) -> Vec<SkippedSwap> {
+        let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_SWAPS_LIMIT);
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .rev()
+            .filter(|swap| query.reason.as_ref().is_none_or(|reason| swap.reason == *reason))
+            .filter(|swap| query.dex.is_none_or(|dex| swap.dex == dex))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// find returns the skipped swaps of a transaction, newest first,
+    /// a transaction may hold several swaps
+    pub fn find(&self, signature: &str) -> Vec<SkippedSwap> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().rev().filter(|swap| swap.signature == signature).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skipped(signature: &str, dex: Dexes, e: &SwapError) -> SkippedSwap {
+        SkippedSwap::new(signature, "pool", dex, e, 1_700_000_000)
+    }
+
+    #[test]
+    fn test_skipped_swap_log_is_bounded() {
+        let log = SkippedSwapLog::new(2);
+        for signature in ["first", "second", "third"] {
+            log.record(skipped(signature, Dexes::PumpAmm, &SwapError::TinySwap));
+        }
+        assert_eq!(log.len(), 2);
+        let signatures: Vec<_> = log
+            .query(&SkippedSwapQuery::default())
+            .into_iter()
+            .map(|swap| swap.signature)
+            .collect();
+        assert_eq!(signatures, vec!["third", "second"]);
+        assert!(log.find("first").is_empty());
+    }
+
+    #[test]
+    fn test_skipped_swap_detail() {
+        let e = SwapError::DbInsertFailure(anyhow::anyhow!("too many parts").context("insert"));
+        let swap = skipped("sig", Dexes::RaydiumCpmm, &e);
+        assert_eq!(swap.reason, "db_insert_failure");
+        assert_eq!(swap.detail.as_deref(), Some("insert: too many parts"));
+        assert_eq!(skipped("sig", Dexes::RaydiumCpmm, &SwapError::ZeroSwap).detail, None);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR};
+use sonar_db::EnvReader;
+use std::{collections::HashMap, time::Duration};
+
+const DEFAULT_MIN_SWAP_UI_AMOUNT: f64 = 0.01; // 0.01 SOL
+const DEFAULT_MIN_SWAP_USD: f64 = 0.1; // 0.1 USDC
+/// The deepest level of nested instructions walked for transfers, the given instructions
+/// being the first level
+pub const DEFAULT_MAX_INSTRUCTION_DEPTH: usize = 8;
+const DEFAULT_WASH_TRADE_WINDOW: Duration = Duration::from_secs(30);
+const DEFAULT_ONCHAIN_SOL_PRICE_MIN_QUOTE_AMOUNT: f64 = 100.0;
+const DEFAULT_QUOTE_PRICE_MAX_STALENESS: Duration = Duration::from_secs(300);
+const DEFAULT_PAIR_SEEN_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// What happens to a swap between two majors, e.g. USDC/USDT
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotePairPolicy {
+    /// The swap is skipped and counted
+    #[default]
+    Skip,
+    /// The swap is stored with `is_quote_pair` set
+    Flag,
+}
+
+/// Thresholds below which swaps are skipped, a threshold of 0 disables the filter,
+/// and the windows the kept swaps are processed with
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapFilterConfig {
+    /// A swap is skipped if all of its transfers are below this ui amount
+    pub min_ui_amount: f64,
+    /// Per-mint overrides of `min_ui_amount`, keyed by mint address
+    pub min_ui_amount_overrides: HashMap<String, f64>,
+    /// A swap is skipped if its usd amount is below this value
+    pub min_swap_usd: f64,
+    /// What happens to the swaps between two majors
+    pub quote_pair_policy: QuotePairPolicy,
+    /// The transfers nested deeper than this level of instructions are ignored
+    pub max_instruction_depth: usize,
+    /// Swapping both sides of a pair within this window is tagged as a wash trade
+    pub wash_trade_window: Duration,
+    /// WSOL/stable swaps below this quote amount don't update the SOL price cache
+    pub onchain_sol_price_min_quote_amount: f64,
+    /// A KV price of a generic quote mint older than this, relative to the swap, is ignored
+    /// in favour of the database
+    pub quote_price_max_staleness: Duration,
+    /// A pair is recorded again once its seen marker expires, the pairs table keeps the
+    /// earliest
+    pub pair_seen_ttl: Duration,
+}
+
+impl Default for SwapFilterConfig {
+    fn default() -> Self {
+        Self {
+            min_ui_amount: DEFAULT_MIN_SWAP_UI_AMOUNT,
+            min_ui_amount_overrides: HashMap::new(),
+            min_swap_usd: DEFAULT_MIN_SWAP_USD,
+            quote_pair_policy: QuotePairPolicy::default(),
+            max_instruction_depth: DEFAULT_MAX_INSTRUCTION_DEPTH,
+            wash_trade_window: DEFAULT_WASH_TRADE_WINDOW,
+            onchain_sol_price_min_quote_amount: DEFAULT_ONCHAIN_SOL_PRICE_MIN_QUOTE_AMOUNT,
+            quote_price_max_staleness: DEFAULT_QUOTE_PRICE_MAX_STALENESS,
+            pair_seen_ttl: DEFAULT_PAIR_SEEN_TTL,
+        }
+    }
+}
+
+impl SwapFilterConfig {
+    /// read_env reads the swap filters, recording the invalid variables
+    ///
+    /// * `MIN_SWAP_UI_AMOUNT` - defaults to 0.01
+    /// * `MIN_SWAP_USD` - defaults to 0.1
+    /// * `MIN_SWAP_UI_AMOUNT_SOL`, `MIN_SWAP_UI_AMOUNT_USDC`, `MIN_SWAP_UI_AMOUNT_USDT` -
+    ///   optional per-quote-mint overrides of `MIN_SWAP_UI_AMOUNT`
+    /// * `QUOTE_PAIR_SWAPS` - `skip` or `flag` the swaps between two majors, defaults to skip
+    /// * `MAX_INSTRUCTION_DEPTH` - the deepest nested instruction walked for transfers,
+    ///   defaults to 8
+    /// * `WASH_TRADE_WINDOW_SECS` - defaults to 30
+    /// * `ONCHAIN_SOL_PRICE_MIN_QUOTE_AMOUNT` - defaults to 100
+    /// * `QUOTE_PRICE_MAX_STALENESS_SECS` - defaults to 300
+    /// * `PAIR_SEEN_TTL_SECS` - defaults to a week
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let mut parse = |key: &str| {
+            let amount = env.parse::<f64>(key)?;
+            env.check(amount >= 0.0, format!("{key} must not be negative, got {amount}"));
+            Some(amount)
+        };
+        let min_ui_amount_overrides = [
+            ("MIN_SWAP_UI_AMOUNT_SOL", WSOL_MINT_KEY_STR),
+            ("MIN_SWAP_UI_AMOUNT_USDC", USDC_MINT_KEY_STR),
+            ("MIN_SWAP_UI_AMOUNT_USDT", USDT_MINT_KEY_STR),
+        ]
+        .into_iter()
+        .filter_map(|(key, mint)| parse(key).map(|amount| (mint.to_string(), amount)))
+        .collect();
+        let min_ui_amount = parse("MIN_SWAP_UI_AMOUNT").unwrap_or(DEFAULT_MIN_SWAP_UI_AMOUNT);
+        let min_swap_usd = parse("MIN_SWAP_USD").unwrap_or(DEFAULT_MIN_SWAP_USD);
+        let onchain_sol_price_min_quote_amount = parse("ONCHAIN_SOL_PRICE_MIN_QUOTE_AMOUNT")
+            .unwrap_or(DEFAULT_ONCHAIN_SOL_PRICE_MIN_QUOTE_AMOUNT);
+
+        let quote_pair_policy = match env.var("QUOTE_PAIR_SWAPS").as_deref() {
+            Some("flag") => QuotePairPolicy::Flag,
+            Some("skip") | None => QuotePairPolicy::Skip,
+            Some(other) => {
+                env.error(format!("QUOTE_PAIR_SWAPS must be skip or flag, got {other:?}"));
+                QuotePairPolicy::Skip
+            }
+        };
+        let max_instruction_depth =
+            env.parse_or("MAX_INSTRUCTION_DEPTH", DEFAULT_MAX_INSTRUCTION_DEPTH);
+        env.check(
+            max_instruction_depth > 0,
+            format!("MAX_INSTRUCTION_DEPTH must be positive, got {max_instruction_depth}"),
+        );
+        Self {
+            min_ui_amount,
+            min_ui_amount_overrides,
+            min_swap_usd,
+            quote_pair_policy,
+            max_instruction_depth,
+            wash_trade_window: env
+                .secs("WASH_TRADE_WINDOW_SECS")
+                .unwrap_or(DEFAULT_WASH_TRADE_WINDOW),
+            onchain_sol_price_min_quote_amount,
+            quote_price_max_staleness: env
+                .secs("QUOTE_PRICE_MAX_STALENESS_SECS")
+                .unwrap_or(DEFAULT_QUOTE_PRICE_MAX_STALENESS),
+            pair_seen_ttl: env.secs("PAIR_SEEN_TTL_SECS").unwrap_or(DEFAULT_PAIR_SEEN_TTL),
+        }
+    }
+
+    /// Returns the minimum ui amount of a transfer of `mint`
+    pub fn min_ui_amount_for(&self, mint: &str) -> f64 {
+        self.min_ui_amount_overrides.get(mint).copied().unwrap_or(self.min_ui_amount)
+    }
+
+    /// Returns true if a transfer of `ui_amount` of `mint` is below the threshold
+    pub fn is_tiny_transfer(&self, mint: &str, ui_amount: f64) -> bool {
+        ui_amount < self.min_ui_amount_for(mint)
+    }
+
+    /// Returns true if a swap of `swap_amount` usd is below the threshold
+    pub fn is_tiny_swap_usd(&self, swap_amount: f64) -> bool {
+        swap_amount < self.min_swap_usd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "2Y6GkQJR93PNL1iYwGcjggoaBRaeTM1p9pC7oCzTpump";
+
+    #[test]
+    fn test_default_config() {
+        let config = SwapFilterConfig::default();
+        assert!(config.is_tiny_transfer(WSOL_MINT_KEY_STR, 0.001));
+        assert!(!config.is_tiny_transfer(WSOL_MINT_KEY_STR, 0.01));
+        assert!(config.is_tiny_transfer(TOKEN, 0.001));
+        assert!(config.is_tiny_swap_usd(0.05));
+        assert!(!config.is_tiny_swap_usd(0.1));
+    }
+
+    #[test]
+    fn test_zero_disables_filters() {
+        let config = SwapFilterConfig {
+            min_ui_amount: 0.0,
+            min_ui_amount_overrides: HashMap::new(),
+            min_swap_usd: 0.0,
+            quote_pair_policy: QuotePairPolicy::Skip,
+            ..Default::default()
+        };
+        assert!(!config.is_tiny_transfer(WSOL_MINT_KEY_STR, 0.0));
+        assert!(!config.is_tiny_transfer(TOKEN, 0.000001));
+        assert!(!config.is_tiny_swap_usd(0.0));
+    }
+
+    #[test]
+    fn test_per_quote_mint_overrides() {
+        let config = SwapFilterConfig {
+            min_ui_amount: 0.01,
+            min_ui_amount_overrides: HashMap::from([
+                (USDC_MINT_KEY_STR.to_string(), 1.0),
+                (WSOL_MINT_KEY_STR.to_string(), 0.0),
+            ]),
+            min_swap_usd: 0.1,
+            quote_pair_policy: QuotePairPolicy::Skip,
+            ..Default::default()
+        };
+        assert!(config.is_tiny_transfer(USDC_MINT_KEY_STR, 0.5));
+        assert!(!config.is_tiny_transfer(USDC_MINT_KEY_STR, 1.0));
+        assert!(!config.is_tiny_transfer(WSOL_MINT_KEY_STR, 0.0001));
+        // mints without override use the default threshold
+        assert!(config.is_tiny_transfer(USDT_MINT_KEY_STR, 0.001));
+        assert!(config.is_tiny_transfer(TOKEN, 0.001));
+    }
+}
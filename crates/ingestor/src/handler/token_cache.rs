@@ -0,0 +1,208 @@
+//! this file keeps the metadata of the recently swapped tokens in memory, saving the KV round
+//! trip of the metadata lookup of every swap
+use sonar_db::models::Token;
+use std::{
+    collections::{HashMap, VecDeque},
+    mem::size_of,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How many tokens are cached by default, about 20MB of a few hundred bytes each
+pub const DEFAULT_TOKEN_CACHE_CAPACITY: usize = 50_000;
+/// How long a token is served from the cache by default, the refreshes of the other
+/// processes are picked up once it expires
+pub const DEFAULT_TOKEN_CACHE_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+struct CachedToken {
+    token: Token,
+    inserted_at: Instant,
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    tokens: HashMap<String, CachedToken>,
+    /// The mints in insertion order, a mint cached again is pushed again with a new generation
+    order: VecDeque<(String, u64, Instant)>,
+    generation: u64,
+    bytes: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, mint: &str) {
+        if let Some(cached) = self.tokens.remove(mint) {
+            self.bytes -= token_bytes(mint, &cached.token);
+        }
+    }
+}
+
+/// A bounded cache of the token metadata, the oldest tokens are evicted first once it is
+/// full or they expired, the lock is only held to copy or insert a token
+#[derive(Debug)]
+pub struct TokenCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl Default for TokenCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_CACHE_CAPACITY, DEFAULT_TOKEN_CACHE_TTL)
+    }
+}
+
+impl TokenCache {
+    /// new caches up to `capacity` tokens for `ttl`, nothing is cached with a zero capacity
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self { entries: Mutex::new(Entries::default()), capacity, ttl }
+    }
+
+    /// get returns the cached token of a mint, None if it isn't cached or expired
+    pub fn get(&self, mint: &str) -> Option<Token> {
+        self.get_at(mint, Instant::now())
+    }
+
+    /// insert caches a token, replacing the cached one of its mint
+    pub fn insert(&self, token: Token) {
+        self.insert_at(token, Instant::now())
+    }
+
+    /// invalidate drops the cached token of a mint, the next lookup resolves it again
+    pub fn invalidate(&self, mint: &str) {
+        self.entries.lock().unwrap().remove(mint);
+    }
+
+    /// len returns the number of cached tokens, the expired ones included until evicted
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// bytes returns the estimated memory of the cached tokens
+    pub fn bytes(&self) -> usize {
+        self.entries.lock().unwrap().bytes
+    }
+
+    fn get_at(&self, mint: &str, now: Instant) -> Option<Token> {
+        let mut entries = self.entries.lock().unwrap();
+        let cached = entries.tokens.get(mint)?;
+        if now.duration_since(cached.inserted_at) < self.ttl {
+            return Some(cached.token.clone());
+        }
+        entries.remove(mint);
+        None
+    }
+
+    fn insert_at(&self, token: Token, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let mint = token.token.clone();
+        entries.remove(&mint);
+        entries.generation += 1;
+        let generation = entries.generation;
+        entries.bytes += token_bytes(&mint, &token);
+        entries.order.push_back((mint.clone(), generation, now));
+        entries.tokens.insert(mint, CachedToken { token, inserted_at: now, generation });
+
+        // the order only holds stale mints until they reach its front, bounding both by the
+        // capacity keeps the memory bounded whatever the churn
+        while let Some((mint, generation, inserted_at)) = entries.order.front().cloned() {
+            let expired = now.duration_since(inserted_at) >= self.ttl;
+            if entries.order.len() <= self.capacity && !expired {
+                break;
+            }
+            entries.order.pop_front();
+            if entries.tokens.get(&mint).is_some_and(|cached| cached.generation == generation) {
+                entries.remove(&mint);
+            }
+        }
+    }
+}
+
+/// token_bytes estimates the memory of a cached token, its strings included
+fn token_bytes(mint: &str, token: &Token) -> usize {
+    let strings = [
+        &token.token,
+        &token.update_authority,
+        &token.name,
+        &token.symbol,
+        &token.uri,
+        &token.launchpad,
+        &token.graduation_pool,
+        &token.launch_pool,
+    ];
+    size_of::<CachedToken>()
+        + size_of::<(String, u64, Instant)>()
+        + 2 * mint.len()
+        + strings.iter().map(|s| s.capacity()).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(mint: &str, supply: f64) -> Token {
+        Token { supply, ..sonar_db::test_utils::make_token(mint, "TKN", "Token") }
+    }
+
+    #[test]
+    fn test_token_cache_hits_and_expires() {
+        let cache = TokenCache::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(cache.get_at("mint", now).is_none());
+
+        cache.insert_at(token("mint", 1_000.0), now);
+        let hit = cache.get_at("mint", now + Duration::from_secs(59)).unwrap();
+        assert_eq!(hit.supply, 1_000.0);
+
+        // an expired token is dropped on lookup
+        assert!(cache.get_at("mint", now + Duration::from_secs(60)).is_none());
+        assert!(cache.is_empty());
+        assert_eq!(cache.bytes(), 0);
+
+        // a token cached again replaces the previous one and is invalidated on demand
+        cache.insert_at(token("mint", 1_000.0), now);
+        cache.insert_at(token("mint", 2_000.0), now + Duration::from_secs(30));
+        assert_eq!(cache.get_at("mint", now + Duration::from_secs(80)).unwrap().supply, 2_000.0);
+        cache.invalidate("mint");
+        assert!(cache.get_at("mint", now + Duration::from_secs(80)).is_none());
+    }
+
+    #[test]
+    fn test_token_cache_is_bounded() {
+        let cache = TokenCache::new(100, Duration::from_secs(60));
+        let now = Instant::now();
+        for i in 0..1_000 {
+            cache.insert_at(token(&format!("mint-{i}"), i as f64), now);
+        }
+        assert_eq!(cache.len(), 100);
+        // the oldest tokens are evicted first
+        assert!(cache.get_at("mint-899", now).is_none());
+        assert!(cache.get_at("mint-900", now).is_some());
+        // a few hundred bytes per token
+        let per_token = cache.bytes() / cache.len();
+        assert!((200..1_000).contains(&per_token), "{per_token} bytes per token");
+
+        // re-caching the same mint doesn't grow the cache
+        for i in 0..1_000 {
+            cache.insert_at(token("mint-999", i as f64), now);
+        }
+        assert!(cache.len() <= 100);
+        assert_eq!(cache.entries.lock().unwrap().order.len(), 100);
+
+        // the expired tokens are evicted by the next insert
+        cache.insert_at(token("fresh", 1.0), now + Duration::from_secs(60));
+        assert_eq!(cache.len(), 1);
+
+        let disabled = TokenCache::new(0, Duration::from_secs(60));
+        disabled.insert(token("mint", 1.0));
+        assert!(disabled.get("mint").is_none());
+    }
+}
@@ -1,9 +1,18 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, USDT_SET, WSOL_MINT_KEY_STR},
+    config::Config,
+    constants::{
+        Dexes, PUMP_LAUNCHPAD, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, USDT_SET, WSOL_MINT_KEY_STR,
+    },
     decoder::{
-        extra_mint_details_from_tx_metadata, MintDetail, TokenTransferDetails, SPL_TOKEN_DECODER,
+        extra_mint_details_from_tx_metadata, is_native_transfer, merge_native_transfers,
+        token_account_owner, MintDetails, TokenTransferDetails, SPL_TOKEN_DECODER,
+        SYSTEM_TRANSFER_DECODER,
+    },
+    handler::{
+        QuotePairPolicy, SkippedSwap, SkippedSwapLog, SwapFilterConfig, TokenCache,
+        DEFAULT_MAX_INSTRUCTION_DEPTH,
     },
-    metrics::NodeMetrics,
+    metrics::{NodeMetrics, SwapStage},
 };
 use anyhow::Result;
 // use backon::{ExponentialBuilder, Retryable};
@@ -12,21 +21,34 @@ use carbon_core::{
     transaction::TransactionMetadata,
 };
 use chrono::Utc;
-use sonar_db::{models::NewPoolEvent, Database, KvStore, MessageQueue, SwapEvent, Trade};
-use sonar_sol_price::get_sol_price;
+use solana_transaction_status::TransactionTokenBalance;
+use sonar_db::{
+    is_major_mint, is_schema_mismatch,
+    models::{NewPoolEvent, Pair, Token, TokenGraduatedEvent},
+    Database, KvStore, MessageQueue, SwapEvent, Trade, TradeV2,
+};
+use sonar_sol_price::{get_sol_price, SolPriceCacheTrait};
 use sonar_token_metadata::get_token_metadata_with_data;
-use std::collections::HashMap;
-use std::{collections::HashSet, sync::Arc};
-use tracing::{debug, error};
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_util::task::TaskTracker;
+use tracing::{debug, debug_span, error, field, info_span, warn, Instrument};
 
-const TINY_SWAP_UI_AMOUNT: f64 = 0.01; // 0.01 SOL
-const TINY_SWAP_AMOUNT: f64 = 0.1; // 0.1 USDC
+/// A SOL price cache updated from the WSOL/stable swaps
+pub type SolPriceCacheRef = Arc<dyn SolPriceCacheTrait + Send + Sync>;
 
 #[derive(Clone)]
 pub struct TokenSwapAccounts {
     pub pair: String,
     pub user_adas: HashSet<String>,
     pub vault_adas: HashSet<String>,
+    /// The fee accounts of the pool, their transfers are fees and not swap legs. None for
+    /// the pools accruing the swap fees in their vaults, without a separate transfer
     pub fee_adas: Option<HashSet<String>>,
     pub quote_mints: Arc<HashSet<String>>,
 }
@@ -37,52 +59,159 @@ pub struct TokenSwapHandler {
     pub message_queue: Arc<MessageQueue>,
     pub db: Arc<Database>,
     pub metrics: Arc<NodeMetrics>,
+    pub swap_filter_config: Arc<SwapFilterConfig>,
+    pub sol_price_cache: Option<SolPriceCacheRef>,
+    pub swap_process_timeout: Duration,
+    /// The share of the token supply from which a trade is also published as a large trade
+    pub large_trade_pct: f64,
+    /// The last skipped swaps, served by the debug server
+    pub skipped_swaps: Arc<SkippedSwapLog>,
+    /// The metadata of the recently swapped tokens, in front of the KV store
+    pub token_cache: Arc<TokenCache>,
+    /// The spawned swap tasks, drained on shutdown before the db is closed
+    pub tasks: TaskTracker,
 }
 
 impl TokenSwapHandler {
+    /// new returns a handler processing the swaps with the filters, the timeout, the large
+    /// trade share and the token cache of `config`
     pub fn new(
         kv_store: Arc<KvStore>,
         message_queue: Arc<MessageQueue>,
         db: Arc<Database>,
         metrics: Arc<NodeMetrics>,
+        config: &Config,
     ) -> Self {
-        Self { kv_store, message_queue, db, metrics }
+        Self {
+            kv_store,
+            message_queue,
+            db,
+            metrics,
+            swap_filter_config: Arc::new(config.swap_filter.clone()),
+            sol_price_cache: None,
+            swap_process_timeout: config.swap_process_timeout,
+            large_trade_pct: config.large_trade_pct,
+            skipped_swaps: Arc::new(SkippedSwapLog::default()),
+            token_cache: Arc::new(TokenCache::new(
+                config.token_cache_capacity,
+                config.token_cache_ttl,
+            )),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    /// set the thresholds below which swaps are skipped
+    pub fn with_swap_filter_config(mut self, swap_filter_config: SwapFilterConfig) -> Self {
+        self.swap_filter_config = Arc::new(swap_filter_config);
+        self
+    }
+
+    /// set the SOL price cache updated with the prices of the WSOL/USDC and WSOL/USDT swaps
+    pub fn with_sol_price_cache(mut self, sol_price_cache: SolPriceCacheRef) -> Self {
+        self.sol_price_cache = Some(sol_price_cache);
+        self
+    }
+
+    /// set the log of the skipped swaps, shared with the debug server or a replay
+    pub fn with_skipped_swaps(mut self, skipped_swaps: Arc<SkippedSwapLog>) -> Self {
+        self.skipped_swaps = skipped_swaps;
+        self
+    }
+
+    /// set the cache of the token metadata looked up by the swaps
+    pub fn with_token_cache(mut self, token_cache: Arc<TokenCache>) -> Self {
+        self.token_cache = token_cache;
+        self
+    }
+
+    /// set the tracker of the spawned swap tasks, shared with the shutdown
+    pub fn with_task_tracker(mut self, tasks: TaskTracker) -> Self {
+        self.tasks = tasks;
+        self
     }
 
-    #[allow(clippy::too_many_arguments)]
+    /// is_healthy returns false once the storage failed in a way retrying can't fix,
+    /// the handler then rejects every swap
+    pub fn is_healthy(&self) -> bool {
+        self.metrics.is_healthy()
+    }
+
+    /// spawn_swap_instruction processes the swap in a task, traced in a `swap` span
+    /// child of the current span
     pub fn spawn_swap_instruction(
         &self,
+        dex: Dexes,
         token_swap_accounts: &TokenSwapAccounts,
         meta: &InstructionMetadata,
         nested_instructions: &[NestedInstruction],
     ) {
         debug!("https://solscan.io/tx/{}", meta.transaction_metadata.signature);
 
+        if !self.is_healthy() {
+            self.metrics.increment_rejected_swaps();
+            return;
+        }
+
         let message_queue = self.message_queue.clone();
         let kv_store = self.kv_store.clone();
         let db = self.db.clone();
         let metrics = self.metrics.clone();
+        let swap_filter_config = self.swap_filter_config.clone();
+        let sol_price_cache = self.sol_price_cache.clone();
         let token_swap_accounts = token_swap_accounts.clone();
         let transaction_metadata = meta.transaction_metadata.clone();
         let nested_instructions = nested_instructions.to_vec();
+        let swap_process_timeout = self.swap_process_timeout;
+        let large_trade_pct = self.large_trade_pct;
+        let skipped_swaps = self.skipped_swaps.clone();
+        let token_cache = self.token_cache.clone();
 
         metrics.increment_total_swaps();
+        metrics.record_processed_slot(transaction_metadata.slot);
 
-        tokio::spawn(async move {
-            match process_token_swap_instruction(
+        let span = info_span!(
+            "swap",
+            signature = %transaction_metadata.signature,
+            pair = %token_swap_accounts.pair,
+            %dex
+        );
+        let task = async move {
+            let process = process_token_swap_instruction(
+                dex,
                 &token_swap_accounts,
                 &transaction_metadata,
                 &nested_instructions,
                 &message_queue,
                 &kv_store,
                 &db,
+                &token_cache,
                 &metrics,
-            )
-            .await
-            {
+                &skipped_swaps,
+                &swap_filter_config,
+                sol_price_cache.as_ref(),
+                large_trade_pct,
+            );
+            let result = process_with_timeout(process, swap_process_timeout, &metrics).await;
+            if let Err(e) = &result {
+                let signature = transaction_metadata.signature.to_string();
+                let timestamp = Utc::now().timestamp();
+                let skipped =
+                    SkippedSwap::new(&signature, &token_swap_accounts.pair, dex, e, timestamp);
+                skipped_swaps.record(skipped);
+            }
+            match result {
                 Ok(_) => {
                     metrics.increment_succeed_swaps();
                 }
+                Err(SwapError::Timeout) => {
+                    metrics.increment_failed_swaps();
+                    error!(
+                        signature = %transaction_metadata.signature,
+                        slot = transaction_metadata.slot,
+                        timeout_secs = swap_process_timeout.as_secs(),
+                        "Swap processing timed out, the transaction needs to be reprocessed"
+                    );
+                }
                 Err(e) => {
                     metrics.increment_failed_swaps();
                     error!(
@@ -91,17 +220,102 @@ impl TokenSwapHandler {
                     );
                 }
             }
-        });
+        };
+        self.tasks.spawn(task.instrument(span));
     }
 
     pub fn spawn_new_pool_instruction(&self, _meta: &InstructionMetadata, event: NewPoolEvent) {
         let message_queue = self.message_queue.clone();
-        tokio::spawn(async move {
+        self.tasks.spawn(async move {
             if let Err(e) = message_queue.publish_new_pool(&event).await {
                 error!("Failed to publish new pool event: {:?}", e);
             }
         });
     }
+
+    /// spawn_launch_instruction publishes the launch pool of a token and records its launch
+    pub fn spawn_launch_instruction(&self, meta: &InstructionMetadata, event: NewPoolEvent) {
+        self.spawn_new_pool_instruction(meta, event.clone());
+        let kv_store = self.kv_store.clone();
+        let db = self.db.clone();
+        let token_cache = self.token_cache.clone();
+        self.tasks.spawn(async move {
+            let (mint, pool) = (&event.token_a_mint, &event.pool);
+            let recorded = record_token_launch(
+                mint,
+                event.dex,
+                pool,
+                event.timestamp,
+                &kv_store,
+                &db,
+                &token_cache,
+            );
+            if let Err(e) = recorded.await {
+                error!(?e, %mint, "Failed to record the launch of the token");
+            }
+        });
+    }
+
+    /// spawn_graduation_instruction records the graduation of a token and publishes it
+    pub fn spawn_graduation_instruction(&self, event: TokenGraduatedEvent) {
+        let kv_store = self.kv_store.clone();
+        let message_queue = self.message_queue.clone();
+        let db = self.db.clone();
+        let token_cache = self.token_cache.clone();
+        self.tasks.spawn(async move {
+            let recorded =
+                record_token_graduation(&event, &kv_store, &message_queue, &db, &token_cache);
+            if let Err(e) = recorded.await {
+                error!(?e, mint = %event.mint, "Failed to record the graduation of the token");
+            }
+        });
+    }
+}
+
+/// record_token_launch stores where and when a token was launched, returns false when its
+/// launch is already recorded, e.g. when the launch instruction is delivered again
+pub async fn record_token_launch(
+    mint: &str,
+    dex: Dexes,
+    pool: &str,
+    timestamp: u64,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    token_cache: &TokenCache,
+) -> Result<bool> {
+    let mut token = get_token_metadata_with_data(mint, kv_store, db).await?;
+    if !token.launch_pool.is_empty() {
+        return Ok(false);
+    }
+    token.launch_dex = Some(dex);
+    token.launch_pool = pool.to_string();
+    token.launch_timestamp = timestamp;
+    db.set_token_launch_info(mint, dex, pool, timestamp).await?;
+    kv_store.set_token(mint, &token).await?;
+    token_cache.insert(token);
+    Ok(true)
+}
+
+/// record_token_graduation stores the graduation on the token and publishes it,
+/// returns false when the token already graduated
+pub async fn record_token_graduation(
+    event: &TokenGraduatedEvent,
+    kv_store: &Arc<KvStore>,
+    message_queue: &Arc<MessageQueue>,
+    db: &Arc<Database>,
+    token_cache: &TokenCache,
+) -> Result<bool> {
+    let mut token = get_token_metadata_with_data(&event.mint, kv_store, db).await?;
+    if token.graduated_at != 0 {
+        return Ok(false);
+    }
+    token.graduated_at = event.timestamp;
+    token.graduation_pool = event.pool.clone();
+    db.mark_token_graduated(&event.mint, &event.pool, event.timestamp).await?;
+    kv_store.set_token(&event.mint, &token).await?;
+    token_cache.insert(token);
+    message_queue.publish_token_graduated(event).await?;
+    Ok(true)
 }
 
 pub struct SwapResult {
@@ -120,10 +334,18 @@ pub enum SwapError {
     ExpectedTwoTokenSwaps,
     #[error("Tiny swap")]
     TinySwap,
+    #[error("Tiny swap in usd")]
+    TinySwapUsd,
     #[error("Zero swap")]
     ZeroSwap,
     #[error("Unexpected swap")]
     UnexpectedSwap,
+    #[error("Unpriced quote")]
+    UnpricedQuote,
+    #[error("Invalid price")]
+    InvalidPrice,
+    #[error("Swap between two majors")]
+    QuotePairSwap,
     #[error("Db insert failure")]
     DbInsertFailure(anyhow::Error),
     #[error("Message send failure")]
@@ -132,6 +354,40 @@ pub enum SwapError {
     KvInsertFailure(anyhow::Error),
     #[error("Token metadata failure")]
     TokenMetadataFailure(anyhow::Error),
+    #[error("Swap processing timed out")]
+    Timeout,
+}
+
+impl SwapError {
+    /// reason returns the snake case name of the error, recorded with the skipped swaps
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SwapError::ExpectedTwoTokenSwaps => "expected_two_token_swaps",
+            SwapError::TinySwap => "tiny_swap",
+            SwapError::TinySwapUsd => "tiny_swap_usd",
+            SwapError::ZeroSwap => "zero_swap",
+            SwapError::UnexpectedSwap => "unexpected_swap",
+            SwapError::UnpricedQuote => "unpriced_quote",
+            SwapError::InvalidPrice => "invalid_price",
+            SwapError::QuotePairSwap => "quote_pair_swap",
+            SwapError::DbInsertFailure(_) => "db_insert_failure",
+            SwapError::MessageSendFailure(_) => "message_send_failure",
+            SwapError::KvInsertFailure(_) => "kv_insert_failure",
+            SwapError::TokenMetadataFailure(_) => "token_metadata_failure",
+            SwapError::Timeout => "timeout",
+        }
+    }
+
+    /// source_error returns the error of the storage or the RPC behind a failure
+    pub fn source_error(&self) -> Option<&anyhow::Error> {
+        match self {
+            SwapError::DbInsertFailure(e)
+            | SwapError::MessageSendFailure(e)
+            | SwapError::KvInsertFailure(e)
+            | SwapError::TokenMetadataFailure(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 /// Updates the metrics for a swap error.
@@ -143,20 +399,82 @@ pub enum SwapError {
 fn update_metrics_for_swap_error(metrics: &NodeMetrics, e: SwapError) {
     match e {
         SwapError::TinySwap => metrics.increment_skipped_tiny_swaps(),
+        SwapError::TinySwapUsd => metrics.increment_skipped_tiny_usd_swaps(),
         SwapError::ZeroSwap => metrics.increment_skipped_zero_swaps(),
         SwapError::TokenMetadataFailure(_) => metrics.increment_skipped_no_metadata(),
         SwapError::UnexpectedSwap => metrics.increment_skipped_unexpected_swaps(),
+        SwapError::UnpricedQuote => metrics.increment_skipped_unpriced_quote(),
+        SwapError::InvalidPrice => metrics.increment_skipped_invalid_price(),
+        SwapError::QuotePairSwap => metrics.increment_skipped_quote_pair_swaps(),
         SwapError::ExpectedTwoTokenSwaps => metrics.increment_skipped_unknown_swaps(),
         SwapError::DbInsertFailure(_) => metrics.increment_db_insert_failure(),
         SwapError::MessageSendFailure(_) => metrics.increment_message_send_failure(),
         SwapError::KvInsertFailure(_) => metrics.increment_kv_insert_failure(),
+        SwapError::Timeout => metrics.increment_timed_out_swaps(),
+    }
+}
+
+/// Keeps a skipped swap in the log and classifies its error in the metrics
+fn record_skipped_swap(
+    metrics: &NodeMetrics,
+    skipped_swaps: &SkippedSwapLog,
+    dex: Dexes,
+    pair: &str,
+    signature: &str,
+    e: SwapError,
+) {
+    skipped_swaps.record(SkippedSwap::new(signature, pair, dex, &e, Utc::now().timestamp()));
+    update_metrics_for_swap_error(metrics, e);
+}
+
+/// Runs the processing of a swap within `timeout`. A swap running out of time is dropped,
+/// cancelling the pending RPC and storage calls, and counted as timed out.
+pub async fn process_with_timeout<F>(
+    process: F,
+    timeout: Duration,
+    metrics: &NodeMetrics,
+) -> Result<(), SwapError>
+where
+    F: Future<Output = Result<(), SwapError>>,
+{
+    match tokio::time::timeout(timeout, process).await {
+        Ok(result) => result,
+        Err(_) => {
+            update_metrics_for_swap_error(metrics, SwapError::Timeout);
+            Err(SwapError::Timeout)
+        }
+    }
+}
+
+/// Runs a stage of the processing of a swap in its own span, recording its duration
+/// as the `duration_us` of the span and in the stage timings of the metrics
+async fn timed<F: Future>(stage: SwapStage, metrics: &NodeMetrics, future: F) -> F::Output {
+    let span = debug_span!("swap_stage", stage = stage.as_str(), duration_us = field::Empty);
+    let started_at = Instant::now();
+    let output = future.instrument(span.clone()).await;
+    let elapsed = started_at.elapsed();
+    span.record("duration_us", elapsed.as_micros() as u64);
+    metrics.record_stage_duration(stage, elapsed);
+    output
+}
+
+/// Reacts to a failed storage write. A schema mismatch flags the storage as failed so no more
+/// swaps are accepted, connection errors and timeouts are left to the retries of the storage.
+fn handle_storage_error(metrics: &NodeMetrics, e: &anyhow::Error) {
+    if is_schema_mismatch(e) && metrics.mark_storage_fatal() {
+        error!(
+            ?e,
+            "Storage schema mismatch, rejecting swaps until the schema is migrated \
+             and the ingestor restarted"
+        );
     }
 }
 
 /// Extracts all token transfers from a transaction's nested instructions.
 ///
 /// This function processes both the outer instructions and all nested inner instructions
-/// recursively to collect all token transfers that occurred in the transaction.
+/// recursively to collect all token transfers that occurred in the transaction, down to
+/// `DEFAULT_MAX_INSTRUCTION_DEPTH` levels.
 ///
 /// # Arguments
 ///
@@ -170,44 +488,150 @@ pub fn get_inner_token_transfers(
     transaction_metadata: &TransactionMetadata,
     nested_instructions: &[NestedInstruction],
 ) -> Vec<TokenTransferDetails> {
-    let mint_details = extra_mint_details_from_tx_metadata(transaction_metadata);
-    recursive_inner_token_transfers(nested_instructions, &mint_details)
+    get_inner_token_transfers_with_vaults(
+        transaction_metadata,
+        nested_instructions,
+        &HashSet::new(),
+        DEFAULT_MAX_INSTRUCTION_DEPTH,
+    )
 }
 
-/// Recursively processes nested instructions to extract token transfers.
+/// Extracts all token transfers, including native SOL transfers into or out of `sol_vaults`.
 ///
-/// This internal function handles the recursive traversal of nested instructions
-/// to collect all token transfers. It processes both the current level instructions
-/// and recursively processes all nested instructions.
+/// Native SOL transfers touching a WSOL token account or one of `sol_vaults` are synthesized
+/// into WSOL transfers and merged with the SPL transfers. The duplicated instructions and the
+/// ones nested deeper than `max_depth` are skipped, see `walk_inner_token_transfers`.
 ///
 /// # Arguments
 ///
-/// * `nested_instructions` - The list of instructions to process
-/// * `mint_details` - A map containing mint account details for token identification
+/// * `transaction_metadata` - The metadata of the transaction containing account information
+/// * `nested_instructions` - The list of instructions to process, including their nested instructions
+/// * `sol_vaults` - The accounts holding native SOL for the pool, e.g. the pool vaults
+/// * `max_depth` - The deepest level of instructions walked, the configured
+///   `max_instruction_depth` of the swap filters
 ///
 /// # Returns
 ///
-/// A vector of `TokenTransferDetails` containing all token transfers found in the current
-/// level and all nested levels of instructions
-fn recursive_inner_token_transfers(
+/// A vector of `TokenTransferDetails` containing all token transfers found in the transaction
+pub fn get_inner_token_transfers_with_vaults(
+    transaction_metadata: &TransactionMetadata,
     nested_instructions: &[NestedInstruction],
-    mint_details: &HashMap<String, MintDetail>,
+    sol_vaults: &HashSet<String>,
+    max_depth: usize,
 ) -> Vec<TokenTransferDetails> {
-    let mut transfers: Vec<TokenTransferDetails> = Vec::new();
-
-    // Process current level instructions
-    let current_transfers = SPL_TOKEN_DECODER
-        .decode_token_transfers_from_instructions(nested_instructions, mint_details);
-    transfers.extend(current_transfers);
-
-    // Recursively process nested instructions
-    for nested_instruction in nested_instructions {
-        transfers.extend(recursive_inner_token_transfers(
-            &nested_instruction.inner_instructions,
-            mint_details,
-        ));
+    walk_inner_token_transfers(transaction_metadata, nested_instructions, sol_vaults, max_depth)
+        .transfers
+}
+
+/// The transfers found by a walk over nested instructions and what the walk skipped
+#[derive(Debug, Default)]
+pub struct InnerTokenTransfers {
+    pub transfers: Vec<TokenTransferDetails>,
+    /// The instructions skipped as exact duplicates of a walked one, with their nested ones
+    pub duplicate_instructions: u64,
+    /// Whether instructions nested deeper than the max depth were skipped
+    pub depth_exceeded: bool,
+}
+
+/// Extracts the token transfers like `get_inner_token_transfers_with_vaults`, reporting the
+/// skipped instructions.
+///
+/// An instruction reached twice, e.g. a subtree repeated by a CPI loop or passed again by a
+/// processor, only counts once, and the instructions nested deeper than `max_depth` are
+/// ignored, the given instructions being the first level.
+pub fn walk_inner_token_transfers(
+    transaction_metadata: &TransactionMetadata,
+    nested_instructions: &[NestedInstruction],
+    sol_vaults: &HashSet<String>,
+    max_depth: usize,
+) -> InnerTokenTransfers {
+    let mint_details = extra_mint_details_from_tx_metadata(transaction_metadata);
+    let mut walk = TransferWalk {
+        mint_details: &mint_details,
+        sol_vaults,
+        max_depth,
+        visited: HashSet::new(),
+        // every token account mostly moves funds once
+        transfers: Vec::with_capacity(mint_details.len()),
+        native_transfers: Vec::new(),
+        duplicate_instructions: 0,
+        depth_exceeded: false,
+    };
+    walk.walk(nested_instructions, 1);
+    InnerTokenTransfers {
+        transfers: merge_native_transfers(walk.transfers, walk.native_transfers),
+        duplicate_instructions: walk.duplicate_instructions,
+        depth_exceeded: walk.depth_exceeded,
+    }
+}
+
+/// instruction_key returns the identity of a nested instruction, its position in the
+/// transaction and its content
+fn instruction_key(nested_instruction: &NestedInstruction) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let metadata = &nested_instruction.metadata;
+    (metadata.stack_height, &metadata.absolute_path).hash(&mut hasher);
+    let instruction = &nested_instruction.instruction;
+    instruction.program_id.hash(&mut hasher);
+    for account in &instruction.accounts {
+        (account.pubkey, account.is_signer, account.is_writable).hash(&mut hasher);
+    }
+    instruction.data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The state of a walk over nested instructions, appending the transfers of every level
+struct TransferWalk<'a> {
+    mint_details: &'a MintDetails,
+    sol_vaults: &'a HashSet<String>,
+    max_depth: usize,
+    visited: HashSet<u64>,
+    transfers: Vec<TokenTransferDetails>,
+    native_transfers: Vec<TokenTransferDetails>,
+    duplicate_instructions: u64,
+    depth_exceeded: bool,
+}
+
+impl TransferWalk<'_> {
+    /// walk extracts the transfers of the instructions at `depth`, then the ones of their
+    /// nested instructions, keeping the order of the levels
+    fn walk(&mut self, nested_instructions: &[NestedInstruction], depth: usize) {
+        if nested_instructions.is_empty() {
+            return;
+        }
+        if depth > self.max_depth {
+            self.depth_exceeded = true;
+            return;
+        }
+        let instructions: Vec<&NestedInstruction> = nested_instructions
+            .iter()
+            .filter(|nested_instruction| {
+                let first = self.visited.insert(instruction_key(nested_instruction));
+                if !first {
+                    self.duplicate_instructions += 1;
+                }
+                first
+            })
+            .collect();
+
+        for nested_instruction in &instructions {
+            let instruction = std::slice::from_ref(*nested_instruction);
+            SPL_TOKEN_DECODER.extend_token_transfers(
+                instruction,
+                self.mint_details,
+                &mut self.transfers,
+            );
+            SYSTEM_TRANSFER_DECODER.extend_native_transfers(
+                instruction,
+                self.mint_details,
+                self.sol_vaults,
+                &mut self.native_transfers,
+            );
+        }
+        for nested_instruction in instructions {
+            self.walk(&nested_instruction.inner_instructions, depth + 1);
+        }
     }
-    transfers
 }
 
 /// Checks if the swap is valid.
@@ -216,6 +640,7 @@ fn recursive_inner_token_transfers(
 ///
 /// * `transfers` - The list of token transfers
 /// * `transaction_metadata` - The transaction metadata
+/// * `config` - The thresholds below which swaps are skipped
 ///
 /// # Returns
 ///
@@ -223,6 +648,7 @@ fn recursive_inner_token_transfers(
 pub fn is_valid_swap(
     transfers: &[TokenTransferDetails],
     transaction_metadata: &TransactionMetadata,
+    config: &SwapFilterConfig,
 ) -> Result<(), SwapError> {
     if transfers.len() != 2 {
         debug!(
@@ -233,12 +659,12 @@ pub fn is_valid_swap(
         return Err(SwapError::ExpectedTwoTokenSwaps);
     }
 
-    if transfers.iter().all(|d| d.ui_amount < TINY_SWAP_UI_AMOUNT) {
+    if transfers.iter().all(|d| config.is_tiny_transfer(&d.mint, d.ui_amount)) {
         debug!("skipping tiny swaps");
         return Err(SwapError::TinySwap);
     }
 
-    if transfers.iter().any(|d| d.ui_amount == 0.0) {
+    if transfers.iter().any(|d| is_zero_amount(d.ui_amount)) {
         debug!("skipping zero swaps (arbitrage likely)");
         return Err(SwapError::ZeroSwap);
     }
@@ -246,6 +672,30 @@ pub fn is_valid_swap(
     Ok(())
 }
 
+/// is_zero_amount returns true for the amounts a price can't be divided by,
+/// the dust amounts underflowing to a subnormal float included
+fn is_zero_amount(amount: f64) -> bool {
+    !amount.is_normal()
+}
+
+/// swap_price returns the price of the base token and the usd amount of a swap,
+/// erroring instead of returning an infinite, NaN or zero price
+pub fn swap_price(
+    base_amount: f64,
+    quote_amount: f64,
+    quote_price: f64,
+) -> Result<(f64, f64), SwapError> {
+    if is_zero_amount(base_amount) || is_zero_amount(quote_amount) {
+        return Err(SwapError::ZeroSwap);
+    }
+    let price = (quote_amount / base_amount) * quote_price;
+    let swap_amount = quote_amount * quote_price;
+    if !price.is_normal() || !swap_amount.is_finite() {
+        return Err(SwapError::InvalidPrice);
+    }
+    Ok((price, swap_amount))
+}
+
 // https://solscan.io/tx/2usSAGxq35GJxQxVKHQ7NHBDnJim95Jyk3AeFrRAcpHc2TJUH3bjhVSvtAWcxnqnQyJFzpPFgJvMHNkTuQ8t779f
 pub fn is_swap_inner_transfer(
     transfer: &TokenTransferDetails,
@@ -265,20 +715,35 @@ pub fn is_swap_inner_transfer(
         && (vaults_adas.contains(&transfer.destination) || vaults_adas.contains(&transfer.source))
 }
 
+/// swap_owner returns the owner of the user token accounts of the swap, which differs from
+/// the fee payer when a trading bot relays the swap, the fee payer when no user token
+/// account holds a balance, e.g. when the user swaps native SOL only
+pub fn swap_owner(
+    user_adas: &HashSet<String>,
+    base: &TokenTransferDetails,
+    quote: &TokenTransferDetails,
+    transaction_metadata: &TransactionMetadata,
+) -> String {
+    [base, quote]
+        .into_iter()
+        .flat_map(|transfer| [&transfer.source, &transfer.destination])
+        .filter(|account| user_adas.contains(*account))
+        .find_map(|account| token_account_owner(transaction_metadata, account))
+        .unwrap_or_else(|| transaction_metadata.fee_payer.to_string())
+}
+
 pub fn build_swap_event(
     pair: &str,
+    user_adas: &HashSet<String>,
     is_buy: bool,
     base: &TokenTransferDetails,
     quote: &TokenTransferDetails,
     quote_price: f64,
     transaction_metadata: &TransactionMetadata,
-) -> SwapEvent {
-    let is_pump = base.mint.to_lowercase().ends_with("pump");
+) -> Result<SwapEvent, SwapError> {
     let base_amount = base.ui_amount;
     let quote_amount = quote.ui_amount;
-
-    let price = (quote_amount / base_amount) * quote_price;
-    let swap_amount = quote_amount * quote_price;
+    let (price, swap_amount) = swap_price(base_amount, quote_amount, quote_price)?;
 
     let signers = transaction_metadata
         .message
@@ -288,7 +753,7 @@ pub fn build_swap_event(
         .map(|pubkey| pubkey.to_string())
         .collect::<Vec<String>>();
 
-    SwapEvent {
+    Ok(SwapEvent {
         pair: pair.to_string(),
         pubkey: base.mint.clone(),
         price,
@@ -298,41 +763,156 @@ pub fn build_swap_event(
         base_amount,
         quote_amount,
         swap_amount,
-        owner: transaction_metadata.fee_payer.to_string(),
+        owner: swap_owner(user_adas, base, quote, transaction_metadata),
         signature: transaction_metadata.signature.to_string(),
         signers,
-        is_pump,
+        is_pump: false,
         is_buy,
+        is_wash: false,
+        fee_amount: 0.0,
+        fee_mint: String::new(),
+        is_quote_pair: false,
+        pct_of_supply: 0.0,
+        fee_payer: transaction_metadata.fee_payer.to_string(),
+    })
+}
+
+/// is_pump_swap returns true if the swap went through the pump AMM or its token was
+/// launched on pump, the graduated tokens keep the flag on the other dexes
+pub fn is_pump_swap(dex: Dexes, launchpad: &str) -> bool {
+    dex == Dexes::PumpAmm || launchpad == PUMP_LAUNCHPAD
+}
+
+/// record_token_launchpad returns the launchpad of a token, recording the launchpad of the dex
+/// the first time the token trades on one, a failure is logged without failing the swap
+async fn record_token_launchpad(
+    dex: Dexes,
+    mut token: Token,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    token_cache: &TokenCache,
+) -> String {
+    let Some(launchpad) = dex.launchpad().filter(|_| token.launchpad.is_empty()) else {
+        return token.launchpad;
+    };
+    token.launchpad = launchpad.to_string();
+    if let Err(e) = db.set_token_launchpad(&token.token, launchpad).await {
+        error!(?e, token = %token.token, "Failed to record the launchpad of the token");
     }
+    if let Err(e) = kv_store.set_token(&token.token, &token).await {
+        error!(?e, token = %token.token, "Failed to cache the launchpad of the token");
+    }
+    token_cache.insert(token.clone());
+    token.launchpad
 }
 
+/// get_cached_token_metadata returns the metadata of a token from the process cache,
+/// resolving it through the KV store and the database on a miss
+async fn get_cached_token_metadata(
+    mint: &str,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    token_cache: &TokenCache,
+    metrics: &NodeMetrics,
+) -> Result<Token> {
+    if let Some(token) = token_cache.get(mint) {
+        metrics.increment_token_cache_hits();
+        return Ok(token);
+    }
+    metrics.increment_token_cache_misses();
+    let token = get_token_metadata_with_data(mint, kv_store, db).await?;
+    token_cache.insert(token.clone());
+    metrics.set_token_cache_size(token_cache.len(), token_cache.bytes());
+    Ok(token)
+}
+
+/// get_swap_event_with_token_transfer_details returns the swap event of the transfers,
+/// along with the metadata of its token when it could be fetched
 #[allow(clippy::too_many_arguments)]
 pub async fn get_swap_event_with_token_transfer_details(
+    dex: Dexes,
     token_swap_accounts: &TokenSwapAccounts,
     transfers: &[TokenTransferDetails],
+    fee_transfers: &[TokenTransferDetails],
     transaction_metadata: &TransactionMetadata,
     kv_store: &Arc<KvStore>,
     db: &Arc<Database>,
-) -> Result<SwapEvent, SwapError> {
-    is_valid_swap(transfers, transaction_metadata)?;
+    token_cache: &TokenCache,
+    metrics: &NodeMetrics,
+    config: &SwapFilterConfig,
+    sol_price_cache: Option<&SolPriceCacheRef>,
+) -> Result<(SwapEvent, Option<Token>), SwapError> {
+    is_valid_swap(transfers, transaction_metadata, config)?;
 
     let (is_buy, base_mint_details, quote_mint_details) =
         get_base_quote_mint(token_swap_accounts, transfers)?;
-    let (_quote_mint, quote_price) = get_quote_price(
-        quote_mint_details.mint.as_str(),
-        Some(transaction_metadata.block_time.unwrap_or(Utc::now().timestamp()) as u64),
-        kv_store,
-    )
-    .await;
+    // the direction is taken from the user side, the vaults disagreeing point at a venue
+    // whose accounts are arranged differently than its processor expects
+    if is_buy != vault_is_buy(token_swap_accounts, base_mint_details, quote_mint_details)
+        && metrics.record_direction_disagreement(Utc::now().timestamp_millis())
+    {
+        warn!(
+            pair = %token_swap_accounts.pair,
+            signature = %transaction_metadata.signature,
+            %dex,
+            is_buy,
+            total = metrics.direction_disagreements.load(std::sync::atomic::Ordering::Relaxed),
+            "The vaults disagree with the user accounts on the swap direction"
+        );
+    }
+
+    // The WSOL/stable swaps define the SOL price themselves, so they are priced from
+    // the transfer amounts instead of the SOL price they would otherwise update
+    let quote_price = match get_onchain_sol_price(base_mint_details, quote_mint_details) {
+        Some(sol_price) => {
+            if let Some(sol_price_cache) = sol_price_cache {
+                update_onchain_sol_price(
+                    sol_price_cache,
+                    sol_price,
+                    quote_mint_details.ui_amount,
+                    config.onchain_sol_price_min_quote_amount,
+                )
+                .await;
+            }
+            STABLE_QUOTE_PRICE
+        }
+        None => {
+            let (_quote_mint, quote_price) = get_quote_price(
+                quote_mint_details.mint.as_str(),
+                Some(transaction_metadata.block_time.unwrap_or(Utc::now().timestamp()) as u64),
+                kv_store,
+                db,
+                config.quote_price_max_staleness,
+            )
+            .await;
+            quote_price
+        }
+    };
+
+    // A swap against an unpriced quote would be priced at zero
+    if quote_price <= 0.0 {
+        return Err(SwapError::UnpricedQuote);
+    }
+
+    // the majors would otherwise be stored as tokens priced around 1 usd, the SOL price
+    // above is still updated when WSOL is configured as a major
+    let is_quote_pair = is_quote_pair_swap(base_mint_details, quote_mint_details);
+    if is_quote_pair && config.quote_pair_policy == QuotePairPolicy::Skip {
+        return Err(SwapError::QuotePairSwap);
+    }
 
     let mut swap_event = build_swap_event(
         &token_swap_accounts.pair,
+        &token_swap_accounts.user_adas,
         is_buy,
         base_mint_details,
         quote_mint_details,
         quote_price,
         transaction_metadata,
-    );
+    )?;
+    (swap_event.fee_amount, swap_event.fee_mint) =
+        get_swap_fee(fee_transfers, base_mint_details, quote_mint_details, quote_price);
+    swap_event.is_quote_pair = is_quote_pair;
 
     // let f = || get_token_metadata_with_data(swap_event.pubkey.as_str(), kv_store, db);
     // let supply = match f.retry(ExponentialBuilder::default()).await {
@@ -343,23 +923,231 @@ pub async fn get_swap_event_with_token_transfer_details(
     //     }
     // };
 
-    let supply = match get_token_metadata_with_data(swap_event.pubkey.as_str(), kv_store, db).await
-    {
-        Ok(token) => token.supply,
+    let metadata = timed(
+        SwapStage::MetadataLookup,
+        metrics,
+        get_cached_token_metadata(swap_event.pubkey.as_str(), kv_store, db, token_cache, metrics),
+    )
+    .await;
+    let (token, launchpad) = match metadata {
+        Ok(token) => {
+            let launchpad =
+                record_token_launchpad(dex, token.clone(), kv_store, db, token_cache).await;
+            (Some(token), launchpad)
+        }
         Err(e) => {
             error!("Failed to get token metadata for {} {:?}", swap_event.pubkey, e);
-            0.0
+            (None, String::new())
         }
     };
+    let supply = token.as_ref().map_or(0.0, |token| token.supply);
 
     swap_event.update_market_cap(supply);
+    swap_event.pct_of_supply = pct_of_supply(swap_event.base_amount, token.as_ref());
+    swap_event.is_pump = is_pump_swap(dex, &launchpad);
 
-    // Skip tiny swaps
-    if swap_event.swap_amount < TINY_SWAP_AMOUNT {
-        return Err(SwapError::TinySwap);
+    // Skip tiny swaps, before the wash trade check records their side
+    if config.is_tiny_swap_usd(swap_event.swap_amount) {
+        return Err(SwapError::TinySwapUsd);
+    }
+
+    swap_event.is_wash = is_wash_trade(
+        &swap_event,
+        quote_mint_details,
+        transaction_metadata,
+        kv_store,
+        config.wash_trade_window,
+    )
+    .await;
+
+    Ok((swap_event, token))
+}
+
+/// pct_of_supply returns the share of the token supply moved by a swap of `base_amount`,
+/// zero when the supply is unknown, for the NFTs and zero decimal tokens whose supply of a
+/// few units makes every swap look large, and when the amount exceeds the supply, which is
+/// then stale or not in ui units
+pub fn pct_of_supply(base_amount: f64, token: Option<&Token>) -> f64 {
+    let Some(token) = token.filter(|token| !token.is_nft && token.decimals > 0) else {
+        return 0.0;
+    };
+    if !token.supply.is_normal() || token.supply < 0.0 {
+        return 0.0;
+    }
+    let pct_of_supply = base_amount / token.supply;
+    if (0.0..=1.0).contains(&pct_of_supply) {
+        pct_of_supply
+    } else {
+        0.0
+    }
+}
+
+/// is_large_trade returns true when the trade moves at least `large_trade_pct` of the
+/// token supply, a threshold of 0 disables the large trades
+pub fn is_large_trade(pct_of_supply: f64, large_trade_pct: f64) -> bool {
+    large_trade_pct > 0.0 && pct_of_supply >= large_trade_pct
+}
+
+/// route_large_trade also publishes the trade as a large trade when it moves enough of the
+/// token supply, returns true if it did, a failure is logged without failing the swap
+async fn route_large_trade(
+    message_queue: &Arc<MessageQueue>,
+    trade: &TradeV2,
+    large_trade_pct: f64,
+) -> bool {
+    if !is_large_trade(trade.trade.pct_of_supply, large_trade_pct) {
+        return false;
+    }
+    match message_queue.publish_large_trade(trade).await {
+        Ok(_) => true,
+        Err(e) => {
+            error!(?e, signature = %trade.trade.signature, "Failed to publish the large trade");
+            false
+        }
+    }
+}
+
+/// Returns the usd value of the fee transfers of a swap and the mint most of it was paid in,
+/// fees paid in other mints than the base and the quote are ignored
+pub fn get_swap_fee(
+    fee_transfers: &[TokenTransferDetails],
+    base: &TokenTransferDetails,
+    quote: &TokenTransferDetails,
+    quote_price: f64,
+) -> (f64, String) {
+    let base_price =
+        if base.ui_amount > 0.0 { quote.ui_amount / base.ui_amount * quote_price } else { 0.0 };
+    let (mut base_fee, mut quote_fee) = (0.0, 0.0);
+    for transfer in fee_transfers {
+        if transfer.mint == quote.mint {
+            quote_fee += transfer.ui_amount * quote_price;
+        } else if transfer.mint == base.mint {
+            base_fee += transfer.ui_amount * base_price;
+        }
     }
+    let fee_mint = if quote_fee > 0.0 && quote_fee >= base_fee {
+        quote.mint.clone()
+    } else if base_fee > 0.0 {
+        base.mint.clone()
+    } else {
+        String::new()
+    };
+    (base_fee + quote_fee, fee_mint)
+}
 
-    Ok(swap_event)
+/// The price of the USDC and USDT quotes
+pub(crate) const STABLE_QUOTE_PRICE: f64 = 1.0;
+
+/// Returns the SOL price defined by a WSOL/USDC or WSOL/USDT swap, None for other swaps
+pub fn get_onchain_sol_price(
+    base: &TokenTransferDetails,
+    quote: &TokenTransferDetails,
+) -> Option<f64> {
+    if base.mint != WSOL_MINT_KEY_STR || !USDT_SET.contains(&quote.mint) || base.ui_amount <= 0.0 {
+        return None;
+    }
+    Some(quote.ui_amount / base.ui_amount)
+}
+
+/// Pushes an on-chain SOL price into the cache unless the swap is below `min_quote_amount`,
+/// returns true if the cache was updated
+async fn update_onchain_sol_price(
+    sol_price_cache: &SolPriceCacheRef,
+    sol_price: f64,
+    quote_amount: f64,
+    min_quote_amount: f64,
+) -> bool {
+    // dust swaps are too easy to move away from the market price
+    if quote_amount < min_quote_amount {
+        return false;
+    }
+    match sol_price_cache.set_price(sol_price).await {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Failed to update on-chain SOL price: {}", e);
+            false
+        }
+    }
+}
+
+/// Sums the raw token balance of `owner` for `mint`, returns None if the owner holds no account
+fn owner_token_balance(
+    balances: &[TransactionTokenBalance],
+    mint: &str,
+    owner: &str,
+) -> Option<u64> {
+    balances
+        .iter()
+        .filter(|b| b.mint == mint && b.owner == owner)
+        .map(|b| b.ui_token_amount.amount.parse::<u64>().unwrap_or(0))
+        .reduce(|a, b| a + b)
+}
+
+/// Checks if the owner swapped in and out of the mint within the transaction.
+///
+/// A regular swap changes the owner's balance of the base mint, a self-swap
+/// (buy and sell in the same transaction) leaves it unchanged. A multi-hop route
+/// through the base mint leaves it unchanged too, but spends or receives the whole
+/// quote amount, while a round trip on the pair only loses the fees in the quote.
+///
+/// # Arguments
+///
+/// * `pre_balances` - The token balances before the transaction
+/// * `post_balances` - The token balances after the transaction
+/// * `mint` - The base mint of the swap
+/// * `quote` - The quote transfer of the swap
+/// * `owner` - The owner of the swap, usually the fee payer
+///
+/// # Returns
+///
+/// A boolean indicating if the swap is a self-swap.
+pub fn is_self_swap(
+    pre_balances: &[TransactionTokenBalance],
+    post_balances: &[TransactionTokenBalance],
+    mint: &str,
+    quote: &TokenTransferDetails,
+    owner: &str,
+) -> bool {
+    let balance_change = |mint: &str| match (
+        owner_token_balance(pre_balances, mint, owner),
+        owner_token_balance(post_balances, mint, owner),
+    ) {
+        (Some(pre), Some(post)) => Some(pre.abs_diff(post)),
+        _ => None,
+    };
+    balance_change(mint) == Some(0)
+        && balance_change(&quote.mint).is_some_and(|change| change < quote.amount / 2)
+}
+
+/// Tags a swap as a wash trade when it is a self-swap, or when the owner swapped
+/// the opposite side of the same pair within `window`.
+async fn is_wash_trade(
+    swap_event: &SwapEvent,
+    quote: &TokenTransferDetails,
+    transaction_metadata: &TransactionMetadata,
+    kv_store: &Arc<KvStore>,
+    window: Duration,
+) -> bool {
+    let meta = &transaction_metadata.meta;
+    if is_self_swap(
+        meta.pre_token_balances.as_deref().unwrap_or_default(),
+        meta.post_token_balances.as_deref().unwrap_or_default(),
+        &swap_event.pubkey,
+        quote,
+        &swap_event.owner,
+    ) {
+        return true;
+    }
+    match kv_store
+        .record_swap_side(&swap_event.pair, &swap_event.owner, swap_event.is_buy, window.as_secs())
+        .await
+    {
+        Ok(is_flip) => is_flip,
+        Err(e) => {
+            error!("Failed to record swap side for {}: {:?}", swap_event.signature, e);
+            false
+        }
+    }
 }
 
 /// Filters token transfers to only include valid swap transfers.
@@ -390,53 +1178,123 @@ pub fn filter_swap_transfers(
         .collect()
 }
 
+/// Splits the transfers of a swap into the swap transfers, see `filter_swap_transfers`,
+/// and the fee transfers into the fee accounts of the pool
+pub fn split_swap_transfers(
+    transfers: &[TokenTransferDetails],
+    token_swap_accounts: &TokenSwapAccounts,
+) -> (Vec<TokenTransferDetails>, Vec<TokenTransferDetails>) {
+    let fee_transfers = match &token_swap_accounts.fee_adas {
+        Some(fee_adas) => {
+            transfers.iter().filter(|t| fee_adas.contains(&t.destination)).cloned().collect()
+        }
+        None => vec![],
+    };
+    (filter_swap_transfers(transfers, token_swap_accounts), fee_transfers)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn process_token_swap_instruction(
+    dex: Dexes,
     token_swap_accounts: &TokenSwapAccounts,
     transaction_metadata: &TransactionMetadata,
     nested_instructions: &[NestedInstruction],
     message_queue: &Arc<MessageQueue>,
     kv_store: &Arc<KvStore>,
     db: &Arc<Database>,
+    token_cache: &TokenCache,
     metrics: &NodeMetrics,
+    skipped_swaps: &SkippedSwapLog,
+    config: &SwapFilterConfig,
+    sol_price_cache: Option<&SolPriceCacheRef>,
+    large_trade_pct: f64,
 ) -> Result<(), SwapError> {
-    let transfers = get_inner_token_transfers(transaction_metadata, nested_instructions);
-    let filtered_transfers = filter_swap_transfers(&transfers, token_swap_accounts);
+    let (filtered_transfers, fee_transfers) =
+        timed(SwapStage::TransferExtraction, metrics, async {
+            let inner = walk_inner_token_transfers(
+                transaction_metadata,
+                nested_instructions,
+                &token_swap_accounts.vault_adas,
+                config.max_instruction_depth,
+            );
+            if inner.duplicate_instructions > 0 {
+                metrics.add_duplicate_instructions(inner.duplicate_instructions);
+            }
+            if inner.depth_exceeded {
+                metrics.increment_instruction_depth_exceeded();
+                debug!(
+                    signature = %transaction_metadata.signature,
+                    max_depth = config.max_instruction_depth,
+                    "Skipped the instructions nested too deep"
+                );
+            }
+            split_swap_transfers(&inner.transfers, token_swap_accounts)
+        })
+        .await;
+    filtered_transfers
+        .iter()
+        .filter(|t| is_native_transfer(t))
+        .for_each(|_| metrics.increment_synthesized_native_transfers());
 
-    let swap_event = match get_swap_event_with_token_transfer_details(
+    let (swap_event, token) = match get_swap_event_with_token_transfer_details(
+        dex,
         token_swap_accounts,
         &filtered_transfers,
+        &fee_transfers,
         transaction_metadata,
         kv_store,
         db,
+        token_cache,
+        metrics,
+        config,
+        sol_price_cache,
     )
     .await
     {
-        Ok(swap_event) => swap_event,
+        Ok(result) => result,
         Err(e) => {
-            update_metrics_for_swap_error(metrics, e);
+            let signature = transaction_metadata.signature.to_string();
+            let pair = &token_swap_accounts.pair;
+            record_skipped_swap(metrics, skipped_swaps, dex, pair, &signature, e);
             return Ok(());
         }
     };
 
-    match db.insert_swap_event(&swap_event).await {
+    if swap_event.is_wash {
+        metrics.increment_tagged_wash_swaps();
+    }
+
+    match timed(SwapStage::DbInsert, metrics, db.insert_swap_event(&swap_event)).await {
         Ok(_) => metrics.increment_db_insert_success(),
         Err(e) => {
             metrics.increment_db_insert_failure();
+            handle_storage_error(metrics, &e);
             return Err(SwapError::DbInsertFailure(e));
         }
     };
+    record_first_seen_pair(
+        dex,
+        token_swap_accounts,
+        &filtered_transfers,
+        &swap_event,
+        kv_store,
+        db,
+        config.pair_seen_ttl,
+    )
+    .await;
 
-    let trade: Trade = swap_event.into();
-    match message_queue.publish_trade(&trade).await {
+    let trade =
+        TradeV2 { trade: Trade::from_swap_event_with_token(swap_event, token.as_ref()), dex };
+    match timed(SwapStage::MqPublish, metrics, message_queue.publish_trade_v2(&trade)).await {
         Ok(_) => metrics.increment_message_send_success(),
         Err(e) => {
             metrics.increment_message_send_failure();
             return Err(SwapError::MessageSendFailure(e));
         }
     }
+    route_large_trade(message_queue, &trade, large_trade_pct).await;
 
-    match kv_store.insert_price(&trade).await {
+    match timed(SwapStage::KvInsert, metrics, kv_store.insert_price(&trade.trade)).await {
         Ok(_) => metrics.increment_kv_insert_success(),
         Err(e) => {
             metrics.increment_kv_insert_failure();
@@ -446,6 +1304,46 @@ pub async fn process_token_swap_instruction(
     Ok(())
 }
 
+/// record_first_seen_pair stores the mints and dex of a pair the first time it is swapped
+/// within `ttl`, a failure is logged without failing the swap
+async fn record_first_seen_pair(
+    dex: Dexes,
+    token_swap_accounts: &TokenSwapAccounts,
+    transfers: &[TokenTransferDetails],
+    swap_event: &SwapEvent,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    ttl: Duration,
+) {
+    match kv_store.mark_pair_seen(&swap_event.pair, ttl.as_secs()).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            error!(?e, pair = %swap_event.pair, "Failed to mark the pair as seen");
+            return;
+        }
+    }
+    let quote_mint = match get_base_quote_mint(token_swap_accounts, transfers) {
+        Ok((_, _, quote)) => quote.mint.clone(),
+        Err(_) => return,
+    };
+    let pair = Pair {
+        pair: swap_event.pair.clone(),
+        base_mint: swap_event.pubkey.clone(),
+        quote_mint,
+        dex,
+        first_seen: swap_event.timestamp,
+    };
+    if let Err(e) = db.insert_pair(&pair).await {
+        error!(?e, pair = %pair.pair, "Failed to record the pair");
+    }
+}
+
+/// is_quote_pair_swap returns true if both sides of the swap are majors, see `MAJOR_MINTS`
+pub fn is_quote_pair_swap(base: &TokenTransferDetails, quote: &TokenTransferDetails) -> bool {
+    is_major_mint(&base.mint) && is_major_mint(&quote.mint)
+}
+
 pub fn get_base_quote_mint<'a>(
     token_swap_accounts: &TokenSwapAccounts,
     transfers: &'a [TokenTransferDetails],
@@ -465,16 +1363,49 @@ pub fn get_base_quote_mint<'a>(
         (base_mint, quote_mint) = (quote_mint, base_mint);
     }
 
-    let is_buy = token_swap_accounts.vault_adas.contains(&quote_mint.destination)
-        || token_swap_accounts.vault_adas.contains(&base_mint.source);
+    let is_buy = user_is_buy(token_swap_accounts, base_mint, quote_mint)
+        .unwrap_or_else(|| vault_is_buy(token_swap_accounts, base_mint, quote_mint));
     Ok((is_buy, base_mint, quote_mint))
 }
 
+/// user_is_buy returns true if the user received the base mint, i.e. the base was paid into
+/// or the quote paid out of a user account, None when the user accounts can't tell the sides
+/// apart, e.g. when they are unknown
+pub fn user_is_buy(
+    token_swap_accounts: &TokenSwapAccounts,
+    base: &TokenTransferDetails,
+    quote: &TokenTransferDetails,
+) -> Option<bool> {
+    let user_adas = &token_swap_accounts.user_adas;
+    let receives_base = user_adas.contains(&base.destination) || user_adas.contains(&quote.source);
+    let receives_quote = user_adas.contains(&quote.destination) || user_adas.contains(&base.source);
+    match (receives_base, receives_quote) {
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// vault_is_buy returns true if the quote mint was paid into or the base mint out of a vault,
+/// which mislabels the pools whose vaults are listed in the inverted order of their mints
+pub fn vault_is_buy(
+    token_swap_accounts: &TokenSwapAccounts,
+    base: &TokenTransferDetails,
+    quote: &TokenTransferDetails,
+) -> bool {
+    token_swap_accounts.vault_adas.contains(&quote.destination)
+        || token_swap_accounts.vault_adas.contains(&base.source)
+}
+
+/// get_quote_price returns the USD price of a quote mint, a generic quote mint being priced
+/// from its KV price unless it is older than `max_staleness`
 #[cfg(not(feature = "hist"))]
 pub async fn get_quote_price(
     quote_mint: &str,
-    _timestamp: Option<u64>,
-    _kv_store: &Arc<KvStore>,
+    timestamp: Option<u64>,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    max_staleness: Duration,
 ) -> (String, f64) {
     if quote_mint == WSOL_MINT_KEY_STR {
         let quote_price = get_sol_price().await;
@@ -484,8 +1415,11 @@ pub async fn get_quote_price(
     } else if quote_mint == USDT_MINT_KEY_STR {
         (USDT_MINT_KEY_STR.to_string(), 1.0)
     } else {
-        // TODO: add support for other mints
-        (quote_mint.to_string(), 0.0)
+        let timestamp = timestamp.unwrap_or(Utc::now().timestamp() as u64);
+        let quote_price =
+            get_generic_quote_price(quote_mint, timestamp, kv_store, db, max_staleness.as_secs())
+                .await;
+        (quote_mint.to_string(), quote_price.unwrap_or(0.0))
     }
 }
 
@@ -494,6 +1428,8 @@ pub async fn get_quote_price(
     quote_mint: &str,
     timestamp: Option<u64>,
     kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    max_staleness: Duration,
 ) -> (String, f64) {
     if quote_mint == USDC_MINT_KEY_STR {
         (USDC_MINT_KEY_STR.to_string(), 1.0)
@@ -513,8 +1449,40 @@ pub async fn get_quote_price(
         let quote_price = get_sol_price().await;
         (WSOL_MINT_KEY_STR.to_string(), quote_price)
     } else {
-        // TODO: add support for other mints
-        (quote_mint.to_string(), 0.0)
+        let timestamp = timestamp.unwrap_or(Utc::now().timestamp() as u64);
+        let quote_price =
+            get_generic_quote_price(quote_mint, timestamp, kv_store, db, max_staleness.as_secs())
+                .await;
+        (quote_mint.to_string(), quote_price.unwrap_or(0.0))
+    }
+}
+
+/// Returns the USD price of a quote mint other than WSOL and the stables at `timestamp`,
+/// from its latest traded price in the KV store unless it is older than `max_staleness_secs`,
+/// otherwise from the database, None if neither has a price
+pub async fn get_generic_quote_price(
+    quote_mint: &str,
+    timestamp: u64,
+    kv_store: &KvStore,
+    db: &Database,
+    max_staleness_secs: u64,
+) -> Option<f64> {
+    match kv_store.get_latest_price(quote_mint).await {
+        Ok(Some((price, price_timestamp)))
+            if price > 0.0 && price_timestamp + max_staleness_secs >= timestamp =>
+        {
+            return Some(price);
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to get the kv price of quote {}: {:?}", quote_mint, e),
+    }
+
+    match db.get_price(quote_mint, timestamp as i32).await {
+        Ok(token_price) => token_price.price.filter(|price| *price > 0.0),
+        Err(e) => {
+            error!("Failed to get the db price of quote {}: {:?}", quote_mint, e);
+            None
+        }
     }
 }
 
@@ -556,7 +1524,10 @@ pub async fn save_swap_event(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{handler::SkippedSwapQuery, test_swaps::MemorySolPriceCache};
     use bigdecimal::ToPrimitive;
+    use solana_account_decoder::parse_token::UiTokenAmount;
+    use sonar_db::test_utils::make_swap_event;
     use std::ops::Div;
 
     #[tokio::test]
@@ -668,6 +1639,475 @@ mod tests {
         assert!(is_valid, "wsol ix should be valid");
     }
 
+    fn token_balance(mint: &str, owner: &str, amount: u64) -> TransactionTokenBalance {
+        TransactionTokenBalance {
+            account_index: 1,
+            mint: mint.to_string(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: Some(amount as f64 / 1e6),
+                decimals: 6,
+                amount: amount.to_string(),
+                ui_amount_string: (amount as f64 / 1e6).to_string(),
+            },
+            owner: owner.to_string(),
+            program_id: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_is_self_swap() {
+        let (mint, owner) = ("2Y6GkQJR93PNL1iYwGcjggoaBRaeTM1p9pC7oCzTpump", "owner");
+        let usdc = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+        let quote = TokenTransferDetails { amount: 1_000_000, ..transfer(usdc, 1.0) };
+
+        // buy and sell in the same transaction leaves the balance unchanged, the quote
+        // balance only pays the fees
+        let pre =
+            vec![token_balance(mint, owner, 1_000_000), token_balance(usdc, owner, 5_000_000)];
+        let post =
+            vec![token_balance(mint, owner, 1_000_000), token_balance(usdc, owner, 4_990_000)];
+        assert!(is_self_swap(&pre, &post, mint, &quote, owner));
+
+        // a multi-hop route through the mint spends the whole quote amount
+        let post =
+            vec![token_balance(mint, owner, 1_000_000), token_balance(usdc, owner, 4_000_000)];
+        assert!(!is_self_swap(&pre, &post, mint, &quote, owner));
+
+        // or receives it, from an input account that is not the quote mint
+        let post =
+            vec![token_balance(mint, owner, 1_000_000), token_balance(usdc, owner, 6_000_000)];
+        assert!(!is_self_swap(&pre, &post, mint, &quote, owner));
+
+        // the quote balance is unknown
+        let pre = vec![token_balance(mint, owner, 1_000_000)];
+        let post = vec![token_balance(mint, owner, 1_000_000)];
+        assert!(!is_self_swap(&pre, &post, mint, &quote, owner));
+
+        // a regular buy increases the balance
+        let pre =
+            vec![token_balance(mint, owner, 1_000_000), token_balance(usdc, owner, 5_000_000)];
+        let post =
+            vec![token_balance(mint, owner, 2_000_000), token_balance(usdc, owner, 4_000_000)];
+        assert!(!is_self_swap(&pre, &post, mint, &quote, owner));
+
+        // a first buy creates the token account
+        assert!(!is_self_swap(&pre[1..], &post, mint, &quote, owner));
+
+        // balances of other owners are ignored
+        let pre =
+            vec![token_balance(mint, "other", 1_000_000), token_balance(usdc, "other", 5_000_000)];
+        let post =
+            vec![token_balance(mint, "other", 1_000_000), token_balance(usdc, "other", 4_990_000)];
+        assert!(!is_self_swap(&pre, &post, mint, &quote, owner));
+    }
+
+    fn transfer(mint: &str, ui_amount: f64) -> TokenTransferDetails {
+        TokenTransferDetails {
+            amount: 0,
+            ui_amount,
+            decimals: 0,
+            program_id: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+            authority: String::new(),
+            destination: String::new(),
+            mint: mint.to_string(),
+            source: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_get_swap_fee() {
+        // 1000 tokens for 2 SOL at 150, the token is priced at 0.3
+        let (base, quote) = (transfer("token", 1000.0), transfer(WSOL_MINT_KEY_STR, 2.0));
+        let fee_transfers = vec![
+            transfer(WSOL_MINT_KEY_STR, 0.01),
+            transfer("token", 10.0),
+            transfer("other", 5.0),
+        ];
+        let (fee_amount, fee_mint) = get_swap_fee(&fee_transfers, &base, &quote, 150.0);
+        assert_eq!(fee_amount, 10.0 * 0.3 + 0.01 * 150.0);
+        // most of the fee value was paid in the token
+        assert_eq!(fee_mint, "token");
+
+        let (_, fee_mint) = get_swap_fee(&fee_transfers[..1], &base, &quote, 150.0);
+        assert_eq!(fee_mint, WSOL_MINT_KEY_STR);
+
+        assert_eq!(get_swap_fee(&[], &base, &quote, 150.0), (0.0, String::new()));
+    }
+
+    #[test]
+    fn test_quote_pair_swap() {
+        let accounts = TokenSwapAccounts {
+            pair: "usdc-usdt-pool".to_string(),
+            user_adas: HashSet::new(),
+            vault_adas: HashSet::new(),
+            fee_adas: None,
+            quote_mints: Arc::new(USDT_SET.clone()),
+        };
+        let transfers = [transfer(USDC_MINT_KEY_STR, 100.0), transfer(USDT_MINT_KEY_STR, 99.9)];
+        // one of the stables is picked as the base
+        let (_, base, quote) = get_base_quote_mint(&accounts, &transfers).unwrap();
+        assert!(is_quote_pair_swap(base, quote));
+
+        // the WSOL/stable swaps are SOL trades
+        let (sol, usdc) = (transfer(WSOL_MINT_KEY_STR, 1.0), transfer(USDC_MINT_KEY_STR, 150.0));
+        assert!(!is_quote_pair_swap(&sol, &usdc));
+        assert!(!is_quote_pair_swap(&transfer("token", 1.0), &usdc));
+
+        let metrics = NodeMetrics::new();
+        update_metrics_for_swap_error(&metrics, SwapError::QuotePairSwap);
+        assert_eq!(metrics.skipped_quote_pair_swaps.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_quote_pair_policy() {
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let (kv_store, _, db) = storages.storages();
+        storages.seed_token(USDC_MINT_KEY_STR, 6, 1_000_000_000.0).await;
+        let accounts = TokenSwapAccounts {
+            pair: "usdc-usdt-pool".to_string(),
+            user_adas: HashSet::new(),
+            vault_adas: HashSet::new(),
+            fee_adas: None,
+            quote_mints: Arc::new(USDT_SET.clone()),
+        };
+        let transfers = [transfer(USDC_MINT_KEY_STR, 1000.0), transfer(USDT_MINT_KEY_STR, 999.0)];
+        let transaction_metadata: TransactionMetadata =
+            large_transaction(1).try_into().expect("Failed to convert transaction update");
+        let (token_cache, metrics) = (TokenCache::default(), NodeMetrics::new());
+        let swap_event = |quote_pair_policy| {
+            let config = SwapFilterConfig { quote_pair_policy, ..Default::default() };
+            let (accounts, transfers, transaction_metadata) =
+                (&accounts, &transfers, &transaction_metadata);
+            let (kv_store, db, token_cache, metrics) = (&kv_store, &db, &token_cache, &metrics);
+            async move {
+                get_swap_event_with_token_transfer_details(
+                    Dexes::OcraWhirlpool,
+                    accounts,
+                    transfers,
+                    &[],
+                    transaction_metadata,
+                    kv_store,
+                    db,
+                    token_cache,
+                    metrics,
+                    &config,
+                    None,
+                )
+                .await
+            }
+        };
+
+        let skipped = swap_event(QuotePairPolicy::Skip).await;
+        assert!(matches!(skipped, Err(SwapError::QuotePairSwap)), "{skipped:?}");
+
+        // the flagged swap is stored, priced at par
+        let (event, token) = swap_event(QuotePairPolicy::Flag).await.unwrap();
+        assert!(event.is_quote_pair);
+        assert_eq!(event.pubkey, USDC_MINT_KEY_STR);
+        assert_eq!(event.swap_amount, 999.0);
+        assert!(token.is_some());
+    }
+
+    /// transfer_between returns a transfer of `mint` from `source` to `destination`
+    fn transfer_between(mint: &str, source: &str, destination: &str) -> TokenTransferDetails {
+        TokenTransferDetails {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            ..transfer(mint, 1.0)
+        }
+    }
+
+    #[test]
+    fn test_swap_direction_inverted_vaults() {
+        // the pool lists the vaults against its mint order, so the account read as its quote
+        // vault is the token account the user receives WSOL in
+        let accounts = TokenSwapAccounts {
+            pair: "inverted-cpmm-pool".to_string(),
+            user_adas: HashSet::from(["user_token".to_string(), "user_wsol".to_string()]),
+            vault_adas: HashSet::from(["user_wsol".to_string(), "vault_token".to_string()]),
+            fee_adas: None,
+            quote_mints: Arc::new(HashSet::from([WSOL_MINT_KEY_STR.to_string()])),
+        };
+
+        // the user pays WSOL for the token
+        let buy = [
+            transfer_between(WSOL_MINT_KEY_STR, "user_wsol", "vault_wsol"),
+            transfer_between("token", "vault_token", "user_token"),
+        ];
+        let (is_buy, base, quote) = get_base_quote_mint(&accounts, &buy).unwrap();
+        assert!(is_buy);
+        assert_eq!((base.mint.as_str(), quote.mint.as_str()), ("token", WSOL_MINT_KEY_STR));
+        assert!(vault_is_buy(&accounts, base, quote));
+
+        // the user sells the token for WSOL, which the vaults alone labeled a buy
+        let sell = [
+            transfer_between("token", "user_token", "vault_token"),
+            transfer_between(WSOL_MINT_KEY_STR, "vault_wsol", "user_wsol"),
+        ];
+        let (is_buy, base, quote) = get_base_quote_mint(&accounts, &sell).unwrap();
+        assert!(!is_buy);
+        assert_eq!(user_is_buy(&accounts, base, quote), Some(false));
+        assert!(vault_is_buy(&accounts, base, quote));
+    }
+
+    #[test]
+    fn test_swap_direction_falls_back_to_vaults() {
+        let accounts = TokenSwapAccounts {
+            pair: "pool".to_string(),
+            user_adas: HashSet::new(),
+            vault_adas: HashSet::from(["vault_wsol".to_string(), "vault_token".to_string()]),
+            fee_adas: None,
+            quote_mints: Arc::new(HashSet::from([WSOL_MINT_KEY_STR.to_string()])),
+        };
+        let sell = [
+            transfer_between("token", "user_token", "vault_token"),
+            transfer_between(WSOL_MINT_KEY_STR, "vault_wsol", "user_wsol"),
+        ];
+        assert_eq!(user_is_buy(&accounts, &sell[0], &sell[1]), None);
+        let (is_buy, _, _) = get_base_quote_mint(&accounts, &sell).unwrap();
+        assert!(!is_buy);
+
+        // the user accounts on both sides can't tell the direction either
+        let accounts = TokenSwapAccounts {
+            user_adas: HashSet::from(["user_token".to_string(), "vault_token".to_string()]),
+            ..accounts
+        };
+        assert_eq!(user_is_buy(&accounts, &sell[0], &sell[1]), None);
+        let (is_buy, _, _) = get_base_quote_mint(&accounts, &sell).unwrap();
+        assert!(!is_buy);
+    }
+
+    #[tokio::test]
+    async fn test_onchain_sol_price() {
+        let (sol, usdc) = (transfer(WSOL_MINT_KEY_STR, 2.0), transfer(USDC_MINT_KEY_STR, 300.0));
+        let sol_price = get_onchain_sol_price(&sol, &usdc).expect("Expected a SOL price");
+        assert_eq!(sol_price, 150.0);
+        assert_eq!(get_onchain_sol_price(&sol, &transfer(USDT_MINT_KEY_STR, 310.0)), Some(155.0));
+        assert_eq!(get_onchain_sol_price(&usdc, &sol), None);
+        assert_eq!(get_onchain_sol_price(&transfer("token", 2.0), &usdc), None);
+
+        let cache: SolPriceCacheRef = Arc::new(MemorySolPriceCache::new(140.0));
+        // dust swaps don't update the cache
+        assert!(!update_onchain_sol_price(&cache, 1000.0, 1.0, 100.0).await);
+        assert_eq!(cache.get_price().await, 140.0);
+        assert!(update_onchain_sol_price(&cache, sol_price, usdc.ui_amount, 100.0).await);
+        assert_eq!(cache.get_price().await, 150.0);
+    }
+
+    const JLP_MINT: &str = "27G8MtK7VtTcCHkpASjSDdkWWYfoqT6ggEuKidVJidD4";
+
+    fn quote_swap_event(mint: &str, timestamp: u64, price: f64) -> SwapEvent {
+        let signature = format!("{mint}-{timestamp}");
+        SwapEvent {
+            market_cap: 0.0,
+            base_amount: 1.0,
+            quote_amount: price,
+            swap_amount: price,
+            ..make_swap_event(mint, "jlp-pair", &signature, timestamp, price)
+        }
+    }
+
+    fn memory_storages() -> (Arc<KvStore>, Arc<Database>) {
+        let db: Database = Box::new(sonar_db::MemoryDb::default());
+        (Arc::new(KvStore::in_memory()), Arc::new(db))
+    }
+
+    #[tokio::test]
+    async fn test_generic_quote_price_fresh_kv() {
+        let (kv_store, db) = memory_storages();
+        let trade: Trade = quote_swap_event(JLP_MINT, 1_000, 4.5).into();
+        kv_store.insert_price(&trade).await.unwrap();
+
+        let price = get_generic_quote_price(JLP_MINT, 1_100, &kv_store, &db, 300).await;
+        assert_eq!(price, Some(4.5));
+        let max_staleness = Duration::from_secs(300);
+        let (_, quote_price) =
+            get_quote_price(JLP_MINT, Some(1_100), &kv_store, &db, max_staleness).await;
+        assert_eq!(quote_price, 4.5);
+    }
+
+    #[tokio::test]
+    async fn test_generic_quote_price_stale_kv_falls_back_to_db() {
+        let (kv_store, db) = memory_storages();
+        let trade: Trade = quote_swap_event(JLP_MINT, 1_000, 4.5).into();
+        kv_store.insert_price(&trade).await.unwrap();
+        db.insert_swap_event(&quote_swap_event(JLP_MINT, 1_900, 4.7)).await.unwrap();
+        // the swaps after the priced swap are not used
+        db.insert_swap_event(&quote_swap_event(JLP_MINT, 2_100, 9.9)).await.unwrap();
+
+        let price = get_generic_quote_price(JLP_MINT, 2_000, &kv_store, &db, 300).await;
+        assert_eq!(price, Some(4.7));
+    }
+
+    #[tokio::test]
+    async fn test_generic_quote_price_unpriced() {
+        let (kv_store, db) = memory_storages();
+        assert_eq!(get_generic_quote_price(JLP_MINT, 2_000, &kv_store, &db, 300).await, None);
+        let (quote_mint, quote_price) =
+            get_quote_price(JLP_MINT, Some(2_000), &kv_store, &db, Duration::from_secs(300)).await;
+        assert_eq!((quote_mint.as_str(), quote_price), (JLP_MINT, 0.0));
+
+        let metrics = NodeMetrics::new();
+        update_metrics_for_swap_error(&metrics, SwapError::UnpricedQuote);
+        assert_eq!(metrics.skipped_unpriced_quote.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_handle_storage_error() {
+        let metrics = NodeMetrics::new();
+        for e in [
+            sonar_db::StorageError::Connection("connection refused".to_string()),
+            sonar_db::StorageError::Timeout("timed out".to_string()),
+            sonar_db::StorageError::RateLimited("too many parts".to_string()),
+        ] {
+            handle_storage_error(&metrics, &anyhow::Error::new(e).context("insert failed"));
+            assert!(metrics.is_healthy());
+        }
+        handle_storage_error(&metrics, &anyhow::anyhow!("unexpected"));
+        assert!(metrics.is_healthy());
+
+        let e = sonar_db::StorageError::SchemaMismatch { detail: "No such column".to_string() };
+        handle_storage_error(&metrics, &anyhow::Error::new(e).context("insert failed"));
+        assert!(!metrics.is_healthy());
+        // the flag only flips once
+        assert!(!metrics.mark_storage_fatal());
+    }
+
+    #[test]
+    fn test_record_skipped_swaps() {
+        let metrics = NodeMetrics::new();
+        let log = SkippedSwapLog::default();
+        for (dex, pair, signature, e) in [
+            (Dexes::PumpAmm, "pump-pool", "tiny", SwapError::TinySwap),
+            (Dexes::RaydiumCpmm, "cpmm-pool", "unpriced", SwapError::UnpricedQuote),
+            (Dexes::PumpAmm, "pump-pool", "tiny-usd", SwapError::TinySwapUsd),
+            (Dexes::PumpAmm, "pump-pool", "tiny-again", SwapError::TinySwap),
+            (Dexes::RaydiumCpmm, "cpmm-pool", "tiny", SwapError::ZeroSwap),
+        ] {
+            record_skipped_swap(&metrics, &log, dex, pair, signature, e);
+        }
+        assert_eq!(log.len(), 5);
+        assert_eq!(metrics.skipped_tiny_swaps.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        let query = |reason: Option<&str>, dex, limit| {
+            let query = SkippedSwapQuery { reason: reason.map(str::to_string), dex, limit };
+            log.query(&query).into_iter().map(|swap| swap.signature).collect::<Vec<_>>()
+        };
+        assert_eq!(query(Some("tiny_swap"), None, None), vec!["tiny-again", "tiny"]);
+        assert_eq!(query(None, Some(Dexes::RaydiumCpmm), None), vec!["tiny", "unpriced"]);
+        assert_eq!(query(Some("tiny_swap"), Some(Dexes::RaydiumCpmm), None), Vec::<String>::new());
+        assert_eq!(query(None, Some(Dexes::PumpAmm), Some(1)), vec!["tiny-again"]);
+
+        // one transaction skipped on two dexes
+        let swaps = log.find("tiny");
+        assert_eq!(swaps.len(), 2);
+        assert_eq!((swaps[0].dex, swaps[0].reason.as_str()), (Dexes::RaydiumCpmm, "zero_swap"));
+        assert_eq!((swaps[1].pair.as_str(), swaps[1].reason.as_str()), ("pump-pool", "tiny_swap"));
+    }
+
+    #[tokio::test]
+    async fn test_process_with_timeout() {
+        let metrics = NodeMetrics::new();
+        let timeout = Duration::from_millis(50);
+        // a token metadata lookup stuck on a stalled RPC
+        let stalled = async {
+            std::future::pending::<Result<f64>>().await.map_err(SwapError::TokenMetadataFailure)?;
+            Ok::<(), SwapError>(())
+        };
+        let started_at = std::time::Instant::now();
+        let result = process_with_timeout(stalled, timeout, &metrics).await;
+        assert!(matches!(result, Err(SwapError::Timeout)));
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        assert_eq!(metrics.timed_out_swaps.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        let result = process_with_timeout(async { Ok(()) }, timeout, &metrics).await;
+        assert!(result.is_ok());
+        assert_eq!(metrics.timed_out_swaps.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_is_pump_swap() {
+        // a pump AMM swap is pump whatever its mint, a mint ending in "pump" isn't enough
+        assert!(is_pump_swap(Dexes::PumpAmm, ""));
+        assert!(!is_pump_swap(Dexes::MeteoraDlmm, ""));
+        assert!(!is_pump_swap(Dexes::RaydiumLaunchpad, "raydium_launchpad"));
+        assert!(is_pump_swap(Dexes::RaydiumCpmm, PUMP_LAUNCHPAD));
+    }
+
+    #[tokio::test]
+    async fn test_record_token_launchpad() {
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let (kv_store, _, db) = storages.storages();
+        let mint = "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpEvL";
+        storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        let token = kv_store.get_token(mint).await.unwrap().unwrap();
+        db.insert_token(&token).await.unwrap();
+
+        // the first trade on a launchpad records it
+        let token_cache = TokenCache::default();
+        let launchpad =
+            record_token_launchpad(Dexes::PumpAmm, token, &kv_store, &db, &token_cache).await;
+        assert_eq!(launchpad, PUMP_LAUNCHPAD);
+        assert_eq!(token_cache.get(mint).unwrap().launchpad, PUMP_LAUNCHPAD);
+        assert!(is_pump_swap(Dexes::PumpAmm, &launchpad));
+        assert_eq!(db.get_token(mint).await.unwrap().unwrap().launchpad, PUMP_LAUNCHPAD);
+
+        // the graduated token keeps the flag on the other dexes and launchpads
+        for dex in [Dexes::RaydiumCpmm, Dexes::RaydiumLaunchpad] {
+            let token = kv_store.get_token(mint).await.unwrap().unwrap();
+            let launchpad = record_token_launchpad(dex, token, &kv_store, &db, &token_cache).await;
+            assert_eq!(launchpad, PUMP_LAUNCHPAD);
+            assert!(is_pump_swap(dex, &launchpad));
+        }
+
+        // a mint ending in "pump" never seen on pump isn't flagged
+        let mint = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+        storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        let token = kv_store.get_token(mint).await.unwrap().unwrap();
+        let launchpad =
+            record_token_launchpad(Dexes::MeteoraDlmm, token, &kv_store, &db, &token_cache).await;
+        assert!(launchpad.is_empty());
+        assert!(!is_pump_swap(Dexes::MeteoraDlmm, &launchpad));
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_token_metadata() {
+        use std::sync::atomic::Ordering;
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let (kv_store, _, db) = storages.storages();
+        let mint = "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpEvL";
+        storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        let mut token = kv_store.get_token(mint).await.unwrap().unwrap();
+        db.insert_token(&token).await.unwrap();
+        let (token_cache, metrics) = (TokenCache::default(), NodeMetrics::new());
+
+        // the first lookup resolves the token through the storages
+        let cached = get_cached_token_metadata(mint, &kv_store, &db, &token_cache, &metrics);
+        assert_eq!(cached.await.unwrap().supply, 1_000_000_000.0);
+        assert_eq!(metrics.token_cache_misses.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.token_cache_entries.load(Ordering::Relaxed), 1);
+        assert!(metrics.token_cache_bytes.load(Ordering::Relaxed) > 0);
+
+        // the next ones are served from the cache without reading the KV store
+        token.supply = 2_000_000_000.0;
+        kv_store.set_token(mint, &token).await.unwrap();
+        for _ in 0..3 {
+            let cached = get_cached_token_metadata(mint, &kv_store, &db, &token_cache, &metrics);
+            assert_eq!(cached.await.unwrap().supply, 1_000_000_000.0);
+        }
+        assert_eq!(metrics.token_cache_hits.load(Ordering::Relaxed), 3);
+        assert_eq!(metrics.token_cache_misses.load(Ordering::Relaxed), 1);
+
+        // a token only in the db is written to the KV store on its first resolution
+        let mint = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+        token.token = mint.to_string();
+        db.insert_token(&token).await.unwrap();
+        assert!(kv_store.get_token(mint).await.unwrap().is_none());
+        get_cached_token_metadata(mint, &kv_store, &db, &token_cache, &metrics).await.unwrap();
+        assert!(kv_store.get_token(mint).await.unwrap().is_some());
+        assert_eq!(metrics.token_cache_misses.load(Ordering::Relaxed), 2);
+    }
+
     #[test]
     #[allow(clippy::excessive_precision)]
     fn test_f64_to_u64() {
@@ -677,4 +2117,417 @@ mod tests {
             supply_bigdecimal.div(10_f64.powi(5)).to_f64().expect("failed to convert to f64");
         assert_eq!(actual, 99999981186616.10216);
     }
+
+    #[test]
+    fn test_swap_price() {
+        // a dust amount of a 9 decimals token is still priced
+        let (price, swap_amount) = swap_price(0.000_000_001, 0.5, 150.0).unwrap();
+        assert!((price - 75_000_000_000.0).abs() < 1.0);
+        assert_eq!(swap_amount, 75.0);
+        // the u64::MAX raw amounts stay finite
+        let max_ui_amount = u64::MAX as f64 / 1e9;
+        let (price, swap_amount) = swap_price(max_ui_amount, max_ui_amount, 1.0).unwrap();
+        assert_eq!(price, 1.0);
+        assert!(swap_amount.is_finite());
+
+        // zero and subnormal denominators would be infinite prices
+        assert!(matches!(swap_price(0.0, 1.0, 150.0), Err(SwapError::ZeroSwap)));
+        assert!(matches!(
+            swap_price(f64::MIN_POSITIVE / 2.0, 1.0, 150.0),
+            Err(SwapError::ZeroSwap)
+        ));
+        assert!(matches!(swap_price(1.0, f64::NAN, 150.0), Err(SwapError::ZeroSwap)));
+        // the price overflows, or underflows to zero
+        assert!(matches!(
+            swap_price(0.000_000_001, u64::MAX as f64, f64::MAX),
+            Err(SwapError::InvalidPrice)
+        ));
+        assert!(matches!(swap_price(1e300, 1e-300, 1.0), Err(SwapError::InvalidPrice)));
+        assert!(matches!(swap_price(1.0, 1.0, f64::INFINITY), Err(SwapError::InvalidPrice)));
+    }
+
+    #[test]
+    fn test_update_market_cap_keeps_finite() {
+        let mut swap_event = quote_swap_event(JLP_MINT, 1_000, 2.0);
+        swap_event.update_market_cap(1_000.0);
+        assert_eq!(swap_event.market_cap, 2_000.0);
+        swap_event.update_market_cap(f64::MAX);
+        assert_eq!(swap_event.market_cap, 0.0);
+        swap_event.update_market_cap(f64::NAN);
+        assert_eq!(swap_event.market_cap, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_pct_of_supply() {
+        let storages = crate::test_swaps::MemoryStorages::default();
+        storages.seed_token(JLP_MINT, 6, 1_000_000.0).await;
+        let token = storages.kv_store.get_token(JLP_MINT).await.unwrap().unwrap();
+        assert_eq!(pct_of_supply(5_000.0, Some(&token)), 0.005);
+        assert_eq!(pct_of_supply(1_000_000.0, Some(&token)), 1.0);
+        // the supply is unknown
+        assert_eq!(pct_of_supply(5_000.0, None), 0.0);
+        let zero_supply = Token { supply: 0.0, ..token.clone() };
+        assert_eq!(pct_of_supply(5_000.0, Some(&zero_supply)), 0.0);
+        let nan_supply = Token { supply: f64::NAN, ..token.clone() };
+        assert_eq!(pct_of_supply(5_000.0, Some(&nan_supply)), 0.0);
+        // the NFTs and zero decimal tokens have a supply of a few units
+        let nft = Token { is_nft: true, decimals: 0, supply: 1.0, ..token.clone() };
+        assert_eq!(pct_of_supply(1.0, Some(&nft)), 0.0);
+        let units = Token { decimals: 0, supply: 10.0, ..token.clone() };
+        assert_eq!(pct_of_supply(1.0, Some(&units)), 0.0);
+        // a supply below the swapped amount isn't in ui units
+        assert_eq!(pct_of_supply(2_000_000.0, Some(&token)), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_route_large_trade() {
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let (_, message_queue, _) = storages.storages();
+        let trade = |pct_of_supply| TradeV2 {
+            trade: Trade { pct_of_supply, ..quote_swap_event(JLP_MINT, 1_000, 2.0).into() },
+            dex: Dexes::MeteoraDlmm,
+        };
+
+        assert!(!is_large_trade(0.004, 0.005));
+        assert!(is_large_trade(0.005, 0.005));
+        // a threshold of 0 disables the large trades
+        assert!(!is_large_trade(1.0, 0.0));
+
+        assert!(!route_large_trade(&message_queue, &trade(0.001), 0.005).await);
+        assert!(route_large_trade(&message_queue, &trade(0.01), 0.005).await);
+        assert!(!route_large_trade(&message_queue, &trade(0.01), 0.0).await);
+        let large_trades = storages.message_queue.large_trades();
+        assert_eq!(large_trades.len(), 1);
+        assert_eq!(large_trades[0].trade.pct_of_supply, 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_record_token_graduation() {
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let (kv_store, message_queue, db) = storages.storages();
+        let mint = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+        storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        let event = TokenGraduatedEvent {
+            mint: mint.to_string(),
+            pool: "pool".to_string(),
+            dex: Dexes::PumpAmm,
+            timestamp: 1_000,
+        };
+        let token_cache = TokenCache::default();
+        let recorded =
+            record_token_graduation(&event, &kv_store, &message_queue, &db, &token_cache);
+        assert!(recorded.await.unwrap());
+        let token = kv_store.get_token(mint).await.unwrap().unwrap();
+        assert_eq!((token.graduated_at, token.graduation_pool.as_str()), (1_000, "pool"));
+        assert_eq!(token_cache.get(mint).unwrap().graduated_at, 1_000);
+        assert_eq!(storages.message_queue.graduations(), vec![event.clone()]);
+
+        // a token graduates once
+        let later = TokenGraduatedEvent { pool: "other".to_string(), timestamp: 2_000, ..event };
+        let recorded =
+            record_token_graduation(&later, &kv_store, &message_queue, &db, &token_cache);
+        assert!(!recorded.await.unwrap());
+        assert_eq!(kv_store.get_token(mint).await.unwrap().unwrap().graduation_pool, "pool");
+        assert_eq!(storages.message_queue.graduations().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_record_token_launch_once() {
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let (kv_store, _, db) = storages.storages();
+        let mint = "24YqgtkwPMmfMHNfvErYLomsuw1R4CWv5V9iaC22bonk";
+        storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        let token = kv_store.get_token(mint).await.unwrap().unwrap();
+        db.insert_token(&token).await.unwrap();
+        let token_cache = TokenCache::default();
+
+        let dex = Dexes::RaydiumLaunchpad;
+        let recorded = record_token_launch(mint, dex, "curve", 1_000, &kv_store, &db, &token_cache);
+        assert!(recorded.await.unwrap());
+        let launch = kv_store.get_token(mint).await.unwrap().unwrap().launch();
+        assert_eq!(launch.launch_dex, Some(Dexes::RaydiumLaunchpad));
+        assert_eq!((launch.launch_pool.as_str(), launch.launch_timestamp), ("curve", 1_000));
+        assert_eq!(token_cache.get(mint).unwrap().launch(), launch);
+
+        // the launch delivered again, or another pool of the token, doesn't replace it
+        for pool in ["curve", "other"] {
+            let recorded =
+                record_token_launch(mint, dex, pool, 2_000, &kv_store, &db, &token_cache);
+            assert!(!recorded.await.unwrap());
+        }
+        db.set_token_launch_info(mint, "pump_fun", "other", 3_000).await.unwrap();
+        assert_eq!(db.get_token(mint).await.unwrap().unwrap().launch(), launch);
+        assert_eq!(kv_store.get_token(mint).await.unwrap().unwrap().launch(), launch);
+    }
+
+    /// large_transaction builds a router transaction with `pairs` token transfers, each nested
+    /// three CPIs deep, and the token balances of their `2 * pairs` accounts
+    fn large_transaction(pairs: u8) -> carbon_core::datasource::TransactionUpdate {
+        use crate::constants::TOKEN_PROGRAM_ID;
+        use solana_message::{compiled_instruction::CompiledInstruction, v0, VersionedMessage};
+        use solana_pubkey::Pubkey;
+        use solana_signature::Signature;
+        use solana_transaction::versioned::VersionedTransaction;
+        use solana_transaction_status::{
+            InnerInstruction, InnerInstructions, TransactionStatusMeta,
+        };
+
+        // the payer, the router, the token program, then the token accounts
+        const FIRST_ACCOUNT: u8 = 3;
+        let mut account_keys = vec![Pubkey::new_unique(), Pubkey::new_unique(), TOKEN_PROGRAM_ID];
+        account_keys.extend((0..2 * pairs).map(|_| Pubkey::new_unique()));
+        let mints = [(Pubkey::new_unique().to_string(), 6), (WSOL_MINT_KEY_STR.to_string(), 9)];
+        let balances: Vec<TransactionTokenBalance> = (0..2 * pairs)
+            .map(|i| {
+                let (mint, decimals) = &mints[(i / 2 % 2) as usize];
+                TransactionTokenBalance {
+                    account_index: FIRST_ACCOUNT + i,
+                    mint: mint.clone(),
+                    ui_token_amount: UiTokenAmount {
+                        ui_amount: None,
+                        decimals: *decimals,
+                        amount: "0".to_string(),
+                        ui_amount_string: "0".to_string(),
+                    },
+                    owner: account_keys[0].to_string(),
+                    program_id: TOKEN_PROGRAM_ID.to_string(),
+                }
+            })
+            .collect();
+
+        let cpi = |stack_height| InnerInstruction {
+            instruction: CompiledInstruction {
+                program_id_index: 1,
+                accounts: vec![],
+                data: vec![],
+            },
+            stack_height: Some(stack_height),
+        };
+        let inner_instructions = (0..pairs)
+            .flat_map(|i| {
+                let source = FIRST_ACCOUNT + 2 * i;
+                let mut data = vec![3];
+                data.extend_from_slice(&(1_000_000_000 + i as u64).to_le_bytes());
+                let transfer = InnerInstruction {
+                    instruction: CompiledInstruction {
+                        program_id_index: 2,
+                        accounts: vec![source, source + 1, 0],
+                        data,
+                    },
+                    stack_height: Some(4),
+                };
+                [cpi(2), cpi(3), transfer]
+            })
+            .collect();
+
+        let message = v0::Message {
+            header: solana_message::MessageHeader {
+                num_required_signatures: 1,
+                ..Default::default()
+            },
+            account_keys,
+            instructions: vec![CompiledInstruction {
+                program_id_index: 1,
+                accounts: vec![],
+                data: vec![],
+            }],
+            ..Default::default()
+        };
+        carbon_core::datasource::TransactionUpdate {
+            signature: Signature::default(),
+            transaction: VersionedTransaction {
+                signatures: vec![Signature::default()],
+                message: VersionedMessage::V0(message),
+            },
+            meta: TransactionStatusMeta {
+                inner_instructions: Some(vec![InnerInstructions {
+                    index: 0,
+                    instructions: inner_instructions,
+                }]),
+                pre_token_balances: Some(balances.clone()),
+                post_token_balances: Some(balances),
+                ..Default::default()
+            },
+            is_vote: false,
+            slot: 0,
+            block_time: None,
+            block_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_large_transaction_transfers() {
+        let pairs = 32;
+        let transaction_update = large_transaction(pairs);
+        let transaction_metadata: TransactionMetadata =
+            transaction_update.clone().try_into().expect("Failed to convert transaction update");
+        let nested_instructions =
+            crate::test_swaps::extract_nested_instructions(&transaction_update)
+                .expect("Failed to extract nested instructions");
+
+        let transfers = get_inner_token_transfers(&transaction_metadata, &nested_instructions);
+        assert_eq!(transfers.len(), pairs as usize);
+        for (i, transfer) in transfers.iter().enumerate() {
+            let decimals = if i % 2 == 0 { 6 } else { 9 };
+            assert_eq!(transfer.decimals, decimals, "transfer {i}");
+            assert_eq!(transfer.amount, 1_000_000_000 + i as u64);
+            assert_eq!(
+                transfer.ui_amount,
+                spl_token::amount_to_ui_amount(transfer.amount, decimals)
+            );
+            assert_eq!(transfer.mint == WSOL_MINT_KEY_STR, i % 2 == 1);
+        }
+    }
+
+    #[test]
+    fn test_duplicated_and_deep_instructions() {
+        let transaction_update = large_transaction(2);
+        let transaction_metadata: TransactionMetadata =
+            transaction_update.clone().try_into().expect("Failed to convert transaction update");
+        let nested_instructions =
+            crate::test_swaps::extract_nested_instructions(&transaction_update)
+                .expect("Failed to extract nested instructions");
+        let no_vaults = HashSet::new();
+        let walk = |nested_instructions: &[NestedInstruction], max_depth| {
+            walk_inner_token_transfers(
+                &transaction_metadata,
+                nested_instructions,
+                &no_vaults,
+                max_depth,
+            )
+        };
+
+        // the swap subtree passed twice counts once
+        let mut repeated = nested_instructions.to_vec();
+        repeated.extend(nested_instructions.iter().cloned());
+        let inner = walk(&repeated, DEFAULT_MAX_INSTRUCTION_DEPTH);
+        assert_eq!(inner.transfers.len(), 2);
+        assert_eq!(inner.duplicate_instructions, 1);
+
+        // so does a CPI loop nesting the outer instruction again
+        let mut looped = nested_instructions[0].clone();
+        looped.inner_instructions[0].inner_instructions.push(nested_instructions[0].clone());
+        let inner = walk(std::slice::from_ref(&looped), DEFAULT_MAX_INSTRUCTION_DEPTH);
+        assert_eq!(inner.transfers.len(), 2);
+        assert_eq!(inner.duplicate_instructions, 1);
+        assert!(!inner.depth_exceeded);
+
+        // the transfers are nested at the fourth level
+        let inner = walk(&nested_instructions, 3);
+        assert!(inner.transfers.is_empty());
+        assert!(inner.depth_exceeded);
+        let inner = walk(&nested_instructions, 4);
+        assert_eq!(inner.transfers.len(), 2);
+        assert!(!inner.depth_exceeded);
+        // the depth given to the extraction applies
+        let transfers = |max_depth| {
+            get_inner_token_transfers_with_vaults(
+                &transaction_metadata,
+                &nested_instructions,
+                &no_vaults,
+                max_depth,
+            )
+        };
+        assert!(transfers(3).is_empty());
+        assert_eq!(transfers(4).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_native_sol_swap_is_not_skipped() {
+        use crate::constants::SYSTEM_PROGRAM_ID;
+        use solana_instruction::{AccountMeta, Instruction};
+        use solana_pubkey::Pubkey;
+
+        // the user pays 1 SOL as lamports into the sol vault of the pool and receives 1000
+        // tokens out of its token vault, the only SPL transfer of the swap
+        let transaction_update = large_transaction(1);
+        let mut nested_instructions =
+            crate::test_swaps::extract_nested_instructions(&transaction_update)
+                .expect("Failed to extract nested instructions")
+                .to_vec();
+        let transaction_metadata: TransactionMetadata =
+            transaction_update.try_into().expect("Failed to convert transaction update");
+        let leaves = &mut nested_instructions[0].inner_instructions[0].inner_instructions[0]
+            .inner_instructions;
+        let (vault_token, user_token, user) = match &leaves[0].instruction.accounts[..] {
+            [source, destination, authority] => {
+                (source.pubkey, destination.pubkey, authority.pubkey)
+            }
+            accounts => panic!("Unexpected transfer accounts {accounts:?}"),
+        };
+        let sol_vault = Pubkey::new_unique();
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&1_000_000_000u64.to_le_bytes());
+        let mut native_transfer = leaves[0].clone();
+        native_transfer.instruction = Instruction {
+            program_id: SYSTEM_PROGRAM_ID,
+            accounts: vec![AccountMeta::new(user, true), AccountMeta::new(sol_vault, false)],
+            data,
+        };
+        leaves.push(native_transfer);
+
+        let accounts = TokenSwapAccounts {
+            pair: "native-sol-pool".to_string(),
+            user_adas: HashSet::from([user.to_string(), user_token.to_string()]),
+            vault_adas: HashSet::from([vault_token.to_string(), sol_vault.to_string()]),
+            fee_adas: None,
+            quote_mints: Arc::new(HashSet::from([WSOL_MINT_KEY_STR.to_string()])),
+        };
+        // the SPL transfers alone hold a single leg of the swap
+        let spl_transfers = get_inner_token_transfers(&transaction_metadata, &nested_instructions);
+        assert_eq!(filter_swap_transfers(&spl_transfers, &accounts).len(), 1);
+
+        let storages = crate::test_swaps::MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
+        let mint = &spl_transfers[0].mint;
+        storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        token_swap_handler.spawn_swap_instruction(
+            Dexes::RaydiumCpmm,
+            &accounts,
+            &nested_instructions[0].metadata,
+            &nested_instructions[0].inner_instructions,
+        );
+
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(
+            swap_events.len(),
+            1,
+            "{:?}",
+            token_swap_handler.skipped_swaps.query(&Default::default())
+        );
+        assert_eq!(swap_events[0].pubkey, *mint);
+        assert_eq!(swap_events[0].base_amount, 1000.0);
+        assert_eq!(swap_events[0].quote_amount, 1.0);
+        let synthesized = &token_swap_handler.metrics.synthesized_native_transfers;
+        assert_eq!(synthesized.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_swap_owner() {
+        use solana_pubkey::Pubkey;
+
+        // a bot pays the fees of a swap between the token accounts of its user
+        let mut transaction_update = large_transaction(1);
+        let user = Pubkey::new_unique().to_string();
+        for balance in transaction_update.meta.pre_token_balances.iter_mut().flatten() {
+            balance.owner = user.clone();
+        }
+        let transaction_metadata: TransactionMetadata =
+            transaction_update.try_into().expect("Failed to convert transaction update");
+        let fee_payer = transaction_metadata.fee_payer.to_string();
+        let account_keys = transaction_metadata.message.static_account_keys();
+        let (source, destination) = (account_keys[3].to_string(), account_keys[4].to_string());
+
+        let mut base = transfer("token", 1.0);
+        base.destination = destination.clone();
+        let mut quote = transfer(WSOL_MINT_KEY_STR, 1.0);
+        quote.source = source.clone();
+        let user_adas = HashSet::from([source, destination]);
+        assert_eq!(swap_owner(&user_adas, &base, &quote, &transaction_metadata), user);
+
+        // the pool side accounts aren't attributed, the fee payer is the fallback
+        let pool_adas = HashSet::from([Pubkey::new_unique().to_string()]);
+        assert_eq!(swap_owner(&pool_adas, &base, &quote, &transaction_metadata), fee_payer);
+        assert_eq!(swap_owner(&HashSet::new(), &base, &quote, &transaction_metadata), fee_payer);
+    }
 }
@@ -1,22 +1,107 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use sonar_pipeline_metrics::PipelineMetrics;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tracing::info;
 
+/// How often a swap the vaults disagree on is logged, the others are only counted
+const DIRECTION_DISAGREEMENT_LOG_INTERVAL_MS: i64 = 10_000;
+
+/// A stage of the processing of a swap, timed separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapStage {
+    TransferExtraction,
+    MetadataLookup,
+    DbInsert,
+    MqPublish,
+    KvInsert,
+}
+
+impl SwapStage {
+    pub const ALL: [SwapStage; 5] = [
+        SwapStage::TransferExtraction,
+        SwapStage::MetadataLookup,
+        SwapStage::DbInsert,
+        SwapStage::MqPublish,
+        SwapStage::KvInsert,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwapStage::TransferExtraction => "transfer_extraction",
+            SwapStage::MetadataLookup => "metadata_lookup",
+            SwapStage::DbInsert => "db_insert",
+            SwapStage::MqPublish => "mq_publish",
+            SwapStage::KvInsert => "kv_insert",
+        }
+    }
+}
+
+/// The number of runs of a stage and their summed duration
+#[derive(Debug, Default)]
+pub struct StageTiming {
+    pub count: AtomicU64,
+    pub total_micros: AtomicU64,
+}
+
+impl StageTiming {
+    /// average_micros returns the mean duration of the stage, 0 if it never ran
+    pub fn average_micros(&self) -> u64 {
+        let count = self.count.load(Ordering::Relaxed);
+        self.total_micros.load(Ordering::Relaxed).checked_div(count).unwrap_or(0)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct NodeMetrics {
     pub total_swaps_processed: AtomicU64,
     pub succeed_swaps: AtomicU64,
     pub failed_swaps: AtomicU64,
     pub skipped_tiny_swaps: AtomicU64,
+    pub skipped_tiny_usd_swaps: AtomicU64,
     pub skipped_zero_swaps: AtomicU64,
     pub skipped_no_metadata: AtomicU64,
     pub skipped_unexpected_swaps: AtomicU64,
     pub skipped_unknown_swaps: AtomicU64,
+    pub skipped_unpriced_quote: AtomicU64,
+    pub skipped_invalid_price: AtomicU64,
+    pub skipped_quote_pair_swaps: AtomicU64,
+    pub rejected_swaps: AtomicU64,
+    pub timed_out_swaps: AtomicU64,
     pub message_send_success: AtomicU64,
     pub message_send_failure: AtomicU64,
     pub db_insert_success: AtomicU64,
     pub db_insert_failure: AtomicU64,
     pub kv_insert_success: AtomicU64,
     pub kv_insert_failure: AtomicU64,
+    pub synthesized_native_transfers: AtomicU64,
+    /// The nested instructions skipped as exact duplicates of a walked one
+    pub duplicate_instructions: AtomicU64,
+    /// The transactions whose instructions nested deeper than the walked depth
+    pub instruction_depth_exceeded: AtomicU64,
+    pub tagged_wash_swaps: AtomicU64,
+    /// The swaps whose direction from the user accounts contradicts the one from the vaults
+    pub direction_disagreements: AtomicU64,
+    /// When a direction disagreement was last logged, in unix milliseconds
+    pub direction_disagreement_logged_ms: AtomicI64,
+    /// The metadata lookups served by the process token cache and the ones resolved
+    pub token_cache_hits: AtomicU64,
+    pub token_cache_misses: AtomicU64,
+    /// The tokens in the cache and their estimated memory, as of the last miss
+    pub token_cache_entries: AtomicU64,
+    pub token_cache_bytes: AtomicU64,
+    pub last_processed_slot: AtomicU64,
+    pub slot_lag: AtomicU64,
+    /// Set once the storage reported an error retrying can't fix, e.g. a schema mismatch
+    pub storage_fatal: AtomicBool,
+    /// Indexed by `SwapStage`
+    pub stage_timings: [StageTiming; SwapStage::ALL.len()],
+    /// The updates received and processed by the carbon pipeline
+    pub pipeline: Arc<PipelineMetrics>,
 }
 
 impl NodeMetrics {
@@ -43,6 +128,10 @@ impl NodeMetrics {
         self.skipped_tiny_swaps.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn increment_skipped_tiny_usd_swaps(&self) {
+        self.skipped_tiny_usd_swaps.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn increment_skipped_zero_swaps(&self) {
         self.skipped_zero_swaps.fetch_add(1, Ordering::Relaxed);
     }
@@ -59,6 +148,26 @@ impl NodeMetrics {
         self.skipped_unknown_swaps.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn increment_skipped_unpriced_quote(&self) {
+        self.skipped_unpriced_quote.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_skipped_invalid_price(&self) {
+        self.skipped_invalid_price.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_skipped_quote_pair_swaps(&self) {
+        self.skipped_quote_pair_swaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_rejected_swaps(&self) {
+        self.rejected_swaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_timed_out_swaps(&self) {
+        self.timed_out_swaps.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn increment_db_insert_success(&self) {
         self.db_insert_success.fetch_add(1, Ordering::Relaxed);
     }
@@ -83,20 +192,153 @@ impl NodeMetrics {
         self.kv_insert_failure.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn increment_synthesized_native_transfers(&self) {
+        self.synthesized_native_transfers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_duplicate_instructions(&self, count: u64) {
+        self.duplicate_instructions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn increment_instruction_depth_exceeded(&self) {
+        self.instruction_depth_exceeded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_tagged_wash_swaps(&self) {
+        self.tagged_wash_swaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a swap the vaults disagree on, returns true if it should be logged, at most once
+    /// per interval so a misread venue doesn't flood the logs
+    pub fn record_direction_disagreement(&self, now_ms: i64) -> bool {
+        self.direction_disagreements.fetch_add(1, Ordering::Relaxed);
+        let last = self.direction_disagreement_logged_ms.load(Ordering::Relaxed);
+        if now_ms - last < DIRECTION_DISAGREEMENT_LOG_INTERVAL_MS {
+            return false;
+        }
+        self.direction_disagreement_logged_ms
+            .compare_exchange(last, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn increment_token_cache_hits(&self) {
+        self.token_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_token_cache_misses(&self) {
+        self.token_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_token_cache_size(&self, entries: usize, bytes: usize) {
+        self.token_cache_entries.store(entries as u64, Ordering::Relaxed);
+        self.token_cache_bytes.store(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Record the slot of a processed transaction, transactions may complete out of order
+    pub fn record_processed_slot(&self, slot: u64) {
+        self.last_processed_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    pub fn last_processed_slot(&self) -> u64 {
+        self.last_processed_slot.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slot_lag(&self, lag: u64) {
+        self.slot_lag.store(lag, Ordering::Relaxed);
+    }
+
+    /// Record how long a stage of a swap took
+    pub fn record_stage_duration(&self, stage: SwapStage, duration: Duration) {
+        let timing = self.stage_timing(stage);
+        timing.count.fetch_add(1, Ordering::Relaxed);
+        timing.total_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stage_timing(&self, stage: SwapStage) -> &StageTiming {
+        &self.stage_timings[stage as usize]
+    }
+
+    /// counters returns the name and the value of the monotonic counters
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("swaps_processed", &self.total_swaps_processed),
+            ("succeed_swaps", &self.succeed_swaps),
+            ("failed_swaps", &self.failed_swaps),
+            ("skipped_tiny_swaps", &self.skipped_tiny_swaps),
+            ("skipped_tiny_usd_swaps", &self.skipped_tiny_usd_swaps),
+            ("skipped_zero_swaps", &self.skipped_zero_swaps),
+            ("skipped_no_metadata", &self.skipped_no_metadata),
+            ("skipped_unexpected_swaps", &self.skipped_unexpected_swaps),
+            ("skipped_unknown_swaps", &self.skipped_unknown_swaps),
+            ("skipped_unpriced_quote", &self.skipped_unpriced_quote),
+            ("skipped_invalid_price", &self.skipped_invalid_price),
+            ("skipped_quote_pair_swaps", &self.skipped_quote_pair_swaps),
+            ("rejected_swaps", &self.rejected_swaps),
+            ("timed_out_swaps", &self.timed_out_swaps),
+            ("message_send_success", &self.message_send_success),
+            ("message_send_failure", &self.message_send_failure),
+            ("db_insert_success", &self.db_insert_success),
+            ("db_insert_failure", &self.db_insert_failure),
+            ("kv_insert_success", &self.kv_insert_success),
+            ("kv_insert_failure", &self.kv_insert_failure),
+            ("synthesized_native_transfers", &self.synthesized_native_transfers),
+            ("duplicate_instructions", &self.duplicate_instructions),
+            ("instruction_depth_exceeded", &self.instruction_depth_exceeded),
+            ("tagged_wash_swaps", &self.tagged_wash_swaps),
+            ("direction_disagreements", &self.direction_disagreements),
+            ("token_cache_hits", &self.token_cache_hits),
+            ("token_cache_misses", &self.token_cache_misses),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name, counter.load(Ordering::Relaxed)))
+        .collect()
+    }
+
+    /// Flag the storage as failed, returns true if it wasn't flagged yet
+    pub fn mark_storage_fatal(&self) -> bool {
+        !self.storage_fatal.swap(true, Ordering::Relaxed)
+    }
+
+    /// Returns false once the storage is flagged as failed
+    pub fn is_healthy(&self) -> bool {
+        !self.storage_fatal.load(Ordering::Relaxed)
+    }
+
     fn log_metrics(&self) {
         let total = self.total_swaps_processed.load(Ordering::Relaxed);
         let succeed = self.succeed_swaps.load(Ordering::Relaxed);
         let failed = self.failed_swaps.load(Ordering::Relaxed);
         let tiny = self.skipped_tiny_swaps.load(Ordering::Relaxed);
+        let tiny_usd = self.skipped_tiny_usd_swaps.load(Ordering::Relaxed);
         let zero = self.skipped_zero_swaps.load(Ordering::Relaxed);
         let unexpected = self.skipped_unexpected_swaps.load(Ordering::Relaxed);
         let unknown = self.skipped_unknown_swaps.load(Ordering::Relaxed);
+        let unpriced_quote = self.skipped_unpriced_quote.load(Ordering::Relaxed);
+        let invalid_price = self.skipped_invalid_price.load(Ordering::Relaxed);
+        let quote_pair = self.skipped_quote_pair_swaps.load(Ordering::Relaxed);
+        let rejected = self.rejected_swaps.load(Ordering::Relaxed);
+        let timed_out = self.timed_out_swaps.load(Ordering::Relaxed);
         let message_send_success = self.message_send_success.load(Ordering::Relaxed);
         let message_send_failure = self.message_send_failure.load(Ordering::Relaxed);
         let db_insert_success = self.db_insert_success.load(Ordering::Relaxed);
         let db_insert_failure = self.db_insert_failure.load(Ordering::Relaxed);
         let kv_insert_success = self.kv_insert_success.load(Ordering::Relaxed);
         let kv_insert_failure = self.kv_insert_failure.load(Ordering::Relaxed);
+        let synthesized_native_transfers =
+            self.synthesized_native_transfers.load(Ordering::Relaxed);
+        let duplicate_instructions = self.duplicate_instructions.load(Ordering::Relaxed);
+        let instruction_depth_exceeded = self.instruction_depth_exceeded.load(Ordering::Relaxed);
+        let tagged_wash_swaps = self.tagged_wash_swaps.load(Ordering::Relaxed);
+        let direction_disagreements = self.direction_disagreements.load(Ordering::Relaxed);
+        let token_cache_hits = self.token_cache_hits.load(Ordering::Relaxed);
+        let token_cache_misses = self.token_cache_misses.load(Ordering::Relaxed);
+        let token_cache_entries = self.token_cache_entries.load(Ordering::Relaxed);
+        let token_cache_bytes = self.token_cache_bytes.load(Ordering::Relaxed);
+        let last_processed_slot = self.last_processed_slot.load(Ordering::Relaxed);
+        let slot_lag = self.slot_lag.load(Ordering::Relaxed);
+        let pipeline = self.pipeline.stats();
+
+        let stage_average_micros = |stage| self.stage_timing(stage).average_micros();
 
         let success_rate = if total > 0 { (succeed as f64 / total as f64) * 100.0 } else { 0.0 };
 
@@ -106,15 +348,44 @@ impl NodeMetrics {
             success_rate = format!("{:.1}%", success_rate),
             failed = failed,
             skipped_tiny_swaps = tiny,
+            skipped_tiny_usd_swaps = tiny_usd,
             skipped_zero_swaps = zero,
             skipped_unexpected_swaps = unexpected,
             skipped_unknown_swaps = unknown,
+            skipped_unpriced_quote = unpriced_quote,
+            skipped_invalid_price = invalid_price,
+            skipped_quote_pair_swaps = quote_pair,
+            rejected_swaps = rejected,
+            timed_out_swaps = timed_out,
             message_send_success = message_send_success,
             message_send_failure = message_send_failure,
             db_insert_success = db_insert_success,
             db_insert_failure = db_insert_failure,
             kv_insert_success = kv_insert_success,
             kv_insert_failure = kv_insert_failure,
+            synthesized_native_transfers = synthesized_native_transfers,
+            duplicate_instructions = duplicate_instructions,
+            instruction_depth_exceeded = instruction_depth_exceeded,
+            tagged_wash_swaps = tagged_wash_swaps,
+            direction_disagreements = direction_disagreements,
+            token_cache_hits = token_cache_hits,
+            token_cache_misses = token_cache_misses,
+            token_cache_entries = token_cache_entries,
+            token_cache_bytes = token_cache_bytes,
+            last_processed_slot = last_processed_slot,
+            slot_lag = slot_lag,
+            pipeline_received = pipeline.received,
+            pipeline_processed = pipeline.processed,
+            pipeline_failed = pipeline.failed,
+            pipeline_queued = pipeline.queued,
+            pipeline_max_queued = pipeline.max_queued,
+            pipeline_process_avg_ms = pipeline.average_process_ms,
+            healthy = self.is_healthy(),
+            transfer_extraction_avg_us = stage_average_micros(SwapStage::TransferExtraction),
+            metadata_lookup_avg_us = stage_average_micros(SwapStage::MetadataLookup),
+            db_insert_avg_us = stage_average_micros(SwapStage::DbInsert),
+            mq_publish_avg_us = stage_average_micros(SwapStage::MqPublish),
+            kv_insert_avg_us = stage_average_micros(SwapStage::KvInsert),
             "swap_metrics"
         );
     }
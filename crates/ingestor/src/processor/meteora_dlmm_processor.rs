@@ -1,10 +1,13 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{Dexes, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_meteora_dlmm_decoder::instructions::{
     swap::{Swap, SwapInstructionAccounts},
@@ -41,16 +44,30 @@ impl From<SwapInstructionAccounts> for TokenSwapAccounts {
             accounts.reserve_x.to_string(), // Reserve X
             accounts.reserve_y.to_string(), // Reserve Y
         ]);
+        // the host fee is transferred from the user token in account on top of the swap
+        let fee_adas = HashSet::from([accounts.host_fee_in.to_string()]);
         TokenSwapAccounts {
             pair,
             user_adas,
             vault_adas: vaults_adas,
-            fee_adas: None,
+            fee_adas: Some(fee_adas),
             quote_mints: get_meteora_dlmm_quote_mints(),
         }
     }
 }
 
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<MeteoraDlmmInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        MeteoraDlmmInstruction::Swap(_) => {
+            Swap::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct MeteoraDlmmInstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -71,16 +88,13 @@ impl Processor for MeteoraDlmmInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        if let MeteoraDlmmInstruction::Swap(_) = &instruction.data {
-            let accounts = Swap::arrange_accounts(&instruction.accounts);
-            if let Some(accounts) = accounts {
-                let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                self.swap_handler.spawn_swap_instruction(
-                    &token_swap_accounts,
-                    &meta,
-                    &nested_instructions,
-                );
-            }
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::MeteoraDlmm,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
         }
         Ok(())
     }
@@ -91,9 +105,9 @@ mod meteora_dlmm_tests {
     use super::*;
     use crate::{
         handler::token_swap_handler::filter_swap_transfers,
+        metrics::SwapStage,
         test_swaps::{
-            get_inner_token_transfers, get_nested_instruction, get_token_swap_handler,
-            TokenTransferDetails,
+            get_inner_token_transfers, get_nested_instruction, MemoryStorages, TokenTransferDetails,
         },
     };
     use carbon_core::{
@@ -102,6 +116,10 @@ mod meteora_dlmm_tests {
         transaction::TransactionMetadata,
     };
     use carbon_meteora_dlmm_decoder::MeteoraDlmmDecoder;
+    use std::{
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    };
 
     async fn test_with_dlmm_decoder(
         tx_hash: &str,
@@ -122,6 +140,36 @@ mod meteora_dlmm_tests {
         (nested_instruction, instruction, transaction_update, transaction_metadata)
     }
 
+    #[test]
+    fn test_host_fee_transfer_is_excluded() {
+        let transfer =
+            |source: &str, destination: &str, mint: &str, ui_amount: f64| TokenTransferDetails {
+                amount: 0,
+                ui_amount,
+                decimals: 9,
+                program_id: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+                authority: "user".to_string(),
+                destination: destination.to_string(),
+                mint: mint.to_string(),
+                source: source.to_string(),
+            };
+        let token_swap_accounts = TokenSwapAccounts {
+            pair: "lb_pair".to_string(),
+            user_adas: HashSet::from(["user_token_in".to_string(), "user_token_out".to_string()]),
+            vault_adas: HashSet::from(["reserve_x".to_string(), "reserve_y".to_string()]),
+            fee_adas: Some(HashSet::from(["host_fee_in".to_string()])),
+            quote_mints: get_meteora_dlmm_quote_mints(),
+        };
+        let transfers = vec![
+            transfer("user_token_in", "reserve_y", WSOL_MINT_KEY_STR, 1.0),
+            transfer("user_token_in", "host_fee_in", WSOL_MINT_KEY_STR, 0.001),
+            transfer("reserve_x", "user_token_out", "token", 1000.0),
+        ];
+        let filtered = filter_swap_transfers(&transfers, &token_swap_accounts);
+        assert_eq!(filtered.len(), 2);
+        assert!(filtered.iter().all(|t| t.destination != "host_fee_in"));
+    }
+
     /// https://solscan.io/tx/3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn
     /// #3.6 - Meteora DLMM Program: swap
     #[tokio::test]
@@ -132,7 +180,8 @@ mod meteora_dlmm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_dlmm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -173,6 +222,48 @@ mod meteora_dlmm_tests {
             }
         );
 
+        storages
+            .seed_token("9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump", 6, 1_000_000_000.0)
+            .await;
+
+        let mut processor = MeteoraDlmmInstructionProcessor::new(token_swap_handler.clone());
+        processor
+            .process(
+                (
+                    nested_instruction.metadata.clone(),
+                    instruction,
+                    nested_instruction.inner_instructions.clone(),
+                    nested_instruction.instruction.clone(),
+                ),
+                Arc::new(MetricsCollection::new(vec![])),
+            )
+            .await
+            .expect("Failed to process instruction");
+
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(swap_events.len(), 1);
+        assert_eq!(swap_events[0].signature, signature);
+        assert_eq!(swap_events[0].pubkey, "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump");
+        assert_eq!(swap_events[0].base_amount, 24000.0);
+        assert_eq!(swap_events[0].quote_amount, 65.256388526);
+    }
+
+    #[tokio::test]
+    async fn test_swap_stage_timings() {
+        let signature = "3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn";
+        let (nested_instruction, instruction, _, _) =
+            test_with_dlmm_decoder(signature, 2, Some(3)).await;
+        let instruction = instruction.expect("Instruction is not some");
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
+        storages
+            .seed_token("9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump", 6, 1_000_000_000.0)
+            .await;
+
+        let metrics = token_swap_handler.metrics.clone();
+        let count = |stage| metrics.stage_timing(stage).count.load(Ordering::Relaxed);
+        assert!(SwapStage::ALL.into_iter().all(|stage| count(stage) == 0));
+
         let mut processor = MeteoraDlmmInstructionProcessor::new(token_swap_handler.clone());
         processor
             .process(
@@ -186,6 +277,104 @@ mod meteora_dlmm_tests {
             )
             .await
             .expect("Failed to process instruction");
+
+        // the swap is processed in a spawned task, the kv insert is its last stage
+        let started_at = Instant::now();
+        while count(SwapStage::KvInsert) == 0 && started_at.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for stage in SwapStage::ALL {
+            assert_eq!(count(stage), 1, "{stage:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_logs_instead_of_writing() {
+        let signature = "3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn";
+        let (nested_instruction, instruction, _, _) =
+            test_with_dlmm_decoder(signature, 2, Some(3)).await;
+        let instruction = instruction.expect("Instruction is not some");
+        let storages = MemoryStorages::dry_run();
+        let token_swap_handler = storages.token_swap_handler().await;
+        storages
+            .seed_token("9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump", 6, 1_000_000_000.0)
+            .await;
+
+        let mut processor = MeteoraDlmmInstructionProcessor::new(token_swap_handler.clone());
+        processor
+            .process(
+                (
+                    nested_instruction.metadata.clone(),
+                    instruction,
+                    nested_instruction.inner_instructions.clone(),
+                    nested_instruction.instruction.clone(),
+                ),
+                Arc::new(MetricsCollection::new(vec![])),
+            )
+            .await
+            .expect("Failed to process instruction");
+
+        // the swap is processed in a spawned task
+        let metrics = token_swap_handler.metrics.clone();
+        let started_at = Instant::now();
+        while metrics.succeed_swaps.load(Ordering::Relaxed) == 0
+            && started_at.elapsed() < Duration::from_secs(5)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // the metrics count the skipped writes, to compare the throughput with the live runs
+        assert_eq!(metrics.succeed_swaps.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.db_insert_success.load(Ordering::Relaxed), 1);
+        assert_eq!(storages.dry_run.writes("swap_event"), 1);
+        assert_eq!((storages.dry_run.writes("pair"), storages.dry_run.writes("trade")), (1, 1));
+        assert!(storages.db.swap_events().is_empty());
+        assert!(storages.db.pairs().is_empty());
+        assert!(storages.trades().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_first_swap_records_pair() {
+        let signature = "3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn";
+        let (nested_instruction, instruction, _, _) =
+            test_with_dlmm_decoder(signature, 2, Some(3)).await;
+        let instruction = instruction.expect("Instruction is not some");
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
+        storages
+            .seed_token("9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump", 6, 1_000_000_000.0)
+            .await;
+
+        let metrics = token_swap_handler.metrics.clone();
+        let mut processor = MeteoraDlmmInstructionProcessor::new(token_swap_handler.clone());
+        for processed in 1..=2 {
+            processor
+                .process(
+                    (
+                        nested_instruction.metadata.clone(),
+                        instruction.clone(),
+                        nested_instruction.inner_instructions.clone(),
+                        nested_instruction.instruction.clone(),
+                    ),
+                    Arc::new(MetricsCollection::new(vec![])),
+                )
+                .await
+                .expect("Failed to process instruction");
+
+            let started_at = Instant::now();
+            while metrics.stage_timing(SwapStage::KvInsert).count.load(Ordering::Relaxed)
+                < processed
+                && started_at.elapsed() < Duration::from_secs(5)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            // the second swap of the pair doesn't record it again
+            let pairs = storages.db.pairs();
+            assert_eq!(pairs.len(), 1);
+            assert_eq!(pairs[0].base_mint, "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump");
+            assert_eq!(pairs[0].quote_mint, "So11111111111111111111111111111111111111112");
+            assert_eq!(pairs[0].dex, Dexes::MeteoraDlmm);
+        }
     }
 
     /// https://solscan.io/tx/5izpNhE1yRru7WuBMV7nWz4DrXvobUWvWuAcTAPgL1tBComtbqQrF6oSbTnhuyv2k4SPX9xNXEpDnunD2eSM1yen
@@ -199,7 +388,8 @@ mod meteora_dlmm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_dlmm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -239,6 +429,10 @@ mod meteora_dlmm_tests {
             }
         );
 
+        storages
+            .seed_token("6p6xgHyF7AeE6TZkSmFsko444wqoP15icUSqi2jfGiPN", 6, 1_000_000_000.0)
+            .await;
+
         let mut processor = MeteoraDlmmInstructionProcessor::new(token_swap_handler.clone());
         processor
             .process(
@@ -252,7 +446,14 @@ mod meteora_dlmm_tests {
             )
             .await
             .expect("Failed to process instruction");
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(swap_events.len(), 1);
+        let swap_event = &swap_events[0];
+        assert_eq!(swap_event.pubkey, "6p6xgHyF7AeE6TZkSmFsko444wqoP15icUSqi2jfGiPN");
+        assert!(swap_event.is_buy);
+        assert_eq!(swap_event.swap_amount, 200.0);
+        assert_eq!(swap_event.price, 200.0 / 18.143267);
+        assert_eq!(storages.trades()[0].signature, signature);
     }
 
     /// https://solscan.io/tx/3iJi5GiGSbhFyu7c7B2MyQU43xtR5krM7bD8pzoL6hJLVRgoqQsKaBXsUDTrvnWrFYsKeZBdDabRVo1d8X2x95YY
@@ -266,7 +467,8 @@ mod meteora_dlmm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_dlmm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -305,6 +507,8 @@ mod meteora_dlmm_tests {
             }
         );
 
+        storages.seed_token(WSOL_MINT_KEY_STR, 9, 0.0).await;
+
         let mut processor = MeteoraDlmmInstructionProcessor::new(token_swap_handler.clone());
         processor
             .process(
@@ -318,6 +522,10 @@ mod meteora_dlmm_tests {
             )
             .await
             .expect("Failed to process instruction");
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        // the WSOL/USDC swap is priced in USDC
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(swap_events.len(), 1);
+        assert_eq!(swap_events[0].pubkey, WSOL_MINT_KEY_STR);
+        assert_eq!(swap_events[0].swap_amount, 1.949327);
     }
 }
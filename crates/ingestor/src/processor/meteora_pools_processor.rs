@@ -1,10 +1,13 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{Dexes, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_meteora_pools_decoder::instructions::{
     swap::{Swap, SwapInstructionAccounts},
@@ -52,6 +55,18 @@ impl From<SwapInstructionAccounts> for TokenSwapAccounts {
     }
 }
 
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<MeteoraPoolsProgramInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        MeteoraPoolsProgramInstruction::Swap(_) => {
+            Swap::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct MeteoraPoolsInstructionProcessor {
     pub swap_handler: Arc<TokenSwapHandler>,
 }
@@ -72,16 +87,13 @@ impl Processor for MeteoraPoolsInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        if let MeteoraPoolsProgramInstruction::Swap(_) = &instruction.data {
-            let accounts = Swap::arrange_accounts(&instruction.accounts);
-            if let Some(accounts) = accounts {
-                let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                self.swap_handler.spawn_swap_instruction(
-                    &token_swap_accounts,
-                    &meta,
-                    &nested_instructions,
-                );
-            }
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::MeteoraPools,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
         }
         Ok(())
     }
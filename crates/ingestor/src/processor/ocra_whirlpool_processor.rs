@@ -1,10 +1,13 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{Dexes, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_orca_whirlpool_decoder::instructions::{
     swap::{Swap, SwapInstructionAccounts},
@@ -69,6 +72,21 @@ impl From<SwapV2InstructionAccounts> for TokenSwapAccounts {
     }
 }
 
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<OrcaWhirlpoolInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        OrcaWhirlpoolInstruction::Swap(_) => {
+            Swap::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        OrcaWhirlpoolInstruction::SwapV2(_) => {
+            SwapV2::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct OcraWhirlpoolInstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -89,32 +107,14 @@ impl Processor for OcraWhirlpoolInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        match &instruction.data {
-            OrcaWhirlpoolInstruction::Swap(_) => {
-                let accounts = Swap::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            OrcaWhirlpoolInstruction::SwapV2(_) => {
-                let accounts = SwapV2::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            _ => {}
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::OcraWhirlpool,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
         }
-
         Ok(())
     }
 }
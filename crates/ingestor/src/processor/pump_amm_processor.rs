@@ -1,16 +1,25 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{
+        Dexes, PUMP_MIGRATION_AUTHORITY, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR,
+    },
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
+    transaction::TransactionMetadata,
 };
 use carbon_pump_swap_decoder::instructions::{
     buy::{Buy, BuyInstructionAccounts},
+    create_pool::{CreatePool, CreatePoolInstructionAccounts},
     sell::{Sell, SellInstructionAccounts},
     PumpSwapInstruction,
 };
+use chrono::Utc;
+use sonar_db::models::TokenGraduatedEvent;
 use std::{collections::HashSet, sync::Arc, sync::LazyLock};
 
 /// A set of quote mints supported by Pump.fun AMM
@@ -83,6 +92,53 @@ impl From<SellInstructionAccounts> for TokenSwapAccounts {
     }
 }
 
+/// is_pump_migration returns true if the pump.fun migration authority signed the transaction
+pub fn is_pump_migration(transaction_metadata: &TransactionMetadata) -> bool {
+    let signers = transaction_metadata.message.header().num_required_signatures as usize;
+    transaction_metadata
+        .message
+        .static_account_keys()
+        .iter()
+        .take(signers)
+        .any(|key| *key == PUMP_MIGRATION_AUTHORITY)
+}
+
+/// get_graduation_event returns the graduation of a pump.fun token when the pool is the
+/// migration of its bonding curve, signed by the migration authority and quoted in WSOL,
+/// anyone can create a pump amm pool for a pump.fun token
+pub fn get_graduation_event(
+    accounts: &CreatePoolInstructionAccounts,
+    transaction_metadata: &TransactionMetadata,
+) -> Option<TokenGraduatedEvent> {
+    if !is_pump_migration(transaction_metadata)
+        || accounts.quote_mint.to_string() != WSOL_MINT_KEY_STR
+    {
+        return None;
+    }
+    let timestamp = transaction_metadata.block_time.unwrap_or(Utc::now().timestamp()) as u64;
+    Some(TokenGraduatedEvent {
+        mint: accounts.base_mint.to_string(),
+        pool: accounts.pool.to_string(),
+        dex: Dexes::PumpAmm,
+        timestamp,
+    })
+}
+
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<PumpSwapInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        PumpSwapInstruction::Buy(_) => {
+            Buy::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        PumpSwapInstruction::Sell(_) => {
+            Sell::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct PumpAmmInstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -103,26 +159,20 @@ impl Processor for PumpAmmInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        if let PumpSwapInstruction::Buy(_) = &instruction.data {
-            let accounts = Buy::arrange_accounts(&instruction.accounts);
-            if let Some(accounts) = accounts {
-                let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                self.swap_handler.spawn_swap_instruction(
-                    &token_swap_accounts,
-                    &meta,
-                    &nested_instructions,
-                );
-            }
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::PumpAmm,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
         }
-        if let PumpSwapInstruction::Sell(_) = &instruction.data {
-            let accounts = Sell::arrange_accounts(&instruction.accounts);
-            if let Some(accounts) = accounts {
-                let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                self.swap_handler.spawn_swap_instruction(
-                    &token_swap_accounts,
-                    &meta,
-                    &nested_instructions,
-                );
+        if let PumpSwapInstruction::CreatePool(_) = &instruction.data {
+            let accounts = CreatePool::arrange_accounts(&instruction.accounts);
+            if let Some(event) = accounts
+                .and_then(|accounts| get_graduation_event(&accounts, &meta.transaction_metadata))
+            {
+                self.swap_handler.spawn_graduation_instruction(event);
             }
         }
         Ok(())
@@ -133,11 +183,13 @@ impl Processor for PumpAmmInstructionProcessor {
 mod pump_amm_tests {
     use super::*;
     use crate::{
-        handler::token_swap_handler::filter_swap_transfers,
+        handler::token_swap_handler::{
+            build_swap_event, filter_swap_transfers, get_base_quote_mint, split_swap_transfers,
+        },
         processor::PumpAmmInstructionProcessor,
         test_swaps::{
-            get_inner_token_transfers, get_nested_instruction, get_token_swap_handler,
-            TokenTransferDetails,
+            get_inner_token_transfers, get_nested_instruction, MemoryStorages,
+            TokenTransferDetails, TEST_SOL_PRICE,
         },
     };
     use carbon_core::{
@@ -176,7 +228,8 @@ mod pump_amm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_amm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -242,7 +295,8 @@ mod pump_amm_tests {
             )
             .await
             .expect("Failed to process instruction");
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        // the accounts of the old pump swap amm are not arranged, so no swap is spawned
+        assert!(storages.db.swap_events().is_empty());
     }
 
     /// https://solscan.io/tx/54cT6UzXbKHn8QuzncXUF81zYM5bq3tWPq4iQvSXorKKB1XUBJaZu7B4dx9dLxThCExMEDK4jAAQDcx9W9FFNCzp
@@ -255,7 +309,8 @@ mod pump_amm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_amm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -324,9 +379,36 @@ mod pump_amm_tests {
             fee_adas: Some(fee_adas),
             quote_mints: get_pump_amm_quote_mints(),
         };
+        let (swap_transfers, fee_transfers) =
+            split_swap_transfers(&transfers, &token_swap_accounts);
+        assert_eq!(swap_transfers.len(), 2);
+        // the protocol fee of 0.000250001 WSOL
+        assert_eq!(fee_transfers, vec![transfers[2].clone()]);
         let transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
         assert_eq!(transfers.len(), 2);
 
+        // the swap parsed from the accounts of the old amm
+        let (is_buy, base, quote) = get_base_quote_mint(&token_swap_accounts, &transfers)
+            .expect("Failed to get base and quote");
+        let swap_event = build_swap_event(
+            &token_swap_accounts.pair,
+            &token_swap_accounts.user_adas,
+            is_buy,
+            base,
+            quote,
+            TEST_SOL_PRICE,
+            &transaction_metadata,
+        )
+        .expect("Failed to build swap event");
+        assert_eq!(swap_event.signature, signature);
+        assert_eq!(swap_event.pubkey, "2Y6GkQJR93PNL1iYwGcjggoaBRaeTM1p9pC7oCzTpump");
+        assert!(swap_event.is_buy);
+        assert_eq!(swap_event.base_amount, 540059.097867);
+        assert_eq!(swap_event.quote_amount, 0.501000002);
+        assert_eq!(swap_event.swap_amount, 0.501000002 * TEST_SOL_PRICE);
+
+        let accounts = Buy::arrange_accounts(&instruction.accounts);
+        assert!(accounts.is_none());
         let mut processor = PumpAmmInstructionProcessor::new(token_swap_handler.clone());
         processor
             .process(
@@ -340,7 +422,8 @@ mod pump_amm_tests {
             )
             .await
             .expect("Failed to process instruction");
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        // the processor does not arrange the accounts of the old amm, so no swap is spawned
+        assert!(storages.db.swap_events().is_empty());
     }
 
     /// https://solscan.io/tx/4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7
@@ -353,7 +436,8 @@ mod pump_amm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_amm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -417,9 +501,14 @@ mod pump_amm_tests {
         let accounts = Sell::arrange_accounts(&instruction.accounts);
         let accounts = accounts.expect("Accounts are not some");
         let token_swap_accounts = TokenSwapAccounts::from(accounts);
+        let (_, fee_transfers) = split_swap_transfers(&transfers, &token_swap_accounts);
         let transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
         assert_eq!(transfers.len(), 2);
 
+        storages
+            .seed_token("7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump", 6, 1_000_000_000.0)
+            .await;
+
         let mut processor = PumpAmmInstructionProcessor::new(token_swap_handler.clone());
         processor
             .process(
@@ -433,7 +522,37 @@ mod pump_amm_tests {
             )
             .await
             .expect("Failed to process instruction");
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(swap_events.len(), 1);
+        let swap_event = &swap_events[0];
+        assert_eq!(swap_event.signature, signature);
+        assert_eq!(swap_event.pubkey, "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump");
+        assert!(!swap_event.is_buy);
+        assert!(swap_event.is_pump);
+        assert_eq!(swap_event.base_amount, 391682.524746);
+        assert_eq!(swap_event.quote_amount, 0.014472232);
+        assert_eq!(swap_event.swap_amount, 0.014472232 * TEST_SOL_PRICE);
+        assert_eq!(swap_event.market_cap, swap_event.price * 1_000_000_000.0);
+        // the protocol fee of 0.000007258 WSOL is paid into the protocol fee recipient,
+        // the creator fee goes to the coin creator vault and isn't a pool fee
+        assert_eq!(fee_transfers.len(), 1);
+        assert_eq!(fee_transfers[0].amount, 7258);
+        assert_eq!(fee_transfers[0].mint, WSOL_MINT_KEY_STR);
+        assert_eq!(swap_event.fee_amount, 0.000007258 * TEST_SOL_PRICE);
+        assert_eq!(swap_event.fee_mint, WSOL_MINT_KEY_STR);
+        assert_eq!(storages.trades().len(), 1);
+        assert_eq!(storages.trades()[0].fee_amount, 0.000007258 * TEST_SOL_PRICE);
+        assert_eq!(storages.trades()[0].fee_mint, WSOL_MINT_KEY_STR);
+        // the trade carries the decimals of the seeded token
+        assert_eq!(storages.trades()[0].decimals, 6);
+        // the first swap on the pump amm records the launchpad of the token
+        let token = storages
+            .kv_store
+            .get_token("7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump")
+            .await
+            .unwrap()
+            .expect("Token is not cached");
+        assert_eq!(token.launchpad, crate::constants::PUMP_LAUNCHPAD);
     }
 
     /// https://solscan.io/tx/4tZNsPeFvmEG5EYGNM5VL4MWJ5gAcAxBJwgymA66wfFFnoQv5huriCV4xveUSunoMdpLzVstGLpCQPG8iDBdAvmx
@@ -446,7 +565,8 @@ mod pump_amm_tests {
         let (nested_instruction, instruction, _, transaction_metadata) =
             test_with_amm_decoder(signature, outer_index, inner_index).await;
         let instruction = instruction.expect("Instruction is not some");
-        let token_swap_handler = get_token_swap_handler().await;
+        let storages = MemoryStorages::default();
+        let token_swap_handler = storages.token_swap_handler().await;
 
         let inner_instructions = nested_instruction.inner_instructions.clone();
         let transfers = get_inner_token_transfers(&transaction_metadata, &inner_instructions);
@@ -457,6 +577,9 @@ mod pump_amm_tests {
         let token_swap_accounts = TokenSwapAccounts::from(accounts);
         let transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
         assert_eq!(transfers.len(), 2);
+        let base =
+            transfers.iter().find(|t| t.mint != WSOL_MINT_KEY_STR).expect("No base transfer");
+        storages.seed_token(&base.mint, base.decimals, 1_000_000_000.0).await;
 
         let mut processor = PumpAmmInstructionProcessor::new(token_swap_handler.clone());
         processor
@@ -471,6 +594,46 @@ mod pump_amm_tests {
             )
             .await
             .expect("Failed to process instruction");
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(swap_events.len(), 1);
+        assert_eq!(swap_events[0].signature, signature);
+        assert_eq!(swap_events[0].pubkey, base.mint);
+        assert!(!swap_events[0].is_buy);
+    }
+
+    /// signed_by returns the metadata of a transaction signed by `signer` alone
+    fn signed_by(signer: solana_pubkey::Pubkey) -> TransactionMetadata {
+        use solana_message::{v0, MessageHeader, VersionedMessage};
+        use solana_pubkey::Pubkey;
+        use solana_signature::Signature;
+        use solana_transaction::versioned::VersionedTransaction;
+        use solana_transaction_status::TransactionStatusMeta;
+
+        let message = v0::Message {
+            header: MessageHeader { num_required_signatures: 1, ..Default::default() },
+            // the migration authority as a non signer account doesn't count
+            account_keys: vec![signer, PUMP_MIGRATION_AUTHORITY, Pubkey::new_unique()],
+            ..Default::default()
+        };
+        TransactionUpdate {
+            signature: Signature::default(),
+            transaction: VersionedTransaction {
+                signatures: vec![Signature::default()],
+                message: VersionedMessage::V0(message),
+            },
+            meta: TransactionStatusMeta::default(),
+            is_vote: false,
+            slot: 0,
+            block_time: Some(1_750_000_000),
+            block_hash: None,
+        }
+        .try_into()
+        .expect("Failed to convert transaction update")
+    }
+
+    #[test]
+    fn test_is_pump_migration() {
+        assert!(is_pump_migration(&signed_by(PUMP_MIGRATION_AUTHORITY)));
+        assert!(!is_pump_migration(&signed_by(solana_pubkey::Pubkey::new_unique())));
     }
 }
@@ -3,8 +3,11 @@ use crate::{
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_raydium_amm_v4_decoder::instructions::{
     initialize2, initialize2::Initialize2, swap_base_in, swap_base_in::SwapBaseIn, swap_base_out,
@@ -58,7 +61,7 @@ pub fn get_new_pool_event(
     timestamp: u64,
 ) -> NewPoolEvent {
     NewPoolEvent {
-        dex: Dexes::RaydiumAmmV4.to_string(),
+        dex: Dexes::RaydiumAmmV4,
         token_a_mint: accounts.coin_mint.to_string(),
         token_b_mint: accounts.pc_mint.to_string(),
         pool: accounts.amm.to_string(),
@@ -90,6 +93,21 @@ impl From<swap_base_out::SwapBaseOutInstructionAccounts> for TokenSwapAccounts {
     }
 }
 
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<RaydiumAmmV4Instruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        RaydiumAmmV4Instruction::SwapBaseIn(_) => {
+            SwapBaseIn::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        RaydiumAmmV4Instruction::SwapBaseOut(_) => {
+            SwapBaseOut::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct RaydiumAmmV4InstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -110,40 +128,22 @@ impl Processor for RaydiumAmmV4InstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        match &instruction.data {
-            RaydiumAmmV4Instruction::SwapBaseIn(_) => {
-                let accounts = SwapBaseIn::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            RaydiumAmmV4Instruction::SwapBaseOut(_) => {
-                let accounts = SwapBaseOut::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            RaydiumAmmV4Instruction::Initialize2(_) => {
-                let accounts = Initialize2::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let block_time =
-                        meta.transaction_metadata.block_time.unwrap_or(Utc::now().timestamp())
-                            as u64;
-                    let new_pool_event = get_new_pool_event(accounts, block_time);
-                    self.swap_handler.spawn_new_pool_instruction(&meta, new_pool_event);
-                }
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::RaydiumAmmV4,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
+        }
+        if let RaydiumAmmV4Instruction::Initialize2(_) = &instruction.data {
+            let accounts = Initialize2::arrange_accounts(&instruction.accounts);
+            if let Some(accounts) = accounts {
+                let block_time =
+                    meta.transaction_metadata.block_time.unwrap_or(Utc::now().timestamp()) as u64;
+                let new_pool_event = get_new_pool_event(accounts, block_time);
+                self.swap_handler.spawn_new_pool_instruction(&meta, new_pool_event);
             }
-            _ => {}
         }
         Ok(())
     }
@@ -1,10 +1,13 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{Dexes, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_raydium_clmm_decoder::instructions::{
     swap::{Swap, SwapInstructionAccounts},
@@ -68,6 +71,21 @@ impl From<SwapV2InstructionAccounts> for TokenSwapAccounts {
         }
     }
 }
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<RaydiumClmmInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        RaydiumClmmInstruction::Swap(_) => {
+            Swap::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        RaydiumClmmInstruction::SwapV2(_) => {
+            SwapV2::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct RaydiumClmmInstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -88,32 +106,14 @@ impl Processor for RaydiumClmmInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        match &instruction.data {
-            RaydiumClmmInstruction::Swap(_e) => {
-                let accounts = Swap::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            RaydiumClmmInstruction::SwapV2(_e) => {
-                let accounts = SwapV2::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            _ => {}
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::RaydiumClmm,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
         }
-
         Ok(())
     }
 }
@@ -125,7 +125,7 @@ mod clmm_tests {
         handler::token_swap_handler::filter_swap_transfers,
         test_swaps::{
             get_inner_token_transfers, get_nested_instruction, get_sol_price,
-            get_token_swap_handler, TokenTransferDetails,
+            get_token_swap_handler, MemorySolPriceCache, MemoryStorages, TokenTransferDetails,
         },
     };
     use carbon_core::{
@@ -135,6 +135,7 @@ mod clmm_tests {
     };
     use carbon_raydium_clmm_decoder::RaydiumClmmDecoder;
     use dotenvy::dotenv;
+    use sonar_sol_price::SolPriceCacheTrait;
 
     async fn test_with_clmm_decoder(
         tx_hash: &str,
@@ -235,6 +236,46 @@ mod clmm_tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(20)).await;
     }
 
+    /// https://solscan.io/tx/2mV1jrKN2QMDkKdkLdNNp7iLPpW7xUG21R7NYSTYrPG6hpfQ4KC34b3XMXjc19mA9RsvFzYU5ws25E7aD24EKaf1
+    /// Swap 170.557402 USDC for 1.192089224 WSOL on the SOL/USDC pool
+    #[tokio::test]
+    async fn test_onchain_sol_price() {
+        let tx_hash = "2mV1jrKN2QMDkKdkLdNNp7iLPpW7xUG21R7NYSTYrPG6hpfQ4KC34b3XMXjc19mA9RsvFzYU5ws25E7aD24EKaf1";
+        let (nested_instruction, instruction, _, _) =
+            test_with_clmm_decoder(tx_hash, 2, None).await;
+        let instruction = instruction.expect("Instruction is not some");
+        let storages = MemoryStorages::default();
+        storages.seed_token(WSOL_MINT_KEY_STR, 9, 1_000_000_000.0).await;
+        let sol_price_cache = Arc::new(MemorySolPriceCache::new(100.0));
+        let token_swap_handler = (*storages.token_swap_handler().await)
+            .clone()
+            .with_sol_price_cache(sol_price_cache.clone());
+
+        let mut processor = RaydiumClmmInstructionProcessor::new(Arc::new(token_swap_handler));
+        processor
+            .process(
+                (
+                    nested_instruction.metadata.clone(),
+                    instruction,
+                    nested_instruction.inner_instructions.clone(),
+                    nested_instruction.instruction.clone(),
+                ),
+                Arc::new(MetricsCollection::new(vec![])),
+            )
+            .await
+            .expect("Failed to process instruction");
+
+        // the swap is priced from its own amounts rather than the cached SOL price
+        let swap_events = storages.wait_for_swap_events(1).await;
+        assert_eq!(swap_events.len(), 1);
+        assert_eq!(swap_events[0].pubkey, WSOL_MINT_KEY_STR);
+        assert_eq!(swap_events[0].base_amount, 1.192089224);
+        assert_eq!(swap_events[0].quote_amount, 170.557402);
+        let sol_price = 170.557402 / 1.192089224;
+        assert!((swap_events[0].price - sol_price).abs() < 1e-9);
+        assert!((sol_price_cache.get_price().await - sol_price).abs() < 1e-9);
+    }
+
     #[tokio::test]
     async fn test_swap_v2_processor() {
         let tx_hash = "65coymtGUzFFxZFvcnaoAxD4MCo6RtkNA5hoZje2az93De5sfnmQP7j5t7AC84H3jFXsBzQHM7kjnZMZVtKfNEjF";
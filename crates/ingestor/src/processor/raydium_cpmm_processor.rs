@@ -1,16 +1,22 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{Dexes, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_raydium_cpmm_decoder::instructions::{
+    initialize::{Initialize, InitializeInstructionAccounts},
     swap_base_input::{SwapBaseInput, SwapBaseInputInstructionAccounts},
     swap_base_output::{SwapBaseOutput, SwapBaseOutputInstructionAccounts},
     RaydiumCpmmInstruction,
 };
+use chrono::Utc;
+use sonar_db::models::NewPoolEvent;
 use std::{collections::HashSet, sync::Arc, sync::LazyLock};
 
 /// A set of quote mints supported by Raydium CPMM
@@ -31,6 +37,16 @@ pub fn get_raydium_cpmm_quote_mints() -> Arc<HashSet<String>> {
     Arc::new(RAYDIUM_CPMM_QUOTE_MINTS.clone())
 }
 
+pub fn get_new_pool_event(accounts: InitializeInstructionAccounts, timestamp: u64) -> NewPoolEvent {
+    NewPoolEvent {
+        dex: Dexes::RaydiumCpmm,
+        token_a_mint: accounts.token_0_mint.to_string(),
+        token_b_mint: accounts.token_1_mint.to_string(),
+        pool: accounts.pool_state.to_string(),
+        timestamp,
+    }
+}
+
 impl From<SwapBaseInputInstructionAccounts> for TokenSwapAccounts {
     fn from(accounts: SwapBaseInputInstructionAccounts) -> Self {
         let pair = accounts.pool_state.to_string();
@@ -38,10 +54,8 @@ impl From<SwapBaseInputInstructionAccounts> for TokenSwapAccounts {
             accounts.input_token_account.to_string(),
             accounts.output_token_account.to_string(),
         ]);
-        let vault_adas = HashSet::from([
-            accounts.input_token_account.to_string(),
-            accounts.output_token_account.to_string(),
-        ]);
+        let vault_adas =
+            HashSet::from([accounts.input_vault.to_string(), accounts.output_vault.to_string()]);
         TokenSwapAccounts {
             pair,
             user_adas,
@@ -71,6 +85,21 @@ impl From<SwapBaseOutputInstructionAccounts> for TokenSwapAccounts {
     }
 }
 
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<RaydiumCpmmInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        RaydiumCpmmInstruction::SwapBaseInput(_) => {
+            SwapBaseInput::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        RaydiumCpmmInstruction::SwapBaseOutput(_) => {
+            SwapBaseOutput::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct RaydiumCpmmInstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -91,30 +120,22 @@ impl Processor for RaydiumCpmmInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        match &instruction.data {
-            RaydiumCpmmInstruction::SwapBaseInput(_) => {
-                let accounts = SwapBaseInput::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            RaydiumCpmmInstruction::SwapBaseOutput(_) => {
-                let accounts = SwapBaseOutput::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::RaydiumCpmm,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
+        }
+        if let RaydiumCpmmInstruction::Initialize(_) = &instruction.data {
+            let accounts = Initialize::arrange_accounts(&instruction.accounts);
+            if let Some(accounts) = accounts {
+                let block_time =
+                    meta.transaction_metadata.block_time.unwrap_or(Utc::now().timestamp()) as u64;
+                let new_pool_event = get_new_pool_event(accounts, block_time);
+                self.swap_handler.spawn_new_pool_instruction(&meta, new_pool_event);
             }
-            _ => {}
         }
 
         Ok(())
@@ -125,7 +146,7 @@ impl Processor for RaydiumCpmmInstructionProcessor {
 mod cpmm_tests {
     use super::*;
     use crate::{
-        handler::token_swap_handler::filter_swap_transfers,
+        handler::token_swap_handler::{filter_swap_transfers, get_base_quote_mint},
         test_swaps::{
             get_inner_token_transfers, get_nested_instruction, get_token_swap_handler,
             TokenTransferDetails,
@@ -204,8 +225,22 @@ mod cpmm_tests {
         let accounts = SwapBaseInput::arrange_accounts(&instruction.accounts);
         let accounts = accounts.expect("Accounts are not some");
         let token_swap_accounts = TokenSwapAccounts::from(accounts);
-        let transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
-        assert_eq!(transfers.len(), 2);
+        assert_eq!(
+            token_swap_accounts.vault_adas,
+            HashSet::from([
+                "DvPZP2ZXpAP1CCoJk4LmTet97YWJ8nkjNSSFyo4dzAvF".to_string(),
+                "HxT2zqXpWcoWbB5KxkDNydm659Ndxn5mvkza1C3js2tu".to_string(),
+            ])
+        );
+        let filtered_transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
+        assert_eq!(filtered_transfers, transfers);
+
+        // WSOL is paid into the pool for the token, a buy
+        let (is_buy, base, quote) =
+            get_base_quote_mint(&token_swap_accounts, &filtered_transfers).unwrap();
+        assert!(is_buy);
+        assert_eq!(base.mint, "866Sh46xjH7cW7aW18tBUmGm3xh6EzGTk1Li7YbbmqJr");
+        assert_eq!(quote.mint, "So11111111111111111111111111111111111111112");
 
         let mut processor = RaydiumCpmmInstructionProcessor::new(token_swap_handler.clone());
         processor
@@ -270,8 +305,21 @@ mod cpmm_tests {
         let accounts = SwapBaseOutput::arrange_accounts(&instruction.accounts);
         let accounts = accounts.expect("Accounts are not some");
         let token_swap_accounts = TokenSwapAccounts::from(accounts);
-        let transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
-        assert_eq!(transfers.len(), 2);
+        assert_eq!(
+            token_swap_accounts.vault_adas,
+            HashSet::from([
+                "5hVU9W7s2g2VRjQR4Hzz5rchwnRP7MZN7Fm1AfSWd3bA".to_string(),
+                "6k3qWpmArZS8S1MmRiXUhWceVAnMWJnn3sDRUxcpcC35".to_string(),
+            ])
+        );
+        let filtered_transfers = filter_swap_transfers(&transfers, &token_swap_accounts);
+        assert_eq!(filtered_transfers, transfers);
+
+        let (is_buy, base, quote) =
+            get_base_quote_mint(&token_swap_accounts, &filtered_transfers).unwrap();
+        assert!(is_buy);
+        assert_eq!(base.mint, "pi1RgmNaLQsNEyEAsrEjgmemojPwitwDAXc3zgseWWF");
+        assert_eq!(quote.mint, "So11111111111111111111111111111111111111112");
 
         let mut processor = RaydiumCpmmInstructionProcessor::new(token_swap_handler.clone());
         processor
@@ -1,16 +1,22 @@
 use crate::{
-    constants::{USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
+    constants::{Dexes, USDC_MINT_KEY_STR, USDT_MINT_KEY_STR, WSOL_MINT_KEY_STR},
     TokenSwapAccounts, TokenSwapHandler,
 };
 use carbon_core::{
-    deserialize::ArrangeAccounts, error::CarbonResult, instruction::InstructionProcessorInputType,
-    metrics::MetricsCollection, processor::Processor,
+    deserialize::ArrangeAccounts,
+    error::CarbonResult,
+    instruction::{DecodedInstruction, InstructionProcessorInputType},
+    metrics::MetricsCollection,
+    processor::Processor,
 };
 use carbon_raydium_launchpad_decoder::instructions::{
+    initialize::{Initialize, InitializeInstructionAccounts},
     sell_exact_in::{SellExactIn, SellExactInInstructionAccounts},
     sell_exact_out::{SellExactOut, SellExactOutInstructionAccounts},
     RaydiumLaunchpadInstruction,
 };
+use chrono::Utc;
+use sonar_db::models::NewPoolEvent;
 use std::{collections::HashSet, sync::Arc, sync::LazyLock};
 
 /// A set of quote mints supported by Raydium Launchpad
@@ -31,6 +37,18 @@ pub fn get_raydium_launchpad_quote_mints() -> Arc<HashSet<String>> {
     Arc::new(RAYDIUM_LAUNCHPAD_QUOTE_MINTS.clone())
 }
 
+/// get_launch_event returns the bonding curve pool initialized for a token, the token is the
+/// base mint of the pool
+pub fn get_launch_event(accounts: &InitializeInstructionAccounts, timestamp: u64) -> NewPoolEvent {
+    NewPoolEvent {
+        dex: Dexes::RaydiumLaunchpad,
+        token_a_mint: accounts.base_mint.to_string(),
+        token_b_mint: accounts.quote_mint.to_string(),
+        pool: accounts.pool_state.to_string(),
+        timestamp,
+    }
+}
+
 impl From<SellExactInInstructionAccounts> for TokenSwapAccounts {
     fn from(accounts: SellExactInInstructionAccounts) -> Self {
         let pair = accounts.pool_state.to_string();
@@ -70,6 +88,21 @@ impl From<SellExactOutInstructionAccounts> for TokenSwapAccounts {
     }
 }
 
+/// get_swap_accounts returns the accounts of a swap instruction, None for the other instructions
+pub fn get_swap_accounts(
+    instruction: &DecodedInstruction<RaydiumLaunchpadInstruction>,
+) -> Option<TokenSwapAccounts> {
+    match &instruction.data {
+        RaydiumLaunchpadInstruction::SellExactIn(_) => {
+            SellExactIn::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        RaydiumLaunchpadInstruction::SellExactOut(_) => {
+            SellExactOut::arrange_accounts(&instruction.accounts).map(TokenSwapAccounts::from)
+        }
+        _ => None,
+    }
+}
+
 pub struct RaydiumLaunchpadInstructionProcessor {
     swap_handler: Arc<TokenSwapHandler>,
 }
@@ -90,32 +123,22 @@ impl Processor for RaydiumLaunchpadInstructionProcessor {
         _metrics: Arc<MetricsCollection>,
     ) -> CarbonResult<()> {
         let (meta, instruction, nested_instructions, _) = data;
-        match &instruction.data {
-            RaydiumLaunchpadInstruction::SellExactIn(_) => {
-                let accounts = SellExactIn::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
-            }
-            RaydiumLaunchpadInstruction::SellExactOut(_) => {
-                let accounts = SellExactOut::arrange_accounts(&instruction.accounts);
-                if let Some(accounts) = accounts {
-                    let token_swap_accounts = TokenSwapAccounts::from(accounts);
-                    self.swap_handler.spawn_swap_instruction(
-                        &token_swap_accounts,
-                        &meta,
-                        &nested_instructions,
-                    );
-                }
+        if let Some(token_swap_accounts) = get_swap_accounts(&instruction) {
+            self.swap_handler.spawn_swap_instruction(
+                Dexes::RaydiumLaunchpad,
+                &token_swap_accounts,
+                &meta,
+                &nested_instructions,
+            );
+        }
+        if let RaydiumLaunchpadInstruction::Initialize(_) = &instruction.data {
+            if let Some(accounts) = Initialize::arrange_accounts(&instruction.accounts) {
+                let block_time =
+                    meta.transaction_metadata.block_time.unwrap_or(Utc::now().timestamp()) as u64;
+                let event = get_launch_event(&accounts, block_time);
+                self.swap_handler.spawn_launch_instruction(&meta, event);
             }
-            _ => {}
         }
-
         Ok(())
     }
 }
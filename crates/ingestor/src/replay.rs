@@ -0,0 +1,414 @@
+//! this file replays a list of transactions through the pipeline into in-memory storages,
+//! reporting what became of the swaps of each one, for the regression runs after a release
+use crate::{
+    config::Config,
+    datasource::{build_pipeline_with_handler, vec::VecDatasource},
+    handler::{fetch_transaction_update, skipped_swaps::SkippedSwapLog},
+    metrics::NodeMetrics,
+    TokenSwapHandler,
+};
+use anyhow::{bail, Context, Result};
+use carbon_core::datasource::TransactionUpdate;
+use serde::Serialize;
+use sonar_db::{
+    Database, KvStore, MemoryDb, MemoryMessageQueue, MessageQueue,
+    DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE,
+};
+use sonar_sol_price::{cache::set_sol_price, SolPriceCache};
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::{info, warn};
+
+/// How many times fetching a transaction is attempted
+const FETCH_ATTEMPTS: u32 = 3;
+/// The delay before the first retry of a fetch, doubled on each retry
+const FETCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// The skipped swaps kept per replayed transaction, a transaction may hold several swaps
+const SKIPPED_SWAPS_PER_TRANSACTION: usize = 8;
+/// How often the replay checks whether the pipeline and the swaps are done
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// The reason of a transaction without any swap of the decoded dexes
+pub const NO_SWAP_REASON: &str = "no_swap";
+
+/// The flags of the replay command
+#[derive(Debug, Clone, clap::Args)]
+pub struct ReplayArgs {
+    /// A file of signatures, one per line, the blank lines and `#` comments are ignored
+    #[arg(long)]
+    pub file: PathBuf,
+    /// How many transactions are fetched at once
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+    /// The usd price of SOL, defaults to the Binance price
+    #[arg(long)]
+    pub sol_price: Option<f64>,
+    /// How long to wait for the pipeline and the swaps to complete, in seconds
+    #[arg(long, default_value_t = 300)]
+    pub timeout_secs: u64,
+}
+
+/// What became of the swaps of a replayed transaction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ReplayOutcome {
+    /// Some swaps were stored, the reasons of the other ones are listed
+    Ingested {
+        swaps: usize,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        skipped: Vec<String>,
+    },
+    /// No swap was stored, `no_swap` when no swap instruction was decoded
+    Skipped { reasons: Vec<String> },
+    /// The transaction couldn't be fetched or decoded
+    Error { error: String },
+}
+
+/// The outcome of a replayed transaction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplayReport {
+    pub signature: String,
+    #[serde(flatten)]
+    pub outcome: ReplayOutcome,
+}
+
+/// read_signatures returns the signatures of a replay file, one per line
+pub fn read_signatures(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// fetch_transaction_updates fetches the transactions with at most `concurrency` fetches at
+/// once, in the order of `signatures`
+pub async fn fetch_transaction_updates<F, Fut>(
+    signatures: &[String],
+    concurrency: usize,
+    fetch: F,
+) -> Vec<Result<TransactionUpdate>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<TransactionUpdate>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for (index, signature) in signatures.iter().enumerate() {
+        let fetch = fetch(signature.clone());
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (index, fetch.await)
+        });
+    }
+    let mut updates: Vec<_> = signatures.iter().map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, update)) => updates[index] = Some(update),
+            Err(e) => warn!(error = ?e, "Failed to join a transaction fetch"),
+        }
+    }
+    updates
+        .into_iter()
+        .map(|update| update.unwrap_or_else(|| Err(anyhow::anyhow!("The fetch panicked"))))
+        .collect()
+}
+
+/// fetch_transaction_update_with_retry fetches a transaction from the RPC, retrying with
+/// an exponential backoff
+pub async fn fetch_transaction_update_with_retry(signature: String) -> Result<TransactionUpdate> {
+    let mut delay = FETCH_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match fetch_transaction_update(&signature).await {
+            Ok(update) => return Ok(update),
+            Err(e) if attempt < FETCH_ATTEMPTS => {
+                warn!(%signature, attempt, error = ?e, "Failed to fetch transaction, retrying");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// replay_outcome returns the outcome of a transaction from its stored swaps and the reasons
+/// of its skipped ones
+fn replay_outcome(swaps: usize, reasons: Vec<String>) -> ReplayOutcome {
+    match (swaps, reasons.is_empty()) {
+        (0, true) => ReplayOutcome::Skipped { reasons: vec![NO_SWAP_REASON.to_string()] },
+        (0, false) => ReplayOutcome::Skipped { reasons },
+        (swaps, _) => ReplayOutcome::Ingested { swaps, skipped: reasons },
+    }
+}
+
+/// replay_transactions feeds the fetched transactions through the pipeline to `handler`,
+/// which writes to `db`, and reports the outcome of each signature once its swaps are done,
+/// fails when they aren't done within `timeout`
+pub async fn replay_transactions(
+    fetched: Vec<(String, Result<TransactionUpdate>)>,
+    handler: TokenSwapHandler,
+    db: &MemoryDb,
+    timeout: Duration,
+) -> Result<Vec<ReplayReport>> {
+    let deadline = Instant::now() + timeout;
+    let capacity = fetched.len().max(1) * SKIPPED_SWAPS_PER_TRANSACTION;
+    let skipped_swaps = Arc::new(SkippedSwapLog::new(capacity));
+    let handler = Arc::new(handler.with_skipped_swaps(skipped_swaps.clone()));
+    let metrics = handler.metrics.clone();
+
+    let mut signatures = vec![];
+    let mut transactions = vec![];
+    for (signature, update) in fetched {
+        match update {
+            Ok(update) => {
+                transactions.push(update);
+                signatures.push((signature, None));
+            }
+            Err(e) => signatures.push((signature, Some(format!("{e:#}")))),
+        }
+    }
+    let count = transactions.len() as u64;
+    let datasource = VecDatasource::new(transactions);
+    let mut pipeline = build_pipeline_with_handler(
+        vec![datasource],
+        handler,
+        DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE,
+    )?;
+    let processed = || {
+        let stats = metrics.pipeline.stats();
+        stats.processed + stats.failed >= count
+    };
+    tokio::select! {
+        result = pipeline.run() => result?,
+        result = wait_for(processed, deadline) => result.context("Failed to process the transactions")?,
+    }
+    wait_for(|| swaps_done(&metrics), deadline).await.context("Failed to process the swaps")?;
+
+    let swap_events = db.swap_events();
+    let reports = signatures
+        .into_iter()
+        .map(|(signature, error)| {
+            let outcome = match error {
+                Some(error) => ReplayOutcome::Error { error },
+                None => {
+                    let swaps = swap_events.iter().filter(|e| e.signature == signature).count();
+                    let reasons = skipped_swaps
+                        .find(&signature)
+                        .into_iter()
+                        .rev()
+                        .map(|skipped| skipped.reason)
+                        .collect();
+                    replay_outcome(swaps, reasons)
+                }
+            };
+            ReplayReport { signature, outcome }
+        })
+        .collect();
+    Ok(reports)
+}
+
+/// swaps_done returns true once every swap the processors spawned completed
+fn swaps_done(metrics: &NodeMetrics) -> bool {
+    let total = metrics.total_swaps_processed.load(Ordering::SeqCst);
+    let succeed = metrics.succeed_swaps.load(Ordering::SeqCst);
+    let failed = metrics.failed_swaps.load(Ordering::SeqCst);
+    succeed + failed >= total
+}
+
+/// wait_for resolves once `done` returns true, fails once the deadline passed
+async fn wait_for(done: impl Fn() -> bool, deadline: Instant) -> Result<()> {
+    while !done() {
+        if Instant::now() >= deadline {
+            bail!("Timed out waiting for the replay");
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// run_replay replays the signatures of the file into in-memory storages with the swap
+/// filters of `config`, the token metadata is fetched from the RPC and the SOL price stays
+/// fixed, so that runs are comparable
+pub async fn run_replay(args: &ReplayArgs, config: &Config) -> Result<Vec<ReplayReport>> {
+    let contents = tokio::fs::read_to_string(&args.file)
+        .await
+        .with_context(|| format!("Failed to read {}", args.file.display()))?;
+    let signatures = read_signatures(&contents);
+    let sol_price = match args.sol_price {
+        Some(sol_price) => sol_price,
+        None => SolPriceCache::new(None, None)?.get_price().await,
+    };
+    set_sol_price(sol_price).await;
+    info!(transactions = signatures.len(), sol_price, "Replaying transactions");
+
+    let updates = fetch_transaction_updates(
+        &signatures,
+        args.concurrency,
+        fetch_transaction_update_with_retry,
+    )
+    .await;
+    let db = MemoryDb::default();
+    let kv_store = Arc::new(KvStore::in_memory());
+    let message_queue: MessageQueue = Box::new(MemoryMessageQueue::default());
+    let boxed_db: Database = Box::new(db.clone());
+    let handler = TokenSwapHandler::new(
+        kv_store,
+        Arc::new(message_queue),
+        Arc::new(boxed_db),
+        Arc::new(NodeMetrics::new()),
+        config,
+    );
+    let fetched = signatures.into_iter().zip(updates).collect();
+    replay_transactions(fetched, handler, &db, Duration::from_secs(args.timeout_secs)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handler::SwapFilterConfig,
+        test_swaps::{get_transaction_data, MemoryStorages, TEST_SOL_PRICE},
+    };
+
+    const DLMM_SWAP: &str =
+        "3m4LERWUekW7im8rgu8QgpSJA8a9yEYL3gDvorbd5YpkXarrL3PGoVmyFyQzd1Pw9oZiQy2LPUjaG8Xr4p433kwn";
+    const USDC_SWAP: &str =
+        "5izpNhE1yRru7WuBMV7nWz4DrXvobUWvWuAcTAPgL1tBComtbqQrF6oSbTnhuyv2k4SPX9xNXEpDnunD2eSM1yen";
+    const PUMP_SELL: &str =
+        "4SikTiGq3nYZrFd3D5ZwckWVbuCtV2TRgHN6E24t4XpnnemmSk98TZ9SRYCzjK4FSiGEsSr85ep45ARP7i3pkJa7";
+    const WSOL_MINT: &str = "So11111111111111111111111111111111111111112";
+    const PUMP_MINT: &str = "7c5Jv9KSCJbct34CqSmtbHpys6u2CtFK9VaPoneGpump";
+    const DLMM_MINT: &str = "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump";
+    const TRUMP_MINT: &str = "6p6xgHyF7AeE6TZkSmFsko444wqoP15icUSqi2jfGiPN";
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn test_read_signatures() {
+        let contents = "# edge cases\nfirst\n\n  second  \n# done\n";
+        assert_eq!(read_signatures(contents), vec!["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_transaction_updates_keeps_the_order() {
+        let signatures = vec![DLMM_SWAP.to_string(), "broken".to_string(), PUMP_SELL.to_string()];
+        let updates = fetch_transaction_updates(&signatures, 2, |signature| async move {
+            if signature == "broken" {
+                anyhow::bail!("Failed to get transaction");
+            }
+            let (_, update, _) = get_transaction_data(&signature).await?;
+            Ok(*update)
+        })
+        .await;
+        assert_eq!(updates[0].as_ref().unwrap().signature.to_string(), DLMM_SWAP);
+        assert!(updates[1].is_err());
+        assert_eq!(updates[2].as_ref().unwrap().signature.to_string(), PUMP_SELL);
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_the_outcome_of_each_transaction() {
+        set_sol_price(TEST_SOL_PRICE).await;
+        let storages = MemoryStorages::default();
+        for mint in [DLMM_MINT, TRUMP_MINT, PUMP_MINT] {
+            storages.seed_token(mint, 6, 1_000_000_000.0).await;
+        }
+        let (kv_store, message_queue, db) = storages.storages();
+        // the pump sell moves too little of both mints
+        let mut config = SwapFilterConfig {
+            min_ui_amount: 0.0,
+            min_ui_amount_overrides: Default::default(),
+            min_swap_usd: 0.0,
+            quote_pair_policy: Default::default(),
+            ..Default::default()
+        };
+        config.min_ui_amount_overrides.insert(WSOL_MINT.to_string(), 1.0);
+        config.min_ui_amount_overrides.insert(PUMP_MINT.to_string(), 1_000_000_000.0);
+        let handler = TokenSwapHandler::new(
+            kv_store,
+            message_queue,
+            db,
+            Arc::new(NodeMetrics::new()),
+            &Config::default(),
+        )
+        .with_swap_filter_config(config);
+
+        let mut fetched = vec![];
+        for signature in [DLMM_SWAP, USDC_SWAP, PUMP_SELL] {
+            let (_, update, _) = get_transaction_data(signature).await.unwrap();
+            fetched.push((signature.to_string(), Ok(*update)));
+        }
+        let error = anyhow::anyhow!("Invalid signature");
+        fetched.push(("not-a-signature".to_string(), Err(error)));
+
+        let reports = replay_transactions(fetched, handler, &storages.db, TIMEOUT).await.unwrap();
+        let signatures: Vec<_> = reports.iter().map(|r| r.signature.as_str()).collect();
+        assert_eq!(signatures, vec![DLMM_SWAP, USDC_SWAP, PUMP_SELL, "not-a-signature"]);
+
+        // the reported swaps are the stored ones, with the amounts of the transactions
+        let swap_events = storages.db.swap_events();
+        let stored = |signature: &str| {
+            swap_events.iter().filter(|e| e.signature == signature).cloned().collect::<Vec<_>>()
+        };
+        for (report, signature) in reports.iter().zip([DLMM_SWAP, USDC_SWAP]) {
+            let ReplayOutcome::Ingested { swaps, .. } = report.outcome else {
+                panic!("Expected {signature} to be ingested, got {:?}", report.outcome);
+            };
+            assert_eq!(swaps, stored(signature).len());
+        }
+        let dlmm = stored(DLMM_SWAP);
+        let dlmm = dlmm.iter().find(|e| e.pubkey == DLMM_MINT).expect("Expected the DLMM swap");
+        assert_eq!((dlmm.base_amount, dlmm.quote_amount), (24000.0, 65.256388526));
+        let usdc = stored(USDC_SWAP);
+        let usdc = usdc.iter().find(|e| e.pubkey == TRUMP_MINT).expect("Expected the USDC swap");
+        assert_eq!((usdc.base_amount, usdc.quote_amount), (18.143267, 200.0));
+        assert_eq!(usdc.swap_amount, 200.0);
+
+        assert!(stored(PUMP_SELL).is_empty());
+        let ReplayOutcome::Skipped { reasons } = &reports[2].outcome else {
+            panic!("Expected the pump sell to be skipped, got {:?}", reports[2].outcome);
+        };
+        assert!(reasons.iter().all(|r| r == "tiny_swap"), "{reasons:?}");
+        assert!(!reasons.is_empty());
+        assert_eq!(
+            serde_json::to_value(&reports[3]).unwrap(),
+            serde_json::json!({
+                "signature": "not-a-signature",
+                "outcome": "error",
+                "error": "Invalid signature",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_times_out() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(wait_for(|| true, deadline).await.is_ok());
+        let error = wait_for(|| false, deadline).await.unwrap_err();
+        assert_eq!(error.to_string(), "Timed out waiting for the replay");
+    }
+
+    #[test]
+    fn test_replay_outcome() {
+        let reasons = |reasons: &[&str]| reasons.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            replay_outcome(0, vec![]),
+            ReplayOutcome::Skipped { reasons: reasons(&[NO_SWAP_REASON]) }
+        );
+        assert_eq!(
+            replay_outcome(0, reasons(&["tiny_swap"])),
+            ReplayOutcome::Skipped { reasons: reasons(&["tiny_swap"]) }
+        );
+        let outcome = replay_outcome(2, reasons(&["zero_swap"]));
+        assert_eq!(
+            serde_json::to_value(&outcome).unwrap(),
+            serde_json::json!({"outcome": "ingested", "swaps": 2, "skipped": ["zero_swap"]})
+        );
+    }
+}
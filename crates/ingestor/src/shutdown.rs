@@ -0,0 +1,45 @@
+//! The graceful shutdown of the ingestor, the signals are shared with the other services
+pub use sonar_db::shutdown::shutdown_signal;
+
+use sonar_db::Database;
+use std::time::Duration;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info};
+
+/// drain_and_close waits for the spawned swap tasks to finish, then commits the swap events
+/// still buffered by the inserters, each within `timeout`
+pub async fn drain_and_close(tasks: &TaskTracker, db: &Database, timeout: Duration) {
+    tasks.close();
+    info!(tasks = tasks.len(), "Draining the swap tasks");
+    if tokio::time::timeout(timeout, tasks.wait()).await.is_err() {
+        error!(tasks = tasks.len(), "Timed out draining the swap tasks after {:?}", timeout);
+    }
+    match tokio::time::timeout(timeout, db.close()).await {
+        Ok(Ok(())) => info!("db closed"),
+        Ok(Err(e)) => error!(error = ?e, "Failed to close db"),
+        Err(_) => error!("Timed out closing db after {:?}", timeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::{test_utils::make_swap_event, MemoryDb};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_drain_and_close_waits_for_the_swap_tasks() {
+        let memory_db = MemoryDb::default();
+        let db: Arc<Database> = Arc::new(Box::new(memory_db.clone()));
+        let tasks = TaskTracker::new();
+        let writer = db.clone();
+        tasks.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            writer.insert_swap_event(&make_swap_event("token", "pair", "sig", 1, 1.0)).await
+        });
+
+        drain_and_close(&tasks, &db, Duration::from_secs(5)).await;
+        assert!(tasks.is_empty());
+        assert_eq!(memory_db.swap_events().len(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{datasource::rpc::make_rpc_client, metrics::NodeMetrics};
+use chrono::Utc;
+use sonar_db::{models::SystemAlert, MessageQueue};
+use std::{
+    env::var,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+const DEFAULT_MAX_SLOT_LAG: u64 = 150; // ~1 minute of slots
+const DEFAULT_SLOT_LAG_ALERT_SECS: u64 = 60;
+const SLOT_LAG_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// The lag has to drop below this ratio of the threshold before an alert is resolved,
+/// so a lag hovering around the threshold doesn't flap between alerting and resolved
+const SLOT_LAG_RECOVERY_RATIO: f64 = 0.8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotLagConfig {
+    /// Lags above this number of slots are considered behind chain head
+    pub max_slot_lag: u64,
+    /// How long the lag has to stay above `max_slot_lag` before alerting
+    pub alert_after: Duration,
+}
+
+impl Default for SlotLagConfig {
+    fn default() -> Self {
+        Self {
+            max_slot_lag: DEFAULT_MAX_SLOT_LAG,
+            alert_after: Duration::from_secs(DEFAULT_SLOT_LAG_ALERT_SECS),
+        }
+    }
+}
+
+impl SlotLagConfig {
+    /// Create a slot lag config from `MAX_SLOT_LAG` and `SLOT_LAG_ALERT_SECS`
+    pub fn from_env() -> Self {
+        let parse = |key: &str, default: u64| {
+            var(key).ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(default)
+        };
+        Self {
+            max_slot_lag: parse("MAX_SLOT_LAG", DEFAULT_MAX_SLOT_LAG),
+            alert_after: Duration::from_secs(parse(
+                "SLOT_LAG_ALERT_SECS",
+                DEFAULT_SLOT_LAG_ALERT_SECS,
+            )),
+        }
+    }
+
+    fn recovery_slot_lag(&self) -> u64 {
+        (self.max_slot_lag as f64 * SLOT_LAG_RECOVERY_RATIO) as u64
+    }
+}
+
+/// Returns the number of slots the ingestor is behind chain head,
+/// None until a transaction has been processed
+pub fn compute_slot_lag(chain_slot: u64, last_processed_slot: u64) -> Option<u64> {
+    if last_processed_slot == 0 {
+        return None;
+    }
+    Some(chain_slot.saturating_sub(last_processed_slot))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotLagAlert {
+    /// The lag stayed above the threshold for longer than `alert_after`
+    Behind(u64),
+    /// The lag dropped back below the recovery threshold
+    Recovered(u64),
+}
+
+/// Tracks the slot lag over time and raises an alert once per incident
+#[derive(Debug)]
+pub struct SlotLagTracker {
+    config: SlotLagConfig,
+    behind_since: Option<Instant>,
+    alerting: bool,
+}
+
+impl SlotLagTracker {
+    pub fn new(config: SlotLagConfig) -> Self {
+        Self { config, behind_since: None, alerting: false }
+    }
+
+    /// Observe the lag at `now`, returns an alert when the state changes
+    pub fn observe(&mut self, lag: u64, now: Instant) -> Option<SlotLagAlert> {
+        if self.alerting {
+            if lag <= self.config.recovery_slot_lag() {
+                self.alerting = false;
+                self.behind_since = None;
+                return Some(SlotLagAlert::Recovered(lag));
+            }
+            return None;
+        }
+
+        if lag <= self.config.max_slot_lag {
+            self.behind_since = None;
+            return None;
+        }
+
+        let behind_since = *self.behind_since.get_or_insert(now);
+        if now.duration_since(behind_since) >= self.config.alert_after {
+            self.alerting = true;
+            return Some(SlotLagAlert::Behind(lag));
+        }
+        None
+    }
+}
+
+/// Spawn a task polling the chain slot and reporting the ingestion lag into `metrics`,
+/// a `system_alert` is published on the message queue when the lag alert changes
+pub fn spawn_slot_lag_monitor(
+    metrics: Arc<NodeMetrics>,
+    message_queue: Option<Arc<MessageQueue>>,
+    config: SlotLagConfig,
+) {
+    tokio::spawn(async move {
+        let rpc_client = make_rpc_client();
+        let mut tracker = SlotLagTracker::new(config);
+        let mut interval = tokio::time::interval(SLOT_LAG_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let chain_slot = match rpc_client.get_slot().await {
+                Ok(slot) => slot,
+                Err(e) => {
+                    error!("Failed to get chain slot: {}", e);
+                    continue;
+                }
+            };
+            let Some(lag) = compute_slot_lag(chain_slot, metrics.last_processed_slot()) else {
+                continue;
+            };
+            metrics.set_slot_lag(lag);
+
+            let message = match tracker.observe(lag, Instant::now()) {
+                Some(SlotLagAlert::Behind(lag)) => {
+                    warn!(lag, chain_slot, "Ingestion is falling behind chain head");
+                    format!("ingestion is {} slots behind chain head", lag)
+                }
+                Some(SlotLagAlert::Recovered(lag)) => {
+                    info!(lag, chain_slot, "Ingestion caught up with chain head");
+                    format!("ingestion caught up with chain head, {} slots behind", lag)
+                }
+                None => continue,
+            };
+            if let Some(message_queue) = &message_queue {
+                let alert = SystemAlert {
+                    source: "slot_lag".to_string(),
+                    message,
+                    value: lag as f64,
+                    timestamp: Utc::now().timestamp() as u64,
+                };
+                if let Err(e) = message_queue.publish_system_alert(&alert).await {
+                    error!("Failed to publish slot lag alert: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_slot_lag() {
+        assert_eq!(compute_slot_lag(1_000, 0), None);
+        assert_eq!(compute_slot_lag(1_000, 900), Some(100));
+        // the processed slot may be ahead of a lagging rpc node
+        assert_eq!(compute_slot_lag(1_000, 1_010), Some(0));
+    }
+
+    #[test]
+    fn test_slot_lag_alert_after_duration() {
+        let config = SlotLagConfig { max_slot_lag: 100, alert_after: Duration::from_secs(30) };
+        let mut tracker = SlotLagTracker::new(config);
+        let start = Instant::now();
+
+        assert_eq!(tracker.observe(150, start), None);
+        assert_eq!(tracker.observe(150, start + Duration::from_secs(20)), None);
+        assert_eq!(
+            tracker.observe(150, start + Duration::from_secs(30)),
+            Some(SlotLagAlert::Behind(150))
+        );
+        // alerted once per incident
+        assert_eq!(tracker.observe(200, start + Duration::from_secs(40)), None);
+        assert_eq!(
+            tracker.observe(50, start + Duration::from_secs(50)),
+            Some(SlotLagAlert::Recovered(50))
+        );
+    }
+
+    #[test]
+    fn test_slot_lag_hysteresis() {
+        let config = SlotLagConfig { max_slot_lag: 100, alert_after: Duration::from_secs(30) };
+        let mut tracker = SlotLagTracker::new(config);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        // dipping below the threshold resets the duration
+        assert_eq!(tracker.observe(101, at(0)), None);
+        assert_eq!(tracker.observe(100, at(20)), None);
+        assert_eq!(tracker.observe(101, at(40)), None);
+        assert_eq!(tracker.observe(101, at(70)), Some(SlotLagAlert::Behind(101)));
+
+        // hovering at the threshold doesn't flap
+        for (i, lag) in [99, 101, 95, 100, 85, 102].into_iter().enumerate() {
+            assert_eq!(tracker.observe(lag, at(80 + i as u64 * 10)), None);
+        }
+        assert_eq!(tracker.observe(80, at(150)), Some(SlotLagAlert::Recovered(80)));
+        assert_eq!(tracker.observe(101, at(160)), None);
+    }
+}
@@ -0,0 +1,70 @@
+//! the storages the ingestor writes the swaps to, or logs them to in a dry run
+use anyhow::Result;
+use sonar_db::{
+    make_db_with_config, make_kv_store_with_config, make_message_queue_with_config, Database,
+    DatabaseConfig, DryRunLog, EnvReader, KvStore, MemoryDb, MemoryMessageQueue, MessageQueue,
+    RedisConfig,
+};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// The database and the redis the swaps are written to
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoragesConfig {
+    pub database: DatabaseConfig,
+    /// The redis of the kv store and the message queue
+    pub redis: RedisConfig,
+}
+
+impl StoragesConfig {
+    /// read_env reads the database and the redis settings
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        Self { database: DatabaseConfig::read_env(env), redis: RedisConfig::read_env(env) }
+    }
+}
+
+#[derive(Clone)]
+pub struct Storages {
+    pub db: Arc<Database>,
+    pub kv_store: Arc<KvStore>,
+    pub message_queue: Arc<MessageQueue>,
+}
+
+impl Storages {
+    /// from_config connects to the database, the kv store and the message queue of `config`
+    pub async fn from_config(config: &StoragesConfig) -> Result<Self> {
+        let db = make_db_with_config(&config.database).await?;
+        info!("db connected");
+        let kv_store = make_kv_store_with_config(&config.redis).await?;
+        info!("kv connected");
+        let message_queue = make_message_queue_with_config(&config.redis).await?;
+        info!("message queue connected");
+        Ok(Self {
+            db: Arc::new(db),
+            kv_store: Arc::new(kv_store),
+            message_queue: Arc::new(message_queue),
+        })
+    }
+
+    /// dry_run returns storages logging the swap events, pairs, tokens and messages to
+    /// `dry_run` instead of writing them, the kv store only lives in the process
+    pub fn dry_run(dry_run: DryRunLog) -> Self {
+        let db: Database = Box::new(MemoryDb::default().with_dry_run(dry_run.clone()));
+        let message_queue: MessageQueue =
+            Box::new(MemoryMessageQueue::default().with_dry_run(dry_run));
+        Self {
+            db: Arc::new(db),
+            kv_store: Arc::new(KvStore::in_memory()),
+            message_queue: Arc::new(message_queue),
+        }
+    }
+
+    /// connect returns the storages of `config`, the dry run storages without one
+    pub async fn connect(config: Option<&StoragesConfig>) -> Result<Self> {
+        if let Some(config) = config {
+            return Self::from_config(config).await;
+        }
+        warn!("Dry run, the swaps are logged instead of written to the storages");
+        Ok(Self::dry_run(DryRunLog::default()))
+    }
+}
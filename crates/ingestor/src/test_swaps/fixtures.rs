@@ -0,0 +1,107 @@
+//! Recorded RPC transactions, so the processor tests can run without a mainnet RPC
+//!
+//! `SONAR_TEST_FIXTURES` selects where transactions come from:
+//!
+//! * `replay` (default) - read `tests/fixtures/{signature}.json`, fetch from the RPC if missing
+//! * `record` - fetch from the RPC and write the fixture
+//! * `live` - always fetch from the RPC, and use Redis and ClickHouse instead of the
+//!   in-memory storages
+use crate::prelude::make_rpc_client;
+use anyhow::{Context, Result};
+use dotenvy::dotenv;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_commitment_config::CommitmentConfig;
+use solana_signature::Signature;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use std::{env::var, path::PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureMode {
+    Replay,
+    Record,
+    Live,
+}
+
+impl FixtureMode {
+    pub fn from_env() -> Self {
+        match var("SONAR_TEST_FIXTURES").as_deref() {
+            Ok("record") => Self::Record,
+            Ok("live") => Self::Live,
+            _ => Self::Replay,
+        }
+    }
+}
+
+/// fixture_path returns the path of the recorded transaction
+pub fn fixture_path(signature: &Signature) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(format!("{signature}.json"))
+}
+
+async fn fetch_transaction(
+    signature: &Signature,
+) -> Result<EncodedConfirmedTransactionWithStatusMeta> {
+    dotenv().ok();
+    let rpc_client = make_rpc_client();
+    let transaction = rpc_client
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Binary),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await
+        .context("Failed to get transaction")?;
+    Ok(transaction)
+}
+
+fn read_fixture(
+    signature: &Signature,
+) -> Result<Option<EncodedConfirmedTransactionWithStatusMeta>> {
+    let path = fixture_path(signature);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .context(format!("Failed to read fixture {}", path.display()))?;
+    let transaction = serde_json::from_str(&content)
+        .context(format!("Failed to parse fixture {}", path.display()))?;
+    Ok(Some(transaction))
+}
+
+fn write_fixture(
+    signature: &Signature,
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<()> {
+    let path = fixture_path(signature);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("Failed to create fixtures dir")?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(transaction)?)
+        .context(format!("Failed to write fixture {}", path.display()))?;
+    Ok(())
+}
+
+/// load_transaction returns the transaction from its fixture or the RPC,
+/// depending on the fixture mode
+pub async fn load_transaction(
+    signature: &Signature,
+) -> Result<EncodedConfirmedTransactionWithStatusMeta> {
+    match FixtureMode::from_env() {
+        FixtureMode::Replay => match read_fixture(signature)? {
+            Some(transaction) => Ok(transaction),
+            // without a fixture the transaction is fetched, record mode saves it
+            None => fetch_transaction(signature).await,
+        },
+        FixtureMode::Record => {
+            let transaction = fetch_transaction(signature).await?;
+            write_fixture(signature, &transaction)?;
+            Ok(transaction)
+        }
+        FixtureMode::Live => fetch_transaction(signature).await,
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{
+    config::Config, handler::analyze::transaction_update_from_encoded, metrics::NodeMetrics,
+};
+pub use crate::{
+    decoder::TokenTransferDetails,
+    handler::{get_inner_token_transfers, TokenSwapHandler},
+};
+use anyhow::{anyhow, Result};
+use carbon_core::{
+    datasource::TransactionUpdate,
+    instruction::{NestedInstruction, NestedInstructions},
+    transaction::TransactionMetadata,
+    transformers::extract_instructions_with_metadata,
+};
+use solana_signature::Signature;
+use sonar_db::{
+    make_db_from_env, make_kv_store_from_env, make_message_queue_from_env, Database, KvStore,
+    MessageQueue,
+};
+use sonar_sol_price::SolPriceCache;
+use std::{str::FromStr, sync::Arc};
+
+mod fixtures;
+mod storage;
+
+pub use fixtures::{fixture_path, load_transaction, FixtureMode};
+pub use storage::{MemorySolPriceCache, MemoryStorages, TEST_SOL_PRICE};
+
+/// get_storages returns in-memory storages, or Redis and ClickHouse with
+/// `SONAR_TEST_FIXTURES=live`
+pub async fn get_storages() -> (Arc<KvStore>, Arc<MessageQueue>, Arc<Database>) {
+    if FixtureMode::from_env() != FixtureMode::Live {
+        return MemoryStorages::default().storages();
+    }
+    let kv_store = make_kv_store_from_env().await.expect("Failed to make kv store");
+    let message_queue = make_message_queue_from_env().await.expect("Failed to make message queue");
+    let db = make_db_from_env().await.expect("Failed to make db");
+    (Arc::new(kv_store), Arc::new(message_queue), Arc::new(db))
+}
+
+pub async fn get_sol_price() -> f64 {
+    let (kv_store, message_queue, _db) = get_storages().await;
+    let price_cache = SolPriceCache::new(Some(kv_store.clone()), Some(message_queue.clone()))
+        .expect("Failed to make sol price cache");
+    let price_cache = Arc::new(price_cache);
+    price_cache.get_price().await
+}
+
+pub async fn get_token_swap_handler() -> Arc<TokenSwapHandler> {
+    let (kv_store, message_queue, db) = get_storages().await;
+    let metrics = Arc::new(NodeMetrics::new());
+    Arc::new(TokenSwapHandler::new(kv_store, message_queue, db, metrics, &Config::default()))
+}
+
+pub async fn get_transaction_data(
+    tx_hash: &str,
+) -> Result<(Signature, Box<TransactionUpdate>, Box<TransactionMetadata>)> {
+    let signature = Signature::from_str(tx_hash).expect("Failed to parse signature");
+    let encoded_transaction =
+        load_transaction(&signature).await.expect("Failed to load transaction");
+    let transaction_update =
+        Box::new(transaction_update_from_encoded(signature, encoded_transaction)?);
+
+    let transaction_metadata: TransactionMetadata = (*transaction_update)
+        .clone()
+        .try_into()
+        .expect("Failed to convert transaction update to transaction metadata.");
+
+    Ok((signature, transaction_update, Box::new(transaction_metadata)))
+}
+
+pub async fn get_nested_instruction(
+    tx_hash: &str,
+    outer_idx: usize,
+    inner_idx: Option<usize>,
+) -> Result<(NestedInstruction, Box<TransactionUpdate>, Box<TransactionMetadata>)> {
+    let (_, transaction_update, transaction_metadata) =
+        get_transaction_data(tx_hash).await.expect("Failed to get transaction data");
+    let nested_instructions = extract_nested_instructions(&transaction_update)
+        .expect("Failed to extract nested instructions");
+    if outer_idx >= nested_instructions.len() {
+        return Err(anyhow!("Outer index out of bounds"));
+    }
+    let mut nested_instruction = nested_instructions[outer_idx].clone();
+    println!(
+        "nested_inner_instructions {:?}",
+        nested_instruction
+            .inner_instructions
+            .iter()
+            .map(|i| i.instruction.program_id.to_string())
+            .collect::<Vec<String>>()
+    );
+    if let Some(inner_idx) = inner_idx {
+        println!(
+            "inner_instructions {:?}",
+            nested_instructions
+                .iter()
+                .map(|i| i
+                    .inner_instructions
+                    .iter()
+                    .map(|j| j.instruction.program_id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "))
+                .collect::<Vec<String>>()
+        );
+        nested_instruction = nested_instruction.inner_instructions[inner_idx].clone()
+    }
+    Ok((nested_instruction, transaction_update, transaction_metadata))
+}
+
+pub fn extract_nested_instructions(
+    transaction_update: &TransactionUpdate,
+) -> Result<NestedInstructions> {
+    let transaction_metadata: TransactionMetadata = transaction_update
+        .clone()
+        .try_into()
+        .map_err(|e| anyhow!("Failed to convert transaction update: {}", e))?;
+    let transaction_metadata = Arc::new(transaction_metadata);
+    let instructions_with_metadata =
+        extract_instructions_with_metadata(&transaction_metadata, transaction_update)
+            .map_err(|e| anyhow!("Failed to extract instructions: {}", e))?;
+
+    Ok(instructions_with_metadata.into())
+}
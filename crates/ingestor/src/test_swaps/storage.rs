@@ -0,0 +1,144 @@
+//! In-memory storages for the swap handler, the tests assert on what it wrote
+use crate::{config::Config, handler::SwapFilterConfig, metrics::NodeMetrics, TokenSwapHandler};
+use anyhow::Result;
+use sonar_db::{
+    models::Token, test_utils::make_token, Database, DryRunLog, KvStore, MemoryDb,
+    MemoryMessageQueue, MessageQueue, SwapEvent, Trade,
+};
+use sonar_sol_price::{cache::set_sol_price, SolPriceCacheTrait};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+/// The SOL price used to price the WSOL quoted swaps
+pub const TEST_SOL_PRICE: f64 = 150.0;
+/// How long to wait for the swaps spawned by a processor
+const SWAP_EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct MemoryStorages {
+    pub kv_store: Arc<KvStore>,
+    pub db: MemoryDb,
+    pub message_queue: MemoryMessageQueue,
+    /// The writes skipped by the dry run storages
+    pub dry_run: DryRunLog,
+}
+
+impl Default for MemoryStorages {
+    fn default() -> Self {
+        Self {
+            kv_store: Arc::new(KvStore::in_memory()),
+            db: MemoryDb::default(),
+            message_queue: MemoryMessageQueue::default(),
+            dry_run: DryRunLog::default(),
+        }
+    }
+}
+
+impl MemoryStorages {
+    /// dry_run returns the storages of `--dry-run`, logging the writes instead of keeping them
+    pub fn dry_run() -> Self {
+        let dry_run = DryRunLog::default();
+        Self {
+            kv_store: Arc::new(KvStore::in_memory()),
+            db: MemoryDb::default().with_dry_run(dry_run.clone()),
+            message_queue: MemoryMessageQueue::default().with_dry_run(dry_run.clone()),
+            dry_run,
+        }
+    }
+
+    /// storages returns the trait objects the handlers take, sharing this state
+    pub fn storages(&self) -> (Arc<KvStore>, Arc<MessageQueue>, Arc<Database>) {
+        let message_queue: MessageQueue = Box::new(self.message_queue.clone());
+        let db: Database = Box::new(self.db.clone());
+        (self.kv_store.clone(), Arc::new(message_queue), Arc::new(db))
+    }
+
+    /// token_swap_handler returns a handler writing to these storages,
+    /// with the swap filters disabled and the SOL price at `TEST_SOL_PRICE`
+    pub async fn token_swap_handler(&self) -> Arc<TokenSwapHandler> {
+        set_sol_price(TEST_SOL_PRICE).await;
+        let (kv_store, message_queue, db) = self.storages();
+        let handler = TokenSwapHandler::new(
+            kv_store,
+            message_queue,
+            db,
+            Arc::new(NodeMetrics::new()),
+            &Config::default(),
+        )
+        .with_swap_filter_config(SwapFilterConfig {
+            min_ui_amount: 0.0,
+            min_ui_amount_overrides: Default::default(),
+            min_swap_usd: 0.0,
+            quote_pair_policy: Default::default(),
+            ..Default::default()
+        });
+        Arc::new(handler)
+    }
+
+    /// seed_token caches the token, so the handler doesn't fetch its metadata from the RPC
+    pub async fn seed_token(&self, mint: &str, decimals: u8, supply: f64) {
+        let token = Token { decimals, supply, ..make_token(mint, "", "") };
+        self.kv_store.set_token(mint, &token).await.expect("Failed to seed token");
+    }
+
+    /// wait_for_swap_events waits until `count` swap events are written or the timeout passes,
+    /// returns the swap events written so far
+    pub async fn wait_for_swap_events(&self, count: usize) -> Vec<SwapEvent> {
+        let started_at = Instant::now();
+        loop {
+            let swap_events = self.db.swap_events();
+            if swap_events.len() >= count || started_at.elapsed() >= SWAP_EVENT_TIMEOUT {
+                return swap_events;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// trades returns the trades published to the message queue
+    pub fn trades(&self) -> Vec<Trade> {
+        self.message_queue.trades()
+    }
+}
+
+/// A SOL price cache of its own, unlike the global price the other tests set
+pub struct MemorySolPriceCache {
+    price: RwLock<f64>,
+}
+
+impl MemorySolPriceCache {
+    pub fn new(price: f64) -> Self {
+        Self { price: RwLock::new(price) }
+    }
+}
+
+#[async_trait::async_trait]
+impl SolPriceCacheTrait for MemorySolPriceCache {
+    fn get_name(&self) -> String {
+        "memory".to_string()
+    }
+    fn get_owner(&self) -> String {
+        "memory".to_string()
+    }
+    fn get_signature(&self) -> String {
+        "memory".to_string()
+    }
+    fn get_kv_store(&self) -> Option<Arc<KvStore>> {
+        None
+    }
+    fn get_message_queue(&self) -> Option<Arc<MessageQueue>> {
+        None
+    }
+    async fn get_price(&self) -> f64 {
+        *self.price.read().await
+    }
+    async fn set_price(&self, price: f64) -> Result<()> {
+        *self.price.write().await = price;
+        Ok(())
+    }
+    async fn start_price_stream(&self) -> Result<()> {
+        Ok(())
+    }
+}
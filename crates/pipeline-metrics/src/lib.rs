@@ -0,0 +1,287 @@
+//! this crate counts the updates flowing through the carbon pipelines of the ingestor and the
+//! streams, and warns when the processed updates fall behind the received ones
+use async_trait::async_trait;
+use carbon_core::{error::CarbonResult, metrics::Metrics};
+use std::{
+    env::var,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::warn;
+
+/// The counter of the updates the pipeline received from its datasources
+pub const UPDATES_RECEIVED: &str = "updates_received";
+/// The counter of the updates the pipeline went through all the pipes of
+pub const UPDATES_PROCESSED: &str = "updates_processed";
+/// The counter of the updates a pipe failed on
+pub const UPDATES_FAILED: &str = "updates_failed";
+/// The gauge of the updates waiting in the channel of the pipeline
+pub const UPDATES_QUEUED: &str = "updates_queued";
+/// The histogram of the time an update took to go through the pipes
+pub const UPDATES_PROCESS_TIME_MS: &str = "updates_process_time_milliseconds";
+
+const DEFAULT_THROUGHPUT_WINDOW_SECS: u64 = 60;
+const DEFAULT_MIN_PROCESSED_RATIO: f64 = 0.9;
+const DEFAULT_MIN_WINDOW_UPDATES: u64 = 100;
+
+/// PipelineMetrics keeps the counters and gauges the pipeline reports, registered with
+/// `.metrics(...)` next to the `LogMetrics`
+#[derive(Debug, Default)]
+pub struct PipelineMetrics {
+    received: AtomicU64,
+    processed: AtomicU64,
+    failed: AtomicU64,
+    queued: AtomicU64,
+    max_queued: AtomicU64,
+    process_time_count: AtomicU64,
+    process_time_total_micros: AtomicU64,
+}
+
+/// A copy of the pipeline counters at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PipelineStats {
+    pub received: u64,
+    pub processed: u64,
+    pub failed: u64,
+    /// The updates waiting in the channel when last reported
+    pub queued: u64,
+    /// The most updates ever waiting in the channel
+    pub max_queued: u64,
+    /// The mean time an update took to go through the pipes
+    pub average_process_ms: f64,
+}
+
+impl PipelineMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// stats returns the current counters of the pipeline
+    pub fn stats(&self) -> PipelineStats {
+        let count = self.process_time_count.load(Ordering::Relaxed);
+        let total_micros = self.process_time_total_micros.load(Ordering::Relaxed);
+        let average_process_ms = match count {
+            0 => 0.0,
+            count => total_micros as f64 / count as f64 / 1000.0,
+        };
+        PipelineStats {
+            received: self.received.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            max_queued: self.max_queued.load(Ordering::Relaxed),
+            average_process_ms,
+        }
+    }
+}
+
+#[async_trait]
+impl Metrics for PipelineMetrics {
+    async fn initialize(&self) -> CarbonResult<()> {
+        Ok(())
+    }
+
+    async fn flush(&self) -> CarbonResult<()> {
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> CarbonResult<()> {
+        Ok(())
+    }
+
+    async fn update_gauge(&self, name: &str, value: f64) -> CarbonResult<()> {
+        if name == UPDATES_QUEUED {
+            let queued = value.max(0.0) as u64;
+            self.queued.store(queued, Ordering::Relaxed);
+            self.max_queued.fetch_max(queued, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    async fn increment_counter(&self, name: &str, value: u64) -> CarbonResult<()> {
+        let counter = match name {
+            UPDATES_RECEIVED => &self.received,
+            UPDATES_PROCESSED => &self.processed,
+            UPDATES_FAILED => &self.failed,
+            _ => return Ok(()),
+        };
+        counter.fetch_add(value, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn record_histogram(&self, name: &str, value: f64) -> CarbonResult<()> {
+        if name == UPDATES_PROCESS_TIME_MS {
+            self.process_time_count.fetch_add(1, Ordering::Relaxed);
+            self.process_time_total_micros
+                .fetch_add((value.max(0.0) * 1000.0) as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputConfig {
+    /// How often the processed updates are compared with the received ones
+    pub window: Duration,
+    /// The alarm fires when fewer than this share of the received updates were processed
+    pub min_ratio: f64,
+    /// A window with fewer received updates is too quiet to judge
+    pub min_updates: u64,
+}
+
+impl Default for ThroughputConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(DEFAULT_THROUGHPUT_WINDOW_SECS),
+            min_ratio: DEFAULT_MIN_PROCESSED_RATIO,
+            min_updates: DEFAULT_MIN_WINDOW_UPDATES,
+        }
+    }
+}
+
+impl ThroughputConfig {
+    /// Create a throughput config from `PIPELINE_THROUGHPUT_WINDOW_SECS`,
+    /// `PIPELINE_MIN_PROCESSED_RATIO` and `PIPELINE_MIN_WINDOW_UPDATES`
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let window = var("PIPELINE_THROUGHPUT_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map_or(default.window, Duration::from_secs);
+        Self {
+            window,
+            min_ratio: var("PIPELINE_MIN_PROCESSED_RATIO")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.min_ratio),
+            min_updates: var("PIPELINE_MIN_WINDOW_UPDATES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.min_updates),
+        }
+    }
+}
+
+/// ThroughputAlarm compares the updates processed with the updates received over the
+/// windows between its checks
+#[derive(Debug)]
+pub struct ThroughputAlarm {
+    config: ThroughputConfig,
+    /// The received and processed counters at the last check
+    last: (u64, u64),
+}
+
+impl ThroughputAlarm {
+    pub fn new(config: ThroughputConfig) -> Self {
+        Self { config, last: (0, 0) }
+    }
+
+    /// check returns the processed/received ratio of the window since the last check when it
+    /// is below the threshold, None when the window kept up or received too few updates
+    pub fn check(&mut self, received: u64, processed: u64) -> Option<f64> {
+        let (last_received, last_processed) =
+            std::mem::replace(&mut self.last, (received, processed));
+        let received = received.saturating_sub(last_received);
+        let processed = processed.saturating_sub(last_processed);
+        if received == 0 || received < self.config.min_updates {
+            return None;
+        }
+        let ratio = processed as f64 / received as f64;
+        (ratio < self.config.min_ratio).then_some(ratio)
+    }
+}
+
+/// spawn_throughput_monitor warns every window the pipeline processed too few of the updates
+/// it received, e.g. when `PIPELINE_CHANNEL_BUFFER_SIZE` is too small for the load
+pub fn spawn_throughput_monitor(
+    pipeline: &'static str,
+    metrics: Arc<PipelineMetrics>,
+    config: ThroughputConfig,
+) {
+    tokio::spawn(async move {
+        let mut alarm = ThroughputAlarm::new(config);
+        let mut interval = tokio::time::interval(config.window);
+        loop {
+            interval.tick().await;
+            let stats = metrics.stats();
+            if let Some(ratio) = alarm.check(stats.received, stats.processed) {
+                warn!(
+                    pipeline,
+                    ratio = format!("{ratio:.3}"),
+                    received = stats.received,
+                    processed = stats.processed,
+                    queued = stats.queued,
+                    max_queued = stats.max_queued,
+                    average_process_ms = stats.average_process_ms,
+                    "Pipeline is falling behind the updates it receives"
+                );
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarm(min_updates: u64) -> ThroughputAlarm {
+        ThroughputAlarm::new(ThroughputConfig {
+            window: Duration::from_secs(60),
+            min_ratio: 0.9,
+            min_updates,
+        })
+    }
+
+    #[test]
+    fn test_throughput_alarm_windows() {
+        let mut alarm = alarm(100);
+        // (received, processed) counters at each check and the expected ratio
+        let checks = [
+            ((1000, 1000), None),
+            ((2000, 1950), None),
+            // 1000 received but only 500 processed in the window
+            ((3000, 2450), Some(0.5)),
+            // catching up processes more than received
+            ((4000, 3900), None),
+            // exactly at the threshold
+            ((5000, 4800), None),
+            ((6000, 5600), Some(0.8)),
+        ];
+        for ((received, processed), expected) in checks {
+            assert_eq!(alarm.check(received, processed), expected, "{received} {processed}");
+        }
+    }
+
+    #[test]
+    fn test_throughput_alarm_quiet_window() {
+        let mut alarm = alarm(100);
+        assert_eq!(alarm.check(0, 0), None);
+        // too few updates to judge, the window still moves on
+        assert_eq!(alarm.check(50, 0), None);
+        assert_eq!(alarm.check(250, 0), Some(0.0));
+        // a restarted pipeline resets its counters
+        assert_eq!(alarm.check(10, 10), None);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_metrics_counters() {
+        let metrics = PipelineMetrics::new();
+        metrics.increment_counter(UPDATES_RECEIVED, 3).await.unwrap();
+        metrics.increment_counter(UPDATES_PROCESSED, 2).await.unwrap();
+        metrics.increment_counter(UPDATES_FAILED, 1).await.unwrap();
+        metrics.increment_counter("updates_successful", 1).await.unwrap();
+        metrics.update_gauge(UPDATES_QUEUED, 40.0).await.unwrap();
+        metrics.update_gauge(UPDATES_QUEUED, 10.0).await.unwrap();
+        metrics.record_histogram(UPDATES_PROCESS_TIME_MS, 2.0).await.unwrap();
+        metrics.record_histogram(UPDATES_PROCESS_TIME_MS, 4.0).await.unwrap();
+
+        let stats = metrics.stats();
+        assert_eq!((stats.received, stats.processed, stats.failed), (3, 2, 1));
+        assert_eq!((stats.queued, stats.max_queued), (10, 40));
+        assert_eq!(stats.average_process_ms, 3.0);
+    }
+}
@@ -0,0 +1,152 @@
+//! The settings of the scheduler, read and validated once at startup
+use crate::job::{
+    TokenRefreshConfig, DEFAULT_CANDLES_CLOSED_MAX_PAIRS, DEFAULT_PRUNE_INACTIVE_DAYS,
+    DEFAULT_PRUNE_OLDER_THAN_DAYS,
+};
+use sonar_db::{ConfigError, DatabaseConfig, EnvReader, RedisConfig};
+use sonar_token_metadata::{DEFAULT_REFRESH_MISSING_CONCURRENCY, DEFAULT_REFRESH_MISSING_LIMIT};
+use std::time::Duration;
+
+/// How long the running jobs may take to stop on shutdown when unset
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The database the jobs aggregate and prune
+    pub database: DatabaseConfig,
+    /// The redis of the kv store and the message queue, without it the kv store is kept in
+    /// memory and the candles closed messages are only logged
+    pub redis: Option<RedisConfig>,
+    /// How long the running jobs may take to stop on shutdown
+    pub shutdown_timeout: Duration,
+    /// The port serving the status of the jobs, no status server when None
+    pub status_port: Option<u16>,
+    /// The maximum number of traded pairs listed in a minute candles closed message
+    pub candles_closed_max_pairs: usize,
+    /// Whether the swap events of the inactive tokens are pruned weekly
+    pub prune_inactive_token_events: bool,
+    pub prune_inactive_days: u32,
+    pub prune_older_than_days: u32,
+    /// The settings of the hourly token metadata refresh, no refresh when None
+    pub refresh_token_metadata: Option<TokenRefreshConfig>,
+    /// Whether the tokens with missing metadata are re-resolved nightly
+    pub refresh_missing_token_metadata: bool,
+    pub refresh_missing_limit: usize,
+    pub refresh_missing_concurrency: usize,
+    /// Whether the rolling top tokens stats are precomputed
+    pub refresh_token_window_stats: bool,
+}
+
+impl Config {
+    /// from_env reads the database and the redis settings, `SCHEDULER_SHUTDOWN_TIMEOUT_SECS`,
+    /// `SCHEDULER_STATUS_PORT`, `CANDLES_CLOSED_MAX_PAIRS` and the switches and settings of
+    /// the optional jobs, listing every invalid one
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_reader(EnvReader::from_env())
+    }
+
+    pub fn from_reader(mut env: EnvReader) -> Result<Self, ConfigError> {
+        let refresh_token_metadata = env
+            .flag_or("REFRESH_TOKEN_METADATA", false)
+            .then(|| TokenRefreshConfig::read_env(&mut env));
+        let refresh_missing_concurrency = env.parse_or(
+            "REFRESH_MISSING_TOKEN_METADATA_CONCURRENCY",
+            DEFAULT_REFRESH_MISSING_CONCURRENCY,
+        );
+        env.check(
+            refresh_missing_concurrency > 0,
+            "REFRESH_MISSING_TOKEN_METADATA_CONCURRENCY must not be 0",
+        );
+        let config = Self {
+            database: DatabaseConfig::read_env(&mut env),
+            redis: RedisConfig::read_env_optional(&mut env),
+            shutdown_timeout: env
+                .secs("SCHEDULER_SHUTDOWN_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            status_port: env.parse("SCHEDULER_STATUS_PORT"),
+            candles_closed_max_pairs: env
+                .parse_or("CANDLES_CLOSED_MAX_PAIRS", DEFAULT_CANDLES_CLOSED_MAX_PAIRS),
+            prune_inactive_token_events: env.flag_or("PRUNE_INACTIVE_TOKEN_EVENTS", false),
+            prune_inactive_days: env.parse_or("PRUNE_INACTIVE_DAYS", DEFAULT_PRUNE_INACTIVE_DAYS),
+            prune_older_than_days: env
+                .parse_or("PRUNE_OLDER_THAN_DAYS", DEFAULT_PRUNE_OLDER_THAN_DAYS),
+            refresh_token_metadata,
+            refresh_missing_token_metadata: env.flag_or("REFRESH_MISSING_TOKEN_METADATA", false),
+            refresh_missing_limit: env
+                .parse_or("REFRESH_MISSING_TOKEN_METADATA_LIMIT", DEFAULT_REFRESH_MISSING_LIMIT),
+            refresh_missing_concurrency,
+            refresh_token_window_stats: env.flag_or("REFRESH_TOKEN_WINDOW_STATS", false),
+        };
+        env.finish(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        Config::from_reader(EnvReader::from_vars(vars))
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config = read(&[
+            ("CLICKHOUSE_URL", "http://localhost:8123"),
+            ("REDIS_URL", "redis://localhost:6379"),
+        ])
+        .unwrap();
+        assert_eq!(config.shutdown_timeout, DEFAULT_SHUTDOWN_TIMEOUT);
+        assert_eq!(config.status_port, None);
+        assert_eq!(config.candles_closed_max_pairs, DEFAULT_CANDLES_CLOSED_MAX_PAIRS);
+        assert!(!config.prune_inactive_token_events);
+        assert_eq!(config.prune_inactive_days, DEFAULT_PRUNE_INACTIVE_DAYS);
+        assert_eq!(config.prune_older_than_days, DEFAULT_PRUNE_OLDER_THAN_DAYS);
+        assert!(config.refresh_token_metadata.is_none());
+        assert!(!config.refresh_missing_token_metadata);
+        assert_eq!(config.refresh_missing_limit, DEFAULT_REFRESH_MISSING_LIMIT);
+        assert!(!config.refresh_token_window_stats);
+        assert_eq!(config.redis.map(|redis| redis.url), Some("redis://localhost:6379".to_string()));
+
+        let config = read(&[
+            ("CLICKHOUSE_URL", "http://localhost:8123"),
+            ("REDIS_URL", "redis://localhost:6379"),
+            ("REFRESH_TOKEN_METADATA", "true"),
+            ("TOKEN_REFRESH_LIMIT", "50"),
+            ("SCHEDULER_SHUTDOWN_TIMEOUT_SECS", "5"),
+            ("SCHEDULER_STATUS_PORT", "9090"),
+        ])
+        .unwrap();
+        assert_eq!(config.refresh_token_metadata.map(|refresh| refresh.limit), Some(50));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(5));
+        assert_eq!(config.status_port, Some(9090));
+    }
+
+    #[test]
+    fn test_redis_is_optional() {
+        let config = read(&[("CLICKHOUSE_URL", "http://localhost:8123")]).unwrap();
+        assert!(config.redis.is_none());
+    }
+
+    #[test]
+    fn test_every_problem_is_listed() {
+        let error = read(&[
+            ("REFRESH_TOKEN_METADATA", "true"),
+            ("TOKEN_REFRESH_TIMEOUT_SECS", "0"),
+            ("REFRESH_MISSING_TOKEN_METADATA_CONCURRENCY", "0"),
+            ("PRUNE_INACTIVE_TOKEN_EVENTS", "yes"),
+            ("PRUNE_INACTIVE_DAYS", "two weeks"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![
+                "TOKEN_REFRESH_TIMEOUT_SECS must be a positive number of seconds, got \"0\"",
+                "REFRESH_MISSING_TOKEN_METADATA_CONCURRENCY must not be 0",
+                "CLICKHOUSE_URL must be set",
+                "PRUNE_INACTIVE_TOKEN_EVENTS must be true or false, got \"yes\"",
+                "PRUNE_INACTIVE_DAYS must be a valid u32, got \"two weeks\"",
+            ]
+        );
+    }
+}
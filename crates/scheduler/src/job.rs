@@ -1,8 +1,18 @@
-use crate::configure_job_notifications;
+use crate::{
+    config::Config, configure_job_notifications, notifications::JobNotifier,
+    status::JobStatusRegistry,
+};
 use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, NaiveTime, TimeDelta, Timelike, Utc};
-use sonar_db::{CandlestickInterval, Database};
-use std::sync::Arc;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Timelike, Utc};
+use futures::{Future, StreamExt};
+use sonar_db::{
+    is_not_supported, make_kv_store_with_config, make_message_queue_with_config,
+    models::{tokens::TOKEN_SUPPLY_EPSILON, CandlesClosedEvent, Token},
+    CandlestickInterval, Database, DryRunLog, EnvReader, KvStore, MemoryMessageQueue, MessageQueue,
+    RedisConfig, STORED_CANDLESTICK_INTERVALS,
+};
+use sonar_token_metadata::{refresh_tokens_with_missing_metadata, resolve_token, RpcTokenResolver};
+use std::{sync::Arc, time::Duration};
 use tokio_cron_scheduler::{job::JobId, Job, JobScheduler, JobSchedulerError};
 use tracing::{error, info, instrument, warn};
 
@@ -15,13 +25,62 @@ const DAY_IN_SECONDS: i64 = 86400;
 const MINUTE_SCHEDULE: &str = "0 * * * * *";
 const HOUR_SCHEDULE: &str = "0 0 * * * *";
 const DAY_SCHEDULE: &str = "0 0 0 * * *";
+const WEEK_SCHEDULE: &str = "0 0 1 * * Sun";
+const NIGHT_SCHEDULE: &str = "0 0 3 * * *";
+
+// Schedule intervals, for the job staleness
+const MINUTE_INTERVAL: Duration = Duration::from_secs(MINUTE_IN_SECONDS as u64);
+const HOUR_INTERVAL: Duration = Duration::from_secs(HOUR_IN_SECONDS as u64);
+const DAY_INTERVAL: Duration = Duration::from_secs(DAY_IN_SECONDS as u64);
+const WEEK_INTERVAL: Duration = Duration::from_secs(7 * DAY_IN_SECONDS as u64);
+
+// Pruning defaults
+pub const DEFAULT_PRUNE_INACTIVE_DAYS: u32 = 14;
+pub const DEFAULT_PRUNE_OLDER_THAN_DAYS: u32 = 30;
+
+// Token metadata refresh defaults
+pub const DEFAULT_TOKEN_REFRESH_LIMIT: usize = 200;
+pub const DEFAULT_TOKEN_REFRESH_CONCURRENCY: usize = 8;
+const DEFAULT_TOKEN_REFRESH_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TOKEN_REFRESH_DELAY_MS: u64 = 100;
+
+// Candles closed defaults
+/// The maximum number of traded pairs listed in a minute candles closed message
+pub const DEFAULT_CANDLES_CLOSED_MAX_PAIRS: usize = 500;
+
+/// candles_closed_event returns the message of the `interval` buckets closed in
+/// `[start_ts, end_ts)`
+pub fn candles_closed_event(
+    interval: &CandlestickInterval,
+    start_ts: i64,
+    end_ts: i64,
+    pairs: Option<Vec<String>>,
+) -> CandlesClosedEvent {
+    CandlesClosedEvent {
+        interval: interval.to_string(),
+        bucket_start: start_ts,
+        bucket_end: end_ts,
+        pairs,
+    }
+}
+
+/// Publishes the candles closed message, a failed publish is logged as the candlesticks
+/// are already written
+async fn publish_candles_closed(mq: &MessageQueue, event: &CandlesClosedEvent) {
+    if let Err(e) = mq.publish_candles_closed(event).await {
+        warn!(error = ?e, interval = %event.interval, "Failed to publish candles closed");
+    }
+}
 
-/// Generic function to aggregate candlesticks
-#[instrument(skip(db, get_end_time), fields(interval = ?interval))]
+/// Generic function to aggregate candlesticks, publishing the closed buckets once they are
+/// aggregated, with up to `max_pairs` traded pairs when set
+#[instrument(skip(db, mq, get_end_time), fields(interval = ?interval))]
 async fn aggregate_candlesticks(
     db: Arc<Database>,
+    mq: Arc<MessageQueue>,
     interval: CandlestickInterval,
     time_delta: TimeDelta,
+    max_pairs: Option<usize>,
     get_end_time: impl FnOnce(DateTime<Utc>) -> Result<DateTime<Utc>>,
 ) -> Result<()> {
     let db_clone = db.clone();
@@ -37,19 +96,85 @@ async fn aggregate_candlesticks(
         "Aggregating candlesticks"
     );
 
-    db_clone
+    let rows = db_clone
         .aggregate_into_candlesticks(start_ts, end_ts, interval)
         .await
         .context("Failed to aggregate into candlesticks")?;
+    info!(?rows, "Aggregated candlesticks");
+
+    let pairs = match max_pairs {
+        Some(limit) => match db.get_active_pairs(start_ts as u64, end_ts as u64, limit).await {
+            Ok(pairs) => Some(pairs),
+            Err(e) => {
+                warn!(error = ?e, "Failed to get the traded pairs of the closed candlesticks");
+                None
+            }
+        },
+        None => None,
+    };
+    publish_candles_closed(&mq, &candles_closed_event(&interval, start_ts, end_ts, pairs)).await;
+
+    derive_closed_candlesticks(&db, &mq, &interval, end_ts).await
+}
+
+/// Derives the candlesticks of the buckets rolled up from `source_interval` that close at
+/// `end_ts`, e.g. the 5m, 15m and 30m buckets from the 1m ones, and publishes them
+async fn derive_closed_candlesticks(
+    db: &Database,
+    mq: &MessageQueue,
+    source_interval: &CandlestickInterval,
+    end_ts: i64,
+) -> Result<()> {
+    for (_, interval) in derived_intervals().into_iter().filter(|(source, interval)| {
+        source == source_interval && end_ts % interval.get_seconds() == 0
+    }) {
+        let start_ts = end_ts - interval.get_seconds();
+        let rows = db
+            .aggregate_candlesticks_from_candlesticks(
+                source_interval.clone(),
+                interval.clone(),
+                start_ts,
+                end_ts,
+            )
+            .await
+            .with_context(|| format!("Failed to derive {interval} candlesticks"))?;
+        info!(?rows, %interval, "Derived candlesticks");
+        publish_candles_closed(mq, &candles_closed_event(&interval, start_ts, end_ts, None)).await;
+    }
     Ok(())
 }
 
+/// Derives the candlesticks of every rolled up interval in `[start_ts, end_ts)` from the
+/// stored finer ones, returns the number of candlesticks written, None when unknown
+pub async fn derive_candlesticks(
+    db: Arc<Database>,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Option<u64>> {
+    let tasks = derived_intervals().into_iter().map(|(source_interval, interval)| {
+        let db = db.clone();
+        async move {
+            db.aggregate_candlesticks_from_candlesticks(source_interval, interval, start_ts, end_ts)
+                .await
+        }
+    });
+    let rows = futures::future::try_join_all(tasks)
+        .await
+        .context("Failed to derive candlesticks from candlesticks")?;
+    Ok(rows.into_iter().sum())
+}
+
 /// Aggregate swap events into 1 minute candlesticks
-#[instrument(skip(db))]
-pub async fn aggregate_minute_candlesticks(db: Arc<Database>) -> Result<()> {
+#[instrument(skip(db, mq))]
+pub async fn aggregate_minute_candlesticks(
+    db: Arc<Database>,
+    mq: Arc<MessageQueue>,
+    max_pairs: usize,
+) -> Result<()> {
     let time_delta =
         TimeDelta::new(MINUTE_IN_SECONDS, 0).context("Failed to create one minute time delta")?;
-    aggregate_candlesticks(db, CandlestickInterval::OneMinute, time_delta, |time| {
+    let interval = CandlestickInterval::OneMinute;
+    aggregate_candlesticks(db, mq, interval, time_delta, Some(max_pairs), |time| {
         let end_time = time
             .date_naive()
             .and_time(
@@ -63,11 +188,11 @@ pub async fn aggregate_minute_candlesticks(db: Arc<Database>) -> Result<()> {
 }
 
 /// Aggregate swap events into 1 hour candlesticks
-#[instrument(skip(db))]
-pub async fn aggregate_hour_candlesticks(db: Arc<Database>) -> Result<()> {
+#[instrument(skip(db, mq))]
+pub async fn aggregate_hour_candlesticks(db: Arc<Database>, mq: Arc<MessageQueue>) -> Result<()> {
     let time_delta =
         TimeDelta::new(HOUR_IN_SECONDS, 0).context("Failed to create one hour time delta")?;
-    aggregate_candlesticks(db, CandlestickInterval::OneHour, time_delta, |time| {
+    aggregate_candlesticks(db, mq, CandlestickInterval::OneHour, time_delta, None, |time| {
         let end_time = time
             .date_naive()
             .and_time(
@@ -81,11 +206,11 @@ pub async fn aggregate_hour_candlesticks(db: Arc<Database>) -> Result<()> {
 }
 
 /// Aggregate swap events into 1 day candlesticks
-#[instrument(skip(db))]
-pub async fn aggregate_day_candlesticks(db: Arc<Database>) -> Result<()> {
+#[instrument(skip(db, mq))]
+pub async fn aggregate_day_candlesticks(db: Arc<Database>, mq: Arc<MessageQueue>) -> Result<()> {
     let time_delta =
         TimeDelta::new(DAY_IN_SECONDS, 0).context("Failed to create one day time delta")?;
-    aggregate_candlesticks(db, CandlestickInterval::OneDay, time_delta, |time| {
+    aggregate_candlesticks(db, mq, CandlestickInterval::OneDay, time_delta, None, |time| {
         let end_time = time
             .date_naive()
             .and_time(NaiveTime::from_hms_opt(0, 0, 0).context("Failed to create naive time")?)
@@ -129,14 +254,275 @@ pub async fn aggregate_swap_events_into_candlesticks(db: Arc<Database>) -> Resul
     let results = futures::future::try_join_all(tasks).await.context("Failed to join tasks")?;
     info!("aggregated swap events into candlesticks succeed: {:?}", results);
 
-    db.remove_swap_events(start_ts).await?;
-    info!("removed swap events from partition: {}", start_ts);
+    // the coarser intervals are rolled up again from the candlesticks written above, their
+    // buckets were published by the minute and hour jobs as they closed
+    let rows = derive_candlesticks(db.clone(), start_ts, end_ts).await?;
+    info!(?rows, "derived candlesticks from candlesticks succeed");
+
+    // the rollups read the swap events of the day, so they run before the partition is dropped
+    aggregate_daily_token_rollups(db.clone(), start_time.date_naive()).await?;
+
+    let removed = skip_not_supported(db.remove_swap_events(start_ts).await)?;
+    if removed.is_some() {
+        info!("removed swap events from partition: {}", start_ts);
+    }
     Ok(())
 }
 
-/// Run all scheduled jobs
-#[instrument(skip(sched, db))]
-pub async fn run_jobs(sched: &mut JobScheduler, db: Arc<Database>) -> Result<Vec<JobId>> {
+/// skip_not_supported turns the error of a maintenance operation the database doesn't
+/// implement, e.g. the partitions on Postgres, into None, so that the jobs scheduled for
+/// every backend don't fail and alert on the others
+fn skip_not_supported<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if is_not_supported(&e) => {
+            info!(error = %e, "Skipping the maintenance the database doesn't support");
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Aggregate the swap events of the day into the daily token rollups, rerunning a day
+/// replaces its rollups
+#[instrument(skip(db))]
+pub async fn aggregate_daily_token_rollups(
+    db: Arc<Database>,
+    date: NaiveDate,
+) -> Result<Option<u64>> {
+    let rows = db
+        .aggregate_daily_token_rollups(date)
+        .await
+        .context("Failed to aggregate daily token rollups")?;
+    info!(?rows, %date, "Aggregated daily token rollups");
+    Ok(rows)
+}
+
+/// Returns the stored intervals rolled up from finer candlesticks, with their source interval
+fn derived_intervals() -> Vec<(CandlestickInterval, CandlestickInterval)> {
+    STORED_CANDLESTICK_INTERVALS
+        .into_iter()
+        .filter_map(|interval| Some((interval.get_source_interval()?, interval)))
+        .collect()
+}
+
+/// Prune swap events of tokens without recent swaps
+#[instrument(skip(db))]
+pub async fn prune_inactive_token_events(
+    db: Arc<Database>,
+    inactive_days: u32,
+    older_than_days: u32,
+) -> Result<()> {
+    let Some(rows) =
+        skip_not_supported(db.estimate_prunable_rows(inactive_days, older_than_days).await)
+            .context("Failed to estimate prunable rows")?
+    else {
+        return Ok(());
+    };
+    info!(rows, inactive_days, older_than_days, "Pruning inactive token swap events");
+
+    let deleted = db
+        .prune_inactive_token_events(inactive_days, older_than_days)
+        .await
+        .context("Failed to prune inactive token events")?;
+    info!(deleted, estimated = rows, "Pruned inactive token swap events");
+    Ok(())
+}
+
+/// Precompute the rolling top tokens stats read by get_top_tokens
+#[instrument(skip(db))]
+pub async fn refresh_token_window_stats(db: Arc<Database>) -> Result<()> {
+    let now = Utc::now().timestamp() as u64;
+    skip_not_supported(db.refresh_token_window_stats(now).await)
+        .context("Failed to refresh token window stats")?;
+    Ok(())
+}
+
+/// The settings of the token metadata refresh
+#[derive(Debug, Clone)]
+pub struct TokenRefreshConfig {
+    /// How many of the most swapped tokens of the last hour are refreshed
+    pub limit: usize,
+    /// How many tokens are resolved at a time
+    pub concurrency: usize,
+    /// How long resolving a token may take
+    pub timeout: Duration,
+    /// How long a worker waits after each token, to stay under the RPC rate limit
+    pub delay: Duration,
+}
+
+impl Default for TokenRefreshConfig {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_TOKEN_REFRESH_LIMIT,
+            concurrency: DEFAULT_TOKEN_REFRESH_CONCURRENCY,
+            timeout: Duration::from_secs(DEFAULT_TOKEN_REFRESH_TIMEOUT_SECS),
+            delay: Duration::from_millis(DEFAULT_TOKEN_REFRESH_DELAY_MS),
+        }
+    }
+}
+
+impl TokenRefreshConfig {
+    /// from_env reads `TOKEN_REFRESH_LIMIT`, `TOKEN_REFRESH_CONCURRENCY`,
+    /// `TOKEN_REFRESH_TIMEOUT_SECS` and `TOKEN_REFRESH_DELAY_MS`, falling back to the defaults
+    pub fn from_env() -> Result<Self> {
+        let mut env = EnvReader::from_env();
+        let config = Self::read_env(&mut env);
+        Ok(env.finish(config)?)
+    }
+
+    /// read_env reads the variables of `from_env`, recording the invalid ones
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        Self {
+            limit: env.parse_or("TOKEN_REFRESH_LIMIT", DEFAULT_TOKEN_REFRESH_LIMIT),
+            concurrency: env
+                .parse_or("TOKEN_REFRESH_CONCURRENCY", DEFAULT_TOKEN_REFRESH_CONCURRENCY)
+                .max(1),
+            timeout: env
+                .secs("TOKEN_REFRESH_TIMEOUT_SECS")
+                .unwrap_or(Duration::from_secs(DEFAULT_TOKEN_REFRESH_TIMEOUT_SECS)),
+            delay: Duration::from_millis(
+                env.parse_or("TOKEN_REFRESH_DELAY_MS", DEFAULT_TOKEN_REFRESH_DELAY_MS),
+            ),
+        }
+    }
+}
+
+/// Returns true if the refreshed token differs from the stored one,
+/// the supply only counts when it moved beyond `TOKEN_SUPPLY_EPSILON`
+pub fn token_changed(stored: &Token, refreshed: &Token) -> bool {
+    let supply_delta = (stored.supply - refreshed.supply).abs();
+    supply_delta > TOKEN_SUPPLY_EPSILON * stored.supply.abs().max(1.0)
+        || stored.name != refreshed.name
+        || stored.symbol != refreshed.symbol
+        || stored.uri != refreshed.uri
+        || stored.decimals != refreshed.decimals
+        || stored.update_authority != refreshed.update_authority
+        || stored.is_mutable != refreshed.is_mutable
+}
+
+/// Resolves the mints with at most `config.concurrency` of them in flight,
+/// a mint taking longer than `config.timeout` is resolved to an error
+pub async fn resolve_tokens<F, Fut>(
+    mints: Vec<String>,
+    config: &TokenRefreshConfig,
+    resolve: F,
+) -> Vec<(String, Result<Token>)>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Token>>,
+{
+    futures::stream::iter(mints)
+        .map(|mint| {
+            let resolved = tokio::time::timeout(config.timeout, resolve(mint.clone()));
+            async move {
+                let result = resolved
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("Timed out resolving token {mint}")));
+                tokio::time::sleep(config.delay).await;
+                (mint, result)
+            }
+        })
+        .buffer_unordered(config.concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Refresh the metadata of the tokens swapped the most in the last hour,
+/// rewriting the tokens whose supply or metadata changed
+#[instrument(skip(db, kv_store))]
+pub async fn refresh_token_metadata(
+    db: Arc<Database>,
+    kv_store: Arc<KvStore>,
+    config: TokenRefreshConfig,
+) -> Result<()> {
+    let since = (Utc::now().timestamp() - HOUR_IN_SECONDS) as u64;
+    let mints =
+        db.get_active_tokens(since, config.limit).await.context("Failed to get active tokens")?;
+    info!(tokens = mints.len(), "Refreshing token metadata");
+
+    let resolved = resolve_tokens(mints, &config, |mint| async move {
+        resolve_token(&mint).await.map(|(token, _)| token)
+    })
+    .await;
+
+    let (mut updated, mut failed) = (0, 0);
+    for (mint, result) in resolved {
+        let mut token = match result {
+            Ok(token) => token,
+            Err(e) => {
+                warn!(error = ?e, mint = %mint, "Failed to resolve token metadata");
+                failed += 1;
+                continue;
+            }
+        };
+        if let Some(stored) = db.get_token(&mint).await.context("Failed to get token")? {
+            if !token_changed(&stored, &token) {
+                continue;
+            }
+            // the launchpad and the launch are recorded by the ingestor, the RPC doesn't know them
+            token.launchpad = stored.launchpad;
+            token.launch_dex = stored.launch_dex;
+            token.launch_pool = stored.launch_pool;
+            token.launch_timestamp = stored.launch_timestamp;
+        }
+        db.insert_token(&token).await.context("Failed to insert token")?;
+        // overwrite the cached token, the ingestor reads the kv store first
+        kv_store.set_token(&mint, &token).await.context("Failed to set token in kv store")?;
+        updated += 1;
+    }
+    info!(updated, failed, "Refreshed token metadata");
+    Ok(())
+}
+
+/// Re-resolve the tokens stored with an empty name, symbol or supply
+#[instrument(skip(db, kv_store))]
+pub async fn refresh_missing_token_metadata(
+    db: Arc<Database>,
+    kv_store: Arc<KvStore>,
+    limit: usize,
+    concurrency: usize,
+) -> Result<()> {
+    let summary =
+        refresh_tokens_with_missing_metadata(&db, &kv_store, &RpcTokenResolver, limit, concurrency)
+            .await?;
+    info!(
+        requested = summary.requested,
+        fixed = summary.fixed,
+        failed = summary.failed,
+        skipped = summary.skipped,
+        "Refreshed tokens with missing metadata"
+    );
+    Ok(())
+}
+
+/// Connect the kv store and the message queue of the jobs to redis, without it the kv store
+/// is kept in memory and the published messages are only logged
+pub async fn make_job_stores(redis: Option<&RedisConfig>) -> Result<(KvStore, MessageQueue)> {
+    match redis {
+        Some(redis) => {
+            let kv_store = make_kv_store_with_config(redis).await?;
+            let message_queue = make_message_queue_with_config(redis).await?;
+            Ok((kv_store, message_queue))
+        }
+        None => {
+            warn!("REDIS_URL is not set, the kv store is kept in memory and nothing is published");
+            let message_queue = MemoryMessageQueue::default().with_dry_run(DryRunLog::default());
+            Ok((KvStore::in_memory(), Box::new(message_queue)))
+        }
+    }
+}
+
+/// Run the candlestick jobs and the jobs enabled by `config`, tracking their runs in `status`
+#[instrument(skip(sched, db, kv_store, mq, config, status))]
+pub async fn run_jobs(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    kv_store: Arc<KvStore>,
+    mq: Arc<MessageQueue>,
+    config: &Config,
+    status: &JobStatusRegistry,
+) -> Result<Vec<JobId>> {
     // Configure shutdown handler before starting jobs
     sched.shutdown_on_ctrl_c();
     sched.set_shutdown_handler(Box::new(|| {
@@ -145,7 +531,73 @@ pub async fn run_jobs(sched: &mut JobScheduler, db: Arc<Database>) -> Result<Vec
         })
     }));
 
-    let jobs = vec![aggregate_swap_events_into_candlesticks_job(sched, db.clone()).await?];
+    let notifier = Arc::new(JobNotifier::from_env()?);
+    let mut jobs = vec![
+        create_minute_job(
+            sched,
+            db.clone(),
+            mq.clone(),
+            config.candles_closed_max_pairs,
+            notifier.clone(),
+            status.clone(),
+        )
+        .await?,
+        create_hour_job(sched, db.clone(), mq.clone(), notifier.clone(), status.clone()).await?,
+        create_day_job(sched, db.clone(), mq.clone(), notifier.clone(), status.clone()).await?,
+        aggregate_swap_events_into_candlesticks_job(
+            sched,
+            db.clone(),
+            notifier.clone(),
+            status.clone(),
+        )
+        .await?,
+    ];
+    if config.prune_inactive_token_events {
+        jobs.push(
+            prune_inactive_token_events_job(
+                sched,
+                db.clone(),
+                config.prune_inactive_days,
+                config.prune_older_than_days,
+                notifier.clone(),
+                status.clone(),
+            )
+            .await?,
+        );
+    }
+    if let Some(refresh) = &config.refresh_token_metadata {
+        jobs.push(
+            refresh_token_metadata_job(
+                sched,
+                db.clone(),
+                kv_store.clone(),
+                refresh.clone(),
+                notifier.clone(),
+                status.clone(),
+            )
+            .await?,
+        );
+    }
+    if config.refresh_missing_token_metadata {
+        jobs.push(
+            refresh_missing_token_metadata_job(
+                sched,
+                db.clone(),
+                kv_store.clone(),
+                config.refresh_missing_limit,
+                config.refresh_missing_concurrency,
+                notifier.clone(),
+                status.clone(),
+            )
+            .await?,
+        );
+    }
+    if config.refresh_token_window_stats {
+        jobs.push(
+            refresh_token_window_stats_job(sched, db.clone(), notifier.clone(), status.clone())
+                .await?,
+        );
+    }
 
     if let Err(e) = sched.start().await {
         error!(error = ?e, "Error starting sched");
@@ -155,16 +607,28 @@ pub async fn run_jobs(sched: &mut JobScheduler, db: Arc<Database>) -> Result<Vec
     Ok(jobs)
 }
 
-/// Create and configure the minute candlestick job
-pub async fn create_minute_job(sched: &mut JobScheduler, db: Arc<Database>) -> Result<JobId> {
+/// Create and configure the minute candlestick job, listing up to `max_pairs` traded pairs in
+/// its candles closed messages
+#[instrument(skip(sched, db, mq, notifier, status))]
+pub async fn create_minute_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    mq: Arc<MessageQueue>,
+    max_pairs: usize,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
     let db_clone = db.clone();
     let name = "aggregate minute candlesticks";
     let schedule = MINUTE_SCHEDULE.to_string();
+    status.register(name, MINUTE_INTERVAL);
 
     let job = Job::new_async(&schedule, move |_uuid, _lock| {
-        let db = db_clone.clone();
+        let status = status.clone();
+        let (db, mq, notifier) = (db_clone.clone(), mq.clone(), notifier.clone());
         Box::pin(async move {
-            let result = aggregate_minute_candlesticks(db).await;
+            let result = status.track(name, aggregate_minute_candlesticks(db, mq, max_pairs)).await;
+            notifier.record(name, &result);
             match result {
                 Ok(()) => {
                     info!("Aggregated minutely candlesticks");
@@ -190,17 +654,25 @@ pub async fn create_minute_job(sched: &mut JobScheduler, db: Arc<Database>) -> R
 }
 
 /// Create and configure the hour candlestick job
-#[instrument(skip(sched, db))]
-#[allow(dead_code)]
-pub async fn create_hour_job(sched: &mut JobScheduler, db: Arc<Database>) -> Result<JobId> {
+#[instrument(skip(sched, db, mq, notifier, status))]
+pub async fn create_hour_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    mq: Arc<MessageQueue>,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
     let db_clone = db.clone();
     let name = "aggregate hour candlesticks";
     let schedule = HOUR_SCHEDULE.to_string();
+    status.register(name, HOUR_INTERVAL);
 
     let job = Job::new_async(&schedule, move |_uuid, _lock| {
-        let db = db_clone.clone();
+        let status = status.clone();
+        let (db, mq, notifier) = (db_clone.clone(), mq.clone(), notifier.clone());
         Box::pin(async move {
-            let result = aggregate_hour_candlesticks(db).await;
+            let result = status.track(name, aggregate_hour_candlesticks(db, mq)).await;
+            notifier.record(name, &result);
             match result {
                 Ok(()) => {
                     info!("Aggregated hourly candlesticks");
@@ -226,17 +698,25 @@ pub async fn create_hour_job(sched: &mut JobScheduler, db: Arc<Database>) -> Res
 }
 
 /// Create and configure the day candlestick job
-#[instrument(skip(sched, db))]
-#[allow(dead_code)]
-pub async fn create_day_job(sched: &mut JobScheduler, db: Arc<Database>) -> Result<JobId> {
+#[instrument(skip(sched, db, mq, notifier, status))]
+pub async fn create_day_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    mq: Arc<MessageQueue>,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
     let db_clone = db.clone();
     let name = "aggregate day candlesticks";
     let schedule = DAY_SCHEDULE.to_string();
+    status.register(name, DAY_INTERVAL);
 
     let job = Job::new_async(&schedule, move |_uuid, _lock| {
-        let db = db_clone.clone();
+        let status = status.clone();
+        let (db, mq, notifier) = (db_clone.clone(), mq.clone(), notifier.clone());
         Box::pin(async move {
-            let result = aggregate_day_candlesticks(db).await;
+            let result = status.track(name, aggregate_day_candlesticks(db, mq)).await;
+            notifier.record(name, &result);
             match result {
                 Ok(()) => {
                     info!("Aggregated daily candlesticks");
@@ -261,20 +741,25 @@ pub async fn create_day_job(sched: &mut JobScheduler, db: Arc<Database>) -> Resu
     Ok(guid)
 }
 
-/// Create and configure the day candlestick job
-#[instrument(skip(sched, db))]
+/// Create and configure the daily job reaggregating the day before its swap events are dropped
+#[instrument(skip(sched, db, notifier, status))]
 async fn aggregate_swap_events_into_candlesticks_job(
     sched: &mut JobScheduler,
     db: Arc<Database>,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
 ) -> Result<JobId> {
     let db_clone = db.clone();
     let name = "aggregate swap events into candlesticks";
     let schedule = DAY_SCHEDULE.to_string();
+    status.register(name, DAY_INTERVAL);
 
     let job = Job::new_async(&schedule, move |_uuid, _lock| {
-        let db = db_clone.clone();
+        let status = status.clone();
+        let (db, notifier) = (db_clone.clone(), notifier.clone());
         Box::pin(async move {
-            let result = aggregate_swap_events_into_candlesticks(db).await;
+            let result = status.track(name, aggregate_swap_events_into_candlesticks(db)).await;
+            notifier.record(name, &result);
             match result {
                 Ok(()) => {
                     info!("Aggregated daily candlesticks");
@@ -299,6 +784,182 @@ async fn aggregate_swap_events_into_candlesticks_job(
     Ok(guid)
 }
 
+/// Create and configure the weekly job pruning inactive token swap events
+#[instrument(skip(sched, db, notifier, status))]
+async fn prune_inactive_token_events_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    inactive_days: u32,
+    older_than_days: u32,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
+    let db_clone = db.clone();
+    let name = "prune inactive token events";
+    let schedule = WEEK_SCHEDULE.to_string();
+    status.register(name, WEEK_INTERVAL);
+
+    let job = Job::new_async(&schedule, move |_uuid, _lock| {
+        let status = status.clone();
+        let (db, notifier) = (db_clone.clone(), notifier.clone());
+        Box::pin(async move {
+            let result = status
+                .track(name, prune_inactive_token_events(db, inactive_days, older_than_days))
+                .await;
+            notifier.record(name, &result);
+            match result {
+                Ok(()) => {
+                    info!("Pruned inactive token events");
+                }
+                Err(e) => {
+                    error!(error = ?e, "Failed to prune inactive token events");
+                }
+            }
+        })
+    })?;
+
+    let guid = job.guid();
+    info!(job_id = ?guid, "Created prune inactive token events job");
+
+    // Configure notifications with error handling
+    if let Err(e) = configure_job_notifications(name, sched, job.clone()).await {
+        warn!(error = ?e, job_id = ?guid, "Failed to configure job notifications, but continuing with job creation");
+    }
+
+    // Then add job to sched
+    sched.add(job).await?;
+    Ok(guid)
+}
+
+/// Create and configure the minutely job precomputing the top tokens stats
+#[instrument(skip(sched, db, notifier, status))]
+async fn refresh_token_window_stats_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
+    let name = "refresh token window stats";
+    let schedule = MINUTE_SCHEDULE.to_string();
+    status.register(name, MINUTE_INTERVAL);
+
+    let job = Job::new_async(&schedule, move |_uuid, _lock| {
+        let status = status.clone();
+        let (db, notifier) = (db.clone(), notifier.clone());
+        Box::pin(async move {
+            let result = status.track(name, refresh_token_window_stats(db)).await;
+            notifier.record(name, &result);
+            match result {
+                Ok(()) => {
+                    info!("Refreshed token window stats");
+                }
+                Err(e) => {
+                    error!(error = ?e, "Failed to refresh token window stats");
+                }
+            }
+        })
+    })?;
+
+    let guid = job.guid();
+    info!(job_id = ?guid, "Created refresh token window stats job");
+
+    // Configure notifications with error handling
+    if let Err(e) = configure_job_notifications(name, sched, job.clone()).await {
+        warn!(error = ?e, job_id = ?guid, "Failed to configure job notifications, but continuing with job creation");
+    }
+
+    // Then add job to sched
+    sched.add(job).await?;
+    Ok(guid)
+}
+
+/// Create and configure the hourly job refreshing the metadata of the active tokens
+#[instrument(skip(sched, db, kv_store, notifier, status))]
+async fn refresh_token_metadata_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    kv_store: Arc<KvStore>,
+    config: TokenRefreshConfig,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
+    let name = "refresh token metadata";
+    let schedule = HOUR_SCHEDULE.to_string();
+    status.register(name, HOUR_INTERVAL);
+
+    let job = Job::new_async(&schedule, move |_uuid, _lock| {
+        let status = status.clone();
+        let (db, kv_store, config) = (db.clone(), kv_store.clone(), config.clone());
+        let notifier = notifier.clone();
+        Box::pin(async move {
+            let result = status.track(name, refresh_token_metadata(db, kv_store, config)).await;
+            notifier.record(name, &result);
+            match result {
+                Ok(()) => {
+                    info!("Refreshed token metadata");
+                }
+                Err(e) => {
+                    error!(error = ?e, "Failed to refresh token metadata");
+                }
+            }
+        })
+    })?;
+
+    let guid = job.guid();
+    info!(job_id = ?guid, "Created refresh token metadata job");
+
+    // Configure notifications with error handling
+    if let Err(e) = configure_job_notifications(name, sched, job.clone()).await {
+        warn!(error = ?e, job_id = ?guid, "Failed to configure job notifications, but continuing with job creation");
+    }
+
+    // Then add job to sched
+    sched.add(job).await?;
+    Ok(guid)
+}
+
+/// Create and configure the nightly job re-resolving the tokens with missing metadata
+#[instrument(skip(sched, db, kv_store, notifier, status))]
+async fn refresh_missing_token_metadata_job(
+    sched: &mut JobScheduler,
+    db: Arc<Database>,
+    kv_store: Arc<KvStore>,
+    limit: usize,
+    concurrency: usize,
+    notifier: Arc<JobNotifier>,
+    status: JobStatusRegistry,
+) -> Result<JobId> {
+    let name = "refresh missing token metadata";
+    let schedule = NIGHT_SCHEDULE.to_string();
+    status.register(name, DAY_INTERVAL);
+
+    let job = Job::new_async(&schedule, move |_uuid, _lock| {
+        let status = status.clone();
+        let (db, kv_store, notifier) = (db.clone(), kv_store.clone(), notifier.clone());
+        Box::pin(async move {
+            let result = status
+                .track(name, refresh_missing_token_metadata(db, kv_store, limit, concurrency))
+                .await;
+            notifier.record(name, &result);
+            if let Err(e) = result {
+                error!(error = ?e, "Failed to refresh missing token metadata");
+            }
+        })
+    })?;
+
+    let guid = job.guid();
+    info!(job_id = ?guid, "Created refresh missing token metadata job");
+
+    // Configure notifications with error handling
+    if let Err(e) = configure_job_notifications(name, sched, job.clone()).await {
+        warn!(error = ?e, job_id = ?guid, "Failed to configure job notifications, but continuing with job creation");
+    }
+
+    // Then add job to sched
+    sched.add(job).await?;
+    Ok(guid)
+}
+
 /// Stop all jobs and shutdown the scheduler
 #[instrument(skip(sched))]
 pub async fn stop_jobs(
@@ -384,6 +1045,184 @@ mod job_tests {
         assert_eq!(MINUTE_SCHEDULE, "0 * * * * *");
         assert_eq!(HOUR_SCHEDULE, "0 0 * * * *");
         assert_eq!(DAY_SCHEDULE, "0 0 0 * * *");
+        assert_eq!(WEEK_SCHEDULE, "0 0 1 * * Sun");
+    }
+
+    #[test]
+    fn test_derived_intervals() {
+        assert_eq!(
+            derived_intervals(),
+            vec![
+                (CandlestickInterval::OneMinute, CandlestickInterval::FiveMinutes),
+                (CandlestickInterval::OneMinute, CandlestickInterval::FifteenMinutes),
+                (CandlestickInterval::OneMinute, CandlestickInterval::ThirtyMinutes),
+                (CandlestickInterval::OneHour, CandlestickInterval::FourHours),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_candles_closed_event() {
+        let event = candles_closed_event(
+            &CandlestickInterval::OneMinute,
+            1747958400,
+            1747958460,
+            Some(vec!["pair".to_string()]),
+        );
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "interval": "1m",
+                "bucket_start": 1747958400,
+                "bucket_end": 1747958460,
+                "pairs": ["pair"],
+            })
+        );
+        // the pairs are only listed by the minute job
+        let event =
+            candles_closed_event(&CandlestickInterval::OneDay, 1747872000, 1747958400, None);
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "interval": "1d",
+                "bucket_start": 1747872000,
+                "bucket_end": 1747958400,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_candlesticks_publishes_candles_closed() {
+        use sonar_db::{test_utils::make_swap_event, DatabaseTrait, MemoryDb, MemoryMessageQueue};
+
+        let memory_db = MemoryDb::default();
+        for (pair, signature, timestamp) in [
+            ("pair-b", "sig-1", 1747958401),
+            ("pair-a", "sig-2", 1747958459),
+            ("pair-c", "sig-3", 1747958402),
+            // the next bucket
+            ("pair-d", "sig-4", 1747958460),
+        ] {
+            memory_db
+                .insert_swap_event(&make_swap_event("mint", pair, signature, timestamp, 1.0))
+                .await
+                .unwrap();
+        }
+        let memory_mq = MemoryMessageQueue::default();
+        let db: Arc<Database> = Arc::new(Box::new(memory_db.clone()));
+        let mq: Arc<MessageQueue> = Arc::new(Box::new(memory_mq.clone()));
+        let end_time = |_: DateTime<Utc>| -> Result<DateTime<Utc>> {
+            Ok(DateTime::from_timestamp(1747958460, 0).unwrap())
+        };
+        let minute = TimeDelta::new(MINUTE_IN_SECONDS, 0).unwrap();
+
+        aggregate_candlesticks(
+            db.clone(),
+            mq.clone(),
+            CandlestickInterval::OneMinute,
+            minute,
+            Some(2),
+            end_time,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            memory_mq.candles_closed(),
+            vec![candles_closed_event(
+                &CandlestickInterval::OneMinute,
+                1747958400,
+                1747958460,
+                Some(vec!["pair-a".to_string(), "pair-b".to_string()]),
+            )]
+        );
+
+        // a failed aggregation doesn't publish
+        memory_db.fail_aggregations(1);
+        let result = aggregate_candlesticks(
+            db.clone(),
+            mq.clone(),
+            CandlestickInterval::OneMinute,
+            minute,
+            Some(2),
+            end_time,
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(memory_mq.candles_closed().len(), 1);
+
+        memory_db.fail_aggregations(1);
+        assert!(aggregate_swap_events_into_candlesticks(db.clone()).await.is_err());
+        assert_eq!(memory_mq.candles_closed().len(), 1);
+
+        // the daily job only rolls the derived buckets up again
+        aggregate_swap_events_into_candlesticks(db.clone()).await.unwrap();
+        assert_eq!(memory_mq.candles_closed().len(), 1);
+
+        // the minute closing the derived buckets closes them too
+        let end_of_day = |_: DateTime<Utc>| -> Result<DateTime<Utc>> {
+            Ok(DateTime::from_timestamp(1747958400, 0).unwrap())
+        };
+        let minute_interval = CandlestickInterval::OneMinute;
+        aggregate_candlesticks(db.clone(), mq.clone(), minute_interval, minute, None, end_of_day)
+            .await
+            .unwrap();
+        let hour = TimeDelta::new(HOUR_IN_SECONDS, 0).unwrap();
+        aggregate_candlesticks(db, mq, CandlestickInterval::OneHour, hour, None, end_of_day)
+            .await
+            .unwrap();
+        let closed: Vec<(String, i64)> = memory_mq.candles_closed()[1..]
+            .iter()
+            .map(|event| (event.interval.clone(), event.bucket_end - event.bucket_start))
+            .collect();
+        let expected =
+            [("1m", 60), ("5m", 300), ("15m", 900), ("30m", 1800), ("1h", 3600), ("4h", 14400)];
+        assert_eq!(closed, expected.map(|(interval, seconds)| (interval.to_string(), seconds)));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_daily_token_rollups() {
+        use sonar_db::{test_utils::make_swap_event, DatabaseTrait, MemoryDb};
+
+        let memory_db = MemoryDb::default();
+        let db: Arc<Database> = Arc::new(Box::new(memory_db.clone()));
+        let date = NaiveDate::from_ymd_opt(2025, 5, 23).unwrap();
+        for (signature, timestamp, price) in
+            [("sig-1", 1747958401, 2.0), ("sig-2", 1747958460, 3.0), ("sig-3", 1748044800, 9.0)]
+        {
+            memory_db
+                .insert_swap_event(&make_swap_event("mint", "pair", signature, timestamp, price))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(aggregate_daily_token_rollups(db.clone(), date).await.unwrap(), Some(1));
+        // rerunning the day keeps a single rollup
+        assert_eq!(aggregate_daily_token_rollups(db.clone(), date).await.unwrap(), Some(1));
+        let history = db.get_token_history("mint", date, date.succ_opt().unwrap()).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].open, history[0].close, history[0].trade_count), (2.0, 3.0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_not_supported() {
+        use sonar_db::MemoryDb;
+
+        // the daily job aggregates and keeps the swap events, the other jobs are skipped
+        let db: Arc<Database> = Arc::new(Box::new(MemoryDb::default().without_maintenance()));
+        aggregate_swap_events_into_candlesticks(db.clone()).await.unwrap();
+        refresh_token_window_stats(db.clone()).await.unwrap();
+        prune_inactive_token_events(db, 14, 30).await.unwrap();
+
+        // the other failures still fail the jobs
+        let memory_db = MemoryDb::default().without_maintenance();
+        memory_db.fail_aggregations(1);
+        let db: Arc<Database> = Arc::new(Box::new(memory_db));
+        assert!(aggregate_swap_events_into_candlesticks(db).await.is_err());
+    }
+
+    #[test]
+    fn test_week_schedule() {
+        assert!(Job::new(WEEK_SCHEDULE, |_uuid, _lock| {}).is_ok());
     }
 
     /// Test the time calculation logic used in aggregate functions
@@ -571,4 +1410,69 @@ mod job_tests {
         let result = NaiveTime::from_hms_opt(23, 59, 59);
         assert!(result.is_some(), "Should return Some for valid time");
     }
+
+    fn make_token(mint: &str, supply: f64) -> Token {
+        Token {
+            update_authority: "authority".to_string(),
+            supply,
+            uri: "https://token".to_string(),
+            is_mutable: true,
+            ..sonar_db::test_utils::make_token(mint, "TKN", "Token")
+        }
+    }
+
+    #[test]
+    fn test_token_changed() {
+        let stored = make_token("mint", 1_000_000_000.0);
+        let refreshed = Token { retrieval_timestamp: 1, ..stored.clone() };
+        assert!(!token_changed(&stored, &refreshed));
+        // float noise on the supply is not a change
+        let refreshed = make_token("mint", 1_000_000_000.000_000_1);
+        assert!(!token_changed(&stored, &refreshed));
+        // a burn is
+        let refreshed = make_token("mint", 999_000_000.0);
+        assert!(token_changed(&stored, &refreshed));
+        // as is a mint of a token without supply
+        assert!(token_changed(&make_token("mint", 0.0), &make_token("mint", 0.5)));
+
+        let renamed = Token { name: "Renamed".to_string(), ..stored.clone() };
+        assert!(token_changed(&stored, &renamed));
+        let resymboled = Token { symbol: "NEW".to_string(), ..stored.clone() };
+        assert!(token_changed(&stored, &resymboled));
+        let frozen = Token { is_mutable: false, ..stored.clone() };
+        assert!(token_changed(&stored, &frozen));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_tokens_bounds_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let config = TokenRefreshConfig {
+            concurrency: 3,
+            timeout: Duration::from_millis(200),
+            delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let (in_flight, max_in_flight) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let mints: Vec<String> = (0..10).map(|i| format!("mint-{i}")).collect();
+        let resolved = resolve_tokens(mints, &config, |mint| {
+            let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                // the last mint hangs past the timeout
+                let delay = if mint == "mint-9" { 1_000 } else { 10 };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(make_token(&mint, 1.0))
+            }
+        })
+        .await;
+
+        assert_eq!(resolved.len(), 10);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+        let failed: Vec<&str> =
+            resolved.iter().filter(|(_, r)| r.is_err()).map(|(m, _)| m.as_str()).collect();
+        assert_eq!(failed, vec!["mint-9"]);
+    }
 }
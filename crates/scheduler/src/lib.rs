@@ -1,8 +1,14 @@
+pub mod config;
 pub mod job;
 pub mod notifications;
-pub mod shutdown;
+pub mod status;
 
-pub use notifications::configure_job_notifications;
+pub use config::Config;
+pub use notifications::{
+    configure_job_notifications, AlertSink, JobAlert, JobNotifier, LogAlertSink, WebhookAlertSink,
+};
 pub use shutdown::{shutdown_signal, shutdown_signal_with_handler};
+pub use sonar_db::shutdown;
+pub use status::{serve_status, JobStatus, JobStatusRegistry};
 pub use tokio_cron_scheduler::{JobScheduler, SimpleJobCode, SimpleNotificationCode};
 pub use tracing::{debug, info};
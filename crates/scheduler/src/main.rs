@@ -1,37 +1,60 @@
 use chrono::Utc;
-use sonar_db::make_db_from_env;
+use sonar_db::make_db_with_config;
 use sonar_scheduler::{
-    job::{run_jobs, stop_jobs},
-    shutdown_signal_with_handler,
+    job::{make_job_stores, run_jobs, stop_jobs},
+    serve_status, shutdown_signal_with_handler, Config, JobStatusRegistry,
 };
-use std::{env, sync::Arc};
+use std::sync::Arc;
 use tokio_cron_scheduler::JobScheduler;
-use tracing::info;
+use tracing::{error, info};
 use tracing_otel_extra::init_logging;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
     init_logging(env!("CARGO_PKG_NAME")).expect("Failed to initialize logging");
-    let graceful_shutdown_timeout = tokio::time::Duration::from_secs(10);
+    let config = Config::from_env()?;
 
-    let db = make_db_from_env().await.expect("Failed to make db");
-    let db = Arc::new(db);
+    let db = Arc::new(make_db_with_config(&config.database).await?);
+    let (kv_store, message_queue) = make_job_stores(config.redis.as_ref()).await?;
+
+    let status = JobStatusRegistry::default();
+    if let Some(port) = config.status_port {
+        let status = status.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_status(port, status).await {
+                error!(error = ?e, "Job status server stopped");
+            }
+        });
+    }
 
     let mut scheduler = JobScheduler::new().await.expect("Could not create scheduler");
     info!("Starting jobs");
-    let jobs = run_jobs(&mut scheduler, db).await.expect("Could not run jobs");
+    let jobs = run_jobs(
+        &mut scheduler,
+        db.clone(),
+        Arc::new(kv_store),
+        Arc::new(message_queue),
+        &config,
+        &status,
+    )
+    .await
+    .expect("Could not run jobs");
 
     // Wait for shutdown signal
     shutdown_signal_with_handler(|| async {
         let stop_time = Utc::now();
-        stop_jobs(&mut scheduler, jobs, graceful_shutdown_timeout)
+        stop_jobs(&mut scheduler, jobs, config.shutdown_timeout)
             .await
             .expect("Could not stop jobs");
         info!(
             "Jobs stopped in {:?}ms",
             Utc::now().signed_duration_since(stop_time).num_milliseconds()
         );
+        if let Err(e) = db.close().await {
+            error!(error = ?e, "Failed to close db");
+        }
     })
     .await;
+    Ok(())
 }
@@ -1,10 +1,27 @@
-use anyhow::Result;
-use std::sync::Arc;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio_cron_scheduler::{
     job::{JobId, JobLocked},
     JobScheduler,
 };
-use tracing::{info, warn};
+use tracing::{error, info, warn};
+
+/// The number of consecutive failures of a job raising a repeated failure alert
+pub const DEFAULT_ALERT_REPEATED_FAILURES: u32 = 3;
+/// The timeout of a single webhook delivery
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+/// The pause before the webhook delivery is retried
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// The number of webhook deliveries attempted per alert
+const WEBHOOK_MAX_ATTEMPTS: u32 = 2;
 
 /// Configure job notifications for a job.
 ///
@@ -95,3 +112,304 @@ pub async fn configure_job_notifications(
     sched.add(job_async).await?;
     Ok(one_m_job_guid)
 }
+
+/// A job failure to alert on
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobAlert {
+    pub job: String,
+    pub error: String,
+    /// The number of failures of the job since its last success
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+impl JobAlert {
+    /// message returns the human readable text of the alert
+    pub fn message(&self) -> String {
+        let last_success =
+            self.last_success.map(|ts| ts.to_rfc3339()).unwrap_or_else(|| "never".to_string());
+        let headline = if self.consecutive_failures > 1 {
+            format!("Job `{}` failed {} times in a row", self.job, self.consecutive_failures)
+        } else {
+            format!("Job `{}` failed", self.job)
+        };
+        format!("{headline}\nerror: {}\nlast success: {last_success}", self.error)
+    }
+}
+
+/// A destination of the job failure alerts
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    async fn send(&self, alert: &JobAlert) -> Result<()>;
+}
+
+/// LogAlertSink only logs the alerts, the default when no webhook is configured
+#[derive(Debug, Default)]
+pub struct LogAlertSink;
+
+#[async_trait]
+impl AlertSink for LogAlertSink {
+    async fn send(&self, alert: &JobAlert) -> Result<()> {
+        error!(
+            job = %alert.job,
+            consecutive_failures = alert.consecutive_failures,
+            last_success = ?alert.last_success,
+            error = %alert.error,
+            "Job alert"
+        );
+        Ok(())
+    }
+}
+
+/// WebhookAlertSink posts the alerts to a Slack or Discord compatible webhook
+#[derive(Debug, Clone)]
+pub struct WebhookAlertSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookAlertSink {
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .context("Failed to build webhook client")?;
+        Ok(Self { client, url: url.into() })
+    }
+
+    /// post sends the payload once, non-success statuses are errors
+    async fn post(&self, payload: &serde_json::Value) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(payload)
+            .send()
+            .await
+            .context("Failed to send webhook")?
+            .error_for_status()
+            .context("Webhook rejected the alert")?;
+        Ok(())
+    }
+}
+
+/// webhook_payload returns the body of an alert, `text` is read by Slack and `content` by Discord
+pub fn webhook_payload(alert: &JobAlert) -> serde_json::Value {
+    let message = alert.message();
+    json!({ "text": message, "content": message })
+}
+
+#[async_trait]
+impl AlertSink for WebhookAlertSink {
+    async fn send(&self, alert: &JobAlert) -> Result<()> {
+        let payload = webhook_payload(alert);
+        let mut attempt = 1;
+        loop {
+            match self.post(&payload).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < WEBHOOK_MAX_ATTEMPTS => {
+                    warn!(error = ?e, attempt, job = %alert.job, "Failed to deliver alert, retrying");
+                    attempt += 1;
+                    tokio::time::sleep(WEBHOOK_RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct JobHealth {
+    consecutive_failures: u32,
+    last_success: Option<DateTime<Utc>>,
+}
+
+/// JobNotifier tracks the outcome of the job runs and alerts on failures
+///
+/// The first failure after a success and every `repeated_failures` consecutive failures are
+/// delivered to the sink in the background, so a slow or broken sink never delays a job.
+pub struct JobNotifier {
+    sink: Arc<dyn AlertSink>,
+    repeated_failures: u32,
+    health: Mutex<HashMap<String, JobHealth>>,
+}
+
+impl JobNotifier {
+    pub fn new(sink: Arc<dyn AlertSink>) -> Self {
+        Self {
+            sink,
+            repeated_failures: DEFAULT_ALERT_REPEATED_FAILURES,
+            health: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_repeated_failures(mut self, repeated_failures: u32) -> Self {
+        self.repeated_failures = repeated_failures.max(1);
+        self
+    }
+
+    /// from_env posts to `ALERT_WEBHOOK_URL` when set and only logs otherwise
+    pub fn from_env() -> Result<Self> {
+        let sink: Arc<dyn AlertSink> = match env::var("ALERT_WEBHOOK_URL") {
+            Ok(url) if !url.is_empty() => Arc::new(WebhookAlertSink::new(url)?),
+            _ => Arc::new(LogAlertSink),
+        };
+        let repeated_failures = env::var("ALERT_REPEATED_FAILURES")
+            .ok()
+            .map(|v| v.parse::<u32>().context("ALERT_REPEATED_FAILURES must be a number"))
+            .transpose()?
+            .unwrap_or(DEFAULT_ALERT_REPEATED_FAILURES);
+        Ok(Self::new(sink).with_repeated_failures(repeated_failures))
+    }
+
+    /// job_succeeded resets the failures of a job
+    pub fn job_succeeded(&self, job: &str) {
+        let mut health = self.health.lock().expect("job health lock poisoned");
+        let entry = health.entry(job.to_string()).or_default();
+        entry.consecutive_failures = 0;
+        entry.last_success = Some(Utc::now());
+    }
+
+    /// job_failed records a failure of a job, returns the alert delivered for it if any
+    pub fn job_failed(&self, job: &str, error: &anyhow::Error) -> Option<JobAlert> {
+        let alert = {
+            let mut health = self.health.lock().expect("job health lock poisoned");
+            let entry = health.entry(job.to_string()).or_default();
+            entry.consecutive_failures += 1;
+            let failures = entry.consecutive_failures;
+            if failures != 1 && failures % self.repeated_failures != 0 {
+                return None;
+            }
+            JobAlert {
+                job: job.to_string(),
+                error: format!("{error:#}"),
+                consecutive_failures: failures,
+                last_success: entry.last_success,
+            }
+        };
+
+        let (sink, delivered) = (self.sink.clone(), alert.clone());
+        tokio::spawn(async move {
+            if let Err(e) = sink.send(&delivered).await {
+                warn!(error = ?e, job = %delivered.job, "Failed to deliver job alert");
+            }
+        });
+        Some(alert)
+    }
+
+    /// record reports the result of a job run
+    pub fn record(&self, job: &str, result: &Result<()>) {
+        match result {
+            Ok(()) => self.job_succeeded(job),
+            Err(e) => {
+                self.job_failed(job, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    /// mock_webhook serves a webhook rejecting the first `rejections` requests,
+    /// returns its url and the accepted payloads
+    async fn mock_webhook(rejections: u32) -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let rejected = Arc::new(AtomicU32::new(0));
+        let router = Router::new().route(
+            "/hook",
+            post(
+                move |State(tx): State<mpsc::UnboundedSender<serde_json::Value>>,
+                      Json(body): Json<serde_json::Value>| {
+                    let rejected = rejected.clone();
+                    async move {
+                        if rejected.fetch_add(1, Ordering::SeqCst) < rejections {
+                            return StatusCode::INTERNAL_SERVER_ERROR;
+                        }
+                        tx.send(body).unwrap();
+                        StatusCode::OK
+                    }
+                },
+            )
+            .with_state(tx),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (format!("http://{addr}/hook"), rx)
+    }
+
+    async fn next_payload(
+        rx: &mut mpsc::UnboundedReceiver<serde_json::Value>,
+    ) -> serde_json::Value {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("No alert delivered")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_single_failure_alert() {
+        // the first delivery is rejected and retried
+        let (url, mut rx) = mock_webhook(1).await;
+        let notifier = JobNotifier::new(Arc::new(WebhookAlertSink::new(url).unwrap()));
+
+        notifier.job_failed("refresh token metadata", &anyhow!("rpc unavailable")).unwrap();
+        let payload = next_payload(&mut rx).await;
+        let text = payload["text"].as_str().unwrap();
+        assert_eq!(payload["content"], payload["text"]);
+        assert!(text.starts_with("Job `refresh token metadata` failed\n"));
+        assert!(text.contains("error: rpc unavailable"));
+        assert!(text.contains("last success: never"));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_alert() {
+        let (url, mut rx) = mock_webhook(0).await;
+        let notifier = JobNotifier::new(Arc::new(WebhookAlertSink::new(url).unwrap()))
+            .with_repeated_failures(3);
+
+        notifier.job_succeeded("prune");
+        let alert = notifier.job_failed("prune", &anyhow!("timeout")).unwrap();
+        assert_eq!(alert.consecutive_failures, 1);
+        next_payload(&mut rx).await;
+        assert!(notifier.job_failed("prune", &anyhow!("timeout")).is_none());
+        // another job does not share the failures
+        assert!(notifier.job_failed("other", &anyhow!("boom")).is_some());
+        next_payload(&mut rx).await;
+
+        let alert = notifier.job_failed("prune", &anyhow!("timeout")).unwrap();
+        assert_eq!(alert.consecutive_failures, 3);
+        let payload = next_payload(&mut rx).await;
+        let text = payload["text"].as_str().unwrap();
+        assert!(text.starts_with("Job `prune` failed 3 times in a row\n"));
+        assert!(text.contains("error: timeout"));
+        let last_success = alert.last_success.unwrap().to_rfc3339();
+        assert!(text.contains(&format!("last success: {last_success}")));
+
+        // a success resets the failures
+        notifier.job_succeeded("prune");
+        assert_eq!(
+            notifier.job_failed("prune", &anyhow!("timeout")).unwrap().consecutive_failures,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_undeliverable_alert() {
+        // nothing listens there, the failure is only logged
+        let sink = WebhookAlertSink::new("http://127.0.0.1:9/hook").unwrap();
+        let alert = JobAlert {
+            job: "job".to_string(),
+            error: "error".to_string(),
+            consecutive_failures: 1,
+            last_success: None,
+        };
+        assert!(sink.send(&alert).await.is_err());
+        let notifier = JobNotifier::new(Arc::new(sink));
+        assert!(notifier.job_failed("job", &anyhow!("error")).is_some());
+    }
+}
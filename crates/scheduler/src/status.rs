@@ -0,0 +1,224 @@
+//! The last runs of the scheduler jobs, served on `GET /status` when `SCHEDULER_STATUS_PORT`
+//! is set so that the scheduler pod can be probed without reading its logs
+use anyhow::{Context, Result};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::net::TcpListener;
+use tracing::info;
+
+/// A job is stale once it hasn't succeeded within this many intervals of its schedule
+pub const STALE_INTERVALS: u32 = 3;
+
+/// The runs of a job since the scheduler started
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    /// The interval of the job schedule, in seconds
+    pub interval_secs: u64,
+    pub registered_at: DateTime<Utc>,
+    pub last_start: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    /// The error of the latest failed run, kept after the next successes
+    pub last_error: Option<String>,
+    /// The number of finished runs, failed or not
+    pub run_count: u64,
+    /// The average duration of the finished runs, in milliseconds
+    pub avg_duration_ms: f64,
+    #[serde(skip)]
+    total_duration: Duration,
+}
+
+impl JobStatus {
+    fn new(interval: Duration, registered_at: DateTime<Utc>) -> Self {
+        Self {
+            interval_secs: interval.as_secs(),
+            registered_at,
+            last_start: None,
+            last_success: None,
+            last_error: None,
+            run_count: 0,
+            avg_duration_ms: 0.0,
+            total_duration: Duration::ZERO,
+        }
+    }
+
+    /// is_stale returns whether the job hasn't succeeded within `STALE_INTERVALS` intervals,
+    /// counted from its registration until its first success
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        let since = self.last_success.unwrap_or(self.registered_at);
+        let max_age = self.interval_secs.saturating_mul(STALE_INTERVALS as u64);
+        now.signed_duration_since(since).num_seconds() > max_age as i64
+    }
+}
+
+/// The status of the registered jobs, shared by the job closures and the status server
+#[derive(Debug, Clone, Default)]
+pub struct JobStatusRegistry {
+    jobs: Arc<RwLock<HashMap<&'static str, JobStatus>>>,
+}
+
+impl JobStatusRegistry {
+    /// register tracks a job run every `interval`, the runs of unregistered jobs are ignored
+    pub fn register(&self, job: &'static str, interval: Duration) {
+        self.register_at(job, interval, Utc::now())
+    }
+
+    /// track runs a job, recording its start, its duration and its result
+    pub async fn track<F>(&self, job: &'static str, run: F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        self.job_started(job, Utc::now());
+        let started = Instant::now();
+        let result = run.await;
+        self.job_finished(job, &result, started.elapsed(), Utc::now());
+        result
+    }
+
+    /// snapshot returns the status of every registered job
+    pub fn snapshot(&self) -> HashMap<&'static str, JobStatus> {
+        self.jobs.read().expect("job status lock poisoned").clone()
+    }
+
+    /// stale_jobs returns the sorted jobs which haven't succeeded within `STALE_INTERVALS`
+    /// intervals at `now`
+    pub fn stale_jobs(&self, now: DateTime<Utc>) -> Vec<&'static str> {
+        let jobs = self.jobs.read().expect("job status lock poisoned");
+        let mut stale: Vec<_> =
+            jobs.iter().filter(|(_, status)| status.is_stale(now)).map(|(job, _)| *job).collect();
+        stale.sort_unstable();
+        stale
+    }
+
+    fn register_at(&self, job: &'static str, interval: Duration, at: DateTime<Utc>) {
+        let mut jobs = self.jobs.write().expect("job status lock poisoned");
+        jobs.insert(job, JobStatus::new(interval, at));
+    }
+
+    fn job_started(&self, job: &'static str, at: DateTime<Utc>) {
+        let mut jobs = self.jobs.write().expect("job status lock poisoned");
+        if let Some(status) = jobs.get_mut(job) {
+            status.last_start = Some(at);
+        }
+    }
+
+    fn job_finished(
+        &self,
+        job: &'static str,
+        result: &Result<()>,
+        duration: Duration,
+        at: DateTime<Utc>,
+    ) {
+        let mut jobs = self.jobs.write().expect("job status lock poisoned");
+        let Some(status) = jobs.get_mut(job) else {
+            return;
+        };
+        match result {
+            Ok(()) => status.last_success = Some(at),
+            Err(e) => status.last_error = Some(format!("{e:#}")),
+        }
+        status.run_count += 1;
+        status.total_duration += duration;
+        status.avg_duration_ms =
+            status.total_duration.as_secs_f64() * 1_000.0 / status.run_count as f64;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    /// Whether every job succeeded within `STALE_INTERVALS` intervals
+    healthy: bool,
+    stale: Vec<&'static str>,
+    jobs: HashMap<&'static str, JobStatus>,
+}
+
+/// get_status returns the status of the jobs, with a 503 if any is stale so that it can
+/// serve as a liveness probe
+async fn get_status(State(registry): State<JobStatusRegistry>) -> impl IntoResponse {
+    let stale = registry.stale_jobs(Utc::now());
+    let code = if stale.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let response = StatusResponse { healthy: stale.is_empty(), stale, jobs: registry.snapshot() };
+    (code, Json(response))
+}
+
+pub fn status_router(registry: JobStatusRegistry) -> Router {
+    Router::new().route("/status", get(get_status)).with_state(registry)
+}
+
+/// serve_status serves the status of the jobs on `port` until the process exits
+pub async fn serve_status(port: u16, registry: JobStatusRegistry) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("Failed to bind the status port {port}"))?;
+    info!(port, "Serving the job status");
+    axum::serve(listener, status_router(registry)).await.context("Failed to serve the job status")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use chrono::TimeDelta;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_staleness() {
+        let registry = JobStatusRegistry::default();
+        let start = Utc::now();
+        registry.register_at("minute", MINUTE, start);
+        registry.register_at("day", Duration::from_secs(86_400), start);
+
+        // a job which never ran is stale 3 intervals after its registration
+        assert!(registry.stale_jobs(start + TimeDelta::seconds(180)).is_empty());
+        assert_eq!(registry.stale_jobs(start + TimeDelta::seconds(181)), vec!["minute"]);
+
+        // a success postpones the staleness, a failure doesn't
+        let run_at = start + TimeDelta::seconds(120);
+        registry.job_finished("minute", &Ok(()), Duration::ZERO, run_at);
+        registry.job_finished("minute", &Err(anyhow!("boom")), Duration::ZERO, run_at);
+        assert!(registry.stale_jobs(run_at + TimeDelta::seconds(180)).is_empty());
+        let later = run_at + TimeDelta::days(3) + TimeDelta::seconds(1);
+        assert_eq!(registry.stale_jobs(later), vec!["day", "minute"]);
+    }
+
+    #[tokio::test]
+    async fn test_registry_records_runs() {
+        let registry = JobStatusRegistry::default();
+        registry.register("aggregate", MINUTE);
+
+        registry.track("aggregate", async { Ok(()) }).await.unwrap();
+        let failed = registry.track("aggregate", async { Err(anyhow!("db unavailable")) }).await;
+        assert!(failed.is_err());
+        registry.job_finished("aggregate", &Ok(()), Duration::from_millis(30), Utc::now());
+
+        let status = &registry.snapshot()["aggregate"];
+        assert_eq!(status.run_count, 3);
+        assert_eq!(status.last_error.as_deref(), Some("db unavailable"));
+        assert!(status.last_success >= status.last_start);
+        assert!(status.avg_duration_ms >= 10.0);
+        assert_eq!(status.interval_secs, 60);
+
+        // the runs of unregistered jobs are ignored
+        registry.track("unknown", async { Ok(()) }).await.unwrap();
+        assert_eq!(registry.snapshot().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_status_unavailable_when_stale() {
+        let registry = JobStatusRegistry::default();
+        registry.register("minute", MINUTE);
+        let response = get_status(State(registry.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        registry.register_at("hour", Duration::from_secs(3_600), Utc::now() - TimeDelta::hours(4));
+        let response = get_status(State(registry)).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -5,10 +5,13 @@
 //! ## Features
 //!
 //! - Real-time SOL price updates via WebSocket connection
-//! - Fallback to REST API when WebSocket is unavailable
+//! - Fallback to REST API when WebSocket is unavailable, stopped for a cooldown after
+//!   consecutive failures or as soon as Binance refuses the IP
 //! - Global price cache accessible throughout the application
 //! - Optional integration with message queue for publishing price updates
 //! - Optional integration with key-value store for persistence
+//! - Optional persistence of the trades to the database, at most one per second, for the
+//!   SOLUSD candlesticks
 //!
 //! ## Usage
 //!
@@ -18,7 +21,7 @@
 //! #[tokio::main]
 //! async fn main() {
 //!     // Create a new price cache instance
-//!     let price_cache = SolPriceCache::new(None, None);
+//!     let price_cache = SolPriceCache::new(None, None).expect("Failed to create the price cache");
 //!
 //!     // Start the price stream in a background task
 //!     let price_cache_clone = price_cache.clone();
@@ -42,14 +45,24 @@
 //! [piotrostr/listen](https://github.com/piotrostr/listen/blob/main/listen-data/src/sol_price_stream.rs)
 //! with modifications to fit the sonar architecture.
 
-use crate::{SolPriceCacheTrait, SOL_PRICE_CACHE};
-use anyhow::Result;
+use crate::{
+    cache::{persist_sol_price_snapshot, restore_sol_price_snapshot},
+    SolPriceCacheTrait, SOL_PRICE_CACHE,
+};
+use anyhow::{Context, Result};
 use chrono::Utc;
 use futures::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
-use sonar_db::{KvStore, MessageQueue, Trade};
-use std::sync::Arc;
+use sonar_db::{Database, KvStore, MessageQueue, ReferencePrice, Trade, SOL_USD_PAIR};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 use tokio::net::TcpStream;
 use tokio::sync::{Mutex, RwLock};
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
@@ -57,9 +70,21 @@ use tokio_tungstenite::{
     connect_async, tungstenite::error::Error as WsError, tungstenite::protocol::Message,
     MaybeTlsStream, WebSocketStream,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use url::Url;
 
+/// The REST requests failing in a row before the circuit opens
+const REST_FAILURE_THRESHOLD: u32 = 3;
+/// The first cooldown of an open circuit, doubled each time a probe fails
+const REST_BASE_COOLDOWN: Duration = Duration::from_secs(30);
+const REST_MAX_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+/// The cooldown once Binance blocks the region (451), bans (418) or rate limits (429) the IP
+const REST_BLOCKED_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+/// The reads served without a request while the circuit is open are logged at most this often
+const REST_CIRCUIT_LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// The REST request is abandoned after this long, a hung request would hold back every reader
+const REST_TIMEOUT: Duration = Duration::from_secs(10);
+
 // Type aliases to reduce complexity
 type WebSocketStreamType = WebSocketStream<MaybeTlsStream<TcpStream>>;
 type SplitSinkType = SplitSink<WebSocketStreamType, Message>;
@@ -75,31 +100,220 @@ struct BinancePrice {
     price: String,
 }
 
+/// A REST response with an error status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestStatusError(pub u16);
+
+impl RestStatusError {
+    /// is_blocked returns true if Binance refuses the IP, retrying soon won't help
+    pub fn is_blocked(&self) -> bool {
+        matches!(self.0, 418 | 429 | 451)
+    }
+}
+
+impl fmt::Display for RestStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "REST request failed with status {}", self.0)
+    }
+}
+
+impl std::error::Error for RestStatusError {}
+
+/// Fetches the SOL price when the stream hasn't set one yet
+#[async_trait::async_trait]
+pub trait RestPriceFetcher: Send + Sync {
+    async fn fetch_price(&self) -> Result<f64>;
+}
+
+/// Fetches the SOL/USDT ticker from the Binance REST API
+#[derive(Debug, Clone)]
+pub struct BinanceRestFetcher {
+    client: reqwest::Client,
+}
+
+impl BinanceRestFetcher {
+    pub fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REST_TIMEOUT)
+            .build()
+            .context("Failed to build the Binance REST client")?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait::async_trait]
+impl RestPriceFetcher for BinanceRestFetcher {
+    async fn fetch_price(&self) -> Result<f64> {
+        let rest_url = "https://api.binance.com/api/v3/ticker/price?symbol=SOLUSDT";
+        let response = self.client.get(rest_url).send().await?;
+        if !response.status().is_success() {
+            return Err(RestStatusError(response.status().as_u16()).into());
+        }
+        let price_data: BinancePrice = response.json().await?;
+        price_data.price.parse::<f64>().map_err(Into::into)
+    }
+}
+
+/// Stops the REST requests for a cooldown after consecutive failures, the first request
+/// after the cooldown probes whether Binance answers again
+#[derive(Debug, Default)]
+struct RestCircuit {
+    failures: u32,
+    /// The cooldown of the latest opening, zero while closed
+    cooldown: Duration,
+    open_until: Option<Instant>,
+    /// The reads served without a request since the last log
+    rejected: u64,
+    last_log: Option<Instant>,
+}
+
+impl RestCircuit {
+    /// try_request returns whether a request may be sent at `now`, a probe once the cooldown
+    /// is over, in which case the circuit stays open for the other readers until it answers
+    fn try_request(&mut self, now: Instant) -> bool {
+        let Some(open_until) = self.open_until else {
+            return true;
+        };
+        if now >= open_until {
+            self.open_until = Some(now + self.cooldown);
+            return true;
+        }
+        self.rejected += 1;
+        if self.last_log.is_none_or(|last| now.duration_since(last) >= REST_CIRCUIT_LOG_INTERVAL) {
+            warn!(
+                rejected = self.rejected,
+                retry_in_secs = open_until.duration_since(now).as_secs(),
+                "Binance REST circuit open, serving the cached SOL price"
+            );
+            self.rejected = 0;
+            self.last_log = Some(now);
+        }
+        false
+    }
+
+    fn record_success(&mut self) {
+        if self.open_until.is_some() {
+            info!("Binance REST circuit closed");
+        }
+        *self = Self::default();
+    }
+
+    /// record_failure opens the circuit after `REST_FAILURE_THRESHOLD` failures in a row or at
+    /// once when Binance refuses the IP, a failed probe doubles the cooldown
+    fn record_failure(&mut self, now: Instant, error: &anyhow::Error) {
+        self.failures += 1;
+        let blocked = error.downcast_ref::<RestStatusError>().is_some_and(|e| e.is_blocked());
+        let cooldown = if blocked {
+            REST_BLOCKED_COOLDOWN
+        } else if self.failures < REST_FAILURE_THRESHOLD {
+            return;
+        } else if self.cooldown.is_zero() {
+            REST_BASE_COOLDOWN
+        } else {
+            (self.cooldown * 2).min(REST_MAX_COOLDOWN)
+        };
+        // a rate limit during a long cooldown doesn't shorten it
+        self.cooldown = cooldown.max(self.cooldown);
+        self.open_until = Some(now + self.cooldown);
+        warn!(
+            failures = self.failures,
+            cooldown_secs = self.cooldown.as_secs(),
+            %error,
+            "Binance REST circuit opened"
+        );
+    }
+
+    fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|open_until| now < open_until)
+    }
+}
+
+/// try_claim_second claims the second of a persisted trade, returns false if the second or a
+/// later one was already claimed
+fn try_claim_second(last_secs: &AtomicU64, now_secs: u64) -> bool {
+    let last = last_secs.load(Ordering::Relaxed);
+    if now_secs <= last {
+        return false;
+    }
+    last_secs.compare_exchange(last, now_secs, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+}
+
 #[derive(Clone)]
 pub struct SolPriceCache {
     price: Arc<RwLock<f64>>,
     message_queue: Option<Arc<MessageQueue>>,
     kv_store: Option<Arc<KvStore>>,
+    rest_fetcher: Arc<dyn RestPriceFetcher>,
+    /// Shared by the clones, so that every reader backs off together
+    rest_circuit: Arc<std::sync::Mutex<RestCircuit>>,
+    /// Held by the reader fetching the REST price, the others wait for its price
+    rest_flight: Arc<Mutex<()>>,
+    /// Persists the trades as the SOLUSD reference prices when set
+    db: Option<Arc<Database>>,
+    /// The second of the latest persisted trade, shared by the clones publishing the trades
+    last_persisted_secs: Arc<AtomicU64>,
 }
 
 impl SolPriceCache {
-    pub fn new(kv_store: Option<Arc<KvStore>>, message_queue: Option<Arc<MessageQueue>>) -> Self {
-        Self {
+    pub fn new(
+        kv_store: Option<Arc<KvStore>>,
+        message_queue: Option<Arc<MessageQueue>>,
+    ) -> Result<Self> {
+        Ok(Self {
             price: SOL_PRICE_CACHE.clone(), // Use the global price cache
             message_queue,
             kv_store,
+            rest_fetcher: Arc::new(BinanceRestFetcher::new()?),
+            rest_circuit: Arc::default(),
+            rest_flight: Arc::default(),
+            db: None,
+            last_persisted_secs: Arc::default(),
+        })
+    }
+
+    /// with_db persists the published trades to the db, at most one per second
+    pub fn with_db(mut self, db: Arc<Database>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// with_rest_fetcher replaces the Binance REST API used while no price is set
+    pub fn with_rest_fetcher(mut self, rest_fetcher: Arc<dyn RestPriceFetcher>) -> Self {
+        self.rest_fetcher = rest_fetcher;
+        self
+    }
+
+    /// is_rest_circuit_open returns true while the REST requests are stopped
+    pub fn is_rest_circuit_open(&self) -> bool {
+        self.rest_circuit.lock().expect("rest circuit lock poisoned").is_open(Instant::now())
+    }
+
+    /**
+     * Create a new price cache and hydrate the global price from the kv store snapshot.
+     *
+     * The snapshot is only used while the global price is still 0.0 and it is fresh enough.
+     */
+    pub async fn new_with_snapshot(
+        kv_store: Option<Arc<KvStore>>,
+        message_queue: Option<Arc<MessageQueue>>,
+    ) -> Result<Self> {
+        let cache = Self::new(kv_store, message_queue)?;
+        if let Some(kv_store) = &cache.kv_store {
+            restore_sol_price_snapshot(kv_store).await;
         }
+        Ok(cache)
     }
 
     /**
-     * Publish the trade to the message queue and the KV store.
+     * Publish the trade to the message queue and the KV store, and persist it to the db
+     * unless a trade of the same second already was.
      *
      * @param new_price - The new price to publish.
      * @return Result<()> - The result of the operation.
      */
     async fn publish_trade(&self, new_price: f64) -> Result<()> {
         let trade: Trade = Trade {
-            pair: "SOLUSD".to_string(),
+            pair: SOL_USD_PAIR.to_string(),
             pubkey: crate::constants::WSOL_MINT_KEY_STR.to_string(),
             price: new_price,
             market_cap: 0.0,
@@ -110,17 +324,39 @@ impl SolPriceCache {
             timestamp: Utc::now().timestamp() as u64,
             is_buy: false,
             is_pump: false,
+            is_wash: false,
+            fee_amount: 0.0,
+            fee_mint: String::new(),
+            pct_of_supply: 0.0,
+            fee_payer: String::new(),
+            symbol: String::new(),
+            decimals: 0,
             owner: "binance".to_string(),
             signers: vec![],
             signature: "binance_websocket".to_string(),
         };
-        if let Some(kv_store) = &self.kv_store {
-            kv_store.insert_price(&trade).await?;
-        }
-        if let Some(mq) = &self.message_queue {
-            mq.publish_trade(&trade).await?;
-        }
-        Ok(())
+        // the cache, the queue and the db are written independently, the first error is
+        // returned once all of them were tried
+        let cached = match &self.kv_store {
+            Some(kv_store) => kv_store.insert_price(&trade).await,
+            None => Ok(()),
+        };
+        let published = match &self.message_queue {
+            Some(mq) => mq.publish_trade(&trade).await,
+            None => Ok(()),
+        };
+        let persisted = match &self.db {
+            Some(db) if try_claim_second(&self.last_persisted_secs, trade.timestamp) => {
+                let price = ReferencePrice {
+                    pair: trade.pair.clone(),
+                    price: trade.price,
+                    timestamp: trade.timestamp,
+                };
+                db.insert_reference_price(&price).await.map_err(Into::into)
+            }
+            _ => Ok(()),
+        };
+        cached.and(published).and(persisted)
     }
 
     /**
@@ -130,43 +366,55 @@ impl SolPriceCache {
      */
     pub async fn set_price(&self, price: f64) {
         *self.price.write().await = price;
+        if let Some(kv_store) = &self.kv_store {
+            persist_sol_price_snapshot(kv_store, price).await;
+        }
     }
 
     /**
      * Get the price from the cache.
      *
-     * If the price is 0.0, fetch the price from the REST API.
+     * If the price is 0.0, fetch the price from the REST API unless its circuit is open.
      *
      * @return f64 - The current price.
      */
     pub async fn get_price(&self) -> f64 {
         let current_price = *self.price.read().await;
         if current_price == 0.0 {
-            match self.fetch_rest_price().await {
-                Ok(rest_price) => {
-                    *self.price.write().await = rest_price;
-                    rest_price
-                }
-                Err(e) => {
-                    error!("Failed to fetch REST price: {}", e);
-                    current_price
-                }
-            }
+            self.fetch_rest_price().await.unwrap_or(current_price)
         } else {
             current_price
         }
     }
 
     /**
-     * Fetch the price from the REST API.
+     * Fetch the price from the REST API through the circuit breaker and set it. A single
+     * request is in flight at once, the readers waiting for it read the price it set.
      *
-     * @return Result<f64> - The price.
+     * @return Option<f64> - The price, None if the request failed or the circuit is open.
      */
-    async fn fetch_rest_price(&self) -> Result<f64> {
-        let rest_url = "https://api.binance.com/api/v3/ticker/price?symbol=SOLUSDT";
-        let response = reqwest::get(rest_url).await?;
-        let price_data: BinancePrice = response.json().await?;
-        price_data.price.parse::<f64>().map_err(Into::into)
+    async fn fetch_rest_price(&self) -> Option<f64> {
+        let _flight = self.rest_flight.lock().await;
+        let current_price = *self.price.read().await;
+        if current_price != 0.0 {
+            return Some(current_price);
+        }
+        let lock_circuit = || self.rest_circuit.lock().expect("rest circuit lock poisoned");
+        if !lock_circuit().try_request(Instant::now()) {
+            return None;
+        }
+        match self.rest_fetcher.fetch_price().await {
+            Ok(price) => {
+                lock_circuit().record_success();
+                self.set_price(price).await;
+                Some(price)
+            }
+            Err(e) => {
+                error!("Failed to fetch REST price: {}", e);
+                lock_circuit().record_failure(Instant::now(), &e);
+                None
+            }
+        }
     }
 
     pub async fn start_price_stream(&self) -> Result<()> {
@@ -368,27 +616,12 @@ impl SolPriceCacheTrait for SolPriceCache {
     }
 
     async fn set_price(&self, price: f64) -> Result<()> {
-        *self.price.write().await = price;
+        SolPriceCache::set_price(self, price).await;
         Ok(())
     }
 
     async fn get_price(&self) -> f64 {
-        let current_price = *self.price.read().await;
-        if current_price == 0.0 {
-            match self.fetch_rest_price().await {
-                Ok(price) => {
-                    // Update the cache with the fetched price
-                    let _ = self.set_price(price).await;
-                    price
-                }
-                Err(e) => {
-                    error!("Failed to fetch SOL price from REST API: {}", e);
-                    0.0
-                }
-            }
-        } else {
-            current_price
-        }
+        SolPriceCache::get_price(self).await
     }
 
     async fn start_price_stream(&self) -> Result<()> {
@@ -400,13 +633,17 @@ impl SolPriceCacheTrait for SolPriceCache {
 mod tests {
     use super::*;
     use crate::get_sol_price;
+    use sonar_db::{
+        models::events::{CandlesClosedEvent, NewPoolEvent, SystemAlert, TokenGraduatedEvent},
+        CandlestickInterval, DatabaseTrait, MessageQueueTrait, OutlierPolicy, TradeV2,
+    };
     use tokio::time::{sleep, Duration};
     use tracing_otel_extra::init_logging;
 
     #[tokio::test]
     async fn test_sol_price_cache() {
         init_logging("binance_sol_price").expect("Failed to initialize logging");
-        let price_cache = SolPriceCache::new(None, None);
+        let price_cache = SolPriceCache::new(None, None).unwrap();
         let price_cache_clone = price_cache.clone();
 
         // Spawn the price stream in a separate task
@@ -429,7 +666,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_rest_fallback() {
-        let price_cache = SolPriceCache::new(None, None);
+        let price_cache = SolPriceCache::new(None, None).unwrap();
 
         // Test initial state (should trigger REST fallback)
         let price = price_cache.get_price().await;
@@ -440,4 +677,198 @@ mod tests {
         let cached_price = *price_cache.price.read().await;
         assert_eq!(price, cached_price, "Price should be cached after REST call");
     }
+
+    /// Answers the REST requests in order, failing once they are exhausted
+    #[derive(Default)]
+    struct MockRestFetcher {
+        responses: std::sync::Mutex<std::collections::VecDeque<Result<f64>>>,
+        calls: std::sync::atomic::AtomicUsize,
+        delay: Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl RestPriceFetcher for MockRestFetcher {
+        async fn fetch_price(&self) -> Result<f64> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            sleep(self.delay).await;
+            let response = self.responses.lock().unwrap().pop_front();
+            response.unwrap_or_else(|| Err(anyhow::anyhow!("connection refused")))
+        }
+    }
+
+    #[test]
+    fn test_rest_circuit_backoff() {
+        let error = anyhow::anyhow!("connection refused");
+        let mut circuit = RestCircuit::default();
+        let start = Instant::now();
+        for _ in 0..REST_FAILURE_THRESHOLD - 1 {
+            assert!(circuit.try_request(start));
+            circuit.record_failure(start, &error);
+        }
+        assert!(!circuit.is_open(start));
+        circuit.record_failure(start, &error);
+        assert!(circuit.is_open(start));
+        assert!(!circuit.try_request(start + Duration::from_secs(29)));
+
+        // a single probe once the cooldown is over, its failure doubles the cooldown
+        let probe_at = start + REST_BASE_COOLDOWN;
+        assert!(circuit.try_request(probe_at));
+        assert!(!circuit.try_request(probe_at));
+        circuit.record_failure(probe_at, &error);
+        assert_eq!(circuit.cooldown, 2 * REST_BASE_COOLDOWN);
+        for _ in 0..10 {
+            circuit.record_failure(probe_at, &error);
+        }
+        assert_eq!(circuit.cooldown, REST_MAX_COOLDOWN);
+
+        circuit.record_success();
+        assert!(!circuit.is_open(probe_at));
+        assert!(circuit.try_request(probe_at));
+
+        // a ban opens the circuit at once for the long cooldown
+        circuit.record_failure(probe_at, &RestStatusError(451).into());
+        assert!(circuit.is_open(probe_at + REST_MAX_COOLDOWN));
+        assert!(!circuit.is_open(probe_at + REST_BLOCKED_COOLDOWN));
+        assert!(!RestStatusError(500).is_blocked());
+    }
+
+    #[tokio::test]
+    async fn test_rest_circuit_stops_requests() {
+        let fetcher = Arc::new(MockRestFetcher::default());
+        let price_cache = SolPriceCache {
+            // a price of its own, the global one is set by the other tests
+            price: Arc::new(RwLock::new(0.0)),
+            ..SolPriceCache::new(None, None).unwrap().with_rest_fetcher(fetcher.clone())
+        };
+        let reader = price_cache.clone();
+        for _ in 0..2 * REST_FAILURE_THRESHOLD {
+            assert_eq!(reader.get_price().await, 0.0);
+        }
+        // the clones share the circuit, no request is sent while it is open
+        let calls = || fetcher.calls.load(std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(calls(), REST_FAILURE_THRESHOLD as usize);
+        assert!(price_cache.is_rest_circuit_open());
+
+        // the probe after the cooldown recovers the price and closes the circuit
+        fetcher.responses.lock().unwrap().push_back(Ok(150.0));
+        price_cache.rest_circuit.lock().unwrap().open_until = Some(Instant::now());
+        assert_eq!(price_cache.get_price().await, 150.0);
+        assert_eq!(calls(), REST_FAILURE_THRESHOLD as usize + 1);
+        assert!(!reader.is_rest_circuit_open());
+        assert_eq!(reader.get_price().await, 150.0);
+    }
+
+    #[tokio::test]
+    async fn test_rest_fetch_is_single_flight() {
+        let fetcher =
+            Arc::new(MockRestFetcher { delay: Duration::from_millis(50), ..Default::default() });
+        fetcher.responses.lock().unwrap().push_back(Ok(150.0));
+        let price_cache = SolPriceCache {
+            price: Arc::new(RwLock::new(0.0)),
+            ..SolPriceCache::new(None, None).unwrap().with_rest_fetcher(fetcher.clone())
+        };
+
+        // the readers arriving while the request is in flight wait for its price
+        let readers = (0..5).map(|_| {
+            let reader = price_cache.clone();
+            tokio::spawn(async move { reader.get_price().await })
+        });
+        for price in futures::future::join_all(readers).await {
+            assert_eq!(price.unwrap(), 150.0);
+        }
+        assert_eq!(fetcher.calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_persist_throttle() {
+        let last = AtomicU64::new(0);
+        assert!(try_claim_second(&last, 1_000));
+        assert!(!try_claim_second(&last, 1_000));
+        // a trade published late doesn't take an earlier second
+        assert!(!try_claim_second(&last, 999));
+        assert!(try_claim_second(&last, 1_001));
+    }
+
+    #[tokio::test]
+    async fn test_publish_trade_persists_reference_price() {
+        let memory_db = sonar_db::MemoryDb::default();
+        let db: Arc<Database> = Arc::new(Box::new(memory_db.clone()));
+        let price_cache = SolPriceCache::new(None, None).unwrap().with_db(db);
+        price_cache.publish_trade(150.0).await.unwrap();
+        let prices = memory_db.reference_prices();
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[0].pair, SOL_USD_PAIR);
+        assert_eq!(prices[0].price, 150.0);
+
+        // the trades of a claimed second are not persisted again
+        price_cache.last_persisted_secs.store(prices[0].timestamp + 60, Ordering::Relaxed);
+        price_cache.publish_trade(151.0).await.unwrap();
+        assert_eq!(memory_db.reference_prices().len(), 1);
+
+        // the SOLUSD candlesticks are read from the persisted trades
+        let candlesticks = memory_db
+            .get_candlesticks_by_pair(
+                SOL_USD_PAIR,
+                None,
+                &CandlestickInterval::OneMinute,
+                &OutlierPolicy::default(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(candlesticks.len(), 1);
+        assert_eq!(candlesticks[0].close, 150.0);
+        assert_eq!(candlesticks[0].volume, 0.0);
+    }
+
+    /// FailingQueue fails every publish
+    struct FailingQueue;
+
+    #[async_trait::async_trait]
+    impl MessageQueueTrait for FailingQueue {
+        async fn new(_url: &str) -> Result<Self> {
+            Ok(Self)
+        }
+
+        async fn publish_trade(&self, _trade: &Trade) -> Result<()> {
+            anyhow::bail!("Failed to publish")
+        }
+
+        async fn publish_new_pool(&self, _new_pool: &NewPoolEvent) -> Result<()> {
+            anyhow::bail!("Failed to publish")
+        }
+
+        async fn publish_system_alert(&self, _alert: &SystemAlert) -> Result<()> {
+            anyhow::bail!("Failed to publish")
+        }
+
+        async fn publish_token_graduated(&self, _graduation: &TokenGraduatedEvent) -> Result<()> {
+            anyhow::bail!("Failed to publish")
+        }
+
+        async fn publish_large_trade(&self, _trade: &TradeV2) -> Result<()> {
+            anyhow::bail!("Failed to publish")
+        }
+
+        async fn publish_candles_closed(&self, _candles: &CandlesClosedEvent) -> Result<()> {
+            anyhow::bail!("Failed to publish")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_trade_persists_despite_the_queue() {
+        let memory_db = sonar_db::MemoryDb::default();
+        let db: Arc<Database> = Arc::new(Box::new(memory_db.clone()));
+        let message_queue: Arc<MessageQueue> = Arc::new(Box::new(FailingQueue));
+        let kv_store = Arc::new(KvStore::in_memory());
+        let price_cache =
+            SolPriceCache::new(Some(kv_store.clone()), Some(message_queue)).unwrap().with_db(db);
+
+        assert!(price_cache.publish_trade(150.0).await.is_err());
+        assert_eq!(memory_db.reference_prices().len(), 1);
+        let trade = kv_store.get_price(crate::constants::WSOL_MINT_KEY_STR).await.unwrap();
+        assert_eq!(trade.unwrap().price, 150.0);
+    }
 }
@@ -1,9 +1,15 @@
+use crate::constants::SOL_PRICE_SNAPSHOT_KEY;
 use anyhow::Result;
 use async_trait::async_trait;
 use chrono::Utc;
-use sonar_db::{KvStore, MessageQueue, Trade};
-use std::sync::{Arc, LazyLock};
+use serde::{Deserialize, Serialize};
+use sonar_db::{KvStore, MessageQueue, Trade, SOL_USD_PAIR};
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, LazyLock,
+};
 use tokio::sync::RwLock;
+use tracing::{error, info};
 
 // Change the global cache to be just the price without Redis connections
 pub static SOL_PRICE_CACHE: LazyLock<Arc<RwLock<f64>>> =
@@ -18,6 +24,106 @@ pub async fn set_sol_price(price: f64) {
     *SOL_PRICE_CACHE.write().await = price;
 }
 
+/// Snapshots older than this are ignored on restore
+static SOL_PRICE_SNAPSHOT_MAX_AGE_SECS: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("SOL_PRICE_SNAPSHOT_MAX_AGE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300)
+});
+
+/// Snapshots are persisted at most once per this many milliseconds
+const SOL_PRICE_SNAPSHOT_INTERVAL_MS: i64 = 1000;
+/// Snapshots expire from the kv store after a day
+const SOL_PRICE_SNAPSHOT_TTL_SECS: u64 = 60 * 60 * 24;
+
+static LAST_SOL_PRICE_SNAPSHOT_MS: AtomicI64 = AtomicI64::new(0);
+
+/// The latest SOL price and the unix timestamp (seconds) it was observed at
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SolPriceSnapshot {
+    pub price: f64,
+    pub timestamp: i64,
+}
+
+impl SolPriceSnapshot {
+    /// is_fresh returns true if the snapshot holds a price no older than `max_age` seconds
+    pub fn is_fresh(&self, now: i64, max_age: i64) -> bool {
+        self.price > 0.0 && now - self.timestamp <= max_age
+    }
+}
+
+/// Claims the next snapshot slot, returns false if one was taken within the last interval
+fn try_claim_snapshot(last_ms: &AtomicI64, now_ms: i64) -> bool {
+    let last = last_ms.load(Ordering::Relaxed);
+    if now_ms - last < SOL_PRICE_SNAPSHOT_INTERVAL_MS {
+        return false;
+    }
+    last_ms.compare_exchange(last, now_ms, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+}
+
+/// Persists the price to the kv store, throttled to once per second across all caches
+pub async fn persist_sol_price_snapshot(kv_store: &KvStore, price: f64) {
+    let now = Utc::now();
+    if price <= 0.0 || !try_claim_snapshot(&LAST_SOL_PRICE_SNAPSHOT_MS, now.timestamp_millis()) {
+        return;
+    }
+    let snapshot = SolPriceSnapshot { price, timestamp: now.timestamp() };
+    if let Err(e) =
+        kv_store.set_ex(SOL_PRICE_SNAPSHOT_KEY, &snapshot, SOL_PRICE_SNAPSHOT_TTL_SECS).await
+    {
+        error!("Failed to persist SOL price snapshot: {}", e);
+    }
+}
+
+async fn restore_snapshot(
+    kv_store: &KvStore,
+    key: &str,
+    price: &RwLock<f64>,
+    max_age: i64,
+) -> Result<Option<f64>> {
+    if *price.read().await != 0.0 {
+        return Ok(None);
+    }
+    let Some(snapshot) = kv_store.get::<SolPriceSnapshot>(key).await? else {
+        return Ok(None);
+    };
+    if !snapshot.is_fresh(Utc::now().timestamp(), max_age) {
+        info!("Ignoring stale SOL price snapshot from {}", snapshot.timestamp);
+        return Ok(None);
+    }
+    let mut price = price.write().await;
+    // another task may have set a live price while we were reading the snapshot
+    if *price != 0.0 {
+        return Ok(None);
+    }
+    *price = snapshot.price;
+    Ok(Some(snapshot.price))
+}
+
+/// Hydrates the global cache from the persisted snapshot if it is still empty,
+/// returns the restored price
+pub async fn restore_sol_price_snapshot(kv_store: &KvStore) -> Option<f64> {
+    match restore_snapshot(
+        kv_store,
+        SOL_PRICE_SNAPSHOT_KEY,
+        &SOL_PRICE_CACHE,
+        *SOL_PRICE_SNAPSHOT_MAX_AGE_SECS,
+    )
+    .await
+    {
+        Ok(Some(price)) => {
+            info!("Restored SOL price {} from snapshot", price);
+            Some(price)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("Failed to restore SOL price snapshot: {}", e);
+            None
+        }
+    }
+}
+
 #[async_trait]
 pub trait SolPriceCacheTrait {
     fn get_name(&self) -> String;
@@ -33,7 +139,7 @@ pub trait SolPriceCacheTrait {
 
     async fn publish_trade(&self, new_price: f64) -> Result<()> {
         let trade = Trade {
-            pair: "SOLUSD".to_string(),
+            pair: SOL_USD_PAIR.to_string(),
             pubkey: crate::constants::WSOL_MINT_KEY_STR.to_string(),
             price: new_price,
             market_cap: 0.0,
@@ -44,6 +150,13 @@ pub trait SolPriceCacheTrait {
             timestamp: Utc::now().timestamp() as u64,
             is_buy: false,
             is_pump: false,
+            is_wash: false,
+            fee_amount: 0.0,
+            fee_mint: String::new(),
+            pct_of_supply: 0.0,
+            fee_payer: String::new(),
+            symbol: String::new(),
+            decimals: 0,
             owner: self.get_owner(),
             signers: vec![],
             signature: self.get_signature(),
@@ -57,6 +170,12 @@ pub trait SolPriceCacheTrait {
         Ok(())
     }
 
+    /// Hydrates the price from the kv store snapshot if the cache is still empty
+    async fn restore_snapshot(&self) -> Option<f64> {
+        let kv_store = self.get_kv_store()?;
+        restore_sol_price_snapshot(&kv_store).await
+    }
+
     async fn get_price_at_timestamp(&self, timestamp: u64) -> Option<f64> {
         if let Some(kv_store) = &self.get_kv_store() {
             if let Ok(price) =
@@ -68,3 +187,53 @@ pub trait SolPriceCacheTrait {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::make_kv_store;
+
+    #[test]
+    fn test_snapshot_freshness() {
+        let snapshot = SolPriceSnapshot { price: 150.0, timestamp: 1_000 };
+        assert!(snapshot.is_fresh(1_100, 300));
+        assert!(!snapshot.is_fresh(1_400, 300));
+        assert!(!SolPriceSnapshot { price: 0.0, timestamp: 1_000 }.is_fresh(1_000, 300));
+    }
+
+    #[test]
+    fn test_snapshot_throttle() {
+        let last = AtomicI64::new(0);
+        assert!(try_claim_snapshot(&last, 10_000));
+        assert!(!try_claim_snapshot(&last, 10_500));
+        assert!(try_claim_snapshot(&last, 11_000));
+    }
+
+    #[tokio::test]
+    async fn test_restore_snapshot() {
+        let kv_store = make_kv_store("redis://localhost:6379").await.unwrap();
+        let now = Utc::now().timestamp();
+
+        let fresh_key = "test:sol_price:snapshot:fresh";
+        let fresh = SolPriceSnapshot { price: 150.0, timestamp: now - 10 };
+        kv_store.set_ex(fresh_key, &fresh, 60).await.unwrap();
+        let price = RwLock::new(0.0);
+        let restored = restore_snapshot(&kv_store, fresh_key, &price, 300).await.unwrap();
+        assert_eq!(restored, Some(150.0));
+        assert_eq!(*price.read().await, 150.0);
+
+        let stale_key = "test:sol_price:snapshot:stale";
+        let stale = SolPriceSnapshot { price: 120.0, timestamp: now - 3_600 };
+        kv_store.set_ex(stale_key, &stale, 60).await.unwrap();
+        let price = RwLock::new(0.0);
+        let restored = restore_snapshot(&kv_store, stale_key, &price, 300).await.unwrap();
+        assert_eq!(restored, None);
+        assert_eq!(*price.read().await, 0.0);
+
+        // a live price is never overwritten by a snapshot
+        let price = RwLock::new(160.0);
+        let restored = restore_snapshot(&kv_store, fresh_key, &price, 300).await.unwrap();
+        assert_eq!(restored, None);
+        assert_eq!(*price.read().await, 160.0);
+    }
+}
@@ -19,7 +19,7 @@
 //! #[tokio::main]
 //! async fn main() {
 //!     // Create a new price cache instance
-//!     let price_cache = SolPriceCache::new(None, None);
+//!     let price_cache = SolPriceCache::new(None, None)?;
 //!
 //!     // Start the price stream
 //!     price_cache.start_price_stream().await?;
@@ -33,7 +33,7 @@
 //! ```
 
 use crate::{
-    cache::SOL_PRICE_CACHE,
+    cache::{persist_sol_price_snapshot, restore_sol_price_snapshot, SOL_PRICE_CACHE},
     constants::{MARKET_PROGRAM_ID, WSOL_MINT_KEY_STR},
     SolPriceCacheTrait,
 };
@@ -50,7 +50,7 @@ use solana_client::{
 };
 use solana_commitment_config::CommitmentConfig;
 use solana_pubkey::Pubkey;
-use sonar_db::{KvStore, MessageQueue, Trade};
+use sonar_db::{KvStore, MessageQueue, Trade, SOL_USD_PAIR};
 use std::{str::FromStr, sync::Arc};
 use tokio::sync::{mpsc, RwLock};
 use tracing::{error, info};
@@ -141,13 +141,32 @@ pub struct SolPriceCache {
 }
 
 impl SolPriceCache {
-    /// Create a new SOL price cache
-    pub fn new(kv_store: Option<Arc<KvStore>>, message_queue: Option<Arc<MessageQueue>>) -> Self {
-        Self { price: SOL_PRICE_CACHE.clone(), message_queue, kv_store }
+    /// Create a new SOL price cache, fallible like the Binance one building its REST client
+    pub fn new(
+        kv_store: Option<Arc<KvStore>>,
+        message_queue: Option<Arc<MessageQueue>>,
+    ) -> Result<Self> {
+        Ok(Self { price: SOL_PRICE_CACHE.clone(), message_queue, kv_store })
+    }
+
+    /// Create a new SOL price cache, hydrating the global price from the kv store snapshot
+    /// while it is still empty
+    pub async fn new_with_snapshot(
+        kv_store: Option<Arc<KvStore>>,
+        message_queue: Option<Arc<MessageQueue>>,
+    ) -> Result<Self> {
+        let cache = Self::new(kv_store, message_queue)?;
+        if let Some(kv_store) = &cache.kv_store {
+            restore_sol_price_snapshot(kv_store).await;
+        }
+        Ok(cache)
     }
 
     pub async fn set_price(&self, price: f64) {
         *self.price.write().await = price;
+        if let Some(kv_store) = &self.kv_store {
+            persist_sol_price_snapshot(kv_store, price).await;
+        }
     }
 
     pub async fn get_price(&self) -> f64 {
@@ -173,7 +192,7 @@ impl SolPriceCache {
 
     async fn publish_trade(&self, new_price: f64) -> Result<()> {
         let trade = Trade {
-            pair: "SOLUSD".to_string(),
+            pair: SOL_USD_PAIR.to_string(),
             pubkey: WSOL_MINT_KEY_STR.to_string(),
             price: new_price,
             market_cap: 0.0,
@@ -184,6 +203,13 @@ impl SolPriceCache {
             timestamp: Utc::now().timestamp() as u64,
             is_buy: false,
             is_pump: false,
+            is_wash: false,
+            fee_amount: 0.0,
+            fee_mint: String::new(),
+            pct_of_supply: 0.0,
+            fee_payer: String::new(),
+            symbol: String::new(),
+            decimals: 0,
             owner: "raydium_clmm".to_string(),
             signers: vec![],
             signature: "raydium_clmm_stream".to_string(),
@@ -226,7 +252,7 @@ impl SolPriceCacheTrait for SolPriceCache {
     }
 
     async fn set_price(&self, price: f64) -> Result<()> {
-        *self.price.write().await = price;
+        SolPriceCache::set_price(self, price).await;
 
         // Publish the trade if we have the necessary components
         if self.kv_store.is_some() || self.message_queue.is_some() {
@@ -240,7 +266,7 @@ impl SolPriceCacheTrait for SolPriceCache {
 
     async fn start_price_stream(&self) -> Result<()> {
         // Start the CLMM stream in a background task
-        let cache = SolPriceCache::new(self.kv_store.clone(), self.message_queue.clone());
+        let cache = SolPriceCache::new(self.kv_store.clone(), self.message_queue.clone())?;
 
         tokio::spawn(async move {
             if let Err(e) = cache.start_price_stream().await {
@@ -4,5 +4,7 @@ pub const SOLANNA: &str = "solana";
 pub const WSOL_MINT_KEY_STR: &str = "So11111111111111111111111111111111111111112";
 /// The program id of the cpmm program
 pub const CPMM_PROGRAM_ID: &str = "8sLbNZoA1cfnvMJLPfp98ZLAnFSYCFApfJKMbiXNLwxj";
+/// The kv key of the latest SOL price snapshot
+pub const SOL_PRICE_SNAPSHOT_KEY: &str = "solana:sol_price:snapshot";
 /// The program id of the cpmm program
 pub const MARKET_PROGRAM_ID: &str = "8sLbNZoA1cfnvMJLPfp98ZLAnFSYCFApfJKMbiXNLwxj";
@@ -12,4 +12,7 @@ pub use clmm::SolPriceCache;
 #[cfg(feature = "binance")]
 pub use binance::SolPriceCache;
 
-pub use cache::{get_sol_price, SolPriceCacheTrait, SOL_PRICE_CACHE};
+pub use cache::{
+    get_sol_price, persist_sol_price_snapshot, restore_sol_price_snapshot, SolPriceCacheTrait,
+    SolPriceSnapshot, SOL_PRICE_CACHE,
+};
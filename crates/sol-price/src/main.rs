@@ -14,7 +14,7 @@ async fn main() {
 
     info!("Starting Sol Price Binance");
 
-    let sol_price_cache = SolPriceCache::new(None, None);
+    let sol_price_cache = SolPriceCache::new(None, None).expect("Failed to create the price cache");
     let price_cache_clone = sol_price_cache.clone();
 
     // Start price stream in background task
@@ -1,42 +1,282 @@
 use crate::{
-    db::DatabaseTrait,
+    ck::{
+        options::{make_client, make_http_client, ClickhouseOptions},
+        recent_tokens::{RecentTokens, DEFAULT_RECENT_TOKENS_CAPACITY},
+        spill::{ClickhouseBatchWriter, InsertRetryConfig, ResilientInserter, SpillStats},
+        CANDLESTICKS_SORTING_KEY, EXPECTED_PROJECTIONS, EXPECTED_TABLES,
+    },
+    db::{day_bounds, get_prices_retrying, paginate_trades, slot_time_window, DatabaseTrait},
+    errors::{classified, http_classified, not_initialized, StorageError},
     models::{
-        candlesticks::Candlestick,
-        swap::{SwapEvent, Trade},
-        tokens::{TokenDailyStat, TokenPrice, TokenSearch, TokenStat, TopToken},
+        candlesticks::{
+            is_reference_pair, merge_candlesticks, Candlestick, LatestCandlestick, OutlierPolicy,
+            ReferencePrice, DEFAULT_CANDLESTICK_LIMIT, TOKEN_CANDLESTICK_PAIR,
+        },
+        dexes::Dexes,
+        pairs::{FeeStat, Pair, PairDetail, PairInfo},
+        swap::{SwapEvent, Trade, TradeCursor, TradeFilter},
+        tokens::{
+            PriceSource, SortOrder, TokenDailyRollup, TokenDailyStat, TokenLaunch,
+            TokenLaunchRecord, TokenPrice, TokenSearch, TokenSearchResult, TokenStat, TopToken,
+            TopTokensPage, TopTokensSort, MAJOR_MINTS,
+        },
+        wallet::{WalletActivity, WalletLabel},
         Token,
     },
+    search::{normalize_query, rank_search_results, FUZZY_FALLBACK_THRESHOLD},
     CandlestickInterval,
 };
 use anyhow::{Context, Result};
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDate, Utc};
 use clickhouse::{inserter::Inserter, Client};
-use futures::future;
-use std::{sync::Arc, time::Duration};
+use futures::stream::BoxStream;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::sync::RwLock;
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
+
+/// Skips the swap events stored with an infinite or NaN price, which would poison the aggregates
+const FINITE_PRICE: &str = "isFinite(price)";
+/// Skips the candlesticks aggregated from such swap events
+const FINITE_CANDLESTICK: &str =
+    "isFinite(open) AND isFinite(high) AND isFinite(low) AND isFinite(close)";
+/// The number of tokens deleted per `ALTER TABLE ... DELETE` mutation
+const PRUNE_BATCH_SIZE: usize = 500;
+const DAY_IN_SECONDS: u64 = 86400;
+/// The top tokens windows precomputed into token_window_stats, in seconds
+pub const TOKEN_STATS_WINDOWS: [u64; 2] = [3600, DAY_IN_SECONDS];
+/// How far a requested window may be from a precomputed one, and a refresh from now
+const TOKEN_STATS_TOLERANCE_SECONDS: u64 = 120;
+/// The approximate distinct signers of the last 24h before `current_ts`, in the same pass
+/// as the other aggregates: the array combinator counts the signers without joining them
+const UNIQUE_TRADERS_24H: &str = "\
+    uniqCombinedArrayIf(64)(signers, timestamp >= current_ts - 86400) AS unique_traders_24h, \
+    uniqCombinedArrayIf(64)(signers, is_buy AND timestamp >= current_ts - 86400) \
+    AS unique_buyers_24h, \
+    uniqCombinedArrayIf(64)(signers, NOT is_buy AND timestamp >= current_ts - 86400) \
+    AS unique_sellers_24h";
+/// The buy and sell split of the volume and turnover of the swap events of a bucket
+const SIDE_VOLUMES: &str = "sumIf(base_amount, is_buy) AS buy_volume, \
+    sumIf(base_amount, NOT is_buy) AS sell_volume, \
+    sumIf(swap_amount, is_buy) AS buy_turnover, \
+    sumIf(swap_amount, NOT is_buy) AS sell_turnover";
+/// The buy and sell split of stored candlesticks rolled up, the rows written before the split
+/// read as zeros
+const CANDLESTICK_SIDE_VOLUMES: &str = "sum(buy_volume) AS buy_volume, \
+    sum(sell_volume) AS sell_volume, \
+    sum(buy_turnover) AS buy_turnover, \
+    sum(sell_turnover) AS sell_turnover";
+/// The approximate distinct signers, buyers and sellers of the swaps of a top tokens window
+const UNIQUE_TRADERS: &str = "uniqCombinedArray(64)(signers) AS unique_traders, \
+    uniqCombinedArrayIf(64)(signers, is_buy) AS unique_buyers, \
+    uniqCombinedArrayIf(64)(signers, NOT is_buy) AS unique_sellers";
+/// The top tokens of the `latest_prices`, `volumes` and `price_changes` relations
+/// The token without volume or price change rows, NULL with `join_use_nulls`, counts as 0
+const TOP_TOKENS_SELECT: &str = r#"
+            SELECT
+                lp.pubkey,
+                lp.price,
+                lp.market_cap,
+                coalesce(v.volume, 0) AS volume,
+                coalesce(v.turnover, 0) AS turnover,
+                coalesce(pc.price_change, 0) AS price_change,
+                coalesce(v.unique_traders, 0) AS unique_traders,
+                coalesce(v.unique_buyers, 0) AS unique_buyers,
+                coalesce(v.unique_sellers, 0) AS unique_sellers
+            FROM latest_prices lp
+            LEFT JOIN volumes v ON lp.pubkey = v.pubkey
+            LEFT JOIN price_changes pc ON lp.pubkey = pc.pubkey
+            "#;
+
+/// Where the top tokens are computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TopTokensSource {
+    /// Scans the swap events since `start_time`
+    Live { start_time: u64 },
+    /// Reads the token_window_stats refresh of `window` seconds at `computed_at`
+    Precomputed { window: u64, computed_at: u64 },
+}
+
+impl TopTokensSource {
+    /// relations returns the `latest_prices`, `volumes` and `price_changes` relations
+    fn relations(&self, exclude_wash: bool) -> String {
+        match *self {
+            TopTokensSource::Live { start_time } => {
+                let wash_condition = if exclude_wash { "AND NOT is_wash" } else { "" };
+                format!(
+                    r#"
+            WITH 
+                latest_prices AS (
+                    SELECT
+                        pubkey,
+                        price,
+                        market_cap,
+                        timestamp,
+                        is_pump
+                    FROM swap_events
+                    WHERE timestamp >= {start_time} AND {FINITE_PRICE} {wash_condition}
+                    ORDER BY timestamp DESC
+                    LIMIT 1 BY pubkey
+                ),
+                volumes AS (
+                    SELECT
+                        pubkey,
+                        sum(base_amount) as volume,
+                        sum(swap_amount) as turnover,
+                        {UNIQUE_TRADERS}
+                    FROM swap_events
+                    WHERE timestamp >= {start_time} AND {FINITE_PRICE} {wash_condition}
+                    GROUP BY pubkey
+                ),
+                price_changes AS (
+                    SELECT
+                        pubkey,
+                        (argMax(price, timestamp) - argMin(price, timestamp)) / argMin(price, timestamp) * 100 as price_change
+                    FROM swap_events
+                    WHERE timestamp >= {start_time} AND {FINITE_PRICE} {wash_condition}
+                    GROUP BY pubkey
+                )"#
+                )
+            }
+            TopTokensSource::Precomputed { window, computed_at } => format!(
+                r#"
+            WITH
+                stats AS (
+                    SELECT *
+                    FROM token_window_stats
+                    WHERE window_secs = {window} AND exclude_wash = {exclude_wash}
+                        AND computed_at = {computed_at}
+                ),
+                latest_prices AS (SELECT pubkey, price, market_cap, is_pump FROM stats),
+                volumes AS (
+                    SELECT pubkey, volume, turnover, unique_traders, unique_buyers, unique_sellers
+                    FROM stats
+                ),
+                price_changes AS (
+                    SELECT
+                        pubkey,
+                        (last_price - first_price) / first_price * 100 as price_change
+                    FROM stats
+                )"#
+            ),
+        }
+    }
+}
+/// The number of trades fetched per page when streaming trades
+const TRADE_PAGE_SIZE: usize = 10_000;
+/// How long the read replica is skipped after a connection failure
+const READ_REPLICA_COOLDOWN_SECONDS: i64 = 30;
+// the token-level candlesticks leave the pair out of their grouping set, it takes the default
+// of the column, the empty sentinel pair
+const _: () = assert!(TOKEN_CANDLESTICK_PAIR.is_empty());
+/// The number of search results returned
+const SEARCH_LIMIT: usize = 10;
+/// The number of candidates fetched per search pass before ranking
+const SEARCH_CANDIDATES: usize = 100;
+/// The columns of a search candidate
+const SEARCH_COLUMNS: &str = "token, name, symbol, decimals, supply, latest_price, price_24h, \
+    tx_count_24h, volume_24h, turnover_24h, \
+    token IN (SELECT token FROM tokens WHERE verified) AS verified";
+/// How often spilled swap events are replayed once the database is reachable again
+const SPILL_RECOVERY_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a written token keeps identical retrievals from being written again
+pub const DEFAULT_TOKEN_DEDUPE_WINDOW: Duration = Duration::from_secs(3600);
+/// The columns two rows of a token must share to be duplicates, all but the retrieval time
+const TOKEN_IDENTITY_COLUMNS: &str = "token, is_nft, update_authority, name, symbol, decimals, \
+    supply, uri, seller_fee_basis_points, primary_sale_happened, is_mutable, launchpad, \
+    graduated_at, graduation_pool, launch_dex, launch_pool, launch_timestamp, verified";
+
+/// The rows of the tokens table before and after collapsing the duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct TokenDedupe {
+    pub rows: u64,
+    pub unique_rows: u64,
+}
+
+/// A value bound to a `?` placeholder of the trade queries
+#[derive(Debug, Clone)]
+enum TradeBind {
+    Str(String),
+    U64(u64),
+    F64(f64),
+}
+
+/// is_connection_error returns true if the error is caused by an unreachable endpoint
+pub(crate) fn is_connection_error(error: &clickhouse::error::Error) -> bool {
+    matches!(error, clickhouse::error::Error::Network(_) | clickhouse::error::Error::TimedOut)
+}
+
+/// summary_written_rows reads the written rows of an `X-ClickHouse-Summary` header, e.g.
+/// `{"read_rows":"10","written_rows":"4",...}`, whose counts are strings
+fn summary_written_rows(summary: &str) -> Option<u64> {
+    let summary: serde_json::Value = serde_json::from_str(summary).ok()?;
+    summary.get("written_rows")?.as_str()?.parse().ok()
+}
+
+/// days_ago returns the unix timestamp `days` days before `now`
+fn days_ago(now: u64, days: u32) -> u64 {
+    now.saturating_sub(days as u64 * DAY_IN_SECONDS)
+}
 
 pub struct ClickhouseDb {
     client: Client,
+    /// the HTTP client of the inserts whose summary header is read
+    http_client: reqwest::Client,
+    read_replica: Option<Client>,
+    read_replica_retry_at: AtomicI64,
     is_initialized: bool,
+    /// Whether initialize runs the DDL, or only verifies the schema another service created
+    initialize_schema: bool,
     max_swap_event_rows: u64,
-    swap_event_inserter: Option<Arc<RwLock<Inserter<SwapEvent>>>>,
+    swap_event_inserter: Option<Arc<ResilientInserter<SwapEvent>>>,
+    insert_retry_config: InsertRetryConfig,
     max_token_rows: u64,
-    token_inserter: Option<Arc<RwLock<Inserter<Token>>>>,
+    /// taken on close
+    token_inserter: Option<Arc<RwLock<Option<Inserter<Token>>>>>,
+    /// taken on close
+    reference_price_inserter: Option<Arc<RwLock<Option<Inserter<ReferencePrice>>>>>,
+    /// the tokens written by this process, their identical retrievals are skipped
+    recent_tokens: RecentTokens,
+    options: ClickhouseOptions,
+    /// the endpoint and the credentials, the clients are rebuilt when the options change
+    url: String,
+    read_url: Option<String>,
+    user: String,
+    password: String,
+    database: String,
 }
 
 impl ClickhouseDb {
-    /// create_inserter creates an inserter for the swap event table
-    fn create_swap_event_inserter(&self) -> Result<Inserter<SwapEvent>> {
-        let inserter = self
-            .client
-            .inserter::<SwapEvent>("swap_events")
-            .context("failed to prepare swap event insert statement")?
-            .with_timeouts(Some(Duration::from_secs(5)), Some(Duration::from_secs(20)))
-            .with_max_rows(self.max_swap_event_rows)
-            .with_max_bytes(1_000_000) // swap event is roughly ~200 bytes
-            .with_period(Some(Duration::from_secs(15)));
-        Ok(inserter)
+    /// create_inserter creates an inserter for the swap event table,
+    /// failed batches are retried and spilled to disk
+    fn create_swap_event_inserter(&self) -> ResilientInserter<SwapEvent> {
+        ResilientInserter::new(
+            Arc::new(self.swap_event_writer()),
+            "swap_events",
+            self.max_swap_event_rows as usize,
+            Duration::from_secs(15),
+            self.insert_retry_config.clone(),
+        )
+    }
+
+    /// swap_event_writer returns the writer of the swap event batches
+    fn swap_event_writer(&self) -> ClickhouseBatchWriter {
+        let (send_timeout, end_timeout) = self.options.insert_timeouts();
+        ClickhouseBatchWriter::new(self.write_client().clone(), "swap_events")
+            .with_timeouts(send_timeout, end_timeout)
+    }
+
+    /// set whether initialize runs the DDL, false only verifies the schema
+    pub fn with_initialize_schema(mut self, initialize_schema: bool) -> Self {
+        self.initialize_schema = initialize_schema;
+        self
     }
 
     /// set the max rows for the inserter
@@ -45,12 +285,50 @@ impl ClickhouseDb {
         self
     }
 
+    /// set how failed swap event batches are retried and spilled
+    pub fn with_insert_retry_config(mut self, config: InsertRetryConfig) -> Self {
+        self.insert_retry_config = config;
+        self
+    }
+
+    /// spill_stats returns the number of swap events spilled to disk and recovered from it
+    pub fn spill_stats(&self) -> SpillStats {
+        self.swap_event_inserter.as_ref().map(|inserter| inserter.stats()).unwrap_or_default()
+    }
+
+    /// spawn_spill_recovery replays the spilled swap events whenever the write endpoint
+    /// answers the health check query
+    fn spawn_spill_recovery(&self, inserter: Arc<ResilientInserter<SwapEvent>>) {
+        let client = self.write_client().clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SPILL_RECOVERY_INTERVAL);
+            loop {
+                interval.tick().await;
+                match inserter.spill_files().await {
+                    Ok(files) if files.is_empty() => continue,
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Failed to list spill files: {:?}", e);
+                        continue;
+                    }
+                }
+                if client.query("SELECT 1").execute().await.is_err() {
+                    continue;
+                }
+                if let Err(e) = inserter.recover().await {
+                    warn!("Failed to recover spilled swap events: {:?}", e);
+                }
+            }
+        });
+    }
+
     pub fn create_token_inserter(&self) -> Result<Inserter<Token>> {
+        let (send_timeout, end_timeout) = self.options.insert_timeouts();
         let inserter = self
-            .client
+            .write_client()
             .inserter::<Token>("tokens")
             .context("failed to prepare token insert statement")?
-            .with_timeouts(Some(Duration::from_secs(5)), Some(Duration::from_secs(20)))
+            .with_timeouts(send_timeout, end_timeout)
             .with_max_rows(self.max_token_rows)
             .with_max_bytes(1_000) // token is roughly ~210 bytes
             .with_period(Some(Duration::from_secs(3)));
@@ -58,722 +336,4243 @@ impl ClickhouseDb {
         Ok(inserter)
     }
 
-    pub fn with_max_token_rows(mut self, max_rows: u64) -> Self {
-        self.max_token_rows = max_rows;
-        self
-    }
-}
-
-#[async_trait::async_trait]
-impl DatabaseTrait for ClickhouseDb {
-    fn new(database_url: &str, user: &str, password: &str, database: &str) -> Self {
-        let client = Client::default()
-            .with_url(database_url)
-            .with_user(user)
-            .with_password(password)
-            .with_database(database);
-
-        info!("Connecting to ClickHouse at {}", database_url);
-        Self {
-            client,
-            is_initialized: false,
-            max_swap_event_rows: 1_000,
-            swap_event_inserter: None,
-            max_token_rows: 1,
-            token_inserter: None,
-        }
-    }
-
-    /// health_check checks the health of the clickhouse database
-    async fn health_check(&self) -> Result<()> {
-        debug!("clickhouse healthz");
-        self.client
-            .query("SELECT 1")
-            .execute()
-            .await
-            .context("Failed to execute health check query")?;
-        Ok(())
-    }
-
-    /// initialize initializes the clickhouse database
-    async fn initialize(&mut self) -> Result<()> {
-        debug!("initializing clickhouse");
-
-        let swap_event_inserter = self.create_swap_event_inserter()?;
-        let swap_event_inserter = Arc::new(RwLock::new(swap_event_inserter));
-        self.swap_event_inserter = Some(swap_event_inserter);
-
-        let token_inserter = self.create_token_inserter()?;
-        let token_inserter = Arc::new(RwLock::new(token_inserter));
-        self.token_inserter = Some(token_inserter);
-
-        self.is_initialized = true;
-
-        Ok(())
-    }
-
-    /// insert_swap_event uses a batched writer to avoid spamming writes
-    /// it is configurable at the initializer
-    async fn insert_swap_event(&self, swap_event: &SwapEvent) -> Result<()> {
-        debug!("inserting swap event: {}", swap_event.signature);
-
-        let mut inserter =
-            self.swap_event_inserter.as_ref().expect("inserter not initialized").write().await;
-
-        inserter.write(swap_event).context("Failed to write price to insert buffer")?;
-
-        let pending = inserter.pending();
-        debug!("Pending: {} rows ({} bytes)", pending.rows, pending.bytes);
+    /// create_reference_price_inserter creates an inserter for the reference prices, sampled
+    /// once per second, committed every minute of prices
+    pub fn create_reference_price_inserter(&self) -> Result<Inserter<ReferencePrice>> {
+        let (send_timeout, end_timeout) = self.options.insert_timeouts();
+        let inserter = self
+            .write_client()
+            .inserter::<ReferencePrice>("reference_prices")
+            .context("failed to prepare reference price insert statement")?
+            .with_timeouts(send_timeout, end_timeout)
+            .with_max_rows(60)
+            .with_period(Some(Duration::from_secs(60)));
 
-        let stats = inserter.commit().await?;
-        if stats.transactions > 0 {
-            info!(
-                "Committed {} swap events {} bytes in {} transactions",
-                stats.rows, stats.bytes, stats.transactions
-            );
-        }
-        Ok(())
+        Ok(inserter)
     }
 
-    /// get_candlesticks_by_token returns a list of candlesticks for a given token and interval
+    /// get_reference_candlesticks aggregates the reference prices of a synthetic pair,
+    /// the candlesticks have no volume
     #[instrument(skip(self))]
-    async fn get_candlesticks_by_token(
+    async fn get_reference_candlesticks(
         &self,
-        mint: &str,
-        pairs: &[String],
-        interval: CandlestickInterval,
+        pair: &str,
+        interval: &CandlestickInterval,
         limit: Option<usize>,
         time_from: Option<i32>,
         time_to: Option<i32>,
     ) -> Result<Vec<Candlestick>> {
         let interval_seconds = interval.get_seconds();
-        let limit = limit.unwrap_or(200);
-        let mut conditions = vec![format!("pubkey = '{}'", mint)];
-
+        let limit = limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT);
+        let mut conditions = vec!["pair = ?", FINITE_PRICE];
+        // the values bound to the `?` placeholders of the time conditions, in order
+        let mut binds = vec![];
         if let Some(time_from) = time_from {
-            conditions.push(format!("timestamp >= {}", time_from));
+            conditions.push("timestamp >= ?");
+            binds.push(time_from);
         }
         if let Some(time_to) = time_to {
-            conditions.push(format!("timestamp < {}", time_to));
-        }
-        if !pairs.is_empty() {
-            let placeholders = vec!["?"; pairs.len()].join(",");
-            conditions.push(format!("pair IN ({})", placeholders));
+            conditions.push("timestamp < ?");
+            binds.push(time_to);
         }
-
         let query = format!(
             r#"
-            WITH 
-                quantileExactWeighted(0.995)(price, 1) AS price_upper_bound, 
-                quantileExactWeighted(0.005)(price, 1) AS price_lower_bound
             SELECT
                 intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
                 argMin(price, timestamp) as open,
-                if(max(price) > price_upper_bound * 20, price_upper_bound, max(price)) AS high, 
-                if(min(price) < price_lower_bound / 20, price_lower_bound, min(price)) AS low, 
+                max(price) as high,
+                min(price) as low,
                 argMax(price, timestamp) as close,
-                sum(base_amount) as volume,
-                sum(swap_amount) as turnover
-            FROM swap_events
+                toFloat64(0) as volume,
+                toFloat64(0) as turnover,
+                toFloat64(0) as buy_volume,
+                toFloat64(0) as sell_volume,
+                toFloat64(0) as buy_turnover,
+                toFloat64(0) as sell_turnover
+            FROM reference_prices
             WHERE {conditions}
             GROUP BY bucket
             ORDER BY bucket DESC
             LIMIT {limit}
             "#,
             conditions = conditions.join(" AND "),
-            limit = limit
         );
+        debug!(query = %query, table = "reference_prices", "Executing SQL query");
 
-        let mut query_builder = self.client.query(&query);
-        if !pairs.is_empty() {
-            for pair in pairs {
-                query_builder = query_builder.bind(pair);
-            }
-        }
-
-        let result = query_builder.fetch_all::<(u64, f64, f64, f64, f64, f64, f64)>().await?;
-
-        let candlesticks: Vec<Candlestick> = result
-            .into_iter()
-            .map(|(timestamp, open, high, low, close, volume, turnover)| Candlestick {
-                timestamp,
-                open,
-                high,
-                low,
-                close,
-                volume,
-                turnover,
+        let (query, binds) = (&query, &binds);
+        let result = self
+            .read(|client| async move {
+                let query = client.query(query).bind(pair);
+                binds.iter().fold(query, |query, bind| query.bind(bind)).fetch_all().await
             })
-            .collect();
+            .await?;
+        Ok(result.into_iter().rev().collect())
+    }
 
-        // Reverse the order of the candlesticks
-        let candlesticks = candlesticks.into_iter().rev().collect();
+    pub fn with_max_token_rows(mut self, max_rows: u64) -> Self {
+        self.max_token_rows = max_rows;
+        self
+    }
 
-        Ok(candlesticks)
+    /// set how long a written token keeps identical retrievals from being written again
+    pub fn with_token_dedupe_window(mut self, window: Duration) -> Self {
+        self.recent_tokens = RecentTokens::new(DEFAULT_RECENT_TOKENS_CAPACITY, window);
+        self
     }
 
-    /// get_candlesticks_by_pair returns a list of candlesticks for a given pair and interval
-    #[instrument(skip(self))]
-    async fn get_candlesticks_by_pair(
-        &self,
-        pair: &str,
-        token: Option<&str>,
-        interval: &CandlestickInterval,
-        limit: Option<usize>,
-        time_from: Option<i32>,
-        time_to: Option<i32>,
-    ) -> Result<Vec<Candlestick>> {
-        let size = limit.unwrap_or(200);
-        let mut candlesticks = self
-            .get_candlesticks_from_swap_events(
-                pair,
+    /// insert_token_launch records a launchpad, graduation or launch of a token, buffered server
+    /// side instead of mutating the token rows, which may not be flushed yet
+    async fn insert_token_launch(&self, record: &TokenLaunchRecord) -> Result<()> {
+        let client = self
+            .write_client()
+            .clone()
+            .with_option("async_insert", "1")
+            .with_option("wait_for_async_insert", "0");
+        let mut insert =
+            client.insert::<TokenLaunchRecord>("token_launches").map_err(classified)?;
+        insert.write(record).await.map_err(classified)?;
+        insert.end().await.map_err(classified)?;
+        Ok(())
+    }
+
+    /// with_token_launches fills the launchpad, the graduation and the launch the token rows
+    /// lack from the earliest records of `token_launches`
+    async fn with_token_launches(&self, mut tokens: Vec<Token>) -> Result<Vec<Token>> {
+        if tokens.is_empty() {
+            return Ok(tokens);
+        }
+        let mints: Vec<&str> = tokens.iter().map(|token| token.token.as_str()).collect();
+        let query = r#"
+            SELECT
                 token,
-                interval,
-                Some(size),
-                time_from,
-                time_to,
-            )
-            .await?;
-        if candlesticks.len() < size {
-            let exclude_buckets = candlesticks.iter().map(|c| c.timestamp).collect::<Vec<_>>();
-            let additional_candlesticks = self
-                .get_candlesticks_from_candlesticks(
-                    pair,
+                first_launchpad AS launchpad,
+                first_graduated_at AS graduated_at,
+                first_graduation_pool AS graduation_pool,
+                first_launch_dex AS launch_dex,
+                first_launch_pool AS launch_pool,
+                first_launch_timestamp AS launch_timestamp,
+                first_recorded_at AS recorded_at
+            FROM (
+                SELECT
                     token,
-                    interval,
-                    Some(size - candlesticks.len()),
-                    time_from,
-                    time_to,
-                    Some(exclude_buckets),
-                )
-                .await?;
-            candlesticks = [additional_candlesticks, candlesticks].concat();
+                    argMinIf(launchpad, recorded_at, launchpad != '') AS first_launchpad,
+                    argMinIf(graduated_at, recorded_at, graduation_pool != '')
+                        AS first_graduated_at,
+                    argMinIf(graduation_pool, recorded_at, graduation_pool != '')
+                        AS first_graduation_pool,
+                    argMinIf(launch_dex, recorded_at, launch_pool != '') AS first_launch_dex,
+                    argMinIf(launch_pool, recorded_at, launch_pool != '') AS first_launch_pool,
+                    argMinIf(launch_timestamp, recorded_at, launch_pool != '')
+                        AS first_launch_timestamp,
+                    min(recorded_at) AS first_recorded_at
+                FROM token_launches
+                WHERE token IN ?
+                GROUP BY token
+            )
+            "#;
+        let mints = &mints;
+        let records: HashMap<String, TokenLaunchRecord> = self
+            .read(|client| async move {
+                client.query(query).bind(mints).fetch_all::<TokenLaunchRecord>().await
+            })
+            .await
+            .context("Failed to fetch the token launches")?
+            .into_iter()
+            .map(|record| (record.token.clone(), record))
+            .collect();
+        for token in &mut tokens {
+            if let Some(record) = records.get(&token.token) {
+                token.apply_launch_record(record);
+            }
         }
-        // sort by timestamp ascending
-        candlesticks.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        // truncate to size
-        candlesticks.truncate(size);
+        Ok(tokens)
+    }
 
-        Ok(candlesticks)
+    /// dedupe_tokens collapses the rows of the tokens table sharing every column but the
+    /// retrieval time into their latest retrieval, only counting them on a dry run.
+    /// The table is rebuilt and swapped in, the tokens written meanwhile are lost, so it
+    /// must run with the ingestion stopped
+    pub async fn dedupe_tokens(&self, dry_run: bool) -> Result<TokenDedupe> {
+        let client = self.job_client();
+        let rows = client
+            .query("SELECT count() FROM tokens")
+            .fetch_one::<u64>()
+            .await
+            .context("Failed to count the tokens")?;
+        let unique_rows = client
+            .query(&format!(
+                "SELECT count() FROM (SELECT 1 FROM tokens LIMIT 1 BY {TOKEN_IDENTITY_COLUMNS})"
+            ))
+            .fetch_one::<u64>()
+            .await
+            .context("Failed to count the unique tokens")?;
+        let dedupe = TokenDedupe { rows, unique_rows };
+        if dry_run || unique_rows == rows {
+            return Ok(dedupe);
+        }
+
+        let statements = [
+            "DROP TABLE IF EXISTS tokens_dedupe".to_string(),
+            "CREATE TABLE tokens_dedupe AS tokens".to_string(),
+            format!(
+                "INSERT INTO tokens_dedupe SELECT * FROM tokens \
+                ORDER BY token, retrieval_timestamp DESC LIMIT 1 BY {TOKEN_IDENTITY_COLUMNS}"
+            ),
+            "EXCHANGE TABLES tokens AND tokens_dedupe".to_string(),
+            "DROP TABLE tokens_dedupe".to_string(),
+        ];
+        for statement in statements {
+            info!(statement = %statement, "Deduplicating tokens");
+            client
+                .query(&statement)
+                .execute()
+                .await
+                .with_context(|| format!("Failed to execute {statement}"))?;
+        }
+        Ok(dedupe)
     }
 
-    #[instrument(skip(self))]
-    async fn get_candlesticks_from_swap_events(
-        &self,
-        pair: &str,
-        token: Option<&str>,
-        interval: &CandlestickInterval,
-        limit: Option<usize>,
-        time_from: Option<i32>,
-        time_to: Option<i32>,
-    ) -> Result<Vec<Candlestick>> {
-        let interval_seconds = interval.get_seconds();
-        let pairs = pair.split(",").map(|s| format!("'{}'", s)).collect::<Vec<_>>().join(",");
-        let mut conditions = vec![format!("pair IN ({})", pairs)];
-        if let Some(token) = token {
-            conditions.push(format!("pubkey = '{}'", token));
+    /// set the compression, the settings, the timeouts and the headers of the client,
+    /// the read replica and the inserters
+    pub fn with_options(mut self, options: ClickhouseOptions) -> Self {
+        info!("ClickHouse client options: {}", options);
+        self.options = options;
+        self.client = self.connect(&self.url);
+        self.http_client = make_http_client(self.options.connect_timeout);
+        self.read_replica = self.read_url.as_deref().map(|url| self.connect(url));
+        self
+    }
+
+    /// options returns the effective options of the client
+    pub fn options(&self) -> &ClickhouseOptions {
+        &self.options
+    }
+
+    /// set a read replica, read-only queries are routed to it
+    pub fn with_read_url(mut self, read_url: &str) -> Self {
+        info!("Routing ClickHouse reads to {}", read_url);
+        self.read_replica = Some(self.connect(read_url));
+        self.read_url = Some(read_url.to_string());
+        self
+    }
+
+    /// connect returns a client of `url` with the credentials and the options
+    fn connect(&self, url: &str) -> Client {
+        let client = make_client(self.options.connect_timeout)
+            .with_url(url)
+            .with_user(&self.user)
+            .with_password(&self.password)
+            .with_database(&self.database);
+        self.options.apply(client)
+    }
+
+    /// write_client returns the client for inserts, aggregations and mutations
+    fn write_client(&self) -> &Client {
+        &self.client
+    }
+
+    /// job_client returns the write client without the `max_execution_time` of the options,
+    /// the aggregations, the prunes and the mutations may run longer than the requests
+    fn job_client(&self) -> Client {
+        self.write_client().clone().with_option("max_execution_time", "0")
+    }
+
+    /// insert_select runs an `INSERT ... SELECT` with the settings of the job client, scanning
+    /// its source once, and returns the rows it wrote as reported by the summary header of
+    /// its response, None when the response has no summary
+    async fn insert_select(&self, query: &str) -> Result<Option<u64>> {
+        // the summary header is only complete once the query has finished
+        let response = self
+            .http_client
+            .post(&self.url)
+            .query(&[
+                ("database", self.database.as_str()),
+                ("max_execution_time", "0"),
+                ("wait_end_of_query", "1"),
+            ])
+            .header("X-ClickHouse-User", &self.user)
+            .header("X-ClickHouse-Key", &self.password)
+            .body(query.to_string())
+            .send()
+            .await
+            .map_err(http_classified)?;
+        if !response.status().is_success() {
+            let message = response.text().await.map_err(http_classified)?;
+            return Err(StorageError::from_clickhouse_response(&message).into());
         }
-        if let Some(time_from) = time_from {
-            conditions.push(format!("timestamp >= {}", time_from));
+        let written_rows = response
+            .headers()
+            .get("X-ClickHouse-Summary")
+            .and_then(|summary| summary.to_str().ok())
+            .and_then(summary_written_rows);
+        if written_rows.is_none() {
+            warn!("The insert response has no summary, the written rows are unknown");
         }
-        if let Some(time_to) = time_to {
-            conditions.push(format!("timestamp < {}", time_to));
+        Ok(written_rows)
+    }
+
+    /// ensure_initialized fails the data methods used before `initialize`, the schema of
+    /// a service not running the DDL may not be there yet
+    fn ensure_initialized(&self) -> Result<()> {
+        if !self.is_initialized {
+            return Err(not_initialized("clickhouse not initialized"));
         }
-        let query = format!(
-            r#"
-            SELECT
-                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
-                argMin(price, timestamp) as open,
-                max(price) as high,
-                min(price) as low,
-                argMax(price, timestamp) as close,
-                sum(base_amount) as volume,
-                sum(swap_amount) as turnover
-            FROM swap_events
-            WHERE {conditions}
-            GROUP BY bucket
-            ORDER BY bucket DESC
-            LIMIT {limit}
-            "#,
-            conditions = conditions.join(" AND "),
-            interval_seconds = interval_seconds,
-            limit = limit.unwrap_or(200)
-        );
-        debug!(
-            query = %query,
-            table = "swap_events",
-            "Executing SQL query"
-        );
+        Ok(())
+    }
 
-        let result =
-            self.client.query(&query).fetch_all::<(u64, f64, f64, f64, f64, f64, f64)>().await?;
-        let candlesticks: Vec<Candlestick> = result
-            .into_iter()
-            .map(|(timestamp, open, high, low, close, volume, turnover)| Candlestick {
+    /// read_client returns the read replica if configured and not cooling down,
+    /// otherwise the write client
+    fn read_client(&self) -> &Client {
+        match &self.read_replica {
+            Some(replica)
+                if Utc::now().timestamp() >= self.read_replica_retry_at.load(Ordering::Relaxed) =>
+            {
+                replica
+            }
+            _ => self.write_client(),
+        }
+    }
+
+    /// read runs a read-only query on the read client, falling back to the write client
+    /// when the read replica is unreachable, errors carry their StorageError
+    async fn read<T, F, Fut>(&self, query: F) -> Result<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = clickhouse::error::Result<T>>,
+    {
+        let client = self.read_client();
+        match query(client.clone()).await {
+            Err(e) if !std::ptr::eq(client, self.write_client()) && is_connection_error(&e) => {
+                warn!(error = ?e, "Read replica unavailable, falling back to the write client");
+                self.read_replica_retry_at.store(
+                    Utc::now().timestamp() + READ_REPLICA_COOLDOWN_SECONDS,
+                    Ordering::Relaxed,
+                );
+                query(self.write_client().clone()).await.map_err(classified)
+            }
+            result => result.map_err(classified),
+        }
+    }
+
+    /// fetch_trades returns the latest trades matching the conditions, and the trades of
+    /// `address` when set, those it traded or relayed as the fee payer. The owner and the fee
+    /// payer are read with a query each, a disjunction of both would skip neither index
+    async fn fetch_trades(
+        &self,
+        conditions: &[&str],
+        binds: &[TradeBind],
+        address: Option<&str>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Trade>> {
+        let Some(address) = address else {
+            return self.fetch_trades_where(conditions, binds, limit, offset).await;
+        };
+        let mut trades = vec![];
+        for condition in ["owner = ?", "fee_payer = ?"] {
+            let conditions = [conditions, &[condition]].concat();
+            let binds = [binds, &[TradeBind::Str(address.to_string())]].concat();
+            trades.extend(self.fetch_trades_where(&conditions, &binds, limit + offset, 0).await?);
+        }
+        // a swap the trader paid for themselves is found by both queries
+        trades.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        let mut seen = HashSet::new();
+        trades.retain(|trade| seen.insert((trade.signature.clone(), trade.pair.clone())));
+        Ok(trades.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// fetch_trades_where returns the latest trades matching the conditions, the `?`
+    /// placeholders are bound to `binds` in order
+    async fn fetch_trades_where(
+        &self,
+        conditions: &[&str],
+        binds: &[TradeBind],
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Trade>> {
+        let query = format!(
+            r#"
+            SELECT
+                pair,
+                pubkey,
+                price,
+                market_cap,
+                base_amount,
+                quote_amount,
+                swap_amount,
+                owner,
+                signature,
+                signers,
+                slot,
                 timestamp,
-                open,
-                high,
-                low,
-                close,
-                volume,
-                turnover,
+                is_buy,
+                is_pump,
+                is_wash,
+                fee_amount,
+                fee_mint,
+                pct_of_supply,
+                fee_payer,
+                -- the token details are only published with the trades
+                '' AS symbol,
+                toUInt8(0) AS decimals
+            FROM swap_events
+            WHERE {cond}
+            ORDER BY timestamp DESC
+            LIMIT {limit} OFFSET {offset}
+        "#,
+            cond = conditions.join(" AND "),
+        );
+        let query = &query;
+        let result = self
+            .read(|client| async move {
+                let mut query = client.query(query);
+                for bind in binds {
+                    query = match bind {
+                        TradeBind::Str(value) => query.bind(value),
+                        TradeBind::U64(value) => query.bind(value),
+                        TradeBind::F64(value) => query.bind(value),
+                    };
+                }
+                query.fetch_all::<Trade>().await
             })
-            .collect();
-        // Reverse the order of the candlesticks
-        let candlesticks = candlesticks.into_iter().rev().collect();
-        Ok(candlesticks)
+            .await?;
+        Ok(result)
     }
 
-    #[instrument(skip(self))]
-    async fn get_candlesticks_from_candlesticks(
+    /// slot_time_window returns the time range `slot` most likely falls in, estimated from
+    /// the latest slot of the hour before the latest stored swap event, so the lookups still
+    /// find a window once the ingestion stopped, None when no swap event is stored
+    async fn slot_time_window(&self, slot: u64) -> Result<Option<(u64, u64)>> {
+        // the latest timestamp is read from the part metadata of the partition key
+        let query = r#"
+            SELECT max(slot), max(timestamp)
+            FROM swap_events
+            WHERE timestamp >= (SELECT max(timestamp) FROM swap_events) - 3600
+        "#;
+        let (anchor_slot, anchor_ts) = self
+            .read(|client| async move { client.query(query).fetch_one::<(u64, u64)>().await })
+            .await?;
+        Ok(slot_time_window(slot, anchor_slot, anchor_ts))
+    }
+
+    /// get_trades_page returns the page of trades after `cursor` matching the filter
+    async fn get_trades_page(
         &self,
-        pair: &str,
-        token: Option<&str>,
-        interval: &CandlestickInterval,
-        limit: Option<usize>,
-        time_from: Option<i32>,
-        time_to: Option<i32>,
-        exclude_buckets: Option<Vec<u64>>,
-    ) -> Result<Vec<Candlestick>> {
-        let interval_seconds = interval.get_seconds();
-        let candlestick_interval = interval.get_candlestick_interval();
-        let pairs = pair.split(",").map(|s| format!("'{}'", s)).collect::<Vec<_>>().join(",");
-        let mut conditions = vec![format!("pair IN ({})", pairs)];
-        if let Some(token) = token {
-            conditions.push(format!("pubkey = '{}'", token));
+        filter: &TradeFilter,
+        cursor: Option<TradeCursor>,
+        size: usize,
+    ) -> Result<Vec<Trade>> {
+        let mut conditions = vec![];
+        if filter.token.is_some() {
+            conditions.push("pubkey = ?");
         }
-        if let Some(time_from) = time_from {
-            conditions.push(format!("timestamp >= {}", time_from));
+        if filter.pair.is_some() {
+            conditions.push("pair = ?");
         }
-        if let Some(time_to) = time_to {
-            conditions.push(format!("timestamp < {}", time_to));
+        if filter.time_from.is_some() {
+            conditions.push("timestamp >= ?");
         }
-        if let Some(exclude_buckets) = exclude_buckets {
-            if !exclude_buckets.is_empty() {
-                let buckets =
-                    exclude_buckets.iter().map(|s| format!("{}", s)).collect::<Vec<_>>().join(",");
-                conditions.push(format!("bucket NOT IN ({})", buckets));
-            }
+        if filter.time_to.is_some() {
+            conditions.push("timestamp < ?");
+        }
+        if cursor.is_some() {
+            conditions.push("(timestamp, signature, pair) > (?, ?, ?)");
+        }
+        if conditions.is_empty() {
+            conditions.push("1");
         }
         let query = format!(
             r#"
             SELECT
-                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
-                argMin(open, timestamp) as open,
-                max(high) as high,
-                min(low) as low,
-                argMax(close, timestamp) as close,
-                sum(volume) as volume,
-                sum(turnover) as turnover
-            FROM candlesticks
-            WHERE {conditions} AND interval = {candlestick_interval}
-            GROUP BY bucket
-            ORDER BY bucket DESC
-            LIMIT {limit}
+                pair,
+                pubkey,
+                price,
+                market_cap,
+                base_amount,
+                quote_amount,
+                swap_amount,
+                owner,
+                signature,
+                signers,
+                slot,
+                timestamp,
+                is_buy,
+                is_pump,
+                is_wash,
+                fee_amount,
+                fee_mint,
+                pct_of_supply,
+                fee_payer,
+                -- the token details are only published with the trades
+                '' AS symbol,
+                toUInt8(0) AS decimals
+            FROM swap_events
+            WHERE {conditions}
+            ORDER BY timestamp, signature, pair
+            LIMIT {size}
             "#,
             conditions = conditions.join(" AND "),
-            interval_seconds = interval_seconds,
-            candlestick_interval = candlestick_interval,
-            limit = limit.unwrap_or(200)
-        );
-        debug!(
-            query = %query,
-            table = "candlesticks",
-            "Executing SQL query"
         );
+        debug!(query = %query, table = "swap_events", "Executing SQL query");
 
-        let result =
-            self.client.query(&query).fetch_all::<(u64, f64, f64, f64, f64, f64, f64)>().await?;
-
-        let candlesticks: Vec<Candlestick> = result
-            .into_iter()
-            .map(|(timestamp, open, high, low, close, volume, turnover)| Candlestick {
-                timestamp,
-                open,
-                high,
-                low,
-                close,
-                volume,
-                turnover,
+        let (query, cursor) = (&query, &cursor);
+        let trades = self
+            .read(|client| async move {
+                let mut query = client.query(query);
+                if let Some(token) = &filter.token {
+                    query = query.bind(token);
+                }
+                if let Some(pair) = &filter.pair {
+                    query = query.bind(pair);
+                }
+                if let Some(time_from) = filter.time_from {
+                    query = query.bind(time_from);
+                }
+                if let Some(time_to) = filter.time_to {
+                    query = query.bind(time_to);
+                }
+                if let Some((timestamp, signature, pair)) = cursor {
+                    query = query.bind(timestamp).bind(signature).bind(pair);
+                }
+                query.fetch_all::<Trade>().await
             })
-            .collect();
+            .await
+            .context("Failed to fetch trades page")?;
+        Ok(trades)
+    }
 
-        // Reverse the order of the candlesticks
-        let candlesticks = candlesticks.into_iter().rev().collect();
+    /// get_inactive_tokens returns the tokens whose latest swap is before `inactive_since`
+    async fn get_inactive_tokens(&self, inactive_since: u64) -> Result<Vec<String>> {
+        let query = r#"
+            SELECT pubkey
+            FROM swap_events
+            GROUP BY pubkey
+            HAVING max(timestamp) < ?
+            "#;
+        debug!(query = %query, table = "swap_events", "Executing SQL query");
+        let tokens = self
+            .job_client()
+            .query(query)
+            .bind(inactive_since)
+            .fetch_all::<String>()
+            .await
+            .context("Failed to fetch inactive tokens")?;
+        Ok(tokens)
+    }
 
-        Ok(candlesticks)
+    /// prune_token_events deletes the swap events of `tokens` before `cutoff`, `batch_size`
+    /// tokens at a time, returns the number of deleted rows
+    async fn prune_token_events(
+        &self,
+        tokens: &[String],
+        cutoff: u64,
+        batch_size: usize,
+    ) -> Result<u64> {
+        let total_batches = tokens.len().div_ceil(batch_size);
+        // wait for each mutation to finish, so that only one batch is in flight at a time
+        // and the counted rows are gone when the next batch starts
+        let client = self.job_client().with_option("mutations_sync", "1");
+        let count = "SELECT count() FROM swap_events WHERE pubkey IN ? AND timestamp < ?";
+        let delete = "ALTER TABLE swap_events DELETE WHERE pubkey IN ? AND timestamp < ?";
+        let mut deleted = 0;
+        for (index, batch) in tokens.chunks(batch_size).enumerate() {
+            let rows = client
+                .query(count)
+                .bind(batch)
+                .bind(cutoff)
+                .fetch_one::<u64>()
+                .await
+                .with_context(|| {
+                    format!("Failed to count batch {}/{}", index + 1, total_batches)
+                })?;
+            client.query(delete).bind(batch).bind(cutoff).execute().await.with_context(|| {
+                format!("Failed to prune batch {}/{}", index + 1, total_batches)
+            })?;
+            deleted += rows;
+            info!(
+                batch = index + 1,
+                total_batches,
+                tokens = batch.len(),
+                rows,
+                "Pruned swap events of inactive tokens"
+            );
+        }
+        Ok(deleted)
     }
 
-    #[instrument(skip(self))]
-    async fn get_top_tokens(
+    /// precomputed_top_tokens returns the window and the time of the latest refresh of
+    /// token_window_stats matching `start_time`, None when the live query has to run
+    async fn precomputed_top_tokens(
         &self,
-        limit: usize,
+        now: u64,
         start_time: u64,
+        exclude_wash: bool,
+    ) -> Option<(u64, u64)> {
+        let requested = now.checked_sub(start_time)?;
+        let window = TOKEN_STATS_WINDOWS
+            .into_iter()
+            .find(|window| window.abs_diff(requested) <= TOKEN_STATS_TOLERANCE_SECONDS)?;
+        let query = r#"
+            SELECT max(computed_at)
+            FROM token_window_stats
+            WHERE window_secs = ? AND exclude_wash = ?
+            "#;
+        let computed_at = self
+            .read(|client| async move {
+                client.query(query).bind(window).bind(exclude_wash).fetch_one::<u64>().await
+            })
+            .await;
+        match computed_at {
+            Ok(computed_at) if now.abs_diff(computed_at) <= TOKEN_STATS_TOLERANCE_SECONDS => {
+                Some((window, computed_at))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!(error = ?e, "Failed to read the token window stats, running the live query");
+                None
+            }
+        }
+    }
+
+    /// fetch_top_tokens returns the page of top tokens of the source
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_top_tokens(
+        &self,
+        source: TopTokensSource,
+        limit: usize,
+        offset: usize,
         min_volume: Option<f64>,
         min_market_cap: Option<f64>,
         pumpfun: Option<bool>,
-    ) -> Result<Vec<TopToken>> {
-        let mut query = format!(
-            r#"
-            WITH 
-                latest_prices AS (
-                    SELECT
-                        pubkey,
-                        price,
-                        market_cap,
-                        timestamp,
-                        is_pump
-                    FROM swap_events
-                    WHERE timestamp >= {start_time}
-                    ORDER BY timestamp DESC
-                    LIMIT 1 BY pubkey
-                ),
-                volumes AS (
-                    SELECT
-                        pubkey,
-                        sum(base_amount) as volume,
-                        sum(swap_amount) as turnover
-                    FROM swap_events
-                    WHERE timestamp >= {start_time}
-                    GROUP BY pubkey
-                ),
-                price_changes AS (
-                    SELECT
-                        pubkey,
-                        (last_value(price) - first_value(price)) / first_value(price) * 100 as price_change
-                    FROM swap_events
-                    WHERE timestamp >= {start_time}
-                    GROUP BY pubkey
-                )
-            SELECT
-                lp.pubkey,
-                lp.price,
-                lp.market_cap,
-                v.volume,
-                v.turnover,
-                pc.price_change
-            FROM latest_prices lp
-            LEFT JOIN volumes v ON lp.pubkey = v.pubkey
-            LEFT JOIN price_changes pc ON lp.pubkey = pc.pubkey
-            "#
-        );
+        exclude_wash: bool,
+        sort_by: TopTokensSort,
+        order: SortOrder,
+    ) -> Result<TopTokensPage> {
+        let mut query = source.relations(exclude_wash);
+        query.push_str(TOP_TOKENS_SELECT);
 
         let mut conditions = Vec::new();
+        // the values bound to the `?` placeholders of the conditions, in order
+        let mut binds = Vec::new();
 
+        // the columns are qualified, the relations share their names
         if let Some(min_volume) = min_volume {
-            conditions.push(format!("v.volume >= {min_volume}"));
+            conditions.push("coalesce(v.volume, 0) >= ?".to_string());
+            binds.push(min_volume);
         }
 
         if let Some(min_market_cap) = min_market_cap {
-            conditions.push(format!("lp.market_cap >= {min_market_cap}"));
+            conditions.push("lp.market_cap >= ?".to_string());
+            binds.push(min_market_cap);
         }
 
         if let Some(pumpfun) = pumpfun {
-            conditions.push(format!("is_pump = {}", pumpfun));
+            conditions.push(format!("lp.is_pump = {pumpfun}"));
         }
 
+        // the majors are alphanumeric, see parse_major_mints
+        let majors: Vec<String> = MAJOR_MINTS.iter().map(|mint| format!("'{mint}'")).collect();
+        conditions.push(format!("lp.pubkey NOT IN ({})", majors.join(", ")));
+
         if !conditions.is_empty() {
             query.push_str(" WHERE ");
             query.push_str(&conditions.join(" AND "));
         }
 
-        query.push_str(&format!(" ORDER BY v.volume DESC LIMIT {}", limit));
-        let result = self.client.query(&query).fetch_all::<TopToken>().await?;
-        Ok(result)
+        let count_query = &format!("SELECT count() FROM ({query})");
+        // the columns come from the sort enum, the pubkey keeps the pages stable on ties
+        query.push_str(&format!(
+            " ORDER BY {} {}, lp.pubkey LIMIT {limit} OFFSET {offset}",
+            sort_by.column(),
+            order.sql()
+        ));
+        let (query, binds) = (&query, &binds);
+        let bound = |client: Client, query: &str| {
+            binds.iter().fold(client.query(query), |query, bind| query.bind(bind))
+        };
+        let (tokens, total) = tokio::try_join!(
+            self.read(|client| async move { bound(client, query).fetch_all::<TopToken>().await }),
+            self.read(|client| async move { bound(client, count_query).fetch_one::<u64>().await }),
+        )?;
+        Ok(TopTokensPage { tokens, total })
     }
 
-    /// get_token_stats returns a list of token stats for a given list of tokens
-    #[instrument(skip(self))]
-    async fn get_token_stats(&self, mints: Vec<String>) -> Result<Vec<TokenStat>> {
-        let query = r#"
-            WITH 
-                now() AS current_time, 
-                toUnixTimestamp(current_time) AS current_ts 
+    /// missing_tables returns the expected tables and views absent from the database
+    pub async fn missing_tables(&self, expected: &[&str]) -> Result<Vec<String>> {
+        let query = "SELECT name FROM system.tables WHERE database = currentDatabase()";
+        let tables = self
+            .write_client()
+            .query(query)
+            .fetch_all::<String>()
+            .await
+            .context("Failed to list the tables")?;
+        Ok(expected
+            .iter()
+            .filter(|table| !tables.iter().any(|name| name == *table))
+            .map(|table| table.to_string())
+            .collect())
+    }
 
-            SELECT 
-                pubkey,
-                argMax(price, timestamp) AS latest_price, 
-                argMax(market_cap, timestamp) AS latest_market_cap,
+    /// missing_projections returns the expected projections absent from their table,
+    /// as `table.projection`
+    pub async fn missing_projections(
+        &self,
+        expected: &[(&str, &str, &str)],
+    ) -> Result<Vec<String>> {
+        let mut missing = vec![];
+        for (table, projection, _) in expected {
+            // system.tables lists the projections in the create query on every server version
+            let query = "SELECT create_table_query FROM system.tables \
+                WHERE database = currentDatabase() AND name = ?";
+            let create_query = self
+                .write_client()
+                .query(query)
+                .bind(table)
+                .fetch_optional::<String>()
+                .await
+                .context("Failed to get the create table query")?;
+            let projection_clause = format!("PROJECTION {projection}");
+            if !create_query.is_some_and(|query| query.contains(&projection_clause)) {
+                missing.push(format!("{table}.{projection}"));
+            }
+        }
+        Ok(missing)
+    }
 
-                coalesce(
-                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 300), 0.0), 
-                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 300)
-                ) AS price_5m,
+    /// ensure_projections adds the missing projections and materializes them for the existing
+    /// parts, the materialization is a mutation that runs in the background
+    pub async fn ensure_projections(&self) -> Result<()> {
+        let missing = self.missing_projections(EXPECTED_PROJECTIONS).await?;
+        for (table, projection, definition) in EXPECTED_PROJECTIONS {
+            if !missing.contains(&format!("{table}.{projection}")) {
+                continue;
+            }
+            info!("Adding projection {} to {}", projection, table);
+            let query = format!(
+                "ALTER TABLE {table} ADD PROJECTION IF NOT EXISTS {projection} ({definition})"
+            );
+            self.job_client()
+                .query(&query)
+                .execute()
+                .await
+                .with_context(|| format!("Failed to add projection {projection}"))?;
+            let query = format!("ALTER TABLE {table} MATERIALIZE PROJECTION {projection}");
+            self.job_client()
+                .query(&query)
+                .execute()
+                .await
+                .with_context(|| format!("Failed to materialize projection {projection}"))?;
+        }
+        Ok(())
+    }
 
-                coalesce(
-                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 3600), 0.0), 
-                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 3600)
-                ) AS price_1h,
+    /// read_replica_health checks the read replica, None when none is configured
+    pub async fn read_replica_health(&self) -> Option<Result<()>> {
+        let replica = self.read_replica.as_ref()?;
+        let result = replica
+            .query("SELECT 1")
+            .execute()
+            .await
+            .context("Failed to execute health check query on the read endpoint");
+        Some(result)
+    }
 
-                coalesce(
-                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 21600), 0.0), 
-                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 21600)
-                ) AS price_6h,
+    /// sorting_key returns the sorting key of a table, None when the table is missing
+    pub async fn sorting_key(&self, table: &str) -> Result<Option<String>> {
+        let query = "SELECT sorting_key FROM system.tables \
+            WHERE database = currentDatabase() AND name = ?";
+        self.write_client()
+            .query(query)
+            .bind(table)
+            .fetch_optional::<String>()
+            .await
+            .context("Failed to get the sorting key")
+    }
 
-                coalesce(
-                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 86400), 0.0), 
-                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 86400)
-                ) AS price_24h,
+    /// rekey_candlesticks rekeys a candlesticks table created without the interval in its
+    /// sorting key, whose merges dropped the candlesticks of the intervals starting at the
+    /// same time. The sorting key can't be changed in place, the table is copied into a
+    /// rekeyed one swapped in its place, the candlesticks written during the copy are lost
+    /// and the merged ones have to be aggregated again, so `sonar db rekey-candlesticks`
+    /// runs it with the ingestion stopped rather than the services on startup
+    pub async fn rekey_candlesticks(&self) -> Result<()> {
+        let Some(sorting_key) = self.sorting_key("candlesticks").await? else {
+            return Ok(());
+        };
+        if sorting_key == CANDLESTICKS_SORTING_KEY {
+            return Ok(());
+        }
+        warn!(%sorting_key, "Rekeying the candlesticks by interval");
+        let queries = [
+            "DROP TABLE IF EXISTS candlesticks_rekeyed".to_string(),
+            format!(
+                "CREATE TABLE candlesticks_rekeyed AS candlesticks \
+                ENGINE = ReplacingMergeTree(timestamp) \
+                PARTITION BY toYYYYMMDD(fromUnixTimestamp(timestamp)) \
+                ORDER BY ({CANDLESTICKS_SORTING_KEY})"
+            ),
+            "INSERT INTO candlesticks_rekeyed SELECT * FROM candlesticks".to_string(),
+            "EXCHANGE TABLES candlesticks AND candlesticks_rekeyed".to_string(),
+            "DROP TABLE candlesticks_rekeyed".to_string(),
+        ];
+        for query in queries {
+            self.job_client()
+                .query(&query)
+                .execute()
+                .await
+                .with_context(|| format!("Failed to rekey the candlesticks: {query}"))?;
+        }
+        Ok(())
+    }
+}
 
-                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 300) AS volume_5m,
-                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 3600) AS volume_1h,
-                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 21600) AS volume_6h,
-                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 86400) AS volume_24h,
+#[async_trait::async_trait]
+impl DatabaseTrait for ClickhouseDb {
+    fn new(database_url: &str, user: &str, password: &str, database: &str) -> Self {
+        let client = make_client(None)
+            .with_url(database_url)
+            .with_user(user)
+            .with_password(password)
+            .with_database(database);
 
-                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 300) AS turnover_5m,
-                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 3600) AS turnover_1h,
-                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 21600) AS turnover_6h,
-                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 86400) AS turnover_24h
-            FROM swap_events
-            WHERE pubkey IN ?
-            GROUP BY pubkey
-            "#;
-        let result = self.client.query(query).bind(mints.clone()).fetch_all::<TokenStat>().await?;
-        Ok(result)
+        info!("Connecting to ClickHouse at {}", database_url);
+        Self {
+            client,
+            http_client: make_http_client(None),
+            read_replica: None,
+            read_replica_retry_at: AtomicI64::new(0),
+            is_initialized: false,
+            initialize_schema: true,
+            max_swap_event_rows: 1_000,
+            swap_event_inserter: None,
+            insert_retry_config: InsertRetryConfig::default(),
+            max_token_rows: 1,
+            token_inserter: None,
+            reference_price_inserter: None,
+            recent_tokens: RecentTokens::new(
+                DEFAULT_RECENT_TOKENS_CAPACITY,
+                DEFAULT_TOKEN_DEDUPE_WINDOW,
+            ),
+            options: ClickhouseOptions::default(),
+            url: database_url.to_string(),
+            read_url: None,
+            user: user.to_string(),
+            password: password.to_string(),
+            database: database.to_string(),
+        }
     }
 
-    /// get_token_daily_stats returns a list of token daily stats for a given list of tokens
-    #[instrument(skip(self))]
-    async fn get_token_daily_stats(&self, tokens: Vec<String>) -> Result<Vec<TokenDailyStat>> {
-        let query = r#"
-            SELECT 
-                pubkey,
-                end_ts as timestamp,
-                latest_price as price,
-                latest_market_cap as market_cap,
-                price_24h,
-                volume_24h,
-                turnover_24h
-            FROM token_24h_stats_v
-            WHERE pubkey IN ? 
-            "#;
-        let result =
-            self.client.query(query).bind(tokens.clone()).fetch_all::<TokenDailyStat>().await?;
-        Ok(result)
+    /// health_check checks the health of the clickhouse database, an unreachable read replica
+    /// only degrades the reads, which fall back to the write endpoint
+    async fn health_check(&self) -> Result<()> {
+        debug!("clickhouse healthz");
+        self.write_client()
+            .query("SELECT 1")
+            .execute()
+            .await
+            .context("Failed to execute health check query on the write endpoint")?;
+        if let Some(Err(e)) = self.read_replica_health().await {
+            warn!(error = ?e, "Read replica degraded, reads fall back to the write endpoint");
+            self.read_replica_retry_at
+                .store(Utc::now().timestamp() + READ_REPLICA_COOLDOWN_SECONDS, Ordering::Relaxed);
+        }
+        Ok(())
     }
 
-    /// get_trades returns a list of trades for a given query
-    #[instrument(skip(self))]
-    async fn get_trades(
-        &self,
-        address: Option<&str>,
-        token: Option<&str>,
-        pair: Option<&str>,
-        signature: Option<&str>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-    ) -> Result<Vec<Trade>> {
-        let mut conditions = vec![];
-        if let Some(pair) = pair {
-            conditions.push(format!("pair = '{}'", pair));
+    /// verify_schema checks the tables, the views and the projections the queries read
+    async fn verify_schema(&self) -> Result<()> {
+        let mut missing = self.missing_tables(EXPECTED_TABLES).await?;
+        missing.extend(self.missing_projections(EXPECTED_PROJECTIONS).await?);
+        if let Some(sorting_key) = self.sorting_key("candlesticks").await? {
+            if sorting_key != CANDLESTICKS_SORTING_KEY {
+                missing.push(format!(
+                    "candlesticks sorting key ({CANDLESTICKS_SORTING_KEY}), \
+                    run `sonar db rekey-candlesticks`"
+                ));
+            }
+        }
+        if !missing.is_empty() {
+            let detail = format!("missing {}", missing.join(", "));
+            return Err(StorageError::SchemaMismatch { detail }.into());
+        }
+        Ok(())
+    }
+
+    /// initialize initializes the clickhouse database, a no-op once initialized
+    async fn initialize(&mut self) -> Result<()> {
+        if self.is_initialized {
+            return Ok(());
         }
-        if let Some(token) = token {
-            conditions.push(format!("pubkey = '{}'", token));
+        debug!(initialize_schema = self.initialize_schema, "initializing clickhouse");
+
+        if self.initialize_schema {
+            // a conflict with another service adding the same projection is retried
+            self.ensure_projections().await?;
         }
-        if let Some(address) = address {
-            conditions.push(format!("has(signers, '{}')", address));
+        // otherwise another service runs the DDL and the writers wait until it is done,
+        // the destructive migrations are left to `sonar db`
+        self.verify_schema().await?;
+
+        let swap_event_inserter = Arc::new(self.create_swap_event_inserter());
+        self.spawn_spill_recovery(swap_event_inserter.clone());
+        self.swap_event_inserter = Some(swap_event_inserter);
+
+        let token_inserter = self.create_token_inserter()?;
+        let token_inserter = Arc::new(RwLock::new(Some(token_inserter)));
+        self.token_inserter = Some(token_inserter);
+
+        let reference_price_inserter = self.create_reference_price_inserter()?;
+        self.reference_price_inserter = Some(Arc::new(RwLock::new(Some(reference_price_inserter))));
+
+        self.is_initialized = true;
+
+        Ok(())
+    }
+
+    /// flush commits the swap events, tokens and reference prices pending in the inserters
+    async fn flush(&self) -> Result<()> {
+        if let Some(inserter) = &self.swap_event_inserter {
+            let rows = inserter.flush().await?;
+            info!("Flushed {} swap events", rows);
         }
-        if let Some(signature) = signature {
-            conditions.push(format!("signature = '{}'", signature));
-            conditions.push("timestamp >= toUnixTimestamp(now() - INTERVAL 1 HOUR)".to_string());
+        if let Some(inserter) = &self.token_inserter {
+            if let Some(inserter) = inserter.write().await.as_mut() {
+                let stats = inserter.force_commit().await.map_err(classified)?;
+                info!("Flushed {} tokens", stats.rows);
+            }
         }
-        if conditions.is_empty() {
-            return Ok(vec![]);
+        if let Some(inserter) = &self.reference_price_inserter {
+            if let Some(inserter) = inserter.write().await.as_mut() {
+                let stats = inserter.force_commit().await.map_err(classified)?;
+                info!("Flushed {} reference prices", stats.rows);
+            }
         }
-        let query = format!(
-            r#"
-            SELECT
-                pair,
-                pubkey,
-                price,
-                market_cap,
-                base_amount,
-                quote_amount,
-                swap_amount,
-                owner,
-                signature,
-                signers,
-                slot,
-                timestamp,
-                is_buy,
-                is_pump
-            FROM swap_events
-            WHERE {cond}
-            ORDER BY timestamp DESC
-            LIMIT {limit} OFFSET {offset}
-        "#,
-            cond = conditions.join(" AND "),
-            limit = limit.unwrap_or(100),
-            offset = offset.unwrap_or(0),
-        );
-        let result = self.client.query(&query).fetch_all::<Trade>().await?;
-        Ok(result)
+        Ok(())
     }
 
-    /// get_price returns the price of a given mint at a given timestamp
+    /// close commits the pending rows and ends the inserters, closing twice is a no-op
+    async fn close(&self) -> Result<()> {
+        if let Some(inserter) = &self.swap_event_inserter {
+            let rows = inserter.close().await?;
+            info!("Closed the swap event inserter, flushed {} swap events", rows);
+        }
+        if let Some(inserter) = &self.token_inserter {
+            if let Some(inserter) = inserter.write().await.take() {
+                let stats = inserter.end().await.map_err(classified)?;
+                info!("Closed the token inserter, flushed {} tokens", stats.rows);
+            }
+        }
+        if let Some(inserter) = &self.reference_price_inserter {
+            if let Some(inserter) = inserter.write().await.take() {
+                let stats = inserter.end().await.map_err(classified)?;
+                info!("Closed the reference price inserter, flushed {} prices", stats.rows);
+            }
+        }
+        Ok(())
+    }
+
+    /// insert_swap_event uses a batched writer to avoid spamming writes
+    /// it is configurable at the initializer
+    async fn insert_swap_event(&self, swap_event: &SwapEvent) -> Result<()> {
+        debug!("inserting swap event: {}", swap_event.signature);
+
+        let inserter = self
+            .swap_event_inserter
+            .as_ref()
+            .ok_or_else(|| not_initialized("no swap event inserter"))?;
+
+        let rows = inserter.write(swap_event.clone()).await?;
+        if rows > 0 {
+            info!("Committed {} swap events", rows);
+        }
+        Ok(())
+    }
+
+    /// insert_reference_price uses a batched writer, the prices are sampled once per second
+    async fn insert_reference_price(&self, price: &ReferencePrice) -> Result<()> {
+        let mut inserter = self
+            .reference_price_inserter
+            .as_ref()
+            .ok_or_else(|| not_initialized("no reference price inserter"))?
+            .write()
+            .await;
+        let inserter = inserter.as_mut().context("reference price inserter is closed")?;
+        inserter.write(price).map_err(classified)?;
+        let stats = inserter.commit().await.map_err(classified)?;
+        if stats.rows > 0 {
+            debug!("Committed {} reference prices", stats.rows);
+        }
+        Ok(())
+    }
+
+    /// get_candlesticks_by_token returns a list of candlesticks for a given token and interval,
+    /// the buckets without swap events are read from the token-level candlesticks
     #[instrument(skip(self))]
-    async fn get_price(&self, token: &str, timestamp: i32) -> Result<TokenPrice> {
-        let token = token.to_string();
+    async fn get_candlesticks_by_token(
+        &self,
+        mint: &str,
+        pairs: &[String],
+        interval: CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Result<Vec<Candlestick>> {
+        self.ensure_initialized()?;
+        let interval_seconds = interval.get_seconds();
+        let limit = limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT);
+        let swap_events_from =
+            interval.bounded_time_from(limit, time_from, time_to, Utc::now().timestamp());
+        let mut conditions = vec![
+            "pubkey = ?".to_string(),
+            FINITE_PRICE.to_string(),
+            format!("timestamp >= {}", swap_events_from),
+        ];
+
+        if let Some(time_to) = time_to {
+            conditions.push(format!("timestamp < {}", time_to));
+        }
+        if !pairs.is_empty() {
+            conditions.push("pair IN ?".to_string());
+        }
+
         let query = format!(
             r#"
             SELECT
-                price,
-                timestamp
+                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
+                argMin(price, timestamp) as open,
+                {high_low},
+                argMax(price, timestamp) as close,
+                sum(base_amount) as volume,
+                sum(swap_amount) as turnover,
+                {SIDE_VOLUMES}
             FROM swap_events
-            WHERE pubkey = '{}' AND timestamp <= {}
-            ORDER BY timestamp DESC
-            LIMIT 1
+            WHERE {conditions}
+            GROUP BY bucket
+            ORDER BY bucket DESC
+            LIMIT {limit}
             "#,
-            token, timestamp
+            high_low = outlier_policy.high_low_sql(),
+            conditions = conditions.join(" AND "),
+            limit = limit
         );
-        let result = self.client.query(&query).fetch_optional::<(f64, i32)>().await?;
-        let price = match result {
-            Some((price, neatest_timestamp)) => TokenPrice {
-                token,
-                timestamp,
-                price: Some(price),
-                neatest_timestamp: Some(neatest_timestamp),
-            },
-            None => TokenPrice { token, price: None, timestamp, neatest_timestamp: None },
-        };
-        Ok(price)
+
+        let query = &query;
+        let result = self
+            .read(|client| async move {
+                let mut query_builder = client.query(query).bind(mint);
+                if !pairs.is_empty() {
+                    query_builder = query_builder.bind(pairs);
+                }
+                query_builder.fetch_all::<Candlestick>().await
+            })
+            .await?;
+
+        // Reverse the order of the candlesticks
+        let candlesticks: Vec<Candlestick> = result.into_iter().rev().collect();
+        // the token-level candlesticks can't be narrowed down to some pairs
+        if !pairs.is_empty() || candlesticks.len() >= limit {
+            return Ok(candlesticks);
+        }
+        let exclude_buckets =
+            candlesticks.iter().map(|c| interval.bucket_start(c.timestamp)).collect::<Vec<_>>();
+        let additional_candlesticks = self
+            .get_candlesticks_from_candlesticks(
+                TOKEN_CANDLESTICK_PAIR,
+                Some(mint),
+                &interval,
+                Some(limit - candlesticks.len()),
+                time_from,
+                time_to,
+                Some(exclude_buckets),
+            )
+            .await?;
+        Ok(merge_candlesticks(candlesticks, additional_candlesticks, &interval, limit))
     }
 
-    /// get_prices returns prices for multiple mint and timestamp combinations
+    /// get_pairs_for_token returns the pairs a token traded in since a given timestamp.
+    ///
+    /// Swap events are complemented with minute candlesticks older than the oldest
+    /// remaining swap event of the token, so pairs whose swap events were removed
+    /// are still returned; their `last_trade_ts` is the start of the candlestick.
     #[instrument(skip(self))]
-    async fn get_prices(&self, tokens: Vec<(&str, i32)>) -> Result<Vec<TokenPrice>> {
-        let tasks = tokens.into_iter().map(|(mint, timestamp)| self.get_price(mint, timestamp));
-        let results = future::try_join_all(tasks).await?;
-        Ok(results)
+    async fn get_pairs_for_token(&self, mint: &str, since: Option<u64>) -> Result<Vec<PairInfo>> {
+        self.ensure_initialized()?;
+        let query = r#"
+            WITH
+                ? AS token,
+                ? AS since,
+                coalesce(
+                    (SELECT minOrNull(timestamp) FROM swap_events WHERE pubkey = token),
+                    toUInt64(toUnixTimestamp(now()))
+                ) AS oldest_swap_event
+            SELECT
+                t.pair AS pair,
+                nullIf(p.recorded_dex, '') AS dex,
+                t.turnover AS turnover,
+                t.last_trade_ts AS last_trade_ts
+            FROM (
+                SELECT pair, sum(turnover) AS turnover, max(last_trade_ts) AS last_trade_ts
+                FROM (
+                    SELECT pair, sum(swap_amount) AS turnover, max(timestamp) AS last_trade_ts
+                    FROM swap_events
+                    WHERE pubkey = token AND timestamp >= since
+                    GROUP BY pair
+                    UNION ALL
+                    SELECT pair, sum(turnover) AS turnover, max(timestamp) AS last_trade_ts
+                    FROM candlesticks FINAL
+                    WHERE pubkey = token AND pair != '' AND interval = 60
+                        AND timestamp >= since AND timestamp < oldest_swap_event
+                    GROUP BY pair
+                )
+                GROUP BY pair
+            ) AS t
+            LEFT JOIN (
+                SELECT pair, argMin(dex, first_seen) AS recorded_dex
+                FROM pairs
+                WHERE base_mint = token OR quote_mint = token
+                GROUP BY pair
+            ) AS p ON t.pair = p.pair
+            ORDER BY turnover DESC, last_trade_ts DESC
+            "#;
+        let since = since.unwrap_or_default();
+        let result = self
+            .read(|client| async move {
+                client.query(query).bind(mint).bind(since).fetch_all::<PairInfo>().await
+            })
+            .await?;
+        Ok(result)
     }
 
-    /// insert_token inserts a token into the database
+    /// insert_pair records the mints and dex of a pair, the pairs are read by their earliest record
     #[instrument(skip(self))]
-    async fn insert_token(&self, token: &Token) -> Result<()> {
-        let mut inserter =
-            self.token_inserter.as_ref().expect("token inserter not initialized").write().await;
-        inserter.write(token)?;
-
-        let pending = inserter.pending();
-        debug!("Pending: {} rows ({} bytes)", pending.rows, pending.bytes);
-
-        let stats = inserter.commit().await?;
-        if stats.rows > 0 {
-            debug!(
-                "Committed {} tokens {} bytes in {} transactions",
-                stats.rows, stats.bytes, stats.transactions
-            );
-        }
-
+    async fn insert_pair(&self, pair: &Pair) -> Result<()> {
+        self.ensure_initialized()?;
+        let mut insert = self.write_client().insert::<Pair>("pairs").map_err(classified)?;
+        insert.write(pair).await.map_err(classified)?;
+        insert.end().await.map_err(classified)?;
         Ok(())
     }
 
-    /// get_token returns a token from the database
-    // #[instrument(skip(self))] skip because it's called in multiple places
-    async fn get_token(&self, token: &str) -> Result<Option<Token>> {
-        let query = format!(
-            r#"
-            SELECT * FROM tokens WHERE token = '{}' LIMIT 1
-            "#,
-            token
-        );
-        let result = self.client.query(&query).fetch_optional::<Token>().await?;
+    /// get_pair_detail returns the mints, the latest price and the 24h stats of a pair,
+    /// a recorded pair whose swap events were pruned is priced by its latest candlestick
+    #[instrument(skip(self))]
+    async fn get_pair_detail(&self, pair: &str) -> Result<Option<PairDetail>> {
+        self.ensure_initialized()?;
+        let query = r#"
+            WITH
+                ? AS target,
+                toUInt64(toUnixTimestamp(now())) - 86400 AS day_ago
+            SELECT
+                target AS pair,
+                if(p.recorded_base_mint != '', p.recorded_base_mint, s.traded_base_mint) AS base_mint,
+                nullIf(p.recorded_quote_mint, '') AS quote_mint,
+                nullIf(p.recorded_dex, '') AS dex,
+                if(s.latest_trade_ts > 0, s.latest_price, c.latest_close) AS price,
+                s.volume_24h AS volume_24h,
+                s.turnover_24h AS turnover_24h,
+                s.trades_24h AS trades_24h,
+                multiIf(
+                    p.earliest_seen = 0, s.earliest_trade_ts,
+                    s.earliest_trade_ts = 0, p.earliest_seen,
+                    least(p.earliest_seen, s.earliest_trade_ts)
+                ) AS first_seen,
+                if(s.latest_trade_ts > 0, s.latest_trade_ts, c.latest_candle_ts) AS last_trade_ts
+            FROM (
+                SELECT
+                    pair,
+                    argMin(base_mint, first_seen) AS recorded_base_mint,
+                    argMin(quote_mint, first_seen) AS recorded_quote_mint,
+                    argMin(dex, first_seen) AS recorded_dex,
+                    min(first_seen) AS earliest_seen
+                FROM pairs
+                WHERE pair = target
+                GROUP BY pair
+            ) AS p
+            FULL OUTER JOIN (
+                SELECT
+                    pair,
+                    topK(1)(pubkey)[1] AS traded_base_mint,
+                    argMax(price, timestamp) AS latest_price,
+                    sumIf(base_amount, timestamp >= day_ago) AS volume_24h,
+                    sumIf(swap_amount, timestamp >= day_ago) AS turnover_24h,
+                    countIf(timestamp >= day_ago) AS trades_24h,
+                    min(timestamp) AS earliest_trade_ts,
+                    max(timestamp) AS latest_trade_ts
+                FROM swap_events
+                WHERE pair = target
+                GROUP BY pair
+            ) AS s ON p.pair = s.pair
+            CROSS JOIN (
+                -- the end of the latest candlestick of any interval
+                SELECT
+                    argMax(close, timestamp + interval) AS latest_close,
+                    max(timestamp + interval) AS latest_candle_ts
+                FROM candlesticks
+                WHERE pair = target
+            ) AS c
+            "#;
+        let result = self
+            .read(|client| async move {
+                client.query(query).bind(pair).fetch_optional::<PairDetail>().await
+            })
+            .await?;
         Ok(result)
     }
 
-    /// get_tokens returns a list of tokens from the database
+    /// get_fee_stats returns the fees collected since a given timestamp per pair and fee mint
     #[instrument(skip(self))]
-    async fn get_tokens(&self, tokens: &[&str]) -> Result<Vec<Token>> {
-        let addrs = tokens.iter().map(|s| format!("'{}'", s)).collect::<Vec<_>>().join(",");
+    async fn get_fee_stats(
+        &self,
+        pair: Option<&str>,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<FeeStat>> {
+        self.ensure_initialized()?;
+        let pair_condition = if pair.is_some() { "AND pair = ?" } else { "" };
         let query = format!(
             r#"
-            SELECT * FROM tokens WHERE token IN ({})
-            "#,
-            addrs
+            SELECT
+                pair,
+                fee_mint,
+                sum(fee_amount) AS total_fee,
+                sum(swap_amount) AS total_swap_amount,
+                count() AS swaps
+            FROM swap_events
+            WHERE timestamp >= ? AND fee_amount > 0 {pair_condition}
+            GROUP BY pair, fee_mint
+            ORDER BY total_fee DESC
+            LIMIT ?
+            "#
         );
-        let result = self.client.query(&query).fetch_all::<Token>().await?;
+        let query = &query;
+        let result = self
+            .read(|client| async move {
+                let mut query_builder = client.query(query).bind(since);
+                if let Some(pair) = pair {
+                    query_builder = query_builder.bind(pair);
+                }
+                query_builder.bind(limit as u64).fetch_all::<FeeStat>().await
+            })
+            .await?;
         Ok(result)
     }
 
-    /// has_token returns true if a token exists in the database
-    async fn has_token(&self, token: &str) -> Result<bool> {
+    /// get_wallet_activity returns the trades of a wallet aggregated per token,
+    /// ordered by turnover
+    #[instrument(skip(self))]
+    async fn get_wallet_activity(
+        &self,
+        address: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        strict_owner: bool,
+        limit: usize,
+    ) -> Result<Vec<WalletActivity>> {
+        self.ensure_initialized()?;
+        let wallet_condition = if strict_owner { "owner = ?" } else { "has(signers, ?)" };
+        let start_condition = if start_time.is_some() { "AND timestamp >= ?" } else { "" };
+        let end_condition = if end_time.is_some() { "AND timestamp <= ?" } else { "" };
         let query = format!(
             r#"
-            SELECT COUNT(*) FROM tokens WHERE token = '{}'
-            "#,
-            token
+            SELECT
+                pubkey AS token,
+                count() AS trade_count,
+                sumIf(base_amount, is_buy) AS buy_base_amount,
+                sumIf(base_amount, NOT is_buy) AS sell_base_amount,
+                buy_base_amount - sell_base_amount AS net_base_amount,
+                sumIf(swap_amount, is_buy) AS buy_turnover,
+                sumIf(swap_amount, NOT is_buy) AS sell_turnover,
+                max(timestamp) AS last_trade_ts
+            FROM swap_events
+            WHERE {wallet_condition} {start_condition} {end_condition}
+            GROUP BY pubkey
+            ORDER BY buy_turnover + sell_turnover DESC, token
+            LIMIT ?
+            "#
         );
-        let result = self.client.query(&query).fetch_optional::<u64>().await?;
-        Ok(result.is_some())
+        let query = &query;
+        let result = self
+            .read(|client| async move {
+                let mut query_builder = client.query(query).bind(address);
+                if let Some(start_time) = start_time {
+                    query_builder = query_builder.bind(start_time);
+                }
+                if let Some(end_time) = end_time {
+                    query_builder = query_builder.bind(end_time);
+                }
+                query_builder.bind(limit as u64).fetch_all::<WalletActivity>().await
+            })
+            .await?;
+        Ok(result)
     }
 
-    /// search_tokens returns a list of tokens that match a given query
+    /// get_candlesticks_by_pair returns a list of candlesticks for a given pair and interval,
+    /// the ones of a reference pair are aggregated from the reference prices
     #[instrument(skip(self))]
-    async fn search_tokens(&self, text: &str) -> Result<Vec<TokenSearch>> {
-        let query = format!(
-            r#"
-            SELECT 
-                token, name, symbol, decimals, supply, latest_price, price_24h, tx_count_24h, volume_24h, turnover_24h
-            FROM token_search_with_stats_v 
-            WHERE token = '{}' OR symbol ILIKE '%{}' OR symbol ILIKE '{}%' OR name ILIKE '%{}' OR name ILIKE '{}%'
-            ORDER BY turnover_24h DESC
-            LIMIT 10
-            "#,
-            text, text, text, text, text,
-        );
-        debug!(
-            query = %query,
-            table = "token_search_with_stats_v",
-            "Executing SQL query"
+    async fn get_candlesticks_by_pair(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Result<Vec<Candlestick>> {
+        self.ensure_initialized()?;
+        if is_reference_pair(pair) {
+            return self
+                .get_reference_candlesticks(pair, interval, limit, time_from, time_to)
+                .await;
+        }
+        let size = limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT);
+        let candlesticks = self
+            .get_candlesticks_from_swap_events(
+                pair,
+                token,
+                interval,
+                outlier_policy,
+                Some(size),
+                time_from,
+                time_to,
+            )
+            .await?;
+        if candlesticks.len() >= size {
+            return Ok(candlesticks);
+        }
+        let exclude_buckets =
+            candlesticks.iter().map(|c| interval.bucket_start(c.timestamp)).collect::<Vec<_>>();
+        let additional_candlesticks = self
+            .get_candlesticks_from_candlesticks(
+                pair,
+                token,
+                interval,
+                Some(size - candlesticks.len()),
+                time_from,
+                time_to,
+                Some(exclude_buckets),
+            )
+            .await?;
+        Ok(merge_candlesticks(candlesticks, additional_candlesticks, interval, size))
+    }
+
+    /// get_latest_candlestick aggregates the swap events of the current and previous buckets,
+    /// a current bucket without trades is returned flat at the previous close
+    #[instrument(skip(self))]
+    async fn get_latest_candlestick(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
+        at: u64,
+    ) -> Result<LatestCandlestick> {
+        self.ensure_initialized()?;
+        let interval_seconds = interval.get_seconds() as u64;
+        let current_bucket = interval.bucket_start(at);
+        let previous_bucket = current_bucket.saturating_sub(interval_seconds);
+        let token_condition = if token.is_some() { "AND pubkey = ?" } else { "" };
+        let query = format!(
+            r#"
+            SELECT
+                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
+                argMin(price, timestamp) as open,
+                {high_low},
+                argMax(price, timestamp) as close,
+                sum(base_amount) as volume,
+                sum(swap_amount) as turnover,
+                {SIDE_VOLUMES}
+            FROM swap_events
+            WHERE pair = ? {token_condition} AND timestamp >= ? AND timestamp < ?
+                AND {FINITE_PRICE}
+            GROUP BY bucket
+            ORDER BY bucket
+            "#,
+            high_low = outlier_policy.high_low_sql(),
         );
+        debug!(query = %query, table = "swap_events", "Executing SQL query");
 
-        let result = self.client.query(&query).fetch_all::<TokenSearch>().await?;
-        Ok(result)
+        let query = &query;
+        let rows = self
+            .read(|client| async move {
+                let mut query = client.query(query).bind(pair);
+                if let Some(token) = token {
+                    query = query.bind(token);
+                }
+                query
+                    .bind(previous_bucket)
+                    .bind(current_bucket + interval_seconds)
+                    .fetch_all::<Candlestick>()
+                    .await
+            })
+            .await
+            .context("Failed to fetch the latest candlesticks")?;
+        Ok(LatestCandlestick::from_buckets(rows, interval, at))
     }
 
-    /// aggregate_into_candlesticks aggregates swap events into candlesticks table
-    async fn aggregate_into_candlesticks(
+    #[instrument(skip(self))]
+    async fn get_candlesticks_from_swap_events(
         &self,
-        start_time: i64,
-        end_time: i64,
-        interval: CandlestickInterval,
-    ) -> Result<()> {
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Result<Vec<Candlestick>> {
+        self.ensure_initialized()?;
         let interval_seconds = interval.get_seconds();
+        let limit = limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT);
+        // the older buckets of a quiet pair are read from the candlesticks table instead
+        let time_from =
+            interval.bounded_time_from(limit, time_from, time_to, Utc::now().timestamp());
+        let pairs = pair.split(",").collect::<Vec<_>>();
+        let mut conditions = vec![
+            "pair IN ?".to_string(),
+            FINITE_PRICE.to_string(),
+            format!("timestamp >= {}", time_from),
+        ];
+        if token.is_some() {
+            conditions.push("pubkey = ?".to_string());
+        }
+        if let Some(time_to) = time_to {
+            conditions.push(format!("timestamp < {}", time_to));
+        }
         let query = format!(
             r#"
-            INSERT INTO candlesticks
             SELECT
-                pair,
-                pubkey,
-                {interval_seconds} as interval,
-                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as tp,
+                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
                 argMin(price, timestamp) as open,
-                max(price) as high,
-                min(price) as low,
+                {high_low},
                 argMax(price, timestamp) as close,
                 sum(base_amount) as volume,
-                sum(swap_amount) as turnover
+                sum(swap_amount) as turnover,
+                {SIDE_VOLUMES}
             FROM swap_events
-            WHERE timestamp >= {start_time} AND timestamp < {end_time}
-            GROUP BY pubkey, pair, tp
+            WHERE {conditions}
+            GROUP BY bucket
+            ORDER BY bucket DESC
+            LIMIT {limit}
             "#,
+            high_low = outlier_policy.high_low_sql(),
+            conditions = conditions.join(" AND "),
             interval_seconds = interval_seconds,
-            start_time = start_time,
-            end_time = end_time
+            limit = limit
         );
-        self.client.query(&query).execute().await?;
-        Ok(())
+        debug!(
+            query = %query,
+            table = "swap_events",
+            "Executing SQL query"
+        );
+
+        let (query, pairs) = (&query, &pairs);
+        let result = self
+            .read(|client| async move {
+                let mut query_builder = client.query(query).bind(pairs);
+                if let Some(token) = token {
+                    query_builder = query_builder.bind(token);
+                }
+                query_builder.fetch_all::<Candlestick>().await
+            })
+            .await?;
+        // Reverse the order of the candlesticks
+        let candlesticks = result.into_iter().rev().collect();
+        Ok(candlesticks)
     }
 
-    /// remove_swap_events removes swap events from the database
-    async fn remove_swap_events(&self, timestamp: i64) -> Result<()> {
-        let dt =
-            DateTime::from_timestamp(timestamp, 0).context("Failed to create UTC timestamp")?;
-        let yyyymmdd = dt.format("%Y%m%d").to_string();
-        let query: String = format!("ALTER TABLE swap_events DROP PARTITION {}", yyyymmdd);
-        debug!(query = %query, "Removing swap events from partition");
-        self.client.query(&query).execute().await?;
-        debug!("Removed swap events from partition: {}", yyyymmdd);
-        Ok(())
+    #[instrument(skip(self))]
+    async fn get_candlesticks_from_candlesticks(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+        exclude_buckets: Option<Vec<u64>>,
+    ) -> Result<Vec<Candlestick>> {
+        self.ensure_initialized()?;
+        let interval_seconds = interval.get_seconds();
+        let candlestick_interval = interval.get_candlestick_interval();
+        let mut pairs = pair.split(",").filter(|s| !s.is_empty()).collect::<Vec<_>>();
+        // without a pair the token-level candlesticks are read, which need the token
+        match (pairs.is_empty(), token) {
+            (false, _) => {}
+            (true, Some(_)) => pairs.push(TOKEN_CANDLESTICK_PAIR),
+            (true, None) => return Ok(vec![]),
+        };
+        let mut conditions = vec!["pair IN ?".to_string(), FINITE_CANDLESTICK.to_string()];
+        if token.is_some() {
+            conditions.push("pubkey = ?".to_string());
+        }
+        if let Some(time_from) = time_from {
+            conditions.push(format!("timestamp >= {}", time_from));
+        }
+        if let Some(time_to) = time_to {
+            conditions.push(format!("timestamp < {}", time_to));
+        }
+        if let Some(exclude_buckets) = exclude_buckets {
+            if !exclude_buckets.is_empty() {
+                let buckets =
+                    exclude_buckets.iter().map(|s| format!("{}", s)).collect::<Vec<_>>().join(",");
+                // the rows are excluded by their bucket, as computed for the swap events
+                conditions.push(format!(
+                    "intDiv(timestamp, {interval_seconds}) * {interval_seconds} NOT IN ({buckets})"
+                ));
+            }
+        }
+        let query = format!(
+            r#"
+            SELECT
+                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as bucket,
+                argMin(open, timestamp) as open,
+                max(high) as high,
+                min(low) as low,
+                argMax(close, timestamp) as close,
+                sum(volume) as volume,
+                sum(turnover) as turnover,
+                {CANDLESTICK_SIDE_VOLUMES}
+            FROM candlesticks
+            WHERE {conditions} AND interval = {candlestick_interval}
+            GROUP BY bucket
+            ORDER BY bucket DESC
+            LIMIT {limit}
+            "#,
+            conditions = conditions.join(" AND "),
+            interval_seconds = interval_seconds,
+            candlestick_interval = candlestick_interval,
+            limit = limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT)
+        );
+        debug!(
+            query = %query,
+            table = "candlesticks",
+            "Executing SQL query"
+        );
+
+        let (query, pairs) = (&query, &pairs);
+        let result = self
+            .read(|client| async move {
+                let mut query_builder = client.query(query).bind(pairs);
+                if let Some(token) = token {
+                    query_builder = query_builder.bind(token);
+                }
+                query_builder.fetch_all::<Candlestick>().await
+            })
+            .await?;
+
+        // Reverse the order of the candlesticks
+        let candlesticks = result.into_iter().rev().collect();
+
+        Ok(candlesticks)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_top_tokens(
+        &self,
+        limit: usize,
+        offset: usize,
+        start_time: u64,
+        min_volume: Option<f64>,
+        min_market_cap: Option<f64>,
+        pumpfun: Option<bool>,
+        exclude_wash: bool,
+        sort_by: TopTokensSort,
+        order: SortOrder,
+    ) -> Result<TopTokensPage> {
+        self.ensure_initialized()?;
+        let now = Utc::now().timestamp().max(0) as u64;
+        let source = match self.precomputed_top_tokens(now, start_time, exclude_wash).await {
+            Some((window, computed_at)) => TopTokensSource::Precomputed { window, computed_at },
+            None => TopTokensSource::Live { start_time },
+        };
+        self.fetch_top_tokens(
+            source,
+            limit,
+            offset,
+            min_volume,
+            min_market_cap,
+            pumpfun,
+            exclude_wash,
+            sort_by,
+            order,
+        )
+        .await
+    }
+
+    /// get_token_stats returns a list of token stats for a given list of tokens
+    #[instrument(skip(self))]
+    async fn get_token_stats(
+        &self,
+        mints: Vec<String>,
+        exclude_wash: bool,
+    ) -> Result<Vec<TokenStat>> {
+        self.ensure_initialized()?;
+        let wash_condition = if exclude_wash { "AND NOT is_wash" } else { "" };
+        let query = format!(
+            r#"
+            WITH 
+                now() AS current_time, 
+                toUnixTimestamp(current_time) AS current_ts 
+
+            SELECT 
+                pubkey,
+                argMax(price, timestamp) AS latest_price, 
+                argMax(market_cap, timestamp) AS latest_market_cap,
+
+                coalesce(
+                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 300), 0.0), 
+                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 300)
+                ) AS price_5m,
+
+                coalesce(
+                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 3600), 0.0), 
+                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 3600)
+                ) AS price_1h,
+
+                coalesce(
+                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 21600), 0.0), 
+                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 21600)
+                ) AS price_6h,
+
+                coalesce(
+                    NULLIF(argMax(price, timestamp) FILTER(WHERE timestamp <= current_ts - 86400), 0.0), 
+                    argMin(price, timestamp) FILTER(WHERE timestamp > current_ts - 86400)
+                ) AS price_24h,
+
+                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 300) AS volume_5m,
+                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 3600) AS volume_1h,
+                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 21600) AS volume_6h,
+                sum(base_amount) FILTER(WHERE timestamp >= current_ts - 86400) AS volume_24h,
+
+                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 300) AS turnover_5m,
+                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 3600) AS turnover_1h,
+                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 21600) AS turnover_6h,
+                sum(swap_amount) FILTER(WHERE timestamp >= current_ts - 86400) AS turnover_24h,
+
+                {UNIQUE_TRADERS_24H}
+            FROM swap_events
+            WHERE pubkey IN ? AND {FINITE_PRICE} {wash_condition}
+            GROUP BY pubkey
+            "#
+        );
+        let query = &query;
+        let mints = &mints;
+        let result =
+            self.read(|client| async move {
+                client.query(query).bind(mints).fetch_all::<TokenStat>().await
+            })
+            .await?;
+        Ok(result)
+    }
+
+    /// get_token_daily_stats returns a list of token daily stats for a given list of tokens
+    #[instrument(skip(self))]
+    async fn get_token_daily_stats(&self, tokens: Vec<String>) -> Result<Vec<TokenDailyStat>> {
+        self.ensure_initialized()?;
+        // the signers are counted in the same pass as the volumes, the 24h view doesn't keep
+        // them and joining it would scan the day twice
+        let query = &format!(
+            r#"
+            WITH toUnixTimestamp(now()) AS current_ts
+            SELECT 
+                pubkey,
+                max(timestamp) AS timestamp,
+                argMax(price, timestamp) AS price,
+                argMax(market_cap, timestamp) AS market_cap,
+                argMin(price, timestamp) AS price_24h,
+                sum(base_amount) AS volume_24h,
+                sum(swap_amount) AS turnover_24h,
+                {UNIQUE_TRADERS_24H}
+            FROM swap_events
+            WHERE pubkey IN ? AND timestamp >= current_ts - 86400 AND {FINITE_PRICE}
+            GROUP BY pubkey
+            "#
+        );
+        let tokens = &tokens;
+        let result = self
+            .read(|client| async move {
+                client.query(query).bind(tokens).fetch_all::<TokenDailyStat>().await
+            })
+            .await?;
+        Ok(result)
+    }
+
+    /// get_trades returns a list of trades for a given query, a signature without `time_from`
+    /// reads the partitions of the last hour first
+    #[instrument(skip(self))]
+    async fn get_trades(&self, filter: &TradeFilter) -> Result<Vec<Trade>> {
+        self.ensure_initialized()?;
+        // the filters are bound in the order of their conditions
+        let mut conditions = vec![];
+        let mut binds = vec![];
+        if let Some(pair) = &filter.pair {
+            conditions.push("pair = ?");
+            binds.push(TradeBind::Str(pair.clone()));
+        }
+        if let Some(token) = &filter.token {
+            conditions.push("pubkey = ?");
+            binds.push(TradeBind::Str(token.clone()));
+        }
+        if let Some(signature) = &filter.signature {
+            conditions.push("signature = ?");
+            binds.push(TradeBind::Str(signature.clone()));
+        }
+        let address = filter.address.as_deref();
+        if conditions.is_empty() && address.is_none() {
+            return Ok(vec![]);
+        }
+        if let Some(category) = filter.category {
+            conditions.push(
+                "owner IN (
+                    SELECT address FROM wallet_labels
+                    GROUP BY address
+                    HAVING argMax(category, added_at) = ?
+                )",
+            );
+            binds.push(TradeBind::Str(category.to_string()));
+        }
+        if let Some(min_pct_supply) = filter.min_pct_supply {
+            conditions.push("pct_of_supply >= ?");
+            binds.push(TradeBind::F64(min_pct_supply));
+        }
+        if let Some(slot) = filter.slot {
+            conditions.push("slot = ?");
+            binds.push(TradeBind::U64(slot));
+        }
+        if let Some(time_from) = filter.time_from {
+            conditions.push("timestamp >= ?");
+            binds.push(TradeBind::U64(time_from));
+        }
+        if let Some(time_to) = filter.time_to {
+            conditions.push("timestamp < ?");
+            binds.push(TradeBind::U64(time_to));
+        }
+        let (limit, offset) = (filter.limit.unwrap_or(100), filter.offset.unwrap_or(0));
+        if filter.signature.is_none() || filter.time_from.is_some() {
+            return self.fetch_trades(&conditions, &binds, address, limit, offset).await;
+        }
+
+        // most signature lookups are of recent trades, the last hour prunes the partitions
+        let mut recent = conditions.clone();
+        recent.push("timestamp >= toUnixTimestamp(now() - INTERVAL 1 HOUR)");
+        let trades = self.fetch_trades(&recent, &binds, address, limit, offset).await?;
+        if !trades.is_empty() {
+            return Ok(trades);
+        }
+        let window = match filter.slot {
+            Some(slot) => self.slot_time_window(slot).await?,
+            None => None,
+        };
+        if let Some((from, to)) = window {
+            conditions.push("timestamp >= ? AND timestamp < ?");
+            binds.extend([TradeBind::U64(from), TradeBind::U64(to)]);
+        }
+        debug!(signature = ?filter.signature, ?window, "Signature not found in the last hour");
+        self.fetch_trades(&conditions, &binds, address, limit, offset).await
+    }
+
+    /// stream_trades streams the trades matching the filter page by page
+    fn stream_trades(&self, filter: TradeFilter) -> BoxStream<'_, Result<Trade>> {
+        let limit = filter.limit;
+        paginate_trades(TRADE_PAGE_SIZE, limit, move |cursor, size| {
+            let filter = filter.clone();
+            async move { self.get_trades_page(&filter, cursor, size).await }
+        })
+    }
+
+    /// get_price returns the price of a given mint at a given timestamp
+    #[instrument(skip(self))]
+    async fn get_price(&self, token: &str, timestamp: i32) -> Result<TokenPrice> {
+        self.ensure_initialized()?;
+        let token = token.to_string();
+        let query = r#"
+            SELECT
+                price,
+                timestamp
+            FROM swap_events
+            WHERE pubkey = ? AND timestamp <= ?
+            ORDER BY timestamp DESC
+            LIMIT 1
+            "#;
+        let token_ref = &token;
+        let result = self
+            .read(|client| async move {
+                client
+                    .query(query)
+                    .bind(token_ref)
+                    .bind(timestamp)
+                    .fetch_optional::<(f64, i32)>()
+                    .await
+            })
+            .await?;
+        let price = match result {
+            Some((price, neatest_timestamp)) => TokenPrice {
+                token,
+                timestamp,
+                price: Some(price),
+                neatest_timestamp: Some(neatest_timestamp),
+                source: Some(PriceSource::Db),
+            },
+            None => TokenPrice {
+                token,
+                price: None,
+                timestamp,
+                neatest_timestamp: None,
+                source: Some(PriceSource::Db),
+            },
+        };
+        Ok(price)
+    }
+
+    /// get_prices returns prices for multiple mint and timestamp combinations
+    #[instrument(skip(self))]
+    async fn get_prices(&self, tokens: Vec<(&str, i32)>) -> Vec<Result<TokenPrice>> {
+        get_prices_retrying(self, tokens).await
+    }
+
+    /// insert_token inserts a token into the database, unless an identical row of it was
+    /// written within the dedupe window
+    #[instrument(skip(self))]
+    async fn insert_token(&self, token: &Token) -> Result<()> {
+        let Some(token_inserter) = self.token_inserter.as_ref() else {
+            return Err(not_initialized("no token inserter"));
+        };
+        if self.recent_tokens.check_and_record(token) {
+            debug!(mint = %token.token, "Skipped the write of an unchanged token");
+            return Ok(());
+        }
+        // a failed write is forgotten, so the next retrieval is written
+        let forget_on_error = |e: anyhow::Error| {
+            self.recent_tokens.forget(&token.token);
+            e
+        };
+        let mut inserter = token_inserter.write().await;
+        let inserter =
+            inserter.as_mut().context("token inserter is closed").map_err(forget_on_error)?;
+        inserter.write(token).map_err(classified).map_err(forget_on_error)?;
+
+        let pending = inserter.pending();
+        debug!("Pending: {} rows ({} bytes)", pending.rows, pending.bytes);
+
+        let stats = inserter.commit().await.map_err(classified).map_err(forget_on_error)?;
+        if stats.rows > 0 {
+            debug!(
+                "Committed {} tokens {} bytes in {} transactions",
+                stats.rows, stats.bytes, stats.transactions
+            );
+        }
+
+        Ok(())
+    }
+
+    /// get_token returns a token from the database
+    // #[instrument(skip(self))] skip because it's called in multiple places
+    async fn get_token(&self, token: &str) -> Result<Option<Token>> {
+        self.ensure_initialized()?;
+        let query = "SELECT * FROM tokens WHERE token = ? LIMIT 1";
+        let result = self
+            .read(|client| async move {
+                client.query(query).bind(token).fetch_optional::<Token>().await
+            })
+            .await?;
+        let tokens = self.with_token_launches(result.into_iter().collect()).await?;
+        Ok(tokens.into_iter().next())
+    }
+
+    /// get_tokens returns a list of tokens from the database
+    #[instrument(skip(self))]
+    async fn get_tokens(&self, tokens: &[&str]) -> Result<Vec<Token>> {
+        self.ensure_initialized()?;
+        if tokens.is_empty() {
+            return Ok(vec![]);
+        }
+        let query = "SELECT * FROM tokens WHERE token IN ?";
+        let result = self
+            .read(
+                |client| async move { client.query(query).bind(tokens).fetch_all::<Token>().await },
+            )
+            .await?;
+        self.with_token_launches(result).await
+    }
+
+    /// has_token returns true if a token exists in the database
+    async fn has_token(&self, token: &str) -> Result<bool> {
+        self.ensure_initialized()?;
+        // a count would always return a row
+        let query = "SELECT 1 FROM tokens WHERE token = ? LIMIT 1";
+        let result =
+            self.read(|client| async move {
+                client.query(query).bind(token).fetch_optional::<u8>().await
+            })
+            .await?;
+        Ok(result.is_some())
+    }
+
+    /// get_tokens_with_missing_metadata returns the mints after `after` whose latest token row
+    /// has an empty name or symbol or a zero supply
+    #[instrument(skip(self))]
+    async fn get_tokens_with_missing_metadata(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.ensure_initialized()?;
+        let query = r#"
+            SELECT token
+            FROM tokens
+            WHERE token > ?
+            GROUP BY token
+            HAVING argMax(name, retrieval_timestamp) = ''
+                OR argMax(symbol, retrieval_timestamp) = ''
+                OR argMax(supply, retrieval_timestamp) = 0
+            ORDER BY token
+            LIMIT ?
+            "#;
+        debug!(query = %query, table = "tokens", "Executing SQL query");
+        let tokens = self
+            .read(|client| async move {
+                client
+                    .query(query)
+                    .bind(after.unwrap_or_default())
+                    .bind(limit as u64)
+                    .fetch_all::<String>()
+                    .await
+            })
+            .await
+            .context("Failed to fetch tokens with missing metadata")?;
+        Ok(tokens)
+    }
+
+    /// search_tokens returns the tokens matching a given query, ranked by the kind of match,
+    /// turnover and verification, falls back to a fuzzy pass when few tokens match strictly
+    #[instrument(skip(self))]
+    async fn search_tokens(&self, text: &str) -> Result<Vec<TokenSearchResult>> {
+        self.ensure_initialized()?;
+        let text = normalize_query(text);
+        if text.is_empty() {
+            return Ok(vec![]);
+        }
+        let text = &text;
+
+        let query = format!(
+            r#"
+            SELECT {SEARCH_COLUMNS}
+            FROM token_search_with_stats_v
+            WHERE token = ?
+                OR positionCaseInsensitiveUTF8(symbol, ?) > 0
+                OR positionCaseInsensitiveUTF8(name, ?) > 0
+            ORDER BY turnover_24h DESC
+            LIMIT {SEARCH_CANDIDATES}
+            "#
+        );
+        debug!(query = %query, table = "token_search_with_stats_v", "Executing SQL query");
+        let query = &query;
+        let mut candidates = self
+            .read(|client| async move {
+                client
+                    .query(query)
+                    .bind(text)
+                    .bind(text)
+                    .bind(text)
+                    .fetch_all::<TokenSearch>()
+                    .await
+            })
+            .await?;
+
+        if candidates.len() < FUZZY_FALLBACK_THRESHOLD {
+            // edit distance 1 on the symbol, shared trigrams on the name,
+            // the candidates are matched precisely when ranked
+            let query = format!(
+                r#"
+                SELECT {SEARCH_COLUMNS}
+                FROM token_search_with_stats_v
+                WHERE editDistance(lower(symbol), lower(?)) <= 1
+                    OR ngramSearchCaseInsensitiveUTF8(name, ?) >= 0.5
+                ORDER BY turnover_24h DESC
+                LIMIT {SEARCH_CANDIDATES}
+                "#
+            );
+            let query = &query;
+            let fuzzy = self
+                .read(|client| async move {
+                    client.query(query).bind(text).bind(text).fetch_all::<TokenSearch>().await
+                })
+                .await?;
+            candidates.extend(fuzzy);
+        }
+
+        let mut results = rank_search_results(text, candidates, SEARCH_LIMIT);
+        // the search rows don't carry the launches, the tokens fill theirs from `token_launches`,
+        // read for the few ranked results only
+        let mints: Vec<&str> = results.iter().map(|result| result.token.token.as_str()).collect();
+        let launches: HashMap<String, TokenLaunch> = self
+            .get_tokens(&mints)
+            .await?
+            .into_iter()
+            .filter(|token| !token.launch_pool.is_empty())
+            .map(|token| (token.token.clone(), token.launch()))
+            .collect();
+        for result in &mut results {
+            if let Some(launch) = launches.get(&result.token.token) {
+                result.launch = launch.clone();
+            }
+        }
+        Ok(results)
+    }
+
+    /// set_token_verified sets the verified flag of a token
+    async fn set_token_verified(&self, mint: &str, verified: bool) -> Result<()> {
+        self.ensure_initialized()?;
+        self.job_client()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE tokens UPDATE verified = ? WHERE token = ?")
+            .bind(verified)
+            .bind(mint)
+            .execute()
+            .await
+            .context("Failed to update the verified flag")?;
+        // the verified flag is part of the identity of the rows
+        self.recent_tokens.forget(mint);
+        Ok(())
+    }
+
+    /// set_token_launchpad records the launchpad of a token, the earliest recorded one is read
+    async fn set_token_launchpad(&self, mint: &str, launchpad: &str) -> Result<()> {
+        self.ensure_initialized()?;
+        self.insert_token_launch(&TokenLaunchRecord {
+            token: mint.to_string(),
+            launchpad: launchpad.to_string(),
+            recorded_at: Utc::now().timestamp_millis() as u64,
+            ..Default::default()
+        })
+        .await
+        .context("Failed to record the launchpad")
+    }
+
+    /// mark_token_graduated records the graduation of a token, the earliest recorded one is read
+    async fn mark_token_graduated(&self, mint: &str, pool: &str, timestamp: u64) -> Result<()> {
+        self.ensure_initialized()?;
+        self.insert_token_launch(&TokenLaunchRecord {
+            token: mint.to_string(),
+            graduated_at: timestamp,
+            graduation_pool: pool.to_string(),
+            recorded_at: Utc::now().timestamp_millis() as u64,
+            ..Default::default()
+        })
+        .await
+        .context("Failed to record the graduation")
+    }
+
+    /// set_token_launch_info records the launch of a token, the earliest recorded launch is read
+    async fn set_token_launch_info(
+        &self,
+        mint: &str,
+        dex: Dexes,
+        pool: &str,
+        timestamp: u64,
+    ) -> Result<()> {
+        self.ensure_initialized()?;
+        self.insert_token_launch(&TokenLaunchRecord {
+            token: mint.to_string(),
+            launch_dex: Some(dex),
+            launch_pool: pool.to_string(),
+            launch_timestamp: timestamp,
+            recorded_at: Utc::now().timestamp_millis() as u64,
+            ..Default::default()
+        })
+        .await
+        .context("Failed to record the launch")
+    }
+
+    /// get_active_tokens returns up to `limit` mints swapped since `since`, most swapped first
+    #[instrument(skip(self))]
+    async fn get_active_tokens(&self, since: u64, limit: usize) -> Result<Vec<String>> {
+        self.ensure_initialized()?;
+        let query = r#"
+            SELECT pubkey
+            FROM swap_events
+            WHERE timestamp >= ?
+            GROUP BY pubkey
+            ORDER BY count() DESC, pubkey
+            LIMIT ?
+            "#;
+        debug!(query = %query, table = "swap_events", "Executing SQL query");
+        let tokens = self
+            .read(|client| async move {
+                client.query(query).bind(since).bind(limit as u64).fetch_all::<String>().await
+            })
+            .await
+            .context("Failed to fetch active tokens")?;
+        Ok(tokens)
+    }
+
+    /// get_active_pairs returns up to `limit` pairs swapped in `[start, end)`, in ascending order
+    #[instrument(skip(self))]
+    async fn get_active_pairs(&self, start: u64, end: u64, limit: usize) -> Result<Vec<String>> {
+        self.ensure_initialized()?;
+        let query = r#"
+            SELECT DISTINCT pair
+            FROM swap_events
+            WHERE timestamp >= ? AND timestamp < ?
+            ORDER BY pair
+            LIMIT ?
+            "#;
+        debug!(query = %query, table = "swap_events", "Executing SQL query");
+        let pairs = self
+            .read(|client| async move {
+                client
+                    .query(query)
+                    .bind(start)
+                    .bind(end)
+                    .bind(limit as u64)
+                    .fetch_all::<String>()
+                    .await
+            })
+            .await
+            .context("Failed to fetch active pairs")?;
+        Ok(pairs)
+    }
+
+    /// aggregate_into_candlesticks aggregates swap events into candlesticks table, per pair and
+    /// per token across its pairs, returns the number of candlesticks written
+    async fn aggregate_into_candlesticks(
+        &self,
+        start_time: i64,
+        end_time: i64,
+        interval: CandlestickInterval,
+    ) -> Result<Option<u64>> {
+        self.ensure_initialized()?;
+        let interval_seconds = interval.get_seconds();
+        // a single scan groups the rows of the pairs and the token-level rows, which leave the
+        // pair out and so take its default, the empty sentinel pair; their open and close are
+        // the first and last trades across all the pairs of the bucket
+        let query = format!(
+            r#"
+            INSERT INTO candlesticks
+            SELECT
+                pair,
+                pubkey,
+                {interval_seconds} as interval,
+                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as tp,
+                argMin(price, timestamp) as open,
+                max(price) as high,
+                min(price) as low,
+                argMax(price, timestamp) as close,
+                sum(base_amount) as volume,
+                sum(swap_amount) as turnover,
+                {SIDE_VOLUMES}
+            FROM swap_events
+            WHERE timestamp >= {start_time} AND timestamp < {end_time} AND {FINITE_PRICE}
+            GROUP BY GROUPING SETS ((pubkey, pair, tp), (pubkey, tp))
+            SETTINGS group_by_use_nulls = 0
+            "#
+        );
+        debug!(query = %query, "Aggregating swap events into candlesticks");
+        self.insert_select(&query).await
+    }
+
+    /// aggregate_candlesticks_from_candlesticks rolls stored candlesticks up into a coarser
+    /// interval, without rescanning the swap events, returns the number of candlesticks written
+    async fn aggregate_candlesticks_from_candlesticks(
+        &self,
+        source_interval: CandlestickInterval,
+        interval: CandlestickInterval,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Option<u64>> {
+        self.ensure_initialized()?;
+        if !interval.can_derive_from(&source_interval) {
+            anyhow::bail!("{interval} candlesticks can't be derived from {source_interval} ones");
+        }
+        let source_seconds = source_interval.get_seconds();
+        let interval_seconds = interval.get_seconds();
+        let select = format!(
+            r#"
+            SELECT
+                pair,
+                pubkey,
+                {interval_seconds} as interval,
+                intDiv(timestamp, {interval_seconds}) * {interval_seconds} as tp,
+                argMin(open, timestamp) as open,
+                max(high) as high,
+                min(low) as low,
+                argMax(close, timestamp) as close,
+                sum(volume) as volume,
+                sum(turnover) as turnover,
+                {CANDLESTICK_SIDE_VOLUMES}
+            FROM candlesticks
+            WHERE interval = {source_seconds}
+                AND timestamp >= {start_time} AND timestamp < {end_time}
+            GROUP BY pubkey, pair, tp
+            "#
+        );
+        let query = format!("INSERT INTO candlesticks {select}");
+        debug!(query = %query, "Aggregating candlesticks into candlesticks");
+        self.insert_select(&query).await
+    }
+
+    /// aggregate_daily_token_rollups inserts the rollups of the day, the table keeps the latest
+    /// insert of a token and day, the days without swap events left keep their rollups
+    #[instrument(skip(self))]
+    async fn aggregate_daily_token_rollups(&self, date: NaiveDate) -> Result<Option<u64>> {
+        self.ensure_initialized()?;
+        let (start_time, end_time) = day_bounds(date);
+        let computed_at = Utc::now().timestamp();
+        let select = format!(
+            r#"
+            SELECT
+                pubkey,
+                toDate('{date}') AS date,
+                argMin(price, timestamp) AS open,
+                max(price) AS high,
+                min(price) AS low,
+                argMax(price, timestamp) AS close,
+                sum(base_amount) AS volume,
+                sum(swap_amount) AS turnover,
+                count() AS trade_count,
+                uniqCombinedArray(64)(signers) AS unique_traders,
+                {computed_at} AS computed_at
+            FROM swap_events
+            WHERE timestamp >= {start_time} AND timestamp < {end_time} AND {FINITE_PRICE}
+            GROUP BY pubkey
+            "#
+        );
+        let query = format!("INSERT INTO token_daily_rollups {select}");
+        debug!(query = %query, "Aggregating swap events into daily token rollups");
+        self.insert_select(&query).await
+    }
+
+    /// get_token_history reads the latest rollup of each day of the range
+    #[instrument(skip(self))]
+    async fn get_token_history(
+        &self,
+        mint: &str,
+        from_date: NaiveDate,
+        to_date: NaiveDate,
+    ) -> Result<Vec<TokenDailyRollup>> {
+        self.ensure_initialized()?;
+        let query = r#"
+            SELECT
+                pubkey,
+                toString(date) AS date,
+                open,
+                high,
+                low,
+                close,
+                volume,
+                turnover,
+                trade_count,
+                unique_traders
+            FROM token_daily_rollups FINAL
+            WHERE pubkey = ? AND date >= toDate(?) AND date <= toDate(?)
+            ORDER BY date
+            "#;
+        let (from_date, to_date) = (from_date.to_string(), to_date.to_string());
+        let (from_date, to_date) = (&from_date, &to_date);
+        let rollups = self
+            .read(|client| async move {
+                client
+                    .query(query)
+                    .bind(mint)
+                    .bind(from_date)
+                    .bind(to_date)
+                    .fetch_all::<TokenDailyRollup>()
+                    .await
+            })
+            .await
+            .context("Failed to fetch the token history")?;
+        Ok(rollups)
+    }
+
+    /// remove_swap_events removes swap events from the database
+    async fn remove_swap_events(&self, timestamp: i64) -> Result<()> {
+        self.ensure_initialized()?;
+        let dt =
+            DateTime::from_timestamp(timestamp, 0).context("Failed to create UTC timestamp")?;
+        let yyyymmdd = dt.format("%Y%m%d").to_string();
+        let query: String = format!("ALTER TABLE swap_events DROP PARTITION {}", yyyymmdd);
+        debug!(query = %query, "Removing swap events from partition");
+        self.job_client().query(&query).execute().await.map_err(classified)?;
+        debug!("Removed swap events from partition: {}", yyyymmdd);
+        Ok(())
+    }
+
+    /// estimate_prunable_rows counts the swap events prune_inactive_token_events would delete
+    #[instrument(skip(self))]
+    async fn estimate_prunable_rows(
+        &self,
+        inactive_days: u32,
+        older_than_days: u32,
+    ) -> Result<u64> {
+        self.ensure_initialized()?;
+        let now = Utc::now().timestamp() as u64;
+        let query = r#"
+            SELECT count()
+            FROM swap_events
+            WHERE timestamp < ? AND pubkey IN (
+                SELECT pubkey
+                FROM swap_events
+                GROUP BY pubkey
+                HAVING max(timestamp) < ?
+            )
+            "#;
+        debug!(query = %query, table = "swap_events", "Executing SQL query");
+        let rows = self
+            .read(|client| async move {
+                client
+                    .query(query)
+                    .bind(days_ago(now, older_than_days))
+                    .bind(days_ago(now, inactive_days))
+                    .fetch_one::<u64>()
+                    .await
+            })
+            .await
+            .context("Failed to estimate prunable rows")?;
+        Ok(rows)
+    }
+
+    /// upsert_wallet_label inserts the label, the latest label of an address is read
+    #[instrument(skip(self))]
+    async fn upsert_wallet_label(&self, label: &WalletLabel) -> Result<()> {
+        self.ensure_initialized()?;
+        let mut insert =
+            self.write_client().insert::<WalletLabel>("wallet_labels").map_err(classified)?;
+        insert.write(label).await.map_err(classified)?;
+        insert.end().await.map_err(classified)?;
+        Ok(())
+    }
+
+    /// get_wallet_labels returns the latest label of the labeled addresses in a single query
+    #[instrument(skip(self, addresses), fields(addresses = addresses.len()))]
+    async fn get_wallet_labels(&self, addresses: &[&str]) -> Result<Vec<WalletLabel>> {
+        self.ensure_initialized()?;
+        if addresses.is_empty() {
+            return Ok(vec![]);
+        }
+        let query = r#"
+            SELECT
+                address,
+                argMax(label, added_at) AS label,
+                argMax(category, added_at) AS category,
+                max(added_at) AS added_at
+            FROM wallet_labels
+            WHERE address IN ?
+            GROUP BY address
+            "#;
+        let labels = self
+            .read(|client| async move {
+                client.query(query).bind(addresses).fetch_all::<WalletLabel>().await
+            })
+            .await
+            .context("Failed to fetch wallet labels")?;
+        Ok(labels)
+    }
+
+    /// refresh_token_window_stats inserts a refresh of every window, with and without the
+    /// wash trades, at `now`
+    #[instrument(skip(self))]
+    async fn refresh_token_window_stats(&self, now: u64) -> Result<()> {
+        self.ensure_initialized()?;
+        for window in TOKEN_STATS_WINDOWS {
+            for exclude_wash in [false, true] {
+                let wash_condition = if exclude_wash { "AND NOT is_wash" } else { "" };
+                let query = format!(
+                    r#"
+                    INSERT INTO token_window_stats
+                    SELECT
+                        {window} AS window_secs,
+                        {exclude_wash} AS exclude_wash,
+                        {now} AS computed_at,
+                        pubkey,
+                        argMax(price, timestamp) AS latest_price,
+                        argMax(market_cap, timestamp) AS latest_market_cap,
+                        argMax(is_pump, timestamp) AS latest_is_pump,
+                        sum(base_amount) AS volume,
+                        sum(swap_amount) AS turnover,
+                        argMin(price, timestamp) AS first_price,
+                        latest_price AS last_price,
+                        {UNIQUE_TRADERS}
+                    FROM swap_events
+                    WHERE timestamp >= {start_time} AND {FINITE_PRICE} {wash_condition}
+                    GROUP BY pubkey
+                    "#,
+                    start_time = now.saturating_sub(window),
+                );
+                debug!(query = %query, table = "token_window_stats", "Executing SQL query");
+                self.job_client().query(&query).execute().await.map_err(classified)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// prune_inactive_token_events deletes old swap events of inactive tokens in bounded batches
+    #[instrument(skip(self))]
+    async fn prune_inactive_token_events(
+        &self,
+        inactive_days: u32,
+        older_than_days: u32,
+    ) -> Result<u64> {
+        self.ensure_initialized()?;
+        let now = Utc::now().timestamp() as u64;
+        let cutoff = days_ago(now, older_than_days);
+        let tokens = self.get_inactive_tokens(days_ago(now, inactive_days)).await?;
+        info!(tokens = tokens.len(), cutoff, "Pruning inactive tokens");
+        self.prune_token_events(&tokens, cutoff, PRUNE_BATCH_SIZE).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::initialize_retrying,
+        errors::{is_schema_mismatch, storage_error},
+        models::wallet::WalletCategory,
+    };
+
+    async fn initialized_db() -> ClickhouseDb {
+        let mut db = ClickhouseDb::new("http://localhost:8123", "default", "", "default");
+        db.initialize().await.unwrap();
+        db
+    }
+
+    // The start of the time range of each test reading every swap event, candlestick or
+    // reference price of a range, one per test so that the tests running in parallel don't
+    // read each other's rows
+    const ROW_COUNT_WINDOW: u64 = 946_684_800; // 2000-01-01
+    const ACROSS_PAIRS_WINDOW: u64 = 978_307_200; // 2001-01-01
+    const NON_FINITE_PRICES_WINDOW: u64 = 978_393_600; // 2001-01-02
+    const OUTLIER_POLICY_WINDOW: u64 = 978_480_000; // 2001-01-03
+    const FROM_CANDLESTICKS_WINDOW: u64 = 1_009_843_200; // 2002-01-01
+    const SIDE_VOLUMES_WINDOW: u64 = 1_041_379_200; // 2003-01-01
+    const ROLLUPS_WINDOW: u64 = 1_072_915_200; // 2004-01-01
+    const TOP_TOKENS_SORTING_WINDOW: u64 = 4_000_000_000;
+    const REFERENCE_CANDLESTICKS_WINDOW: u64 = 4_200_000_000;
+
+    fn make_swap_event(pubkey: &str, pair: &str, timestamp: u64) -> SwapEvent {
+        let signature = format!("{pubkey}-{timestamp}");
+        SwapEvent {
+            market_cap: 0.0,
+            base_amount: 1.0,
+            swap_amount: 1.0,
+            ..crate::test_utils::make_swap_event(pubkey, pair, &signature, timestamp, 1.0)
+        }
+    }
+
+    #[test]
+    fn test_summary_written_rows() {
+        let summary = r#"{"read_rows":"10","read_bytes":"800","written_rows":"4","written_bytes":"320","total_rows_to_read":"10","result_rows":"4","result_bytes":"320","elapsed_ns":"1000"}"#;
+        assert_eq!(summary_written_rows(summary), Some(4));
+        assert_eq!(summary_written_rows(r#"{"read_rows":"10"}"#), None);
+        assert_eq!(summary_written_rows("not json"), None);
+    }
+
+    #[test]
+    fn test_days_ago() {
+        assert_eq!(days_ago(10 * DAY_IN_SECONDS, 3), 7 * DAY_IN_SECONDS);
+        assert_eq!(days_ago(DAY_IN_SECONDS, 0), DAY_IN_SECONDS);
+        assert_eq!(days_ago(DAY_IN_SECONDS, 2), 0);
+    }
+
+    #[tokio::test]
+    async fn test_inserters_use_options() {
+        let db = ClickhouseDb::new("http://localhost:8123", "default", "", "default");
+        assert_eq!(
+            db.swap_event_writer().timeouts(),
+            (Some(Duration::from_secs(5)), Some(Duration::from_secs(20)))
+        );
+
+        let options = ClickhouseOptions {
+            async_insert: true,
+            connect_timeout: Some(Duration::from_secs(2)),
+            request_timeout: Some(Duration::from_secs(45)),
+            ..Default::default()
+        };
+        let db = db.with_read_url("http://replica:8123").with_options(options.clone());
+        assert_eq!(db.options(), &options);
+        assert_eq!(
+            db.swap_event_writer().timeouts(),
+            (Some(Duration::from_secs(2)), Some(Duration::from_secs(45)))
+        );
+        assert!(db.create_token_inserter().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_applies_to_queries() {
+        let options = ClickhouseOptions {
+            connect_timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        // a non-routable address never answers the connect
+        let db = ClickhouseDb::new("http://10.255.255.1:8123", "default", "", "default")
+            .with_options(options);
+        let started = std::time::Instant::now();
+        assert!(db.health_check().await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_get_pairs_for_token() {
+        let db = initialized_db().await;
+        let now = Utc::now().timestamp() as u64;
+        let token = "pairs-test-token";
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (pair, swap_amount, timestamp) in [
+            ("pairs-test-pool-a", 10.0, now - 30),
+            ("pairs-test-pool-b", 50.0, now - 20),
+            ("pairs-test-pool-b", 50.0, now - 10),
+            ("pairs-test-pool-c", 30.0, now - 5),
+            ("pairs-test-pool-c", 1000.0, days_ago(now, 2)),
+        ] {
+            let event = SwapEvent {
+                swap_amount,
+                signature: format!("{}-{}", pair, timestamp),
+                ..make_swap_event(token, pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+        let recorded = Pair {
+            pair: "pairs-test-pool-b".to_string(),
+            base_mint: token.to_string(),
+            quote_mint: "wsol".to_string(),
+            dex: Dexes::PumpAmm,
+            first_seen: now - 20,
+        };
+        db.insert_pair(&recorded).await.unwrap();
+
+        let pairs = db.get_pairs_for_token(token, Some(days_ago(now, 1))).await.unwrap();
+        let names: Vec<_> = pairs.iter().map(|p| p.pair.as_str()).collect();
+        assert_eq!(names, vec!["pairs-test-pool-b", "pairs-test-pool-c", "pairs-test-pool-a"]);
+        assert_eq!(pairs[0].turnover, 100.0);
+        assert_eq!(pairs[0].last_trade_ts, now - 10);
+        // the dex of the recorded pairs is returned
+        assert_eq!((pairs[0].dex, pairs[1].dex), (Some(Dexes::PumpAmm), None));
+
+        // without a window the older trades are included
+        let pairs = db.get_pairs_for_token(token, None).await.unwrap();
+        assert_eq!(pairs[0].pair, "pairs-test-pool-c");
+        assert_eq!(pairs[0].turnover, 1030.0);
+
+        for (table, column) in [("swap_events", "pubkey"), ("pairs", "base_mint")] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE {column} = ?"))
+                .bind(token)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_into_candlesticks_row_count() {
+        let db = initialized_db().await;
+        let start = ROW_COUNT_WINDOW;
+        let token = "aggregate-test-token";
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (pair, timestamp) in [
+            ("aggregate-test-pool-a", start + 10),
+            ("aggregate-test-pool-a", start + 20),
+            ("aggregate-test-pool-a", start + 70),
+            ("aggregate-test-pool-b", start + 30),
+        ] {
+            let event = SwapEvent {
+                signature: format!("{}-{}", pair, timestamp),
+                ..make_swap_event(token, pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let (start, end) = (start as i64, start as i64 + 3600);
+        let rows = db
+            .aggregate_into_candlesticks(start, end, CandlestickInterval::OneMinute)
+            .await
+            .unwrap();
+        // pool a trades in two minutes, pool b in one, the token in two
+        assert_eq!(rows, Some(5));
+        let rows = db
+            .aggregate_into_candlesticks(end, end + 3600, CandlestickInterval::OneMinute)
+            .await
+            .unwrap();
+        assert_eq!(rows, Some(0));
+
+        for table in ["swap_events", "candlesticks"] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE pubkey = ?"))
+                .bind(token)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_candlesticks_across_pairs() {
+        let db = initialized_db().await;
+        let start = ACROSS_PAIRS_WINDOW;
+        let token = "token-candles-test-token";
+        let (pool_a, pool_b) = ("token-candles-test-pool-a", "token-candles-test-pool-b");
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        // the first trade of the minute is in pool b, the last in pool a
+        for (pair, timestamp, price) in [
+            (pool_a, start + 10, 1.0),
+            (pool_a, start + 50, 4.0),
+            (pool_b, start + 5, 2.0),
+            (pool_b, start + 40, 3.0),
+        ] {
+            let event = SwapEvent {
+                price,
+                swap_amount: price,
+                signature: format!("{}-{}", pair, timestamp),
+                ..make_swap_event(token, pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let (time_from, time_to) = (start as i32, start as i32 + 3600);
+        let rows = db
+            .aggregate_into_candlesticks(
+                time_from as i64,
+                time_to as i64,
+                CandlestickInterval::OneMinute,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows, Some(3));
+
+        // once the swap events are pruned, the token chart is read from the token-level rows
+        let client = db.client.clone().with_option("mutations_sync", "1");
+        client
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+        let candlesticks = db
+            .get_candlesticks_by_token(
+                token,
+                &[],
+                CandlestickInterval::OneMinute,
+                &OutlierPolicy::default(),
+                None,
+                Some(time_from),
+                Some(time_to),
+            )
+            .await
+            .unwrap();
+        assert_eq!(candlesticks.len(), 1);
+        let candlestick = &candlesticks[0];
+        assert_eq!(
+            (candlestick.timestamp, candlestick.open, candlestick.high),
+            (start as u64, 2.0, 4.0)
+        );
+        assert_eq!((candlestick.low, candlestick.close, candlestick.volume), (1.0, 4.0, 4.0));
+
+        // the pair-scoped reads don't see the token-level rows
+        let candlesticks = db
+            .get_candlesticks_from_candlesticks(
+                pool_a,
+                Some(token),
+                &CandlestickInterval::OneMinute,
+                None,
+                Some(time_from),
+                Some(time_to),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(candlesticks.len(), 1);
+        assert_eq!((candlesticks[0].open, candlesticks[0].close), (1.0, 4.0));
+        assert_eq!(candlesticks[0].volume, 2.0);
+        let pairs = db.get_pairs_for_token(token, None).await.unwrap();
+        let names: Vec<_> = pairs.iter().map(|p| p.pair.as_str()).collect();
+        assert_eq!(names, vec![pool_a, pool_b]);
+
+        client
+            .query("ALTER TABLE candlesticks DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_candlesticks_from_candlesticks() {
+        let db = initialized_db().await;
+        let start = FROM_CANDLESTICKS_WINDOW;
+        let (token, pair) = ("derive-test-token", "derive-test-pool");
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        // three minutes of the first five, and the first minute of the next five
+        for (timestamp, price, base_amount) in [
+            (start + 10, 2.0, 1.0),
+            (start + 70, 5.0, 2.0),
+            (start + 80, 1.0, 3.0),
+            (start + 250, 3.0, 4.0),
+            (start + 300, 4.0, 5.0),
+        ] {
+            let event = SwapEvent {
+                price,
+                base_amount,
+                swap_amount: price * base_amount,
+                signature: format!("{}-{}", pair, timestamp),
+                ..make_swap_event(token, pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let (start, end) = (start as i64, start as i64 + 3600);
+        let rows = db
+            .aggregate_into_candlesticks(start, end, CandlestickInterval::OneMinute)
+            .await
+            .unwrap();
+        // four minutes of the pair, and as many of the token
+        assert_eq!(rows, Some(8));
+        let rows = db
+            .aggregate_candlesticks_from_candlesticks(
+                CandlestickInterval::OneMinute,
+                CandlestickInterval::FiveMinutes,
+                start,
+                end,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows, Some(4));
+        // the rows written again are counted again, the table replaces them
+        let rows = db
+            .aggregate_candlesticks_from_candlesticks(
+                CandlestickInterval::OneMinute,
+                CandlestickInterval::FiveMinutes,
+                start,
+                end,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows, Some(4));
+        assert!(db
+            .aggregate_candlesticks_from_candlesticks(
+                CandlestickInterval::FiveMinutes,
+                CandlestickInterval::OneMinute,
+                start,
+                end,
+            )
+            .await
+            .is_err());
+
+        let candlesticks = db
+            .get_candlesticks_from_candlesticks(
+                pair,
+                Some(token),
+                &CandlestickInterval::FiveMinutes,
+                None,
+                Some(start as i32),
+                Some(end as i32),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(candlesticks.len(), 2);
+        let first = &candlesticks[0];
+        assert_eq!(first.timestamp, start as u64);
+        assert_eq!((first.open, first.high, first.low, first.close), (2.0, 5.0, 1.0, 3.0));
+        assert_eq!(first.volume, 10.0);
+        assert_eq!(first.turnover, 2.0 + 10.0 + 3.0 + 12.0);
+        let second = &candlesticks[1];
+        assert_eq!((second.open, second.close, second.volume), (4.0, 4.0, 5.0));
+
+        for table in ["swap_events", "candlesticks"] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE pubkey = ?"))
+                .bind(token)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_candlesticks_sorting_key() {
+        let db = initialized_db().await;
+        let sorting_key = db.sorting_key("candlesticks").await.unwrap();
+        assert_eq!(sorting_key.as_deref(), Some(CANDLESTICKS_SORTING_KEY));
+        assert_eq!(db.sorting_key("missing_table").await.unwrap(), None);
+        // a table keyed by interval is kept
+        db.rekey_candlesticks().await.unwrap();
+        assert!(db.verify_schema().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_token_daily_rollups() {
+        let db = initialized_db().await;
+        let date = NaiveDate::from_ymd_opt(2004, 1, 1).unwrap();
+        let start = ROLLUPS_WINDOW;
+        let (token, pair) = ("rollups-test-token", "rollups-test-pool");
+
+        let write = |events: Vec<(u64, f64)>| async {
+            let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+            for (timestamp, price) in events {
+                let event = SwapEvent {
+                    price,
+                    signature: format!("{}-{}", pair, timestamp),
+                    ..make_swap_event(token, pair, timestamp)
+                };
+                insert.write(&event).await.unwrap();
+            }
+            insert.end().await.unwrap();
+        };
+        write(vec![(start + 10, 2.0), (start + 20, 5.0), (start + 30, 1.0)]).await;
+        assert_eq!(db.aggregate_daily_token_rollups(date).await.unwrap(), Some(1));
+
+        // aggregating the day again replaces its rollup
+        write(vec![(start + 40, 3.0)]).await;
+        assert_eq!(db.aggregate_daily_token_rollups(date).await.unwrap(), Some(1));
+        let history = db.get_token_history(token, date, date.succ_opt().unwrap()).await.unwrap();
+        assert_eq!(history.len(), 1);
+        let rollup = &history[0];
+        assert_eq!(rollup.date, "2004-01-01");
+        assert_eq!((rollup.open, rollup.high, rollup.low, rollup.close), (2.0, 5.0, 1.0, 3.0));
+        assert_eq!(rollup.trade_count, 4);
+
+        // the rollups outlive the swap events of the day
+        db.remove_swap_events(start as i64).await.unwrap();
+        assert_eq!(db.aggregate_daily_token_rollups(date).await.unwrap(), Some(0));
+        assert_eq!(db.get_token_history(token, date, date).await.unwrap(), history);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE token_daily_rollups DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_candlestick_side_volumes() {
+        let db = initialized_db().await;
+        let start = SIDE_VOLUMES_WINDOW;
+        let (token, pair) = ("sides-test-token", "sides-test-pool");
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        // two buys and a sell in the first minute, a sell in the second
+        for (timestamp, base_amount, is_buy) in
+            [(start + 10, 1.0, true), (start + 20, 2.0, true), (start + 30, 4.0, false)]
+                .into_iter()
+                .chain([(start + 70, 8.0, false)])
+        {
+            let event = SwapEvent {
+                price: 2.0,
+                base_amount,
+                swap_amount: 2.0 * base_amount,
+                is_buy,
+                signature: format!("{}-{}", pair, timestamp),
+                ..make_swap_event(token, pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let sides =
+            |c: &Candlestick| (c.buy_volume, c.sell_volume, c.buy_turnover, c.sell_turnover);
+        let (start, end) = (start as i64, start as i64 + 3600);
+        let live = db
+            .get_candlesticks_from_swap_events(
+                pair,
+                Some(token),
+                &CandlestickInterval::OneMinute,
+                &OutlierPolicy::default(),
+                None,
+                Some(start as i32),
+                Some(end as i32),
+            )
+            .await
+            .unwrap();
+        assert_eq!(live.len(), 2);
+        assert_eq!(sides(&live[0]), (3.0, 4.0, 6.0, 8.0));
+        assert_eq!(sides(&live[1]), (0.0, 8.0, 0.0, 16.0));
+
+        let rows = db
+            .aggregate_into_candlesticks(start, end, CandlestickInterval::OneMinute)
+            .await
+            .unwrap();
+        assert_eq!(rows, Some(2));
+        db.aggregate_candlesticks_from_candlesticks(
+            CandlestickInterval::OneMinute,
+            CandlestickInterval::FiveMinutes,
+            start,
+            end,
+        )
+        .await
+        .unwrap();
+        for (interval, expected) in [
+            (CandlestickInterval::OneMinute, vec![(3.0, 4.0, 6.0, 8.0), (0.0, 8.0, 0.0, 16.0)]),
+            (CandlestickInterval::FiveMinutes, vec![(3.0, 12.0, 6.0, 24.0)]),
+        ] {
+            let aggregated = db
+                .get_candlesticks_from_candlesticks(
+                    pair,
+                    Some(token),
+                    &interval,
+                    None,
+                    Some(start as i32),
+                    Some(end as i32),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(aggregated.iter().map(sides).collect::<Vec<_>>(), expected);
+        }
+
+        for table in ["swap_events", "candlesticks"] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE pubkey = ?"))
+                .bind(token)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_candlestick_outlier_policy() {
+        let db = initialized_db().await;
+        let start = OUTLIER_POLICY_WINDOW;
+        let (token, pair, thin_pair) =
+            ("outlier-test-token", "outlier-test-pool", "outlier-test-thin-pool");
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        // 200 trades at 1.0 and a fat-finger trade at 100.0 in the same minute
+        for i in 0..=200u64 {
+            let event = SwapEvent {
+                price: if i == 200 { 100.0 } else { 1.0 },
+                signature: format!("{}-{}", pair, i),
+                ..make_swap_event(token, pair, start + i % 60)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        // too few trades in the next minute to tell the outlier apart
+        for (i, price) in [1.0, 100.0].into_iter().enumerate() {
+            let event = SwapEvent {
+                price,
+                signature: format!("{}-{}", thin_pair, i),
+                ..make_swap_event(token, thin_pair, start + 60 + i as u64)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let clamped = OutlierPolicy::default();
+        let unclamped = clamped.with_clamp(Some(false));
+        let (from, to) = (Some(start as i32), Some(start as i32 + 3600));
+        let interval = CandlestickInterval::OneMinute;
+        for policy in [&clamped, &unclamped] {
+            let expected_high = if policy.enabled { 1.0 } else { 100.0 };
+            let by_token = db
+                .get_candlesticks_by_token(token, &[], interval, policy, None, from, to)
+                .await
+                .unwrap();
+            assert_eq!(by_token.len(), 2);
+            assert_eq!(by_token[0].high, expected_high);
+            assert_eq!(by_token[0].low, 1.0);
+            assert_eq!(by_token[1].high, 100.0);
+
+            let by_pair = db
+                .get_candlesticks_by_pair(pair, Some(token), &interval, policy, None, from, to)
+                .await
+                .unwrap();
+            assert_eq!(by_pair.len(), 1);
+            assert_eq!(by_pair[0].high, expected_high);
+            assert_eq!(by_pair[0].high, by_token[0].high);
+
+            let thin = db
+                .get_candlesticks_by_pair(thin_pair, Some(token), &interval, policy, None, from, to)
+                .await
+                .unwrap();
+            assert_eq!(thin[0].high, 100.0);
+        }
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_non_finite_prices_skipped() {
+        let db = initialized_db().await;
+        let start = NON_FINITE_PRICES_WINDOW;
+        let (token, pair) = ("non-finite-test-token", "non-finite-test-pool");
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        // the rows stored before the swap prices were checked, among regular trades
+        for (i, price) in [1.0, f64::NAN, f64::INFINITY, 2.0].into_iter().enumerate() {
+            let event = SwapEvent {
+                price,
+                signature: format!("{}-{}", pair, i),
+                ..make_swap_event(token, pair, start + i as u64)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let policy = OutlierPolicy::default();
+        let (from, to) = (Some(start as i32), Some(start as i32 + 3600));
+        let interval = CandlestickInterval::OneMinute;
+        let by_token = db
+            .get_candlesticks_by_token(token, &[], interval.clone(), &policy, None, from, to)
+            .await
+            .unwrap();
+        let by_pair = db
+            .get_candlesticks_by_pair(pair, Some(token), &interval, &policy, None, from, to)
+            .await
+            .unwrap();
+        for candlesticks in [&by_token, &by_pair] {
+            assert_eq!(candlesticks.len(), 1);
+            let candlestick = &candlesticks[0];
+            let ohlc = (candlestick.open, candlestick.high, candlestick.low, candlestick.close);
+            assert_eq!(ohlc, (1.0, 2.0, 1.0, 2.0));
+            assert_eq!(candlestick.volume, 2.0);
+        }
+        let latest =
+            db.get_latest_candlestick(pair, None, &interval, &policy, start + 30).await.unwrap();
+        assert_eq!(latest.current.unwrap().candlestick.high, 2.0);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_top_tokens_sorting() {
+        let db = initialized_db().await;
+        let start = TOP_TOKENS_SORTING_WINDOW;
+        // (token, volume, turnover, open, close, market cap)
+        let tokens = [
+            ("top-test-a", 3.0, 1.0, 1.0, 1.5, 20.0),
+            ("top-test-b", 2.0, 3.0, 2.0, 1.0, 30.0),
+            ("top-test-c", 1.0, 2.0, 1.0, 2.0, 10.0),
+        ];
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (token, volume, turnover, open, close, market_cap) in tokens {
+            for (i, price) in [open, close].into_iter().enumerate() {
+                let event = SwapEvent {
+                    price,
+                    market_cap,
+                    base_amount: volume / 2.0,
+                    swap_amount: turnover / 2.0,
+                    ..make_swap_event(token, "get-top-tokens-sorting-pair", start + i as u64)
+                };
+                insert.write(&event).await.unwrap();
+            }
+        }
+        insert.end().await.unwrap();
+
+        let cases = [
+            (TopTokensSort::Volume, SortOrder::Desc, ["top-test-a", "top-test-b", "top-test-c"]),
+            (TopTokensSort::Volume, SortOrder::Asc, ["top-test-c", "top-test-b", "top-test-a"]),
+            (TopTokensSort::Turnover, SortOrder::Desc, ["top-test-b", "top-test-c", "top-test-a"]),
+            (
+                TopTokensSort::PriceChange,
+                SortOrder::Desc,
+                ["top-test-c", "top-test-a", "top-test-b"],
+            ),
+            (TopTokensSort::MarketCap, SortOrder::Desc, ["top-test-b", "top-test-a", "top-test-c"]),
+        ];
+        for (sort_by, order, expected) in cases {
+            let page = db
+                .get_top_tokens(10, 0, start, None, None, None, false, sort_by, order)
+                .await
+                .unwrap();
+            let ranked: Vec<&str> = page.tokens.iter().map(|t| t.pubkey.as_str()).collect();
+            assert_eq!(ranked, expected, "{sort_by} {order}");
+            assert_eq!(page.total, 3);
+        }
+        let page = db
+            .get_top_tokens(
+                10,
+                0,
+                start,
+                None,
+                None,
+                None,
+                false,
+                TopTokensSort::PriceChange,
+                SortOrder::Desc,
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.tokens[0].price_change, 100.0);
+        assert_eq!(page.tokens[2].price_change, -50.0);
+
+        // the total counts every matching token, not only the page
+        let page = db
+            .get_top_tokens(
+                1,
+                1,
+                start,
+                None,
+                None,
+                None,
+                false,
+                TopTokensSort::Volume,
+                SortOrder::Desc,
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.tokens.len(), 1);
+        assert_eq!(page.tokens[0].pubkey, "top-test-b");
+        assert_eq!(page.total, 3);
+        let page = db
+            .get_top_tokens(
+                10,
+                0,
+                start,
+                Some(2.0),
+                None,
+                None,
+                false,
+                TopTokensSort::Volume,
+                SortOrder::Desc,
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.total, 2);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE timestamp >= ?")
+            .bind(start)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_top_tokens_filters() {
+        // the default settings, the missing join rows read as 0 rather than NULL
+        let db = initialized_db().await;
+        let now = Utc::now().timestamp() as u64;
+        let events = [
+            SwapEvent {
+                is_pump: true,
+                ..make_swap_event("top-filter-pump", "get-top-tokens-filters-pair", now - 60)
+            },
+            SwapEvent {
+                base_amount: 0.0,
+                ..make_swap_event("top-filter-zero", "get-top-tokens-filters-pair", now - 60)
+            },
+        ];
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for event in &events {
+            insert.write(event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+        db.refresh_token_window_stats(now).await.unwrap();
+
+        let sources = [
+            TopTokensSource::Live { start_time: now - 3600 },
+            TopTokensSource::Precomputed { window: 3600, computed_at: now },
+        ];
+        for source in sources {
+            let top = |min_volume, pumpfun| {
+                let db = &db;
+                async move {
+                    let page = db
+                        .fetch_top_tokens(
+                            source,
+                            10_000,
+                            0,
+                            min_volume,
+                            None,
+                            pumpfun,
+                            false,
+                            TopTokensSort::Volume,
+                            SortOrder::Desc,
+                        )
+                        .await
+                        .unwrap();
+                    page.tokens
+                        .into_iter()
+                        .map(|t| t.pubkey)
+                        .filter(|pubkey| pubkey.starts_with("top-filter-"))
+                        .collect::<Vec<_>>()
+                }
+            };
+            assert_eq!(top(None, Some(true)).await, vec!["top-filter-pump"], "{source:?}");
+            assert_eq!(top(None, Some(false)).await, vec!["top-filter-zero"], "{source:?}");
+            // a token without volume is kept by a minimum volume of 0
+            let all = vec!["top-filter-pump", "top-filter-zero"];
+            assert_eq!(top(Some(0.0), None).await, all, "{source:?}");
+            assert_eq!(top(Some(0.5), None).await, vec!["top-filter-pump"], "{source:?}");
+        }
+
+        for table in ["swap_events", "token_window_stats"] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE pubkey LIKE 'top-filter-%'"))
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_top_tokens_excludes_majors() {
+        let db = initialized_db().await;
+        let now = Utc::now().timestamp() as u64;
+        let usdt = crate::models::tokens::USDT_MINT;
+        let events = [
+            make_swap_event("top-majors-token", "get-top-tokens-excludes-majors-pair", now - 60),
+            SwapEvent {
+                signature: "top-majors-usdt".to_string(),
+                is_quote_pair: true,
+                swap_amount: 1_000_000.0,
+                ..make_swap_event(usdt, "get-top-tokens-excludes-majors-pair", now - 60)
+            },
+        ];
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for event in &events {
+            insert.write(event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let page = db
+            .fetch_top_tokens(
+                TopTokensSource::Live { start_time: now - 3600 },
+                10_000,
+                0,
+                None,
+                None,
+                None,
+                false,
+                TopTokensSort::Volume,
+                SortOrder::Desc,
+            )
+            .await
+            .unwrap();
+        let tokens: Vec<&str> = page.tokens.iter().map(|t| t.pubkey.as_str()).collect();
+        assert!(tokens.contains(&"top-majors-token"));
+        assert!(!tokens.contains(&usdt));
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE signature LIKE 'top-majors-%'")
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reference_candlesticks() {
+        let mut db = ClickhouseDb::new("http://localhost:8123", "default", "", "default");
+        db.initialize().await.unwrap();
+        // the latest buckets are read, the window is after those of the other tests
+        let start = REFERENCE_CANDLESTICKS_WINDOW;
+        let pair = crate::models::candlesticks::SOL_USD_PAIR;
+        let prices = [(0, 150.0), (20, 152.0), (40, 149.0), (59, 151.0), (60, 153.0)];
+        for (offset, price) in prices {
+            let price = ReferencePrice { pair: pair.to_string(), price, timestamp: start + offset };
+            db.insert_reference_price(&price).await.unwrap();
+        }
+        db.flush().await.unwrap();
+
+        let candlesticks = db
+            .get_candlesticks_by_pair(
+                pair,
+                None,
+                &CandlestickInterval::OneMinute,
+                &OutlierPolicy::default(),
+                Some(2),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(candlesticks.len(), 2);
+        let first = &candlesticks[0];
+        assert_eq!(first.timestamp, start);
+        assert_eq!((first.open, first.high, first.low, first.close), (150.0, 152.0, 149.0, 151.0));
+        assert_eq!(first.volume, 0.0);
+        assert_eq!(candlesticks[1].open, 153.0);
+
+        // the time range is bound, only this window is past the largest time_from
+        let in_range = |time_from, time_to| {
+            db.get_candlesticks_by_pair(
+                pair,
+                None,
+                &CandlestickInterval::OneMinute,
+                &OutlierPolicy::default(),
+                None,
+                time_from,
+                time_to,
+            )
+        };
+        assert_eq!(in_range(Some(i32::MAX), None).await.unwrap().len(), 2);
+        let before = in_range(Some(i32::MAX - 60), Some(i32::MAX)).await.unwrap();
+        assert!(before.is_empty());
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE reference_prices DELETE WHERE timestamp >= ?")
+            .bind(start)
+            .execute()
+            .await
+            .unwrap();
+        db.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_top_tokens_precomputed_matches_live() {
+        let db = initialized_db().await;
+        let now = Utc::now().timestamp() as u64;
+        let tokens: Vec<String> = (0..20).map(|i| format!("window-stats-test-{i:02}")).collect();
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (i, token) in tokens.iter().enumerate() {
+            // every token trades in both windows, some of its trades are wash trades
+            for (j, age) in [7200, 1800, 600, 60].into_iter().enumerate() {
+                let event = SwapEvent {
+                    price: (i + 1) as f64 * (j + 1) as f64,
+                    market_cap: (i * 10 + j) as f64,
+                    base_amount: (i % 7 + j) as f64,
+                    swap_amount: (i % 5 + j) as f64,
+                    is_pump: i % 2 == 0,
+                    is_wash: j == 2 && i % 3 == 0,
+                    ..make_swap_event(
+                        token,
+                        "top-tokens-precomputed-matches-live-pair",
+                        now - age - i as u64,
+                    )
+                };
+                insert.write(&event).await.unwrap();
+            }
+        }
+        insert.end().await.unwrap();
+        db.refresh_token_window_stats(now).await.unwrap();
+
+        let seeded = |page: TopTokensPage| -> Vec<TopToken> {
+            page.tokens.into_iter().filter(|t| tokens.contains(&t.pubkey)).collect()
+        };
+        for window in TOKEN_STATS_WINDOWS {
+            for exclude_wash in [false, true] {
+                for (sort_by, pumpfun) in [
+                    (TopTokensSort::Volume, None),
+                    (TopTokensSort::PriceChange, Some(true)),
+                    (TopTokensSort::MarketCap, Some(false)),
+                ] {
+                    let fetch = |source| {
+                        db.fetch_top_tokens(
+                            source,
+                            10_000,
+                            0,
+                            None,
+                            None,
+                            pumpfun,
+                            exclude_wash,
+                            sort_by,
+                            SortOrder::Desc,
+                        )
+                    };
+                    let live = fetch(TopTokensSource::Live { start_time: now - window }).await;
+                    let precomputed =
+                        fetch(TopTokensSource::Precomputed { window, computed_at: now }).await;
+
+                    let (live, precomputed) = (seeded(live.unwrap()), seeded(precomputed.unwrap()));
+                    assert!(!live.is_empty());
+                    assert_eq!(live.len(), precomputed.len());
+                    for (live, precomputed) in live.iter().zip(&precomputed) {
+                        assert_eq!(live.pubkey, precomputed.pubkey, "{window}s {sort_by}");
+                        assert_eq!(live.price, precomputed.price);
+                        assert_eq!(live.market_cap, precomputed.market_cap);
+                        assert!((live.volume - precomputed.volume).abs() < 1e-9);
+                        assert!((live.turnover - precomputed.turnover).abs() < 1e-9);
+                        assert!((live.price_change - precomputed.price_change).abs() < 1e-9);
+                        assert_eq!(live.unique_traders, precomputed.unique_traders);
+                    }
+                }
+            }
+        }
+
+        // a matching window reads the refresh, which doesn't see the later swaps
+        let late = SwapEvent {
+            base_amount: 1000.0,
+            ..make_swap_event(&tokens[0], "top-tokens-precomputed-matches-live-pair", now)
+        };
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        insert.write(&late).await.unwrap();
+        insert.end().await.unwrap();
+        let (db, tokens) = (&db, &tokens);
+        let top_volume = |start_time| async move {
+            let page = db
+                .get_top_tokens(
+                    10_000,
+                    0,
+                    start_time,
+                    None,
+                    None,
+                    None,
+                    false,
+                    TopTokensSort::Volume,
+                    SortOrder::Desc,
+                )
+                .await
+                .unwrap();
+            seeded(page).into_iter().find(|t| t.pubkey == tokens[0]).unwrap().volume
+        };
+        let precomputed = top_volume(now - 3600 + 30).await;
+        let live = top_volume(now - 3000).await;
+        assert!(precomputed < 1000.0);
+        assert!(live >= 1000.0);
+
+        for (table, condition) in [
+            ("swap_events", "pubkey LIKE 'window-stats-test-%'"),
+            ("token_window_stats", "pubkey LIKE 'window-stats-test-%'"),
+        ] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE {condition}"))
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unique_traders() {
+        let db = initialized_db().await;
+        let token = "unique-traders-test";
+        let now = Utc::now().timestamp() as u64;
+        // 100 buyers, 80 sellers of which 50 also bought, and a trader older than a day
+        let buyers = (0..100).map(|i| (format!("trader-{i}"), true));
+        let sellers = (50..130).map(|i| (format!("trader-{i}"), false));
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (i, (signer, is_buy)) in buyers.chain(sellers).enumerate() {
+            let event = SwapEvent {
+                signers: vec![signer],
+                is_buy,
+                ..make_swap_event(token, "unique-traders-pair", now - 60 - i as u64)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        let old = SwapEvent {
+            signers: vec!["old-trader".to_string()],
+            ..make_swap_event(token, "unique-traders-pair", now - 2 * DAY_IN_SECONDS)
+        };
+        insert.write(&old).await.unwrap();
+        insert.end().await.unwrap();
+
+        let within = |count: u64, expected: u64| count.abs_diff(expected) * 100 <= expected * 2;
+        let stats = db.get_token_stats(vec![token.to_string()], false).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert!(within(stats[0].unique_traders_24h, 130), "{}", stats[0].unique_traders_24h);
+        assert!(within(stats[0].unique_buyers_24h, 100), "{}", stats[0].unique_buyers_24h);
+        assert!(within(stats[0].unique_sellers_24h, 80), "{}", stats[0].unique_sellers_24h);
+        // the signers are counted without multiplying the swaps
+        assert_eq!(stats[0].volume_24h, 180.0);
+
+        let page = db
+            .fetch_top_tokens(
+                TopTokensSource::Live { start_time: now - DAY_IN_SECONDS },
+                10_000,
+                0,
+                None,
+                None,
+                None,
+                false,
+                TopTokensSort::Volume,
+                SortOrder::Desc,
+            )
+            .await
+            .unwrap();
+        let top = page.tokens.iter().find(|t| t.pubkey == token).unwrap();
+        assert!(within(top.unique_traders, 130), "{}", top.unique_traders);
+        assert!(within(top.unique_buyers, 100), "{}", top.unique_buyers);
+        assert!(within(top.unique_sellers, 80), "{}", top.unique_sellers);
+        assert_eq!(top.volume, 180.0);
+
+        let daily = db.get_token_daily_stats(vec![token.to_string()]).await.unwrap();
+        assert_eq!(daily.len(), 1);
+        assert!(within(daily[0].unique_traders_24h, 130), "{}", daily[0].unique_traders_24h);
+        assert!(within(daily[0].unique_buyers_24h, 100), "{}", daily[0].unique_buyers_24h);
+        assert!(within(daily[0].unique_sellers_24h, 80), "{}", daily[0].unique_sellers_24h);
+        // the trader older than a day is neither counted nor summed
+        assert_eq!(daily[0].volume_24h, 180.0);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_pair_detail() {
+        let db = initialized_db().await;
+        let now = Utc::now().timestamp() as u64;
+        let pair = "pair-detail-test-pool";
+        assert!(db.get_pair_detail(pair).await.unwrap().is_none());
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (price, timestamp) in [(1.0, days_ago(now, 2)), (2.0, now - 60), (3.0, now - 30)] {
+            let event = SwapEvent {
+                price,
+                swap_amount: price,
+                ..make_swap_event("pair-detail-test-token", pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let detail = db.get_pair_detail(pair).await.unwrap().unwrap();
+        assert_eq!(detail.base_mint, "pair-detail-test-token");
+        assert_eq!((detail.quote_mint, detail.dex), (None, None));
+        assert_eq!((detail.price, detail.trades_24h, detail.turnover_24h), (3.0, 2, 5.0));
+        assert_eq!((detail.first_seen, detail.last_trade_ts), (days_ago(now, 2), now - 30));
+
+        for first_seen in [now - 60, days_ago(now, 3)] {
+            let recorded = Pair {
+                pair: pair.to_string(),
+                base_mint: "pair-detail-test-token".to_string(),
+                quote_mint: "wsol".to_string(),
+                dex: Dexes::RaydiumAmmV4,
+                first_seen,
+            };
+            db.insert_pair(&recorded).await.unwrap();
+        }
+        let detail = db.get_pair_detail(pair).await.unwrap().unwrap();
+        assert_eq!(detail.quote_mint.as_deref(), Some("wsol"));
+        assert_eq!(detail.dex, Some(Dexes::RaydiumAmmV4));
+        assert_eq!(detail.first_seen, days_ago(now, 3));
+
+        // the merges keep the earliest record of the pair
+        db.client.query("OPTIMIZE TABLE pairs FINAL").execute().await.unwrap();
+        let detail = db.get_pair_detail(pair).await.unwrap().unwrap();
+        assert_eq!(detail.first_seen, days_ago(now, 3));
+
+        // once the swap events are pruned the pair is priced by its latest candlestick
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pair = ?")
+            .bind(pair)
+            .execute()
+            .await
+            .unwrap();
+        db.client
+            .query(
+                "INSERT INTO candlesticks (pair, pubkey, interval, timestamp, close) \
+                 VALUES (?, 'pair-detail-test-token', 60, ?, 3.0)",
+            )
+            .bind(pair)
+            .bind(now - 120)
+            .execute()
+            .await
+            .unwrap();
+        let detail = db.get_pair_detail(pair).await.unwrap().unwrap();
+        assert_eq!(
+            (detail.base_mint.as_str(), detail.dex),
+            ("pair-detail-test-token", Some(Dexes::RaydiumAmmV4))
+        );
+        assert_eq!((detail.price, detail.last_trade_ts, detail.trades_24h), (3.0, now - 60, 0));
+
+        for table in ["swap_events", "pairs", "candlesticks"] {
+            db.client
+                .clone()
+                .with_option("mutations_sync", "1")
+                .query(&format!("ALTER TABLE {table} DELETE WHERE pair = ?"))
+                .bind(pair)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_fee_stats() {
+        let db = initialized_db().await;
+        let pair = "fee-stats-test-pool";
+        // (fee amount, fee mint, timestamp), the swaps without fees are left out
+        let fees =
+            [(1.5, "wsol", 1_000), (3.5, "wsol", 2_000), (4.0, "token", 3_000), (0.0, "", 4_000)];
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (fee_amount, fee_mint, timestamp) in fees {
+            let event = SwapEvent {
+                fee_amount,
+                fee_mint: fee_mint.to_string(),
+                swap_amount: 10.0,
+                ..make_swap_event("fee-stats-test-token", pair, timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let stats = db.get_fee_stats(Some(pair), 0, 10).await.unwrap();
+        let stats: Vec<(&str, f64, f64, u64)> = stats
+            .iter()
+            .map(|s| (s.fee_mint.as_str(), s.fee_amount, s.swap_amount, s.swaps))
+            .collect();
+        assert_eq!(stats, vec![("wsol", 5.0, 20.0, 2), ("token", 4.0, 10.0, 1)]);
+
+        let stats = db.get_fee_stats(Some(pair), 1_500, 1).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].fee_mint.as_str(), stats[0].fee_amount), ("token", 4.0));
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pair = ?")
+            .bind(pair)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_wallet_activity() {
+        let db = initialized_db().await;
+        let wallet = "wallet-activity-test-wallet";
+        // (token, owner, is_buy, base amount, swap amount, timestamp)
+        let trades = [
+            ("wallet-activity-test-a", wallet, true, 10.0, 100.0, 1_000),
+            ("wallet-activity-test-a", wallet, false, 4.0, 60.0, 2_000),
+            ("wallet-activity-test-b", wallet, true, 1.0, 500.0, 3_000),
+            // co-signed by the wallet, a bot owns the trade
+            ("wallet-activity-test-a", "wallet-activity-test-bot", true, 2.0, 20.0, 4_000),
+        ];
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (token, owner, is_buy, base_amount, swap_amount, timestamp) in trades {
+            let event = SwapEvent {
+                owner: owner.to_string(),
+                signers: vec![owner.to_string(), wallet.to_string()],
+                is_buy,
+                base_amount,
+                swap_amount,
+                ..make_swap_event(token, "get-wallet-activity-pair", timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let activity = db.get_wallet_activity(wallet, None, None, false, 500).await.unwrap();
+        let tokens: Vec<&str> = activity.iter().map(|a| a.token.as_str()).collect();
+        assert_eq!(tokens, vec!["wallet-activity-test-b", "wallet-activity-test-a"]);
+        let a = &activity[1];
+        assert_eq!((a.trade_count, a.buy_base_amount, a.sell_base_amount), (3, 12.0, 4.0));
+        assert_eq!((a.net_base_amount, a.buy_turnover, a.sell_turnover), (8.0, 120.0, 60.0));
+        assert_eq!(a.last_trade_ts, 4_000);
+
+        // the co-signed trade isn't the wallet's own
+        let activity = db.get_wallet_activity(wallet, None, None, true, 500).await.unwrap();
+        let a = activity.iter().find(|a| a.token == "wallet-activity-test-a").unwrap();
+        assert_eq!((a.trade_count, a.net_base_amount, a.last_trade_ts), (2, 6.0, 2_000));
+
+        let activity =
+            db.get_wallet_activity(wallet, Some(1_500), Some(3_500), false, 500).await.unwrap();
+        let counts: Vec<u64> = activity.iter().map(|a| a.trade_count).collect();
+        assert_eq!(counts, vec![1, 1]);
+        assert_eq!(db.get_wallet_activity(wallet, None, None, false, 1).await.unwrap().len(), 1);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE has(signers, ?)")
+            .bind(wallet)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wallet_labels() {
+        let db = initialized_db().await;
+        let (cex, bot, token) =
+            ("wallet-labels-test-cex", "wallet-labels-test-bot", "wallet-labels-test-token");
+        let label = |address: &str, label: &str, category: WalletCategory, added_at| WalletLabel {
+            address: address.to_string(),
+            label: label.to_string(),
+            category: category.to_string(),
+            added_at,
+        };
+        db.upsert_wallet_label(&label(cex, "Exchange", WalletCategory::Other, 1)).await.unwrap();
+        db.upsert_wallet_label(&label(cex, "Binance 1", WalletCategory::Cex, 2)).await.unwrap();
+        db.upsert_wallet_label(&label(bot, "Sniper", WalletCategory::Sniper, 1)).await.unwrap();
+
+        // the latest label of an address wins, the unlabeled addresses are left out
+        let mut labels =
+            db.get_wallet_labels(&[cex, bot, "wallet-labels-test-none"]).await.unwrap();
+        labels.sort_by(|a, b| a.address.cmp(&b.address));
+        assert_eq!(
+            labels,
+            vec![
+                label(bot, "Sniper", WalletCategory::Sniper, 1),
+                label(cex, "Binance 1", WalletCategory::Cex, 2)
+            ]
+        );
+        assert!(db.get_wallet_labels(&[]).await.unwrap().is_empty());
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (owner, timestamp) in [(cex, 1_000), (bot, 2_000), ("wallet-labels-test-none", 3_000)] {
+            let event = SwapEvent {
+                owner: owner.to_string(),
+                ..make_swap_event(token, "wallet-labels-pair", timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let owners = |trades: Vec<Trade>| -> Vec<String> {
+            trades.into_iter().map(|trade| trade.owner).collect()
+        };
+        let trades = db
+            .get_trades(&TradeFilter {
+                token: Some(token.to_string()),
+                category: Some(WalletCategory::Cex),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(owners(trades), vec![cex.to_string()]);
+        // the address is no longer labeled as other
+        let trades = db
+            .get_trades(&TradeFilter {
+                token: Some(token.to_string()),
+                category: Some(WalletCategory::Other),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(trades.is_empty());
+        let trades = db
+            .get_trades(&TradeFilter { token: Some(token.to_string()), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(trades.len(), 3);
+
+        let client = db.client.clone().with_option("mutations_sync", "1");
+        client
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+        client
+            .query("ALTER TABLE wallet_labels DELETE WHERE startsWith(address, ?)")
+            .bind("wallet-labels-test-")
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_token_launch_is_recorded_without_mutation() {
+        let db = initialized_db().await;
+        let mint = "token-launch-test-mint";
+        // the launch is recorded before the token row is written
+        db.set_token_launchpad(mint, "raydium_launchpad").await.unwrap();
+        db.set_token_launch_info(mint, Dexes::RaydiumLaunchpad, "curve", 5).await.unwrap();
+        db.set_token_launch_info(mint, Dexes::RaydiumLaunchpad, "other-curve", 6).await.unwrap();
+        let mut insert = db.client.insert::<Token>("tokens").unwrap();
+        insert.write(&crate::test_utils::make_token(mint, "LAUNCH", "Launch")).await.unwrap();
+        insert.end().await.unwrap();
+        db.client.query("SYSTEM FLUSH ASYNC INSERT QUEUE").execute().await.unwrap();
+
+        db.set_token_launchpad(mint, "pump").await.unwrap();
+        db.mark_token_graduated(mint, "pool", 900).await.unwrap();
+        db.client.query("SYSTEM FLUSH ASYNC INSERT QUEUE").execute().await.unwrap();
+
+        // the earliest launchpad and launch are read
+        let token = db.get_token(mint).await.unwrap().unwrap();
+        assert_eq!(token.launchpad, "raydium_launchpad");
+        assert_eq!((token.graduated_at, token.graduation_pool.as_str()), (900, "pool"));
+        assert_eq!(token.launch_dex, Some(Dexes::RaydiumLaunchpad));
+        assert_eq!(token.launch_pool, "curve");
+        assert_eq!(token.launch_timestamp, 5);
+        let tokens = db.get_tokens(&[mint]).await.unwrap();
+        assert_eq!(tokens[0].launch(), token.launch());
+
+        let client = db.client.clone().with_option("mutations_sync", "1");
+        for table in ["tokens", "token_launches"] {
+            client
+                .query(&format!("ALTER TABLE {table} DELETE WHERE token = ?"))
+                .bind(mint)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_string_filters_are_bound() {
+        let db = initialized_db().await;
+        let (mint, other) = ("quoted-test-mint'", "quoted-test-mint' OR '1'='1");
+        let mut insert = db.client.insert::<Token>("tokens").unwrap();
+        insert.write(&crate::test_utils::make_token(mint, "QUOTE", "Quote")).await.unwrap();
+        insert.end().await.unwrap();
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        insert.write(&make_swap_event(mint, "string-filters-are-bound-pair", 1_000)).await.unwrap();
+        insert.end().await.unwrap();
+
+        // the quote is matched literally instead of breaking or widening the queries
+        assert_eq!(
+            db.get_token(mint).await.unwrap().map(|token| token.symbol),
+            Some("QUOTE".into())
+        );
+        assert!(db.get_token(other).await.unwrap().is_none());
+        let tokens = db.get_tokens(&[mint, other]).await.unwrap();
+        assert_eq!(tokens.iter().map(|token| token.token.as_str()).collect::<Vec<_>>(), [mint]);
+        assert!(db.get_tokens(&[]).await.unwrap().is_empty());
+        assert!(db.has_token(mint).await.unwrap());
+        assert!(!db.has_token(other).await.unwrap());
+        assert_eq!(db.get_price(mint, 2_000).await.unwrap().price, Some(1.0));
+        assert_eq!(db.get_price(other, 2_000).await.unwrap().price, None);
+        let trades = db
+            .get_trades(&TradeFilter { token: Some(mint.to_string()), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        let trades = db
+            .get_trades(&TradeFilter { address: Some(other.to_string()), ..Default::default() })
+            .await
+            .unwrap();
+        assert!(trades.is_empty());
+
+        let client = db.client.clone().with_option("mutations_sync", "1");
+        for (table, column) in [("tokens", "token"), ("swap_events", "pubkey")] {
+            client
+                .query(&format!("ALTER TABLE {table} DELETE WHERE {column} = ?"))
+                .bind(mint)
+                .execute()
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_trades_of_wallet() {
+        let db = initialized_db().await;
+        let token = "wallet-trades-test-mint";
+        let (owner, bot) = ("wallet-trades-test-owner", "wallet-trades-test-bot");
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (timestamp, fee_payer) in [(1_000, bot), (2_000, owner)] {
+            let event = SwapEvent {
+                owner: owner.to_string(),
+                fee_payer: fee_payer.to_string(),
+                ..make_swap_event(token, "get-trades-of-wallet-pair", timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let timestamps = |address, limit, offset| {
+            let db = &db;
+            async move {
+                let trades = db
+                    .get_trades(&TradeFilter {
+                        address: Some(address.to_string()),
+                        limit,
+                        offset,
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+                trades.into_iter().map(|trade| trade.timestamp).collect::<Vec<_>>()
+            }
+        };
+        // the trader finds its swaps once each, the bot the swap it relayed
+        assert_eq!(timestamps(owner, None, None).await, vec![2_000, 1_000]);
+        assert_eq!(timestamps(bot, None, None).await, vec![1_000]);
+        assert_eq!(timestamps(owner, Some(1), Some(1)).await, vec![1_000]);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_trades_min_pct_supply() {
+        let db = initialized_db().await;
+        let token = "min-pct-supply-test-mint";
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        for (timestamp, pct_of_supply) in [(1_000, 0.0), (2_000, 0.004), (3_000, 0.02)] {
+            let event = SwapEvent {
+                pct_of_supply,
+                ..make_swap_event(token, "get-trades-min-pct-supply-pair", timestamp)
+            };
+            insert.write(&event).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let timestamps = |min_pct_supply| {
+            let db = &db;
+            async move {
+                let filter = TradeFilter {
+                    token: Some(token.to_string()),
+                    min_pct_supply,
+                    ..Default::default()
+                };
+                let trades = db.get_trades(&filter).await.unwrap();
+                trades.into_iter().map(|trade| trade.timestamp).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(timestamps(None).await, vec![3_000, 2_000, 1_000]);
+        assert_eq!(timestamps(Some(0.004)).await, vec![3_000, 2_000]);
+        assert_eq!(timestamps(Some(0.005)).await, vec![3_000]);
+        assert!(timestamps(Some(0.5)).await.is_empty());
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_signature_lookup_beyond_last_hour() {
+        let db = initialized_db().await;
+        let timestamp = Utc::now().timestamp() as u64 - 3 * 86_400;
+        let event = SwapEvent {
+            signature: "old-signature-test".to_string(),
+            slot: 42,
+            ..make_swap_event(
+                "old-signature-test-mint",
+                "signature-lookup-beyond-last-hour-pair",
+                timestamp,
+            )
+        };
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        insert.write(&event).await.unwrap();
+        insert.end().await.unwrap();
+
+        let lookup = |slot: Option<u64>, time_from: Option<u64>| {
+            db.get_trades(&TradeFilter {
+                signature: Some(event.signature.clone()),
+                slot,
+                time_from,
+                ..Default::default()
+            })
+        };
+        let trades = lookup(None, None).await.unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].timestamp, timestamp);
+        assert!(lookup(Some(43), None).await.unwrap().is_empty());
+        // an explicit time_from replaces the fallback
+        assert!(lookup(None, Some(timestamp + 1)).await.unwrap().is_empty());
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE signature = ?")
+            .bind(&event.signature)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_insert_token_dedupe() {
+        let mut db = ClickhouseDb::new("http://localhost:8123", "default", "", "default")
+            .with_token_dedupe_window(Duration::from_secs(3600));
+        db.initialize().await.unwrap();
+        let mint = "dedupe-test-token";
+        let count = |db: &ClickhouseDb| {
+            db.client
+                .query("SELECT count() FROM tokens WHERE token = ?")
+                .bind(mint)
+                .fetch_one::<u64>()
+        };
+        let now = Utc::now().timestamp() as u64;
+        let token = |retrieval_timestamp: u64, name: &str| Token {
+            retrieval_timestamp,
+            ..crate::test_utils::make_token(mint, "DDP", name)
+        };
+
+        // repeated resolutions of the unchanged mint are written once per window
+        for offset in 0..5 {
+            db.insert_token(&token(now + offset, "Dedupe")).await.unwrap();
+        }
+        assert_eq!(count(&db).await.unwrap(), 1);
+        // a change is written right away
+        db.insert_token(&token(now + 10, "Renamed")).await.unwrap();
+        assert_eq!(count(&db).await.unwrap(), 2);
+        db.insert_token(&token(now + 11, "Renamed")).await.unwrap();
+        assert_eq!(count(&db).await.unwrap(), 2);
+        // as is the first resolution after the window
+        db.insert_token(&token(now + 3_611, "Renamed")).await.unwrap();
+        assert_eq!(count(&db).await.unwrap(), 3);
+
+        // without a window every resolution is written
+        let db = db.with_token_dedupe_window(Duration::ZERO);
+        db.insert_token(&token(now + 3_612, "Renamed")).await.unwrap();
+        assert_eq!(count(&db).await.unwrap(), 4);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE tokens DELETE WHERE token = ?")
+            .bind(mint)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_uninitialized_writes_fail() {
+        // no request is sent, the reads and writes fail before reaching the server
+        let db = ClickhouseDb::new("http://localhost:8123", "default", "", "default")
+            .with_token_dedupe_window(Duration::ZERO);
+        let event = make_swap_event("uninitialized-test", "uninitialized-writes-fail-pair", 0);
+        let e = db.insert_swap_event(&event).await.unwrap_err();
+        assert!(matches!(storage_error(&e), Some(StorageError::NotInitialized(_))));
+        let token = crate::test_utils::make_token("uninitialized-test", "UNI", "Uninitialized");
+        let e = db.insert_token(&token).await.unwrap_err();
+        assert!(matches!(storage_error(&e), Some(StorageError::NotInitialized(_))));
+        let e = db.set_token_verified("uninitialized-test", true).await.unwrap_err();
+        assert!(matches!(storage_error(&e), Some(StorageError::NotInitialized(_))));
+        let e = db.get_token("uninitialized-test").await.unwrap_err();
+        assert!(matches!(storage_error(&e), Some(StorageError::NotInitialized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_without_schema() {
+        // the services not running the DDL only verify the schema
+        let mut db = ClickhouseDb::new("http://localhost:8123", "default", "", "default")
+            .with_initialize_schema(false);
+        db.initialize().await.unwrap();
+        assert!(db.is_initialized);
+        // initializing twice keeps the inserters
+        let inserter = db.swap_event_inserter.clone().unwrap();
+        db.initialize().await.unwrap();
+        assert!(Arc::ptr_eq(&inserter, db.swap_event_inserter.as_ref().unwrap()));
+
+        // the schema isn't there yet, the failure is retried as another service may create it
+        let mut db = ClickhouseDb::new("http://localhost:8123", "default", "", "missing_database")
+            .with_initialize_schema(false);
+        let e = initialize_retrying(&mut db, 2, Duration::from_millis(10)).await.unwrap_err();
+        assert!(is_schema_mismatch(&e), "{e:#}");
+        assert!(!db.is_initialized);
+        let e = db
+            .insert_swap_event(&make_swap_event(
+                "missing-schema-test",
+                "initialize-without-schema-pair",
+                0,
+            ))
+            .await;
+        assert!(e.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_flush_and_close_inserters() {
+        let mut db = ClickhouseDb::new("http://localhost:8123", "default", "", "default")
+            .with_max_swap_event_rows(1_000);
+        db.initialize().await.unwrap();
+        let token = "flush-test-token";
+        let count = |db: &ClickhouseDb| {
+            db.client
+                .query("SELECT count() FROM swap_events WHERE pubkey = ?")
+                .bind(token)
+                .fetch_one::<u64>()
+        };
+
+        db.insert_swap_event(&make_swap_event(token, "flush-and-close-inserters-pair", 1))
+            .await
+            .unwrap();
+        assert_eq!(count(&db).await.unwrap(), 0);
+        db.flush().await.unwrap();
+        assert_eq!(count(&db).await.unwrap(), 1);
+
+        db.insert_swap_event(&make_swap_event(token, "flush-and-close-inserters-pair", 2))
+            .await
+            .unwrap();
+        db.close().await.unwrap();
+        db.close().await.unwrap();
+        assert_eq!(count(&db).await.unwrap(), 2);
+        assert!(db
+            .insert_swap_event(&make_swap_event(token, "flush-and-close-inserters-pair", 3))
+            .await
+            .is_err());
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pubkey = ?")
+            .bind(token)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_replica_routing_and_fallback() {
+        let db = ClickhouseDb::new("http://localhost:8123", "default", "", "default");
+        assert!(std::ptr::eq(db.read_client(), db.write_client()));
+
+        // an unreachable read replica falls back to the write client and cools down
+        let db = db.with_read_url("http://localhost:1");
+        assert!(!std::ptr::eq(db.read_client(), db.write_client()));
+        let result = db
+            .read(|client| async move { client.query("SELECT 1").fetch_one::<u8>().await })
+            .await
+            .expect("Failed to fall back to the write client");
+        assert_eq!(result, 1);
+        assert!(std::ptr::eq(db.read_client(), db.write_client()));
+
+        // the read replica is retried once the cooldown has passed
+        db.read_replica_retry_at.store(0, Ordering::Relaxed);
+        assert!(!std::ptr::eq(db.read_client(), db.write_client()));
+
+        // the unreachable read replica is degraded, not unhealthy, and cools down
+        assert!(db.health_check().await.is_ok());
+        assert!(matches!(db.read_replica_health().await, Some(Err(_))));
+        assert!(std::ptr::eq(db.read_client(), db.write_client()));
+    }
+
+    #[tokio::test]
+    async fn test_prune_inactive_token_events() {
+        let db = initialized_db().await;
+        let now = Utc::now().timestamp() as u64;
+        let pair = "prune-test-pair";
+        let active = "prune-test-active";
+        let inactive: Vec<String> = (0..5).map(|i| format!("prune-test-inactive-{i}")).collect();
+
+        let mut insert = db.client.insert::<SwapEvent>("swap_events").unwrap();
+        insert.write(&make_swap_event(active, pair, days_ago(now, 60))).await.unwrap();
+        insert.write(&make_swap_event(active, pair, now)).await.unwrap();
+        for token in &inactive {
+            insert.write(&make_swap_event(token, pair, days_ago(now, 60))).await.unwrap();
+            insert.write(&make_swap_event(token, pair, days_ago(now, 20))).await.unwrap();
+        }
+        insert.end().await.unwrap();
+
+        let tokens = db.get_inactive_tokens(days_ago(now, 14)).await.unwrap();
+        assert!(inactive.iter().all(|token| tokens.contains(token)));
+        assert!(!tokens.contains(&active.to_string()));
+        assert!(db.estimate_prunable_rows(14, 30).await.unwrap() >= 5);
+
+        // only the tokens of this test are pruned, three batches of at most two tokens
+        let deleted = db.prune_token_events(&inactive, days_ago(now, 30), 2).await.unwrap();
+        assert_eq!(deleted, 5);
+        let remaining = db
+            .client
+            .query("SELECT pubkey, timestamp FROM swap_events WHERE pair = ? ORDER BY pubkey")
+            .bind(pair)
+            .fetch_all::<(String, u64)>()
+            .await
+            .unwrap();
+        assert_eq!(remaining.len(), 7);
+        assert!(remaining
+            .iter()
+            .all(|(token, timestamp)| token == active || *timestamp == days_ago(now, 20)));
+
+        // nothing is left to delete
+        assert_eq!(db.prune_token_events(&inactive, days_ago(now, 30), 2).await.unwrap(), 0);
+
+        db.client
+            .clone()
+            .with_option("mutations_sync", "1")
+            .query("ALTER TABLE swap_events DELETE WHERE pair = ?")
+            .bind(pair)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    /// Times a pair scoped time range over a few million swap events with and without the
+    /// pair projection, `cargo test -p sonar-db --features slow-tests`
+    #[cfg(feature = "slow-tests")]
+    #[tokio::test]
+    async fn test_pair_projection_timing() {
+        let db = ClickhouseDb::new("http://localhost:8123", "default", "", "default");
+        let table = "swap_events_projection_bench";
+        let client = db.client.clone().with_option("mutations_sync", "1");
+        client.query(&format!("DROP TABLE IF EXISTS {table}")).execute().await.unwrap();
+        // the copy carries the sorting key and the projection of swap_events
+        client.query(&format!("CREATE TABLE {table} AS swap_events")).execute().await.unwrap();
+
+        // 3M swap events over 35 days, 100 pairs of 100 tokens each
+        let start = 946_684_800u64;
+        let query = format!(
+            "INSERT INTO {table} (pair, pubkey, timestamp, price, swap_amount, signature) \
+            SELECT concat('bench-pool-', toString(number % 100)), \
+                concat('bench-token-', toString(number % 10000)), ? + number, 1.0, 1.0, \
+                toString(number) \
+            FROM numbers(3000000)"
+        );
+        client.query(&query).bind(start).execute().await.unwrap();
+
+        // an hour of a pair, the sorting key alone reads the day of every token of the pair
+        let (from, to) = (start + 10 * DAY_IN_SECONDS, start + 10 * DAY_IN_SECONDS + 3600);
+        let query = format!(
+            "SELECT count(), sum(swap_amount) FROM {table} \
+            WHERE pair = ? AND timestamp >= ? AND timestamp < ?"
+        );
+        let mut results = vec![];
+        let mut elapsed = vec![];
+        for use_projections in ["0", "1"] {
+            let client = db.client.clone().with_option("optimize_use_projections", use_projections);
+            let started = std::time::Instant::now();
+            for _ in 0..10 {
+                let result = client
+                    .query(&query)
+                    .bind("bench-pool-7")
+                    .bind(from)
+                    .bind(to)
+                    .fetch_one::<(u64, f64)>()
+                    .await
+                    .unwrap();
+                results.push(result);
+            }
+            elapsed.push(started.elapsed());
+        }
+        // the projection only changes how the rows are read, and reads fewer of them
+        assert!(results.iter().all(|result| *result == (36, 36.0)));
+        assert!(
+            elapsed[1] < elapsed[0],
+            "the projection took {:?}, the sorting key {:?}",
+            elapsed[1],
+            elapsed[0]
+        );
+
+        client.query(&format!("DROP TABLE {table}")).execute().await.unwrap();
     }
 }
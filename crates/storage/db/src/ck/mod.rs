@@ -1,9 +1,49 @@
-use crate::db::{Database, DatabaseTrait};
+use crate::{
+    db::{
+        initialize_retrying, Database, DatabaseTrait, INITIALIZE_ATTEMPTS, INITIALIZE_RETRY_DELAY,
+    },
+    env::EnvReader,
+};
 use anyhow::Result;
-use std::env::var;
+use std::time::Duration;
 
 pub mod db;
-use db::ClickhouseDb;
+pub mod options;
+pub mod recent_tokens;
+pub mod spill;
+use db::{ClickhouseDb, DEFAULT_TOKEN_DEDUPE_WINDOW};
+use options::ClickhouseOptions;
+use spill::InsertRetryConfig;
+
+/// The tables and views the queries read
+pub const EXPECTED_TABLES: &[&str] = &[
+    "swap_events",
+    "swap_events_ohlcv_v",
+    "candlesticks",
+    "pairs",
+    "tokens",
+    "token_launches",
+    "token_search_with_stats_v",
+    "token_window_stats",
+    "token_daily_rollups",
+    "wallet_labels",
+    "reference_prices",
+];
+
+/// The projections the queries rely on, as (table, projection, definition)
+pub const EXPECTED_PROJECTIONS: &[(&str, &str, &str)] = &[(
+    "swap_events",
+    "projection_pair_candles",
+    // the sorting key leads with the pair but keeps the trades of a pair ordered by token,
+    // the projection serves the candlesticks of a pair time range without reading every
+    // token, the trades read the other columns from the table
+    "SELECT pair, pubkey, timestamp, price, base_amount, swap_amount, is_buy \
+    ORDER BY pair, timestamp",
+)];
+
+/// The sorting key of the candlesticks, the candlesticks of the intervals starting at the
+/// same time are distinct rows of the ReplacingMergeTree
+pub const CANDLESTICKS_SORTING_KEY: &str = "pubkey, pair, interval, timestamp";
 
 /// Create a new Clickhouse database
 ///
@@ -13,11 +53,19 @@ use db::ClickhouseDb;
 /// * `user` - The username for the Clickhouse database
 /// * `password` - The password for the Clickhouse database
 /// * `database` - The name of the Clickhouse database
+/// * `read_url` - The URL of an optional read replica, read-only queries are routed to it
+///   and fall back to `database_url` when it is unreachable
 /// * `max_swap_event_rows` - The maximum number of swap events to store in the database,
 ///   defaults to 1000
 /// * `max_token_rows` - The maximum number of tokens to store in the database,
 ///   defaults to 1, note that this is large than 1, the get tokens would return none,
 ///   please use it with caution
+/// * `options` - The compression, the settings, the timeouts and the headers of the client
+///
+/// Failed swap event batches are retried and spilled as configured by
+/// [`InsertRetryConfig::read_env`], and identical token retrievals are written once per
+/// `CLICKHOUSE_TOKEN_DEDUPE_SECS`. The projections are only added with `INITIALIZE_SCHEMA`
+/// unset or true, and the initialization is retried while another service holds the tables
 ///
 /// # Returns
 ///
@@ -27,28 +75,105 @@ pub async fn make_db(
     user: &str,
     password: &str,
     database: &str,
+    read_url: Option<&str>,
     max_swap_event_rows: Option<u64>,
     max_token_rows: Option<u64>,
+    options: ClickhouseOptions,
 ) -> Result<Database> {
-    let max_swap_event_rows = max_swap_event_rows.unwrap_or(1000);
-    let max_token_rows = max_token_rows.unwrap_or(1);
-    let mut db = ClickhouseDb::new(database_url, user, password, database)
-        .with_max_swap_event_rows(max_swap_event_rows)
-        .with_max_token_rows(max_token_rows);
-    db.initialize().await?;
+    let mut env = EnvReader::from_env();
+    let config = ClickhouseConfig {
+        database_url: database_url.to_string(),
+        user: user.to_string(),
+        password: password.to_string(),
+        database: database.to_string(),
+        read_url: read_url.map(str::to_string),
+        max_swap_event_rows,
+        max_token_rows,
+        options,
+        insert_retry: InsertRetryConfig::read_env(&mut env),
+        token_dedupe_window: read_token_dedupe_window(&mut env),
+        initialize_schema: env.flag_or("INITIALIZE_SCHEMA", true),
+    };
+    make_clickhouse_db(&env.finish(config)?).await
+}
+
+/// make_clickhouse_db creates the ClickHouse database of `config` and initializes it
+pub async fn make_clickhouse_db(config: &ClickhouseConfig) -> Result<Database> {
+    let mut db = config
+        .connect()
+        .with_max_swap_event_rows(config.max_swap_event_rows.unwrap_or(1000))
+        .with_max_token_rows(config.max_token_rows.unwrap_or(1))
+        .with_insert_retry_config(config.insert_retry.clone())
+        .with_token_dedupe_window(config.token_dedupe_window)
+        .with_initialize_schema(config.initialize_schema);
+    initialize_retrying(&mut db, INITIALIZE_ATTEMPTS, INITIALIZE_RETRY_DELAY).await?;
     Ok(Box::new(db))
 }
 
-pub async fn make_db_from_env() -> Result<Database> {
-    let database_url = var("CLICKHOUSE_URL").expect("Expected CLICKHOUSE_URL to be set");
-    let user = var("CLICKHOUSE_USER").expect("Expected CLICKHOUSE_USER to be set");
-    let password = var("CLICKHOUSE_PASSWORD").expect("Expected CLICKHOUSE_PASSWORD to be set");
-    let database = var("CLICKHOUSE_DATABASE").expect("Expected CLICKHOUSE_DATABASE to be set");
-    let max_swap_event_rows = var("CLICKHOUSE_MAX_SWAP_EVENTS_ROWS")
-        .ok()
-        .map(|v| v.parse::<u64>().expect("CLICKHOUSE_MAX_SWAP_EVENTS_ROWS must be a number"));
-    let max_token_rows = var("CLICKHOUSE_MAX_TOKEN_ROWS")
-        .ok()
-        .map(|v| v.parse::<u64>().expect("CLICKHOUSE_MAX_TOKEN_ROWS must be a number"));
-    make_db(&database_url, &user, &password, &database, max_swap_event_rows, max_token_rows).await
+/// read_token_dedupe_window reads `CLICKHOUSE_TOKEN_DEDUPE_SECS`, 0 disables the dedupe
+fn read_token_dedupe_window(env: &mut EnvReader) -> Duration {
+    env.parse::<u64>("CLICKHOUSE_TOKEN_DEDUPE_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TOKEN_DEDUPE_WINDOW)
+}
+
+/// The ClickHouse settings of the env
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClickhouseConfig {
+    pub database_url: String,
+    pub user: String,
+    pub password: String,
+    pub database: String,
+    pub read_url: Option<String>,
+    pub max_swap_event_rows: Option<u64>,
+    pub max_token_rows: Option<u64>,
+    pub options: ClickhouseOptions,
+    pub insert_retry: InsertRetryConfig,
+    /// How long an identical token retrieval isn't written again, no dedupe when zero
+    pub token_dedupe_window: Duration,
+    /// Whether the missing tables are created, otherwise they are only verified
+    pub initialize_schema: bool,
+}
+
+impl ClickhouseConfig {
+    /// read_env reads the `CLICKHOUSE_*` variables and `INITIALIZE_SCHEMA`, `CLICKHOUSE_URL`
+    /// being required, the user and the database default to `default` and the password to
+    /// none
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        Self {
+            database_url: env.required("CLICKHOUSE_URL"),
+            user: env.var("CLICKHOUSE_USER").unwrap_or_else(|| "default".to_string()),
+            password: env.var("CLICKHOUSE_PASSWORD").unwrap_or_default(),
+            database: env.var("CLICKHOUSE_DATABASE").unwrap_or_else(|| "default".to_string()),
+            read_url: env.var("CLICKHOUSE_READ_URL"),
+            max_swap_event_rows: env.parse("CLICKHOUSE_MAX_SWAP_EVENTS_ROWS"),
+            max_token_rows: env.parse("CLICKHOUSE_MAX_TOKEN_ROWS"),
+            options: ClickhouseOptions::read_env(env),
+            insert_retry: InsertRetryConfig::read_env(env),
+            token_dedupe_window: read_token_dedupe_window(env),
+            initialize_schema: env.flag_or("INITIALIZE_SCHEMA", true),
+        }
+    }
+
+    /// from_env reads the variables of `read_env`, errors on the missing or invalid ones
+    pub fn from_env() -> Result<Self> {
+        let mut env = EnvReader::from_env();
+        let config = Self::read_env(&mut env);
+        Ok(env.finish(config)?)
+    }
+
+    /// connect returns a client of the database, without initializing its inserters
+    pub fn connect(&self) -> ClickhouseDb {
+        let db = ClickhouseDb::new(&self.database_url, &self.user, &self.password, &self.database);
+        let db = match &self.read_url {
+            Some(read_url) => db.with_read_url(read_url),
+            None => db,
+        };
+        db.with_options(self.options.clone())
+    }
+}
+
+/// make_clickhouse_db_from_env creates the ClickHouse database of the `CLICKHOUSE_*` variables
+pub async fn make_clickhouse_db_from_env() -> Result<Database> {
+    make_clickhouse_db(&ClickhouseConfig::from_env()?).await
 }
@@ -0,0 +1,246 @@
+//! Client settings of the ClickHouse connection, unset options keep the client defaults
+use crate::env::EnvReader;
+use anyhow::{bail, Result};
+use clickhouse::{Client, Compression};
+use hyper_tls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client as HttpClient},
+    rt::TokioExecutor,
+};
+use std::{fmt, time::Duration};
+
+/// The send timeout of the inserts when no connect timeout is configured
+pub const DEFAULT_INSERT_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+/// The end timeout of the inserts when no request timeout is configured
+pub const DEFAULT_INSERT_END_TIMEOUT: Duration = Duration::from_secs(20);
+/// The TCP keepalive and the idle timeout of the pooled connections, as the client defaults
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// make_client returns a client whose connections are established within `connect_timeout`,
+/// the default client when unset
+pub fn make_client(connect_timeout: Option<Duration>) -> Client {
+    let Some(connect_timeout) = connect_timeout else {
+        return Client::default();
+    };
+    let mut connector = HttpConnector::new();
+    connector.set_connect_timeout(Some(connect_timeout));
+    connector.set_keepalive(Some(TCP_KEEPALIVE));
+    connector.enforce_http(false);
+    let http_client = HttpClient::builder(TokioExecutor::new())
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .build(HttpsConnector::new_with_connector(connector));
+    Client::with_http_client(http_client)
+}
+
+/// make_http_client returns a plain HTTP client for the queries whose response headers are
+/// read, which the clickhouse client doesn't expose
+pub fn make_http_client(connect_timeout: Option<Duration>) -> reqwest::Client {
+    let builder = reqwest::Client::builder()
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT);
+    let builder = match connect_timeout {
+        Some(connect_timeout) => builder.connect_timeout(connect_timeout),
+        None => builder,
+    };
+    builder.build().unwrap_or_default()
+}
+
+/// The compression of the ClickHouse HTTP payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClickhouseCompression {
+    None,
+    Lz4,
+}
+
+impl std::str::FromStr for ClickhouseCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "lz4" => Ok(Self::Lz4),
+            _ => bail!("CLICKHOUSE_COMPRESSION must be lz4 or none, got {s:?}"),
+        }
+    }
+}
+
+impl fmt::Display for ClickhouseCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+/// How the ClickHouse client talks to the server, applied to the query client,
+/// the read replica and the inserters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClickhouseOptions {
+    /// The payload compression, the client default when unset
+    pub compression: Option<ClickhouseCompression>,
+    /// Whether the inserts are buffered server side, sets `async_insert`
+    /// and `wait_for_async_insert`
+    pub async_insert: bool,
+    /// The time allowed to connect, also to send the first chunk of an insert
+    pub connect_timeout: Option<Duration>,
+    /// The time allowed to finish an insert, also the `max_execution_time` of the queries
+    /// but the aggregations, the prunes and the mutations
+    pub request_timeout: Option<Duration>,
+    /// The extra HTTP headers of every request, some managed providers require them
+    pub headers: Vec<(String, String)>,
+}
+
+/// parse_headers parses `name=value` pairs separated by commas
+fn parse_headers(value: &str) -> Result<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            }
+            _ => bail!("CLICKHOUSE_HEADERS must be name=value pairs, got {pair:?}"),
+        })
+        .collect()
+}
+
+impl ClickhouseOptions {
+    /// read_env reads `CLICKHOUSE_COMPRESSION`, `CLICKHOUSE_ASYNC_INSERT`,
+    /// `CLICKHOUSE_CONNECT_TIMEOUT_SECS`, `CLICKHOUSE_REQUEST_TIMEOUT_SECS`
+    /// and `CLICKHOUSE_HEADERS`, recording the invalid ones
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let compression = env.var("CLICKHOUSE_COMPRESSION").and_then(|v| env.merge(v.parse()));
+        let headers = env.var("CLICKHOUSE_HEADERS").map(|v| parse_headers(&v));
+        Self {
+            compression,
+            async_insert: env.flag_or("CLICKHOUSE_ASYNC_INSERT", false),
+            connect_timeout: env.secs("CLICKHOUSE_CONNECT_TIMEOUT_SECS"),
+            request_timeout: env.secs("CLICKHOUSE_REQUEST_TIMEOUT_SECS"),
+            headers: headers.and_then(|headers| env.merge(headers)).unwrap_or_default(),
+        }
+    }
+
+    /// settings returns the ClickHouse settings sent with every request
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let mut settings = vec![];
+        if self.async_insert {
+            settings.push(("async_insert", "1".to_string()));
+            settings.push(("wait_for_async_insert", "1".to_string()));
+        }
+        if let Some(timeout) = self.request_timeout {
+            settings.push(("max_execution_time", timeout.as_secs().to_string()));
+        }
+        settings
+    }
+
+    /// insert_timeouts returns the send and end timeouts of the inserts
+    pub fn insert_timeouts(&self) -> (Option<Duration>, Option<Duration>) {
+        (
+            Some(self.connect_timeout.unwrap_or(DEFAULT_INSERT_SEND_TIMEOUT)),
+            Some(self.request_timeout.unwrap_or(DEFAULT_INSERT_END_TIMEOUT)),
+        )
+    }
+
+    /// apply sets the compression, the settings and the headers on the client
+    pub fn apply(&self, mut client: Client) -> Client {
+        match self.compression {
+            Some(ClickhouseCompression::None) => {
+                client = client.with_compression(Compression::None)
+            }
+            Some(ClickhouseCompression::Lz4) => client = client.with_compression(Compression::Lz4),
+            None => {}
+        }
+        for (name, value) in self.settings() {
+            client = client.with_option(name, value);
+        }
+        for (name, value) in &self.headers {
+            client = client.with_header(name, value);
+        }
+        client
+    }
+}
+
+/// Display prints the effective options, the header values are left out as they may hold secrets
+impl fmt::Display for ClickhouseOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (send_timeout, end_timeout) = self.insert_timeouts();
+        let secs = |t: Option<Duration>| t.map(|t| format!("{}s", t.as_secs()));
+        write!(
+            f,
+            "compression={}, async_insert={}, insert_timeouts={}/{}, max_execution_time={}, headers=[{}]",
+            self.compression.map(|c| c.to_string()).unwrap_or_else(|| "default".to_string()),
+            self.async_insert,
+            secs(send_timeout).unwrap_or_default(),
+            secs(end_timeout).unwrap_or_default(),
+            secs(self.request_timeout).unwrap_or_else(|| "default".to_string()),
+            self.headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", "),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(vars: &[(&str, &str)]) -> Result<ClickhouseOptions, crate::ConfigError> {
+        let mut env = EnvReader::from_vars(vars);
+        let options = ClickhouseOptions::read_env(&mut env);
+        env.finish(options)
+    }
+
+    #[test]
+    fn test_options_read_env() {
+        let options = read(&[]).unwrap();
+        assert_eq!(options, ClickhouseOptions::default());
+        assert!(options.settings().is_empty());
+        assert_eq!(
+            options.insert_timeouts(),
+            (Some(DEFAULT_INSERT_SEND_TIMEOUT), Some(DEFAULT_INSERT_END_TIMEOUT))
+        );
+
+        let options = read(&[
+            ("CLICKHOUSE_COMPRESSION", "LZ4"),
+            ("CLICKHOUSE_ASYNC_INSERT", "true"),
+            ("CLICKHOUSE_CONNECT_TIMEOUT_SECS", "3"),
+            ("CLICKHOUSE_REQUEST_TIMEOUT_SECS", "60"),
+            ("CLICKHOUSE_HEADERS", "X-Api-Key=secret, X-Region = eu"),
+        ])
+        .unwrap();
+        assert_eq!(options.compression, Some(ClickhouseCompression::Lz4));
+        assert!(options.async_insert);
+        assert_eq!(options.connect_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(options.request_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(
+            options.headers,
+            vec![
+                ("X-Api-Key".to_string(), "secret".to_string()),
+                ("X-Region".to_string(), "eu".to_string())
+            ]
+        );
+        assert_eq!(
+            options.settings(),
+            vec![
+                ("async_insert", "1".to_string()),
+                ("wait_for_async_insert", "1".to_string()),
+                ("max_execution_time", "60".to_string()),
+            ]
+        );
+        let display = options.to_string();
+        assert!(display.contains("compression=lz4"));
+        assert!(display.contains("headers=[X-Api-Key, X-Region]"));
+        assert!(!display.contains("secret"));
+
+        let error = read(&[
+            ("CLICKHOUSE_COMPRESSION", "zstd"),
+            ("CLICKHOUSE_ASYNC_INSERT", "yes"),
+            ("CLICKHOUSE_CONNECT_TIMEOUT_SECS", "0"),
+            ("CLICKHOUSE_REQUEST_TIMEOUT_SECS", "soon"),
+            ("CLICKHOUSE_HEADERS", "X-Api-Key"),
+        ])
+        .unwrap_err();
+        assert_eq!(error.problems.len(), 5, "{error}");
+    }
+}
@@ -0,0 +1,156 @@
+//! the tokens recently written by this process, so the repeated retrievals of an unchanged
+//! token are skipped without querying the table, the rows still buffered in the token
+//! inserter included
+use crate::models::Token;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// How many written tokens are remembered by default, the oldest are forgotten first
+pub const DEFAULT_RECENT_TOKENS_CAPACITY: usize = 100_000;
+
+#[derive(Debug, Default)]
+struct Entries {
+    tokens: HashMap<String, (Token, u64)>,
+    /// The mints in write order, a mint written again is pushed again with a new generation
+    order: VecDeque<(String, u64)>,
+    generation: u64,
+}
+
+/// A bounded record of the last row written of each token, checked and updated under one
+/// lock, so concurrent writes of the same retrieval are written once
+#[derive(Debug)]
+pub struct RecentTokens {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    window: Duration,
+}
+
+impl RecentTokens {
+    /// new remembers up to `capacity` tokens, a retrieval within `window` of the written one
+    /// is a duplicate, a zero window or capacity disables the dedupe
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        Self { entries: Mutex::new(Entries::default()), capacity, window }
+    }
+
+    /// check_and_record returns true if `token` repeats the row written within the window,
+    /// otherwise records it as written and returns false
+    pub fn check_and_record(&self, token: &Token) -> bool {
+        if self.window.is_zero() || self.capacity == 0 {
+            return false;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((recent, _)) = entries.tokens.get(&token.token) {
+            let since = token.retrieval_timestamp.saturating_sub(self.window.as_secs());
+            if recent.retrieval_timestamp >= since && is_duplicate_token(recent, token) {
+                return true;
+            }
+        }
+
+        entries.generation += 1;
+        let generation = entries.generation;
+        entries.tokens.insert(token.token.clone(), (token.clone(), generation));
+        entries.order.push_back((token.token.clone(), generation));
+        while entries.tokens.len() > self.capacity {
+            let Some((mint, generation)) = entries.order.pop_front() else {
+                break;
+            };
+            if entries.tokens.get(&mint).is_some_and(|(_, latest)| *latest == generation) {
+                entries.tokens.remove(&mint);
+            }
+        }
+        false
+    }
+
+    /// forget drops the recorded row of a mint, so its next retrieval is written
+    pub fn forget(&self, mint: &str) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).tokens.remove(mint);
+    }
+}
+
+/// is_duplicate_token returns true if writing `token` would only repeat the `recent` row,
+/// the launchpad, the graduation and the launch recorded on the row are kept unless the token
+/// sets them
+pub fn is_duplicate_token(recent: &Token, token: &Token) -> bool {
+    recent.same_metadata(token)
+        && (token.launchpad.is_empty() || token.launchpad == recent.launchpad)
+        && (token.graduated_at == 0 || token.graduated_at == recent.graduated_at)
+        && (token.graduation_pool.is_empty() || token.graduation_pool == recent.graduation_pool)
+        && (token.launch_pool.is_empty()
+            || (token.launch_pool == recent.launch_pool
+                && token.launch_dex == recent.launch_dex
+                && token.launch_timestamp == recent.launch_timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::make_token;
+
+    #[test]
+    fn test_is_duplicate_token() {
+        let recent = Token {
+            retrieval_timestamp: 1_000,
+            launchpad: "pump".to_string(),
+            graduated_at: 900,
+            graduation_pool: "pool".to_string(),
+            ..make_token("mint", "TKN", "Token")
+        };
+        // a later retrieval of the same metadata, the ingestor doesn't know the graduation
+        let token = Token {
+            retrieval_timestamp: 1_100,
+            graduated_at: 0,
+            graduation_pool: String::new(),
+            supply: recent.supply * (1.0 + 1e-12),
+            ..recent.clone()
+        };
+        assert!(is_duplicate_token(&recent, &token));
+        assert!(is_duplicate_token(&recent, &Token { launchpad: String::new(), ..token.clone() }));
+
+        assert!(!is_duplicate_token(&recent, &Token { supply: 1.0, ..token.clone() }));
+        assert!(!is_duplicate_token(&recent, &Token { uri: "uri".to_string(), ..token.clone() }));
+        let relaunched = Token { launchpad: "bonk".to_string(), ..token.clone() };
+        assert!(!is_duplicate_token(&recent, &relaunched));
+        assert!(!is_duplicate_token(&recent, &Token { graduated_at: 950, ..token.clone() }));
+        let regraduated = Token { graduation_pool: "other".to_string(), ..token.clone() };
+        assert!(!is_duplicate_token(&recent, &regraduated));
+        let relisted = Token {
+            launch_pool: "pool".to_string(),
+            launch_dex: Some(crate::Dexes::PumpFun),
+            ..token
+        };
+        assert!(!is_duplicate_token(&recent, &relisted));
+    }
+
+    #[test]
+    fn test_check_and_record() {
+        let recent_tokens = RecentTokens::new(2, Duration::from_secs(3600));
+        let token = |mint: &str, retrieval_timestamp: u64, name: &str| Token {
+            retrieval_timestamp,
+            ..make_token(mint, "TKN", name)
+        };
+
+        assert!(!recent_tokens.check_and_record(&token("a", 1_000, "A")));
+        assert!(recent_tokens.check_and_record(&token("a", 1_001, "A")));
+        // a change is written and becomes the recorded row
+        assert!(!recent_tokens.check_and_record(&token("a", 1_002, "Renamed")));
+        assert!(recent_tokens.check_and_record(&token("a", 1_003, "Renamed")));
+        // as is the first retrieval after the window
+        assert!(!recent_tokens.check_and_record(&token("a", 4_603, "Renamed")));
+
+        // the oldest written mint is forgotten beyond the capacity
+        assert!(!recent_tokens.check_and_record(&token("b", 4_603, "B")));
+        assert!(!recent_tokens.check_and_record(&token("c", 4_603, "C")));
+        assert!(!recent_tokens.check_and_record(&token("a", 4_604, "Renamed")));
+        assert!(recent_tokens.check_and_record(&token("c", 4_604, "C")));
+
+        recent_tokens.forget("c");
+        assert!(!recent_tokens.check_and_record(&token("c", 4_605, "C")));
+
+        let disabled = RecentTokens::new(2, Duration::ZERO);
+        assert!(!disabled.check_and_record(&token("a", 1_000, "A")));
+        assert!(!disabled.check_and_record(&token("a", 1_000, "A")));
+    }
+}
@@ -14,13 +14,19 @@ CREATE TABLE IF NOT EXISTS swap_events (
   signers Array(String) CODEC(LZ4),
   is_buy Bool,
   is_pump Bool,
+  is_wash Bool DEFAULT false,
+  fee_amount Float64 DEFAULT 0,
+  fee_mint LowCardinality(String) DEFAULT '' CODEC(LZ4),
+  is_quote_pair Bool DEFAULT false,
+  pct_of_supply Float64 DEFAULT 0,
+  fee_payer LowCardinality(String) DEFAULT '' CODEC(LZ4),
   INDEX idx_pubkey_timestamp (pubkey, timestamp) TYPE minmax GRANULARITY 1,
   INDEX idx_signers signers TYPE bloom_filter(0.01) GRANULARITY 4,
-  INDEX idx_signature_timestamp (signature, timestamp) TYPE minmax GRANULARITY 1024
-
-  -- we could use projections, but it's not worth it for the current query
-  -- PROJECTION projection_by_pubkey (SELECT pubkey, timestamp, price, market_cap, base_amount, quote_amount, swap_amount ORDER BY pubkey, timestamp),
-	-- PROJECTION projection_by_owner (SELECT owner, timestamp, price, base_amount, quote_amount, swap_amount, signature ORDER BY owner, timestamp)
+  INDEX idx_owner owner TYPE bloom_filter(0.01) GRANULARITY 4,
+  INDEX idx_fee_payer fee_payer TYPE bloom_filter(0.01) GRANULARITY 4,
+  INDEX idx_signature_timestamp (signature, timestamp) TYPE minmax GRANULARITY 1024,
+  -- the candlesticks of a pair time range read it, it only carries the columns they aggregate
+  PROJECTION projection_pair_candles (SELECT pair, pubkey, timestamp, price, base_amount, swap_amount, is_buy ORDER BY pair, timestamp)
 )
 ENGINE = MergeTree() 
 PARTITION BY toYYYYMMDD(fromUnixTimestamp(timestamp))
@@ -55,9 +61,149 @@ CREATE TABLE IF NOT EXISTS candlesticks
     `low` Float64,
     `close` Float64,
     `volume` Float64,
-    `turnover` Float64
+    `turnover` Float64,
+    `buy_volume` Float64 DEFAULT 0,
+    `sell_volume` Float64 DEFAULT 0,
+    `buy_turnover` Float64 DEFAULT 0,
+    `sell_turnover` Float64 DEFAULT 0
 )
 ENGINE = ReplacingMergeTree(timestamp)
 PARTITION BY toYYYYMMDD(fromUnixTimestamp(timestamp))
-PRIMARY KEY (pubkey, pair, timestamp)
-ORDER BY (pubkey, pair, timestamp);
+PRIMARY KEY (pubkey, pair, interval, timestamp)
+ORDER BY (pubkey, pair, interval, timestamp);
+
+-- the mints and dex of the pairs, recorded on their first processed swap,
+-- read by their earliest record
+CREATE TABLE IF NOT EXISTS pairs
+(
+    `pair` String,
+    `base_mint` String,
+    `quote_mint` String,
+    `dex` LowCardinality(String),
+    `first_seen` UInt64,
+    -- the inverted first_seen, so that merges keep the earliest record of a pair
+    `first_seen_version` UInt64 MATERIALIZED bitNot(first_seen)
+)
+ENGINE = ReplacingMergeTree(first_seen_version)
+ORDER BY pair;
+
+-- the rolling per-token stats of the top tokens windows, inserted every minute by the
+-- scheduler and read by their latest computed_at, older refreshes expire
+CREATE TABLE IF NOT EXISTS token_window_stats
+(
+    `window_secs` UInt32,
+    `exclude_wash` Bool,
+    `computed_at` UInt64,
+    `pubkey` String,
+    `price` Float64,
+    `market_cap` Float64,
+    `is_pump` Bool,
+    `volume` Float64,
+    `turnover` Float64,
+    `first_price` Float64,
+    `last_price` Float64,
+    `unique_traders` UInt64,
+    `unique_buyers` UInt64,
+    `unique_sellers` UInt64
+)
+ENGINE = MergeTree
+ORDER BY (window_secs, exclude_wash, computed_at, pubkey)
+TTL toDateTime(computed_at) + INTERVAL 1 HOUR;
+
+-- the per-token trading of each UTC day, written by the daily scheduler job before the
+-- swap events of the day are dropped, a day aggregated again is replaced by its latest insert
+CREATE TABLE IF NOT EXISTS token_daily_rollups
+(
+    `pubkey` String,
+    `date` Date,
+    `open` Float64,
+    `high` Float64,
+    `low` Float64,
+    `close` Float64,
+    `volume` Float64,
+    `turnover` Float64,
+    `trade_count` UInt64,
+    `unique_traders` UInt64,
+    `computed_at` UInt64
+)
+ENGINE = ReplacingMergeTree(computed_at)
+PARTITION BY toYYYYMM(date)
+ORDER BY (pubkey, date);
+
+-- the labels of the known wallets, e.g. the CEX hot wallets, imported by the analysts,
+-- read by the latest label of an address
+CREATE TABLE IF NOT EXISTS wallet_labels
+(
+    `address` String,
+    `label` String,
+    `category` LowCardinality(String),
+    `added_at` UInt64
+)
+ENGINE = ReplacingMergeTree(added_at)
+ORDER BY address;
+
+-- the launchpads, graduations and launches of the tokens, inserted as the ingestor sees them
+-- instead of mutating the token rows, which may still be buffered, every field is read from
+-- the earliest record setting it and fills the token rows lacking it
+CREATE TABLE IF NOT EXISTS token_launches
+(
+    `token` String,
+    `launchpad` LowCardinality(String),
+    `graduated_at` UInt64,
+    `graduation_pool` String,
+    `launch_dex` LowCardinality(String),
+    `launch_pool` String,
+    `launch_timestamp` UInt64,
+    `recorded_at` UInt64
+)
+ENGINE = ReplacingMergeTree
+ORDER BY (token, launchpad, graduation_pool, launch_pool);
+
+-- the reference prices of the synthetic pairs, e.g. the Binance SOL/USD trades sampled once
+-- per second with PERSIST_SOL_PRICE_TRADES, read by the candlesticks of these pairs
+CREATE TABLE IF NOT EXISTS reference_prices
+(
+    `pair` LowCardinality(String),
+    `price` Float64,
+    `timestamp` UInt64
+)
+ENGINE = MergeTree
+PARTITION BY toYYYYMM(fromUnixTimestamp(timestamp))
+ORDER BY (pair, timestamp);
+
+-- migrations for existing deployments
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS is_wash Bool DEFAULT false AFTER is_pump;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS verified Bool DEFAULT false;
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS fee_amount Float64 DEFAULT 0 AFTER is_wash;
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS fee_mint LowCardinality(String) DEFAULT '' CODEC(LZ4) AFTER fee_amount;
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS is_quote_pair Bool DEFAULT false AFTER fee_mint;
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS pct_of_supply Float64 DEFAULT 0 AFTER is_quote_pair;
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS fee_payer LowCardinality(String) DEFAULT '' CODEC(LZ4) AFTER pct_of_supply;
+-- the indexes are only built for the new parts, the trades of a wallet in the older parts
+-- are found by scanning them
+ALTER TABLE swap_events ADD INDEX IF NOT EXISTS idx_owner owner TYPE bloom_filter(0.01) GRANULARITY 4;
+ALTER TABLE swap_events ADD INDEX IF NOT EXISTS idx_fee_payer fee_payer TYPE bloom_filter(0.01) GRANULARITY 4;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launchpad LowCardinality(String) DEFAULT '' AFTER is_mutable;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS graduated_at UInt64 DEFAULT 0 AFTER launchpad;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS graduation_pool String DEFAULT '' AFTER graduated_at;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launch_dex LowCardinality(String) DEFAULT '' AFTER graduation_pool;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launch_pool String DEFAULT '' AFTER launch_dex;
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launch_timestamp UInt64 DEFAULT 0 AFTER launch_pool;
+ALTER TABLE token_window_stats ADD COLUMN IF NOT EXISTS unique_traders UInt64 AFTER last_price;
+ALTER TABLE token_window_stats ADD COLUMN IF NOT EXISTS unique_buyers UInt64 AFTER unique_traders;
+ALTER TABLE token_window_stats ADD COLUMN IF NOT EXISTS unique_sellers UInt64 AFTER unique_buyers;
+-- the candlesticks aggregated before the buy and sell split read it as zeros
+ALTER TABLE candlesticks ADD COLUMN IF NOT EXISTS buy_volume Float64 DEFAULT 0 AFTER turnover;
+ALTER TABLE candlesticks ADD COLUMN IF NOT EXISTS sell_volume Float64 DEFAULT 0 AFTER buy_volume;
+ALTER TABLE candlesticks ADD COLUMN IF NOT EXISTS buy_turnover Float64 DEFAULT 0 AFTER sell_volume;
+ALTER TABLE candlesticks ADD COLUMN IF NOT EXISTS sell_turnover Float64 DEFAULT 0 AFTER buy_turnover;
+-- the projection copying every column is replaced by the narrower projection_pair_candles
+ALTER TABLE swap_events DROP PROJECTION IF EXISTS projection_by_pair_timestamp;
+-- the projection is only built for the new parts, `initialize` adds it and materializes the
+-- existing parts once when it is missing, to do it by hand run the rewrite a single time:
+--   ALTER TABLE swap_events MATERIALIZE PROJECTION projection_pair_candles;
+ALTER TABLE swap_events ADD PROJECTION IF NOT EXISTS projection_pair_candles (SELECT pair, pubkey, timestamp, price, base_amount, swap_amount, is_buy ORDER BY pair, timestamp);
+-- candlesticks keyed without the interval merge the candlesticks of different intervals
+-- starting at the same time, the sorting key of an existing table can't be changed in place,
+-- `initialize` copies it into a table keyed by interval swapped in its place, then
+-- `sonar backfill-candlesticks` rolls the derived intervals up again
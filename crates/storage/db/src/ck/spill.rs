@@ -0,0 +1,477 @@
+//! Batched inserts that retry failed commits and spill undeliverable batches to disk,
+//! so a ClickHouse restart doesn't drop the rows buffered in memory
+use super::options::{DEFAULT_INSERT_END_TIMEOUT, DEFAULT_INSERT_SEND_TIMEOUT};
+use crate::{
+    env::EnvReader,
+    errors::{classified, is_schema_mismatch},
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use clickhouse::{Client, Row};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{info, warn};
+
+/// How failed batch inserts are retried and where they are spilled
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertRetryConfig {
+    /// The number of insert attempts before a batch is spilled
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled on every further retry
+    pub initial_backoff: Duration,
+    /// The directory of the spill files
+    pub spill_dir: PathBuf,
+}
+
+impl Default for InsertRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            spill_dir: PathBuf::from("spill"),
+        }
+    }
+}
+
+impl InsertRetryConfig {
+    /// Reads `CLICKHOUSE_INSERT_MAX_ATTEMPTS`, `CLICKHOUSE_INSERT_BACKOFF_MS` and
+    /// `CLICKHOUSE_SPILL_DIR`, falling back to the defaults
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let default = Self::default();
+        let max_attempts = env.parse_or("CLICKHOUSE_INSERT_MAX_ATTEMPTS", default.max_attempts);
+        env.check(max_attempts > 0, "CLICKHOUSE_INSERT_MAX_ATTEMPTS must not be 0");
+        Self {
+            max_attempts,
+            initial_backoff: env
+                .millis("CLICKHOUSE_INSERT_BACKOFF_MS")
+                .unwrap_or(default.initial_backoff),
+            spill_dir: env
+                .var("CLICKHOUSE_SPILL_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(default.spill_dir),
+        }
+    }
+}
+
+/// Writes a batch of rows in a single insert
+#[async_trait]
+pub trait BatchWriter<T>: Send + Sync {
+    async fn insert(&self, rows: &[T]) -> Result<()>;
+}
+
+/// Inserts batches into a ClickHouse table
+pub struct ClickhouseBatchWriter {
+    client: Client,
+    table: String,
+    send_timeout: Option<Duration>,
+    end_timeout: Option<Duration>,
+}
+
+impl ClickhouseBatchWriter {
+    pub fn new(client: Client, table: &str) -> Self {
+        Self {
+            client,
+            table: table.to_string(),
+            send_timeout: Some(DEFAULT_INSERT_SEND_TIMEOUT),
+            end_timeout: Some(DEFAULT_INSERT_END_TIMEOUT),
+        }
+    }
+
+    /// set the send and end timeouts of the inserts
+    pub fn with_timeouts(
+        mut self,
+        send_timeout: Option<Duration>,
+        end_timeout: Option<Duration>,
+    ) -> Self {
+        self.send_timeout = send_timeout;
+        self.end_timeout = end_timeout;
+        self
+    }
+
+    /// timeouts returns the send and end timeouts of the inserts
+    pub fn timeouts(&self) -> (Option<Duration>, Option<Duration>) {
+        (self.send_timeout, self.end_timeout)
+    }
+}
+
+#[async_trait]
+impl<T> BatchWriter<T> for ClickhouseBatchWriter
+where
+    T: Row + Serialize + Send + Sync,
+{
+    async fn insert(&self, rows: &[T]) -> Result<()> {
+        let mut insert = self
+            .client
+            .insert::<T>(&self.table)
+            .context(format!("failed to prepare {} insert statement", self.table))?
+            .with_timeouts(self.send_timeout, self.end_timeout);
+        for row in rows {
+            insert.write(row).await.map_err(classified)?;
+        }
+        insert.end().await.map_err(classified)?;
+        Ok(())
+    }
+}
+
+/// The number of rows spilled to disk and replayed from it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SpillStats {
+    pub spilled_rows: u64,
+    pub recovered_rows: u64,
+}
+
+struct Batch<T> {
+    rows: Vec<T>,
+    started_at: Instant,
+}
+
+/// Buffers rows and commits them once `max_rows` are pending or `period` has passed,
+/// retrying failed commits and spilling the batch to a JSONL file when they keep failing
+pub struct ResilientInserter<T> {
+    writer: Arc<dyn BatchWriter<T>>,
+    table: String,
+    config: InsertRetryConfig,
+    max_rows: usize,
+    period: Duration,
+    batch: Mutex<Batch<T>>,
+    recovering: Mutex<()>,
+    spill_seq: AtomicU64,
+    spilled_rows: AtomicU64,
+    recovered_rows: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl<T> ResilientInserter<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+{
+    pub fn new(
+        writer: Arc<dyn BatchWriter<T>>,
+        table: &str,
+        max_rows: usize,
+        period: Duration,
+        config: InsertRetryConfig,
+    ) -> Self {
+        Self {
+            writer,
+            table: table.to_string(),
+            config,
+            max_rows: max_rows.max(1),
+            period,
+            batch: Mutex::new(Batch { rows: Vec::new(), started_at: Instant::now() }),
+            recovering: Mutex::new(()),
+            spill_seq: AtomicU64::new(0),
+            spilled_rows: AtomicU64::new(0),
+            recovered_rows: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn stats(&self) -> SpillStats {
+        SpillStats {
+            spilled_rows: self.spilled_rows.load(Ordering::Relaxed),
+            recovered_rows: self.recovered_rows.load(Ordering::Relaxed),
+        }
+    }
+
+    /// write buffers the row and commits the batch when it is due,
+    /// returns the number of rows committed
+    pub async fn write(&self, row: T) -> Result<u64> {
+        // writers wait while a batch is being retried, which slows the pipeline down
+        // instead of growing the buffer without bound
+        let mut batch = self.batch.lock().await;
+        if self.closed.load(Ordering::Relaxed) {
+            anyhow::bail!("{} inserter is closed", self.table);
+        }
+        batch.rows.push(row);
+        if batch.rows.len() < self.max_rows && batch.started_at.elapsed() < self.period {
+            return Ok(0);
+        }
+        let rows = std::mem::take(&mut batch.rows);
+        batch.started_at = Instant::now();
+        self.commit(rows).await
+    }
+
+    /// flush commits the pending rows regardless of the batch thresholds
+    pub async fn flush(&self) -> Result<u64> {
+        let mut batch = self.batch.lock().await;
+        let rows = std::mem::take(&mut batch.rows);
+        batch.started_at = Instant::now();
+        self.commit(rows).await
+    }
+
+    /// close commits the pending rows and rejects further writes, closing twice is a no-op
+    pub async fn close(&self) -> Result<u64> {
+        self.closed.store(true, Ordering::Relaxed);
+        self.flush().await
+    }
+
+    async fn commit(&self, rows: Vec<T>) -> Result<u64> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        match self.insert_with_retry(&rows).await {
+            Ok(()) => Ok(rows.len() as u64),
+            Err(e) => {
+                warn!(error = ?e, rows = rows.len(), table = %self.table, "Spilling batch to disk");
+                self.spill(&rows).await?;
+                // the batch is kept for after the migration, the caller has to stop writing
+                if is_schema_mismatch(&e) {
+                    return Err(e);
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    async fn insert_with_retry(&self, rows: &[T]) -> Result<()> {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.writer.insert(rows).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.config.max_attempts && !is_schema_mismatch(&e) => {
+                    warn!(error = ?e, attempt, table = %self.table, "Batch insert failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn spill(&self, rows: &[T]) -> Result<()> {
+        tokio::fs::create_dir_all(&self.config.spill_dir)
+            .await
+            .context("Failed to create spill dir")?;
+        let mut lines = String::new();
+        for row in rows {
+            lines.push_str(&serde_json::to_string(row)?);
+            lines.push('\n');
+        }
+        // the timestamp and sequence keep the files in write order
+        let path = self.config.spill_dir.join(format!(
+            "{}-{:016}-{:06}.jsonl",
+            self.table,
+            Utc::now().timestamp_millis(),
+            self.spill_seq.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&path, lines)
+            .await
+            .context(format!("Failed to write spill file {}", path.display()))?;
+        self.spilled_rows.fetch_add(rows.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// spill_files returns the spill files of the table, oldest first
+    pub async fn spill_files(&self) -> Result<Vec<PathBuf>> {
+        let mut entries = match tokio::fs::read_dir(&self.config.spill_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).context("Failed to read spill dir"),
+        };
+        let prefix = format!("{}-", self.table);
+        let mut files = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with(&prefix) && name.ends_with(".jsonl") {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// recover replays the spill files and removes them once inserted,
+    /// returns the number of rows recovered
+    pub async fn recover(&self) -> Result<u64> {
+        // a recovery is already running
+        let Ok(_guard) = self.recovering.try_lock() else {
+            return Ok(0);
+        };
+        let mut recovered = 0;
+        for path in self.spill_files().await? {
+            let content = tokio::fs::read_to_string(&path)
+                .await
+                .context(format!("Failed to read spill file {}", path.display()))?;
+            let rows = content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match serde_json::from_str::<T>(line) {
+                    Ok(row) => Some(row),
+                    Err(e) => {
+                        warn!(error = ?e, path = %path.display(), "Skipping malformed spilled row");
+                        None
+                    }
+                })
+                .collect::<Vec<T>>();
+            if !rows.is_empty() {
+                self.insert_with_retry(&rows).await?;
+            }
+            tokio::fs::remove_file(&path)
+                .await
+                .context(format!("Failed to remove spill file {}", path.display()))?;
+            recovered += rows.len() as u64;
+            self.recovered_rows.fetch_add(rows.len() as u64, Ordering::Relaxed);
+        }
+        if recovered > 0 {
+            info!(rows = recovered, table = %self.table, "Recovered spilled rows");
+        }
+        Ok(recovered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::StorageError;
+    use std::sync::atomic::AtomicU32;
+
+    /// a writer failing the next `failures` inserts, with a schema mismatch if `schema_mismatch`
+    #[derive(Default)]
+    struct MockWriter {
+        failures: AtomicU32,
+        attempts: AtomicU32,
+        schema_mismatch: bool,
+        rows: std::sync::Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl BatchWriter<u64> for MockWriter {
+        async fn insert(&self, rows: &[u64]) -> Result<()> {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            let failing = self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |f| f.checked_sub(1))
+                .is_ok();
+            if failing && self.schema_mismatch {
+                return Err(StorageError::SchemaMismatch {
+                    detail: "No such column is_wash".to_string(),
+                }
+                .into());
+            }
+            if failing {
+                anyhow::bail!("connection refused");
+            }
+            self.rows.lock().unwrap().extend_from_slice(rows);
+            Ok(())
+        }
+    }
+
+    fn make_inserter(writer: Arc<MockWriter>, name: &str) -> ResilientInserter<u64> {
+        let spill_dir = std::env::temp_dir().join(format!(
+            "sonar-spill-{}-{}",
+            name,
+            Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        let config = InsertRetryConfig {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            spill_dir,
+        };
+        ResilientInserter::new(writer, "swap_events", 3, Duration::from_secs(60), config)
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() {
+        let writer = Arc::new(MockWriter { failures: AtomicU32::new(2), ..Default::default() });
+        let inserter = make_inserter(writer.clone(), "retry");
+
+        assert_eq!(inserter.write(1).await.unwrap(), 0);
+        assert_eq!(inserter.write(2).await.unwrap(), 0);
+        assert_eq!(inserter.write(3).await.unwrap(), 3);
+        assert_eq!(writer.attempts.load(Ordering::Relaxed), 3);
+        assert_eq!(*writer.rows.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(inserter.stats(), SpillStats::default());
+        assert!(inserter.spill_files().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spills_and_recovers() {
+        let writer = Arc::new(MockWriter { failures: AtomicU32::new(3), ..Default::default() });
+        let inserter = make_inserter(writer.clone(), "recover");
+
+        // the batch is spilled once the attempts are exhausted
+        for row in 1..=3 {
+            assert_eq!(inserter.write(row).await.unwrap(), 0);
+        }
+        assert_eq!(inserter.stats().spilled_rows, 3);
+        assert_eq!(inserter.spill_files().await.unwrap().len(), 1);
+        assert!(writer.rows.lock().unwrap().is_empty());
+
+        // writes keep being accepted into a fresh batch
+        for row in 4..=6 {
+            inserter.write(row).await.unwrap();
+        }
+        inserter.write(7).await.unwrap();
+        assert_eq!(inserter.flush().await.unwrap(), 1);
+        assert_eq!(*writer.rows.lock().unwrap(), vec![4, 5, 6, 7]);
+
+        assert_eq!(inserter.recover().await.unwrap(), 3);
+        assert_eq!(*writer.rows.lock().unwrap(), vec![4, 5, 6, 7, 1, 2, 3]);
+        assert!(inserter.spill_files().await.unwrap().is_empty());
+        assert_eq!(inserter.stats(), SpillStats { spilled_rows: 3, recovered_rows: 3 });
+
+        let _ = std::fs::remove_dir_all(&inserter.config.spill_dir);
+    }
+
+    #[tokio::test]
+    async fn test_schema_mismatch_is_not_retried() {
+        let writer = Arc::new(MockWriter {
+            failures: AtomicU32::new(1),
+            schema_mismatch: true,
+            ..Default::default()
+        });
+        let inserter = make_inserter(writer.clone(), "schema");
+        inserter.write(1).await.unwrap();
+        inserter.write(2).await.unwrap();
+        let error = inserter.write(3).await.unwrap_err();
+        assert!(is_schema_mismatch(&error));
+        assert_eq!(writer.attempts.load(Ordering::Relaxed), 1);
+        // the rows are kept for after the migration
+        assert_eq!(inserter.stats().spilled_rows, 3);
+        assert_eq!(inserter.recover().await.unwrap(), 3);
+
+        let _ = std::fs::remove_dir_all(&inserter.config.spill_dir);
+    }
+
+    #[tokio::test]
+    async fn test_recover_keeps_files_while_failing() {
+        let writer =
+            Arc::new(MockWriter { failures: AtomicU32::new(u32::MAX), ..Default::default() });
+        let inserter = make_inserter(writer.clone(), "failing");
+        for row in 1..=3 {
+            inserter.write(row).await.unwrap();
+        }
+        assert!(inserter.recover().await.is_err());
+        assert_eq!(inserter.spill_files().await.unwrap().len(), 1);
+        assert_eq!(inserter.stats().recovered_rows, 0);
+
+        let _ = std::fs::remove_dir_all(&inserter.config.spill_dir);
+    }
+
+    #[tokio::test]
+    async fn test_close_commits_pending_rows() {
+        let writer = Arc::new(MockWriter::default());
+        let inserter = make_inserter(writer.clone(), "close");
+        assert_eq!(inserter.write(1).await.unwrap(), 0);
+        assert_eq!(inserter.flush().await.unwrap(), 1);
+        assert_eq!(inserter.write(2).await.unwrap(), 0);
+
+        assert_eq!(inserter.close().await.unwrap(), 1);
+        assert_eq!(inserter.close().await.unwrap(), 0);
+        assert!(inserter.write(3).await.is_err());
+        assert_eq!(*writer.rows.lock().unwrap(), vec![1, 2]);
+    }
+}
@@ -1,58 +1,240 @@
 use crate::models::{
-    candlesticks::{Candlestick, CandlestickInterval},
-    swap::{SwapEvent, Trade},
-    tokens::{Token, TokenDailyStat, TokenPrice, TokenSearch, TokenStat, TopToken},
+    candlesticks::{
+        Candlestick, CandlestickInterval, LatestCandlestick, OutlierPolicy, ReferencePrice,
+    },
+    dexes::Dexes,
+    pairs::{FeeStat, Pair, PairDetail, PairInfo},
+    swap::{SwapEvent, Trade, TradeCursor, TradeFilter},
+    tokens::{
+        SortOrder, Token, TokenDailyRollup, TokenDailyStat, TokenPrice, TokenSearchResult,
+        TokenStat, TopTokensPage, TopTokensSort,
+    },
+    wallet::{WalletActivity, WalletLabel},
+};
+#[cfg(feature = "postgres")]
+use crate::pg::PostgresConfig;
+use crate::{
+    ck::ClickhouseConfig,
+    env::{ConfigError, EnvReader},
+    errors::storage_error,
 };
 use anyhow::Result;
+use chrono::{NaiveDate, NaiveTime};
+use futures::{future, stream::BoxStream, Future};
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+use strum_macros::{Display, EnumString};
+use tracing::warn;
+
+/// The attempts of `initialize_retrying` when the storage is unavailable or busy
+pub const INITIALIZE_ATTEMPTS: u32 = 5;
+/// The delay before the second attempt, doubled before each next one
+pub const INITIALIZE_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 /// A boxed database
 pub type Database = Box<dyn DatabaseTrait + Send + Sync>;
 
+/// The storage backend of the database, ClickHouse unless a small deployment opts into Postgres
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum DatabaseBackend {
+    #[default]
+    Clickhouse,
+    Postgres,
+}
+
+impl DatabaseBackend {
+    /// read_env reads `DATABASE_BACKEND`, ClickHouse when unset
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let Some(backend) = env.var("DATABASE_BACKEND") else {
+            return Self::default();
+        };
+        let parsed = Self::from_str(&backend.to_lowercase()).map_err(|_| {
+            format!("DATABASE_BACKEND must be clickhouse or postgres, got {backend:?}")
+        });
+        env.merge(parsed).unwrap_or_default()
+    }
+}
+
+/// The settings of the database of the backend selected by `DATABASE_BACKEND`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatabaseConfig {
+    Clickhouse(ClickhouseConfig),
+    #[cfg(feature = "postgres")]
+    Postgres(PostgresConfig),
+}
+
+impl DatabaseConfig {
+    /// read_env reads `DATABASE_BACKEND` and the variables of the backend
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        match DatabaseBackend::read_env(env) {
+            DatabaseBackend::Clickhouse => Self::Clickhouse(ClickhouseConfig::read_env(env)),
+            #[cfg(feature = "postgres")]
+            DatabaseBackend::Postgres => Self::Postgres(PostgresConfig::read_env(env)),
+            #[cfg(not(feature = "postgres"))]
+            DatabaseBackend::Postgres => {
+                env.error("DATABASE_BACKEND=postgres requires building with the postgres feature");
+                Self::Clickhouse(ClickhouseConfig::default())
+            }
+        }
+    }
+
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut env = EnvReader::from_env();
+        let config = Self::read_env(&mut env);
+        env.finish(config)
+    }
+}
+
+/// make_db_with_config creates the database of `config` and initializes it
+pub async fn make_db_with_config(config: &DatabaseConfig) -> Result<Database> {
+    match config {
+        DatabaseConfig::Clickhouse(config) => crate::ck::make_clickhouse_db(config).await,
+        #[cfg(feature = "postgres")]
+        DatabaseConfig::Postgres(config) => crate::pg::make_postgres_db_with_config(config).await,
+    }
+}
+
+/// make_db_from_env creates the database of the backend selected by `DATABASE_BACKEND`
+pub async fn make_db_from_env() -> Result<Database> {
+    make_db_with_config(&DatabaseConfig::from_env()?).await
+}
+
+/// initialize_schema_from_env reads `INITIALIZE_SCHEMA`, true when unset; the services
+/// started with false leave the DDL to the designated one and only verify the schema
+pub fn initialize_schema_from_env() -> Result<bool> {
+    let mut env = EnvReader::from_env();
+    let initialize_schema = env.flag_or("INITIALIZE_SCHEMA", true);
+    Ok(env.finish(initialize_schema)?)
+}
+
+/// initialize_retrying initializes the database, retrying up to `attempts` times with a
+/// jittered exponential backoff while the failure may be transient, e.g. another service
+/// creating the same tables
+pub async fn initialize_retrying<D>(db: &mut D, attempts: u32, delay: Duration) -> Result<()>
+where
+    D: DatabaseTrait + Send + ?Sized,
+{
+    let mut attempt = 1;
+    loop {
+        match db.initialize().await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < attempts && is_initialize_retryable(&e) => {
+                let delay = retry_delay(delay, attempt);
+                warn!(error = ?e, attempt, ?delay, "Failed to initialize the database, retrying");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e.context(format!(
+                    "Failed to initialize the database after {attempt} attempts"
+                )))
+            }
+        }
+    }
+}
+
+fn is_initialize_retryable(error: &anyhow::Error) -> bool {
+    storage_error(error).is_some_and(|e| e.is_initialize_retryable())
+}
+
+/// retry_delay returns `delay` doubled for each attempt after the first, plus up to half of
+/// it so that the services starting together don't retry in lockstep
+fn retry_delay(delay: Duration, attempt: u32) -> Duration {
+    let delay = delay.saturating_mul(1 << (attempt - 1).min(16));
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let jitter = nanos.subsec_nanos() as u64 % (delay.as_millis() as u64 / 2 + 1);
+    delay + Duration::from_millis(jitter)
+}
+
+/// The storage of the swap events, the candlesticks, the pairs and the tokens
+///
+/// The reads are cancellation-safe: dropping their future drops the HTTP request or the
+/// postgres fetch in flight, so a handler whose client went away stops waiting on them.
+/// The writes aren't all: a dropped `insert_token` may abort the commit of the clickhouse
+/// token inserter in flight, losing the rows buffered with it, and the server may still run
+/// a dropped `aggregate_*`, `prune_*` or `remove_swap_events` statement to completion.
 #[async_trait::async_trait]
 pub trait DatabaseTrait {
     fn new(database_url: &str, password: &str, user: &str, database: &str) -> Self
     where
         Self: Sized;
+    /// initialize creates the missing tables, or only verifies them with `INITIALIZE_SCHEMA`
+    /// false, and starts the batched writers; initializing twice is a no-op
     async fn initialize(&mut self) -> Result<()>;
     async fn health_check(&self) -> Result<()>;
+    /// verify_schema fails when a table, a view or a projection the queries rely on is missing
+    async fn verify_schema(&self) -> Result<()>;
+    /// commits the rows buffered by the batched writers
+    async fn flush(&self) -> Result<()>;
+    /// flushes and ends the batched writers before shutting down, closing twice is a no-op
+    async fn close(&self) -> Result<()>;
 
     /// uses a batched writer to avoid spamming writes
     async fn insert_swap_event(&self, swap_event: &SwapEvent) -> Result<()>;
 
-    /// returns a list of candlesticks for a given token and interval
+    /// insert_reference_price records a sampled price of a synthetic pair, e.g. SOLUSD,
+    /// the candlesticks of the pair are built from them
+    async fn insert_reference_price(&self, price: &ReferencePrice) -> Result<()>;
+
+    /// returns a list of candlesticks for a given token and interval,
+    /// the high and low clamped by the outlier policy
+    #[allow(clippy::too_many_arguments)]
     async fn get_candlesticks_by_token(
         &self,
         token: &str,
         pairs: &[String],
         interval: CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
         limit: Option<usize>,
         time_from: Option<i32>,
         time_to: Option<i32>,
     ) -> Result<Vec<Candlestick>>;
 
-    /// returns a list of candlesticks for a given pair and interval
+    /// returns a list of candlesticks for a given pair and interval,
+    /// the high and low of the ones built from swap events clamped by the outlier policy;
+    /// the ones of a reference pair are built from its reference prices, without volume
+    #[allow(clippy::too_many_arguments)]
     async fn get_candlesticks_by_pair(
         &self,
         pair: &str,
         token: Option<&str>,
         interval: &CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
         limit: Option<usize>,
         time_from: Option<i32>,
         time_to: Option<i32>,
     ) -> Result<Vec<Candlestick>>;
 
-    /// returns a list of candlesticks for a given pair and interval
+    /// get_latest_candlestick returns the candlesticks of the bucket containing `at` and of the
+    /// previous one for a given pair and interval, aggregated from the swap events of both only
+    async fn get_latest_candlestick(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
+        at: u64,
+    ) -> Result<LatestCandlestick>;
+
+    /// returns a list of candlesticks for a given pair and interval,
+    /// the high and low clamped by the outlier policy
+    #[allow(clippy::too_many_arguments)]
     async fn get_candlesticks_from_swap_events(
         &self,
         pair: &str,
         token: Option<&str>,
         interval: &CandlestickInterval,
+        outlier_policy: &OutlierPolicy,
         limit: Option<usize>,
         time_from: Option<i32>,
         time_to: Option<i32>,
     ) -> Result<Vec<Candlestick>>;
 
-    /// returns a list of candlesticks for a given pair and interval
+    /// returns a list of candlesticks for a given pair and interval, an empty pair returns the
+    /// token-level candlesticks of the token
     #[allow(clippy::too_many_arguments)]
     async fn get_candlesticks_from_candlesticks(
         &self,
@@ -65,43 +247,87 @@ pub trait DatabaseTrait {
         exclude_buckets: Option<Vec<u64>>,
     ) -> Result<Vec<Candlestick>>;
 
-    /// returns a list of top tokens for a given
-    /// limit
+    /// returns the pairs a token traded in since a given timestamp,
+    /// ordered by turnover descending
+    async fn get_pairs_for_token(&self, mint: &str, since: Option<u64>) -> Result<Vec<PairInfo>>;
+
+    /// records the mints and dex of a pair, the first record of a pair wins
+    async fn insert_pair(&self, pair: &Pair) -> Result<()>;
+
+    /// returns the mints, the latest price and the 24h stats of a pair,
+    /// None if the pair never traded
+    async fn get_pair_detail(&self, pair: &str) -> Result<Option<PairDetail>>;
+
+    /// returns the fees collected since a given timestamp per pair and fee mint,
+    /// of a single pair if given, ordered by fees descending
+    async fn get_fee_stats(
+        &self,
+        pair: Option<&str>,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<FeeStat>>;
+
+    /// returns the trades of a wallet aggregated per token between the given timestamps,
+    /// ordered by turnover descending, the wallet is matched on the signers of the trades
+    /// or, if `strict_owner`, on their owner
+    async fn get_wallet_activity(
+        &self,
+        address: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        strict_owner: bool,
+        limit: usize,
+    ) -> Result<Vec<WalletActivity>>;
+
+    /// returns a page of top tokens for a given
+    /// limit and offset
     /// min_volume
     /// min_market_cap
     /// time_range
     /// and pumpfun
+    /// ranked by `sort_by` in `order`, with the number of tokens matching the filters;
+    /// a priced token without volume has a volume of 0, so that `min_volume` 0 keeps it, and
+    /// pumpfun matches the `is_pump` of its latest swap
+    #[allow(clippy::too_many_arguments)]
     async fn get_top_tokens(
         &self,
         limit: usize,
+        offset: usize,
         start_time: u64,
         min_volume: Option<f64>,
         min_market_cap: Option<f64>,
         pumpfun: Option<bool>,
-    ) -> Result<Vec<TopToken>>;
+        exclude_wash: bool,
+        sort_by: TopTokensSort,
+        order: SortOrder,
+    ) -> Result<TopTokensPage>;
 
     /// returns a list of token stats for a given list of tokens
-    async fn get_token_stats(&self, tokens: Vec<String>) -> Result<Vec<TokenStat>>;
+    async fn get_token_stats(
+        &self,
+        tokens: Vec<String>,
+        exclude_wash: bool,
+    ) -> Result<Vec<TokenStat>>;
 
     /// returns a list of token daily stats for a given list of tokens
     async fn get_token_daily_stats(&self, tokens: Vec<String>) -> Result<Vec<TokenDailyStat>>;
 
-    /// returns a list of swap events for a given query
-    async fn get_trades(
-        &self,
-        address: Option<&str>,
-        token: Option<&str>,
-        pair: Option<&str>,
-        signature: Option<&str>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-    ) -> Result<Vec<Trade>>;
+    /// returns the latest swap events matching the filter, the address matching the owner or
+    /// the fee payer, none without an address, a token, a pair or a signature.
+    /// A signature without `time_from` is looked up in the last hour first, then in the
+    /// days around `slot` when given, then in all the stored swap events
+    async fn get_trades(&self, filter: &TradeFilter) -> Result<Vec<Trade>>;
+
+    /// stream_trades streams the trades matching the filter in ascending time order,
+    /// trades are fetched page by page so that the whole result is never held in memory
+    fn stream_trades(&self, filter: TradeFilter) -> BoxStream<'_, Result<Trade>>;
 
     /// get_price returns the price of a given mint at a given timestamp
     async fn get_price(&self, mint: &str, timestamp: i32) -> Result<TokenPrice>;
 
-    /// get_prices returns prices for multiple mint and timestamp combinations
-    async fn get_prices(&self, queries: Vec<(&str, i32)>) -> Result<Vec<TokenPrice>>;
+    /// get_prices returns the price of every mint and timestamp combination in the order of
+    /// the queries, a failed query only fails its own item
+    async fn get_prices(&self, queries: Vec<(&str, i32)>) -> Vec<Result<TokenPrice>>;
 
     /// insert_token inserts a token into the database
     async fn insert_token(&self, token: &Token) -> Result<()>;
@@ -115,17 +341,343 @@ pub trait DatabaseTrait {
     /// has_token returns true if a token exists in the database
     async fn has_token(&self, mint: &str) -> Result<bool>;
 
-    /// search_tokens returns a list of tokens that match a given query
-    async fn search_tokens(&self, query: &str) -> Result<Vec<TokenSearch>>;
+    /// get_tokens_with_missing_metadata returns up to `limit` mints after `after`, in mint
+    /// order, whose latest token has an empty name or symbol or a zero supply, i.e. whose
+    /// metadata failed to resolve
+    async fn get_tokens_with_missing_metadata(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>>;
+
+    /// search_tokens returns the tokens matching a given query, best match first
+    async fn search_tokens(&self, query: &str) -> Result<Vec<TokenSearchResult>>;
+
+    /// set_token_verified marks a token as verified, boosting it in search results
+    async fn set_token_verified(&self, mint: &str, verified: bool) -> Result<()>;
+
+    /// set_token_launchpad records the launchpad of a token, unless one is already recorded
+    async fn set_token_launchpad(&self, mint: &str, launchpad: &str) -> Result<()>;
 
-    /// aggregates swap events into candlesticks table
+    /// mark_token_graduated records the pool a token migrated to from its bonding curve,
+    /// unless its graduation is already recorded
+    async fn mark_token_graduated(&self, mint: &str, pool: &str, timestamp: u64) -> Result<()>;
+
+    /// set_token_launch_info records the dex and the pool a token was launched in, unless
+    /// its launch is already recorded
+    async fn set_token_launch_info(
+        &self,
+        mint: &str,
+        dex: Dexes,
+        pool: &str,
+        timestamp: u64,
+    ) -> Result<()>;
+
+    /// get_active_tokens returns up to `limit` mints swapped since `since`, most swapped first
+    async fn get_active_tokens(&self, since: u64, limit: usize) -> Result<Vec<String>>;
+
+    /// get_active_pairs returns up to `limit` pairs swapped in `[start, end)`, in ascending order
+    async fn get_active_pairs(&self, start: u64, end: u64, limit: usize) -> Result<Vec<String>>;
+
+    /// aggregates swap events into candlesticks table, per pair and per token with the
+    /// `TOKEN_CANDLESTICK_PAIR` pair, returns the number of rows written, None when the
+    /// backend doesn't report it
     async fn aggregate_into_candlesticks(
         &self,
         start_time: i64,
         end_time: i64,
         interval: CandlestickInterval,
-    ) -> Result<()>;
+    ) -> Result<Option<u64>>;
+
+    /// aggregates `source_interval` candlesticks into `interval` candlesticks, which it divides,
+    /// returns the number of rows written, None when the backend doesn't report it
+    async fn aggregate_candlesticks_from_candlesticks(
+        &self,
+        source_interval: CandlestickInterval,
+        interval: CandlestickInterval,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Option<u64>>;
+
+    /// aggregate_daily_token_rollups rolls the swap events of the UTC day `date` up per token,
+    /// replacing the rollups of a day aggregated again, returns the number of rollups written,
+    /// None when the backend doesn't report it
+    async fn aggregate_daily_token_rollups(&self, date: NaiveDate) -> Result<Option<u64>>;
+
+    /// get_token_history returns the daily rollups of a token from `from_date` to `to_date`,
+    /// both included, in ascending order
+    async fn get_token_history(
+        &self,
+        mint: &str,
+        from_date: NaiveDate,
+        to_date: NaiveDate,
+    ) -> Result<Vec<TokenDailyRollup>>;
 
     /// remove_swap_events removes swap events from the database
     async fn remove_swap_events(&self, partition: i64) -> Result<()>;
+
+    /// estimate_prunable_rows returns the number of swap events that
+    /// prune_inactive_token_events would delete, without deleting anything
+    async fn estimate_prunable_rows(&self, inactive_days: u32, older_than_days: u32)
+        -> Result<u64>;
+
+    /// prune_inactive_token_events deletes swap events older than `older_than_days` for tokens
+    /// with no swaps in the last `inactive_days`, returns the number of deleted swap events
+    async fn prune_inactive_token_events(
+        &self,
+        inactive_days: u32,
+        older_than_days: u32,
+    ) -> Result<u64>;
+
+    /// upsert_wallet_label records the label of a wallet, replacing its previous label
+    async fn upsert_wallet_label(&self, label: &WalletLabel) -> Result<()>;
+
+    /// get_wallet_labels returns the labels of the labeled addresses among `addresses`
+    async fn get_wallet_labels(&self, addresses: &[&str]) -> Result<Vec<WalletLabel>>;
+
+    /// refresh_token_window_stats precomputes the top tokens stats of the rolling windows as
+    /// of `now`, get_top_tokens reads them for the matching windows
+    async fn refresh_token_window_stats(&self, now: u64) -> Result<()>;
+}
+
+/// get_prices_retrying fetches the prices concurrently with `get_price`, in the order of the
+/// queries, a failed query is retried once before its error is returned
+pub async fn get_prices_retrying<D>(db: &D, queries: Vec<(&str, i32)>) -> Vec<Result<TokenPrice>>
+where
+    D: DatabaseTrait + Sync + ?Sized,
+{
+    let tasks = queries.into_iter().map(|(mint, timestamp)| async move {
+        match db.get_price(mint, timestamp).await {
+            Ok(price) => Ok(price),
+            Err(e) => {
+                warn!(error = ?e, mint, timestamp, "Failed to get price, retrying");
+                db.get_price(mint, timestamp).await
+            }
+        }
+    });
+    future::join_all(tasks).await
+}
+
+/// day_bounds returns the unix timestamps of the start of the UTC day and of the next one
+pub(crate) fn day_bounds(date: NaiveDate) -> (u64, u64) {
+    let start = date.and_time(NaiveTime::MIN).and_utc().timestamp() as u64;
+    (start, start + 86_400)
+}
+
+/// The target duration of a Solana slot
+pub const SLOT_DURATION_MS: u64 = 400;
+
+/// slot_time_window returns the time range `slot` most likely falls in, estimated from a
+/// later slot and its timestamp and widened by a day plus a fifth of the distance to absorb
+/// the drift of the slot times, None when the anchor isn't later than the slot
+pub(crate) fn slot_time_window(slot: u64, anchor_slot: u64, anchor_ts: u64) -> Option<(u64, u64)> {
+    if anchor_slot == 0 || slot > anchor_slot {
+        return None;
+    }
+    let elapsed = (anchor_slot - slot) * SLOT_DURATION_MS / 1000;
+    let estimate = anchor_ts.saturating_sub(elapsed);
+    let margin = 86_400 + elapsed / 5;
+    Some((estimate.saturating_sub(margin), estimate + margin))
+}
+
+/// paginate_trades turns a page fetcher into a stream of trades
+///
+/// `fetch_page` is called with the cursor of the last yielded trade and the page size,
+/// until a page shorter than the page size is returned or `limit` trades are yielded.
+pub fn paginate_trades<'a, F, Fut>(
+    page_size: usize,
+    limit: Option<usize>,
+    fetch_page: F,
+) -> BoxStream<'a, Result<Trade>>
+where
+    F: Fn(Option<TradeCursor>, usize) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<Vec<Trade>>> + Send + 'a,
+{
+    Box::pin(async_stream::try_stream! {
+        let mut cursor: Option<TradeCursor> = None;
+        let mut remaining = limit.unwrap_or(usize::MAX);
+        while remaining > 0 {
+            let size = page_size.min(remaining);
+            let page = fetch_page(cursor.take(), size).await?;
+            let is_last_page = page.len() < size;
+            remaining -= page.len();
+            cursor = page.last().map(Trade::cursor);
+            for trade in page {
+                yield trade;
+            }
+            if is_last_page {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_database_backend_from_str() {
+        assert_eq!(DatabaseBackend::from_str("clickhouse").unwrap(), DatabaseBackend::Clickhouse);
+        assert_eq!(DatabaseBackend::from_str("postgres").unwrap(), DatabaseBackend::Postgres);
+        assert!(DatabaseBackend::from_str("mysql").is_err());
+        assert_eq!(DatabaseBackend::default().to_string(), "clickhouse");
+    }
+
+    #[test]
+    fn test_database_config_read_env() {
+        fn read(vars: &[(&str, &str)]) -> Result<DatabaseConfig, ConfigError> {
+            let mut env = EnvReader::from_vars(vars);
+            let config = DatabaseConfig::read_env(&mut env);
+            env.finish(config)
+        }
+        let DatabaseConfig::Clickhouse(config) =
+            read(&[("CLICKHOUSE_URL", "http://localhost:8123")]).unwrap()
+        else {
+            panic!("expected the clickhouse backend");
+        };
+        assert_eq!(config.user, "default");
+        assert_eq!(config.database, "default");
+        assert!(config.initialize_schema);
+
+        let error = read(&[("DATABASE_BACKEND", "mysql"), ("CLICKHOUSE_TOKEN_DEDUPE_SECS", "-1")])
+            .unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![
+                "DATABASE_BACKEND must be clickhouse or postgres, got \"mysql\"",
+                "CLICKHOUSE_URL must be set",
+                "CLICKHOUSE_TOKEN_DEDUPE_SECS must be a valid u64, got \"-1\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let delay = Duration::from_millis(100);
+        for attempt in 1..=4 {
+            let expected = delay * 2u32.pow(attempt - 1);
+            let jittered = retry_delay(delay, attempt);
+            assert!(jittered >= expected && jittered <= expected * 3 / 2, "{jittered:?}");
+        }
+    }
+
+    #[test]
+    fn test_slot_time_window() {
+        // 3 days of slots before the anchor
+        let (from, to) = slot_time_window(100_000_000, 100_648_000, 1_700_259_200).unwrap();
+        let estimate = 1_700_259_200 - 259_200;
+        assert!(from <= estimate - 86_400 && to >= estimate + 86_400);
+        assert!(to < 1_700_259_200 + 86_400);
+        assert_eq!(slot_time_window(1, 0, 0), None);
+        assert_eq!(slot_time_window(200, 100, 1_700_000_000), None);
+    }
+
+    fn make_trade(timestamp: u64) -> Trade {
+        let signature = format!("signature-{timestamp}");
+        crate::test_utils::make_swap_event("token", "pair", &signature, timestamp, 1.0).into()
+    }
+
+    /// a mock database holding `total` trades, recording the requested cursors
+    fn mock_pages(
+        total: u64,
+        cursors: Arc<Mutex<Vec<Option<TradeCursor>>>>,
+    ) -> impl Fn(Option<TradeCursor>, usize) -> futures::future::Ready<Result<Vec<Trade>>> {
+        mock_trade_pages((0..total).map(make_trade).collect(), cursors)
+    }
+
+    /// a mock database holding `trades`, paged by the full keyset cursor like the backends
+    fn mock_trade_pages(
+        mut trades: Vec<Trade>,
+        cursors: Arc<Mutex<Vec<Option<TradeCursor>>>>,
+    ) -> impl Fn(Option<TradeCursor>, usize) -> futures::future::Ready<Result<Vec<Trade>>> {
+        trades.sort_by_key(Trade::cursor);
+        move |cursor, size| {
+            cursors.lock().unwrap().push(cursor.clone());
+            let page = trades
+                .iter()
+                .filter(|trade| match &cursor {
+                    Some(cursor) => &trade.cursor() > cursor,
+                    None => true,
+                })
+                .take(size)
+                .cloned()
+                .collect();
+            futures::future::ready(Ok(page))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paginate_trades() {
+        let cursors = Arc::new(Mutex::new(vec![]));
+        let trades: Vec<Trade> = paginate_trades(10, None, mock_pages(25, cursors.clone()))
+            .map(|t| t.unwrap())
+            .collect()
+            .await;
+        let timestamps: Vec<u64> = trades.iter().map(|t| t.timestamp).collect();
+        assert_eq!(timestamps, (0..25).collect::<Vec<_>>());
+
+        let cursors = cursors.lock().unwrap();
+        assert_eq!(cursors.len(), 3);
+        assert_eq!(cursors[0], None);
+        assert_eq!(cursors[1], Some(make_trade(9).cursor()));
+        assert_eq!(cursors[2], Some(make_trade(19).cursor()));
+    }
+
+    #[tokio::test]
+    async fn test_paginate_trades_with_limit() {
+        let cursors = Arc::new(Mutex::new(vec![]));
+        let trades: Vec<Trade> = paginate_trades(10, Some(15), mock_pages(100, cursors.clone()))
+            .map(|t| t.unwrap())
+            .collect()
+            .await;
+        assert_eq!(trades.len(), 15);
+        assert_eq!(trades.last().unwrap().timestamp, 14);
+        assert_eq!(cursors.lock().unwrap().len(), 2);
+
+        // an exact multiple of the page size ends with an empty page
+        let cursors = Arc::new(Mutex::new(vec![]));
+        let trades: Vec<Trade> = paginate_trades(10, None, mock_pages(20, cursors.clone()))
+            .map(|t| t.unwrap())
+            .collect()
+            .await;
+        assert_eq!(trades.len(), 20);
+        assert_eq!(cursors.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_paginate_trades_shared_timestamps() {
+        // five trades in the same second span the page boundaries
+        let trades: Vec<Trade> = (0..5)
+            .map(|i| {
+                let signature = format!("signature-{i}");
+                crate::test_utils::make_swap_event("token", "pair", &signature, 100, 1.0).into()
+            })
+            .chain([make_trade(101)])
+            .collect();
+        let cursors = Arc::new(Mutex::new(vec![]));
+        let paged: Vec<Trade> = paginate_trades(2, None, mock_trade_pages(trades, cursors.clone()))
+            .map(|t| t.unwrap())
+            .collect()
+            .await;
+
+        let signatures: Vec<&str> = paged.iter().map(|t| t.signature.as_str()).collect();
+        assert_eq!(
+            signatures,
+            [
+                "signature-0",
+                "signature-1",
+                "signature-2",
+                "signature-3",
+                "signature-4",
+                "signature-101"
+            ]
+        );
+        assert_eq!(
+            cursors.lock().unwrap()[1],
+            Some((100, "signature-1".to_string(), "pair".to_string()))
+        );
+    }
 }
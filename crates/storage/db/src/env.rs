@@ -0,0 +1,227 @@
+//! Reads the variables of a config, collecting every invalid one so they are reported at once
+use crate::models::candlesticks::OutlierPolicy;
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+/// The capacity of the channel between the datasources and a pipeline when unset
+pub const DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE: usize = 10_000;
+
+/// The problems of the variables of a config
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid configuration: {}", .problems.join("; "))]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+/// Reads the variables of a lookup, the env unless testing, and records their problems
+/// instead of failing on the first one
+pub struct EnvReader<'a> {
+    lookup: Box<dyn Fn(&str) -> Option<String> + 'a>,
+    problems: Vec<String>,
+}
+
+impl Default for EnvReader<'_> {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+impl<'a> EnvReader<'a> {
+    /// new reads the variables of `lookup`
+    pub fn new(lookup: impl Fn(&str) -> Option<String> + 'a) -> Self {
+        Self { lookup: Box::new(lookup), problems: vec![] }
+    }
+
+    /// from_env reads the variables of the process env
+    pub fn from_env() -> Self {
+        Self::new(|name| std::env::var(name).ok())
+    }
+
+    /// from_vars reads the variables of `vars`, e.g. a config in a test
+    pub fn from_vars(vars: &'a [(&'a str, &'a str)]) -> Self {
+        Self::new(|name| vars.iter().find(|(key, _)| *key == name).map(|(_, v)| v.to_string()))
+    }
+
+    /// var returns a variable, None if it is unset or empty
+    pub fn var(&self, name: &str) -> Option<String> {
+        (self.lookup)(name).filter(|v| !v.is_empty())
+    }
+
+    /// required returns a variable, an empty string and a problem if it is unset
+    pub fn required(&mut self, name: &str) -> String {
+        self.var(name).unwrap_or_else(|| {
+            self.error(format!("{name} must be set"));
+            String::new()
+        })
+    }
+
+    /// parse parses a variable, None if it is unset or invalid
+    pub fn parse<T: FromStr>(&mut self, name: &str) -> Option<T> {
+        let value = self.var(name)?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                let kind = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+                self.error(format!("{name} must be a valid {kind}, got {value:?}"));
+                None
+            }
+        }
+    }
+
+    /// parse_required parses a variable, None and a problem if it is unset or invalid
+    pub fn parse_required<T: FromStr>(&mut self, name: &str) -> Option<T> {
+        if self.var(name).is_none() {
+            self.error(format!("{name} must be set"));
+            return None;
+        }
+        self.parse(name)
+    }
+
+    /// parse_or parses a variable, the default if it is unset or invalid
+    pub fn parse_or<T: FromStr>(&mut self, name: &str, default: T) -> T {
+        self.parse(name).unwrap_or(default)
+    }
+
+    /// flag_or parses `true`/`false` or `1`/`0`, the default if it is unset or invalid
+    pub fn flag_or(&mut self, name: &str, default: bool) -> bool {
+        match self.var(name).map(|v| v.to_ascii_lowercase()).as_deref() {
+            None => default,
+            Some("true" | "1") => true,
+            Some("false" | "0") => false,
+            Some(other) => {
+                self.error(format!("{name} must be true or false, got {other:?}"));
+                default
+            }
+        }
+    }
+
+    /// secs parses a positive number of seconds, None if it is unset or invalid
+    pub fn secs(&mut self, name: &str) -> Option<Duration> {
+        self.positive(name, "seconds").map(Duration::from_secs)
+    }
+
+    /// millis parses a positive number of milliseconds, None if it is unset or invalid
+    pub fn millis(&mut self, name: &str) -> Option<Duration> {
+        self.positive(name, "milliseconds").map(Duration::from_millis)
+    }
+
+    fn positive(&mut self, name: &str, unit: &str) -> Option<u64> {
+        let value = self.var(name)?;
+        match value.parse::<u64>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                self.error(format!("{name} must be a positive number of {unit}, got {value:?}"));
+                None
+            }
+        }
+    }
+
+    /// check records `problem` unless `ok`
+    pub fn check(&mut self, ok: bool, problem: impl Into<String>) {
+        if !ok {
+            self.error(problem);
+        }
+    }
+
+    /// error records a problem
+    pub fn error(&mut self, problem: impl Into<String>) {
+        self.problems.push(problem.into());
+    }
+
+    /// pipeline_channel_buffer_size parses `PIPELINE_CHANNEL_BUFFER_SIZE`, the capacity of the
+    /// channel between the datasources and a pipeline
+    pub fn pipeline_channel_buffer_size(&mut self) -> usize {
+        let size =
+            self.parse_or("PIPELINE_CHANNEL_BUFFER_SIZE", DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE);
+        self.check(size > 0, "PIPELINE_CHANNEL_BUFFER_SIZE must not be 0");
+        size
+    }
+
+    /// outlier_policy reads `CANDLESTICK_CLAMP`, `CANDLESTICK_CLAMP_QUANTILE`,
+    /// `CANDLESTICK_CLAMP_BAND_MULTIPLIER` and `CANDLESTICK_CLAMP_MIN_TRADES`
+    pub fn outlier_policy(&mut self) -> OutlierPolicy {
+        let default = OutlierPolicy::default();
+        let quantile = self.parse_or("CANDLESTICK_CLAMP_QUANTILE", default.quantile);
+        self.check(
+            (0.5..1.0).contains(&quantile),
+            format!("CANDLESTICK_CLAMP_QUANTILE must be between 0.5 and 1, got {quantile}"),
+        );
+        let band_multiplier =
+            self.parse_or("CANDLESTICK_CLAMP_BAND_MULTIPLIER", default.band_multiplier);
+        self.check(
+            band_multiplier >= 1.0,
+            format!("CANDLESTICK_CLAMP_BAND_MULTIPLIER must be at least 1, got {band_multiplier}"),
+        );
+        OutlierPolicy {
+            enabled: self.flag_or("CANDLESTICK_CLAMP", default.enabled),
+            quantile,
+            band_multiplier,
+            min_trades: self.parse_or("CANDLESTICK_CLAMP_MIN_TRADES", default.min_trades),
+        }
+    }
+
+    /// merge returns the value of a config read on its own, recording its error
+    pub fn merge<T, E: Display>(&mut self, result: Result<T, E>) -> Option<T> {
+        result.map_err(|e| self.error(e.to_string())).ok()
+    }
+
+    /// finish returns `config` if no problem was recorded, every problem otherwise
+    pub fn finish<T>(self, config: T) -> Result<T, ConfigError> {
+        if self.problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { problems: self.problems })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_reader_collects_every_problem() {
+        let mut env = EnvReader::from_vars(&[
+            ("PORT", "80a"),
+            ("ENABLED", "yes"),
+            ("TIMEOUT_SECS", "0"),
+            ("LIMIT", "5"),
+            ("EMPTY", ""),
+            ("PIPELINE_CHANNEL_BUFFER_SIZE", "0"),
+        ]);
+        assert_eq!(env.parse_or::<u16>("PORT", 8080), 8080);
+        assert!(!env.flag_or("ENABLED", false));
+        assert_eq!(env.secs("TIMEOUT_SECS"), None);
+        assert_eq!(env.parse_or::<usize>("LIMIT", 1), 5);
+        assert_eq!(env.var("EMPTY"), None);
+        assert_eq!(env.required("URL"), "");
+        assert_eq!(env.parse_required::<u64>("SLOT"), None);
+        assert_eq!(env.merge::<u8, _>(Err("CUSTOM is broken")), None);
+        assert_eq!(env.pipeline_channel_buffer_size(), 0);
+
+        let error = env.finish(()).unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![
+                "PORT must be a valid u16, got \"80a\"",
+                "ENABLED must be true or false, got \"yes\"",
+                "TIMEOUT_SECS must be a positive number of seconds, got \"0\"",
+                "URL must be set",
+                "SLOT must be set",
+                "CUSTOM is broken",
+                "PIPELINE_CHANNEL_BUFFER_SIZE must not be 0",
+            ]
+        );
+        assert!(error.to_string().starts_with("Invalid configuration: PORT must be"));
+    }
+
+    #[test]
+    fn test_env_reader_defaults() {
+        let mut env = EnvReader::new(|_| None);
+        assert_eq!(env.parse_or("LIMIT", 7u32), 7);
+        assert!(env.flag_or("ENABLED", true));
+        assert_eq!(env.millis("TIMEOUT_MS"), None);
+        assert_eq!(env.pipeline_channel_buffer_size(), DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE);
+        assert_eq!(env.outlier_policy(), OutlierPolicy::default());
+        assert_eq!(env.finish(42).unwrap(), 42);
+    }
+}
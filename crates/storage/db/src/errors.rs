@@ -1,10 +1,379 @@
+use bb8_redis::{bb8::RunError, redis::RedisError};
+
 // https://docs.rs/tracing-error/latest/tracing_error/
-#[derive(Debug, thiserror::Error)]
+/// A storage failure classified by how the caller should react to it
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
 #[non_exhaustive]
 pub enum StorageError {
-    #[error("Redis error: {0}")]
-    Redis(#[from] redis::RedisError),
+    /// The backend is unreachable or dropped the connection
+    #[error("Storage connection error: {0}")]
+    Connection(String),
+
+    #[error("Storage timed out: {0}")]
+    Timeout(String),
+
+    /// The tables don't match the rows, e.g. a missing column, retrying won't help
+    #[error("Storage schema mismatch: {detail}")]
+    SchemaMismatch { detail: String },
+
+    /// The backend rejects the query until it catches up, e.g. too many parts
+    #[error("Storage rate limited: {0}")]
+    RateLimited(String),
+
+    /// The backend doesn't implement the operation, e.g. dropping a partition of Postgres
+    #[error("Not supported by the storage backend: {0}")]
+    NotSupported(String),
+
+    /// Another client changed the same object concurrently, e.g. two services creating a
+    /// replicated table at once, retrying once it is done succeeds
+    #[error("Storage conflict: {0}")]
+    Conflict(String),
+
+    /// The storage is used before its `initialize`, e.g. a write without an inserter
+    #[error("Storage not initialized: {0}")]
+    NotInitialized(String),
+
+    #[error("Storage error: {0}")]
+    Other(String),
+}
+
+/// ClickHouse error codes of a table not matching the query or the inserted rows
+const CLICKHOUSE_SCHEMA_CODES: &[u32] = &[
+    8,   // THERE_IS_NO_COLUMN
+    10,  // NOT_FOUND_COLUMN_IN_BLOCK
+    16,  // NO_SUCH_COLUMN_IN_TABLE
+    33,  // CANNOT_READ_ALL_DATA
+    44,  // ILLEGAL_COLUMN
+    47,  // UNKNOWN_IDENTIFIER
+    53,  // TYPE_MISMATCH
+    60,  // UNKNOWN_TABLE
+    70,  // CANNOT_CONVERT_TYPE
+    81,  // UNKNOWN_DATABASE
+    117, // INCORRECT_DATA
+];
+/// ClickHouse error codes of queries that ran out of time
+const CLICKHOUSE_TIMEOUT_CODES: &[u32] = &[
+    159, // TIMEOUT_EXCEEDED
+    209, // SOCKET_TIMEOUT
+];
+/// ClickHouse error codes of an unreachable server or cluster node
+const CLICKHOUSE_CONNECTION_CODES: &[u32] = &[
+    210, // NETWORK_ERROR
+    279, // ALL_CONNECTION_TRIES_FAILED
+];
+/// ClickHouse error codes of concurrent DDL on the same table, e.g. services starting together
+const CLICKHOUSE_CONFLICT_CODES: &[u32] = &[
+    57,  // TABLE_ALREADY_EXISTS
+    242, // TABLE_IS_READ_ONLY
+    253, // REPLICA_ALREADY_EXISTS
+    341, // UNFINISHED
+    473, // DEADLOCK_AVOIDED
+    517, // CANNOT_ASSIGN_ALTER
+    999, // KEEPER_EXCEPTION
+];
+/// ClickHouse error codes of a server shedding load
+const CLICKHOUSE_RATE_LIMITED_CODES: &[u32] = &[
+    202, // TOO_MANY_SIMULTANEOUS_QUERIES
+    203, // NO_FREE_CONNECTION
+    252, // TOO_MANY_PARTS
+];
+
+impl StorageError {
+    /// is_transient returns true if the operation may succeed when retried
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            StorageError::Connection(_) | StorageError::Timeout(_) | StorageError::RateLimited(_)
+        )
+    }
+
+    /// is_initialize_retryable returns true if initializing may succeed when retried, the
+    /// missing tables included as the service running the DDL may not be done yet
+    pub fn is_initialize_retryable(&self) -> bool {
+        self.is_transient()
+            || matches!(self, StorageError::Conflict(_) | StorageError::SchemaMismatch { .. })
+    }
+
+    /// is_schema_mismatch returns true if the tables need a migration before retrying
+    pub fn is_schema_mismatch(&self) -> bool {
+        matches!(self, StorageError::SchemaMismatch { .. })
+    }
+
+    /// from_clickhouse_response classifies the exception returned by the ClickHouse server,
+    /// e.g. `Code: 16. DB::Exception: No such column foo in table swap_events`
+    pub fn from_clickhouse_response(message: &str) -> Self {
+        let detail = message.trim().to_string();
+        let code = message
+            .split_once("Code: ")
+            .map(|(_, rest)| rest.chars().take_while(char::is_ascii_digit).collect::<String>())
+            .and_then(|code| code.parse::<u32>().ok());
+        match code {
+            Some(code) if CLICKHOUSE_SCHEMA_CODES.contains(&code) => {
+                StorageError::SchemaMismatch { detail }
+            }
+            Some(code) if CLICKHOUSE_TIMEOUT_CODES.contains(&code) => StorageError::Timeout(detail),
+            Some(code) if CLICKHOUSE_CONNECTION_CODES.contains(&code) => {
+                StorageError::Connection(detail)
+            }
+            Some(code) if CLICKHOUSE_RATE_LIMITED_CODES.contains(&code) => {
+                StorageError::RateLimited(detail)
+            }
+            Some(code) if CLICKHOUSE_CONFLICT_CODES.contains(&code) => {
+                StorageError::Conflict(detail)
+            }
+            Some(_) => StorageError::Other(detail),
+            // proxies in front of the server don't always forward the code
+            None => {
+                let lowercase = detail.to_lowercase();
+                if ["unknown column", "no such column", "missing columns", "unknown table"]
+                    .iter()
+                    .any(|pattern| lowercase.contains(pattern))
+                {
+                    StorageError::SchemaMismatch { detail }
+                } else {
+                    StorageError::Other(detail)
+                }
+            }
+        }
+    }
+}
+
+impl From<&clickhouse::error::Error> for StorageError {
+    fn from(error: &clickhouse::error::Error) -> Self {
+        use clickhouse::error::Error;
+        match error {
+            Error::Network(_) => StorageError::Connection(error.to_string()),
+            Error::TimedOut => StorageError::Timeout(error.to_string()),
+            Error::BadResponse(message) => StorageError::from_clickhouse_response(message),
+            // the rows can't be decoded, the struct and the table columns differ
+            Error::NotEnoughData | Error::InvalidUtf8Encoding(_) | Error::InvalidTagEncoding(_) => {
+                StorageError::SchemaMismatch { detail: error.to_string() }
+            }
+            _ => StorageError::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<clickhouse::error::Error> for StorageError {
+    fn from(error: clickhouse::error::Error) -> Self {
+        StorageError::from(&error)
+    }
+}
+
+/// Postgres error codes of a table not matching the query or the inserted rows
+#[cfg(feature = "postgres")]
+const POSTGRES_SCHEMA_CODES: &[&str] = &[
+    "42P01", // undefined_table
+    "42703", // undefined_column
+    "42804", // datatype_mismatch
+];
+/// Postgres error codes of a server shedding load
+#[cfg(feature = "postgres")]
+const POSTGRES_RATE_LIMITED_CODES: &[&str] = &[
+    "53300", // too_many_connections
+    "57P03", // cannot_connect_now
+];
+
+#[cfg(feature = "postgres")]
+impl From<&sqlx::Error> for StorageError {
+    fn from(error: &sqlx::Error) -> Self {
+        use sqlx::Error;
+        match error {
+            Error::Io(_) | Error::Tls(_) | Error::PoolClosed => {
+                StorageError::Connection(error.to_string())
+            }
+            Error::PoolTimedOut => StorageError::Timeout(error.to_string()),
+            Error::ColumnNotFound(_) | Error::ColumnDecode { .. } => {
+                StorageError::SchemaMismatch { detail: error.to_string() }
+            }
+            Error::Database(e) => match e.code() {
+                Some(code) if POSTGRES_SCHEMA_CODES.contains(&code.as_ref()) => {
+                    StorageError::SchemaMismatch { detail: error.to_string() }
+                }
+                Some(code) if POSTGRES_RATE_LIMITED_CODES.contains(&code.as_ref()) => {
+                    StorageError::RateLimited(error.to_string())
+                }
+                _ => StorageError::Other(error.to_string()),
+            },
+            _ => StorageError::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<&RedisError> for StorageError {
+    fn from(error: &RedisError) -> Self {
+        if error.is_timeout() {
+            StorageError::Timeout(error.to_string())
+        } else if error.is_io_error()
+            || error.is_connection_refusal()
+            || error.is_connection_dropped()
+        {
+            StorageError::Connection(error.to_string())
+        } else {
+            StorageError::Other(error.to_string())
+        }
+    }
+}
+
+impl From<RedisError> for StorageError {
+    fn from(error: RedisError) -> Self {
+        StorageError::from(&error)
+    }
+}
+
+/// classified wraps a ClickHouse error with its StorageError, keeping the original as the source
+pub(crate) fn classified(error: clickhouse::error::Error) -> anyhow::Error {
+    let storage_error = StorageError::from(&error);
+    anyhow::Error::new(error).context(storage_error)
+}
+
+/// http_classified wraps an error of a request to the ClickHouse HTTP interface with its
+/// StorageError, keeping the original as the source
+pub(crate) fn http_classified(error: reqwest::Error) -> anyhow::Error {
+    let storage_error = if error.is_timeout() {
+        StorageError::Timeout(error.to_string())
+    } else if error.is_connect() || error.is_request() {
+        StorageError::Connection(error.to_string())
+    } else {
+        StorageError::Other(error.to_string())
+    };
+    anyhow::Error::new(error).context(storage_error)
+}
+
+/// pg_classified wraps a Postgres error with its StorageError, keeping the original as the source
+#[cfg(feature = "postgres")]
+pub(crate) fn pg_classified(error: sqlx::Error) -> anyhow::Error {
+    let storage_error = StorageError::from(&error);
+    anyhow::Error::new(error).context(storage_error)
+}
+
+/// not_supported returns the error of an operation the storage backend doesn't implement
+pub(crate) fn not_supported(operation: &str) -> anyhow::Error {
+    anyhow::Error::new(StorageError::NotSupported(operation.to_string()))
+}
+
+/// not_initialized returns the error of a use of the storage before its `initialize`
+pub(crate) fn not_initialized(what: &str) -> anyhow::Error {
+    anyhow::Error::new(StorageError::NotInitialized(format!("{what}, call initialize first")))
+}
+
+/// Returns the classification of a storage failure, looking for a StorageError in the error
+/// or classifying the first ClickHouse, Redis or Postgres error of its chain, None for other errors
+pub fn storage_error(error: &anyhow::Error) -> Option<StorageError> {
+    if let Some(e) = error.downcast_ref::<StorageError>() {
+        return Some(e.clone());
+    }
+    error.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<StorageError>() {
+            return Some(e.clone());
+        }
+        if let Some(e) = cause.downcast_ref::<clickhouse::error::Error>() {
+            return Some(e.into());
+        }
+        if let Some(e) = cause.downcast_ref::<RedisError>() {
+            return Some(e.into());
+        }
+        #[cfg(feature = "postgres")]
+        if let Some(e) = cause.downcast_ref::<sqlx::Error>() {
+            return Some(e.into());
+        }
+        match cause.downcast_ref::<RunError<RedisError>>() {
+            Some(RunError::User(e)) => Some(e.into()),
+            Some(RunError::TimedOut) => {
+                Some(StorageError::Timeout("Redis pool timed out".to_string()))
+            }
+            None => None,
+        }
+    })
+}
+
+/// Returns true if the error was caused by an unreachable, timed out or overloaded storage
+/// backend, as opposed to an invalid query or unexpected data
+pub fn is_unavailable(error: &anyhow::Error) -> bool {
+    storage_error(error).is_some_and(|e| e.is_transient())
+}
+
+/// Returns true if the storage backend doesn't implement the operation
+pub fn is_not_supported(error: &anyhow::Error) -> bool {
+    matches!(storage_error(error), Some(StorageError::NotSupported(_)))
+}
+
+/// Returns true if the error was caused by tables not matching the queries or the rows
+pub fn is_schema_mismatch(error: &anyhow::Error) -> bool {
+    storage_error(error).is_some_and(|e| e.is_schema_mismatch())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_is_unavailable() {
+        let error = anyhow::Error::new(clickhouse::error::Error::TimedOut);
+        assert!(is_unavailable(&error));
+        assert!(is_unavailable(&error.context("Failed to get token")));
+
+        let error: Result<(), _> = Err(clickhouse::error::Error::NotEnoughData);
+        assert!(!is_unavailable(&error.context("Failed to get token").unwrap_err()));
+        assert!(!is_unavailable(&anyhow::anyhow!("Token not found")));
+    }
+
+    #[test]
+    fn test_classify_clickhouse_response() {
+        let cases = [
+            (
+                "Code: 16. DB::Exception: No such column is_wash in table default.swap_events. (NO_SUCH_COLUMN_IN_TABLE)",
+                "schema",
+            ),
+            ("Code: 47. DB::Exception: Unknown expression identifier `verified`", "schema"),
+            ("Code: 60. DB::Exception: Table default.tokens does not exist. (UNKNOWN_TABLE)", "schema"),
+            ("Code: 159. DB::Exception: Timeout exceeded: elapsed 30 seconds", "timeout"),
+            ("Code: 210. DB::NetException: Connection refused (NETWORK_ERROR)", "connection"),
+            ("Code: 252. DB::Exception: Too many parts (300). (TOO_MANY_PARTS)", "rate_limited"),
+            ("Code: 202. DB::Exception: Too many simultaneous queries", "rate_limited"),
+            (
+                "Code: 253. DB::Exception: Replica /clickhouse/tables/swap_events/replicas/r1 already exists. (REPLICA_ALREADY_EXISTS)",
+                "conflict",
+            ),
+            ("Code: 62. DB::Exception: Syntax error: failed at position 1", "other"),
+            ("Unknown column 'verified' in the insert", "schema"),
+            ("upstream connect error", "other"),
+        ];
+        for (message, expected) in cases {
+            let class = match StorageError::from_clickhouse_response(message) {
+                StorageError::SchemaMismatch { .. } => "schema",
+                StorageError::Timeout(_) => "timeout",
+                StorageError::Connection(_) => "connection",
+                StorageError::RateLimited(_) => "rate_limited",
+                StorageError::Conflict(_) => "conflict",
+                StorageError::NotSupported(_)
+                | StorageError::NotInitialized(_)
+                | StorageError::Other(_) => "other",
+            };
+            assert_eq!(class, expected, "{message}");
+        }
+    }
+
+    #[test]
+    fn test_storage_error_in_chain() {
+        let error = classified(clickhouse::error::Error::BadResponse(
+            "Code: 16. DB::Exception: No such column is_wash in table swap_events".to_string(),
+        ));
+        assert!(is_schema_mismatch(&error));
+        let error = error.context("Failed to insert swap events");
+        assert!(is_schema_mismatch(&error));
+        assert!(!is_unavailable(&error));
+
+        let error = classified(clickhouse::error::Error::TimedOut).context("Failed to get token");
+        assert!(matches!(storage_error(&error), Some(StorageError::Timeout(_))));
+        assert!(is_unavailable(&error));
+
+        // unconverted ClickHouse errors are classified from the chain
+        let error: Result<(), _> = Err(clickhouse::error::Error::NotEnoughData);
+        let error = error.context("Failed to get pairs").unwrap_err();
+        assert!(is_schema_mismatch(&error));
 
-    #[error("Clickhouse error: {0}")]
-    Clickhouse(#[from] clickhouse::error::Error),
+        assert_eq!(storage_error(&anyhow::anyhow!("Token not found")), None);
+    }
 }
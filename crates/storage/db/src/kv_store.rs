@@ -1,37 +1,307 @@
-use crate::models::{swap::Trade, Token};
+use crate::{
+    env::{ConfigError, EnvReader},
+    message_queue::{
+        decode_sequenced_message, recent_trades_key, trade_seq_base, trade_seq_key,
+        RECENT_TRADES_MAX, TRADE_SEQ_TTL_SECS,
+    },
+    models::{
+        pairs::{PairPrice, PoolStatePrice, PrimaryPair},
+        swap::Trade,
+        wallet::WalletLabel,
+        Token, TokenRiskFlags,
+    },
+};
 use anyhow::{Context, Result};
-use bb8_redis::{bb8, redis::AsyncCommands, RedisConnectionManager};
+use bb8_redis::{
+    bb8,
+    redis::{AsyncCommands, Script},
+    RedisConnectionManager,
+};
 use serde::{de::DeserializeOwned, Serialize};
-use std::env::var;
+#[cfg(any(test, feature = "memory-kv"))]
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+use std::{env::var, time::Duration};
 use tracing::{debug, info};
 
+#[derive(Debug, Clone)]
+enum KvBackend {
+    Redis(bb8::Pool<RedisConnectionManager>),
+    #[cfg(any(test, feature = "memory-kv"))]
+    Memory(Arc<Mutex<MemoryKv>>),
+}
+
+/// An in-process stand-in for Redis, for tests and runs without services
+#[cfg(any(test, feature = "memory-kv"))]
+#[derive(Debug, Default)]
+struct MemoryKv {
+    values: HashMap<String, (String, Instant)>,
+    price_history: HashMap<String, BTreeMap<u64, f64>>,
+    recent_trades: HashMap<String, VecDeque<String>>,
+}
+
+#[cfg(any(test, feature = "memory-kv"))]
+impl MemoryKv {
+    /// lock locks the map, a panic of another holder leaves the values consistent
+    fn lock(memory: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        memory.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&mut self, key: &str) -> Option<String> {
+        match self.values.get(key) {
+            Some((_, expires_at)) if *expires_at <= Instant::now() => {
+                self.values.remove(key);
+                None
+            }
+            Some((value, _)) => Some(value.clone()),
+            None => None,
+        }
+    }
+
+    fn set_ex(&mut self, key: &str, value: String, seconds: u64) -> Option<String> {
+        let previous = self.get(key);
+        let expires_at = Instant::now() + Duration::from_secs(seconds);
+        self.values.insert(key.to_string(), (value, expires_at));
+        previous
+    }
+}
+
+/// The half life of the turnover ranking the pairs of a token
+const PAIR_TURNOVER_HALF_LIFE_SECS: f64 = 60.0 * 60.0;
+
+/// decay_turnover returns the turnover at `from` decayed to `to`
+fn decay_turnover(turnover: f64, from: u64, to: u64) -> f64 {
+    turnover * 0.5_f64.powf(to.saturating_sub(from) as f64 / PAIR_TURNOVER_HALF_LIFE_SECS)
+}
+
+/// How many times the turnover of the primary pair another pair needs to replace it,
+/// so that two pools of similar depth don't flap
+const PRIMARY_PAIR_HYSTERESIS: f64 = 1.5;
+/// The primary pair outlives the prices, a token trading again keeps its pool
+const PRIMARY_PAIR_TTL_SECS: u64 = 60 * 60 * 24 * 30;
+/// The labels are imported by hand, a label an hour old is fresh enough
+const WALLET_LABEL_TTL_SECS: u64 = 60 * 60;
+/// The risk flags only change when a mint authority updates an extension, which the
+/// Token-2022 stream rewrites, so they are kept for a week by default
+pub const DEFAULT_RISK_FLAGS_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+/// The mint the next missing metadata refresh starts after
+const MISSING_METADATA_CURSOR_KEY: &str = "solana:metadata:refresh_cursor";
+
+/// next_primary_pair returns the primary pair after a trade bringing `pair` to `turnover`,
+/// None when the stored one stays as is
+fn next_primary_pair(
+    current: Option<&PrimaryPair>,
+    pair: &str,
+    turnover: f64,
+    timestamp: u64,
+) -> Option<PrimaryPair> {
+    let next = PrimaryPair { pair: pair.to_string(), turnover, timestamp, pinned: false };
+    let Some(current) = current else {
+        return Some(next);
+    };
+    if current.pinned {
+        return None;
+    }
+    if current.pair == pair {
+        return Some(next);
+    }
+    let current_turnover = decay_turnover(current.turnover, current.timestamp, timestamp);
+    (turnover > current_turnover * PRIMARY_PAIR_HYSTERESIS).then_some(next)
+}
+
+/// The script setting a key only while it holds the value read before, an empty expected
+/// value standing for a missing key
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+if (redis.call('GET', KEYS[1]) or '') ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+return 1
+"#;
+
+static COMPARE_AND_SET: LazyLock<Script> = LazyLock::new(|| Script::new(COMPARE_AND_SET_SCRIPT));
+
+/// How many times an update reads the key again after a concurrent write
+const UPDATE_ATTEMPTS: usize = 32;
+
+/// fresher_price returns the swap price, or the pool state price when it is strictly newer
+fn fresher_price(swap: Option<(f64, u64)>, pool_state: Option<(f64, u64)>) -> Option<(f64, u64)> {
+    match (swap, pool_state) {
+        (Some(swap), Some(pool_state)) if pool_state.1 > swap.1 => Some(pool_state),
+        (None, pool_state) => pool_state,
+        (swap, _) => swap,
+    }
+}
+
+/// Which trades update the latest price of a mint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MintPriceMode {
+    /// Every trade, whatever its pair
+    #[default]
+    Latest,
+    /// The trades of the pair with the highest decayed turnover, so that a dust pool
+    /// doesn't override the price of the canonical one
+    TopPair,
+}
+
+impl MintPriceMode {
+    /// from_env reads `MINT_PRICE_MODE`, `latest` or `top_pair`, defaults to `latest`
+    pub fn from_env() -> Self {
+        match var("MINT_PRICE_MODE").as_deref() {
+            Ok("top_pair") => MintPriceMode::TopPair,
+            _ => MintPriceMode::Latest,
+        }
+    }
+}
+
+/// The redis settings of the kv store, the message queue and the subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisConfig {
+    pub url: String,
+    /// Whether the published messages are wrapped in an envelope
+    pub mq_envelope: bool,
+    pub mint_price_mode: MintPriceMode,
+    /// How long the risk flags of a token are cached
+    pub risk_flags_ttl: Duration,
+}
+
+impl RedisConfig {
+    /// read_env reads `REDIS_URL`, `MQ_ENVELOPE`, `MINT_PRICE_MODE` and `RISK_FLAGS_TTL_SECS`
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let url = env.required("REDIS_URL");
+        let mq_envelope = env.flag_or("MQ_ENVELOPE", false);
+        let mint_price_mode = match env.var("MINT_PRICE_MODE").as_deref() {
+            None | Some("latest") => MintPriceMode::Latest,
+            Some("top_pair") => MintPriceMode::TopPair,
+            Some(other) => {
+                env.error(format!("MINT_PRICE_MODE must be latest or top_pair, got {other:?}"));
+                MintPriceMode::Latest
+            }
+        };
+        let risk_flags_ttl = env.secs("RISK_FLAGS_TTL_SECS").unwrap_or(DEFAULT_RISK_FLAGS_TTL);
+        Self { url, mq_envelope, mint_price_mode, risk_flags_ttl }
+    }
+
+    /// read_env_optional reads the variables of `read_env`, None when `REDIS_URL` is unset
+    pub fn read_env_optional(env: &mut EnvReader) -> Option<Self> {
+        env.var("REDIS_URL").is_some().then(|| Self::read_env(env))
+    }
+
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut env = EnvReader::from_env();
+        let config = Self::read_env(&mut env);
+        env.finish(config)
+    }
+}
+
+/// The pair whose trades update the latest price of a mint in `MintPriceMode::TopPair`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TopPair {
+    pair: String,
+    turnover: f64,
+    timestamp: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct KvStore {
-    pool: bb8::Pool<RedisConnectionManager>,
+    backend: KvBackend,
+    mint_price_mode: MintPriceMode,
+    risk_flags_ttl: Duration,
 }
 
 impl KvStore {
-    pub(crate) async fn get_connection(
-        &self,
+    async fn get_connection(
+        pool: &bb8::Pool<RedisConnectionManager>,
     ) -> Result<bb8::PooledConnection<'_, RedisConnectionManager>> {
-        let conn = self.pool.get().await.context(format!(
-            "Failed to get Redis connection: {:#?}",
-            self.pool.state().statistics
-        ))?;
+        let conn = pool
+            .get()
+            .await
+            .context(format!("Failed to get Redis connection: {:#?}", pool.state().statistics))?;
         Ok(conn)
     }
 
     pub async fn new(redis_url: &str) -> Result<Self> {
         let pool = make_kv_pool(redis_url).await?;
         info!("Connected to Redis KV store at {}", redis_url);
-        Ok(Self { pool })
+        Ok(Self {
+            backend: KvBackend::Redis(pool),
+            mint_price_mode: MintPriceMode::default(),
+            risk_flags_ttl: DEFAULT_RISK_FLAGS_TTL,
+        })
     }
 
-    pub async fn get<T: DeserializeOwned + Send>(&self, key: &str) -> Result<Option<T>> {
-        let mut conn = self.get_connection().await?;
+    /// in_memory creates a kv store backed by a process local map instead of Redis
+    #[cfg(any(test, feature = "memory-kv"))]
+    pub fn in_memory() -> Self {
+        Self {
+            backend: KvBackend::Memory(Arc::default()),
+            mint_price_mode: MintPriceMode::default(),
+            risk_flags_ttl: DEFAULT_RISK_FLAGS_TTL,
+        }
+    }
+
+    /// Set which trades update the latest price of a mint.
+    pub fn with_mint_price_mode(mut self, mint_price_mode: MintPriceMode) -> Self {
+        self.mint_price_mode = mint_price_mode;
+        self
+    }
+
+    /// Set how long the risk flags of a token are cached.
+    pub fn with_risk_flags_ttl(mut self, risk_flags_ttl: Duration) -> Self {
+        self.risk_flags_ttl = risk_flags_ttl;
+        self
+    }
+
+    /// ping checks that the Redis server answers, always succeeds in memory
+    pub async fn ping(&self) -> Result<()> {
+        if let KvBackend::Redis(pool) = &self.backend {
+            let mut conn = Self::get_connection(pool).await?;
+            bb8_redis::redis::cmd("PING")
+                .query_async::<String>(&mut *conn)
+                .await
+                .context("Failed to ping Redis")?;
+        }
+        Ok(())
+    }
+
+    async fn get_raw(&self, key: &str) -> Result<Option<String>> {
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let value: Option<String> =
+                    conn.get(key).await.context(format!("Failed to get value for key: {}", key))?;
+                Ok(value)
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => Ok(MemoryKv::lock(memory).get(key)),
+        }
+    }
 
-        let value: Option<String> =
-            conn.get(key).await.context(format!("Failed to get value for key: {}", key))?;
+    /// get_many_raw returns the values of the keys with a single MGET, in the same order
+    async fn get_many_raw(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let values = bb8_redis::redis::cmd("MGET")
+                    .arg(keys)
+                    .query_async(&mut *conn)
+                    .await
+                    .context("Failed to get values")?;
+                Ok(values)
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                let mut memory = MemoryKv::lock(memory);
+                Ok(keys.iter().map(|key| memory.get(key)).collect())
+            }
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned + Send>(&self, key: &str) -> Result<Option<T>> {
+        let value = self.get_raw(key).await?;
 
         value
             .map(|json_str| {
@@ -47,21 +317,109 @@ impl KvStore {
         value: &T,
         seconds: u64,
     ) -> Result<()> {
-        let mut conn = self.get_connection().await?;
-
         let json_str = serde_json::to_string(value)?;
-        let _: () = conn
-            .set_ex(key, json_str, seconds)
-            .await
-            .context(format!("Failed to set key: {}", key))?;
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let _: () = conn
+                    .set_ex(key, json_str, seconds)
+                    .await
+                    .context(format!("Failed to set key: {}", key))?;
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                MemoryKv::lock(memory).set_ex(key, json_str, seconds);
+            }
+        }
         debug!(key, "redis set ok");
         Ok(())
     }
 
+    /// compare_and_set sets the key when it still holds `expected`, None for a missing key,
+    /// returns whether it was set
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: String,
+        seconds: u64,
+    ) -> Result<bool> {
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let set: bool = COMPARE_AND_SET
+                    .key(key)
+                    .arg(expected.unwrap_or_default())
+                    .arg(value)
+                    .arg(seconds)
+                    .invoke_async(&mut *conn)
+                    .await
+                    .context(format!("Failed to compare and set key: {}", key))?;
+                Ok(set)
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                let mut memory = MemoryKv::lock(memory);
+                if memory.get(key).as_deref() != expected {
+                    return Ok(false);
+                }
+                memory.set_ex(key, value, seconds);
+                Ok(true)
+            }
+        }
+    }
+
+    /// update replaces the value of a key with `next` of the current one, reading it again
+    /// when a concurrent writer changed it in between, `next` returning None keeps it
+    async fn update<T, F>(&self, key: &str, seconds: u64, mut next: F) -> Result<Option<T>>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync,
+        F: FnMut(Option<T>) -> Option<T> + Send,
+    {
+        for _ in 0..UPDATE_ATTEMPTS {
+            let raw = self.get_raw(key).await?;
+            let current = raw
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()
+                .with_context(|| format!("Failed to deserialize value for key: {}", key))?;
+            let Some(value) = next(current) else {
+                return Ok(None);
+            };
+            let json_str = serde_json::to_string(&value)?;
+            if self.compare_and_set(key, raw.as_deref(), json_str, seconds).await? {
+                debug!(key, "redis update ok");
+                return Ok(Some(value));
+            }
+        }
+        anyhow::bail!("Failed to update key: {} after {} attempts", key, UPDATE_ATTEMPTS)
+    }
+
+    pub async fn del(&self, key: &str) -> Result<()> {
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let _: () =
+                    conn.del(key).await.context(format!("Failed to delete key: {}", key))?;
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                MemoryKv::lock(memory).values.remove(key);
+            }
+        }
+        debug!(key, "redis del ok");
+        Ok(())
+    }
+
     pub async fn exists(&self, key: &str) -> Result<bool> {
-        let mut conn = self.get_connection().await?;
-        let exists: bool =
-            conn.exists(key).await.context(format!("Failed to check if key exists: {}", key))?;
+        let exists = match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                conn.exists(key).await.context(format!("Failed to check if key exists: {}", key))?
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => MemoryKv::lock(memory).get(key).is_some(),
+        };
         debug!(key, exists, "redis exists ok");
         Ok(exists)
     }
@@ -70,20 +428,184 @@ impl KvStore {
         format!("solana:price:{}", mint)
     }
 
+    fn get_pool_state_price_key(&self, mint: &str) -> String {
+        format!("solana:price:pool_state:{}", mint)
+    }
+
     fn get_price_history_key(&self, mint: &str) -> String {
         format!("solana:price:history:{}", mint)
     }
 
+    fn get_pair_price_key(&self, pair: &str) -> String {
+        format!("solana:price:pair:{}", pair)
+    }
+
+    fn get_top_pair_key(&self, mint: &str) -> String {
+        format!("solana:price:top:{}", mint)
+    }
+
+    fn get_primary_pair_key(&self, mint: &str) -> String {
+        format!("solana:token:primary_pair:{}", mint)
+    }
+
+    /// insert_price stores the trade as the latest price of its pair, and of its mint
+    /// as configured by the `MintPriceMode`
     pub async fn insert_price(&self, price: &Trade) -> Result<()> {
+        let pair_price = self.insert_pair_price(price).await?;
+        self.update_primary_pair(price, pair_price.turnover).await?;
+        if self.mint_price_mode == MintPriceMode::TopPair {
+            let key = self.get_top_pair_key(&price.pubkey);
+            let top = self
+                .update(&key, 60 * 60 * 24, |top: Option<TopPair>| {
+                    let top_turnover = top
+                        .filter(|top| top.pair != price.pair)
+                        .map(|top| decay_turnover(top.turnover, top.timestamp, price.timestamp))
+                        .unwrap_or_default();
+                    (pair_price.turnover >= top_turnover).then(|| TopPair {
+                        pair: price.pair.clone(),
+                        turnover: pair_price.turnover,
+                        timestamp: price.timestamp,
+                    })
+                })
+                .await?;
+            if top.is_none() {
+                return Ok(());
+            }
+        }
         let key = self.get_price_key(&price.pubkey);
         self.set_ex(&key, price, 60 * 60 * 24).await
     }
 
+    /// insert_pair_price stores the trade as the latest price of its pair,
+    /// adding its turnover to the decayed turnover of the pair, the concurrent trades of a
+    /// pair all add up
+    async fn insert_pair_price(&self, price: &Trade) -> Result<PairPrice> {
+        let key = self.get_pair_price_key(&price.pair);
+        let pair_price = self
+            .update(&key, 60 * 60 * 24, |previous: Option<PairPrice>| {
+                let turnover = previous
+                    .map(|previous| {
+                        decay_turnover(previous.turnover, previous.timestamp, price.timestamp)
+                    })
+                    .unwrap_or_default();
+                Some(PairPrice {
+                    price: price.price,
+                    timestamp: price.timestamp,
+                    signature: price.signature.clone(),
+                    turnover: turnover + price.swap_amount,
+                })
+            })
+            .await?
+            .context("Failed to update pair price")?;
+        Ok(pair_price)
+    }
+
+    /// update_primary_pair switches the primary pair of the traded mint to the trade's pair
+    /// when its turnover exceeds the primary's by `PRIMARY_PAIR_HYSTERESIS`
+    async fn update_primary_pair(&self, price: &Trade, turnover: f64) -> Result<()> {
+        let key = self.get_primary_pair_key(&price.pubkey);
+        self.update(&key, PRIMARY_PAIR_TTL_SECS, |current: Option<PrimaryPair>| {
+            next_primary_pair(current.as_ref(), &price.pair, turnover, price.timestamp)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// get_primary_pair returns the canonical pool of a mint
+    pub async fn get_primary_pair(&self, mint: &str) -> Result<Option<PrimaryPair>> {
+        let key = self.get_primary_pair_key(mint);
+        self.get(&key).await
+    }
+
+    /// set_primary_pair pins the primary pair of a mint, or releases the pin when `pinned`
+    /// is false, letting the trades replace it again
+    pub async fn set_primary_pair(
+        &self,
+        mint: &str,
+        pair: &str,
+        pinned: bool,
+        timestamp: u64,
+    ) -> Result<PrimaryPair> {
+        let turnover = self
+            .get_latest_pair_price(pair)
+            .await?
+            .map(|price| decay_turnover(price.turnover, price.timestamp, timestamp))
+            .unwrap_or_default();
+        let primary = PrimaryPair { pair: pair.to_string(), turnover, timestamp, pinned };
+        self.set_ex(&self.get_primary_pair_key(mint), &primary, PRIMARY_PAIR_TTL_SECS).await?;
+        Ok(primary)
+    }
+
+    /// get_latest_pair_price returns the latest price of a pair
+    pub async fn get_latest_pair_price(&self, pair: &str) -> Result<Option<PairPrice>> {
+        let key = self.get_pair_price_key(pair);
+        self.get(&key).await
+    }
+
     pub async fn get_price(&self, mint: &str) -> Result<Option<Trade>> {
         let key = self.get_price_key(mint);
         self.get(&key).await
     }
 
+    /// insert_pool_state_price stores the mid-price of a pool as the pool state price of
+    /// its base mint, in `MintPriceMode::TopPair` only the top pair of the mint updates it
+    pub async fn insert_pool_state_price(&self, mint: &str, price: &PoolStatePrice) -> Result<()> {
+        if self.mint_price_mode == MintPriceMode::TopPair {
+            let top = self.get::<TopPair>(&self.get_top_pair_key(mint)).await?;
+            if top.is_some_and(|top| top.pair != price.pair) {
+                return Ok(());
+            }
+        }
+        let key = self.get_pool_state_price_key(mint);
+        self.set_ex(&key, price, 60 * 60 * 24).await
+    }
+
+    /// get_pool_state_price returns the latest pool state price of a mint
+    pub async fn get_pool_state_price(&self, mint: &str) -> Result<Option<PoolStatePrice>> {
+        let key = self.get_pool_state_price_key(mint);
+        self.get(&key).await
+    }
+
+    /// get_latest_price returns the latest price and its timestamp, the traded one unless
+    /// the pool state price is newer
+    pub async fn get_latest_price(&self, mint: &str) -> Result<Option<(f64, u64)>> {
+        let trade = self.get_price(mint).await?;
+        let pool_state = self.get_pool_state_price(mint).await?;
+        Ok(fresher_price(
+            trade.map(|trade| (trade.price, trade.timestamp)),
+            pool_state.map(|price| (price.price, price.timestamp)),
+        ))
+    }
+
+    /// get_latest_prices returns the latest prices of the mints with a single MGET,
+    /// in the same order as `mints`
+    pub async fn get_latest_prices(&self, mints: &[&str]) -> Result<Vec<Option<(f64, u64)>>> {
+        if mints.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys = mints
+            .iter()
+            .map(|mint| self.get_price_key(mint))
+            .chain(mints.iter().map(|mint| self.get_pool_state_price_key(mint)))
+            .collect::<Vec<_>>();
+        let mut values = self.get_many_raw(&keys).await.context("Failed to get latest prices")?;
+        let pool_states = values.split_off(mints.len());
+        let prices = values
+            .into_iter()
+            .zip(pool_states)
+            .map(|(trade, pool_state)| {
+                let trade = trade
+                    .and_then(|json_str| serde_json::from_str::<Trade>(&json_str).ok())
+                    .map(|trade| (trade.price, trade.timestamp));
+                let pool_state = pool_state
+                    .and_then(|json_str| serde_json::from_str::<PoolStatePrice>(&json_str).ok())
+                    .map(|price| (price.price, price.timestamp));
+                fresher_price(trade, pool_state)
+            })
+            .collect();
+        Ok(prices)
+    }
+
     // use zset to store price at timestamp
     pub async fn set_price_at_timestamp(
         &self,
@@ -92,22 +614,172 @@ impl KvStore {
         timestamp: u64,
     ) -> Result<()> {
         let key = self.get_price_history_key(mint);
-        let mut conn = self.get_connection().await?;
-        conn.zadd::<_, _, _, ()>(key, price, timestamp)
-            .await
-            .context(format!("Failed to set price at timestamp: {}", timestamp))?;
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                conn.zadd::<_, _, _, ()>(key, price, timestamp)
+                    .await
+                    .context(format!("Failed to set price at timestamp: {}", timestamp))?;
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                MemoryKv::lock(memory)
+                    .price_history
+                    .entry(key)
+                    .or_default()
+                    .insert(timestamp, price);
+            }
+        }
         Ok(())
     }
 
     pub async fn get_price_at_timestamp(&self, mint: &str, timestamp: u64) -> Result<f64> {
         let key = self.get_price_history_key(mint);
-        let mut conn = self.get_connection().await?;
-        let price: Vec<f64> = conn
-            .zrevrangebyscore_limit(key, timestamp, 0, 0, 1)
-            .await
-            .context(format!("Failed to get price at timestamp: {}", timestamp))?;
-        let price = price.first().copied().unwrap_or(0.0);
-        Ok(price)
+        let price = match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let price: Vec<f64> = conn
+                    .zrevrangebyscore_limit(key, timestamp, 0, 0, 1)
+                    .await
+                    .context(format!("Failed to get price at timestamp: {}", timestamp))?;
+                price.first().copied()
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => MemoryKv::lock(memory)
+                .price_history
+                .get(&key)
+                .and_then(|history| history.range(..=timestamp).next_back())
+                .map(|(_, price)| *price),
+        };
+        Ok(price.unwrap_or(0.0))
+    }
+
+    fn get_swap_side_key(&self, pair: &str, owner: &str) -> String {
+        format!("solana:swap:side:{}:{}", pair, owner)
+    }
+
+    /// record_swap_side stores the latest swap side of an owner on a pair for `seconds`,
+    /// returns true if the owner swapped the opposite side within that window
+    pub async fn record_swap_side(
+        &self,
+        pair: &str,
+        owner: &str,
+        is_buy: bool,
+        seconds: u64,
+    ) -> Result<bool> {
+        let key = self.get_swap_side_key(pair, owner);
+        let previous: Option<bool> = match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                bb8_redis::redis::cmd("SET")
+                    .arg(&key)
+                    .arg(is_buy)
+                    .arg("EX")
+                    .arg(seconds)
+                    .arg("GET")
+                    .query_async(&mut *conn)
+                    .await
+                    .context(format!("Failed to record swap side: {}", key))?
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => MemoryKv::lock(memory)
+                .set_ex(&key, is_buy.to_string(), seconds)
+                .map(|previous| previous == "true"),
+        };
+        Ok(previous.is_some_and(|was_buy| was_buy != is_buy))
+    }
+
+    fn get_pair_seen_key(&self, pair: &str) -> String {
+        format!("solana:pair:seen:{}", pair)
+    }
+
+    /// mark_pair_seen flags the pair as seen for `seconds`,
+    /// returns true if it wasn't flagged yet, i.e. on the first swap of the pair
+    pub async fn mark_pair_seen(&self, pair: &str, seconds: u64) -> Result<bool> {
+        let key = self.get_pair_seen_key(pair);
+        match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                let set: Option<String> = bb8_redis::redis::cmd("SET")
+                    .arg(&key)
+                    .arg(1)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(seconds)
+                    .query_async(&mut *conn)
+                    .await
+                    .context(format!("Failed to mark pair as seen: {}", key))?;
+                Ok(set.is_some())
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                let mut memory = MemoryKv::lock(memory);
+                if memory.get(&key).is_some() {
+                    return Ok(false);
+                }
+                memory.set_ex(&key, "1".to_string(), seconds);
+                Ok(true)
+            }
+        }
+    }
+
+    /// get_recent_trades returns the sequence number of the latest trade the message queue
+    /// published for a token and its recent trades with their sequence number, oldest first
+    pub async fn get_recent_trades(&self, token: &str) -> Result<(u64, Vec<(u64, Trade)>)> {
+        let (seq, payloads): (Option<u64>, Vec<String>) = match &self.backend {
+            KvBackend::Redis(pool) => {
+                let mut conn = Self::get_connection(pool).await?;
+                bb8_redis::redis::pipe()
+                    .atomic()
+                    .get(trade_seq_key(token))
+                    .lrange(recent_trades_key(token), 0, -1)
+                    .query_async(&mut *conn)
+                    .await
+                    .context(format!("Failed to get the recent trades of {}", token))?
+            }
+            #[cfg(any(test, feature = "memory-kv"))]
+            KvBackend::Memory(memory) => {
+                let mut memory = MemoryKv::lock(memory);
+                let seq = memory.get(&trade_seq_key(token)).and_then(|seq| seq.parse().ok());
+                let payloads = memory
+                    .recent_trades
+                    .get(&recent_trades_key(token))
+                    .map(|payloads| payloads.iter().cloned().collect())
+                    .unwrap_or_default();
+                (seq, payloads)
+            }
+        };
+        let mut trades = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            match decode_sequenced_message::<Trade>(&payload)? {
+                (trade, Some(seq)) => trades.push((seq, trade)),
+                (trade, None) => debug!(signature = %trade.signature, "recent trade without seq"),
+            }
+        }
+        Ok((seq.unwrap_or_default(), trades))
+    }
+
+    /// push_recent_trade numbers a trade and keeps it in the recent trades of its token, as
+    /// the message queue does when it publishes to Redis
+    #[cfg(any(test, feature = "memory-kv"))]
+    pub fn push_recent_trade(&self, trade: &Trade) -> Result<u64> {
+        let KvBackend::Memory(memory) = &self.backend else {
+            anyhow::bail!("Redis numbers the trades as the message queue publishes them");
+        };
+        let mut memory = MemoryKv::lock(memory);
+        let seq_key = trade_seq_key(&trade.pubkey);
+        let seq = memory
+            .get(&seq_key)
+            .and_then(|seq| seq.parse::<u64>().ok())
+            .unwrap_or_else(|| trade_seq_base(chrono::Utc::now().timestamp().max(0) as u64))
+            + 1;
+        memory.set_ex(&seq_key, seq.to_string(), TRADE_SEQ_TTL_SECS);
+        let mut payload = serde_json::to_value(trade)?;
+        payload["seq"] = seq.into();
+        let recent = memory.recent_trades.entry(recent_trades_key(&trade.pubkey)).or_default();
+        recent.push_back(payload.to_string());
+        recent.drain(..recent.len().saturating_sub(RECENT_TRADES_MAX));
+        Ok(seq)
     }
 
     fn get_token_key(&self, pubkey: &str) -> String {
@@ -124,10 +796,104 @@ impl KvStore {
         self.get(&key).await
     }
 
+    /// get_tokens returns the cached tokens of the mints with a single MGET, in the same order
+    /// as `mints`, None for the mints missing from the cache
+    pub async fn get_tokens(&self, mints: &[&str]) -> Result<Vec<Option<Token>>> {
+        if mints.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys = mints.iter().map(|mint| self.get_token_key(mint)).collect::<Vec<_>>();
+        let values = self.get_many_raw(&keys).await.context("Failed to get tokens")?;
+        let tokens = values
+            .into_iter()
+            .map(|value| value.and_then(|json_str| serde_json::from_str(&json_str).ok()))
+            .collect();
+        Ok(tokens)
+    }
+
+    /// delete_token drops the cached token, the next lookup reads the db or the RPC again
+    pub async fn delete_token(&self, mint: &str) -> Result<()> {
+        let key = self.get_token_key(mint);
+        self.del(&key).await
+    }
+
     pub async fn has_token(&self, mint: &str) -> Result<bool> {
         let key = self.get_token_key(mint);
         self.exists(&key).await
     }
+
+    fn get_unresolvable_token_key(&self, mint: &str) -> String {
+        format!("solana:metadata:unresolvable:{}", mint)
+    }
+
+    /// mark_token_unresolvable records that the metadata of a mint failed to resolve, the
+    /// refreshes skip it for `seconds`
+    pub async fn mark_token_unresolvable(&self, mint: &str, seconds: u64) -> Result<()> {
+        let key = self.get_unresolvable_token_key(mint);
+        self.set_ex(&key, &true, seconds).await
+    }
+
+    pub async fn is_token_unresolvable(&self, mint: &str) -> Result<bool> {
+        let key = self.get_unresolvable_token_key(mint);
+        self.exists(&key).await
+    }
+
+    /// set_missing_metadata_cursor stores the mint the next missing metadata refresh starts
+    /// after, None starts it over from the first mint
+    pub async fn set_missing_metadata_cursor(&self, mint: Option<&str>) -> Result<()> {
+        match mint {
+            Some(mint) => self.set_ex(MISSING_METADATA_CURSOR_KEY, &mint, 60 * 60 * 24 * 30).await,
+            None => self.del(MISSING_METADATA_CURSOR_KEY).await,
+        }
+    }
+
+    pub async fn get_missing_metadata_cursor(&self) -> Result<Option<String>> {
+        self.get(MISSING_METADATA_CURSOR_KEY).await
+    }
+
+    fn get_wallet_label_key(&self, address: &str) -> String {
+        format!("solana:wallet:label:{}", address)
+    }
+
+    /// get_wallet_labels returns the cached label lookups of the addresses with a single MGET,
+    /// in the same order as `addresses`, None for the addresses missing from the cache and
+    /// Some(None) for the addresses cached as unlabeled
+    pub async fn get_wallet_labels(
+        &self,
+        addresses: &[&str],
+    ) -> Result<Vec<Option<Option<WalletLabel>>>> {
+        if addresses.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys = addresses.iter().map(|a| self.get_wallet_label_key(a)).collect::<Vec<_>>();
+        let values = self.get_many_raw(&keys).await.context("Failed to get wallet labels")?;
+        let labels = values
+            .into_iter()
+            .map(|value| value.and_then(|json_str| serde_json::from_str(&json_str).ok()))
+            .collect();
+        Ok(labels)
+    }
+
+    /// set_wallet_label caches the label of an address for an hour, None caches that the
+    /// address is unlabeled
+    pub async fn set_wallet_label(&self, address: &str, label: Option<&WalletLabel>) -> Result<()> {
+        let key = self.get_wallet_label_key(address);
+        self.set_ex(&key, &label, WALLET_LABEL_TTL_SECS).await
+    }
+
+    fn get_token_risk_key(&self, mint: &str) -> String {
+        format!("solana:risk:{}", mint)
+    }
+
+    pub async fn set_token_risk_flags(&self, mint: &str, flags: &TokenRiskFlags) -> Result<()> {
+        let key = self.get_token_risk_key(mint);
+        self.set_ex(&key, flags, self.risk_flags_ttl.as_secs()).await
+    }
+
+    pub async fn get_token_risk_flags(&self, mint: &str) -> Result<Option<TokenRiskFlags>> {
+        let key = self.get_token_risk_key(mint);
+        self.get(&key).await
+    }
 }
 
 pub async fn make_kv_store(redis_url: &str) -> Result<KvStore> {
@@ -135,9 +901,15 @@ pub async fn make_kv_store(redis_url: &str) -> Result<KvStore> {
     Ok(kv)
 }
 
+/// make_kv_store_with_config creates the kv store of `config`, with its mint price mode
+/// and risk flags ttl
+pub async fn make_kv_store_with_config(config: &RedisConfig) -> Result<KvStore> {
+    let kv = make_kv_store(&config.url).await?;
+    Ok(kv.with_mint_price_mode(config.mint_price_mode).with_risk_flags_ttl(config.risk_flags_ttl))
+}
+
 pub async fn make_kv_store_from_env() -> Result<KvStore> {
-    let redis_url = var("REDIS_URL").expect("Expected REDIS_URL to be set");
-    make_kv_store(&redis_url).await
+    make_kv_store_with_config(&RedisConfig::from_env()?).await
 }
 
 /// make a redis connection pool
@@ -153,3 +925,270 @@ pub async fn make_kv_pool(redis_url: &str) -> Result<bb8::Pool<RedisConnectionMa
         .await?;
     Ok(pool)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::pairs::PriceSource;
+
+    #[test]
+    fn test_redis_config_read_env() {
+        let mut env = EnvReader::new(|name| (name == "REDIS_URL").then(|| "redis://r:6379".into()));
+        let config = RedisConfig::read_env(&mut env);
+        assert_eq!(env.finish(()), Ok(()));
+        assert_eq!(
+            config,
+            RedisConfig {
+                url: "redis://r:6379".to_string(),
+                mq_envelope: false,
+                mint_price_mode: MintPriceMode::Latest,
+                risk_flags_ttl: DEFAULT_RISK_FLAGS_TTL,
+            }
+        );
+
+        let mut env = EnvReader::new(|name| match name {
+            "REDIS_URL" => Some("redis://r:6379".into()),
+            "RISK_FLAGS_TTL_SECS" => Some("2592000".into()),
+            _ => None,
+        });
+        let config = RedisConfig::read_env(&mut env);
+        assert_eq!(env.finish(()), Ok(()));
+        assert_eq!(config.risk_flags_ttl, Duration::from_secs(2_592_000));
+
+        let mut env = EnvReader::new(|name| match name {
+            "MQ_ENVELOPE" => Some("maybe".into()),
+            "MINT_PRICE_MODE" => Some("top".into()),
+            "RISK_FLAGS_TTL_SECS" => Some("0".into()),
+            _ => None,
+        });
+        RedisConfig::read_env(&mut env);
+        assert_eq!(env.finish(()).unwrap_err().problems.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_kv_store() {
+        let kv_store = KvStore::in_memory();
+        kv_store.set_ex("key", &42u64, 60).await.unwrap();
+        assert_eq!(kv_store.get::<u64>("key").await.unwrap(), Some(42));
+        assert!(kv_store.exists("key").await.unwrap());
+        assert!(!kv_store.exists("missing").await.unwrap());
+        kv_store.del("key").await.unwrap();
+        assert!(!kv_store.exists("key").await.unwrap());
+
+        kv_store.set_price_at_timestamp("mint", 1.5, 100).await.unwrap();
+        kv_store.set_price_at_timestamp("mint", 2.5, 200).await.unwrap();
+        assert_eq!(kv_store.get_price_at_timestamp("mint", 150).await.unwrap(), 1.5);
+        assert_eq!(kv_store.get_price_at_timestamp("mint", 50).await.unwrap(), 0.0);
+
+        assert!(!kv_store.record_swap_side("pair", "owner", true, 60).await.unwrap());
+        assert!(!kv_store.record_swap_side("pair", "owner", true, 60).await.unwrap());
+        assert!(kv_store.record_swap_side("pair", "owner", false, 60).await.unwrap());
+
+        assert!(kv_store.mark_pair_seen("pair", 60).await.unwrap());
+        assert!(!kv_store.mark_pair_seen("pair", 60).await.unwrap());
+
+        // expired keys are gone
+        kv_store.set_ex("expired", &1u64, 0).await.unwrap();
+        assert_eq!(kv_store.get::<u64>("expired").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_wallet_label_cache() {
+        let kv_store = KvStore::in_memory();
+        let label = WalletLabel {
+            address: "cex".to_string(),
+            label: "Binance 1".to_string(),
+            category: "cex".to_string(),
+            added_at: 1,
+        };
+        kv_store.set_wallet_label("cex", Some(&label)).await.unwrap();
+        kv_store.set_wallet_label("unlabeled", None).await.unwrap();
+        let cached = kv_store.get_wallet_labels(&["cex", "unlabeled", "missing"]).await.unwrap();
+        assert_eq!(cached, vec![Some(Some(label)), Some(None), None]);
+        assert!(kv_store.get_wallet_labels(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_tokens() {
+        let kv_store = KvStore::in_memory();
+        let token = crate::test_utils::make_token("mint", "BONK", "Bonk");
+        kv_store.set_token("mint", &token).await.unwrap();
+        let cached = kv_store.get_tokens(&["missing", "mint"]).await.unwrap();
+        assert!(cached[0].is_none());
+        assert_eq!(cached[1].as_ref().map(|t| t.symbol.as_str()), Some("BONK"));
+        assert!(kv_store.get_tokens(&[]).await.unwrap().is_empty());
+    }
+
+    fn make_trade(pair: &str, price: f64, timestamp: u64, swap_amount: f64) -> Trade {
+        let signature = format!("{pair}-{timestamp}");
+        let event = crate::test_utils::make_swap_event("mint", pair, &signature, timestamp, price);
+        Trade { market_cap: 0.0, base_amount: 1.0, swap_amount, ..event.into() }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_trades_add_up() {
+        let kv_store = KvStore::in_memory();
+        let tasks: Vec<_> = (0..64)
+            .map(|i| {
+                let kv_store = kv_store.clone();
+                let trade =
+                    Trade { signature: format!("deep-{i}"), ..make_trade("deep", 1.0, 100, 10.0) };
+                tokio::spawn(async move { kv_store.insert_price(&trade).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        // none of the trades of the same second is lost to another read before its write
+        let deep = kv_store.get_latest_pair_price("deep").await.unwrap().unwrap();
+        assert!((deep.turnover - 640.0).abs() < 1e-9, "{}", deep.turnover);
+        let primary = kv_store.get_primary_pair("mint").await.unwrap().unwrap();
+        assert_eq!(primary.pair, "deep");
+    }
+
+    #[tokio::test]
+    async fn test_insert_price_writes_pair_and_mint_prices() {
+        let kv_store = KvStore::in_memory();
+        kv_store.insert_price(&make_trade("deep", 1.0, 100, 1000.0)).await.unwrap();
+        kv_store.insert_price(&make_trade("dust", 2.0, 110, 1.0)).await.unwrap();
+
+        // each pair keeps its own price, the mint follows the latest trade
+        let deep = kv_store.get_latest_pair_price("deep").await.unwrap().unwrap();
+        assert_eq!((deep.price, deep.timestamp, deep.signature.as_str()), (1.0, 100, "deep-100"));
+        let dust = kv_store.get_latest_pair_price("dust").await.unwrap().unwrap();
+        assert_eq!((dust.price, dust.timestamp, dust.signature.as_str()), (2.0, 110, "dust-110"));
+        assert_eq!(kv_store.get_latest_pair_price("missing").await.unwrap(), None);
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((2.0, 110)));
+
+        // the turnover of a pair decays with its age
+        kv_store.insert_price(&make_trade("deep", 1.5, 100 + 3600, 0.0)).await.unwrap();
+        let deep = kv_store.get_latest_pair_price("deep").await.unwrap().unwrap();
+        assert!((deep.turnover - 500.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_top_pair_mint_price() {
+        let kv_store = KvStore::in_memory().with_mint_price_mode(MintPriceMode::TopPair);
+        kv_store.insert_price(&make_trade("deep", 1.0, 100, 1000.0)).await.unwrap();
+        // a dust pool doesn't override the price of the deep one
+        kv_store.insert_price(&make_trade("dust", 2.0, 110, 1.0)).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((1.0, 100)));
+        assert_eq!(kv_store.get_latest_pair_price("dust").await.unwrap().unwrap().price, 2.0);
+
+        // the deep pool keeps updating it
+        kv_store.insert_price(&make_trade("deep", 1.1, 120, 10.0)).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((1.1, 120)));
+
+        // until another pool outgrows it
+        kv_store.insert_price(&make_trade("dust", 2.5, 130, 5000.0)).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((2.5, 130)));
+    }
+
+    #[tokio::test]
+    async fn test_pool_state_price_until_fresher_swap() {
+        let kv_store = KvStore::in_memory();
+        let pool_state = |price, timestamp| PoolStatePrice {
+            pair: "deep".to_string(),
+            price,
+            timestamp,
+            source: PriceSource::PoolState,
+        };
+        // the pool state price fills in for a mint without trades
+        kv_store.insert_pool_state_price("mint", &pool_state(0.9, 90)).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((0.9, 90)));
+
+        // a swap at the same time or later wins
+        kv_store.insert_price(&make_trade("deep", 1.0, 100, 10.0)).await.unwrap();
+        kv_store.insert_pool_state_price("mint", &pool_state(1.1, 100)).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((1.0, 100)));
+
+        // a newer pool state wins over an older swap
+        kv_store.insert_pool_state_price("mint", &pool_state(1.2, 105)).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((1.2, 105)));
+        assert_eq!(
+            kv_store.get_latest_prices(&["mint", "missing"]).await.unwrap(),
+            vec![Some((1.2, 105)), None]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pool_state_price_follows_top_pair() {
+        let kv_store = KvStore::in_memory().with_mint_price_mode(MintPriceMode::TopPair);
+        kv_store.insert_price(&make_trade("deep", 1.0, 100, 1000.0)).await.unwrap();
+        let price = PoolStatePrice {
+            pair: "dust".to_string(),
+            price: 2.0,
+            timestamp: 110,
+            source: PriceSource::PoolState,
+        };
+        kv_store.insert_pool_state_price("mint", &price).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((1.0, 100)));
+
+        let price = PoolStatePrice { pair: "deep".to_string(), ..price };
+        kv_store.insert_pool_state_price("mint", &price).await.unwrap();
+        assert_eq!(kv_store.get_latest_price("mint").await.unwrap(), Some((2.0, 110)));
+    }
+
+    #[test]
+    fn test_next_primary_pair_hysteresis() {
+        let primary = |pair: &str, turnover, pinned| PrimaryPair {
+            pair: pair.to_string(),
+            turnover,
+            timestamp: 100,
+            pinned,
+        };
+        // the first traded pair becomes the primary
+        assert_eq!(next_primary_pair(None, "a", 10.0, 100), Some(primary("a", 10.0, false)));
+
+        // the primary pair refreshes its turnover
+        let current = primary("a", 100.0, false);
+        assert_eq!(
+            next_primary_pair(Some(&current), "a", 120.0, 100),
+            Some(primary("a", 120.0, false))
+        );
+
+        // another pair needs 1.5 times the turnover to replace it
+        assert_eq!(next_primary_pair(Some(&current), "b", 140.0, 100), None);
+        assert_eq!(next_primary_pair(Some(&current), "b", 150.0, 100), None);
+        assert_eq!(
+            next_primary_pair(Some(&current), "b", 151.0, 100),
+            Some(primary("b", 151.0, false))
+        );
+
+        // the turnover of the primary decays until the other pair is compared
+        let later = next_primary_pair(Some(&current), "b", 80.0, 100 + 3600).unwrap();
+        assert_eq!((later.pair.as_str(), later.timestamp), ("b", 3700));
+
+        // a pinned primary is never replaced, not even refreshed
+        let pinned = primary("a", 0.0, true);
+        assert_eq!(next_primary_pair(Some(&pinned), "b", 1e9, 100), None);
+        assert_eq!(next_primary_pair(Some(&pinned), "a", 1e9, 100), None);
+    }
+
+    #[tokio::test]
+    async fn test_primary_pair() {
+        let kv_store = KvStore::in_memory();
+        assert_eq!(kv_store.get_primary_pair("mint").await.unwrap(), None);
+
+        kv_store.insert_price(&make_trade("deep", 1.0, 100, 1000.0)).await.unwrap();
+        kv_store.insert_price(&make_trade("other", 1.0, 110, 1400.0)).await.unwrap();
+        let primary = kv_store.get_primary_pair("mint").await.unwrap().unwrap();
+        assert_eq!(primary.pair, "deep");
+
+        // the other pool takes over once well ahead
+        kv_store.insert_price(&make_trade("other", 1.0, 120, 1000.0)).await.unwrap();
+        assert_eq!(kv_store.get_primary_pair("mint").await.unwrap().unwrap().pair, "other");
+
+        // a pinned pair wins over the trades
+        let pinned = kv_store.set_primary_pair("mint", "deep", true, 130).await.unwrap();
+        assert!(pinned.pinned);
+        kv_store.insert_price(&make_trade("other", 1.0, 140, 1e6)).await.unwrap();
+        assert_eq!(kv_store.get_primary_pair("mint").await.unwrap(), Some(pinned));
+
+        // until the pin is released
+        kv_store.set_primary_pair("mint", "deep", false, 150).await.unwrap();
+        kv_store.insert_price(&make_trade("other", 1.0, 160, 10.0)).await.unwrap();
+        assert_eq!(kv_store.get_primary_pair("mint").await.unwrap().unwrap().pair, "other");
+    }
+}
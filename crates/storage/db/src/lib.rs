@@ -1,24 +1,64 @@
 pub mod ck;
 pub mod db;
+pub mod env;
 pub mod errors;
 pub mod kv_store;
+pub mod memory;
 pub mod message_queue;
-pub mod models;
+#[cfg(feature = "postgres")]
+pub mod pg;
 pub mod redis_subscriber;
+pub mod search;
+pub mod shutdown;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+
+pub use sonar_models as models;
 
 pub use {
-    ck::{make_db, make_db_from_env},
-    db::{Database, DatabaseTrait},
-    errors::StorageError,
-    kv_store::{make_kv_pool, make_kv_store, make_kv_store_from_env, KvStore},
+    ck::{make_clickhouse_db, make_clickhouse_db_from_env, make_db, ClickhouseConfig},
+    db::{
+        initialize_retrying, initialize_schema_from_env, make_db_from_env, make_db_with_config,
+        paginate_trades, Database, DatabaseBackend, DatabaseConfig, DatabaseTrait,
+    },
+    env::{ConfigError, EnvReader, DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE},
+    errors::{is_not_supported, is_schema_mismatch, is_unavailable, storage_error, StorageError},
+    kv_store::{
+        make_kv_pool, make_kv_store, make_kv_store_from_env, make_kv_store_with_config, KvStore,
+        MintPriceMode, RedisConfig,
+    },
+    memory::{DryRunLog, MemoryDb, MemoryMessageQueue},
     message_queue::{
-        make_message_queue, make_message_queue_from_env, MessageQueue, MessageQueueTrait,
-        RedisMessageQueue,
+        decode_message, decode_sequenced_message, encode_message, make_message_queue,
+        make_message_queue_from_env, make_message_queue_with_config, Envelope, MessageQueue,
+        MessageQueueTrait, RedisMessageQueue, CANDLES_CLOSED_CHANNEL, GRADUATIONS_CHANNEL,
+        LARGE_TRADES_CHANNEL, NEW_POOLS_CHANNEL, RECENT_TRADES_MAX, RECENT_TRADES_TTL_SECS,
     },
     models::{
-        candlesticks::{Candlestick, CandlestickInterval},
-        swap::{SwapEvent, Trade},
-        tokens::{clean_string, TopToken},
+        candlesticks::{
+            is_reference_pair, reference_candlesticks, Candlestick, CandlestickInterval,
+            CurrentCandlestick, Denomination, LatestCandlestick, OutlierPolicy, QuotePrices,
+            ReferencePrice, CANDLESTICK_INTERVALS, DEFAULT_CANDLESTICK_LIMIT, SOL_USD_PAIR,
+            STORED_CANDLESTICK_INTERVALS, TOKEN_CANDLESTICK_PAIR,
+        },
+        dexes::{Dexes, DEXES, PUMP_LAUNCHPAD},
+        pairs::{
+            FeeStat, Pair, PairDetail, PairInfo, PairPrice, PoolStatePrice, PriceSource,
+            PrimaryPair,
+        },
+        swap::{SwapEvent, Trade, TradeCursor, TradeFilter, TradeV2, TRADE_CSV_HEADER},
+        tokens::{
+            clean_string, is_major_mint, MatchReason, SortOrder, TokenDailyRollup, TokenLaunch,
+            TokenLaunchRecord, TokenRiskFlags, TokenSearchResult, TopToken, TopTokensPage,
+            TopTokensSort, MAJOR_MINTS, USDC_MINT, USDT_MINT, WSOL_MINT,
+        },
+        wallet::{WalletActivity, WalletCategory, WalletLabel},
     },
     redis_subscriber::{make_redis_subscriber, make_redis_subscriber_from_env, RedisSubscriber},
 };
+
+#[cfg(feature = "postgres")]
+pub use pg::{
+    db::PostgresDb, make_postgres_db, make_postgres_db_from_env, make_postgres_db_with_config,
+    PostgresConfig,
+};
@@ -0,0 +1,1486 @@
+//! In-process implementations of the storage traits, capturing what is written to them,
+//! for tests and runs without ClickHouse and Redis
+use crate::{
+    db::{day_bounds, get_prices_retrying, paginate_trades, DatabaseTrait},
+    errors::not_supported,
+    message_queue::MessageQueueTrait,
+    models::{
+        candlesticks::{
+            is_reference_pair, reference_candlesticks, Candlestick, CandlestickInterval,
+            LatestCandlestick, OutlierPolicy, ReferencePrice, DEFAULT_CANDLESTICK_LIMIT,
+        },
+        dexes::Dexes,
+        events::{CandlesClosedEvent, NewPoolEvent, SystemAlert, TokenGraduatedEvent},
+        pairs::{FeeStat, Pair, PairDetail, PairInfo},
+        swap::{SwapEvent, Trade, TradeFilter, TradeV2},
+        tokens::{
+            is_major_mint, PriceSource, SortOrder, Token, TokenDailyRollup, TokenDailyStat,
+            TokenPrice, TokenSearch, TokenSearchResult, TokenStat, TopToken, TopTokensPage,
+            TopTokensSort,
+        },
+        wallet::{WalletActivity, WalletLabel},
+    },
+    search::{normalize_query, rank_search_results},
+};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use futures::stream::BoxStream;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// The number of search results returned, as in ClickHouse
+const SEARCH_LIMIT: usize = 10;
+
+/// DryRunLog logs the writes of a dry run at INFO instead of keeping them, clones share the
+/// counts of the logged writes
+#[derive(Debug, Clone, Default)]
+pub struct DryRunLog {
+    writes: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+}
+
+impl DryRunLog {
+    /// writes returns how many writes of a kind were logged, e.g. `swap_event` or `trade`
+    pub fn writes(&self, kind: &str) -> u64 {
+        self.writes.lock().unwrap().get(kind).copied().unwrap_or_default()
+    }
+
+    /// log logs the serialized value the storage would have written
+    pub fn log<T: Serialize>(&self, kind: &'static str, value: &T) {
+        *self.writes.lock().unwrap().entry(kind).or_default() += 1;
+        match serde_json::to_string(value) {
+            Ok(json) => info!(kind, value = %json, "Dry run, skipped the write"),
+            Err(e) => warn!(kind, error = ?e, "Dry run, failed to serialize the skipped write"),
+        }
+    }
+}
+
+/// Counts the queries dropped before they completed
+struct CancelGuard<'a> {
+    cancelled: &'a AtomicUsize,
+    completed: bool,
+}
+
+impl CancelGuard<'_> {
+    fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cancelled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A database keeping swap events and tokens in memory, clones share the same state,
+/// the candlesticks, top tokens and search are computed from the swap events without the
+/// outlier clamping, the other analytical queries return empty results
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDb {
+    swap_events: Arc<Mutex<Vec<SwapEvent>>>,
+    reference_prices: Arc<Mutex<Vec<ReferencePrice>>>,
+    tokens: Arc<Mutex<HashMap<String, Token>>>,
+    verified_tokens: Arc<Mutex<HashSet<String>>>,
+    pairs: Arc<Mutex<HashMap<String, Pair>>>,
+    wallet_labels: Arc<Mutex<HashMap<String, WalletLabel>>>,
+    /// The daily rollups by token and day
+    token_daily_rollups: Arc<Mutex<BTreeMap<(String, NaiveDate), TokenDailyRollup>>>,
+    /// How many times the wallet labels were looked up
+    wallet_label_lookups: Arc<AtomicUsize>,
+    /// How many more times the price queries of a mint fail
+    price_failures: Arc<Mutex<HashMap<String, usize>>>,
+    /// How many more times the candlestick aggregations fail
+    aggregation_failures: Arc<AtomicUsize>,
+    /// How long the candlestick, top tokens and search queries take
+    query_delay: Option<Duration>,
+    /// How many delayed queries were dropped before they completed
+    cancelled_queries: Arc<AtomicUsize>,
+    /// Logs the swap events, pairs and tokens instead of keeping them
+    dry_run: Option<DryRunLog>,
+    /// Whether the maintenance operations Postgres doesn't implement fail as not supported
+    maintenance_unsupported: bool,
+}
+
+impl MemoryDb {
+    /// with_dry_run logs the swap events, pairs and tokens instead of keeping them, so that a
+    /// dry run against live traffic doesn't grow
+    pub fn with_dry_run(mut self, dry_run: DryRunLog) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// skip_write logs the write in a dry run, returns true if it must be skipped
+    fn skip_write<T: Serialize>(&self, kind: &'static str, value: &T) -> bool {
+        let Some(dry_run) = &self.dry_run else {
+            return false;
+        };
+        dry_run.log(kind, value);
+        true
+    }
+
+    /// swap_events returns the swap events inserted so far, in insertion order
+    pub fn swap_events(&self) -> Vec<SwapEvent> {
+        self.swap_events.lock().unwrap().clone()
+    }
+
+    /// reference_prices returns the reference prices inserted so far, in insertion order
+    pub fn reference_prices(&self) -> Vec<ReferencePrice> {
+        self.reference_prices.lock().unwrap().clone()
+    }
+
+    /// pairs returns the pairs recorded so far
+    pub fn pairs(&self) -> Vec<Pair> {
+        self.pairs.lock().unwrap().values().cloned().collect()
+    }
+
+    /// is_verified returns true if the token was marked as verified
+    pub fn is_verified(&self, mint: &str) -> bool {
+        self.verified_tokens.lock().unwrap().contains(mint)
+    }
+
+    /// wallet_label_lookups returns how many times get_wallet_labels was called
+    pub fn wallet_label_lookups(&self) -> usize {
+        self.wallet_label_lookups.load(Ordering::Relaxed)
+    }
+
+    /// fail_prices makes the next `times` price queries of the mint fail
+    pub fn fail_prices(&self, mint: &str, times: usize) {
+        self.price_failures.lock().unwrap().insert(mint.to_string(), times);
+    }
+
+    /// with_query_delay delays the candlestick, top tokens and search queries, to stand in
+    /// for a slow database
+    pub fn with_query_delay(mut self, delay: Duration) -> Self {
+        self.query_delay = Some(delay);
+        self
+    }
+
+    /// cancelled_queries returns how many delayed queries were dropped before they completed
+    pub fn cancelled_queries(&self) -> usize {
+        self.cancelled_queries.load(Ordering::Relaxed)
+    }
+
+    /// stall waits for the query delay, counting the queries dropped while they wait
+    async fn stall(&self) {
+        let Some(delay) = self.query_delay else {
+            return;
+        };
+        let guard = CancelGuard { cancelled: &self.cancelled_queries, completed: false };
+        tokio::time::sleep(delay).await;
+        guard.complete();
+    }
+
+    /// without_maintenance makes the partition removal, the prune and the window stats
+    /// refresh fail as not supported, as on Postgres
+    pub fn without_maintenance(mut self) -> Self {
+        self.maintenance_unsupported = true;
+        self
+    }
+
+    /// check_maintenance fails the maintenance `operation` when it is unsupported
+    fn check_maintenance(&self, operation: &str) -> Result<()> {
+        if self.maintenance_unsupported {
+            return Err(not_supported(operation));
+        }
+        Ok(())
+    }
+
+    /// fail_aggregations makes the next `times` candlestick aggregations fail
+    pub fn fail_aggregations(&self, times: usize) {
+        self.aggregation_failures.store(times, Ordering::Relaxed);
+    }
+
+    fn trades(&self, filter: impl Fn(&SwapEvent) -> bool) -> Vec<Trade> {
+        let mut trades: Vec<Trade> = self
+            .swap_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| filter(event))
+            .cloned()
+            .map(Trade::from)
+            .collect();
+        trades.sort_by_key(Trade::cursor);
+        trades
+    }
+
+    /// candlesticks buckets the finite trades matching the filter in the time range,
+    /// returns the latest `limit` buckets in ascending order
+    fn candlesticks(
+        &self,
+        filter: impl Fn(&SwapEvent) -> bool,
+        interval: &CandlestickInterval,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Vec<Candlestick> {
+        let trades = self.trades(|event| {
+            filter(event)
+                && event.price.is_finite()
+                && time_from.is_none_or(|time_from| event.timestamp as i64 >= time_from as i64)
+                && time_to.is_none_or(|time_to| (event.timestamp as i64) < time_to as i64)
+        });
+        let candlesticks = bucket_candlesticks(trades, interval);
+        let skip = candlesticks.len().saturating_sub(limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT));
+        candlesticks.into_iter().skip(skip).collect()
+    }
+
+    fn latest_price(&self, mint: &str, timestamp: i32) -> TokenPrice {
+        let latest =
+            self.trades(|event| event.pubkey == mint && event.timestamp <= timestamp as u64).pop();
+        TokenPrice {
+            token: mint.to_string(),
+            timestamp,
+            price: latest.as_ref().map(|trade| trade.price),
+            neatest_timestamp: latest.as_ref().map(|trade| trade.timestamp as i32),
+            source: latest.map(|_| PriceSource::Db),
+        }
+    }
+}
+
+/// bucket_candlesticks aggregates trades sorted by time into candlesticks, in ascending order
+fn bucket_candlesticks(trades: Vec<Trade>, interval: &CandlestickInterval) -> Vec<Candlestick> {
+    let mut candlesticks: BTreeMap<u64, Candlestick> = BTreeMap::new();
+    for trade in trades {
+        let bucket = interval.bucket_start(trade.timestamp);
+        let candlestick = candlesticks.entry(bucket).or_insert(Candlestick {
+            timestamp: bucket,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: 0.0,
+            turnover: 0.0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            buy_turnover: 0.0,
+            sell_turnover: 0.0,
+        });
+        candlestick.high = candlestick.high.max(trade.price);
+        candlestick.low = candlestick.low.min(trade.price);
+        candlestick.close = trade.price;
+        candlestick.volume += trade.base_amount;
+        candlestick.turnover += trade.swap_amount;
+        if trade.is_buy {
+            candlestick.buy_volume += trade.base_amount;
+            candlestick.buy_turnover += trade.swap_amount;
+        } else {
+            candlestick.sell_volume += trade.base_amount;
+            candlestick.sell_turnover += trade.swap_amount;
+        }
+    }
+    candlesticks.into_values().collect()
+}
+
+#[async_trait::async_trait]
+impl DatabaseTrait for MemoryDb {
+    fn new(_database_url: &str, _password: &str, _user: &str, _database: &str) -> Self {
+        Self::default()
+    }
+
+    async fn initialize(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn verify_schema(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn insert_swap_event(&self, swap_event: &SwapEvent) -> Result<()> {
+        if self.skip_write("swap_event", swap_event) {
+            return Ok(());
+        }
+        self.swap_events.lock().unwrap().push(swap_event.clone());
+        Ok(())
+    }
+
+    async fn insert_reference_price(&self, price: &ReferencePrice) -> Result<()> {
+        if self.skip_write("reference_price", price) {
+            return Ok(());
+        }
+        self.reference_prices.lock().unwrap().push(price.clone());
+        Ok(())
+    }
+
+    async fn get_candlesticks_by_token(
+        &self,
+        token: &str,
+        pairs: &[String],
+        interval: CandlestickInterval,
+        _outlier_policy: &OutlierPolicy,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Result<Vec<Candlestick>> {
+        self.stall().await;
+        Ok(self.candlesticks(
+            |event| event.pubkey == token && (pairs.is_empty() || pairs.contains(&event.pair)),
+            &interval,
+            limit,
+            time_from,
+            time_to,
+        ))
+    }
+
+    async fn get_candlesticks_by_pair(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        _outlier_policy: &OutlierPolicy,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Result<Vec<Candlestick>> {
+        self.stall().await;
+        if is_reference_pair(pair) {
+            let prices = self.reference_prices.lock().unwrap().clone();
+            let prices = prices.into_iter().filter(|price| {
+                price.pair == pair
+                    && time_from.is_none_or(|time_from| price.timestamp as i64 >= time_from as i64)
+                    && time_to.is_none_or(|time_to| (price.timestamp as i64) < time_to as i64)
+            });
+            let candlesticks = reference_candlesticks(prices, interval);
+            let skip =
+                candlesticks.len().saturating_sub(limit.unwrap_or(DEFAULT_CANDLESTICK_LIMIT));
+            return Ok(candlesticks.into_iter().skip(skip).collect());
+        }
+        Ok(self.candlesticks(
+            |event| event.pair == pair && token.is_none_or(|token| event.pubkey == token),
+            interval,
+            limit,
+            time_from,
+            time_to,
+        ))
+    }
+
+    async fn get_latest_candlestick(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        _outlier_policy: &OutlierPolicy,
+        at: u64,
+    ) -> Result<LatestCandlestick> {
+        let interval_seconds = interval.get_seconds() as u64;
+        let current_bucket = interval.bucket_start(at);
+        let previous_bucket = current_bucket.saturating_sub(interval_seconds);
+        let trades = self.trades(|event| {
+            event.pair == pair
+                && event.price.is_finite()
+                && token.is_none_or(|token| event.pubkey == token)
+                && (previous_bucket..current_bucket + interval_seconds).contains(&event.timestamp)
+        });
+        Ok(LatestCandlestick::from_buckets(bucket_candlesticks(trades, interval), interval, at))
+    }
+
+    async fn get_candlesticks_from_swap_events(
+        &self,
+        pair: &str,
+        token: Option<&str>,
+        interval: &CandlestickInterval,
+        _outlier_policy: &OutlierPolicy,
+        limit: Option<usize>,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+    ) -> Result<Vec<Candlestick>> {
+        Ok(self.candlesticks(
+            |event| {
+                pair.split(',').any(|pair| pair == event.pair)
+                    && token.is_none_or(|token| event.pubkey == token)
+            },
+            interval,
+            limit,
+            time_from,
+            time_to,
+        ))
+    }
+
+    async fn get_candlesticks_from_candlesticks(
+        &self,
+        _pair: &str,
+        _token: Option<&str>,
+        _interval: &CandlestickInterval,
+        _limit: Option<usize>,
+        _time_from: Option<i32>,
+        _time_to: Option<i32>,
+        _exclude_buckets: Option<Vec<u64>>,
+    ) -> Result<Vec<Candlestick>> {
+        Ok(vec![])
+    }
+
+    async fn get_pairs_for_token(&self, mint: &str, since: Option<u64>) -> Result<Vec<PairInfo>> {
+        let since = since.unwrap_or_default();
+        let trades = self.trades(|event| event.pubkey == mint && event.timestamp >= since);
+        let recorded = self.pairs.lock().unwrap();
+        let mut pairs: HashMap<String, PairInfo> = HashMap::new();
+        for trade in trades {
+            let pair = pairs.entry(trade.pair.clone()).or_insert_with(|| PairInfo {
+                pair: trade.pair.clone(),
+                dex: recorded.get(&trade.pair).map(|p| p.dex),
+                turnover: 0.0,
+                last_trade_ts: 0,
+            });
+            pair.turnover += trade.swap_amount;
+            pair.last_trade_ts = pair.last_trade_ts.max(trade.timestamp);
+        }
+        let mut pairs: Vec<PairInfo> = pairs.into_values().collect();
+        pairs.sort_by(|a, b| b.turnover.total_cmp(&a.turnover));
+        Ok(pairs)
+    }
+
+    async fn insert_pair(&self, pair: &Pair) -> Result<()> {
+        if self.skip_write("pair", pair) {
+            return Ok(());
+        }
+        let mut pairs = self.pairs.lock().unwrap();
+        pairs
+            .entry(pair.pair.clone())
+            .and_modify(|recorded| {
+                if pair.first_seen < recorded.first_seen {
+                    *recorded = pair.clone();
+                }
+            })
+            .or_insert_with(|| pair.clone());
+        Ok(())
+    }
+
+    async fn get_pair_detail(&self, pair: &str) -> Result<Option<PairDetail>> {
+        let trades = self.trades(|event| event.pair == pair);
+        let recorded = self.pairs.lock().unwrap().get(pair).cloned();
+        let Some(latest) = trades.last() else {
+            // the swap events of a recorded pair may have been pruned
+            return Ok(recorded.map(|recorded| PairDetail {
+                pair: recorded.pair,
+                base_mint: recorded.base_mint,
+                quote_mint: Some(recorded.quote_mint),
+                dex: Some(recorded.dex),
+                price: 0.0,
+                volume_24h: 0.0,
+                turnover_24h: 0.0,
+                trades_24h: 0,
+                first_seen: recorded.first_seen,
+                last_trade_ts: 0,
+            }));
+        };
+        let mut mint_trades: HashMap<&str, usize> = HashMap::new();
+        for trade in &trades {
+            *mint_trades.entry(trade.pubkey.as_str()).or_default() += 1;
+        }
+        let most_traded_mint = mint_trades
+            .into_iter()
+            .max_by_key(|(mint, count)| (*count, std::cmp::Reverse(*mint)))
+            .map(|(mint, _)| mint.to_string())
+            .unwrap_or_default();
+        let day_ago = (chrono::Utc::now().timestamp() as u64).saturating_sub(86400);
+        let last_day: Vec<&Trade> = trades.iter().filter(|t| t.timestamp >= day_ago).collect();
+        let first_seen = trades[0].timestamp;
+        Ok(Some(PairDetail {
+            pair: pair.to_string(),
+            base_mint: recorded.as_ref().map_or(most_traded_mint, |p| p.base_mint.clone()),
+            quote_mint: recorded.as_ref().map(|p| p.quote_mint.clone()),
+            dex: recorded.as_ref().map(|p| p.dex),
+            price: latest.price,
+            volume_24h: last_day.iter().map(|t| t.base_amount).sum(),
+            turnover_24h: last_day.iter().map(|t| t.swap_amount).sum(),
+            trades_24h: last_day.len() as u64,
+            first_seen: recorded.map_or(first_seen, |p| p.first_seen.min(first_seen)),
+            last_trade_ts: latest.timestamp,
+        }))
+    }
+
+    async fn get_wallet_activity(
+        &self,
+        address: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        strict_owner: bool,
+        limit: usize,
+    ) -> Result<Vec<WalletActivity>> {
+        let trades = self.trades(|event| {
+            let is_wallet = if strict_owner {
+                event.owner == address
+            } else {
+                event.signers.iter().any(|signer| signer == address)
+            };
+            is_wallet
+                && start_time.is_none_or(|start_time| event.timestamp >= start_time)
+                && end_time.is_none_or(|end_time| event.timestamp <= end_time)
+        });
+        let mut activities: HashMap<String, WalletActivity> = HashMap::new();
+        for trade in trades {
+            let activity =
+                activities.entry(trade.pubkey.clone()).or_insert_with(|| WalletActivity {
+                    token: trade.pubkey.clone(),
+                    trade_count: 0,
+                    buy_base_amount: 0.0,
+                    sell_base_amount: 0.0,
+                    net_base_amount: 0.0,
+                    buy_turnover: 0.0,
+                    sell_turnover: 0.0,
+                    last_trade_ts: 0,
+                });
+            activity.trade_count += 1;
+            if trade.is_buy {
+                activity.buy_base_amount += trade.base_amount;
+                activity.buy_turnover += trade.swap_amount;
+            } else {
+                activity.sell_base_amount += trade.base_amount;
+                activity.sell_turnover += trade.swap_amount;
+            }
+            activity.net_base_amount = activity.buy_base_amount - activity.sell_base_amount;
+            activity.last_trade_ts = activity.last_trade_ts.max(trade.timestamp);
+        }
+        let mut activities: Vec<WalletActivity> = activities.into_values().collect();
+        activities.sort_by(|a, b| {
+            let turnover = |a: &WalletActivity| a.buy_turnover + a.sell_turnover;
+            turnover(b).total_cmp(&turnover(a)).then_with(|| a.token.cmp(&b.token))
+        });
+        activities.truncate(limit);
+        Ok(activities)
+    }
+
+    async fn get_fee_stats(
+        &self,
+        pair: Option<&str>,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<FeeStat>> {
+        let mut stats: HashMap<(String, String), FeeStat> = HashMap::new();
+        let trades = self.trades(|event| {
+            event.fee_amount > 0.0
+                && event.timestamp >= since
+                && pair.is_none_or(|pair| event.pair == pair)
+        });
+        for trade in trades {
+            let key = (trade.pair.clone(), trade.fee_mint.clone());
+            let stat = stats.entry(key).or_insert_with(|| FeeStat {
+                pair: trade.pair.clone(),
+                fee_mint: trade.fee_mint.clone(),
+                fee_amount: 0.0,
+                swap_amount: 0.0,
+                swaps: 0,
+            });
+            stat.fee_amount += trade.fee_amount;
+            stat.swap_amount += trade.swap_amount;
+            stat.swaps += 1;
+        }
+        let mut stats: Vec<FeeStat> = stats.into_values().collect();
+        stats.sort_by(|a, b| b.fee_amount.total_cmp(&a.fee_amount));
+        stats.truncate(limit);
+        Ok(stats)
+    }
+
+    async fn get_top_tokens(
+        &self,
+        limit: usize,
+        offset: usize,
+        start_time: u64,
+        min_volume: Option<f64>,
+        min_market_cap: Option<f64>,
+        pumpfun: Option<bool>,
+        exclude_wash: bool,
+        sort_by: TopTokensSort,
+        order: SortOrder,
+    ) -> Result<TopTokensPage> {
+        self.stall().await;
+        let trades = self.trades(|event| {
+            event.timestamp >= start_time
+                && event.price.is_finite()
+                && !(exclude_wash && event.is_wash)
+                && !is_major_mint(&event.pubkey)
+        });
+        let mut tokens: BTreeMap<String, (TopToken, f64, bool)> = BTreeMap::new();
+        let mut traders: HashMap<String, [HashSet<String>; 3]> = HashMap::new();
+        for trade in trades {
+            let (token, first_price, is_pump) =
+                tokens.entry(trade.pubkey.clone()).or_insert_with(|| {
+                    let token = TopToken {
+                        pubkey: trade.pubkey.clone(),
+                        price: 0.0,
+                        market_cap: 0.0,
+                        volume: 0.0,
+                        turnover: 0.0,
+                        price_change: 0.0,
+                        unique_traders: 0,
+                        unique_buyers: 0,
+                        unique_sellers: 0,
+                    };
+                    (token, trade.price, false)
+                });
+            token.price = trade.price;
+            token.market_cap = trade.market_cap;
+            token.volume += trade.base_amount;
+            token.turnover += trade.swap_amount;
+            token.price_change = (trade.price - *first_price) / *first_price * 100.0;
+            *is_pump = trade.is_pump;
+            let [all, buyers, sellers] = traders.entry(trade.pubkey.clone()).or_default();
+            let side = if trade.is_buy { buyers } else { sellers };
+            for signer in &trade.signers {
+                all.insert(signer.clone());
+                side.insert(signer.clone());
+            }
+        }
+        let mut tokens: Vec<TopToken> = tokens
+            .into_values()
+            .filter(|(token, _, is_pump)| {
+                min_volume.is_none_or(|min_volume| token.volume >= min_volume)
+                    && min_market_cap
+                        .is_none_or(|min_market_cap| token.market_cap >= min_market_cap)
+                    && pumpfun.is_none_or(|pumpfun| *is_pump == pumpfun)
+            })
+            .map(|(mut token, _, _)| {
+                let [all, buyers, sellers] = &traders[&token.pubkey];
+                token.unique_traders = all.len() as u64;
+                token.unique_buyers = buyers.len() as u64;
+                token.unique_sellers = sellers.len() as u64;
+                token
+            })
+            .collect();
+        let key = |token: &TopToken| match sort_by {
+            TopTokensSort::Volume => token.volume,
+            TopTokensSort::Turnover => token.turnover,
+            TopTokensSort::PriceChange => token.price_change,
+            TopTokensSort::MarketCap => token.market_cap,
+        };
+        tokens.sort_by(|a, b| match order {
+            SortOrder::Asc => key(a).total_cmp(&key(b)),
+            SortOrder::Desc => key(b).total_cmp(&key(a)),
+        });
+        let total = tokens.len() as u64;
+        let tokens = tokens.into_iter().skip(offset).take(limit).collect();
+        Ok(TopTokensPage { tokens, total })
+    }
+
+    async fn get_token_stats(
+        &self,
+        _tokens: Vec<String>,
+        _exclude_wash: bool,
+    ) -> Result<Vec<TokenStat>> {
+        Ok(vec![])
+    }
+
+    async fn get_token_daily_stats(&self, _tokens: Vec<String>) -> Result<Vec<TokenDailyStat>> {
+        Ok(vec![])
+    }
+
+    async fn get_trades(&self, filter: &TradeFilter) -> Result<Vec<Trade>> {
+        let (address, token) = (filter.address.as_deref(), filter.token.as_deref());
+        let (pair, signature) = (filter.pair.as_deref(), filter.signature.as_deref());
+        if address.is_none() && token.is_none() && pair.is_none() && signature.is_none() {
+            return Ok(vec![]);
+        }
+        let labels = self.wallet_labels.lock().unwrap().clone();
+        let mut trades = self.trades(|event| {
+            address.is_none_or(|address| event.owner == address || event.fee_payer == address)
+                && token.is_none_or(|token| event.pubkey == token)
+                && pair.is_none_or(|pair| event.pair == pair)
+                && signature.is_none_or(|signature| event.signature == signature)
+                && filter.slot.is_none_or(|slot| event.slot == slot)
+                && filter.time_from.is_none_or(|time_from| event.timestamp >= time_from)
+                && filter.time_to.is_none_or(|time_to| event.timestamp < time_to)
+                && filter.category.is_none_or(|category| {
+                    labels.get(&event.owner).is_some_and(|l| l.category == category.to_string())
+                })
+                && filter.min_pct_supply.is_none_or(|min| event.pct_of_supply >= min)
+        });
+        trades.reverse();
+        Ok(trades
+            .into_iter()
+            .skip(filter.offset.unwrap_or_default())
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    fn stream_trades(&self, filter: TradeFilter) -> BoxStream<'_, Result<Trade>> {
+        let trades = self.trades(|event| {
+            filter.token.as_ref().is_none_or(|token| &event.pubkey == token)
+                && filter.pair.as_ref().is_none_or(|pair| &event.pair == pair)
+                && filter.time_from.is_none_or(|time_from| event.timestamp >= time_from)
+                && filter.time_to.is_none_or(|time_to| event.timestamp <= time_to)
+        });
+        paginate_trades(trades.len().max(1), filter.limit, move |cursor, size| {
+            let page = trades
+                .iter()
+                .filter(|trade| cursor.as_ref().is_none_or(|cursor| &trade.cursor() > cursor))
+                .take(size)
+                .cloned()
+                .collect();
+            futures::future::ready(Ok(page))
+        })
+    }
+
+    async fn get_price(&self, mint: &str, timestamp: i32) -> Result<TokenPrice> {
+        if let Some(failures) = self.price_failures.lock().unwrap().get_mut(mint) {
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(anyhow!("injected price failure of {mint}"));
+            }
+        }
+        Ok(self.latest_price(mint, timestamp))
+    }
+
+    async fn get_prices(&self, queries: Vec<(&str, i32)>) -> Vec<Result<TokenPrice>> {
+        get_prices_retrying(self, queries).await
+    }
+
+    async fn insert_token(&self, token: &Token) -> Result<()> {
+        if self.skip_write("token", token) {
+            return Ok(());
+        }
+        self.tokens.lock().unwrap().insert(token.token.clone(), token.clone());
+        Ok(())
+    }
+
+    async fn get_token(&self, mint: &str) -> Result<Option<Token>> {
+        Ok(self.tokens.lock().unwrap().get(mint).cloned())
+    }
+
+    async fn get_tokens(&self, mints: &[&str]) -> Result<Vec<Token>> {
+        let tokens = self.tokens.lock().unwrap();
+        Ok(mints.iter().filter_map(|mint| tokens.get(*mint).cloned()).collect())
+    }
+
+    async fn has_token(&self, mint: &str) -> Result<bool> {
+        Ok(self.tokens.lock().unwrap().contains_key(mint))
+    }
+
+    async fn get_tokens_with_missing_metadata(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let tokens = self.tokens.lock().unwrap();
+        let mut mints: Vec<String> = tokens
+            .values()
+            .filter(|t| t.name.is_empty() || t.symbol.is_empty() || t.supply == 0.0)
+            .filter(|t| after.is_none_or(|after| t.token.as_str() > after))
+            .map(|t| t.token.clone())
+            .collect();
+        mints.sort();
+        mints.truncate(limit);
+        Ok(mints)
+    }
+
+    async fn search_tokens(&self, query: &str) -> Result<Vec<TokenSearchResult>> {
+        self.stall().await;
+        let query = normalize_query(query);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        let tokens = self.tokens.lock().unwrap().clone();
+        let mut candidates: Vec<TokenSearch> = tokens
+            .values()
+            .map(|token| TokenSearch {
+                token: token.token.clone(),
+                name: token.name.clone(),
+                symbol: token.symbol.clone(),
+                decimals: token.decimals,
+                supply: token.supply,
+                latest_price: 0.0,
+                price_24h: 0.0,
+                tx_count_24h: 0,
+                volume_24h: 0.0,
+                turnover_24h: 0.0,
+                verified: self.is_verified(&token.token),
+            })
+            .collect();
+        // the ties keep the same order across runs
+        candidates.sort_by(|a, b| a.token.cmp(&b.token));
+        let mut results = rank_search_results(&query, candidates, SEARCH_LIMIT);
+        for result in &mut results {
+            result.launch = tokens[&result.token.token].launch();
+        }
+        Ok(results)
+    }
+
+    async fn set_token_verified(&self, mint: &str, verified: bool) -> Result<()> {
+        let mut verified_tokens = self.verified_tokens.lock().unwrap();
+        if verified {
+            verified_tokens.insert(mint.to_string());
+        } else {
+            verified_tokens.remove(mint);
+        }
+        Ok(())
+    }
+
+    async fn set_token_launchpad(&self, mint: &str, launchpad: &str) -> Result<()> {
+        if let Some(token) = self.tokens.lock().unwrap().get_mut(mint) {
+            if token.launchpad.is_empty() {
+                token.launchpad = launchpad.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    async fn mark_token_graduated(&self, mint: &str, pool: &str, timestamp: u64) -> Result<()> {
+        if let Some(token) = self.tokens.lock().unwrap().get_mut(mint) {
+            if token.graduated_at == 0 {
+                token.graduated_at = timestamp;
+                token.graduation_pool = pool.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_token_launch_info(
+        &self,
+        mint: &str,
+        dex: Dexes,
+        pool: &str,
+        timestamp: u64,
+    ) -> Result<()> {
+        if let Some(token) = self.tokens.lock().unwrap().get_mut(mint) {
+            if token.launch_pool.is_empty() {
+                token.launch_dex = Some(dex);
+                token.launch_pool = pool.to_string();
+                token.launch_timestamp = timestamp;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_active_tokens(&self, since: u64, limit: usize) -> Result<Vec<String>> {
+        let mut swaps: HashMap<String, usize> = HashMap::new();
+        for event in self.swap_events.lock().unwrap().iter().filter(|e| e.timestamp >= since) {
+            *swaps.entry(event.pubkey.clone()).or_default() += 1;
+        }
+        let mut tokens: Vec<(String, usize)> = swaps.into_iter().collect();
+        tokens.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(tokens.into_iter().take(limit).map(|(token, _)| token).collect())
+    }
+
+    async fn get_active_pairs(&self, start: u64, end: u64, limit: usize) -> Result<Vec<String>> {
+        let pairs: BTreeSet<String> = self
+            .swap_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.timestamp >= start && e.timestamp < end)
+            .map(|e| e.pair.clone())
+            .collect();
+        Ok(pairs.into_iter().take(limit).collect())
+    }
+
+    async fn aggregate_into_candlesticks(
+        &self,
+        _start_time: i64,
+        _end_time: i64,
+        _interval: CandlestickInterval,
+    ) -> Result<Option<u64>> {
+        let failures = self.aggregation_failures.load(Ordering::Relaxed);
+        if failures > 0 {
+            self.aggregation_failures.store(failures - 1, Ordering::Relaxed);
+            return Err(anyhow!("injected aggregation failure"));
+        }
+        Ok(Some(0))
+    }
+
+    async fn aggregate_candlesticks_from_candlesticks(
+        &self,
+        _source_interval: CandlestickInterval,
+        _interval: CandlestickInterval,
+        _start_time: i64,
+        _end_time: i64,
+    ) -> Result<Option<u64>> {
+        Ok(Some(0))
+    }
+
+    async fn aggregate_daily_token_rollups(&self, date: NaiveDate) -> Result<Option<u64>> {
+        let (start, end) = day_bounds(date);
+        let mut trades: BTreeMap<String, Vec<SwapEvent>> = BTreeMap::new();
+        for event in self.swap_events.lock().unwrap().iter() {
+            if event.timestamp >= start && event.timestamp < end && event.price.is_finite() {
+                trades.entry(event.pubkey.clone()).or_default().push(event.clone());
+            }
+        }
+        let mut rollups = self.token_daily_rollups.lock().unwrap();
+        let rows = trades.len() as u64;
+        for (pubkey, mut events) in trades {
+            events.sort_by_key(|event| event.timestamp);
+            let prices = events.iter().map(|event| event.price);
+            let signers: HashSet<&String> = events.iter().flat_map(|e| &e.signers).collect();
+            let rollup = TokenDailyRollup {
+                pubkey: pubkey.clone(),
+                date: date.to_string(),
+                open: events[0].price,
+                high: prices.clone().fold(f64::MIN, f64::max),
+                low: prices.fold(f64::MAX, f64::min),
+                close: events[events.len() - 1].price,
+                volume: events.iter().map(|event| event.base_amount).sum(),
+                turnover: events.iter().map(|event| event.swap_amount).sum(),
+                trade_count: events.len() as u64,
+                unique_traders: signers.len() as u64,
+            };
+            rollups.insert((pubkey, date), rollup);
+        }
+        Ok(Some(rows))
+    }
+
+    async fn get_token_history(
+        &self,
+        mint: &str,
+        from_date: NaiveDate,
+        to_date: NaiveDate,
+    ) -> Result<Vec<TokenDailyRollup>> {
+        let rollups = self.token_daily_rollups.lock().unwrap();
+        Ok(rollups
+            .range((mint.to_string(), from_date)..=(mint.to_string(), to_date))
+            .map(|(_, rollup)| rollup.clone())
+            .collect())
+    }
+
+    async fn remove_swap_events(&self, _partition: i64) -> Result<()> {
+        self.check_maintenance("remove_swap_events")
+    }
+
+    async fn estimate_prunable_rows(
+        &self,
+        _inactive_days: u32,
+        _older_than_days: u32,
+    ) -> Result<u64> {
+        self.check_maintenance("estimate_prunable_rows")?;
+        Ok(0)
+    }
+
+    async fn prune_inactive_token_events(
+        &self,
+        _inactive_days: u32,
+        _older_than_days: u32,
+    ) -> Result<u64> {
+        self.check_maintenance("prune_inactive_token_events")?;
+        Ok(0)
+    }
+
+    async fn upsert_wallet_label(&self, label: &WalletLabel) -> Result<()> {
+        self.wallet_labels.lock().unwrap().insert(label.address.clone(), label.clone());
+        Ok(())
+    }
+
+    async fn get_wallet_labels(&self, addresses: &[&str]) -> Result<Vec<WalletLabel>> {
+        self.wallet_label_lookups.fetch_add(1, Ordering::Relaxed);
+        let labels = self.wallet_labels.lock().unwrap();
+        Ok(addresses.iter().filter_map(|address| labels.get(*address).cloned()).collect())
+    }
+
+    async fn refresh_token_window_stats(&self, _now: u64) -> Result<()> {
+        self.check_maintenance("refresh_token_window_stats")
+    }
+}
+
+/// A message queue recording the published messages, clones share the same state
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMessageQueue {
+    trades: Arc<Mutex<Vec<Trade>>>,
+    new_pools: Arc<Mutex<Vec<NewPoolEvent>>>,
+    system_alerts: Arc<Mutex<Vec<SystemAlert>>>,
+    graduations: Arc<Mutex<Vec<TokenGraduatedEvent>>>,
+    large_trades: Arc<Mutex<Vec<TradeV2>>>,
+    candles_closed: Arc<Mutex<Vec<CandlesClosedEvent>>>,
+    /// Logs the messages instead of recording them
+    dry_run: Option<DryRunLog>,
+}
+
+impl MemoryMessageQueue {
+    /// with_dry_run logs the messages instead of recording them
+    pub fn with_dry_run(mut self, dry_run: DryRunLog) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// skip_publish logs the message in a dry run, returns true if it must be skipped
+    fn skip_publish<T: Serialize>(&self, kind: &'static str, message: &T) -> bool {
+        let Some(dry_run) = &self.dry_run else {
+            return false;
+        };
+        dry_run.log(kind, message);
+        true
+    }
+
+    pub fn trades(&self) -> Vec<Trade> {
+        self.trades.lock().unwrap().clone()
+    }
+
+    pub fn new_pools(&self) -> Vec<NewPoolEvent> {
+        self.new_pools.lock().unwrap().clone()
+    }
+
+    pub fn system_alerts(&self) -> Vec<SystemAlert> {
+        self.system_alerts.lock().unwrap().clone()
+    }
+
+    pub fn graduations(&self) -> Vec<TokenGraduatedEvent> {
+        self.graduations.lock().unwrap().clone()
+    }
+
+    pub fn large_trades(&self) -> Vec<TradeV2> {
+        self.large_trades.lock().unwrap().clone()
+    }
+
+    pub fn candles_closed(&self) -> Vec<CandlesClosedEvent> {
+        self.candles_closed.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageQueueTrait for MemoryMessageQueue {
+    async fn new(_url: &str) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    async fn publish_trade(&self, trade: &Trade) -> Result<()> {
+        if self.skip_publish("trade", trade) {
+            return Ok(());
+        }
+        self.trades.lock().unwrap().push(trade.clone());
+        Ok(())
+    }
+
+    async fn publish_new_pool(&self, new_pool: &NewPoolEvent) -> Result<()> {
+        if self.skip_publish("new_pool", new_pool) {
+            return Ok(());
+        }
+        self.new_pools.lock().unwrap().push(new_pool.clone());
+        Ok(())
+    }
+
+    async fn publish_system_alert(&self, alert: &SystemAlert) -> Result<()> {
+        if self.skip_publish("system_alert", alert) {
+            return Ok(());
+        }
+        self.system_alerts.lock().unwrap().push(alert.clone());
+        Ok(())
+    }
+
+    async fn publish_token_graduated(&self, graduation: &TokenGraduatedEvent) -> Result<()> {
+        if self.skip_publish("token_graduated", graduation) {
+            return Ok(());
+        }
+        self.graduations.lock().unwrap().push(graduation.clone());
+        Ok(())
+    }
+
+    async fn publish_large_trade(&self, trade: &TradeV2) -> Result<()> {
+        if self.skip_publish("large_trade", trade) {
+            return Ok(());
+        }
+        self.large_trades.lock().unwrap().push(trade.clone());
+        Ok(())
+    }
+
+    async fn publish_candles_closed(&self, candles_closed: &CandlesClosedEvent) -> Result<()> {
+        if self.skip_publish("candles_closed", candles_closed) {
+            return Ok(());
+        }
+        self.candles_closed.lock().unwrap().push(candles_closed.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::tokens::{TokenLaunch, DEFAULT_MAJOR_MINTS},
+        test_utils::make_token,
+    };
+    use futures::StreamExt;
+
+    fn make_swap_event(pair: &str, timestamp: u64, price: f64) -> SwapEvent {
+        let signature = format!("{pair}-{timestamp}");
+        SwapEvent {
+            market_cap: 0.0,
+            base_amount: 1.0,
+            swap_amount: price,
+            ..crate::test_utils::make_swap_event("token", pair, &signature, timestamp, price)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_skips_writes() {
+        let dry_run = DryRunLog::default();
+        let db = MemoryDb::default().with_dry_run(dry_run.clone());
+        let message_queue = MemoryMessageQueue::default().with_dry_run(dry_run.clone());
+        let event = make_swap_event("pool-a", 10, 1.0);
+        db.insert_swap_event(&event).await.unwrap();
+        db.insert_swap_event(&event).await.unwrap();
+        db.insert_token(&make_token("token", "TKN", "Token")).await.unwrap();
+        message_queue.publish_trade(&event.clone().into()).await.unwrap();
+
+        assert!(db.swap_events().is_empty());
+        assert!(!db.has_token("token").await.unwrap());
+        assert!(message_queue.trades().is_empty());
+        assert_eq!((dry_run.writes("swap_event"), dry_run.writes("token")), (2, 1));
+        assert_eq!((dry_run.writes("trade"), dry_run.writes("pair")), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_trades_and_prices() {
+        let db = MemoryDb::default();
+        for event in [
+            make_swap_event("pool-a", 10, 1.0),
+            make_swap_event("pool-b", 30, 3.0),
+            make_swap_event("pool-a", 20, 2.0),
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+        assert_eq!(db.swap_events().len(), 3);
+
+        let trades = db
+            .get_trades(&TradeFilter {
+                token: Some("token".to_string()),
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let timestamps: Vec<u64> = trades.iter().map(|t| t.timestamp).collect();
+        assert_eq!(timestamps, vec![30, 20]);
+
+        let price = db.get_price("token", 25).await.unwrap();
+        assert_eq!((price.price, price.neatest_timestamp), (Some(2.0), Some(20)));
+        assert_eq!(db.get_price("token", 5).await.unwrap().price, None);
+
+        let pairs = db.get_pairs_for_token("token", None).await.unwrap();
+        assert_eq!(pairs[0].pair, "pool-b");
+        assert_eq!(pairs[1].turnover, 3.0);
+
+        let filter = TradeFilter { token: Some("token".to_string()), ..Default::default() };
+        let streamed: Vec<u64> =
+            db.stream_trades(filter).map(|t| t.unwrap().timestamp).collect().await;
+        assert_eq!(streamed, vec![10, 20, 30]);
+
+        // a swap relayed by a bot is found by both the trader and the fee payer
+        let mut relayed = make_swap_event("pool-a", 40, 4.0);
+        relayed.fee_payer = "bot".to_string();
+        db.insert_swap_event(&relayed).await.unwrap();
+        for address in ["owner", "bot"] {
+            let trades = db
+                .get_trades(&TradeFilter {
+                    address: Some(address.to_string()),
+                    time_from: Some(40),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(trades.len(), 1, "{address}");
+            assert_eq!((trades[0].owner.as_str(), trades[0].fee_payer.as_str()), ("owner", "bot"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_prices_retry_once() {
+        let db = MemoryDb::default();
+        db.insert_swap_event(&make_swap_event("pool-a", 10, 1.0)).await.unwrap();
+
+        // a single failure is retried
+        db.fail_prices("token", 1);
+        let prices = db.get_prices(vec![("token", 20), ("other", 20)]).await;
+        assert_eq!(prices[0].as_ref().unwrap().price, Some(1.0));
+        assert_eq!(prices[1].as_ref().unwrap().price, None);
+
+        // a second failure only fails its own item
+        db.fail_prices("token", 2);
+        let prices = db.get_prices(vec![("other", 20), ("token", 20)]).await;
+        assert!(prices[0].is_ok());
+        assert!(prices[1].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_latest_candlestick() {
+        let db = MemoryDb::default();
+        let interval = CandlestickInterval::OneMinute;
+        let policy = OutlierPolicy::default();
+        // the trades of the previous minute and of an older one, none in the current minute
+        for event in [
+            make_swap_event("pool-a", 30, 9.0),
+            make_swap_event("pool-a", 70, 1.0),
+            make_swap_event("pool-a", 119, 2.0),
+            // a price stored before the swap prices were checked
+            make_swap_event("pool-a", 100, f64::NAN),
+            make_swap_event("pool-b", 121, 5.0),
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+
+        let latest = db.get_latest_candlestick("pool-a", None, &interval, &policy, 125).await;
+        let latest = latest.unwrap();
+        let previous = latest.previous.expect("the previous minute has trades");
+        assert_eq!(previous.timestamp, 60);
+        assert_eq!(
+            (previous.open, previous.high, previous.low, previous.close),
+            (1.0, 2.0, 1.0, 2.0)
+        );
+        assert_eq!((previous.buy_volume, previous.sell_volume), (2.0, 0.0));
+        let current = latest.current.expect("the previous close is carried over");
+        assert!(current.synthetic);
+        assert_eq!(current.candlestick.timestamp, 120);
+        let flat = (current.candlestick.open, current.candlestick.high, current.candlestick.close);
+        assert_eq!(flat, (2.0, 2.0, 2.0));
+        assert_eq!(current.candlestick.volume, 0.0);
+
+        // the first trade of the minute replaces the synthetic candlestick
+        db.insert_swap_event(&make_swap_event("pool-a", 122, 3.0)).await.unwrap();
+        let latest = db.get_latest_candlestick("pool-a", None, &interval, &policy, 125).await;
+        let current = latest.unwrap().current.unwrap();
+        assert!(!current.synthetic);
+        assert_eq!((current.candlestick.open, current.candlestick.close), (3.0, 3.0));
+
+        // nothing traded in either minute
+        let latest = db.get_latest_candlestick("pool-a", None, &interval, &policy, 245).await;
+        let latest = latest.unwrap();
+        assert!(latest.current.is_none() && latest.previous.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_active_tokens() {
+        let db = MemoryDb::default();
+        let with_token = |token: &str, timestamp: u64| SwapEvent {
+            pubkey: token.to_string(),
+            ..make_swap_event("pool", timestamp, 1.0)
+        };
+        for event in [
+            with_token("stale", 5),
+            with_token("stale", 6),
+            with_token("stale", 7),
+            with_token("quiet", 10),
+            with_token("busy", 10),
+            with_token("busy", 20),
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+        assert_eq!(db.get_active_tokens(10, 10).await.unwrap(), vec!["busy", "quiet"]);
+        assert_eq!(db.get_active_tokens(10, 1).await.unwrap(), vec!["busy"]);
+        assert_eq!(db.get_active_tokens(0, 1).await.unwrap(), vec!["stale"]);
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_fee_stats() {
+        let db = MemoryDb::default();
+        let with_fee = |pair: &str, timestamp: u64, fee_amount: f64| SwapEvent {
+            fee_amount,
+            fee_mint: "wsol".to_string(),
+            ..make_swap_event(pair, timestamp, 10.0)
+        };
+        for event in [
+            with_fee("pool-a", 10, 0.5),
+            with_fee("pool-a", 20, 0.25),
+            with_fee("pool-b", 20, 1.0),
+            make_swap_event("pool-c", 20, 10.0),
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+
+        let stats = db.get_fee_stats(None, 0, 10).await.unwrap();
+        let pairs: Vec<&str> = stats.iter().map(|s| s.pair.as_str()).collect();
+        assert_eq!(pairs, vec!["pool-b", "pool-a"]);
+        assert_eq!((stats[1].fee_amount, stats[1].swap_amount, stats[1].swaps), (0.75, 20.0, 2));
+
+        let stats = db.get_fee_stats(Some("pool-a"), 15, 10).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].fee_amount, stats[0].swaps), (0.25, 1));
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_pair_detail() {
+        let db = MemoryDb::default();
+        assert!(db.get_pair_detail("pool-a").await.unwrap().is_none());
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        for event in [
+            make_swap_event("pool-a", now - 2 * 86400, 1.0),
+            make_swap_event("pool-a", now - 60, 2.0),
+            make_swap_event("pool-a", now - 30, 3.0),
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+        let detail = db.get_pair_detail("pool-a").await.unwrap().unwrap();
+        assert_eq!(detail.base_mint, "token");
+        assert_eq!((detail.quote_mint, detail.dex), (None, None));
+        assert_eq!((detail.price, detail.trades_24h, detail.turnover_24h), (3.0, 2, 5.0));
+        assert_eq!((detail.first_seen, detail.last_trade_ts), (now - 2 * 86400, now - 30));
+
+        for first_seen in [now - 60, now - 3 * 86400] {
+            let pair = Pair {
+                pair: "pool-a".to_string(),
+                base_mint: "token".to_string(),
+                quote_mint: "wsol".to_string(),
+                dex: Dexes::RaydiumAmmV4,
+                first_seen,
+            };
+            db.insert_pair(&pair).await.unwrap();
+        }
+        assert_eq!(db.pairs().len(), 1);
+        let detail = db.get_pair_detail("pool-a").await.unwrap().unwrap();
+        assert_eq!(detail.quote_mint.as_deref(), Some("wsol"));
+        assert_eq!(detail.dex, Some(Dexes::RaydiumAmmV4));
+        assert_eq!(detail.first_seen, now - 3 * 86400);
+        let pairs = db.get_pairs_for_token("token", None).await.unwrap();
+        assert_eq!(pairs[0].dex, Some(Dexes::RaydiumAmmV4));
+
+        // a recorded pair is still served once its swap events are gone
+        let db = MemoryDb::default();
+        let pair = Pair {
+            pair: "pool-b".to_string(),
+            base_mint: "token".to_string(),
+            quote_mint: "wsol".to_string(),
+            dex: Dexes::RaydiumAmmV4,
+            first_seen: now - 60,
+        };
+        db.insert_pair(&pair).await.unwrap();
+        let detail = db.get_pair_detail("pool-b").await.unwrap().unwrap();
+        assert_eq!((detail.base_mint.as_str(), detail.trades_24h), ("token", 0));
+        assert_eq!(detail.first_seen, now - 60);
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_wallet_activity() {
+        let db = MemoryDb::default();
+        let trade = |token: &str, owner: &str, is_buy: bool, timestamp: u64| SwapEvent {
+            pubkey: token.to_string(),
+            owner: owner.to_string(),
+            signers: vec![owner.to_string(), "wallet".to_string()],
+            is_buy,
+            ..make_swap_event("pool", timestamp, 10.0)
+        };
+        for event in [
+            trade("token-a", "wallet", true, 10),
+            trade("token-a", "wallet", false, 20),
+            trade("token-a", "bot", true, 30),
+            trade("token-b", "wallet", true, 40),
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+
+        let activity = db.get_wallet_activity("wallet", None, None, false, 500).await.unwrap();
+        assert_eq!(activity[0].token, "token-a");
+        assert_eq!((activity[0].trade_count, activity[0].net_base_amount), (3, 1.0));
+        assert_eq!((activity[0].buy_turnover, activity[0].sell_turnover), (20.0, 10.0));
+        assert_eq!(activity[0].last_trade_ts, 30);
+
+        let activity = db.get_wallet_activity("wallet", None, None, true, 500).await.unwrap();
+        let tokens: Vec<(&str, u64)> =
+            activity.iter().map(|a| (a.token.as_str(), a.trade_count)).collect();
+        assert_eq!(tokens, vec![("token-a", 2), ("token-b", 1)]);
+
+        let activity =
+            db.get_wallet_activity("wallet", Some(25), Some(40), true, 500).await.unwrap();
+        assert_eq!(activity.len(), 1);
+        assert_eq!(activity[0].token, "token-b");
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_excludes_majors() {
+        let db = MemoryDb::default();
+        let usdt = DEFAULT_MAJOR_MINTS[1];
+        let stable_swap = SwapEvent {
+            pubkey: usdt.to_string(),
+            is_quote_pair: true,
+            ..make_swap_event("stable-pool", 20, 1.0)
+        };
+        for event in [make_swap_event("pool-a", 10, 2.0), stable_swap] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+        for token in [make_token("token", "TUSD", "Token USD"), make_token(usdt, "USDT", "USDT")] {
+            db.insert_token(&token).await.unwrap();
+        }
+
+        let page = db
+            .get_top_tokens(10, 0, 0, None, None, None, false, Default::default(), SortOrder::Desc)
+            .await
+            .unwrap();
+        let tokens: Vec<&str> = page.tokens.iter().map(|t| t.pubkey.as_str()).collect();
+        assert_eq!(tokens, vec!["token"]);
+
+        let results = db.search_tokens("usd").await.unwrap();
+        let tokens: Vec<&str> = results.iter().map(|r| r.token.token.as_str()).collect();
+        assert_eq!(tokens, vec!["token"]);
+        assert_eq!(results[0].launch, TokenLaunch::default());
+
+        // the search results carry the launch of the token
+        db.set_token_launch_info("token", "raydium_launchpad", "curve", 5).await.unwrap();
+        let results = db.search_tokens("usd").await.unwrap();
+        assert_eq!(results[0].launch.launch_pool, "curve");
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_tokens_with_missing_metadata() {
+        let db = MemoryDb::default();
+        let no_supply = Token { supply: 0.0, ..make_token("no-supply", "NS", "No Supply") };
+        for token in [
+            make_token("complete", "OK", "Complete"),
+            make_token("no-name", "NN", ""),
+            make_token("no-symbol", "", "No Symbol"),
+            no_supply,
+        ] {
+            db.insert_token(&token).await.unwrap();
+        }
+
+        let mints = db.get_tokens_with_missing_metadata(None, 10).await.unwrap();
+        assert_eq!(mints, vec!["no-name", "no-supply", "no-symbol"]);
+        assert_eq!(db.get_tokens_with_missing_metadata(None, 1).await.unwrap(), vec!["no-name"]);
+        // the next page starts after the cursor
+        let mints = db.get_tokens_with_missing_metadata(Some("no-name"), 10).await.unwrap();
+        assert_eq!(mints, vec!["no-supply", "no-symbol"]);
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_token_daily_rollups() {
+        let db = MemoryDb::default();
+        let date = NaiveDate::from_ymd_opt(2025, 5, 23).unwrap();
+        let (start, end) = day_bounds(date);
+        for event in [
+            make_swap_event("pool-a", start + 10, 2.0),
+            make_swap_event("pool-b", start + 20, 5.0),
+            make_swap_event("pool-a", start + 30, 1.0),
+            SwapEvent { signers: vec!["other".to_string()], ..make_swap_event("pool-a", end, 9.0) },
+        ] {
+            db.insert_swap_event(&event).await.unwrap();
+        }
+
+        assert_eq!(db.aggregate_daily_token_rollups(date).await.unwrap(), Some(1));
+        let history = db.get_token_history("token", date, date).await.unwrap();
+        assert_eq!(history.len(), 1);
+        let rollup = &history[0];
+        assert_eq!(rollup.date, "2025-05-23");
+        assert_eq!((rollup.open, rollup.high, rollup.low, rollup.close), (2.0, 5.0, 1.0, 1.0));
+        assert_eq!((rollup.volume, rollup.turnover, rollup.trade_count), (3.0, 8.0, 3));
+
+        // aggregating the day again replaces its rollup
+        db.insert_swap_event(&make_swap_event("pool-a", start + 40, 3.0)).await.unwrap();
+        db.aggregate_daily_token_rollups(date).await.unwrap();
+        let history = db.get_token_history("token", date, date).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].close, history[0].trade_count), (3.0, 4));
+
+        let next = date.succ_opt().unwrap();
+        db.aggregate_daily_token_rollups(next).await.unwrap();
+        let history = db.get_token_history("token", date, next).await.unwrap();
+        let dates: Vec<&str> = history.iter().map(|rollup| rollup.date.as_str()).collect();
+        assert_eq!(dates, vec!["2025-05-23", "2025-05-24"]);
+        assert_eq!(history[1].unique_traders, 1);
+        assert!(db.get_token_history("other", date, next).await.unwrap().is_empty());
+    }
+}
@@ -1,12 +1,130 @@
 use crate::{
-    kv_store::make_kv_pool,
-    models::{events::NewPoolEvent, swap::Trade},
+    kv_store::{make_kv_pool, RedisConfig},
+    models::{
+        events::{CandlesClosedEvent, NewPoolEvent, SystemAlert, TokenGraduatedEvent},
+        swap::{Trade, TradeV2},
+    },
 };
 use anyhow::{Context, Result};
 use bb8_redis::{bb8, RedisConnectionManager};
-use std::env::var;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::LazyLock;
 use tracing::info;
 
+/// The schemas of the enveloped messages, with the version of their data
+pub const TRADE_SCHEMA: &str = "trade";
+pub const NEW_POOL_SCHEMA: &str = "new_pool";
+pub const SYSTEM_ALERT_SCHEMA: &str = "system_alert";
+pub const TOKEN_GRADUATED_SCHEMA: &str = "token_graduated";
+pub const LARGE_TRADE_SCHEMA: &str = "large_trade";
+pub const CANDLES_CLOSED_SCHEMA: &str = "candles_closed";
+
+/// The channel the ingestor publishes new pools on
+pub const NEW_POOLS_CHANNEL: &str = "new-pools";
+/// The channel the ingestor publishes token graduations on
+pub const GRADUATIONS_CHANNEL: &str = "token-graduations";
+/// The channel the ingestor publishes the trades moving a large share of the supply on
+pub const LARGE_TRADES_CHANNEL: &str = "large_trades";
+/// The channel the scheduler publishes the closed candlestick buckets on
+pub const CANDLES_CLOSED_CHANNEL: &str = "candles_closed";
+
+/// The most recent trades of a token kept for the clients resuming its room
+pub const RECENT_TRADES_MAX: usize = 500;
+/// How long the recent trades of a token are kept after its latest trade
+pub const RECENT_TRADES_TTL_SECS: u64 = 600;
+/// How long the sequence number of a token outlives its latest trade
+pub(crate) const TRADE_SEQ_TTL_SECS: u64 = 60 * 60 * 24 * 30;
+/// The sequence numbers a token may take per second without running into a sequence started
+/// later, the numbers stay below 2^53 for the JSON clients
+const TRADE_SEQS_PER_SEC: u64 = 1_000_000;
+
+/// The script numbering a trade in the trades of its token, keeping it in the recent trades
+/// and publishing it in a single step, so that every subscriber sees the same numbers in
+/// the same order. A sequence that expired starts again from the base of the current time, above
+/// every number it took before. The number is spliced in front of the fields of the payload object
+const PUBLISH_TRADE_SCRIPT: &str = r#"
+redis.call('SET', KEYS[1], ARGV[6], 'NX')
+local seq = redis.call('INCR', KEYS[1])
+redis.call('EXPIRE', KEYS[1], ARGV[5])
+local payload = '{"seq":' .. string.format('%d', seq) .. ',' .. string.sub(ARGV[2], 2)
+redis.call('RPUSH', KEYS[2], payload)
+redis.call('LTRIM', KEYS[2], -tonumber(ARGV[3]), -1)
+redis.call('EXPIRE', KEYS[2], ARGV[4])
+redis.call('PUBLISH', ARGV[1], payload)
+return seq
+"#;
+
+static PUBLISH_TRADE: LazyLock<redis::Script> =
+    LazyLock::new(|| redis::Script::new(PUBLISH_TRADE_SCRIPT));
+
+/// trade_seq_key returns the key of the sequence number of the trades of a token
+pub(crate) fn trade_seq_key(token: &str) -> String {
+    format!("solana:trade:seq:{}", token)
+}
+
+/// trade_seq_base returns the number a sequence started at `now` counts from
+pub(crate) fn trade_seq_base(now: u64) -> u64 {
+    now * TRADE_SEQS_PER_SEC
+}
+
+/// recent_trades_key returns the key of the recent sequenced payloads of a token
+pub(crate) fn recent_trades_key(token: &str) -> String {
+    format!("solana:trade:recent:{}", token)
+}
+
+/// A message tagged with the schema and version of its data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub schema: String,
+    pub version: u32,
+    pub data: T,
+}
+
+/// A published payload, enveloped or raw as published before the envelopes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message<T> {
+    Enveloped(Envelope<T>),
+    Raw(T),
+}
+
+/// encode_message serializes the data, in an envelope if asked
+pub fn encode_message<T: Serialize>(
+    envelope: bool,
+    schema: &str,
+    version: u32,
+    data: &T,
+) -> Result<String> {
+    let payload = if envelope {
+        serde_json::to_string(&Envelope { schema: schema.to_string(), version, data })
+    } else {
+        serde_json::to_string(data)
+    };
+    payload.with_context(|| format!("Failed to serialize {schema} message"))
+}
+
+/// decode_message parses the data of a payload, whether it is enveloped or raw
+pub fn decode_message<T: DeserializeOwned>(payload: &str) -> Result<T> {
+    let message = serde_json::from_str::<Message<T>>(payload)
+        .context("Failed to decode the message, neither enveloped nor raw")?;
+    Ok(match message {
+        Message::Enveloped(envelope) => envelope.data,
+        Message::Raw(data) => data,
+    })
+}
+
+/// decode_sequenced_message parses the data of a payload and the sequence number the queue
+/// published it with, None for a message published without one
+pub fn decode_sequenced_message<T: DeserializeOwned>(payload: &str) -> Result<(T, Option<u64>)> {
+    #[derive(Deserialize)]
+    struct Sequence {
+        seq: Option<u64>,
+    }
+    let Sequence { seq } =
+        serde_json::from_str(payload).context("Failed to decode the sequence number")?;
+    Ok((decode_message(payload)?, seq))
+}
+
 /// A boxed message queue
 pub type MessageQueue = Box<dyn MessageQueueTrait + Send + Sync>;
 
@@ -18,17 +136,42 @@ pub trait MessageQueueTrait {
 
     async fn publish_trade(&self, trade: &Trade) -> Result<()>;
 
+    /// Publish a trade with its dex, the raw format drops the fields unknown to raw consumers
+    async fn publish_trade_v2(&self, trade: &TradeV2) -> Result<()> {
+        self.publish_trade(&trade.trade).await
+    }
+
     /// Publish a new pool event to the message queue
     async fn publish_new_pool(&self, new_pool: &NewPoolEvent) -> Result<()>;
+
+    /// Publish an operational alert to the message queue
+    async fn publish_system_alert(&self, alert: &SystemAlert) -> Result<()>;
+
+    /// Publish the migration of a token from its bonding curve to the message queue
+    async fn publish_token_graduated(&self, graduation: &TokenGraduatedEvent) -> Result<()>;
+
+    /// Publish a trade moving a large share of the token supply, next to its regular publish
+    async fn publish_large_trade(&self, trade: &TradeV2) -> Result<()>;
+
+    /// Publish the candlestick buckets finalized by the scheduler to the message queue
+    async fn publish_candles_closed(&self, candles_closed: &CandlesClosedEvent) -> Result<()>;
 }
 
 // Redis implementation of MessageQueue
 #[derive(Debug, Clone)]
 pub struct RedisMessageQueue {
     pool: bb8::Pool<RedisConnectionManager>,
+    /// Publish the messages in envelopes, raw by default for the existing consumers
+    envelope: bool,
 }
 
 impl RedisMessageQueue {
+    /// Set whether the messages are published in envelopes
+    pub fn with_envelope(mut self, envelope: bool) -> Self {
+        self.envelope = envelope;
+        self
+    }
+
     async fn publish_message(&self, channel: &str, payload: &str) -> Result<()> {
         let mut conn = self.pool.get().await.context(format!(
             "Failed to get Redis connection: {:#?}",
@@ -43,6 +186,39 @@ impl RedisMessageQueue {
 
         Ok(())
     }
+
+    /// publish_trade_message numbers the payload of a trade of `token`, keeps it in the
+    /// recent trades of the token and publishes it
+    async fn publish_trade_message(&self, token: &str, payload: &str) -> Result<()> {
+        let mut conn = self.pool.get().await.context(format!(
+            "Failed to get Redis connection: {:#?}",
+            self.pool.state().statistics
+        ))?;
+        PUBLISH_TRADE
+            .key(trade_seq_key(token))
+            .key(recent_trades_key(token))
+            .arg("trade")
+            .arg(payload)
+            .arg(RECENT_TRADES_MAX)
+            .arg(RECENT_TRADES_TTL_SECS)
+            .arg(TRADE_SEQ_TTL_SECS)
+            .arg(trade_seq_base(chrono::Utc::now().timestamp().max(0) as u64))
+            .invoke_async::<u64>(&mut *conn)
+            .await
+            .context("Failed to publish trade to Redis")?;
+
+        Ok(())
+    }
+
+    /// ping checks that the Redis server of the queue answers
+    pub async fn ping(&self) -> Result<()> {
+        let mut conn = self.pool.get().await.context("Failed to get Redis connection")?;
+        redis::cmd("PING")
+            .query_async::<String>(&mut *conn)
+            .await
+            .context("Failed to ping Redis")?;
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -50,26 +226,62 @@ impl MessageQueueTrait for RedisMessageQueue {
     async fn new(url: &str) -> Result<Self> {
         let pool = make_kv_pool(url).await?;
         info!("Connected to Redis message queue at {}", url);
-        Ok(Self { pool })
+        Ok(Self { pool, envelope: false })
     }
 
     async fn publish_trade(&self, price_update: &Trade) -> Result<()> {
-        let payload =
-            serde_json::to_string(price_update).context("Failed to serialize price update")?;
-        let channel = "trade";
-        self.publish_message(channel, &payload).await?;
+        let payload = encode_message(self.envelope, TRADE_SCHEMA, 1, price_update)?;
+        self.publish_trade_message(&price_update.pubkey, &payload).await?;
+
+        Ok(())
+    }
+
+    async fn publish_trade_v2(&self, trade: &TradeV2) -> Result<()> {
+        let payload = if self.envelope {
+            encode_message(true, TRADE_SCHEMA, 2, trade)?
+        } else {
+            encode_message(false, TRADE_SCHEMA, 1, &trade.trade)?
+        };
+        self.publish_trade_message(&trade.trade.pubkey, &payload).await?;
 
         Ok(())
     }
 
     async fn publish_new_pool(&self, new_pool: &NewPoolEvent) -> Result<()> {
-        let payload =
-            serde_json::to_string(new_pool).context("Failed to serialize new pool event")?;
-        let channel = "new-pools";
+        let payload = encode_message(self.envelope, NEW_POOL_SCHEMA, 1, new_pool)?;
+        self.publish_message(NEW_POOLS_CHANNEL, &payload).await?;
+
+        Ok(())
+    }
+
+    async fn publish_system_alert(&self, alert: &SystemAlert) -> Result<()> {
+        let payload = encode_message(self.envelope, SYSTEM_ALERT_SCHEMA, 1, alert)?;
+        let channel = "system_alert";
         self.publish_message(channel, &payload).await?;
 
         Ok(())
     }
+
+    async fn publish_token_graduated(&self, graduation: &TokenGraduatedEvent) -> Result<()> {
+        let payload = encode_message(self.envelope, TOKEN_GRADUATED_SCHEMA, 1, graduation)?;
+        self.publish_message(GRADUATIONS_CHANNEL, &payload).await?;
+
+        Ok(())
+    }
+
+    async fn publish_large_trade(&self, trade: &TradeV2) -> Result<()> {
+        let payload = encode_message(self.envelope, LARGE_TRADE_SCHEMA, 1, trade)?;
+        self.publish_message(LARGE_TRADES_CHANNEL, &payload).await?;
+
+        Ok(())
+    }
+
+    async fn publish_candles_closed(&self, candles_closed: &CandlesClosedEvent) -> Result<()> {
+        let payload = encode_message(self.envelope, CANDLES_CLOSED_SCHEMA, 1, candles_closed)?;
+        self.publish_message(CANDLES_CLOSED_CHANNEL, &payload).await?;
+
+        Ok(())
+    }
 }
 
 pub async fn make_message_queue(redis_url: &str) -> Result<MessageQueue> {
@@ -77,7 +289,129 @@ pub async fn make_message_queue(redis_url: &str) -> Result<MessageQueue> {
     Ok(Box::new(message_queue))
 }
 
+/// make_message_queue_with_config creates the message queue of `config`, with its envelope
+pub async fn make_message_queue_with_config(config: &RedisConfig) -> Result<MessageQueue> {
+    let message_queue =
+        RedisMessageQueue::new(&config.url).await?.with_envelope(config.mq_envelope);
+    Ok(Box::new(message_queue))
+}
+
 pub async fn make_message_queue_from_env() -> Result<MessageQueue> {
-    let redis_url = var("REDIS_URL").expect("Expected REDIS_URL to be set");
-    make_message_queue(&redis_url).await
+    make_message_queue_with_config(&RedisConfig::from_env()?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::Dexes, test_utils::make_swap_event, SwapEvent};
+
+    fn make_trade() -> TradeV2 {
+        let event = SwapEvent {
+            market_cap: 1_500.0,
+            base_amount: 2.0,
+            quote_amount: 3.0,
+            swap_amount: 3.0,
+            slot: 1,
+            fee_amount: 0.01,
+            fee_mint: "wsol".to_string(),
+            ..make_swap_event("token", "pair", "signature", 1_700_000_000, 1.5)
+        };
+        let trade = Trade { symbol: "TOKEN".to_string(), decimals: 6, ..event.into() };
+        TradeV2 { trade, dex: Dexes::RaydiumAmmV4 }
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        let trade = make_trade();
+        let payload = encode_message(false, TRADE_SCHEMA, 1, &trade.trade).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(value["token"], "token");
+        assert!(value.get("schema").is_none());
+        assert!(value.get("dex").is_none());
+
+        let decoded: Trade = decode_message(&payload).unwrap();
+        assert_eq!(decoded.cursor(), trade.trade.cursor());
+        assert_eq!(decoded.fee_amount, 0.01);
+    }
+
+    #[test]
+    fn test_enveloped_round_trip() {
+        let trade = make_trade();
+        let payload = encode_message(true, TRADE_SCHEMA, 2, &trade).unwrap();
+        let envelope: Envelope<TradeV2> = serde_json::from_str(&payload).unwrap();
+        assert_eq!((envelope.schema.as_str(), envelope.version), ("trade", 2));
+        assert_eq!(envelope.data.dex, Dexes::RaydiumAmmV4);
+        assert!(payload.contains(r#""dex":"raydium_amm_v4""#));
+        assert_eq!(envelope.data.trade.cursor(), trade.trade.cursor());
+
+        let decoded: TradeV2 = decode_message(&payload).unwrap();
+        assert_eq!(decoded.dex, Dexes::RaydiumAmmV4);
+        assert_eq!((decoded.trade.symbol.as_str(), decoded.trade.decimals), ("TOKEN", 6));
+    }
+
+    #[test]
+    fn test_trade_without_token_details() {
+        // the trades published before the enrichment don't carry the token details
+        let mut value = serde_json::to_value(make_trade().trade).unwrap();
+        let object = value.as_object_mut().unwrap();
+        object.remove("symbol");
+        object.remove("decimals");
+        let decoded: Trade = decode_message(&value.to_string()).unwrap();
+        assert_eq!((decoded.symbol.as_str(), decoded.decimals), ("", 0));
+    }
+
+    #[test]
+    fn test_v1_consumer_parses_enveloped_data() {
+        let trade = make_trade();
+        let payload = encode_message(true, TRADE_SCHEMA, 2, &trade).unwrap();
+
+        // a consumer of the raw format, unwrapping the data, ignores the v2 fields
+        let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        let decoded: Trade = serde_json::from_value(value["data"].clone()).unwrap();
+        assert_eq!(decoded.cursor(), trade.trade.cursor());
+        assert_eq!(decoded.price, 1.5);
+
+        let decoded: Trade = decode_message(&payload).unwrap();
+        assert_eq!(decoded.signature, "signature");
+    }
+
+    #[test]
+    fn test_decode_sequenced_message() {
+        let trade = make_trade();
+        for envelope in [false, true] {
+            let payload = encode_message(envelope, TRADE_SCHEMA, 2, &trade).unwrap();
+            // the payload as the publish script numbers it
+            let sequenced = format!(r#"{{"seq":7,{}"#, &payload[1..]);
+            let (decoded, seq) = decode_sequenced_message::<Trade>(&sequenced).unwrap();
+            assert_eq!((decoded.cursor(), seq), (trade.trade.cursor(), Some(7)));
+            let (_, seq) = decode_sequenced_message::<Trade>(&payload).unwrap();
+            assert_eq!(seq, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_published_trades_are_numbered() {
+        let url = "redis://localhost:6379";
+        let message_queue = RedisMessageQueue::new(url).await.unwrap().with_envelope(true);
+        let kv_store = crate::KvStore::new(url).await.unwrap();
+        let mut trade = make_trade();
+        trade.trade.pubkey = format!("sequenced-test-{}", std::process::id());
+        let (start, _) = kv_store.get_recent_trades(&trade.trade.pubkey).await.unwrap();
+
+        message_queue.publish_trade_v2(&trade).await.unwrap();
+        let (first, _) = kv_store.get_recent_trades(&trade.trade.pubkey).await.unwrap();
+        assert!(first > start);
+        message_queue.publish_trade(&trade.trade).await.unwrap();
+        let (seq, recent) = kv_store.get_recent_trades(&trade.trade.pubkey).await.unwrap();
+        assert_eq!(seq, first + 1);
+        let seqs: Vec<u64> = recent.iter().map(|(seq, _)| *seq).collect();
+        assert_eq!(seqs[seqs.len() - 2..], [first, first + 1]);
+        assert_eq!(recent.last().unwrap().1.cursor(), trade.trade.cursor());
+    }
+
+    #[test]
+    fn test_decode_message_rejects_other_payloads() {
+        assert!(decode_message::<Trade>("testing message").is_err());
+        assert!(decode_message::<Trade>(r#"{"schema": "trade", "version": 2}"#).is_err());
+    }
 }
@@ -1,9 +0,0 @@
-pub mod candlesticks;
-pub mod events;
-pub mod swap;
-pub mod tokens;
-
-pub use candlesticks::Candlestick;
-pub use events::NewPoolEvent;
-pub use swap::SwapEvent;
-pub use tokens::{Token, TokenMetadata};
@@ -0,0 +1,133 @@
+-- the Postgres tables of the self-hosted deployments, `initialize` creates them when missing
+
+-- the swap events, the candlesticks of the recent buckets are aggregated from them
+CREATE TABLE IF NOT EXISTS swap_events
+(
+    pair TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    market_cap DOUBLE PRECISION NOT NULL,
+    base_amount DOUBLE PRECISION NOT NULL,
+    quote_amount DOUBLE PRECISION NOT NULL,
+    swap_amount DOUBLE PRECISION NOT NULL,
+    owner TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    signers TEXT[] NOT NULL,
+    slot BIGINT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    is_buy BOOLEAN NOT NULL,
+    is_pump BOOLEAN NOT NULL,
+    is_wash BOOLEAN NOT NULL DEFAULT false,
+    fee_amount DOUBLE PRECISION NOT NULL DEFAULT 0,
+    fee_mint TEXT NOT NULL DEFAULT '',
+    is_quote_pair BOOLEAN NOT NULL DEFAULT false,
+    pct_of_supply DOUBLE PRECISION NOT NULL DEFAULT 0,
+    fee_payer TEXT NOT NULL DEFAULT '',
+    -- a swap delivered again is skipped, the constraint also indexes the signatures
+    UNIQUE (signature, pair)
+);
+CREATE INDEX IF NOT EXISTS swap_events_by_pair_timestamp ON swap_events (pair, timestamp);
+CREATE INDEX IF NOT EXISTS swap_events_by_pubkey_timestamp ON swap_events (pubkey, timestamp);
+CREATE INDEX IF NOT EXISTS swap_events_by_timestamp ON swap_events (timestamp);
+CREATE INDEX IF NOT EXISTS swap_events_by_signers ON swap_events USING GIN (signers);
+
+-- the candlesticks aggregated by the scheduler, a bucket aggregated again is replaced
+CREATE TABLE IF NOT EXISTS candlesticks
+(
+    pair TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    "interval" INTEGER NOT NULL,
+    timestamp BIGINT NOT NULL,
+    open DOUBLE PRECISION NOT NULL,
+    high DOUBLE PRECISION NOT NULL,
+    low DOUBLE PRECISION NOT NULL,
+    close DOUBLE PRECISION NOT NULL,
+    volume DOUBLE PRECISION NOT NULL,
+    turnover DOUBLE PRECISION NOT NULL,
+    buy_volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+    sell_volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+    buy_turnover DOUBLE PRECISION NOT NULL DEFAULT 0,
+    sell_turnover DOUBLE PRECISION NOT NULL DEFAULT 0,
+    PRIMARY KEY (pubkey, pair, "interval", timestamp)
+);
+CREATE INDEX IF NOT EXISTS candlesticks_by_pair ON candlesticks (pair, "interval", timestamp);
+
+-- the mints and dex of the pairs, the earliest record of a pair is kept
+CREATE TABLE IF NOT EXISTS pairs
+(
+    pair TEXT PRIMARY KEY,
+    base_mint TEXT NOT NULL,
+    quote_mint TEXT NOT NULL,
+    dex TEXT NOT NULL,
+    first_seen BIGINT NOT NULL
+);
+
+-- the metadata of the tokens, the latest retrieval is kept
+CREATE TABLE IF NOT EXISTS tokens
+(
+    token TEXT PRIMARY KEY,
+    retrieval_timestamp BIGINT NOT NULL,
+    is_nft BOOLEAN NOT NULL,
+    update_authority TEXT NOT NULL,
+    name TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    decimals SMALLINT NOT NULL,
+    supply DOUBLE PRECISION NOT NULL,
+    uri TEXT NOT NULL,
+    seller_fee_basis_points INTEGER NOT NULL,
+    primary_sale_happened BOOLEAN NOT NULL,
+    is_mutable BOOLEAN NOT NULL,
+    launchpad TEXT NOT NULL DEFAULT '',
+    graduated_at BIGINT NOT NULL DEFAULT 0,
+    graduation_pool TEXT NOT NULL DEFAULT '',
+    launch_dex TEXT NOT NULL DEFAULT '',
+    launch_pool TEXT NOT NULL DEFAULT '',
+    launch_timestamp BIGINT NOT NULL DEFAULT 0,
+    verified BOOLEAN NOT NULL DEFAULT false
+);
+
+-- the per-token trading of each UTC day, a day aggregated again is replaced
+CREATE TABLE IF NOT EXISTS token_daily_rollups
+(
+    pubkey TEXT NOT NULL,
+    date DATE NOT NULL,
+    open DOUBLE PRECISION NOT NULL,
+    high DOUBLE PRECISION NOT NULL,
+    low DOUBLE PRECISION NOT NULL,
+    close DOUBLE PRECISION NOT NULL,
+    volume DOUBLE PRECISION NOT NULL,
+    turnover DOUBLE PRECISION NOT NULL,
+    trade_count BIGINT NOT NULL,
+    unique_traders BIGINT NOT NULL,
+    PRIMARY KEY (pubkey, date)
+);
+
+-- the labels of the known wallets imported by the analysts, the latest label is kept
+CREATE TABLE IF NOT EXISTS wallet_labels
+(
+    address TEXT PRIMARY KEY,
+    label TEXT NOT NULL,
+    category TEXT NOT NULL,
+    added_at BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS wallet_labels_by_category ON wallet_labels (category);
+
+-- the reference prices of the synthetic pairs, e.g. the Binance SOL/USD trades sampled once
+-- per second, read by the candlesticks of these pairs
+CREATE TABLE IF NOT EXISTS reference_prices
+(
+    pair TEXT NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    timestamp BIGINT NOT NULL,
+    PRIMARY KEY (pair, timestamp)
+);
+
+-- migrations for existing deployments
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS pct_of_supply DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE swap_events ADD COLUMN IF NOT EXISTS fee_payer TEXT NOT NULL DEFAULT '';
+-- the trades of a wallet are those it traded or relayed as the fee payer
+CREATE INDEX IF NOT EXISTS swap_events_by_owner_timestamp ON swap_events (owner, timestamp);
+CREATE INDEX IF NOT EXISTS swap_events_by_fee_payer_timestamp ON swap_events (fee_payer, timestamp);
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launch_dex TEXT NOT NULL DEFAULT '';
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launch_pool TEXT NOT NULL DEFAULT '';
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS launch_timestamp BIGINT NOT NULL DEFAULT 0;
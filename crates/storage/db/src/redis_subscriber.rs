@@ -1,8 +1,9 @@
+use crate::kv_store::RedisConfig;
 use anyhow::Result;
 use async_stream::stream;
 use futures::{Stream, StreamExt};
 use redis::{AsyncCommands, Msg};
-use std::{env, pin::Pin};
+use std::pin::Pin;
 use tracing::info;
 
 #[derive(Clone)]
@@ -69,8 +70,7 @@ pub async fn make_redis_subscriber(redis_url: &str) -> Result<RedisSubscriber> {
 }
 
 pub async fn make_redis_subscriber_from_env() -> Result<RedisSubscriber> {
-    let redis_url = env::var("REDIS_URL").expect("Expected REDIS_URL to be set");
-    make_redis_subscriber(&redis_url).await
+    make_redis_subscriber(&RedisConfig::from_env()?.url).await
 }
 
 #[cfg(test)]
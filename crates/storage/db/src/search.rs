@@ -0,0 +1,224 @@
+use crate::models::tokens::{
+    is_major_mint, MatchReason, TokenLaunch, TokenSearch, TokenSearchResult,
+};
+
+/// The score of verified tokens is multiplied by this
+pub const VERIFIED_MULTIPLIER: f64 = 2.0;
+/// The fuzzy pass runs when the strict pass matches fewer tokens than this
+pub const FUZZY_FALLBACK_THRESHOLD: usize = 3;
+/// Queries shorter than this are never fuzzy matched, every short symbol is one edit away
+const MIN_FUZZY_QUERY_CHARS: usize = 3;
+
+/// normalize_query trims the query and collapses inner whitespace
+pub fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// edit_distance returns the Levenshtein distance of two strings, in chars
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// match_reason returns how a token matches a normalized query, None if it doesn't
+pub fn match_reason(query: &str, token: &TokenSearch) -> Option<MatchReason> {
+    if token.token == query {
+        return Some(MatchReason::Mint);
+    }
+    let query = query.to_lowercase();
+    let symbol = token.symbol.trim().to_lowercase();
+    let name = token.name.trim().to_lowercase();
+    if symbol == query {
+        Some(MatchReason::SymbolExact)
+    } else if symbol.starts_with(&query) {
+        Some(MatchReason::SymbolPrefix)
+    } else if name.starts_with(&query) {
+        Some(MatchReason::NamePrefix)
+    } else if symbol.contains(&query) || name.contains(&query) {
+        Some(MatchReason::Substring)
+    } else if query.chars().count() >= MIN_FUZZY_QUERY_CHARS
+        && (edit_distance(&symbol, &query) <= 1
+            || name.split_whitespace().any(|word| edit_distance(word, &query) <= 1))
+    {
+        Some(MatchReason::Fuzzy)
+    } else {
+        None
+    }
+}
+
+/// score ranks a match by its kind, the turnover of the token and whether it is verified
+pub fn score(token: &TokenSearch, reason: MatchReason) -> f64 {
+    let score = reason.boost() + token.turnover_24h.max(0.0).ln_1p();
+    if token.verified {
+        score * VERIFIED_MULTIPLIER
+    } else {
+        score
+    }
+}
+
+/// rank_search_results scores the candidates against a normalized query, best first,
+/// an exact mint address match is returned alone
+pub fn rank_search_results(
+    query: &str,
+    candidates: Vec<TokenSearch>,
+    limit: usize,
+) -> Vec<TokenSearchResult> {
+    let mut results: Vec<TokenSearchResult> = vec![];
+    for token in candidates {
+        // the stablecoins quote the pairs, they aren't traded tokens
+        if is_major_mint(&token.token) || results.iter().any(|r| r.token.token == token.token) {
+            continue;
+        }
+        let Some(reason) = match_reason(query, &token) else {
+            continue;
+        };
+        let score = score(&token, reason);
+        let result = TokenSearchResult {
+            token,
+            score: Some(score),
+            match_reason: Some(reason),
+            launch: TokenLaunch::default(),
+        };
+        if reason == MatchReason::Mint {
+            return vec![result];
+        }
+        results.push(result);
+    }
+    results.sort_by(|a, b| b.score.unwrap_or_default().total_cmp(&a.score.unwrap_or_default()));
+    results.truncate(limit);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_token(token: &str, symbol: &str, name: &str, turnover_24h: f64) -> TokenSearch {
+        TokenSearch {
+            token: token.to_string(),
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 6,
+            supply: 0.0,
+            latest_price: 0.0,
+            price_24h: 0.0,
+            tx_count_24h: 0,
+            volume_24h: 0.0,
+            turnover_24h,
+            verified: false,
+        }
+    }
+
+    fn ranked_tokens(results: &[TokenSearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.token.token.as_str()).collect()
+    }
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(normalize_query("bonk "), "bonk");
+        assert_eq!(normalize_query("  dog \t wif hat "), "dog wif hat");
+        assert_eq!(normalize_query("   "), "");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("bonk", "bonk"), 0);
+        assert_eq!(edit_distance("bonk", "bnok"), 2);
+        assert_eq!(edit_distance("bonk", "bonc"), 1);
+        assert_eq!(edit_distance("bonk", "bonks"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_exact_symbol_outranks_substring_turnover() {
+        let candidates = vec![
+            make_token("scam", "BONKAI", "Bonk AI", 10_000_000.0),
+            make_token("bonk", "Bonk", "Bonk", 50_000.0),
+        ];
+        let results = rank_search_results(&normalize_query("bonk "), candidates, 10);
+        assert_eq!(ranked_tokens(&results), vec!["bonk", "scam"]);
+        assert_eq!(results[0].match_reason, Some(MatchReason::SymbolExact));
+        assert_eq!(results[1].match_reason, Some(MatchReason::SymbolPrefix));
+    }
+
+    #[test]
+    fn test_typo_recovery() {
+        let candidates = vec![
+            make_token("bonk", "BONK", "Bonk", 50_000.0),
+            make_token("wif", "WIF", "dogwifhat", 80_000.0),
+        ];
+        let results = rank_search_results("bonc", candidates.clone(), 10);
+        assert_eq!(ranked_tokens(&results), vec!["bonk"]);
+        assert_eq!(results[0].match_reason, Some(MatchReason::Fuzzy));
+
+        // short queries are not fuzzy matched
+        assert!(rank_search_results("wi", candidates, 10)
+            .iter()
+            .all(|result| result.match_reason != Some(MatchReason::Fuzzy)));
+    }
+
+    #[test]
+    fn test_verified_boost() {
+        let candidates = vec![
+            make_token("fake", "PEPE", "Pepe", 5_000_000.0),
+            TokenSearch { verified: true, ..make_token("real", "PEPE", "Pepe", 100_000.0) },
+        ];
+        let results = rank_search_results("pepe", candidates, 10);
+        assert_eq!(ranked_tokens(&results), vec!["real", "fake"]);
+        let (real, fake) = (results[0].score.unwrap(), results[1].score.unwrap());
+        assert_eq!(
+            real,
+            score(&make_token("real", "PEPE", "Pepe", 100_000.0), MatchReason::SymbolExact)
+                * VERIFIED_MULTIPLIER
+        );
+        assert!(real > fake);
+    }
+
+    #[test]
+    fn test_mint_short_circuits() {
+        let mint = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+        let candidates = vec![
+            make_token("other", "BONK", "Bonk", 10_000_000.0),
+            make_token(mint, "Bonk", "Bonk", 50_000.0),
+            make_token("another", "BONKAI", "Bonk AI", 0.0),
+        ];
+        let results = rank_search_results(mint, candidates, 10);
+        assert_eq!(ranked_tokens(&results), vec![mint]);
+        assert_eq!(results[0].match_reason, Some(MatchReason::Mint));
+    }
+
+    #[test]
+    fn test_rank_search_results_limit_and_dedup() {
+        let candidates = vec![
+            make_token("a", "DOGE", "Doge", 3.0),
+            make_token("a", "DOGE", "Doge", 3.0),
+            make_token("b", "DOGE2", "Doge 2", 2.0),
+            make_token("c", "XYZ", "Unrelated", 1_000.0),
+        ];
+        let results = rank_search_results("doge", candidates, 1);
+        assert_eq!(ranked_tokens(&results), vec!["a"]);
+    }
+
+    #[test]
+    fn test_majors_are_excluded() {
+        use crate::models::tokens::{USDC_MINT, USDT_MINT};
+        let candidates = vec![
+            make_token(USDT_MINT, "USDT", "USDT", 50_000_000.0),
+            make_token(USDC_MINT, "USDC", "USD Coin", 90_000_000.0),
+            make_token("token", "TUSD", "Token USD", 10.0),
+        ];
+        let results = rank_search_results("usd", candidates.clone(), 10);
+        assert_eq!(ranked_tokens(&results), vec!["token"]);
+        // nor are they returned for their mint
+        assert!(rank_search_results(USDC_MINT, candidates, 10).is_empty());
+    }
+}
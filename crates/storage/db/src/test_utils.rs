@@ -0,0 +1,87 @@
+//! Fixtures for the tests of the crates on top of the storage, with the `test-utils` feature
+use crate::{
+    db::{Database, DatabaseTrait},
+    kv_store::KvStore,
+    memory::MemoryDb,
+    models::{swap::SwapEvent, Token},
+};
+use std::sync::Arc;
+
+/// make_swap_event returns a buy of 1000 `mint` in `pair` at `price`, by the `owner` signer
+pub fn make_swap_event(
+    mint: &str,
+    pair: &str,
+    signature: &str,
+    timestamp: u64,
+    price: f64,
+) -> SwapEvent {
+    SwapEvent {
+        pair: pair.to_string(),
+        pubkey: mint.to_string(),
+        price,
+        market_cap: price * 1_000_000_000.0,
+        timestamp,
+        slot: timestamp,
+        base_amount: 1_000.0,
+        quote_amount: 1.0,
+        swap_amount: price * 1_000.0,
+        owner: "owner".to_string(),
+        signature: signature.to_string(),
+        signers: vec!["owner".to_string()],
+        is_pump: false,
+        is_buy: true,
+        is_wash: false,
+        fee_amount: 0.0,
+        fee_mint: String::new(),
+        is_quote_pair: false,
+        pct_of_supply: 0.0,
+        fee_payer: String::new(),
+    }
+}
+
+/// make_token returns the metadata of a fungible token with 6 decimals
+pub fn make_token(mint: &str, symbol: &str, name: &str) -> Token {
+    Token {
+        retrieval_timestamp: 0,
+        is_nft: false,
+        token: mint.to_string(),
+        update_authority: String::new(),
+        name: name.to_string(),
+        symbol: symbol.to_string(),
+        decimals: 6,
+        supply: 1_000_000_000.0,
+        uri: String::new(),
+        seller_fee_basis_points: 0,
+        primary_sale_happened: false,
+        is_mutable: false,
+        launchpad: String::new(),
+        graduated_at: 0,
+        graduation_pool: String::new(),
+        launch_dex: None,
+        launch_pool: String::new(),
+        launch_timestamp: 0,
+    }
+}
+
+/// seeded_db returns an in-memory database holding the swap events and tokens,
+/// clones of it share the same state
+pub async fn seeded_db(swap_events: &[SwapEvent], tokens: &[Token]) -> MemoryDb {
+    let db = MemoryDb::default();
+    for swap_event in swap_events {
+        db.insert_swap_event(swap_event).await.expect("Failed to seed swap event");
+    }
+    for token in tokens {
+        db.insert_token(token).await.expect("Failed to seed token");
+    }
+    db
+}
+
+/// seeded_storages returns an in-memory database holding the swap events and tokens,
+/// and an empty in-memory kv store
+pub async fn seeded_storages(
+    swap_events: &[SwapEvent],
+    tokens: &[Token],
+) -> (Arc<Database>, Arc<KvStore>) {
+    let db: Database = Box::new(seeded_db(swap_events, tokens).await);
+    (Arc::new(db), Arc::new(KvStore::in_memory()))
+}
@@ -0,0 +1,708 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{collections::BTreeMap, str::FromStr};
+use strum::{AsRefStr, Display, EnumProperty, EnumString, IntoStaticStr};
+
+#[derive(
+    Debug,
+    Clone,
+    Eq,
+    PartialEq,
+    AsRefStr,
+    IntoStaticStr,
+    Display,
+    EnumProperty,
+    Serialize,
+    EnumString,
+    utoipa::ToSchema
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CandlestickInterval {
+    #[strum(serialize = "1s", props(seconds = 1))]
+    #[schema(rename = "1s")]
+    OneSecond,
+    #[strum(serialize = "5s", props(seconds = 5))]
+    #[schema(rename = "5s")]
+    FiveSeconds,
+    #[strum(serialize = "15s", props(seconds = 15))]
+    #[schema(rename = "15s")]
+    FifteenSeconds,
+    #[strum(serialize = "30s", props(seconds = 30))]
+    #[schema(rename = "30s")]
+    ThirtySeconds,
+    #[strum(serialize = "1m", props(seconds = 60))]
+    #[schema(rename = "1m")]
+    OneMinute,
+    #[strum(serialize = "5m", props(seconds = 300))]
+    #[schema(rename = "5m")]
+    FiveMinutes,
+    #[strum(serialize = "15m", props(seconds = 900))]
+    #[schema(rename = "15m")]
+    FifteenMinutes,
+    #[strum(serialize = "30m", props(seconds = 1800))]
+    #[schema(rename = "30m")]
+    ThirtyMinutes,
+    #[strum(serialize = "1h", props(seconds = 3600))]
+    #[schema(rename = "1h")]
+    OneHour,
+    #[strum(serialize = "4h", props(seconds = 14400))]
+    #[schema(rename = "4h")]
+    FourHours,
+    #[strum(serialize = "1d", props(seconds = 86400))]
+    #[schema(rename = "1d")]
+    OneDay,
+}
+
+/// Every interval, finest first
+pub const CANDLESTICK_INTERVALS: [CandlestickInterval; 11] = [
+    CandlestickInterval::OneSecond,
+    CandlestickInterval::FiveSeconds,
+    CandlestickInterval::FifteenSeconds,
+    CandlestickInterval::ThirtySeconds,
+    CandlestickInterval::OneMinute,
+    CandlestickInterval::FiveMinutes,
+    CandlestickInterval::FifteenMinutes,
+    CandlestickInterval::ThirtyMinutes,
+    CandlestickInterval::OneHour,
+    CandlestickInterval::FourHours,
+    CandlestickInterval::OneDay,
+];
+
+/// The pair of the token-level candlesticks, aggregated across all the pairs of a token
+pub const TOKEN_CANDLESTICK_PAIR: &str = "";
+
+/// The synthetic pair of the SOL/USD reference price, sourced from the Binance trades
+pub const SOL_USD_PAIR: &str = "SOLUSD";
+
+/// is_reference_pair returns true if the candlesticks of the pair are built from the
+/// reference prices instead of the swap events
+pub fn is_reference_pair(pair: &str) -> bool {
+    pair == SOL_USD_PAIR
+}
+
+/// The number of candlesticks returned when no limit is given
+pub const DEFAULT_CANDLESTICK_LIMIT: usize = 200;
+
+/// The intervals stored in the candlesticks table, finest first
+pub const STORED_CANDLESTICK_INTERVALS: [CandlestickInterval; 7] = [
+    CandlestickInterval::OneMinute,
+    CandlestickInterval::FiveMinutes,
+    CandlestickInterval::FifteenMinutes,
+    CandlestickInterval::ThirtyMinutes,
+    CandlestickInterval::OneHour,
+    CandlestickInterval::FourHours,
+    CandlestickInterval::OneDay,
+];
+
+impl CandlestickInterval {
+    /// Returns the interval in seconds
+    pub fn get_seconds(&self) -> i64 {
+        self.get_int("seconds").expect("Failed to get seconds")
+    }
+
+    /// Returns the interval in candlestick, the largest stored interval dividing this one,
+    /// 1 for the intervals finer than a minute which are only built from swap events
+    pub fn get_candlestick_interval(&self) -> i64 {
+        let seconds = self.get_seconds();
+        STORED_CANDLESTICK_INTERVALS
+            .iter()
+            .map(|interval| interval.get_seconds())
+            .filter(|stored| seconds % stored == 0)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Returns the start of the bucket of this interval containing `timestamp`
+    pub fn bucket_start(&self, timestamp: u64) -> u64 {
+        let seconds = self.get_seconds() as u64;
+        timestamp / seconds * seconds
+    }
+
+    /// Returns the stored interval the candlesticks of this one are derived from,
+    /// None for the intervals aggregated from swap events
+    pub fn get_source_interval(&self) -> Option<CandlestickInterval> {
+        match self {
+            CandlestickInterval::FiveMinutes
+            | CandlestickInterval::FifteenMinutes
+            | CandlestickInterval::ThirtyMinutes => Some(CandlestickInterval::OneMinute),
+            CandlestickInterval::FourHours => Some(CandlestickInterval::OneHour),
+            _ => None,
+        }
+    }
+
+    /// Returns true if candlesticks of this interval can be built from `source` candlesticks,
+    /// i.e. the source is finer and divides it
+    pub fn can_derive_from(&self, source: &CandlestickInterval) -> bool {
+        let (seconds, source_seconds) = (self.get_seconds(), source.get_seconds());
+        seconds > source_seconds && seconds % source_seconds == 0
+    }
+
+    /// Returns the number of buckets of this interval the range `[time_from, time_to)` spans
+    pub fn bucket_count(&self, time_from: i64, time_to: i64) -> u64 {
+        let seconds = self.get_seconds();
+        let start = time_from.div_euclid(seconds);
+        let end = (time_to.max(time_from) + seconds - 1).div_euclid(seconds);
+        (end - start) as u64
+    }
+
+    /// Returns the finest interval, this one or coarser, spanning the range in at most
+    /// `max_buckets` buckets, None when even a day is too fine
+    pub fn fit(&self, time_from: i64, time_to: i64, max_buckets: u64) -> Option<Self> {
+        CANDLESTICK_INTERVALS.into_iter().find(|interval| {
+            interval.get_seconds() >= self.get_seconds()
+                && interval.bucket_count(time_from, time_to) <= max_buckets
+        })
+    }
+
+    /// Returns `time_from`, or when omitted the start of the bucket `limit` buckets before
+    /// `time_to` or `now`, the latest `limit` candlesticks never start earlier, so that a query
+    /// doesn't scan the whole history for them
+    pub fn bounded_time_from(
+        &self,
+        limit: usize,
+        time_from: Option<i32>,
+        time_to: Option<i32>,
+        now: i64,
+    ) -> i32 {
+        if let Some(time_from) = time_from {
+            return time_from;
+        }
+        let end = time_to.map_or(now, i64::from);
+        let start = end.saturating_sub((limit as i64).saturating_mul(self.get_seconds())).max(0);
+        self.bucket_start(start as u64) as i32
+    }
+}
+
+impl<'de> Deserialize<'de> for CandlestickInterval {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        CandlestickInterval::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub struct Candlestick {
+    #[serde(rename = "t", alias = "timestamp")]
+    #[schema(rename = "t")]
+    pub timestamp: u64,
+    #[serde(rename = "o", alias = "open")]
+    #[schema(rename = "o")]
+    pub open: f64,
+    #[serde(rename = "h", alias = "high")]
+    #[schema(rename = "h")]
+    pub high: f64,
+    #[serde(rename = "l", alias = "low")]
+    #[schema(rename = "l")]
+    pub low: f64,
+    #[serde(rename = "c", alias = "close")]
+    #[schema(rename = "c")]
+    pub close: f64,
+    #[serde(rename = "v", alias = "volume")]
+    #[schema(rename = "v")]
+    pub volume: f64,
+    #[serde(rename = "vc", alias = "turnover")]
+    pub turnover: f64,
+    /// The part of the volume bought
+    #[serde(rename = "bv", alias = "buy_volume", default)]
+    pub buy_volume: f64,
+    /// The part of the volume sold
+    #[serde(rename = "sv", alias = "sell_volume", default)]
+    pub sell_volume: f64,
+    /// The part of the turnover bought
+    #[serde(rename = "bvc", alias = "buy_turnover", default)]
+    pub buy_turnover: f64,
+    /// The part of the turnover sold
+    #[serde(rename = "svc", alias = "sell_turnover", default)]
+    pub sell_turnover: f64,
+}
+
+/// A reference price of a synthetic pair, e.g. a Binance SOL/USD trade sampled once per second
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReferencePrice {
+    pub pair: String,
+    pub price: f64,
+    /// unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+/// reference_candlesticks buckets the finite reference prices by interval, the volumes are 0,
+/// returns the candlesticks in ascending order
+pub fn reference_candlesticks(
+    prices: impl IntoIterator<Item = ReferencePrice>,
+    interval: &CandlestickInterval,
+) -> Vec<Candlestick> {
+    let mut prices = prices.into_iter().filter(|price| price.price.is_finite()).collect::<Vec<_>>();
+    prices.sort_by_key(|price| price.timestamp);
+    let mut candlesticks: BTreeMap<u64, Candlestick> = BTreeMap::new();
+    for ReferencePrice { price, timestamp, .. } in prices {
+        let bucket = interval.bucket_start(timestamp);
+        let candlestick = candlesticks.entry(bucket).or_insert(Candlestick {
+            timestamp: bucket,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0.0,
+            turnover: 0.0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            buy_turnover: 0.0,
+            sell_turnover: 0.0,
+        });
+        candlestick.high = candlestick.high.max(price);
+        candlestick.low = candlestick.low.min(price);
+        candlestick.close = price;
+    }
+    candlesticks.into_values().collect()
+}
+
+/// The candlestick of the bucket still open
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct CurrentCandlestick {
+    #[serde(flatten)]
+    pub candlestick: Candlestick,
+    /// True when nothing traded in the bucket yet, the candlestick is flat at the previous close
+    #[serde(default)]
+    pub synthetic: bool,
+}
+
+/// The candlesticks of the current bucket and of the previous, closed one,
+/// for the clients to refresh the latest bar and reconcile a rollover
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct LatestCandlestick {
+    pub current: Option<CurrentCandlestick>,
+    pub previous: Option<Candlestick>,
+}
+
+impl LatestCandlestick {
+    /// from_buckets returns the latest candlestick of the `interval` candlesticks of the bucket
+    /// containing `at` and of the one before, the other candlesticks are ignored
+    pub fn from_buckets(
+        candlesticks: Vec<Candlestick>,
+        interval: &CandlestickInterval,
+        at: u64,
+    ) -> Self {
+        let current_bucket = interval.bucket_start(at);
+        let previous_bucket = current_bucket.saturating_sub(interval.get_seconds() as u64);
+        let mut latest = Self::default();
+        for candlestick in candlesticks {
+            if candlestick.timestamp == current_bucket {
+                latest.current = Some(CurrentCandlestick { candlestick, synthetic: false });
+            } else if candlestick.timestamp == previous_bucket {
+                latest.previous = Some(candlestick);
+            }
+        }
+        if latest.current.is_none() {
+            latest.current = latest.previous.as_ref().map(|previous| CurrentCandlestick {
+                candlestick: Candlestick {
+                    timestamp: current_bucket,
+                    open: previous.close,
+                    high: previous.close,
+                    low: previous.close,
+                    close: previous.close,
+                    volume: 0.0,
+                    turnover: 0.0,
+                    buy_volume: 0.0,
+                    sell_volume: 0.0,
+                    buy_turnover: 0.0,
+                    sell_turnover: 0.0,
+                },
+                synthetic: true,
+            });
+        }
+        latest
+    }
+}
+
+/// merge_candlesticks merges the candlesticks built from the swap events with the ones rolled up
+/// from the stored candlesticks by their `interval` bucket, keeping the swap events one of a
+/// bucket in both, returns the latest `size` candlesticks in ascending order
+pub fn merge_candlesticks(
+    from_swap_events: Vec<Candlestick>,
+    from_candlesticks: Vec<Candlestick>,
+    interval: &CandlestickInterval,
+    size: usize,
+) -> Vec<Candlestick> {
+    let mut buckets: BTreeMap<u64, Candlestick> = BTreeMap::new();
+    for candlestick in from_candlesticks.into_iter().chain(from_swap_events) {
+        let timestamp = interval.bucket_start(candlestick.timestamp);
+        buckets.insert(timestamp, Candlestick { timestamp, ..candlestick });
+    }
+    let skip = buckets.len().saturating_sub(size);
+    buckets.into_values().skip(skip).collect()
+}
+
+/// The currency the prices of candlesticks and trades are quoted in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Denomination {
+    #[default]
+    Usd,
+    Sol,
+}
+
+/// QuotePrices holds the closes of the quote candlesticks by the start of their bucket
+#[derive(Debug, Clone)]
+pub struct QuotePrices {
+    interval: CandlestickInterval,
+    closes: BTreeMap<u64, f64>,
+}
+
+impl QuotePrices {
+    /// new indexes the quote candlesticks of `interval`, skipping the unusable closes
+    pub fn new(quote: &[Candlestick], interval: &CandlestickInterval) -> Self {
+        let closes = quote
+            .iter()
+            .filter(|c| c.close.is_finite() && c.close > 0.0)
+            .map(|c| (interval.bucket_start(c.timestamp), c.close))
+            .collect();
+        Self { interval: interval.clone(), closes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.closes.is_empty()
+    }
+
+    /// at returns the quote price of the bucket of `timestamp`, the last known close is carried
+    /// forward over the buckets without quote trades, the first one backward before it
+    pub fn at(&self, timestamp: u64) -> Option<f64> {
+        let bucket = self.interval.bucket_start(timestamp);
+        self.closes
+            .range(..=bucket)
+            .next_back()
+            .or_else(|| self.closes.iter().next())
+            .map(|(_, close)| *close)
+    }
+
+    /// nearest returns the quote price of the bucket closest to the one of `timestamp`
+    pub fn nearest(&self, timestamp: u64) -> Option<f64> {
+        let bucket = self.interval.bucket_start(timestamp);
+        let before = self.closes.range(..=bucket).next_back();
+        let after = self.closes.range(bucket..).next();
+        match (before, after) {
+            (Some((b, before)), Some((a, after))) => {
+                Some(if bucket - b <= a - bucket { *before } else { *after })
+            }
+            (Some((_, close)), None) | (None, Some((_, close))) => Some(*close),
+            (None, None) => None,
+        }
+    }
+
+    /// denominate divides the prices of candlesticks by the quote price of their bucket,
+    /// the volume and the USD turnover are left as is
+    pub fn denominate(&self, candlesticks: Vec<Candlestick>) -> Option<Vec<Candlestick>> {
+        candlesticks
+            .into_iter()
+            .map(|c| {
+                let quote = self.at(c.timestamp)?;
+                Some(Candlestick {
+                    open: c.open / quote,
+                    high: c.high / quote,
+                    low: c.low / quote,
+                    close: c.close / quote,
+                    ..c
+                })
+            })
+            .collect()
+    }
+}
+
+/// How the high and low of candlesticks built from swap events are clamped against
+/// fat-finger trades. A high above `band_multiplier` times the `quantile` price of its bucket
+/// is replaced by that price, a low below the `1 - quantile` price divided by `band_multiplier`
+/// likewise. Buckets with fewer than `min_trades` trades are left alone, their quantiles are
+/// the trades themselves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutlierPolicy {
+    pub enabled: bool,
+    pub quantile: f64,
+    pub band_multiplier: f64,
+    pub min_trades: u64,
+}
+
+impl Default for OutlierPolicy {
+    fn default() -> Self {
+        Self { enabled: true, quantile: 0.995, band_multiplier: 20.0, min_trades: 10 }
+    }
+}
+
+impl OutlierPolicy {
+    /// with_clamp returns the policy enabled or disabled by a `clamp` query parameter,
+    /// unchanged if it is None
+    pub fn with_clamp(&self, clamp: Option<bool>) -> Self {
+        Self { enabled: clamp.unwrap_or(self.enabled), ..self.clone() }
+    }
+
+    /// high_low_sql returns the `high` and `low` columns of a swap events aggregation
+    pub fn high_low_sql(&self) -> String {
+        if !self.enabled {
+            return "max(price) AS high, min(price) AS low".to_string();
+        }
+        let upper = format!("quantileExactWeighted({:.6})(price, 1)", self.quantile);
+        let lower = format!("quantileExactWeighted({:.6})(price, 1)", 1.0 - self.quantile);
+        let (band, min_trades) = (self.band_multiplier, self.min_trades);
+        format!(
+            "if(count() >= {min_trades} AND max(price) > {upper} * {band}, {upper}, max(price)) \
+             AS high, \
+             if(count() >= {min_trades} AND min(price) < {lower} / {band}, {lower}, min(price)) \
+             AS low"
+        )
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct CandlestickQuery {
+    pub mint: String,
+    pub interval: CandlestickInterval,
+    pub limit: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_count() {
+        let minute = CandlestickInterval::OneMinute;
+        assert_eq!(minute.bucket_count(0, 3600), 60);
+        // partial buckets at both ends count
+        assert_eq!(minute.bucket_count(30, 3630), 61);
+        assert_eq!(minute.bucket_count(60, 60), 0);
+        assert_eq!(minute.bucket_count(120, 60), 0);
+        // a year of minutes
+        assert_eq!(minute.bucket_count(0, 365 * 86400), 525_600);
+    }
+
+    #[test]
+    fn test_fit_interval() {
+        let year = 365 * 86400;
+        let minute = CandlestickInterval::OneMinute;
+        assert_eq!(minute.fit(0, 3600, 5000), Some(CandlestickInterval::OneMinute));
+        // a week of 1m is 10080 buckets, 5m is 2016
+        assert_eq!(minute.fit(0, 7 * 86400, 5000), Some(CandlestickInterval::FiveMinutes));
+        assert_eq!(minute.fit(0, year, 5000), Some(CandlestickInterval::FourHours));
+        // never finer than requested
+        assert_eq!(
+            CandlestickInterval::OneHour.fit(0, 3600, 5000),
+            Some(CandlestickInterval::OneHour)
+        );
+        assert_eq!(minute.fit(0, 20 * year, 5000), None);
+    }
+
+    #[test]
+    fn test_bounded_time_from() {
+        let minute = CandlestickInterval::OneMinute;
+        assert_eq!(minute.bounded_time_from(200, Some(5), None, 1_000_000), 5);
+        // 200 minutes before now, aligned to the minute
+        assert_eq!(minute.bounded_time_from(200, None, None, 1_000_030), 988_020);
+        // before the end of the range when given
+        assert_eq!(minute.bounded_time_from(10, None, Some(6000), 1_000_000), 5400);
+        assert_eq!(minute.bounded_time_from(200, None, None, 600), 0);
+    }
+
+    #[test]
+    fn test_candlestick_interval() {
+        let interval = CandlestickInterval::OneSecond;
+        assert_eq!(interval.get_seconds(), 1);
+    }
+
+    #[test]
+    fn test_get_candlestick_interval() {
+        let cases = [
+            (CandlestickInterval::OneSecond, 1),
+            (CandlestickInterval::ThirtySeconds, 1),
+            (CandlestickInterval::OneMinute, 60),
+            (CandlestickInterval::FiveMinutes, 300),
+            (CandlestickInterval::FifteenMinutes, 900),
+            (CandlestickInterval::ThirtyMinutes, 1800),
+            (CandlestickInterval::OneHour, 3600),
+            (CandlestickInterval::FourHours, 14400),
+            (CandlestickInterval::OneDay, 86400),
+        ];
+        for (interval, expected) in cases {
+            assert_eq!(interval.get_candlestick_interval(), expected, "{interval}");
+        }
+    }
+
+    #[test]
+    fn test_bucket_start() {
+        assert_eq!(CandlestickInterval::OneMinute.bucket_start(125), 120);
+        assert_eq!(CandlestickInterval::OneMinute.bucket_start(120), 120);
+        assert_eq!(CandlestickInterval::OneHour.bucket_start(7199), 3600);
+    }
+
+    #[test]
+    fn test_source_interval() {
+        for interval in STORED_CANDLESTICK_INTERVALS {
+            if let Some(source) = interval.get_source_interval() {
+                assert!(interval.can_derive_from(&source), "{interval} from {source}");
+                assert!(STORED_CANDLESTICK_INTERVALS.contains(&source));
+                assert_eq!(source.get_source_interval(), None);
+            }
+        }
+        assert_eq!(
+            CandlestickInterval::FourHours.get_source_interval(),
+            Some(CandlestickInterval::OneHour)
+        );
+        assert!(!CandlestickInterval::OneMinute.can_derive_from(&CandlestickInterval::OneMinute));
+        assert!(!CandlestickInterval::OneMinute.can_derive_from(&CandlestickInterval::FiveMinutes));
+        assert!(CandlestickInterval::FifteenMinutes
+            .can_derive_from(&CandlestickInterval::ThirtySeconds));
+        assert!(!CandlestickInterval::OneHour.can_derive_from(&CandlestickInterval::FourHours));
+    }
+
+    #[test]
+    fn test_candlestick_interval_display() {
+        let interval = CandlestickInterval::OneSecond;
+        assert_eq!(format!("{}", interval), "1s");
+    }
+
+    fn candlestick(timestamp: u64, close: f64) -> Candlestick {
+        Candlestick {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: 1.0,
+            turnover: close,
+            buy_volume: 1.0,
+            sell_volume: 0.0,
+            buy_turnover: close,
+            sell_turnover: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_candlestick_without_sides() {
+        // the candlesticks serialized before the buy and sell split
+        let json = r#"{"t":60,"o":1.0,"h":2.0,"l":1.0,"c":2.0,"v":3.0,"vc":4.5}"#;
+        let candlestick: Candlestick = serde_json::from_str(json).unwrap();
+        assert_eq!(candlestick.volume, 3.0);
+        assert_eq!((candlestick.buy_volume, candlestick.sell_turnover), (0.0, 0.0));
+
+        let json = serde_json::to_value(candlestick).unwrap();
+        assert_eq!((json["bv"].as_f64(), json["svc"].as_f64()), (Some(0.0), Some(0.0)));
+    }
+
+    fn closes(candlesticks: &[Candlestick]) -> Vec<(u64, f64)> {
+        candlesticks.iter().map(|c| (c.timestamp, c.close)).collect()
+    }
+
+    #[test]
+    fn test_merge_candlesticks_keeps_newest() {
+        let interval = CandlestickInterval::OneMinute;
+        // the swap events hold the latest minutes, the stored candlesticks the older ones
+        let from_swap_events = vec![candlestick(240, 4.0), candlestick(300, 5.0)];
+        let from_candlesticks = vec![candlestick(120, 2.0), candlestick(180, 3.0)];
+        let merged = merge_candlesticks(from_swap_events, from_candlesticks, &interval, 3);
+        assert_eq!(closes(&merged), vec![(180, 3.0), (240, 4.0), (300, 5.0)]);
+
+        // fewer candlesticks than the size are all kept
+        let merged = merge_candlesticks(vec![candlestick(60, 1.0)], vec![], &interval, 3);
+        assert_eq!(closes(&merged), vec![(60, 1.0)]);
+        assert!(merge_candlesticks(vec![], vec![], &interval, 3).is_empty());
+    }
+
+    #[test]
+    fn test_merge_candlesticks_prefers_swap_events() {
+        let interval = CandlestickInterval::FiveMinutes;
+        let from_swap_events = vec![candlestick(300, 5.0)];
+        // a stored bucket of the same 5m bucket, reported at an unaligned timestamp
+        let from_candlesticks = vec![candlestick(0, 1.0), candlestick(360, 9.0)];
+        let merged = merge_candlesticks(from_swap_events, from_candlesticks, &interval, 10);
+        assert_eq!(closes(&merged), vec![(0, 1.0), (300, 5.0)]);
+    }
+
+    #[test]
+    fn test_quote_prices_carry_forward() {
+        let interval = CandlestickInterval::OneMinute;
+        // the quote did not trade at 180 and 240, nor before 120
+        let quote = QuotePrices::new(
+            &[candlestick(120, 100.0), candlestick(300, 200.0), candlestick(360, 0.0)],
+            &interval,
+        );
+        assert_eq!(quote.at(60), Some(100.0));
+        assert_eq!(quote.at(150), Some(100.0));
+        assert_eq!(quote.at(240), Some(100.0));
+        assert_eq!(quote.at(300), Some(200.0));
+        // the zero close is skipped
+        assert_eq!(quote.at(360), Some(200.0));
+
+        let candlesticks =
+            vec![candlestick(120, 50.0), candlestick(240, 20.0), candlestick(300, 50.0)];
+        let denominated = quote.denominate(candlesticks).unwrap();
+        assert_eq!(closes(&denominated), vec![(120, 0.5), (240, 0.2), (300, 0.25)]);
+        assert_eq!(denominated[1].high, 0.2);
+        // the turnover stays in USD
+        assert_eq!(denominated[1].turnover, 20.0);
+
+        let empty = QuotePrices::new(&[], &interval);
+        assert!(empty.is_empty());
+        assert!(empty.denominate(vec![candlestick(120, 50.0)]).is_none());
+        assert!(empty.denominate(vec![]).is_some_and(|c| c.is_empty()));
+    }
+
+    #[test]
+    fn test_quote_prices_nearest() {
+        let interval = CandlestickInterval::OneMinute;
+        let quote =
+            QuotePrices::new(&[candlestick(120, 100.0), candlestick(480, 200.0)], &interval);
+        assert_eq!(quote.nearest(0), Some(100.0));
+        assert_eq!(quote.nearest(130), Some(100.0));
+        assert_eq!(quote.nearest(300), Some(100.0));
+        assert_eq!(quote.nearest(400), Some(200.0));
+        assert_eq!(quote.nearest(1000), Some(200.0));
+        assert_eq!(QuotePrices::new(&[], &interval).nearest(100), None);
+    }
+
+    #[test]
+    fn test_outlier_policy_sql() {
+        let policy = OutlierPolicy::default();
+        assert_eq!(
+            policy.high_low_sql(),
+            "if(count() >= 10 AND max(price) > quantileExactWeighted(0.995000)(price, 1) * 20, \
+             quantileExactWeighted(0.995000)(price, 1), max(price)) AS high, \
+             if(count() >= 10 AND min(price) < quantileExactWeighted(0.005000)(price, 1) / 20, \
+             quantileExactWeighted(0.005000)(price, 1), min(price)) AS low"
+        );
+
+        let disabled = policy.with_clamp(Some(false));
+        assert!(!disabled.enabled);
+        assert_eq!(disabled.high_low_sql(), "max(price) AS high, min(price) AS low");
+        assert_eq!(disabled.with_clamp(None), disabled);
+        assert_eq!(disabled.with_clamp(Some(true)), policy);
+    }
+
+    #[test]
+    fn test_reference_candlesticks() {
+        let price = |timestamp: u64, price: f64| ReferencePrice {
+            pair: SOL_USD_PAIR.to_string(),
+            price,
+            timestamp,
+        };
+        let prices = vec![
+            price(65, 151.0),
+            price(60, 150.0),
+            price(70, f64::NAN),
+            price(90, 149.0),
+            price(119, 152.0),
+            price(130, 153.0),
+        ];
+        let candlesticks = reference_candlesticks(prices, &CandlestickInterval::OneMinute);
+        assert_eq!(candlesticks.len(), 2);
+        let first = &candlesticks[0];
+        assert_eq!(first.timestamp, 60);
+        assert_eq!((first.open, first.high, first.low, first.close), (150.0, 152.0, 149.0, 152.0));
+        assert_eq!(first.volume, 0.0);
+        assert_eq!(candlesticks[1].timestamp, 120);
+        assert_eq!(candlesticks[1].open, 153.0);
+        assert!(is_reference_pair("SOLUSD"));
+        assert!(!is_reference_pair(TOKEN_CANDLESTICK_PAIR));
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use solana_pubkey::{pubkey, Pubkey};
+use strum::{Display, EnumString};
+
+/// The launchpad of the tokens traded on the pump bonding curve and AMM
+pub const PUMP_LAUNCHPAD: &str = "pump";
+
+/// The venues the swaps are decoded from, displayed as the snake case names stored with the
+/// pairs and published with the trades and the new pools
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Display,
+    EnumString,
+    utoipa::ToSchema
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Dexes {
+    MeteoraDammV2,
+    MeteoraDlmm,
+    MeteoraPools,
+    /// The Orca whirlpools, stored as `ocra_whirlpool`
+    OcraWhirlpool,
+    /// The pump swap AMM the pump.fun tokens graduate to
+    PumpAmm,
+    /// The pump.fun bonding curve
+    PumpFun,
+    RaydiumAmmV4,
+    RaydiumClmm,
+    RaydiumCpmm,
+    RaydiumLaunchpad,
+}
+
+/// Every dex, in the order of the variants
+pub const DEXES: [Dexes; 10] = [
+    Dexes::MeteoraDammV2,
+    Dexes::MeteoraDlmm,
+    Dexes::MeteoraPools,
+    Dexes::OcraWhirlpool,
+    Dexes::PumpAmm,
+    Dexes::PumpFun,
+    Dexes::RaydiumAmmV4,
+    Dexes::RaydiumClmm,
+    Dexes::RaydiumCpmm,
+    Dexes::RaydiumLaunchpad,
+];
+
+impl Dexes {
+    /// program_id returns the program of the dex
+    pub fn program_id(&self) -> Pubkey {
+        match self {
+            Dexes::MeteoraDammV2 => pubkey!("cpamdpZCGKUy5JxQXB4dcpGPiikHawvSWAd6mEn1sGG"),
+            Dexes::MeteoraDlmm => pubkey!("LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo"),
+            Dexes::MeteoraPools => pubkey!("Eo7WjKq67rjJQSZxS6z3YkapzY3eMj6Xy8X5EQVn5UaB"),
+            Dexes::OcraWhirlpool => pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc"),
+            Dexes::PumpAmm => pubkey!("pAMMBay6oceH9fJKBRHGP5D4bD4sWpmSwMn52FMfXEA"),
+            Dexes::PumpFun => pubkey!("6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P"),
+            Dexes::RaydiumAmmV4 => pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8"),
+            Dexes::RaydiumClmm => pubkey!("CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK"),
+            Dexes::RaydiumCpmm => pubkey!("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C"),
+            Dexes::RaydiumLaunchpad => pubkey!("LanMV9sAd7wArD4vJFi2qDdfnVhFxYSUg6eADduJ3uj"),
+        }
+    }
+
+    /// from_program_id returns the dex of a program, None for the programs of no dex
+    pub fn from_program_id(program_id: &Pubkey) -> Option<Self> {
+        DEXES.into_iter().find(|dex| dex.program_id() == *program_id)
+    }
+
+    /// launchpad returns the launchpad the tokens traded on the dex were launched on,
+    /// None for the dexes which aren't launchpads
+    pub fn launchpad(&self) -> Option<&'static str> {
+        match self {
+            Dexes::PumpAmm | Dexes::PumpFun => Some(PUMP_LAUNCHPAD),
+            Dexes::RaydiumLaunchpad => Some("raydium_launchpad"),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes a dex as its name, the ClickHouse and Postgres columns store the name
+pub mod dex_name {
+    use super::Dexes;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(dex: &Dexes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(dex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Dexes, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(|_| D::Error::custom(format!("unknown dex {name}")))
+    }
+}
+
+/// Serializes an optional dex as its name, or null when unknown
+pub mod optional_dex_name {
+    use super::Dexes;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(dex: &Option<Dexes>, serializer: S) -> Result<S::Ok, S::Error> {
+        dex.map(|dex| dex.to_string()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Dexes>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|name| name.parse().map_err(|_| D::Error::custom(format!("unknown dex {name}"))))
+            .transpose()
+    }
+}
+
+/// Serializes an optional dex as its name, or an empty name when unknown, for the columns
+/// and payloads storing the unknown dex as an empty string
+pub mod dex_name_or_empty {
+    use super::Dexes;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(dex: &Option<Dexes>, serializer: S) -> Result<S::Ok, S::Error> {
+        match dex {
+            Some(dex) => serializer.collect_str(dex),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Dexes>, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match name.as_str() {
+            "" => Ok(None),
+            _ => {
+                name.parse().map(Some).map_err(|_| D::Error::custom(format!("unknown dex {name}")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_dexes_round_trip() {
+        for dex in DEXES {
+            let name = dex.to_string();
+            assert_eq!(Dexes::from_str(&name).unwrap(), dex, "{name}");
+            let json = serde_json::to_string(&dex).unwrap();
+            assert_eq!(json, format!("\"{name}\""));
+            assert_eq!(serde_json::from_str::<Dexes>(&json).unwrap(), dex);
+            assert_eq!(Dexes::from_program_id(&dex.program_id()), Some(dex));
+        }
+        assert!(Dexes::from_str("RaydiumAmmV4").is_err());
+    }
+
+    #[test]
+    fn test_dexes_stored_names() {
+        // the names already stored with the pairs and published with the trades
+        let names = [
+            (Dexes::MeteoraDlmm, "meteora_dlmm"),
+            (Dexes::MeteoraPools, "meteora_pools"),
+            (Dexes::OcraWhirlpool, "ocra_whirlpool"),
+            (Dexes::PumpAmm, "pump_amm"),
+            (Dexes::RaydiumAmmV4, "raydium_amm_v4"),
+            (Dexes::RaydiumClmm, "raydium_clmm"),
+            (Dexes::RaydiumCpmm, "raydium_cpmm"),
+            (Dexes::RaydiumLaunchpad, "raydium_launchpad"),
+        ];
+        for (dex, name) in names {
+            assert_eq!(dex.to_string(), name);
+        }
+        assert_eq!(Dexes::MeteoraDammV2.to_string(), "meteora_damm_v2");
+        assert_eq!(Dexes::PumpFun.to_string(), "pump_fun");
+    }
+
+    #[test]
+    fn test_dex_name_adapters() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Row {
+            #[serde(with = "dex_name")]
+            dex: Dexes,
+            #[serde(with = "optional_dex_name")]
+            optional: Option<Dexes>,
+            #[serde(with = "dex_name_or_empty")]
+            launch_dex: Option<Dexes>,
+        }
+
+        let row = Row { dex: Dexes::PumpAmm, optional: None, launch_dex: None };
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(json, r#"{"dex":"pump_amm","optional":null,"launch_dex":""}"#);
+        assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+
+        let row = Row {
+            dex: Dexes::OcraWhirlpool,
+            optional: Some(Dexes::RaydiumClmm),
+            launch_dex: Some(Dexes::RaydiumLaunchpad),
+        };
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(
+            json,
+            r#"{"dex":"ocra_whirlpool","optional":"raydium_clmm","launch_dex":"raydium_launchpad"}"#
+        );
+        assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+
+        let unknown = r#"{"dex":"RaydiumAmmV4","optional":null,"launch_dex":""}"#;
+        assert!(serde_json::from_str::<Row>(unknown).is_err());
+    }
+}
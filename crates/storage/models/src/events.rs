@@ -0,0 +1,46 @@
+use crate::dexes::Dexes;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewPoolEvent {
+    pub dex: Dexes,
+    pub token_a_mint: String,
+    pub token_b_mint: String,
+    pub pool: String,
+    pub timestamp: u64,
+}
+
+/// A token migrated from its launchpad bonding curve to an AMM pool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenGraduatedEvent {
+    pub mint: String,
+    pub pool: String,
+    pub dex: Dexes,
+    pub timestamp: u64,
+}
+
+/// The candlestick buckets of an interval finalized by the scheduler, published on the
+/// `candles_closed` channel once they are aggregated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CandlesClosedEvent {
+    /// The interval of the candlesticks, e.g. `1m`
+    pub interval: String,
+    /// The start of the first closed bucket, inclusive
+    pub bucket_start: i64,
+    /// The end of the last closed bucket, exclusive
+    pub bucket_end: i64,
+    /// The pairs traded in the buckets, only listed by the minute job and capped in size
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pairs: Option<Vec<String>>,
+}
+
+/// An operational alert published on the `system_alert` channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemAlert {
+    /// The component raising the alert, e.g. `slot_lag`
+    pub source: String,
+    pub message: String,
+    /// The measured value that triggered the alert
+    pub value: f64,
+    pub timestamp: u64,
+}
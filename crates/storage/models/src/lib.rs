@@ -0,0 +1,18 @@
+//! The models of the sonar storage and API, without the storage itself
+//!
+//! The `clickhouse` feature derives the ClickHouse rows, the `metadata` feature converts the
+//! on-chain token metadata.
+pub mod candlesticks;
+pub mod dexes;
+pub mod events;
+pub mod pairs;
+pub mod swap;
+pub mod tokens;
+pub mod wallet;
+
+pub use candlesticks::Candlestick;
+pub use dexes::Dexes;
+pub use events::{CandlesClosedEvent, NewPoolEvent, SystemAlert, TokenGraduatedEvent};
+pub use pairs::PairInfo;
+pub use swap::SwapEvent;
+pub use tokens::{Token, TokenMetadata, TokenRiskFlags};
@@ -0,0 +1,106 @@
+use crate::dexes::{dex_name, optional_dex_name, Dexes};
+use serde::{Deserialize, Serialize};
+
+/// A pool a token trades in, with its turnover over the requested window
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PairInfo {
+    pub pair: String,
+    /// The dex of the pair, if known
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "optional_dex_name")]
+    pub dex: Option<Dexes>,
+    pub turnover: f64,
+    pub last_trade_ts: u64,
+}
+
+/// The mints and dex of a pool, recorded on its first processed swap
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Pair {
+    pub pair: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    #[serde(with = "dex_name")]
+    pub dex: Dexes,
+    pub first_seen: u64,
+}
+
+/// A pool with its latest price and its stats over the last 24 hours
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PairDetail {
+    pub pair: String,
+    /// The recorded base mint, or the mint traded the most in the pair
+    pub base_mint: String,
+    /// The quote mint, if the pair was recorded
+    pub quote_mint: Option<String>,
+    /// The dex of the pair, if the pair was recorded
+    #[serde(with = "optional_dex_name")]
+    pub dex: Option<Dexes>,
+    /// The price of the base mint at the latest trade, denoted as usd
+    pub price: f64,
+    pub volume_24h: f64,
+    pub turnover_24h: f64,
+    pub trades_24h: u64,
+    pub first_seen: u64,
+    pub last_trade_ts: u64,
+}
+
+/// The fees a pool collected in a mint over the requested window
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct FeeStat {
+    pub pair: String,
+    pub fee_mint: String,
+    /// The fees, denoted as usd
+    pub fee_amount: f64,
+    /// The turnover of the swaps paying the fees, denoted as usd
+    pub swap_amount: f64,
+    pub swaps: u64,
+}
+
+/// The latest price of a pool, cached per pair so that the pools of a token don't override
+/// each other
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PairPrice {
+    /// The price of the base mint, denoted as usd
+    pub price: f64,
+    pub timestamp: u64,
+    pub signature: String,
+    /// The turnover of the pair decayed by its age, ranks the pairs of a token
+    #[serde(default)]
+    pub turnover: f64,
+}
+
+/// Where a cached price of a mint comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    /// A processed swap
+    Swap,
+    /// The account state of a pool, e.g. the active bin of a DLMM pair
+    PoolState,
+}
+
+/// The mid-price of a pool derived from its account state, it keeps the price of a mint
+/// moving between its swaps
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PoolStatePrice {
+    pub pair: String,
+    /// The price of the base mint, denoted as usd
+    pub price: f64,
+    pub timestamp: u64,
+    pub source: PriceSource,
+}
+
+/// The canonical pool of a token, charted by default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PrimaryPair {
+    pub pair: String,
+    /// The decayed turnover of the pair at `timestamp`
+    pub turnover: f64,
+    pub timestamp: u64,
+    /// Pinned by an admin, trades don't replace it
+    #[serde(default)]
+    pub pinned: bool,
+}
@@ -0,0 +1,292 @@
+use super::{wallet::WalletCategory, Dexes, Token};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Swap {
+    pub coin_price: f64,
+    pub coin_mint: String,
+    pub pc_mint: String,
+    pub coin_decimals: u64,
+    pub pc_decimals: u64,
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SwapEvent {
+    pub pair: String,
+    pub pubkey: String,
+    pub price: f64,
+    pub market_cap: f64,
+    pub base_amount: f64,  // base amount
+    pub quote_amount: f64, // quote amount
+    pub swap_amount: f64,  // denoted as usd
+    pub owner: String,
+    pub signature: String,
+    pub signers: Vec<String>,
+    pub slot: u64,
+    pub timestamp: u64,
+    pub is_buy: bool,
+    pub is_pump: bool,
+    /// the swap is likely a wash trade, e.g. a self-swap of the fee payer
+    pub is_wash: bool,
+    /// the fees paid into the fee accounts of the pool, denoted as usd,
+    /// zero if the dex has no identifiable fee transfers
+    #[serde(default)]
+    pub fee_amount: f64,
+    /// the mint most of the fee value was paid in, empty without fees
+    #[serde(default)]
+    pub fee_mint: String,
+    /// both sides of the swap are majors, e.g. USDC/USDT, the analytics leave it out
+    #[serde(default)]
+    pub is_quote_pair: bool,
+    /// the share of the token supply moved by the swap, `base_amount / supply`,
+    /// zero when the supply is unknown
+    #[serde(default)]
+    pub pct_of_supply: f64,
+    /// the account paying the transaction fee, the relayer of the trading bots swapping on
+    /// behalf of the owner, empty for the swaps recorded before it was
+    #[serde(default)]
+    pub fee_payer: String,
+}
+
+impl SwapEvent {
+    /// update_market_cap sets the market cap of the supply, zero when it isn't finite
+    pub fn update_market_cap(&mut self, supply: f64) {
+        let market_cap = self.price * supply;
+        self.market_cap = if market_cap.is_finite() { market_cap } else { 0.0 };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, utoipa::IntoParams, utoipa::ToSchema)]
+pub struct TradeQuery {
+    pub tx_hash: String,
+    pub address: Option<String>,
+    pub token: Option<String>,
+    pub pair: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Filter of the trades, `get_trades` needs one of `address`, `token`, `pair` or
+/// `signature`, `stream_trades` reads the token, the pair, the time range and the limit
+#[derive(Debug, Clone, Default)]
+pub struct TradeFilter {
+    /// the owner or the fee payer of the trades
+    pub address: Option<String>,
+    pub token: Option<String>,
+    pub pair: Option<String>,
+    pub signature: Option<String>,
+    pub slot: Option<u64>,
+    pub time_from: Option<u64>,
+    pub time_to: Option<u64>,
+    /// the category of the latest label of the owners
+    pub category: Option<WalletCategory>,
+    /// the share of the token supply the trades move at least
+    pub min_pct_supply: Option<f64>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// The csv header of a trade, matching [`Trade::to_csv`]
+pub const TRADE_CSV_HEADER: &str = "pair,token,price,market_cap,base_amount,quote_amount,swap_amount,owner,signature,signers,slot,timestamp,is_buy,is_pump,is_wash";
+
+/// Keyset cursor of a trade, trades are ordered by `(timestamp, signature, pair)`
+pub type TradeCursor = (u64, String, String);
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Clone, Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Trade {
+    #[serde(rename = "pair")]
+    pub pair: String,
+    #[serde(rename = "token")]
+    pub pubkey: String,
+    #[serde(rename = "price")]
+    pub price: f64,
+    #[serde(rename = "market_cap")]
+    pub market_cap: f64,
+    #[serde(rename = "base_amount")]
+    pub base_amount: f64, // base amount
+    #[serde(rename = "quote_amount")]
+    pub quote_amount: f64, // quote amount
+    #[serde(rename = "swap_amount")]
+    pub swap_amount: f64, // denoted as usd
+    #[serde(rename = "owner")]
+    pub owner: String,
+    #[serde(rename = "signature")]
+    pub signature: String,
+    #[serde(rename = "signers")]
+    pub signers: Vec<String>,
+    #[serde(rename = "slot")]
+    pub slot: u64,
+    #[serde(rename = "timestamp")]
+    pub timestamp: u64,
+    #[serde(rename = "is_buy")]
+    pub is_buy: bool,
+    #[serde(rename = "is_pump")]
+    pub is_pump: bool,
+    #[serde(rename = "is_wash", default)]
+    pub is_wash: bool,
+    #[serde(rename = "fee_amount", default)]
+    pub fee_amount: f64, // denoted as usd
+    #[serde(rename = "fee_mint", default)]
+    pub fee_mint: String,
+    /// The share of the token supply moved by the trade, zero when the supply is unknown
+    #[serde(rename = "pct_of_supply", default)]
+    pub pct_of_supply: f64,
+    /// The account paying the transaction fee, differs from the owner for the bot trades
+    #[serde(rename = "fee_payer", default)]
+    pub fee_payer: String,
+    /// The symbol of the token, not stored with the swap events
+    #[serde(rename = "symbol", default)]
+    pub symbol: String,
+    /// The decimals of the token, not stored with the swap events
+    #[serde(rename = "decimals", default)]
+    pub decimals: u8,
+}
+
+/// A trade as published in the enveloped format, with the fields raw consumers don't know
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TradeV2 {
+    #[serde(flatten)]
+    pub trade: Trade,
+    pub dex: Dexes,
+}
+
+impl Trade {
+    /// cursor returns the keyset cursor of the trade
+    pub fn cursor(&self) -> TradeCursor {
+        (self.timestamp, self.signature.clone(), self.pair.clone())
+    }
+
+    /// from_swap_event_with_token returns the trade of the swap event, enriched with the
+    /// symbol and decimals of the token when its metadata is known
+    pub fn from_swap_event_with_token(swap_event: SwapEvent, token: Option<&Token>) -> Self {
+        let mut trade = Trade::from(swap_event);
+        if let Some(token) = token {
+            trade.symbol = token.symbol.clone();
+            trade.decimals = token.decimals;
+        }
+        trade
+    }
+
+    /// to_csv serializes the trade into a csv record, the signers are written as a JSON array
+    pub fn to_csv(&self) -> String {
+        let signers = serde_json::to_string(&self.signers).unwrap_or_default();
+        [
+            csv_escape(&self.pair),
+            csv_escape(&self.pubkey),
+            self.price.to_string(),
+            self.market_cap.to_string(),
+            self.base_amount.to_string(),
+            self.quote_amount.to_string(),
+            self.swap_amount.to_string(),
+            csv_escape(&self.owner),
+            csv_escape(&self.signature),
+            csv_escape(&signers),
+            self.slot.to_string(),
+            self.timestamp.to_string(),
+            self.is_buy.to_string(),
+            self.is_pump.to_string(),
+            self.is_wash.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+/// Quote a csv field if it contains a separator, a quote or a new line
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl From<SwapEvent> for Trade {
+    fn from(swap_event: SwapEvent) -> Self {
+        Trade {
+            pair: swap_event.pair,
+            pubkey: swap_event.pubkey,
+            price: swap_event.price,
+            market_cap: swap_event.market_cap,
+            base_amount: swap_event.base_amount,
+            quote_amount: swap_event.quote_amount,
+            swap_amount: swap_event.swap_amount,
+            owner: swap_event.owner,
+            signature: swap_event.signature,
+            signers: swap_event.signers,
+            slot: swap_event.slot,
+            timestamp: swap_event.timestamp,
+            is_buy: swap_event.is_buy,
+            is_pump: swap_event.is_pump,
+            is_wash: swap_event.is_wash,
+            fee_amount: swap_event.fee_amount,
+            fee_mint: swap_event.fee_mint,
+            pct_of_supply: swap_event.pct_of_supply,
+            fee_payer: swap_event.fee_payer,
+            symbol: String::new(),
+            decimals: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_trade(signers: Vec<&str>) -> Trade {
+        let event = SwapEvent {
+            pair: "pair".to_string(),
+            pubkey: "token".to_string(),
+            price: 0.5,
+            market_cap: 0.0,
+            base_amount: 0.0,
+            quote_amount: 1.0,
+            swap_amount: 0.0,
+            owner: "owner".to_string(),
+            signature: "signature".to_string(),
+            signers: vec![],
+            slot: 0,
+            timestamp: 1747958400,
+            is_buy: true,
+            is_pump: false,
+            is_wash: false,
+            fee_amount: 0.0,
+            fee_mint: String::new(),
+            is_quote_pair: false,
+            pct_of_supply: 0.0,
+            fee_payer: String::new(),
+        };
+        Trade {
+            market_cap: 1000.0,
+            base_amount: 2.0,
+            swap_amount: 150.0,
+            signers: signers.into_iter().map(String::from).collect(),
+            slot: 1,
+            ..event.into()
+        }
+    }
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_trade_to_csv() {
+        let record = make_trade(vec!["a", "b"]).to_csv();
+        assert_eq!(
+            record,
+            r#"pair,token,0.5,1000,2,1,150,owner,signature,"[""a"",""b""]",1,1747958400,true,false,false"#
+        );
+        assert_eq!(record.matches(',').count(), TRADE_CSV_HEADER.matches(',').count() + 1);
+
+        let record = make_trade(vec!["a"]).to_csv();
+        assert!(record.contains(r#","[""a""]","#));
+
+        let record = make_trade(vec![]).to_csv();
+        assert!(record.contains(",[],"));
+    }
+}
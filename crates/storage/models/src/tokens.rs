@@ -0,0 +1,517 @@
+use crate::dexes::{dex_name_or_empty, Dexes};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::LazyLock};
+use strum::Display;
+
+/// The wrapped SOL mint
+pub const WSOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+/// The USDC mint
+pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+/// The USDT mint
+pub const USDT_MINT: &str = "Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB";
+
+/// The stablecoins quoting the pairs, USDC and USDT
+pub const DEFAULT_MAJOR_MINTS: [&str; 2] = [USDC_MINT, USDT_MINT];
+
+/// The majors kept out of the top tokens and the search, a swap between two of them is a
+/// quote pair swap rather than a trade of a token. `MAJOR_MINTS` overrides the defaults
+/// with comma separated mints
+pub static MAJOR_MINTS: LazyLock<HashSet<String>> =
+    LazyLock::new(|| parse_major_mints(std::env::var("MAJOR_MINTS").ok().as_deref()));
+
+/// parse_major_mints returns the comma separated mints, the defaults when unset or empty,
+/// the values which can't be a mint are dropped so they are safe to inline into queries
+pub fn parse_major_mints(value: Option<&str>) -> HashSet<String> {
+    let mints: HashSet<String> = value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|mint| !mint.is_empty() && mint.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_string)
+        .collect();
+    match mints.is_empty() {
+        true => DEFAULT_MAJOR_MINTS.iter().map(|mint| mint.to_string()).collect(),
+        false => mints,
+    }
+}
+
+/// is_major_mint returns true if the mint is one of the configured majors
+pub fn is_major_mint(mint: &str) -> bool {
+    MAJOR_MINTS.contains(mint)
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TopToken {
+    pub pubkey: String,
+    pub price: f64,
+    pub market_cap: f64,
+    pub volume: f64,
+    pub turnover: f64,
+    pub price_change: f64,
+    /// The approximate number of distinct signers of the window
+    #[serde(default)]
+    pub unique_traders: u64,
+    #[serde(default)]
+    pub unique_buyers: u64,
+    #[serde(default)]
+    pub unique_sellers: u64,
+}
+
+/// The column the top tokens are ranked by
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    Display,
+    utoipa::ToSchema
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum TopTokensSort {
+    #[default]
+    Volume,
+    Turnover,
+    PriceChange,
+    MarketCap,
+}
+
+impl TopTokensSort {
+    /// column returns the column of the top tokens query to order by, the coalesced alias
+    /// so the tokens without volume or price change sort as 0
+    pub fn column(&self) -> &'static str {
+        match self {
+            TopTokensSort::Volume => "volume",
+            TopTokensSort::Turnover => "turnover",
+            TopTokensSort::PriceChange => "price_change",
+            TopTokensSort::MarketCap => "market_cap",
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    Display,
+    utoipa::ToSchema
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+/// A page of the top tokens and the number of tokens matching the filters
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TopTokensPage {
+    pub tokens: Vec<TopToken>,
+    pub total: u64,
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenStat {
+    pub pubkey: String,
+    pub price: f64,
+    pub market_cap: f64,
+    pub price_5m: f64,
+    pub price_1h: f64,
+    pub price_6h: f64,
+    pub price_24h: f64,
+    pub volume_5m: f64,
+    pub volume_1h: f64,
+    pub volume_6h: f64,
+    pub volume_24h: f64,
+    pub turnover_5m: f64,
+    pub turnover_1h: f64,
+    pub turnover_6h: f64,
+    pub turnover_24h: f64,
+    /// The approximate number of distinct signers of the last 24h
+    #[serde(default)]
+    pub unique_traders_24h: u64,
+    #[serde(default)]
+    pub unique_buyers_24h: u64,
+    #[serde(default)]
+    pub unique_sellers_24h: u64,
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenDailyStat {
+    pub pubkey: String,
+    pub timestamp: u64,
+    pub price: f64,
+    pub market_cap: f64,
+    pub price_24h: f64,
+    pub volume_24h: f64,
+    pub turnover_24h: f64,
+    /// The approximate number of distinct signers of the last 24h
+    #[serde(default)]
+    pub unique_traders_24h: u64,
+    #[serde(default)]
+    pub unique_buyers_24h: u64,
+    #[serde(default)]
+    pub unique_sellers_24h: u64,
+}
+
+/// The trading of a token over a UTC day, kept after its swap events are pruned
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenDailyRollup {
+    pub pubkey: String,
+    /// The UTC day, e.g. `2025-05-23`
+    pub date: String,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub turnover: f64,
+    pub trade_count: u64,
+    /// The number of distinct signers of the day, approximate on ClickHouse
+    pub unique_traders: u64,
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenPrice {
+    pub token: String,
+    pub timestamp: i32,
+    pub price: Option<f64>,
+    pub neatest_timestamp: Option<i32>,
+    /// where the price was read from, for debugging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PriceSource>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceSource {
+    Kv,
+    Db,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Creator {
+    pub address: String,
+    pub verified: bool,
+    pub share: u8,
+}
+
+#[cfg(feature = "metadata")]
+impl From<mpl_token_metadata::types::Creator> for Creator {
+    fn from(creator: mpl_token_metadata::types::Creator) -> Self {
+        Self {
+            address: creator.address.to_string(),
+            verified: creator.verified,
+            share: creator.share,
+        }
+    }
+}
+
+/// Cleans a metadata string by removing null terminators
+///
+/// # Arguments
+///
+/// * `s`: The metadata string to clean
+///
+/// # Returns
+///
+/// * `String`: The cleaned metadata string
+///
+/// # Examples
+///
+/// ```rust
+/// use sonar_db::clean_string;
+/// let s = "USD Coin\0\0\0";
+/// let cleaned = clean_string(s);
+/// assert_eq!(cleaned, "USD Coin");
+/// ```
+pub fn clean_string(s: &str) -> String {
+    s.trim_matches(char::from(0)).to_string()
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Clone, Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Token {
+    pub retrieval_timestamp: u64,
+    pub is_nft: bool,
+    pub token: String,
+    pub update_authority: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub supply: f64,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub primary_sale_happened: bool,
+    pub is_mutable: bool,
+    /// The launchpad the token was first seen trading on, empty when not launched on one
+    #[serde(default)]
+    pub launchpad: String,
+    /// When the token migrated from its bonding curve to an AMM pool, 0 when it didn't
+    #[serde(default)]
+    pub graduated_at: u64,
+    /// The AMM pool the token migrated to, empty when it didn't
+    #[serde(default)]
+    pub graduation_pool: String,
+    /// The dex the token was launched on, empty when its launch wasn't observed
+    #[serde(default, with = "dex_name_or_empty")]
+    #[schema(value_type = String)]
+    pub launch_dex: Option<Dexes>,
+    /// The pool the token was launched in, e.g. its bonding curve
+    #[serde(default)]
+    pub launch_pool: String,
+    /// When the launch pool was initialized, 0 when the launch wasn't observed
+    #[serde(default)]
+    pub launch_timestamp: u64,
+}
+
+/// The launch provenance of a token, empty when its launch wasn't observed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenLaunch {
+    /// The dex the token was launched on, empty when its launch wasn't observed
+    #[serde(with = "dex_name_or_empty")]
+    #[schema(value_type = String)]
+    pub launch_dex: Option<Dexes>,
+    pub launch_pool: String,
+    pub launch_timestamp: u64,
+}
+
+/// A launchpad, graduation or launch of a token recorded in its own row, leaving the fields it
+/// doesn't record empty, the earliest record setting a field wins
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenLaunchRecord {
+    pub token: String,
+    pub launchpad: String,
+    pub graduated_at: u64,
+    pub graduation_pool: String,
+    #[serde(with = "dex_name_or_empty")]
+    pub launch_dex: Option<Dexes>,
+    pub launch_pool: String,
+    pub launch_timestamp: u64,
+    /// When the record was written, in milliseconds
+    pub recorded_at: u64,
+}
+
+/// The relative supply change below which two retrievals of a token hold the same supply
+pub const TOKEN_SUPPLY_EPSILON: f64 = 1e-9;
+
+impl Token {
+    /// same_metadata returns true if both retrievals resolved the same metadata, the supply
+    /// only differs when it moved beyond `TOKEN_SUPPLY_EPSILON`
+    pub fn same_metadata(&self, other: &Token) -> bool {
+        let supply_delta = (self.supply - other.supply).abs();
+        supply_delta <= TOKEN_SUPPLY_EPSILON * self.supply.abs().max(1.0)
+            && self.token == other.token
+            && self.name == other.name
+            && self.symbol == other.symbol
+            && self.uri == other.uri
+            && self.decimals == other.decimals
+            && self.is_nft == other.is_nft
+            && self.update_authority == other.update_authority
+            && self.seller_fee_basis_points == other.seller_fee_basis_points
+            && self.primary_sale_happened == other.primary_sale_happened
+            && self.is_mutable == other.is_mutable
+    }
+
+    /// apply_launch_record fills the launchpad, the graduation and the launch the token row
+    /// lacks from `record`
+    pub fn apply_launch_record(&mut self, record: &TokenLaunchRecord) {
+        if self.launchpad.is_empty() {
+            self.launchpad = record.launchpad.clone();
+        }
+        if self.graduation_pool.is_empty() && !record.graduation_pool.is_empty() {
+            self.graduated_at = record.graduated_at;
+            self.graduation_pool = record.graduation_pool.clone();
+        }
+        if self.launch_pool.is_empty() && !record.launch_pool.is_empty() {
+            self.launch_dex = record.launch_dex;
+            self.launch_pool = record.launch_pool.clone();
+            self.launch_timestamp = record.launch_timestamp;
+        }
+    }
+
+    /// launch returns the launch provenance of the token
+    pub fn launch(&self) -> TokenLaunch {
+        TokenLaunch {
+            launch_dex: self.launch_dex,
+            launch_pool: self.launch_pool.clone(),
+            launch_timestamp: self.launch_timestamp,
+        }
+    }
+}
+
+/// Honeypot-style traits of a Token-2022 mint, decoded from its extensions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenRiskFlags {
+    /// The highest transfer fee of the current and the scheduled fee config
+    pub transfer_fee_bps: u16,
+    /// The permanent delegate can transfer or burn tokens from any account
+    pub has_permanent_delegate: bool,
+    /// Every transfer invokes the transfer hook program, which may reject it
+    pub has_transfer_hook: bool,
+    /// New token accounts start frozen
+    pub default_frozen: bool,
+}
+
+impl TokenRiskFlags {
+    /// is_risky returns true if any of the flags is raised
+    pub fn is_risky(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenSearch {
+    pub token: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub supply: f64,
+    pub latest_price: f64,
+    pub price_24h: f64,
+    pub tx_count_24h: u64,
+    pub volume_24h: f64,
+    pub turnover_24h: f64,
+    /// Whether the token has been verified by an admin
+    pub verified: bool,
+}
+
+/// How a token matched a search query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchReason {
+    Mint,
+    SymbolExact,
+    SymbolPrefix,
+    NamePrefix,
+    Substring,
+    Fuzzy,
+}
+
+impl MatchReason {
+    /// The score added for the kind of match, outweighing the turnover of unrelated tokens
+    pub fn boost(&self) -> f64 {
+        match self {
+            MatchReason::Mint => 1000.0,
+            MatchReason::SymbolExact => 50.0,
+            MatchReason::SymbolPrefix => 20.0,
+            MatchReason::NamePrefix => 15.0,
+            MatchReason::Substring => 5.0,
+            MatchReason::Fuzzy => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenSearchResult {
+    #[serde(flatten)]
+    pub token: TokenSearch,
+    /// The ranking score, only returned with `debug=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Why the token matched, only returned with `debug=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_reason: Option<MatchReason>,
+    #[serde(flatten)]
+    pub launch: TokenLaunch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TokenMetadata {
+    pub mint: String,
+    pub update_authority: String,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub primary_sale_happened: bool,
+    pub is_mutable: bool,
+}
+
+#[cfg(feature = "metadata")]
+impl From<mpl_token_metadata::accounts::Metadata> for TokenMetadata {
+    fn from(metadata: mpl_token_metadata::accounts::Metadata) -> Self {
+        Self {
+            mint: metadata.mint.to_string(),
+            update_authority: metadata.update_authority.to_string(),
+            name: clean_string(&metadata.name),
+            symbol: clean_string(&metadata.symbol),
+            uri: clean_string(&metadata.uri),
+            seller_fee_basis_points: metadata.seller_fee_basis_points,
+            primary_sale_happened: metadata.primary_sale_happened,
+            is_mutable: metadata.is_mutable,
+        }
+    }
+}
+
+#[cfg(feature = "metadata")]
+impl From<spl_token_metadata_interface::state::TokenMetadata> for TokenMetadata {
+    fn from(metadata: spl_token_metadata_interface::state::TokenMetadata) -> Self {
+        Self {
+            mint: metadata.mint.to_string(),
+            update_authority: metadata.update_authority.0.to_string(),
+            name: clean_string(&metadata.name),
+            symbol: clean_string(&metadata.symbol),
+            uri: clean_string(&metadata.uri),
+            seller_fee_basis_points: 0,
+            primary_sale_happened: false,
+            is_mutable: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_major_mints() {
+        let defaults = parse_major_mints(None);
+        assert_eq!(defaults.len(), 2);
+        assert!(defaults.contains(DEFAULT_MAJOR_MINTS[0]));
+        assert_eq!(parse_major_mints(Some(" ")), defaults);
+
+        let mints = parse_major_mints(Some("mint-a', mintB ,,mintC"));
+        assert_eq!(mints, HashSet::from(["mintB".to_string(), "mintC".to_string()]));
+    }
+
+    #[test]
+    fn test_clean_metadata_string() {
+        let usdc_name = "USD Coin\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        assert_eq!(clean_string(usdc_name), "USD Coin");
+
+        let usdc_symbol = "USDC\0\0\0\0\0\0";
+        assert_eq!(clean_string(usdc_symbol), "USDC");
+
+        let usdc_uri = "\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        assert_eq!(clean_string(usdc_uri), "");
+    }
+}
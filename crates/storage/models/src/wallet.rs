@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+/// The trades of a wallet in a token over the requested window
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct WalletActivity {
+    pub token: String,
+    pub trade_count: u64,
+    pub buy_base_amount: f64,
+    pub sell_base_amount: f64,
+    /// The bought minus the sold base amount, the change of the position
+    pub net_base_amount: f64,
+    /// The turnover of the buys, denoted as usd
+    pub buy_turnover: f64,
+    /// The turnover of the sells, denoted as usd
+    pub sell_turnover: f64,
+    pub last_trade_ts: u64,
+}
+
+/// The kinds of the wallets the analysts label, stored as their snake case names
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    Display,
+    EnumString,
+    utoipa::ToSchema
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum WalletCategory {
+    /// The hot wallets of the centralized exchanges
+    Cex,
+    /// The MEV bots, e.g. the sandwich bots
+    Mev,
+    /// The bots buying the tokens in their first blocks
+    Sniper,
+    /// The wallets deploying the tokens
+    Deployer,
+    Other,
+}
+
+/// A known wallet and what it is, the latest label of an address wins
+#[cfg_attr(feature = "clickhouse", derive(clickhouse::Row))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct WalletLabel {
+    pub address: String,
+    pub label: String,
+    /// The snake case name of the `WalletCategory`
+    pub category: String,
+    pub added_at: u64,
+}
@@ -1,33 +1,39 @@
 use crate::{
+    config::Config,
     datasource::build_pipeline,
-    handlers::health,
-    shutdown::shutdown_signal_with_handler,
-    ws::{on_connect, IoProxy},
+    handlers::{health, stats},
+    processor::WhaleMetrics,
+    ws::{on_connect, ConnectionTracker, IoProxy},
 };
 use anyhow::{Context, Result};
 use axum::{routing::get, Router};
 use carbon_core::datasource::Datasource;
-use socketioxide::SocketIo;
+use socketioxide::{handler::ConnectHandler, SocketIo};
+use sonar_auth::{authenticate, WsAuth};
+use sonar_db::{make_kv_store, shutdown::shutdown_signal_with_handler};
+use sonar_pipeline_metrics::PipelineMetrics;
 use std::sync::Arc;
 use std::{net::SocketAddr, str::FromStr};
 use tokio::net::TcpListener;
 use tracing::{error, info};
 
-#[derive(Clone, Default)]
-pub struct App;
+#[derive(Clone)]
+pub struct App {
+    config: Config,
+}
 
 impl App {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: Config) -> Self {
+        Self { config }
     }
 
-    pub fn get_port(&self) -> Result<u16> {
-        let port = std::env::var("PORT")
-            .context("PORT environment variable is not set")?
-            .parse::<u16>()
-            .context("Failed to parse PORT as a number")?;
+    /// from_env returns the app of the config of the env
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new(Config::from_env()?))
+    }
 
-        Ok(port)
+    pub fn get_port(&self) -> Result<u16> {
+        Ok(self.config.port)
     }
 
     pub async fn run<DS>(&self, datasources: Vec<DS>) -> Result<()>
@@ -37,17 +43,47 @@ impl App {
         let port = self.get_port()?;
         let addr = format!("0.0.0.0:{port}");
 
+        let tracker = Arc::new(ConnectionTracker::new(self.config.connection.clone()));
         let (layer, io) = SocketIo::builder()
             .max_payload(1024 * 1024 * 10) // 10MB max payload
             .max_buffer_size(128 * 10) // Increase from default 128 to 1280 packets
             .ws_read_buffer_size(64 * 1024) // Increase from default 4KB to 64KB
+            .with_state(tracker.clone())
+            .with_state(WsAuth::from_env()?)
             .build_layer();
-        io.ns("/", on_connect);
-
-        let io_proxy = IoProxy::new(Arc::new(io), None);
-        let app = Router::new().layer(layer).route("/health", get(health::get_health));
-
-        let mut pipeline = build_pipeline(datasources, Arc::new(io_proxy))?;
+        io.ns("/", on_connect.with(authenticate));
+
+        let io = Arc::new(io);
+        let io_proxy = IoProxy::new(io.clone(), None);
+        let whale_metrics = Arc::new(WhaleMetrics::default());
+        let pipeline_metrics = Arc::new(PipelineMetrics::new());
+        let stats_state = stats::WsStatsState {
+            io,
+            tracker,
+            whale_metrics: whale_metrics.clone(),
+            pipeline_metrics: pipeline_metrics.clone(),
+        };
+        let app = Router::new()
+            .layer(layer)
+            .route("/health", get(health::get_health))
+            .route("/ws-stats", get(stats::get_ws_stats))
+            .with_state(stats_state);
+
+        // the token risk flags are persisted for the api when a kv store is configured
+        let kv_store = match &self.config.redis_url {
+            Some(redis_url) => Some(Arc::new(
+                make_kv_store(redis_url).await.context("Failed to create KvStore client")?,
+            )),
+            None => None,
+        };
+        let mut pipeline = build_pipeline(
+            datasources,
+            Arc::new(io_proxy),
+            kv_store,
+            whale_metrics,
+            pipeline_metrics,
+            self.config.pipeline_channel_buffer_size,
+        )?;
 
         // Spawn pipeline in background
         tokio::spawn(async move {
@@ -0,0 +1,82 @@
+//! The settings of the streams service, read and validated once at startup
+use crate::ws::ConnectionConfig;
+use sonar_db::{ConfigError, EnvReader};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The port the server listens on
+    pub port: u16,
+    /// The redis the token risk flags are persisted to for the api, not persisted when unset
+    pub redis_url: Option<String>,
+    /// The capacity of the channel between the datasources and the pipeline
+    pub pipeline_channel_buffer_size: usize,
+    /// The heartbeat of the socket.io connections
+    pub connection: ConnectionConfig,
+}
+
+impl Config {
+    /// from_env reads `PORT`, `REDIS_URL`, `PIPELINE_CHANNEL_BUFFER_SIZE`,
+    /// `WS_PING_INTERVAL_SECS` and `WS_IDLE_TIMEOUT_SECS`, listing every invalid one
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_reader(EnvReader::from_env())
+    }
+
+    pub fn from_reader(mut env: EnvReader) -> Result<Self, ConfigError> {
+        let port = env.parse_required::<u16>("PORT");
+        let pipeline_channel_buffer_size = env.pipeline_channel_buffer_size();
+        let config = Self {
+            port: port.unwrap_or_default(),
+            redis_url: env.var("REDIS_URL"),
+            pipeline_channel_buffer_size,
+            connection: ConnectionConfig::read_env(&mut env),
+        };
+        env.finish(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE;
+    use std::time::Duration;
+
+    fn read(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        Config::from_reader(EnvReader::from_vars(vars))
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config = read(&[("PORT", "8081")]).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                port: 8081,
+                redis_url: None,
+                pipeline_channel_buffer_size: DEFAULT_PIPELINE_CHANNEL_BUFFER_SIZE,
+                connection: ConnectionConfig::default(),
+            }
+        );
+
+        let config = read(&[("PORT", "8081"), ("WS_PING_INTERVAL_SECS", "5")]).unwrap();
+        assert_eq!(config.connection.ping_interval, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_every_problem_is_listed() {
+        let error = read(&[
+            ("PIPELINE_CHANNEL_BUFFER_SIZE", "0"),
+            ("WS_PING_INTERVAL_SECS", "often"),
+            ("WS_IDLE_TIMEOUT_SECS", "0"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![
+                "PORT must be set",
+                "PIPELINE_CHANNEL_BUFFER_SIZE must not be 0",
+                "WS_PING_INTERVAL_SECS must be a positive number of seconds, got \"often\"",
+                "WS_IDLE_TIMEOUT_SECS must be a positive number of seconds, got \"0\"",
+            ]
+        );
+    }
+}
@@ -3,6 +3,7 @@ use crate::constants::{
     PUMP_SWAP_PROGRAM_ID, RAYDIUM_AMM_V4_PROGRAM_ID, RAYDIUM_CLMM_PROGRAM_ID,
     RAYDIUM_CPMM_PROGRAM_ID, SYSTEM_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID,
 };
+use anyhow::{Context, Result};
 use carbon_yellowstone_grpc_datasource::{BlockFilters, YellowstoneGrpcGeyserClient};
 use std::{
     collections::{HashMap, HashSet},
@@ -12,8 +13,8 @@ use std::{
 use tokio::sync::RwLock;
 use yellowstone_grpc_proto::geyser::{CommitmentLevel, SubscribeRequestFilterAccounts};
 
-pub fn make_geyser_datasource() -> YellowstoneGrpcGeyserClient {
-    let endpoint = var("GEYSER_URL").expect("GEYSER_URL is not set");
+pub fn make_geyser_datasource() -> Result<YellowstoneGrpcGeyserClient> {
+    let endpoint = var("GEYSER_URL").context("GEYSER_URL is not set")?;
     let x_token = var("GEYSER_X_TOKEN").ok();
 
     // Set up transaction filters to swap transactions
@@ -42,7 +43,7 @@ pub fn make_geyser_datasource() -> YellowstoneGrpcGeyserClient {
     let transaction_filters = Default::default();
     let block_filters = BlockFilters { filters: HashMap::new(), failed_transactions: Some(false) };
     let account_deletions_tracked = Arc::new(RwLock::new(HashSet::new()));
-    YellowstoneGrpcGeyserClient::new(
+    Ok(YellowstoneGrpcGeyserClient::new(
         endpoint,
         x_token,
         Some(CommitmentLevel::Processed),
@@ -50,5 +51,5 @@ pub fn make_geyser_datasource() -> YellowstoneGrpcGeyserClient {
         transaction_filters,
         block_filters,
         account_deletions_tracked,
-    )
+    ))
 }
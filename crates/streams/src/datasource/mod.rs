@@ -3,7 +3,7 @@ use crate::{
         MeteoraDammV2AccountProcessor, MeteoraDlmmAccountProcessor, MeteoraPoolsAccountProcessor,
         PumpSwapAccountProcessor, RaydiumAmmV4AccountProcessor, RaydiumClmmAccountProcessor,
         RaydiumCpmmAccountProcessor, SystemAccountProcessor, Token2022AccountProcessor,
-        TokenAccountProcessor,
+        TokenAccountProcessor, WhaleMetrics,
     },
     ws::IoProxy,
 };
@@ -24,6 +24,8 @@ use carbon_system_program_decoder::SystemProgramDecoder;
 use carbon_token_2022_decoder::Token2022Decoder;
 use carbon_token_program_decoder::TokenProgramDecoder;
 use socketioxide::adapter::Adapter;
+use sonar_db::KvStore;
+use sonar_pipeline_metrics::{spawn_throughput_monitor, PipelineMetrics, ThroughputConfig};
 use std::sync::Arc;
 use tracing::info;
 
@@ -36,16 +38,16 @@ pub use ws::make_ws_datasource;
 pub fn build_pipeline<DS, A: Adapter>(
     datasources: Vec<DS>,
     io_proxy: Arc<IoProxy<A>>,
+    kv_store: Option<Arc<KvStore>>,
+    whale_metrics: Arc<WhaleMetrics>,
+    pipeline_metrics: Arc<PipelineMetrics>,
+    channel_buffer_size: usize,
 ) -> Result<Pipeline>
 where
     DS: Datasource + Send + Sync + 'static,
 {
-    let channel_buffer_size = std::env::var("PIPELINE_CHANNEL_BUFFER_SIZE")
-        .unwrap_or_else(|_| "10000".to_string())
-        .parse::<usize>()
-        .unwrap_or(10_000);
-
     info!("Building pipeline with channel buffer size: {}", channel_buffer_size);
+    spawn_throughput_monitor("streams", pipeline_metrics.clone(), ThroughputConfig::from_env());
 
     let mut builder = Pipeline::builder();
     for ds in datasources.into_iter() {
@@ -55,18 +57,23 @@ where
     info!("Configuring pipeline with Token2022, Token, and System program decoders");
 
     let token_account_processor = TokenAccountProcessor::new(io_proxy.clone());
-    let token_2022_account_processor = Token2022AccountProcessor::new(io_proxy.clone());
-    let system_account_processor = SystemAccountProcessor::new(io_proxy.clone());
+    let token_2022_account_processor =
+        Token2022AccountProcessor::new(io_proxy.clone()).with_kv_store(kv_store.clone());
+    let system_account_processor =
+        SystemAccountProcessor::new(io_proxy.clone()).with_whale_metrics(whale_metrics);
     let raydium_amm_v4_account_processor = RaydiumAmmV4AccountProcessor::new(io_proxy.clone());
     let raydium_clmm_account_processor = RaydiumClmmAccountProcessor::new(io_proxy.clone());
     let raydium_cpmm_account_processor = RaydiumCpmmAccountProcessor::new(io_proxy.clone());
-    let meteora_dlmm_account_processor = MeteoraDlmmAccountProcessor::new(io_proxy.clone());
+    let meteora_dlmm_account_processor =
+        MeteoraDlmmAccountProcessor::new(io_proxy.clone()).with_kv_store(kv_store.clone());
     let meteora_pools_account_processor = MeteoraPoolsAccountProcessor::new(io_proxy.clone());
-    let meteora_damm_v2_account_processor = MeteoraDammV2AccountProcessor::new(io_proxy.clone());
+    let meteora_damm_v2_account_processor =
+        MeteoraDammV2AccountProcessor::new(io_proxy.clone()).with_kv_store(kv_store);
     let pump_swap_account_processor = PumpSwapAccountProcessor::new(io_proxy.clone());
 
     let pipeline: Pipeline = builder
         .metrics(Arc::new(LogMetrics::new()))
+        .metrics(pipeline_metrics)
         .shutdown_strategy(ShutdownStrategy::Immediate)
         .channel_buffer_size(channel_buffer_size)
         .account(TokenProgramDecoder, token_account_processor)
@@ -3,6 +3,7 @@ use crate::constants::{
     RAYDIUM_AMM_V4_PROGRAM_ID, RAYDIUM_CLMM_PROGRAM_ID, RAYDIUM_CPMM_PROGRAM_ID, SYSTEM_PROGRAM_ID,
     TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID,
 };
+use anyhow::{Context, Result};
 use carbon_rpc_program_subscribe_datasource::{Filters, RpcProgramSubscribe};
 use solana_account_decoder_client_types::UiAccountEncoding;
 use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
@@ -184,8 +185,8 @@ pub fn make_pump_swap_account_subscribe_filter() -> Filters {
 /// # Arguments
 ///
 /// * `rpc_ws_url` - The URL of the RPC websocket
-pub fn make_ws_datasource() -> RpcProgramSubscribe {
-    let rpc_ws_url = var("RPC_WS_URL").expect("RPC_WS_URL is not set");
+pub fn make_ws_datasource() -> Result<RpcProgramSubscribe> {
+    let rpc_ws_url = var("RPC_WS_URL").context("RPC_WS_URL is not set")?;
     let multiple_filters = vec![
         make_token_account_subscribe_filters(),
         make_token_2022_account_subscribe_filters(),
@@ -198,5 +199,5 @@ pub fn make_ws_datasource() -> RpcProgramSubscribe {
         make_meteora_damm_v2_account_subscribe_filters(),
         make_pump_swap_account_subscribe_filter(),
     ];
-    RpcProgramSubscribe::new(rpc_ws_url).with_multiple_filters(multiple_filters)
+    Ok(RpcProgramSubscribe::new(rpc_ws_url).with_multiple_filters(multiple_filters))
 }
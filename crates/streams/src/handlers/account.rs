@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
 use socketioxide::{
     adapter::Adapter,
-    extract::{Data, SocketRef},
+    extract::{AckSender, Data, SocketRef},
 };
+use sonar_auth::{ClientClaims, RoomPolicy};
+use std::sync::Arc;
+use tracing::warn;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AccountChange {
@@ -12,14 +15,25 @@ pub struct AccountChange {
 /// Subscribe on account change events for the given accounts.
 ///
 /// This handler is used to subscribe on account change events for the given accounts.
-/// It will join the socket to the given accounts.
+/// It will join the socket to the given accounts its tier may join, and ack an error
+/// listing the denied ones.
 ///
 /// # Arguments
 /// * `socket` - The socket to join the rooms to.
+/// * `claims` - The claims the socket connected with.
+/// * `policy` - The minimum tier of the rooms.
 pub async fn subscribe_on_account_change<A: Adapter>(
     socket: SocketRef<A>,
     Data(req): Data<AccountChange>,
+    claims: ClientClaims,
+    policy: Arc<RoomPolicy>,
+    ack: AckSender<A>,
 ) {
-    let rooms: Vec<String> = req.accounts;
+    let (rooms, error) = policy.authorize(&claims, req.accounts);
     socket.join(rooms);
+    if let Some(error) = error {
+        warn!(?socket.id, ?error, "Denied joining account rooms");
+        // the client may not have requested an ack
+        let _ = ack.send(&error);
+    }
 }
@@ -0,0 +1,50 @@
+use crate::{
+    processor::WhaleMetrics,
+    ws::{ConnectionStats, ConnectionTracker},
+};
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+use socketioxide::SocketIo;
+use sonar_pipeline_metrics::PipelineMetrics;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct WsStatsState {
+    pub io: Arc<SocketIo>,
+    pub tracker: Arc<ConnectionTracker>,
+    pub whale_metrics: Arc<WhaleMetrics>,
+    pub pipeline_metrics: Arc<PipelineMetrics>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct WsStatsResponse {
+    #[serde(flatten)]
+    pub connections: ConnectionStats,
+    pub rooms: usize,
+    /// The whale transfers emitted since the start
+    pub whale_alerts: u64,
+    /// The updates waiting in the channel of the pipeline
+    pub pipeline_queued: u64,
+    /// The updates the pipeline received but didn't finish processing
+    pub pipeline_backlog: u64,
+}
+
+/// Handler to get the websocket connection and room counts
+pub async fn get_ws_stats(State(state): State<WsStatsState>) -> Json<WsStatsResponse> {
+    let rooms = match state.io.rooms().await {
+        Ok(rooms) => rooms.len(),
+        Err(e) => {
+            warn!("Failed to get websocket rooms: {e}");
+            0
+        }
+    };
+    let pipeline = state.pipeline_metrics.stats();
+    Json(WsStatsResponse {
+        connections: state.tracker.metrics.stats(),
+        rooms,
+        whale_alerts: state.whale_metrics.alerts(),
+        pipeline_queued: pipeline.queued,
+        pipeline_backlog: pipeline.received.saturating_sub(pipeline.processed),
+    })
+}
@@ -1,9 +1,11 @@
 pub mod app;
+pub mod config;
 pub mod constants;
 pub mod datasource;
 pub mod handlers;
 pub mod processor;
-pub mod shutdown;
 pub mod ws;
 
 pub use carbon_core::datasource::Datasource;
+pub use config::Config;
+pub use sonar_db::shutdown;
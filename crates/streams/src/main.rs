@@ -13,8 +13,8 @@ async fn main() -> Result<()> {
 
     info!("Starting Streams service...");
 
-    let datasources = vec![make_ws_datasource()];
-    let app = App::new();
+    let datasources = vec![make_ws_datasource()?];
+    let app = App::from_env()?;
     app.run(datasources).await.context("Failed to run app")?;
 
     info!("Pipeline completed successfully");
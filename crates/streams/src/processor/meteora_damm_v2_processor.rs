@@ -1,19 +1,31 @@
-use crate::ws::IoProxy;
+use crate::{
+    processor::pool_price::{damm_v2_price, publish_pool_price, PoolPriceThrottle},
+    ws::IoProxy,
+};
 use carbon_core::{
     account::AccountProcessorInputType, error::CarbonResult, metrics::MetricsCollection,
     processor::Processor,
 };
 use carbon_meteora_damm_v2_decoder::accounts::MeteoraDammV2Account;
 use socketioxide::adapter::Adapter;
-use std::sync::Arc;
+use sonar_db::KvStore;
+use std::{sync::Arc, time::Instant};
 
 pub struct MeteoraDammV2AccountProcessor<A: Adapter> {
     io: Arc<IoProxy<A>>,
+    kv_store: Option<Arc<KvStore>>,
+    throttle: PoolPriceThrottle,
 }
 
 impl<A: Adapter> MeteoraDammV2AccountProcessor<A> {
     pub fn new(io: Arc<IoProxy<A>>) -> Self {
-        Self { io }
+        Self { io, kv_store: None, throttle: PoolPriceThrottle::default() }
+    }
+
+    /// Publish the sqrt price of the pool updates into the kv store
+    pub fn with_kv_store(mut self, kv_store: Option<Arc<KvStore>>) -> Self {
+        self.kv_store = kv_store;
+        self
     }
 }
 
@@ -29,6 +41,23 @@ impl<A: Adapter> Processor for MeteoraDammV2AccountProcessor<A> {
         let (meta, account, _solana_account) = data;
 
         if let MeteoraDammV2Account::Pool(pool) = account.data {
+            let pair = meta.pubkey.to_string();
+            if let Some(kv_store) = self.kv_store.clone() {
+                if self.throttle.allow(&pair, Instant::now()) {
+                    let base_mint = pool.token_a_mint.to_string();
+                    let quote_mint = pool.token_b_mint.to_string();
+                    let sqrt_price = pool.sqrt_price;
+                    tokio::spawn(async move {
+                        let price = |base, quote| damm_v2_price(sqrt_price, base, quote);
+                        if let Err(e) =
+                            publish_pool_price(&kv_store, &pair, &base_mint, &quote_mint, price)
+                                .await
+                        {
+                            tracing::warn!("Failed to publish Meteora DAMM v2 pool price: {}", e);
+                        }
+                    });
+                }
+            }
             if let Ok(value) = serde_json::to_value(&pool) {
                 let io = self.io.clone();
                 tokio::spawn(async move {
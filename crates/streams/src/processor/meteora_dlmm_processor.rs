@@ -1,19 +1,31 @@
-use crate::ws::IoProxy;
+use crate::{
+    processor::pool_price::{dlmm_price, publish_pool_price, PoolPriceThrottle},
+    ws::IoProxy,
+};
 use carbon_core::{
     account::AccountProcessorInputType, error::CarbonResult, metrics::MetricsCollection,
     processor::Processor,
 };
 use carbon_meteora_dlmm_decoder::accounts::MeteoraDlmmAccount;
 use socketioxide::adapter::Adapter;
-use std::sync::Arc;
+use sonar_db::KvStore;
+use std::{sync::Arc, time::Instant};
 
 pub struct MeteoraDlmmAccountProcessor<A: Adapter> {
     io: Arc<IoProxy<A>>,
+    kv_store: Option<Arc<KvStore>>,
+    throttle: PoolPriceThrottle,
 }
 
 impl<A: Adapter> MeteoraDlmmAccountProcessor<A> {
     pub fn new(io: Arc<IoProxy<A>>) -> Self {
-        Self { io }
+        Self { io, kv_store: None, throttle: PoolPriceThrottle::default() }
+    }
+
+    /// Publish the active bin price of the pair updates into the kv store
+    pub fn with_kv_store(mut self, kv_store: Option<Arc<KvStore>>) -> Self {
+        self.kv_store = kv_store;
+        self
     }
 }
 
@@ -29,11 +41,28 @@ impl<A: Adapter> Processor for MeteoraDlmmAccountProcessor<A> {
         let (meta, account, _solana_account) = data;
 
         if let MeteoraDlmmAccount::LbPair(lb_pair) = account.data {
+            let pair = meta.pubkey.to_string();
+            if let Some(kv_store) = self.kv_store.clone() {
+                if self.throttle.allow(&pair, Instant::now()) {
+                    let base_mint = lb_pair.token_x_mint.to_string();
+                    let quote_mint = lb_pair.token_y_mint.to_string();
+                    let (active_id, bin_step) = (lb_pair.active_id, lb_pair.bin_step);
+                    tokio::spawn(async move {
+                        let price = |base, quote| dlmm_price(active_id, bin_step, base, quote);
+                        if let Err(e) =
+                            publish_pool_price(&kv_store, &pair, &base_mint, &quote_mint, price)
+                                .await
+                        {
+                            tracing::warn!("Failed to publish Meteora DLMM pair price: {}", e);
+                        }
+                    });
+                }
+            }
             if let Ok(value) = serde_json::to_value(&lb_pair) {
                 let io = self.io.clone();
                 tokio::spawn(async move {
                     if let Err(e) = io.broadcast_account_change(&account.owner, meta, value).await {
-                        tracing::warn!("Failed to broadcast Meteora DAMM v2 pool update: {}", e);
+                        tracing::warn!("Failed to broadcast Meteora DLMM pair update: {}", e);
                     }
                 });
             }
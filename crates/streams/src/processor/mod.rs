@@ -7,6 +7,9 @@ pub use meteora_dlmm_processor::MeteoraDlmmAccountProcessor;
 pub mod meteora_pools_processor;
 pub use meteora_pools_processor::MeteoraPoolsAccountProcessor;
 
+pub mod pool_price;
+pub use pool_price::{damm_v2_price, dlmm_price};
+
 pub mod pump_swap_processor;
 pub use pump_swap_processor::PumpSwapAccountProcessor;
 
@@ -17,7 +20,7 @@ pub mod token_2022_account_processor;
 pub use token_2022_account_processor::Token2022AccountProcessor;
 
 pub mod system_processor;
-pub use system_processor::SystemAccountProcessor;
+pub use system_processor::{SystemAccountProcessor, WhaleConfig, WhaleMetrics};
 
 pub mod raydium_amm_v4_processor;
 pub use raydium_amm_v4_processor::RaydiumAmmV4AccountProcessor;
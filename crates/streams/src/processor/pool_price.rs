@@ -0,0 +1,243 @@
+use anyhow::Result;
+use sonar_db::{
+    is_major_mint, KvStore, PoolStatePrice, PriceSource, USDC_MINT, USDT_MINT, WSOL_MINT,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// A pool publishes its mid-price at most once per interval
+const POOL_PRICE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The most pools the throttle tracks, the pools updating faster are dropped until the
+/// entries older than `POOL_PRICE_INTERVAL` are evicted
+const MAX_THROTTLED_POOLS: usize = 100_000;
+
+/// dlmm_price returns the price of token x in token y at the active bin of a DLMM pair
+pub fn dlmm_price(active_id: i32, bin_step: u16, decimals_x: u8, decimals_y: u8) -> f64 {
+    let base = 1.0 + bin_step as f64 / 10_000.0;
+    base.powi(active_id) * 10f64.powi(decimals_x as i32 - decimals_y as i32)
+}
+
+/// damm_v2_price returns the price of token a in token b from the Q64.64 sqrt price of a
+/// DAMM v2 pool
+pub fn damm_v2_price(sqrt_price: u128, decimals_a: u8, decimals_b: u8) -> f64 {
+    let sqrt_price = sqrt_price as f64 / 2f64.powi(64);
+    sqrt_price * sqrt_price * 10f64.powi(decimals_a as i32 - decimals_b as i32)
+}
+
+/// PoolPriceThrottle lets each pool publish its mid-price once per `POOL_PRICE_INTERVAL`,
+/// tracking up to `MAX_THROTTLED_POOLS` pools
+#[derive(Debug, Default)]
+pub struct PoolPriceThrottle {
+    published: HashMap<String, Instant>,
+}
+
+impl PoolPriceThrottle {
+    /// allow returns true and records the publish when the pool didn't publish recently
+    pub fn allow(&mut self, pool: &str, now: Instant) -> bool {
+        if let Some(published) = self.published.get_mut(pool) {
+            if now.duration_since(*published) < POOL_PRICE_INTERVAL {
+                return false;
+            }
+            *published = now;
+            return true;
+        }
+        if self.published.len() >= MAX_THROTTLED_POOLS {
+            self.published
+                .retain(|_, published| now.duration_since(*published) < POOL_PRICE_INTERVAL);
+            if self.published.len() >= MAX_THROTTLED_POOLS {
+                return false;
+            }
+        }
+        self.published.insert(pool.to_string(), now);
+        true
+    }
+
+    /// len returns the number of tracked pools
+    pub fn len(&self) -> usize {
+        self.published.len()
+    }
+
+    /// is_empty returns true when no pool is tracked
+    pub fn is_empty(&self) -> bool {
+        self.published.is_empty()
+    }
+}
+
+/// get_quote_price returns the usd price of a quote mint, None for the other mints or while
+/// the SOL price is unknown
+async fn get_quote_price(kv_store: &KvStore, mint: &str) -> Result<Option<f64>> {
+    match mint {
+        WSOL_MINT => Ok(kv_store.get_latest_price(WSOL_MINT).await?.map(|(price, _)| price)),
+        mint if is_major_mint(mint) => Ok(Some(1.0)),
+        _ => Ok(None),
+    }
+}
+
+/// is_quote_mint returns true for the mints pricing the pools, WSOL and the majors
+fn is_quote_mint(mint: &str) -> bool {
+    mint == WSOL_MINT || is_major_mint(mint)
+}
+
+/// get_decimals returns the decimals of a mint, from the kv store unless it is a quote mint
+async fn get_decimals(kv_store: &KvStore, mint: &str) -> Result<Option<u8>> {
+    match mint {
+        WSOL_MINT => Ok(Some(9)),
+        USDC_MINT | USDT_MINT => Ok(Some(6)),
+        _ => Ok(kv_store.get_token(mint).await?.map(|token| token.decimals)),
+    }
+}
+
+/// publish_pool_price stores the mid-price of a pool as the pool state price of its token,
+/// `price` returns the price of the base mint in the quote mint from the base and quote
+/// decimals. The token is the mint of the pool which isn't WSOL nor a major, on either side.
+/// Pools of two quote mints, priced by their own feeds, of two tokens, or of unknown decimals
+/// are skipped
+pub async fn publish_pool_price(
+    kv_store: &KvStore,
+    pool: &str,
+    base_mint: &str,
+    quote_mint: &str,
+    price: impl FnOnce(u8, u8) -> f64,
+) -> Result<()> {
+    let (token, quote, inverted) = match (is_quote_mint(base_mint), is_quote_mint(quote_mint)) {
+        (false, true) => (base_mint, quote_mint, false),
+        (true, false) => (quote_mint, base_mint, true),
+        _ => return Ok(()),
+    };
+    let Some(quote_price) = get_quote_price(kv_store, quote).await? else {
+        return Ok(());
+    };
+    let (Some(base_decimals), Some(quote_decimals)) =
+        (get_decimals(kv_store, base_mint).await?, get_decimals(kv_store, quote_mint).await?)
+    else {
+        return Ok(());
+    };
+    let base_price = price(base_decimals, quote_decimals);
+    let price = if inverted { quote_price / base_price } else { base_price * quote_price };
+    if !price.is_finite() || price <= 0.0 {
+        return Ok(());
+    }
+    let price = PoolStatePrice {
+        pair: pool.to_string(),
+        price,
+        timestamp: chrono::Utc::now().timestamp() as u64,
+        source: PriceSource::PoolState,
+    };
+    kv_store.insert_pool_state_price(token, &price).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonar_db::{models::Token, SOL_USD_PAIR};
+
+    fn make_token(mint: &str, decimals: u8) -> Token {
+        Token { decimals, supply: 0.0, ..sonar_db::test_utils::make_token(mint, "", "") }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual / expected - 1.0).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_dlmm_price() {
+        // the active bin 0 is the price 1 in the smallest units
+        assert_close(dlmm_price(0, 10, 6, 6), 1.0);
+        // a SOL/USDC pair of bin step 10 at the active bin -1898
+        assert_close(dlmm_price(-1898, 10, 9, 6), 150.0102532544464);
+        // a token of 6 decimals quoted in SOL, bin step 25 at the active bin 100
+        assert_close(dlmm_price(100, 25, 6, 9), 1.283624888738461e-3);
+    }
+
+    #[test]
+    fn test_damm_v2_price() {
+        // the sqrt price 1 in Q64.64
+        assert_close(damm_v2_price(1 << 64, 9, 9), 1.0);
+        // a token of 6 decimals at 0.000001 SOL
+        assert_close(damm_v2_price(583_337_266_871_351_552, 6, 9), 1e-6);
+        // the min and max sqrt prices of the program
+        assert_close(damm_v2_price(4_295_048_016, 6, 6), 5.421214630269582e-20);
+        assert_close(
+            damm_v2_price(79_226_673_521_066_979_257_578_248_091, 6, 6),
+            1.844605071373595e19,
+        );
+    }
+
+    #[test]
+    fn test_pool_price_throttle() {
+        let mut throttle = PoolPriceThrottle::default();
+        let now = Instant::now();
+        assert!(throttle.allow("pool", now));
+        assert!(!throttle.allow("pool", now + Duration::from_millis(500)));
+        assert!(throttle.allow("other", now + Duration::from_millis(500)));
+        assert!(throttle.allow("pool", now + POOL_PRICE_INTERVAL));
+    }
+
+    #[test]
+    fn test_pool_price_throttle_is_bounded() {
+        let mut throttle = PoolPriceThrottle::default();
+        let now = Instant::now();
+        for pool in 0..MAX_THROTTLED_POOLS {
+            assert!(throttle.allow(&pool.to_string(), now));
+        }
+        // the tracked pools are all recent, a new pool waits for them to expire
+        let later = now + Duration::from_millis(500);
+        assert!(!throttle.allow("new", later));
+        assert!(throttle.allow("new", now + POOL_PRICE_INTERVAL));
+        assert_eq!(throttle.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_publish_pool_price() {
+        let kv_store = KvStore::in_memory();
+        kv_store.set_token("mint", &make_token("mint", 6)).await.unwrap();
+        let price = |base, quote| dlmm_price(100, 25, base, quote);
+
+        // the SOL price is unknown yet
+        publish_pool_price(&kv_store, "pool", "mint", WSOL_MINT, price).await.unwrap();
+        assert_eq!(kv_store.get_pool_state_price("mint").await.unwrap(), None);
+
+        // the SOL/USDC pools don't override the SOL price
+        publish_pool_price(&kv_store, "sol", WSOL_MINT, USDC_MINT, |_, _| 150.0).await.unwrap();
+        assert_eq!(kv_store.get_pool_state_price(WSOL_MINT).await.unwrap(), None);
+
+        // a pool quoted in WSOL is converted with the SOL price
+        let sol = PoolStatePrice {
+            pair: SOL_USD_PAIR.to_string(),
+            price: 150.0,
+            timestamp: 1,
+            source: PriceSource::PoolState,
+        };
+        kv_store.insert_pool_state_price(WSOL_MINT, &sol).await.unwrap();
+        publish_pool_price(&kv_store, "pool", "mint", WSOL_MINT, price).await.unwrap();
+        let stored = kv_store.get_pool_state_price("mint").await.unwrap().unwrap();
+        assert_close(stored.price, 1.283624888738461e-3 * 150.0);
+        assert_eq!((stored.pair.as_str(), stored.source), ("pool", PriceSource::PoolState));
+
+        // a pool quoted in a major needs no conversion
+        publish_pool_price(&kv_store, "usdc", "mint", USDC_MINT, |_, _| 0.25).await.unwrap();
+        assert_close(kv_store.get_pool_state_price("mint").await.unwrap().unwrap().price, 0.25);
+
+        // a pool of WSOL as x and the token as y prices the token by the inverse
+        publish_pool_price(&kv_store, "inverted", WSOL_MINT, "mint", |base, quote| {
+            assert_eq!((base, quote), (9, 6));
+            1000.0
+        })
+        .await
+        .unwrap();
+        let stored = kv_store.get_pool_state_price("mint").await.unwrap().unwrap();
+        assert_close(stored.price, 150.0 / 1000.0);
+        assert_eq!(stored.pair, "inverted");
+
+        // the unknown tokens and the pools of two tokens are skipped
+        publish_pool_price(&kv_store, "pool", "unknown", WSOL_MINT, price).await.unwrap();
+        assert_eq!(kv_store.get_pool_state_price("unknown").await.unwrap(), None);
+        kv_store.set_token("other", &make_token("other", 6)).await.unwrap();
+        publish_pool_price(&kv_store, "tokens", "mint", "other", |_, _| 2.0).await.unwrap();
+        assert_eq!(kv_store.get_pool_state_price("other").await.unwrap(), None);
+        assert_eq!(kv_store.get_pool_state_price("mint").await.unwrap().unwrap().pair, "inverted");
+    }
+}
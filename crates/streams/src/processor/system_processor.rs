@@ -1,19 +1,189 @@
-use crate::ws::IoProxy;
+use crate::ws::{event::WhaleTransferEvent, IoProxy};
 use carbon_core::{
     account::AccountProcessorInputType, error::CarbonResult, metrics::MetricsCollection,
     processor::Processor,
 };
 use carbon_system_program_decoder::accounts::SystemAccount;
 use socketioxide::adapter::Adapter;
-use std::sync::Arc;
+use solana_pubkey::Pubkey;
+use sonar_db::EnvReader;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+const DEFAULT_WHALE_SOL_THRESHOLD: u64 = 1000;
+const DEFAULT_WHALE_MAX_ACCOUNTS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhaleConfig {
+    /// A balance change of at least this many lamports is a whale transfer
+    pub threshold_lamports: u64,
+    /// How many accounts the last seen balances are kept for
+    pub max_accounts: usize,
+}
+
+impl Default for WhaleConfig {
+    fn default() -> Self {
+        Self {
+            threshold_lamports: DEFAULT_WHALE_SOL_THRESHOLD * LAMPORTS_PER_SOL,
+            max_accounts: DEFAULT_WHALE_MAX_ACCOUNTS,
+        }
+    }
+}
+
+impl WhaleConfig {
+    /// Create a whale config from `WHALE_SOL_THRESHOLD` and `WHALE_MAX_ACCOUNTS`,
+    /// the invalid values fall back to the defaults
+    pub fn from_env() -> Self {
+        Self::read_env(&mut EnvReader::from_env())
+    }
+
+    /// read_env reads the variables of `from_env`, recording the invalid ones
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let threshold_sol =
+            env.parse::<f64>("WHALE_SOL_THRESHOLD").unwrap_or(DEFAULT_WHALE_SOL_THRESHOLD as f64);
+        Self {
+            threshold_lamports: (threshold_sol * LAMPORTS_PER_SOL as f64) as u64,
+            max_accounts: env
+                .parse::<usize>("WHALE_MAX_ACCOUNTS")
+                .unwrap_or(DEFAULT_WHALE_MAX_ACCOUNTS),
+        }
+    }
+}
+
+/// The count of the emitted whale alerts
+#[derive(Debug, Default)]
+pub struct WhaleMetrics {
+    pub alerts: AtomicU64,
+}
+
+impl WhaleMetrics {
+    pub fn record_alert(&self) {
+        self.alerts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn alerts(&self) -> u64 {
+        self.alerts.load(Ordering::Relaxed)
+    }
+}
+
+/// The last seen balance of an account
+#[derive(Debug, Clone, Copy)]
+struct SeenBalance {
+    lamports: u64,
+    slot: u64,
+    /// The slot of the last alert, at most one alert is emitted per slot
+    alert_slot: Option<u64>,
+    /// The position of the account in the recency order
+    tick: u64,
+}
+
+/// WhaleDetector turns the balances of the account updates into balance changes,
+/// the balances are kept for the most recently updated accounts only
+#[derive(Debug)]
+pub struct WhaleDetector {
+    config: WhaleConfig,
+    balances: HashMap<Pubkey, SeenBalance>,
+    /// The accounts by tick, the first is the least recently updated
+    recency: BTreeMap<u64, Pubkey>,
+    next_tick: u64,
+}
+
+impl WhaleDetector {
+    pub fn new(config: WhaleConfig) -> Self {
+        Self { config, balances: HashMap::new(), recency: BTreeMap::new(), next_tick: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.balances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.balances.is_empty()
+    }
+
+    /// observe records the balance of an account at a slot, returns the whale transfer when
+    /// it changed by at least the threshold since the last seen balance. The updates older
+    /// than the last seen slot are ignored
+    pub fn observe(
+        &mut self,
+        account: Pubkey,
+        lamports: u64,
+        slot: u64,
+    ) -> Option<WhaleTransferEvent> {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+
+        let Some(seen) = self.balances.get_mut(&account) else {
+            self.balances.insert(account, SeenBalance { lamports, slot, alert_slot: None, tick });
+            self.recency.insert(tick, account);
+            self.evict();
+            return None;
+        };
+        if slot < seen.slot {
+            return None;
+        }
+        self.recency.remove(&seen.tick);
+        self.recency.insert(tick, account);
+
+        let delta = lamports as i128 - seen.lamports as i128;
+        seen.lamports = lamports;
+        seen.slot = slot;
+        seen.tick = tick;
+        if delta.unsigned_abs() < self.config.threshold_lamports as u128
+            || seen.alert_slot == Some(slot)
+        {
+            return None;
+        }
+        seen.alert_slot = Some(slot);
+        Some(WhaleTransferEvent {
+            account: account.to_string(),
+            delta_sol: delta as f64 / LAMPORTS_PER_SOL as f64,
+            slot,
+        })
+    }
+
+    /// Drops the least recently updated accounts above the capacity
+    fn evict(&mut self) {
+        while self.balances.len() > self.config.max_accounts.max(1) {
+            let Some((_, account)) = self.recency.pop_first() else {
+                break;
+            };
+            self.balances.remove(&account);
+        }
+    }
+}
 
 pub struct SystemAccountProcessor<A: Adapter> {
     io: Arc<IoProxy<A>>,
+    whales: WhaleDetector,
+    whale_metrics: Arc<WhaleMetrics>,
 }
 
 impl<A: Adapter> SystemAccountProcessor<A> {
     pub fn new(io: Arc<IoProxy<A>>) -> Self {
-        Self { io }
+        Self {
+            io,
+            whales: WhaleDetector::new(WhaleConfig::from_env()),
+            whale_metrics: Arc::new(WhaleMetrics::default()),
+        }
+    }
+
+    /// set the thresholds of the whale transfers
+    pub fn with_whale_config(mut self, config: WhaleConfig) -> Self {
+        self.whales = WhaleDetector::new(config);
+        self
+    }
+
+    /// set the metrics counting the whale alerts
+    pub fn with_whale_metrics(mut self, whale_metrics: Arc<WhaleMetrics>) -> Self {
+        self.whale_metrics = whale_metrics;
+        self
     }
 }
 
@@ -28,6 +198,16 @@ impl<A: Adapter> Processor for SystemAccountProcessor<A> {
     ) -> CarbonResult<()> {
         let (meta, account, solana_account) = data;
 
+        if let Some(event) = self.whales.observe(meta.pubkey, solana_account.lamports, meta.slot) {
+            self.whale_metrics.record_alert();
+            let io = self.io.clone();
+            tokio::spawn(async move {
+                if let Err(e) = io.broadcast_whale_transfer(&event).await {
+                    tracing::warn!("Failed to broadcast whale transfer: {}", e);
+                }
+            });
+        }
+
         if let SystemAccount::Legacy(_) = account.data {
             if let Ok(value) = serde_json::to_value(solana_account) {
                 let io = self.io.clone();
@@ -42,3 +222,125 @@ impl<A: Adapter> Processor for SystemAccountProcessor<A> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use carbon_core::account::{AccountDecoder, AccountMetadata};
+    use carbon_system_program_decoder::SystemProgramDecoder;
+    use socketioxide::SocketIo;
+
+    const SOL: u64 = LAMPORTS_PER_SOL;
+
+    fn detector(max_accounts: usize) -> WhaleDetector {
+        WhaleDetector::new(WhaleConfig { threshold_lamports: 1000 * SOL, max_accounts })
+    }
+
+    #[test]
+    fn test_whale_threshold() {
+        let mut whales = detector(10);
+        let account = Pubkey::new_unique();
+        // the first balance has nothing to compare with
+        assert_eq!(whales.observe(account, 5000 * SOL, 1), None);
+        assert_eq!(whales.observe(account, 5999 * SOL, 2), None);
+
+        let event = whales.observe(account, 4000 * SOL, 3).expect("Expected a whale transfer");
+        assert_eq!(event.account, account.to_string());
+        assert_eq!((event.delta_sol, event.slot), (-1999.0, 3));
+        assert_eq!(whales.observe(account, 5000 * SOL, 4).unwrap().delta_sol, 1000.0);
+    }
+
+    #[test]
+    fn test_whale_dedup_per_slot() {
+        let mut whales = detector(10);
+        let account = Pubkey::new_unique();
+        whales.observe(account, 0, 1);
+        assert!(whales.observe(account, 2000 * SOL, 2).is_some());
+        // the same slot delivered twice, or a second change within the slot
+        assert_eq!(whales.observe(account, 2000 * SOL, 2), None);
+        assert_eq!(whales.observe(account, 4000 * SOL, 2), None);
+        // an update older than the last seen slot is ignored
+        assert_eq!(whales.observe(account, 0, 1), None);
+        assert!(whales.observe(account, 0, 3).is_some());
+    }
+
+    #[test]
+    fn test_whale_lru_eviction() {
+        let mut whales = detector(2);
+        let (first, second, third) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        whales.observe(first, 0, 1);
+        whales.observe(second, 0, 1);
+        // the first account is updated last, the second is evicted
+        whales.observe(first, 0, 2);
+        whales.observe(third, 0, 2);
+        assert_eq!(whales.len(), 2);
+
+        // without its last balance, the second account starts over and evicts the first
+        assert_eq!(whales.observe(second, 5000 * SOL, 3), None);
+        assert!(!whales.balances.contains_key(&first));
+        assert!(whales.observe(third, 5000 * SOL, 3).is_some());
+    }
+
+    #[test]
+    fn test_whale_config_from_env() {
+        let config = WhaleConfig::read_env(&mut EnvReader::from_vars(&[
+            ("WHALE_SOL_THRESHOLD", "2.5"),
+            ("WHALE_MAX_ACCOUNTS", "20"),
+        ]));
+        assert_eq!(config, WhaleConfig { threshold_lamports: 2_500_000_000, max_accounts: 20 });
+
+        // the number of accounts is a count, a fraction is invalid
+        let mut env = EnvReader::from_vars(&[("WHALE_MAX_ACCOUNTS", "1.5")]);
+        assert_eq!(WhaleConfig::read_env(&mut env), WhaleConfig::default());
+        assert!(env.finish(()).is_err());
+    }
+
+    /// system_account returns the input of the processor, a nonce account holding `lamports`
+    fn system_account(
+        pubkey: Pubkey,
+        lamports: u64,
+        slot: u64,
+    ) -> AccountProcessorInputType<SystemAccount> {
+        // a current, uninitialized nonce account
+        let mut data = vec![0; 80];
+        data[0] = 1;
+        let account = solana_account::Account {
+            lamports,
+            data,
+            owner: solana_program::system_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        };
+        let decoded =
+            SystemProgramDecoder.decode_account(&account).expect("Failed to decode nonce account");
+        (AccountMetadata { slot, pubkey, transaction_signature: None }, decoded, account)
+    }
+
+    #[tokio::test]
+    async fn test_processor_alerts_whales() {
+        let (_svc, io) = SocketIo::builder().build_svc();
+        let whale_metrics = Arc::new(WhaleMetrics::default());
+        let mut processor = SystemAccountProcessor::new(Arc::new(IoProxy::new(Arc::new(io), None)))
+            .with_whale_config(WhaleConfig { threshold_lamports: 1000 * SOL, max_accounts: 10 })
+            .with_whale_metrics(whale_metrics.clone());
+        let metrics = Arc::new(MetricsCollection::new(vec![]));
+        let (whale, shrimp) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let updates = [
+            (whale, 5000 * SOL, 1),
+            (shrimp, 10 * SOL, 1),
+            (whale, 2000 * SOL, 2),
+            (shrimp, 20 * SOL, 2),
+            // the same slot again
+            (whale, 0, 2),
+            (whale, 3000 * SOL, 3),
+        ];
+        for (pubkey, lamports, slot) in updates {
+            let input = system_account(pubkey, lamports, slot);
+            processor.process(input, metrics.clone()).await.unwrap();
+        }
+        assert_eq!(whale_metrics.alerts(), 2);
+        assert_eq!(processor.whales.len(), 2);
+    }
+}
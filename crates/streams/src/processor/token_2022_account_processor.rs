@@ -1,19 +1,28 @@
-use crate::ws::IoProxy;
+use crate::ws::{event::TokenRiskEvent, IoProxy};
 use carbon_core::{
     account::AccountProcessorInputType, error::CarbonResult, metrics::MetricsCollection,
     processor::Processor,
 };
 use carbon_token_2022_decoder::accounts::Token2022Account;
 use socketioxide::adapter::Adapter;
+use sonar_db::KvStore;
+use sonar_token_metadata::get_token_risk_flags;
 use std::sync::Arc;
 
 pub struct Token2022AccountProcessor<A: Adapter> {
     io: Arc<IoProxy<A>>,
+    kv_store: Option<Arc<KvStore>>,
 }
 
 impl<A: Adapter> Token2022AccountProcessor<A> {
     pub fn new(io: Arc<IoProxy<A>>) -> Self {
-        Self { io }
+        Self { io, kv_store: None }
+    }
+
+    /// Persist the risk flags of mint updates into the kv store
+    pub fn with_kv_store(mut self, kv_store: Option<Arc<KvStore>>) -> Self {
+        self.kv_store = kv_store;
+        self
     }
 }
 
@@ -28,17 +37,44 @@ impl<A: Adapter> Processor for Token2022AccountProcessor<A> {
     ) -> CarbonResult<()> {
         let (meta, account, solana_account) = data;
 
-        if let Token2022Account::Token(account) = account.data {
-            if let Ok(value) = serde_json::to_value(account) {
+        match account.data {
+            Token2022Account::Token(account) => {
+                if let Ok(value) = serde_json::to_value(account) {
+                    let io = self.io.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            io.broadcast_account_change(&solana_account.owner, meta, value).await
+                        {
+                            tracing::warn!("Failed to broadcast token 2022 account: {}", e);
+                        }
+                    });
+                }
+            }
+            Token2022Account::Mint(_) => {
+                let flags = match get_token_risk_flags(&solana_account.data) {
+                    Ok(flags) => flags,
+                    Err(e) => {
+                        tracing::warn!("Failed to decode mint extensions {}: {}", meta.pubkey, e);
+                        return Ok(());
+                    }
+                };
+                let event = TokenRiskEvent { mint: meta.pubkey.to_string(), flags };
                 let io = self.io.clone();
+                let kv_store = self.kv_store.clone();
                 tokio::spawn(async move {
-                    if let Err(e) =
-                        io.broadcast_account_change(&solana_account.owner, meta, value).await
-                    {
-                        tracing::warn!("Failed to broadcast token 2022 account: {}", e);
+                    if let Some(kv_store) = kv_store {
+                        if let Err(e) =
+                            kv_store.set_token_risk_flags(&event.mint, &event.flags).await
+                        {
+                            tracing::warn!("Failed to persist token risk flags: {}", e);
+                        }
+                    }
+                    if let Err(e) = io.broadcast_token_risk(&event).await {
+                        tracing::warn!("Failed to broadcast token risk: {}", e);
                     }
                 });
             }
+            _ => {}
         }
         Ok(())
     }
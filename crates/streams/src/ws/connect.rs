@@ -1,20 +1,285 @@
-use crate::handlers::account::subscribe_on_account_change;
+use crate::handlers::account::{subscribe_on_account_change, AccountChange};
 pub use crate::ws::event::RequestEvent;
-use socketioxide::{adapter::Adapter, extract::SocketRef};
-use tracing::{info, warn};
+use serde::Serialize;
+use socketioxide::{
+    adapter::Adapter,
+    extract::{AckSender, Data, Extension, SocketRef, State},
+};
+use sonar_auth::{ClientClaims, WsAuth};
+use sonar_db::EnvReader;
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+const DEFAULT_PING_INTERVAL_SECS: u64 = 25;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
+
+/// Application-level heartbeat settings, independent of the transport ping
+/// which proxies keep alive on behalf of backgrounded clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    /// How often the server emits a `ping` event to each socket
+    pub ping_interval: Duration,
+    /// Sockets that haven't sent any event within this duration are disconnected
+    pub idle_timeout: Duration,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL_SECS),
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Create a connection config from `WS_PING_INTERVAL_SECS` and `WS_IDLE_TIMEOUT_SECS`
+    /// the invalid values fall back to the defaults
+    pub fn from_env() -> Self {
+        Self::read_env(&mut EnvReader::from_env())
+    }
+
+    /// read_env reads the variables of `from_env`, recording the invalid ones
+    pub fn read_env(env: &mut EnvReader) -> Self {
+        let defaults = Self::default();
+        Self {
+            ping_interval: env.secs("WS_PING_INTERVAL_SECS").unwrap_or(defaults.ping_interval),
+            idle_timeout: env.secs("WS_IDLE_TIMEOUT_SECS").unwrap_or(defaults.idle_timeout),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    pub connects: AtomicU64,
+    pub disconnects: AtomicU64,
+    pub idle_disconnects: AtomicU64,
+    pub connections: AtomicI64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnectionStats {
+    pub connects: u64,
+    pub disconnects: u64,
+    pub idle_disconnects: u64,
+    pub connections: i64,
+}
+
+impl ConnectionMetrics {
+    pub fn record_connect(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_disconnect(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn record_idle_disconnect(&self) {
+        self.idle_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            connects: self.connects.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+            idle_disconnects: self.idle_disconnects.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Shared socket.io state tracking the lifecycle of client connections
+#[derive(Debug)]
+pub struct ConnectionTracker {
+    pub config: ConnectionConfig,
+    pub metrics: ConnectionMetrics,
+    started_at: Instant,
+}
+
+impl ConnectionTracker {
+    pub fn new(config: ConnectionConfig) -> Self {
+        Self { config, metrics: ConnectionMetrics::default(), started_at: Instant::now() }
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+
+    /// Mark the socket owning `last_seen` as active
+    fn touch(&self, last_seen: &AtomicU64) {
+        last_seen.store(self.now_millis(), Ordering::Relaxed);
+    }
+
+    /// Returns true if the socket owning `last_seen` exceeded the idle timeout
+    fn is_idle(&self, last_seen: &AtomicU64) -> bool {
+        let idle_millis = self.now_millis().saturating_sub(last_seen.load(Ordering::Relaxed));
+        idle_millis > self.config.idle_timeout.as_millis() as u64
+    }
+}
 
 /// Called when a client connects to the server
 pub fn on_connect<A: Adapter>(
     socket: SocketRef<A>,
-    // Data(data): Data<Value>, // auth data
-    // State(state): State<AppState>,
+    State(tracker): State<Arc<ConnectionTracker>>,
+    State(auth): State<WsAuth>,
 ) {
     info!(ns = socket.ns(), ?socket.id, "Websocket connected");
-    socket.on(RequestEvent::AccountChange.to_string(), subscribe_on_account_change);
+    tracker.metrics.record_connect();
+
+    let last_seen = Arc::new(AtomicU64::new(tracker.now_millis()));
+
+    let (account_tracker, account_last_seen) = (tracker.clone(), last_seen.clone());
+    socket.on(
+        RequestEvent::AccountChange.to_string(),
+        move |socket: SocketRef<A>,
+              data: Data<AccountChange>,
+              Extension(claims): Extension<ClientClaims>,
+              ack: AckSender<A>| {
+            account_tracker.touch(&account_last_seen);
+            subscribe_on_account_change(socket, data, claims, auth.policy.clone(), ack)
+        },
+    );
+
+    let (ping_tracker, ping_last_seen) = (tracker.clone(), last_seen.clone());
+    socket.on(RequestEvent::Ping.to_string(), move |socket: SocketRef<A>| {
+        ping_tracker.touch(&ping_last_seen);
+        if let Err(e) = socket.emit(RequestEvent::Pong.to_string(), &()) {
+            warn!(?socket.id, "Failed to emit pong: {e}");
+        }
+    });
+
+    let (fallback_tracker, fallback_last_seen) = (tracker.clone(), last_seen.clone());
+    socket.on_fallback(move |_socket: SocketRef<A>| {
+        fallback_tracker.touch(&fallback_last_seen);
+    });
+
     socket.on_disconnect(on_disconnect);
+
+    tokio::spawn(disconnect_idle_socket(socket, tracker, last_seen));
 }
 
 /// Called when a client disconnects from the server
-pub async fn on_disconnect<A: Adapter>(socket: SocketRef<A>) {
+pub async fn on_disconnect<A: Adapter>(
+    socket: SocketRef<A>,
+    State(tracker): State<Arc<ConnectionTracker>>,
+) {
+    tracker.metrics.record_disconnect();
     warn!(ns = socket.ns(), ?socket.id, "Websocket disconnected");
 }
+
+/// Emit a `ping` event every ping interval and disconnect the socket once it
+/// hasn't sent any event within the idle timeout.
+async fn disconnect_idle_socket<A: Adapter>(
+    socket: SocketRef<A>,
+    tracker: Arc<ConnectionTracker>,
+    last_seen: Arc<AtomicU64>,
+) {
+    let mut interval = tokio::time::interval(tracker.config.ping_interval);
+    // the first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if !socket.connected() {
+            break;
+        }
+
+        if tracker.is_idle(&last_seen) {
+            info!(ns = socket.ns(), ?socket.id, "Disconnecting idle websocket");
+            tracker.metrics.record_idle_disconnect();
+            if let Err(e) = socket.disconnect() {
+                error!("Failed to disconnect idle websocket: {e}");
+            }
+            break;
+        }
+
+        if let Err(e) = socket.emit(RequestEvent::Ping.to_string(), &()) {
+            warn!(?socket.id, "Failed to emit ping: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socketioxide::{handler::ConnectHandler, SocketIo};
+    use sonar_auth::{authenticate, HmacAuthorizer, RoomPolicy, Tier, TokenClaims};
+
+    #[test]
+    fn test_connection_metrics() {
+        let metrics = ConnectionMetrics::default();
+        metrics.record_connect();
+        metrics.record_connect();
+        metrics.record_disconnect();
+        metrics.record_idle_disconnect();
+
+        let stats = metrics.stats();
+        assert_eq!(stats.connects, 2);
+        assert_eq!(stats.disconnects, 1);
+        assert_eq!(stats.idle_disconnects, 1);
+        assert_eq!(stats.connections, 1);
+    }
+
+    #[tokio::test]
+    async fn test_idle_disconnect() {
+        let config = ConnectionConfig {
+            ping_interval: Duration::from_millis(20),
+            idle_timeout: Duration::from_millis(50),
+        };
+        let tracker = Arc::new(ConnectionTracker::new(config));
+        let (_svc, io) = SocketIo::builder()
+            .with_state(tracker.clone())
+            .with_state(WsAuth::default())
+            .build_svc();
+        io.ns("/", on_connect.with(authenticate));
+
+        let (_tx1, _rx1) = io.new_dummy_sock("/", ()).await;
+        let (_tx2, _rx2) = io.new_dummy_sock("/", ()).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(tracker.metrics.stats().connections, 2);
+
+        // the pings run on the clock, wait for both disconnects instead of a fixed delay
+        let started_at = Instant::now();
+        while tracker.metrics.stats().idle_disconnects < 2
+            && started_at.elapsed() < Duration::from_secs(5)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let stats = tracker.metrics.stats();
+        assert_eq!(stats.connects, 2);
+        assert_eq!(stats.idle_disconnects, 2);
+        assert_eq!(stats.disconnects, 2);
+        assert_eq!(stats.connections, 0);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_rejects_invalid_tokens() {
+        let tracker = Arc::new(ConnectionTracker::new(ConnectionConfig::default()));
+        let authorizer = HmacAuthorizer::new("secret");
+        let auth = WsAuth::new(Arc::new(authorizer.clone()), RoomPolicy::default());
+        let (_svc, io) =
+            SocketIo::builder().with_state(tracker.clone()).with_state(auth).build_svc();
+        io.ns("/", on_connect.with(authenticate));
+
+        let token = authorizer.sign(&TokenClaims {
+            sub: "user".to_string(),
+            tier: Tier::Premium,
+            exp: u64::MAX,
+        });
+        let (_tx1, _rx1) = io.new_dummy_sock("/", serde_json::json!({ "token": token })).await;
+        let (_tx2, _rx2) = io.new_dummy_sock("/", ()).await;
+        let (_tx3, _rx3) = io.new_dummy_sock("/", serde_json::json!({ "token": "forged" })).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // the anonymous client connects, the forged token is rejected
+        assert_eq!(tracker.metrics.stats().connects, 2);
+    }
+}
@@ -8,6 +8,7 @@ use carbon_raydium_clmm_decoder::accounts::pool_state::PoolState as RaydiumClmmP
 use carbon_raydium_cpmm_decoder::accounts::pool_state::PoolState as RaydiumCpmmPoolState;
 use carbon_token_2022_decoder::accounts::token::Token;
 use serde::{Deserialize, Serialize};
+use sonar_db::{Dexes, TokenRiskFlags};
 use spl_token::state::Account as TokenAccount;
 
 #[derive(Debug, Eq, PartialEq, strum_macros::Display)]
@@ -18,6 +19,14 @@ pub enum RequestEvent {
     TokenHolder,
     #[strum(to_string = "lp")]
     Lp,
+    #[strum(to_string = "token_risk")]
+    TokenRisk,
+    #[strum(to_string = "whale_transfer")]
+    WhaleTransfer,
+    #[strum(to_string = "ping")]
+    Ping,
+    #[strum(to_string = "pong")]
+    Pong,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -80,9 +89,26 @@ impl TokenHolderEvent {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TokenRiskEvent {
+    pub mint: String,
+    #[serde(flatten)]
+    pub flags: TokenRiskFlags,
+}
+
+/// A native SOL balance change above the whale threshold, emitted to the `whales` room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WhaleTransferEvent {
+    pub account: String,
+    /// The balance change, negative when SOL left the account
+    pub delta_sol: f64,
+    pub slot: u64,
+}
+
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct LpEvent {
     pub lp: String,
+    pub dex: Dexes,
     pub base_mint: String,
     pub quote_mint: String,
 }
@@ -91,6 +117,7 @@ impl LpEvent {
     pub fn from_meteora_damm_v2(meta: &AccountMetadata, pool: &MeteoraDammV2Pool) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::MeteoraDammV2,
             base_mint: pool.token_a_mint.to_string(),
             quote_mint: pool.token_b_mint.to_string(),
         }
@@ -99,6 +126,7 @@ impl LpEvent {
     pub fn from_meteora_dlmm(meta: &AccountMetadata, lb_pair: &MeteoraDlmmLbPair) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::MeteoraDlmm,
             base_mint: lb_pair.token_x_mint.to_string(),
             quote_mint: lb_pair.token_y_mint.to_string(),
         }
@@ -107,6 +135,7 @@ impl LpEvent {
     pub fn from_meteora_pool(meta: &AccountMetadata, pool: &MeteoraPoolsPool) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::MeteoraPools,
             base_mint: pool.token_a_mint.to_string(),
             quote_mint: pool.token_b_mint.to_string(),
         }
@@ -115,6 +144,7 @@ impl LpEvent {
     pub fn from_pump_swap(meta: &AccountMetadata, pool: &PumpSwapPool) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::PumpAmm,
             base_mint: pool.base_mint.to_string(),
             quote_mint: pool.quote_mint.to_string(),
         }
@@ -123,6 +153,7 @@ impl LpEvent {
     pub fn from_raydium_amm_v4(meta: &AccountMetadata, amm_info: &RaydiumAmmV4AmmInfo) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::RaydiumAmmV4,
             base_mint: amm_info.coin_mint.to_string(),
             quote_mint: amm_info.pc_mint.to_string(),
         }
@@ -131,6 +162,7 @@ impl LpEvent {
     pub fn from_raydium_clmm(meta: &AccountMetadata, pool: &RaydiumClmmPoolState) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::RaydiumClmm,
             base_mint: pool.token_mint0.to_string(),
             quote_mint: pool.token_mint1.to_string(),
         }
@@ -139,6 +171,7 @@ impl LpEvent {
     pub fn from_raydium_cpmm(meta: &AccountMetadata, pool: &RaydiumCpmmPoolState) -> Self {
         LpEvent {
             lp: meta.pubkey.to_string(),
+            dex: Dexes::RaydiumCpmm,
             base_mint: pool.token0_mint.to_string(),
             quote_mint: pool.token1_mint.to_string(),
         }
@@ -1,10 +1,14 @@
-use crate::ws::event::{LpEvent, RequestEvent, TokenHolderEvent};
+use crate::ws::event::{
+    LpEvent, RequestEvent, TokenHolderEvent, TokenRiskEvent, WhaleTransferEvent,
+};
 use carbon_core::account::AccountMetadata;
 use serde_json::{json, Value};
 use socketioxide::{adapter::Adapter, BroadcastError, SocketIo};
 use std::sync::Arc;
 
 pub const CHANNEL_BUFFER_SIZE: usize = 4 * 1000; // 4k
+/// The room of the whale transfers, joined like the account rooms
+pub const WHALES_ROOM: &str = "whales";
 
 #[derive(Clone)]
 pub struct IoProxy<A: Adapter> {
@@ -48,6 +52,21 @@ impl<A: Adapter> IoProxy<A> {
         Ok(())
     }
 
+    /// broadcast_token_risk emits the risk flags of a mint to the room of the mint
+    pub async fn broadcast_token_risk(&self, data: &TokenRiskEvent) -> Result<(), BroadcastError> {
+        self.io.to(data.mint.clone()).emit(RequestEvent::TokenRisk.to_string(), data).await?;
+        Ok(())
+    }
+
+    /// broadcast_whale_transfer emits a whale transfer to the whales room
+    pub async fn broadcast_whale_transfer(
+        &self,
+        data: &WhaleTransferEvent,
+    ) -> Result<(), BroadcastError> {
+        self.io.to(WHALES_ROOM).emit(RequestEvent::WhaleTransfer.to_string(), data).await?;
+        Ok(())
+    }
+
     pub async fn broadcast_lp(&self, data: &LpEvent) -> Result<(), BroadcastError> {
         self.io.emit(RequestEvent::Lp.to_string(), data).await?;
         Ok(())
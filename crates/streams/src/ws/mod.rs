@@ -4,5 +4,5 @@ pub mod event;
 pub mod io;
 
 pub use adapter::init_adapter;
-pub use connect::on_connect;
+pub use connect::{on_connect, ConnectionConfig, ConnectionStats, ConnectionTracker};
 pub use io::IoProxy;
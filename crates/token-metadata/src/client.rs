@@ -1,12 +1,420 @@
-use solana_client::nonblocking::rpc_client::RpcClient;
-use std::env::var;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    http_sender::HttpSender,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use std::{
+    env::var,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
 
-/// Make a RPC client
+/// The timeout of a single RPC request, so a stalled endpoint can't hang the metadata lookups
+static RPC_TIMEOUT_SECS: LazyLock<u64> = LazyLock::new(|| {
+    var("TOKEN_METADATA_RPC_TIMEOUT_SECS").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(10)
+});
+
+/// The endpoints of `RPC_URLS`, shared by the clients made from the env so that
+/// a quarantine outlives the client that hit the rate limit
+/// a process without endpoints starts, and its requests fail until it is restarted with them
+static ENV_ENDPOINTS: LazyLock<Arc<EndpointPool>> = LazyLock::new(|| {
+    let urls = try_rpc_urls_from_env().unwrap_or_else(|e| {
+        warn!("{e:#}, the RPC requests fail");
+        vec![]
+    });
+    Arc::new(EndpointPool::new(urls))
+});
+
+/// The client of `RPC_URLS`, shared by the callers of [`make_rpc_client`] so that its HTTP
+/// connections are reused across the lookups
+static ENV_CLIENT: LazyLock<Arc<RotatingRpcClient>> = LazyLock::new(|| {
+    Arc::new(RotatingRpcClient::new(ENV_ENDPOINTS.clone(), Duration::from_secs(*RPC_TIMEOUT_SECS)))
+});
+
+/// The cooldown of an endpoint after its first rate limit, doubled on each consecutive one
+const BASE_COOLDOWN: Duration = Duration::from_secs(1);
+const MAX_COOLDOWN: Duration = Duration::from_secs(60);
+/// The JSON-RPC error code of the rate limited requests
+const RATE_LIMITED_CODE: i64 = -32429;
+
+/// try_rpc_urls_from_env returns the comma separated `RPC_URLS`, or `RPC_URL` if unset
+pub fn try_rpc_urls_from_env() -> anyhow::Result<Vec<String>> {
+    let urls = var("RPC_URLS")
+        .or_else(|_| var("RPC_URL"))
+        .map_err(|_| anyhow::anyhow!("Expected RPC_URLS or RPC_URL to be set"))?;
+    let urls: Vec<String> =
+        urls.split(',').map(|url| url.trim().to_string()).filter(|url| !url.is_empty()).collect();
+    anyhow::ensure!(!urls.is_empty(), "RPC_URLS is empty");
+    Ok(urls)
+}
+
+/// The requests served and failed by an endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointStats {
+    pub url: String,
+    pub successes: u64,
+    pub failures: u64,
+    /// The failures due to a rate limit
+    pub rate_limited: u64,
+}
+
+#[derive(Debug, Default)]
+struct Quarantine {
+    until: Option<Instant>,
+    /// The consecutive rate limits, reset by a success
+    strikes: u32,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    successes: AtomicU64,
+    failures: AtomicU64,
+    rate_limited: AtomicU64,
+    quarantine: Mutex<Quarantine>,
+}
+
+/// The health of a set of endpoints, requests are distributed round-robin over the
+/// endpoints that are not quarantined
+#[derive(Debug)]
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+impl EndpointPool {
+    pub fn new(urls: Vec<String>) -> Self {
+        let endpoints = urls
+            .into_iter()
+            .map(|url| Endpoint {
+                url,
+                successes: AtomicU64::new(0),
+                failures: AtomicU64::new(0),
+                rate_limited: AtomicU64::new(0),
+                quarantine: Mutex::new(Quarantine::default()),
+            })
+            .collect();
+        Self { endpoints, next: AtomicUsize::new(0) }
+    }
+
+    /// urls returns the urls of the endpoints
+    pub fn urls(&self) -> Vec<String> {
+        self.endpoints.iter().map(|endpoint| endpoint.url.clone()).collect()
+    }
+
+    /// pick returns the next endpoint in turn that isn't quarantined nor excluded,
+    /// the one released the soonest if they all are quarantined
+    fn pick(&self, excluded: &[usize]) -> Option<usize> {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let candidates: Vec<usize> = (0..self.endpoints.len())
+            .map(|offset| (start + offset) % self.endpoints.len())
+            .filter(|index| !excluded.contains(index))
+            .collect();
+        let released_at = |index: usize| {
+            self.endpoints[index].quarantine.lock().unwrap().until.filter(|until| *until > now)
+        };
+        candidates
+            .iter()
+            .find(|index| released_at(**index).is_none())
+            .or_else(|| candidates.iter().min_by_key(|index| released_at(**index)))
+            .copied()
+    }
+
+    fn record_success(&self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        endpoint.successes.fetch_add(1, Ordering::Relaxed);
+        *endpoint.quarantine.lock().unwrap() = Quarantine::default();
+    }
+
+    fn record_failure(&self, index: usize) {
+        self.endpoints[index].failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// record_rate_limit quarantines the endpoint, for twice as long as the previous time
+    /// if it was rate limited again since its last success
+    fn record_rate_limit(&self, index: usize) -> Duration {
+        let endpoint = &self.endpoints[index];
+        endpoint.failures.fetch_add(1, Ordering::Relaxed);
+        endpoint.rate_limited.fetch_add(1, Ordering::Relaxed);
+        let mut quarantine = endpoint.quarantine.lock().unwrap();
+        let cooldown =
+            BASE_COOLDOWN.saturating_mul(1 << quarantine.strikes.min(16)).min(MAX_COOLDOWN);
+        quarantine.strikes += 1;
+        quarantine.until = Some(Instant::now() + cooldown);
+        cooldown
+    }
+
+    /// is_quarantined returns true if the endpoint of the url is cooling down
+    pub fn is_quarantined(&self, url: &str) -> bool {
+        self.endpoints.iter().filter(|endpoint| endpoint.url == url).any(|endpoint| {
+            endpoint.quarantine.lock().unwrap().until.is_some_and(|until| until > Instant::now())
+        })
+    }
+
+    /// stats returns the counters of each endpoint
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointStats {
+                url: endpoint.url.clone(),
+                successes: endpoint.successes.load(Ordering::Relaxed),
+                failures: endpoint.failures.load(Ordering::Relaxed),
+                rate_limited: endpoint.rate_limited.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// is_rate_limited returns true if the endpoint answered 429 or a -32429 JSON-RPC error
+pub fn is_rate_limited(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Reqwest(e) => e.status().is_some_and(|status| status.as_u16() == 429),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == RATE_LIMITED_CODE
+        }
+        _ => false,
+    }
+}
+
+/// A sender distributing the requests over several endpoints, a rate limited request
+/// is retried on the next endpoint, once per endpoint
+pub struct RotatingRpcSender {
+    pool: Arc<EndpointPool>,
+    /// The transports of the endpoints of the pool, in the same order
+    senders: Vec<Box<dyn RpcSender + Send + Sync>>,
+}
+
+impl RotatingRpcSender {
+    pub fn new(pool: Arc<EndpointPool>, senders: Vec<Box<dyn RpcSender + Send + Sync>>) -> Self {
+        assert_eq!(pool.endpoints.len(), senders.len(), "one sender per endpoint");
+        Self { pool, senders }
+    }
+}
+
+#[async_trait::async_trait]
+impl RpcSender for RotatingRpcSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> ClientResult<serde_json::Value> {
+        let mut tried = Vec::with_capacity(self.senders.len());
+        loop {
+            let Some(index) = self.pool.pick(&tried) else {
+                let message = "No RPC endpoint, set RPC_URLS or RPC_URL".to_string();
+                return Err(ClientErrorKind::Custom(message).into());
+            };
+            tried.push(index);
+            match self.senders[index].send(request, params.clone()).await {
+                Ok(value) => {
+                    self.pool.record_success(index);
+                    return Ok(value);
+                }
+                Err(e) if is_rate_limited(&e) => {
+                    let cooldown = self.pool.record_rate_limit(index);
+                    warn!(
+                        url = self.pool.endpoints[index].url,
+                        cooldown_ms = cooldown.as_millis() as u64,
+                        "RPC endpoint rate limited, quarantined"
+                    );
+                    if tried.len() == self.senders.len() {
+                        return Err(e);
+                    }
+                }
+                Err(e) => {
+                    self.pool.record_failure(index);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        let mut stats = RpcTransportStats::default();
+        for sender in &self.senders {
+            let sender_stats = sender.get_transport_stats();
+            stats.request_count += sender_stats.request_count;
+            stats.elapsed_time += sender_stats.elapsed_time;
+            stats.rate_limited_time += sender_stats.rate_limited_time;
+        }
+        stats
+    }
+
+    fn url(&self) -> String {
+        self.pool.urls().join(",")
+    }
+}
+
+/// A RPC client rotating over several endpoints, usable as a [`RpcClient`]
+pub struct RotatingRpcClient {
+    client: RpcClient,
+    pool: Arc<EndpointPool>,
+}
+
+impl RotatingRpcClient {
+    /// Make a client over the endpoints of the pool, one HTTP transport each
+    pub fn new(pool: Arc<EndpointPool>, timeout: Duration) -> Self {
+        let senders = pool
+            .urls()
+            .into_iter()
+            .map(|url| {
+                Box::new(HttpSender::new_with_timeout(url, timeout))
+                    as Box<dyn RpcSender + Send + Sync>
+            })
+            .collect();
+        Self::with_senders(pool, senders)
+    }
+
+    /// Make a client over the given transports of the endpoints of the pool
+    pub fn with_senders(
+        pool: Arc<EndpointPool>,
+        senders: Vec<Box<dyn RpcSender + Send + Sync>>,
+    ) -> Self {
+        let sender = RotatingRpcSender::new(pool.clone(), senders);
+        let client = RpcClient::new_sender(sender, RpcClientConfig::default());
+        Self { client, pool }
+    }
+
+    /// endpoint_stats returns the counters of each endpoint
+    pub fn endpoint_stats(&self) -> Vec<EndpointStats> {
+        self.pool.stats()
+    }
+}
+
+impl Deref for RotatingRpcClient {
+    type Target = RpcClient;
+
+    fn deref(&self) -> &RpcClient {
+        &self.client
+    }
+}
+
+/// rpc_endpoint_stats returns the counters of the endpoints of `RPC_URLS`
+pub fn rpc_endpoint_stats() -> Vec<EndpointStats> {
+    ENV_ENDPOINTS.stats()
+}
+
+/// Returns the RPC client of the process, made on the first call
 ///
 /// # Arguments
 ///
-/// * `rpc_url` - The URL of the RPC node
-pub fn make_rpc_client() -> RpcClient {
-    let rpc_url = var("RPC_URL").expect("RPC_URL is not set");
-    RpcClient::new(rpc_url)
+/// * `rpc_urls` - The URLs of the RPC nodes, `RPC_URLS` or `RPC_URL`
+/// * `timeout` - The timeout of each request, `TOKEN_METADATA_RPC_TIMEOUT_SECS`
+pub fn make_rpc_client() -> Arc<RotatingRpcClient> {
+    ENV_CLIENT.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use solana_client::rpc_request::RpcResponseErrorData;
+
+    /// A transport answering every request, or failing them as rate limited
+    struct MockSender {
+        url: String,
+        rate_limited: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl RpcSender for MockSender {
+        async fn send(
+            &self,
+            _request: RpcRequest,
+            _params: serde_json::Value,
+        ) -> ClientResult<serde_json::Value> {
+            if self.rate_limited {
+                return Err(RpcError::RpcResponseError {
+                    code: RATE_LIMITED_CODE,
+                    message: "Too many requests".to_string(),
+                    data: RpcResponseErrorData::Empty,
+                }
+                .into());
+            }
+            Ok(json!(42))
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            RpcTransportStats::default()
+        }
+
+        fn url(&self) -> String {
+            self.url.clone()
+        }
+    }
+
+    fn make_client(endpoints: &[(&str, bool)]) -> RotatingRpcClient {
+        let pool =
+            Arc::new(EndpointPool::new(endpoints.iter().map(|(url, _)| url.to_string()).collect()));
+        let senders = endpoints
+            .iter()
+            .map(|(url, rate_limited)| {
+                Box::new(MockSender { url: url.to_string(), rate_limited: *rate_limited })
+                    as Box<dyn RpcSender + Send + Sync>
+            })
+            .collect();
+        RotatingRpcClient::with_senders(pool, senders)
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_endpoint_is_quarantined() {
+        let client = make_client(&[("limited", true), ("healthy", false)]);
+
+        // the first request lands on the limited endpoint and is retried on the healthy one
+        assert_eq!(client.get_slot().await.unwrap(), 42);
+        assert!(client.pool.is_quarantined("limited"));
+        assert!(!client.pool.is_quarantined("healthy"));
+
+        // while quarantined, every request goes to the healthy endpoint
+        for _ in 0..4 {
+            assert_eq!(client.get_slot().await.unwrap(), 42);
+        }
+        let stats = client.endpoint_stats();
+        assert_eq!((stats[0].successes, stats[0].failures, stats[0].rate_limited), (0, 1, 1));
+        assert_eq!((stats[1].successes, stats[1].failures), (5, 0));
+    }
+
+    #[tokio::test]
+    async fn test_requests_rotate_over_healthy_endpoints() {
+        let client = make_client(&[("first", false), ("second", false)]);
+        for _ in 0..4 {
+            client.get_slot().await.unwrap();
+        }
+        let successes: Vec<u64> = client.endpoint_stats().iter().map(|s| s.successes).collect();
+        assert_eq!(successes, vec![2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_every_endpoint_rate_limited() {
+        let client = make_client(&[("first", true), ("second", true)]);
+        let e = client.get_slot().await.unwrap_err();
+        assert!(is_rate_limited(&e));
+        let rate_limited: Vec<u64> =
+            client.endpoint_stats().iter().map(|s| s.rate_limited).collect();
+        assert_eq!(rate_limited, vec![1, 1]);
+
+        // the cooldown doubles on consecutive rate limits
+        let pool = &client.pool;
+        assert_eq!(pool.record_rate_limit(0), BASE_COOLDOWN * 2);
+        assert_eq!(pool.record_rate_limit(0), BASE_COOLDOWN * 4);
+        pool.record_success(0);
+        assert!(!pool.is_quarantined("first"));
+        assert_eq!(pool.record_rate_limit(0), BASE_COOLDOWN);
+    }
+
+    #[tokio::test]
+    async fn test_no_endpoint_fails_the_requests() {
+        let client = make_client(&[]);
+        let e = client.get_slot().await.unwrap_err();
+        assert!(e.to_string().contains("RPC_URLS"));
+    }
 }
@@ -1,9 +1,24 @@
 pub mod client;
 pub mod constants;
 pub mod metadata;
+pub mod refresh;
+pub mod risk;
 
 /// Re-export the crate functions
 pub use crate::{
-    client::make_rpc_client,
-    metadata::{get_mpl_token_metadata, get_token_data, get_token_metadata_with_data},
+    client::{
+        make_rpc_client, rpc_endpoint_stats, try_rpc_urls_from_env, EndpointPool, EndpointStats,
+        RotatingRpcClient,
+    },
+    metadata::{
+        fetch_mpl_token_metadata, get_mpl_token_metadata, get_token_data,
+        get_token_metadata_with_data, get_token_metadata_with_resolver, resolve_token,
+        resolve_token_partial, BackgroundResolver, Coalescer, ResolvedToken, RpcTokenResolver,
+        TokenResolver, DEFAULT_BACKGROUND_RESOLVE_CONCURRENCY,
+    },
+    refresh::{
+        refresh_tokens_with_missing_metadata, RefreshSummary, DEFAULT_REFRESH_MISSING_CONCURRENCY,
+        DEFAULT_REFRESH_MISSING_LIMIT,
+    },
+    risk::get_token_risk_flags,
 };
@@ -2,15 +2,17 @@
 use crate::{
     client::make_rpc_client,
     constants::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID},
+    risk::get_token_risk_flags,
 };
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use bigdecimal::{BigDecimal, ToPrimitive};
 use mpl_token_metadata::accounts::Metadata;
 use solana_commitment_config::CommitmentConfig;
 use solana_program::program_pack::Pack;
 use solana_pubkey::Pubkey;
 use sonar_db::{
-    models::{Token, TokenMetadata},
+    models::{Token, TokenMetadata, TokenRiskFlags},
     Database, KvStore,
 };
 use spl_token_2022::{
@@ -20,12 +22,18 @@ use spl_token_2022::{
 };
 use spl_token_metadata_interface::state::TokenMetadata as TokenMetadataExtension;
 use std::{
+    collections::HashMap,
+    future::Future,
     ops::Div,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, LazyLock, Mutex},
     time::{SystemTime, UNIX_EPOCH},
 };
-use tracing::debug;
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, warn};
+
+/// The token resolutions in flight in the process, shared by the concurrent lookups of a mint
+static IN_FLIGHT_RESOLUTIONS: LazyLock<Coalescer<Token>> = LazyLock::new(Coalescer::default);
 
 /// Used to facilitate token data retrieval from the RPC Node, the struct contains
 /// mint data for tokens and whether it is a NFT
@@ -39,6 +47,8 @@ pub struct PackedTokenData {
     pub data: Mint,
     /// The token metadata if available from extension
     pub metadata: Option<TokenMetadata>,
+    /// The risk flags decoded from the extensions of Token-2022 mints
+    pub risk_flags: Option<TokenRiskFlags>,
 }
 
 pub async fn get_token_data(mint: &str) -> Result<PackedTokenData> {
@@ -52,10 +62,10 @@ pub async fn get_token_data(mint: &str) -> Result<PackedTokenData> {
         .value
         .context(format!("Failed to get mint value: {}", mint))?;
 
-    let (mint_data, token_metadata) = match token_account.owner {
+    let (mint_data, token_metadata, risk_flags) = match token_account.owner {
         TOKEN_PROGRAM_ID => {
             let mint = Mint::unpack_from_slice(&token_account.data).expect("Failed to unpack mint");
-            (mint, None)
+            (mint, None, None)
         }
         TOKEN_2022_PROGRAM_ID => {
             let state_with_extensions = StateWithExtensions::<Mint>::unpack(&token_account.data)
@@ -67,7 +77,8 @@ pub async fn get_token_data(mint: &str) -> Result<PackedTokenData> {
             let metadata = pod_state_with_extensions
                 .get_variable_len_extension::<TokenMetadataExtension>()
                 .ok();
-            (mint_data, metadata)
+            let risk_flags = get_token_risk_flags(&token_account.data).ok();
+            (mint_data, metadata, risk_flags)
         }
         _ => {
             // should not happen
@@ -80,10 +91,19 @@ pub async fn get_token_data(mint: &str) -> Result<PackedTokenData> {
         is_nft,
         data: mint_data,
         metadata: token_metadata.map(|metadata| metadata.into()),
+        risk_flags,
     })
 }
 
 pub async fn get_mpl_token_metadata(mint: &str) -> Result<TokenMetadata> {
+    fetch_mpl_token_metadata(mint)
+        .await?
+        .context(format!("Failed to get metadata account value: {}", mint))
+}
+
+/// fetch_mpl_token_metadata returns the MPL metadata of a mint, None when the mint has no
+/// metadata account and an error when the RPC call failed
+pub async fn fetch_mpl_token_metadata(mint: &str) -> Result<Option<TokenMetadata>> {
     let client = make_rpc_client();
     let pubkey = Pubkey::from_str(mint).context(format!("Failed to parse mint: {}", mint))?;
 
@@ -98,12 +118,12 @@ pub async fn get_mpl_token_metadata(mint: &str) -> Result<TokenMetadata> {
         .get_account_with_commitment(&metadata_pubkey, CommitmentConfig::processed())
         .await
         .context(format!("Failed to get metadata account: {}", mint))?
-        .value
-        .context(format!("Failed to get metadata account value: {}", mint))?;
+        .value;
 
-    let metadata = Metadata::from_bytes(&account.data).expect("Failed to unpack metadata");
-    let token_metadata = TokenMetadata::from(metadata);
-    Ok(token_metadata)
+    Ok(account.map(|account| {
+        let metadata = Metadata::from_bytes(&account.data).expect("Failed to unpack metadata");
+        TokenMetadata::from(metadata)
+    }))
 }
 
 /// Trait to extend TokenMetadata with additional functionality
@@ -199,6 +219,143 @@ pub fn pack_token_metadata(
             |t| t.is_mutable,
             false,
         ),
+        launchpad: String::new(),
+        graduated_at: 0,
+        graduation_pool: String::new(),
+        launch_dex: None,
+        launch_pool: String::new(),
+        launch_timestamp: 0,
+    }
+}
+
+/// A token resolved from the RPC
+#[derive(Clone, Debug)]
+pub struct ResolvedToken {
+    pub token: Token,
+    pub risk_flags: Option<TokenRiskFlags>,
+    /// false when fetching the metadata failed and the token was packed without it
+    pub complete: bool,
+}
+
+/// resolve_token_partial fetches the token and its risk flags from the RPC, without caching
+/// them, a failed metadata fetch leaves the name and symbol empty and the token incomplete
+pub async fn resolve_token_partial(mint: &str) -> Result<ResolvedToken> {
+    let pack_token = get_token_data(mint).await.context("Failed to get token data from rpc")?;
+    let (token_metadata, complete) = if let Some(metadata) = &pack_token.metadata {
+        (Some(metadata.clone()), true)
+    } else {
+        // Fall back to MPL metadata if extension metadata is not available
+        match fetch_mpl_token_metadata(mint).await {
+            Ok(metadata) => (metadata, true),
+            Err(e) => {
+                warn!(error = ?e, mint, "Failed to get token metadata from rpc");
+                (None, false)
+            }
+        }
+    };
+
+    let token = pack_token_metadata(&pack_token, &token_metadata);
+    Ok(ResolvedToken { token, risk_flags: pack_token.risk_flags, complete })
+}
+
+/// resolve_token fetches the token and its risk flags from the RPC, without caching them,
+/// failing when the metadata couldn't be fetched
+pub async fn resolve_token(mint: &str) -> Result<(Token, Option<TokenRiskFlags>)> {
+    let resolved = resolve_token_partial(mint).await?;
+    if !resolved.complete {
+        return Err(anyhow!("Failed to get metadata of token {} from rpc", mint));
+    }
+    Ok((resolved.token, resolved.risk_flags))
+}
+
+/// Resolves the tokens missing from the kv store and the db
+#[async_trait]
+pub trait TokenResolver: Send + Sync {
+    async fn resolve(&self, mint: &str) -> Result<ResolvedToken>;
+}
+
+/// RpcTokenResolver resolves the tokens from the RPC
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RpcTokenResolver;
+
+#[async_trait]
+impl TokenResolver for RpcTokenResolver {
+    async fn resolve(&self, mint: &str) -> Result<ResolvedToken> {
+        resolve_token_partial(mint).await
+    }
+}
+
+/// The outcome of a resolution, None while in flight, the error formatted to be shared
+type Outcome<T> = Option<Result<T, String>>;
+
+/// Coalescer runs a single resolution per key at a time, the callers of a key arriving while
+/// it is in flight wait for its outcome instead of starting their own
+pub struct Coalescer<T> {
+    in_flight: Mutex<HashMap<String, watch::Receiver<Outcome<T>>>>,
+}
+
+impl<T> Default for Coalescer<T> {
+    fn default() -> Self {
+        Self { in_flight: Mutex::new(HashMap::new()) }
+    }
+}
+
+/// Removes the key of a resolution once it completes or is cancelled, so the next call of the
+/// key resolves it again
+struct InFlightGuard<'a, T> {
+    coalescer: &'a Coalescer<T>,
+    key: &'a str,
+}
+
+impl<T> Drop for InFlightGuard<'_, T> {
+    fn drop(&mut self) {
+        self.coalescer.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(self.key);
+    }
+}
+
+impl<T: Clone> Coalescer<T> {
+    /// run returns the outcome of the resolution of `key` in flight, or runs `resolve` when
+    /// none is, a cancelled resolution fails its waiting callers
+    pub async fn run<F, Fut>(&self, key: &str, resolve: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let sender = {
+            let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+            match in_flight.get(key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    in_flight.insert(key.to_string(), receiver);
+                    Ok(sender)
+                }
+            }
+        };
+        let sender = match sender {
+            Ok(sender) => sender,
+            Err(mut receiver) => {
+                let outcome = receiver
+                    .wait_for(Option::is_some)
+                    .await
+                    .map_err(|_| anyhow!("The resolution of {} was cancelled", key))?;
+                return match outcome.as_ref() {
+                    Some(Ok(value)) => Ok(value.clone()),
+                    Some(Err(e)) => Err(anyhow!("{}", e)),
+                    None => unreachable!("waited for an outcome"),
+                };
+            }
+        };
+
+        let _guard = InFlightGuard { coalescer: self, key };
+        let result = resolve().await;
+        sender.send_replace(Some(result.as_ref().map(T::clone).map_err(|e| format!("{e:#}"))));
+        result
+    }
+
+    /// in_flight returns the number of resolutions in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).len()
     }
 }
 
@@ -206,6 +363,18 @@ pub async fn get_token_metadata_with_data(
     mint: &str,
     kv_store: &Arc<KvStore>,
     db: &Arc<Database>,
+) -> Result<Token> {
+    get_token_metadata_with_resolver(mint, kv_store, db, &RpcTokenResolver).await
+}
+
+/// get_token_metadata_with_resolver reads the token from the kv store, then the db, then
+/// the resolver, a token whose metadata failed to resolve is returned without being stored
+/// so the next lookup tries again. The concurrent lookups of a mint share one resolution
+pub async fn get_token_metadata_with_resolver(
+    mint: &str,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    resolver: &dyn TokenResolver,
 ) -> Result<Token> {
     if let Some(token) =
         kv_store.get_token(mint).await.context("Failed to get token from kv store")?
@@ -218,18 +387,72 @@ pub async fn get_token_metadata_with_data(
         return Ok(token);
     }
 
-    let pack_token = get_token_data(mint).await.context("Failed to get token data from rpc")?;
-    let token_metadata = if let Some(metadata) = &pack_token.metadata {
-        Some(metadata.clone())
-    } else {
-        // Fall back to MPL metadata if extension metadata is not available
-        get_mpl_token_metadata(mint).await.ok()
-    };
+    IN_FLIGHT_RESOLUTIONS.run(mint, || resolve_and_store(mint, kv_store, db, resolver)).await
+}
 
-    let token = pack_token_metadata(&pack_token, &token_metadata);
+/// The resolutions a BackgroundResolver runs at a time by default
+pub const DEFAULT_BACKGROUND_RESOLVE_CONCURRENCY: usize = 8;
+
+/// BackgroundResolver resolves the tokens missing from the kv store and the db off the
+/// request path, storing them for the next lookups, at most `concurrency` at a time
+#[derive(Clone)]
+pub struct BackgroundResolver {
+    resolver: Arc<dyn TokenResolver>,
+    permits: Arc<Semaphore>,
+}
+
+impl Default for BackgroundResolver {
+    fn default() -> Self {
+        Self::new(Arc::new(RpcTokenResolver), DEFAULT_BACKGROUND_RESOLVE_CONCURRENCY)
+    }
+}
+
+impl BackgroundResolver {
+    pub fn new(resolver: Arc<dyn TokenResolver>, concurrency: usize) -> Self {
+        Self { resolver, permits: Arc::new(Semaphore::new(concurrency.max(1))) }
+    }
+
+    /// spawn starts the resolution of the mint, sharing the one in flight of the mint,
+    /// returns false without starting it when `concurrency` resolutions are running
+    pub fn spawn(&self, mint: &str, kv_store: Arc<KvStore>, db: Arc<Database>) -> bool {
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            return false;
+        };
+        let resolver = self.resolver.clone();
+        let mint = mint.to_string();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let result =
+                get_token_metadata_with_resolver(&mint, &kv_store, &db, resolver.as_ref()).await;
+            if let Err(e) = result {
+                warn!(error = ?e, mint = %mint, "Failed to resolve token in background");
+            }
+        });
+        true
+    }
+}
+
+/// resolve_and_store resolves a token missing from the kv store and the db and stores it
+/// when its metadata resolved
+async fn resolve_and_store(
+    mint: &str,
+    kv_store: &Arc<KvStore>,
+    db: &Arc<Database>,
+    resolver: &dyn TokenResolver,
+) -> Result<Token> {
+    let ResolvedToken { token, risk_flags, complete } = resolver.resolve(mint).await?;
+    if !complete {
+        return Ok(token);
+    }
 
     db.insert_token(&token).await.context("Failed to insert token into db")?;
     kv_store.set_token(mint, &token).await.context("Failed to set token in kv store")?;
+    if let Some(risk_flags) = &risk_flags {
+        kv_store
+            .set_token_risk_flags(mint, risk_flags)
+            .await
+            .context("Failed to set token risk flags in kv store")?;
+    }
 
     Ok(token)
 }
@@ -238,6 +461,131 @@ pub async fn get_token_metadata_with_data(
 mod tests {
     use super::*;
     use dotenvy::dotenv;
+    use sonar_db::test_utils::{make_token, seeded_storages};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// Resolves every mint as if the metadata fetch failed or succeeded
+    struct MockResolver {
+        complete: bool,
+    }
+
+    #[async_trait]
+    impl TokenResolver for MockResolver {
+        async fn resolve(&self, mint: &str) -> Result<ResolvedToken> {
+            let token = if self.complete {
+                make_token(mint, "MOCK", "Mock")
+            } else {
+                Token { supply: 0.0, ..make_token(mint, "", "") }
+            };
+            Ok(ResolvedToken { token, risk_flags: None, complete: self.complete })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_metadata_is_not_stored() {
+        let (db, kv_store) = seeded_storages(&[], &[]).await;
+        let mint = "mint";
+
+        let failing = MockResolver { complete: false };
+        let token = get_token_metadata_with_resolver(mint, &kv_store, &db, &failing).await.unwrap();
+        assert!(token.symbol.is_empty());
+        assert!(db.get_token(mint).await.unwrap().is_none());
+        assert!(kv_store.get_token(mint).await.unwrap().is_none());
+
+        // the next lookup resolves the token again
+        let resolver = MockResolver { complete: true };
+        let token =
+            get_token_metadata_with_resolver(mint, &kv_store, &db, &resolver).await.unwrap();
+        assert_eq!(token.symbol, "MOCK");
+        assert_eq!(db.get_token(mint).await.unwrap().unwrap().symbol, "MOCK");
+        assert_eq!(kv_store.get_token(mint).await.unwrap().unwrap().symbol, "MOCK");
+    }
+
+    /// Counts the resolutions, each staying in flight for a while
+    #[derive(Default)]
+    struct CountingResolver {
+        resolutions: AtomicUsize,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl TokenResolver for CountingResolver {
+        async fn resolve(&self, mint: &str) -> Result<ResolvedToken> {
+            self.resolutions.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if self.fail {
+                return Err(anyhow!("rpc unavailable"));
+            }
+            let token = make_token(mint, "HOT", "Hot");
+            Ok(ResolvedToken { token, risk_flags: None, complete: true })
+        }
+    }
+
+    /// resolve_concurrently looks the mint up 50 times concurrently
+    async fn resolve_concurrently(
+        mint: &str,
+        resolver: &Arc<CountingResolver>,
+    ) -> Vec<Result<Token>> {
+        let (db, kv_store) = seeded_storages(&[], &[]).await;
+        let lookups = (0..50).map(|_| {
+            let (mint, kv_store, db, resolver) =
+                (mint.to_string(), kv_store.clone(), db.clone(), resolver.clone());
+            tokio::spawn(async move {
+                get_token_metadata_with_resolver(&mint, &kv_store, &db, resolver.as_ref()).await
+            })
+        });
+        futures::future::join_all(lookups).await.into_iter().map(|r| r.unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_lookups_share_one_resolution() {
+        let resolver = Arc::new(CountingResolver::default());
+        let tokens = resolve_concurrently("hot-mint", &resolver).await;
+        assert_eq!(resolver.resolutions.load(Ordering::SeqCst), 1);
+        assert!(tokens.iter().all(|token| token.as_ref().unwrap().symbol == "HOT"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_resolution_is_retried() {
+        let resolver = Arc::new(CountingResolver { fail: true, ..Default::default() });
+        let results = resolve_concurrently("failing-hot-mint", &resolver).await;
+        assert_eq!(resolver.resolutions.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|result| result.is_err()));
+
+        // the failed resolution is no longer in flight, the next lookups resolve again
+        resolve_concurrently("failing-hot-mint", &resolver).await;
+        assert_eq!(resolver.resolutions.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_background_resolutions_are_bounded() {
+        let (db, kv_store) = seeded_storages(&[], &[]).await;
+        let resolver = Arc::new(CountingResolver::default());
+        let background = BackgroundResolver::new(resolver.clone(), 2);
+        assert!(background.spawn("mint-a", kv_store.clone(), db.clone()));
+        assert!(background.spawn("mint-b", kv_store.clone(), db.clone()));
+        assert!(!background.spawn("mint-c", kv_store.clone(), db.clone()));
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(resolver.resolutions.load(Ordering::SeqCst), 2);
+        assert_eq!(kv_store.get_token("mint-a").await.unwrap().unwrap().symbol, "HOT");
+        assert!(kv_store.get_token("mint-c").await.unwrap().is_none());
+        // the permits are released once the resolutions complete
+        assert!(background.spawn("mint-c", kv_store, db));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_resolution_is_removed() {
+        let coalescer = Coalescer::<u64>::default();
+        let resolution = coalescer.run("key", || futures::future::pending());
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), resolution).await;
+        assert!(cancelled.is_err());
+        assert_eq!(coalescer.in_flight(), 0);
+        assert_eq!(coalescer.run("key", || async { Ok(42) }).await.unwrap(), 42);
+    }
 
     #[tokio::test]
     async fn test_get_usdc_data() {
@@ -0,0 +1,210 @@
+//! this file re-resolves the tokens stored with missing metadata.
+use crate::metadata::{ResolvedToken, TokenResolver};
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use sonar_db::{models::Token, Database, KvStore};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+pub const DEFAULT_REFRESH_MISSING_LIMIT: usize = 500;
+pub const DEFAULT_REFRESH_MISSING_CONCURRENCY: usize = 4;
+/// How long a mint whose metadata failed to resolve is skipped by the refreshes
+pub const UNRESOLVABLE_TOKEN_TTL_SECS: u64 = 60 * 60 * 6;
+
+/// The outcome of refreshing the tokens with missing metadata
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RefreshSummary {
+    /// How many tokens with missing metadata were found
+    pub requested: usize,
+    /// How many tokens now have a name, a symbol and a supply
+    pub fixed: usize,
+    /// How many tokens still miss metadata
+    pub failed: usize,
+    /// How many tokens were skipped, their metadata failed to resolve recently
+    pub skipped: usize,
+}
+
+/// Returns true if the token has a name, a symbol and a supply
+pub fn has_metadata(token: &Token) -> bool {
+    !token.name.is_empty() && !token.symbol.is_empty() && token.supply != 0.0
+}
+
+/// refresh_tokens_with_missing_metadata re-resolves up to `limit` tokens stored with an empty
+/// name, symbol or supply, at most `concurrency` at a time, and stores the fixed ones. Each
+/// refresh continues after the last mint of the previous one, and skips the mints that failed
+/// within `UNRESOLVABLE_TOKEN_TTL_SECS`
+pub async fn refresh_tokens_with_missing_metadata(
+    db: &Arc<Database>,
+    kv_store: &Arc<KvStore>,
+    resolver: &dyn TokenResolver,
+    limit: usize,
+    concurrency: usize,
+) -> Result<RefreshSummary> {
+    let after = kv_store
+        .get_missing_metadata_cursor()
+        .await
+        .context("Failed to get the missing metadata cursor")?;
+    let mints = db
+        .get_tokens_with_missing_metadata(after.as_deref(), limit)
+        .await
+        .context("Failed to get tokens with missing metadata")?;
+    info!(tokens = mints.len(), ?after, "Refreshing tokens with missing metadata");
+    // a short page is the last one, the next refresh starts over
+    let cursor = if mints.len() < limit { None } else { mints.last().cloned() };
+    kv_store
+        .set_missing_metadata_cursor(cursor.as_deref())
+        .await
+        .context("Failed to set the missing metadata cursor")?;
+
+    let results: Vec<(String, Result<Option<bool>>)> = futures::stream::iter(mints)
+        .map(|mint| async move {
+            let result = refresh_unless_unresolvable(db, kv_store, resolver, &mint).await;
+            (mint, result)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut summary = RefreshSummary { requested: results.len(), ..Default::default() };
+    for (mint, result) in results {
+        match result {
+            Ok(Some(true)) => summary.fixed += 1,
+            Ok(Some(false)) => summary.failed += 1,
+            Ok(None) => summary.skipped += 1,
+            Err(e) => {
+                warn!(error = ?e, mint = %mint, "Failed to refresh token metadata");
+                summary.failed += 1;
+            }
+        }
+    }
+    info!(
+        fixed = summary.fixed,
+        failed = summary.failed,
+        skipped = summary.skipped,
+        "Refreshed tokens with missing metadata"
+    );
+    Ok(summary)
+}
+
+/// Refreshes a token unless it failed to resolve recently, None if it was skipped, and
+/// records the mints failing to resolve again
+async fn refresh_unless_unresolvable(
+    db: &Arc<Database>,
+    kv_store: &Arc<KvStore>,
+    resolver: &dyn TokenResolver,
+    mint: &str,
+) -> Result<Option<bool>> {
+    if kv_store.is_token_unresolvable(mint).await.context("Failed to check unresolvable token")? {
+        return Ok(None);
+    }
+    let result = refresh_token(db, kv_store, resolver, mint).await;
+    if !matches!(result, Ok(true)) {
+        kv_store
+            .mark_token_unresolvable(mint, UNRESOLVABLE_TOKEN_TTL_SECS)
+            .await
+            .context("Failed to mark token unresolvable")?;
+    }
+    result.map(Some)
+}
+
+/// Drops the cached token and resolves it again, returns true if the token was fixed
+async fn refresh_token(
+    db: &Arc<Database>,
+    kv_store: &Arc<KvStore>,
+    resolver: &dyn TokenResolver,
+    mint: &str,
+) -> Result<bool> {
+    kv_store.delete_token(mint).await.context("Failed to delete token from kv store")?;
+
+    let ResolvedToken { mut token, risk_flags, complete } = resolver.resolve(mint).await?;
+    if !complete || !has_metadata(&token) {
+        return Ok(false);
+    }
+    if let Some(stored) = db.get_token(mint).await.context("Failed to get token")? {
+        // the launchpad is recorded by the ingestor, the RPC doesn't know it
+        token.launchpad = stored.launchpad;
+        token.graduated_at = stored.graduated_at;
+        token.graduation_pool = stored.graduation_pool;
+        token.launch_dex = stored.launch_dex;
+        token.launch_pool = stored.launch_pool;
+        token.launch_timestamp = stored.launch_timestamp;
+    }
+
+    db.insert_token(&token).await.context("Failed to insert token into db")?;
+    kv_store.set_token(mint, &token).await.context("Failed to set token in kv store")?;
+    if let Some(risk_flags) = &risk_flags {
+        kv_store
+            .set_token_risk_flags(mint, risk_flags)
+            .await
+            .context("Failed to set token risk flags in kv store")?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use sonar_db::test_utils::{make_token, seeded_storages};
+    use std::collections::HashMap;
+
+    /// Resolves the mints from a map, the missing ones as an RPC failure
+    struct MockResolver(HashMap<String, ResolvedToken>);
+
+    #[async_trait]
+    impl TokenResolver for MockResolver {
+        async fn resolve(&self, mint: &str) -> Result<ResolvedToken> {
+            self.0.get(mint).cloned().context("rpc unavailable")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens_with_missing_metadata() {
+        let empty = |mint: &str| Token { supply: 0.0, ..make_token(mint, "", "") };
+        let stored = Token { launchpad: "pump".to_string(), ..empty("fixed") };
+        let tokens = [stored, empty("incomplete"), empty("down"), make_token("ok", "OK", "Ok")];
+        let (db, kv_store) = seeded_storages(&[], &tokens).await;
+        // the poisoned token was cached too
+        kv_store.set_token("fixed", &tokens[0]).await.unwrap();
+
+        let resolved = |token: Token, complete| ResolvedToken { token, risk_flags: None, complete };
+        let resolver = MockResolver(HashMap::from([
+            ("fixed".to_string(), resolved(make_token("fixed", "FIX", "Fixed"), true)),
+            ("incomplete".to_string(), resolved(empty("incomplete"), false)),
+        ]));
+
+        let summary =
+            refresh_tokens_with_missing_metadata(&db, &kv_store, &resolver, 10, 2).await.unwrap();
+        assert_eq!(summary, RefreshSummary { requested: 3, fixed: 1, failed: 2, skipped: 0 });
+
+        let fixed = db.get_token("fixed").await.unwrap().unwrap();
+        assert_eq!((fixed.symbol.as_str(), fixed.launchpad.as_str()), ("FIX", "pump"));
+        assert_eq!(kv_store.get_token("fixed").await.unwrap().unwrap().symbol, "FIX");
+        let missing = db.get_tokens_with_missing_metadata(None, 10).await.unwrap();
+        assert_eq!(missing, vec!["down", "incomplete"]);
+
+        // the failed mints are skipped until their negative cache entry expires
+        assert!(kv_store.is_token_unresolvable("down").await.unwrap());
+        assert!(!kv_store.is_token_unresolvable("fixed").await.unwrap());
+        let summary =
+            refresh_tokens_with_missing_metadata(&db, &kv_store, &resolver, 10, 2).await.unwrap();
+        assert_eq!(summary, RefreshSummary { requested: 2, fixed: 0, failed: 0, skipped: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_refresh_continues_after_the_last_mint() {
+        let empty = |mint: &str| Token { supply: 0.0, ..make_token(mint, "", "") };
+        let tokens = [empty("a"), empty("b"), empty("c")];
+        let (db, kv_store) = seeded_storages(&[], &tokens).await;
+        let resolver = MockResolver(HashMap::new());
+        let refresh = || refresh_tokens_with_missing_metadata(&db, &kv_store, &resolver, 2, 1);
+
+        // the pages rotate through the mints instead of retrying the first ones
+        assert_eq!(refresh().await.unwrap().failed, 2);
+        assert_eq!(kv_store.get_missing_metadata_cursor().await.unwrap().as_deref(), Some("b"));
+        assert_eq!(refresh().await.unwrap().failed, 1);
+        assert!(kv_store.is_token_unresolvable("c").await.unwrap());
+        // the short page starts the next refresh over
+        assert_eq!(kv_store.get_missing_metadata_cursor().await.unwrap(), None);
+    }
+}
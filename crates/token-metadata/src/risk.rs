@@ -0,0 +1,114 @@
+//! Decodes the risk flags of Token-2022 mints from their extensions
+use anyhow::{Context, Result};
+use solana_pubkey::Pubkey;
+use sonar_db::TokenRiskFlags;
+use spl_token_2022::{
+    extension::{
+        default_account_state::DefaultAccountState, permanent_delegate::PermanentDelegate,
+        transfer_fee::TransferFeeConfig, transfer_hook::TransferHook, BaseStateWithExtensions,
+        StateWithExtensions,
+    },
+    state::{AccountState, Mint},
+};
+
+/// Decodes the risk flags from the data of a Token-2022 mint account,
+/// mints without extensions have no flags raised
+pub fn get_token_risk_flags(data: &[u8]) -> Result<TokenRiskFlags> {
+    let mint = StateWithExtensions::<Mint>::unpack(data).context("Failed to unpack mint")?;
+
+    // the newer fee takes effect at its epoch, flag whichever is higher
+    let transfer_fee_bps = mint
+        .get_extension::<TransferFeeConfig>()
+        .map(|config| {
+            u16::from(config.older_transfer_fee.transfer_fee_basis_points)
+                .max(u16::from(config.newer_transfer_fee.transfer_fee_basis_points))
+        })
+        .unwrap_or(0);
+    let has_permanent_delegate = mint
+        .get_extension::<PermanentDelegate>()
+        .is_ok_and(|extension| Option::<Pubkey>::from(extension.delegate).is_some());
+    let has_transfer_hook = mint
+        .get_extension::<TransferHook>()
+        .is_ok_and(|extension| Option::<Pubkey>::from(extension.program_id).is_some());
+    let default_frozen = mint
+        .get_extension::<DefaultAccountState>()
+        .is_ok_and(|extension| extension.state == AccountState::Frozen as u8);
+
+    Ok(TokenRiskFlags {
+        transfer_fee_bps,
+        has_permanent_delegate,
+        has_transfer_hook,
+        default_frozen,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spl_token_2022::extension::{ExtensionType, StateWithExtensionsMut};
+
+    /// builds an initialized mint account with the given extensions
+    fn make_mint_account(
+        extensions: &[ExtensionType],
+        init: impl FnOnce(&mut StateWithExtensionsMut<Mint>),
+    ) -> Vec<u8> {
+        let len = ExtensionType::try_calculate_account_len::<Mint>(extensions).unwrap();
+        let mut data = vec![0u8; len];
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        init(&mut state);
+        state.base =
+            Mint { decimals: 6, is_initialized: true, supply: 1_000, ..Default::default() };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        data
+    }
+
+    #[test]
+    fn test_transfer_fee_flags() {
+        let data = make_mint_account(&[ExtensionType::TransferFeeConfig], |state| {
+            let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+            config.older_transfer_fee.transfer_fee_basis_points = 100.into();
+            config.newer_transfer_fee.transfer_fee_basis_points = 250.into();
+        });
+        let flags = get_token_risk_flags(&data).unwrap();
+        assert_eq!(
+            flags,
+            TokenRiskFlags {
+                transfer_fee_bps: 250,
+                has_permanent_delegate: false,
+                has_transfer_hook: false,
+                default_frozen: false,
+            }
+        );
+        assert!(flags.is_risky());
+    }
+
+    #[test]
+    fn test_delegate_hook_and_frozen_flags() {
+        let extensions = [
+            ExtensionType::PermanentDelegate,
+            ExtensionType::TransferHook,
+            ExtensionType::DefaultAccountState,
+        ];
+        let data = make_mint_account(&extensions, |state| {
+            let delegate = state.init_extension::<PermanentDelegate>(true).unwrap();
+            delegate.delegate = Some(Pubkey::new_unique()).try_into().unwrap();
+            let hook = state.init_extension::<TransferHook>(true).unwrap();
+            hook.program_id = Some(Pubkey::new_unique()).try_into().unwrap();
+            let default_state = state.init_extension::<DefaultAccountState>(true).unwrap();
+            default_state.state = AccountState::Frozen as u8;
+        });
+        let flags = get_token_risk_flags(&data).unwrap();
+        assert_eq!(flags.transfer_fee_bps, 0);
+        assert!(flags.has_permanent_delegate);
+        assert!(flags.has_transfer_hook);
+        assert!(flags.default_frozen);
+    }
+
+    #[test]
+    fn test_plain_mint_has_no_flags() {
+        let data = make_mint_account(&[], |_| {});
+        let flags = get_token_risk_flags(&data).unwrap();
+        assert!(!flags.is_risky());
+    }
+}